@@ -51,7 +51,9 @@ macro_rules! app_loader_component_static {
 
 pub struct AppLoaderComponent<
     S: dynamic_binary_storage::DynamicBinaryStore + 'static,
-    L: dynamic_binary_storage::DynamicProcessLoad + 'static,
+    L: dynamic_binary_storage::DynamicProcessLoad
+        + dynamic_binary_storage::DynamicProcessUpdate
+        + 'static,
 > {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
@@ -61,7 +63,9 @@ pub struct AppLoaderComponent<
 
 impl<
         S: dynamic_binary_storage::DynamicBinaryStore + 'static,
-        L: dynamic_binary_storage::DynamicProcessLoad + 'static,
+        L: dynamic_binary_storage::DynamicProcessLoad
+            + dynamic_binary_storage::DynamicProcessUpdate
+            + 'static,
     > AppLoaderComponent<S, L>
 {
     pub fn new(
@@ -81,7 +85,9 @@ impl<
 
 impl<
         S: dynamic_binary_storage::DynamicBinaryStore + 'static,
-        L: dynamic_binary_storage::DynamicProcessLoad + 'static,
+        L: dynamic_binary_storage::DynamicProcessLoad
+            + dynamic_binary_storage::DynamicProcessUpdate
+            + 'static,
     > Component for AppLoaderComponent<S, L>
 {
     type StaticInput = (
@@ -111,6 +117,10 @@ impl<
             self.load_driver,
             dynamic_app_loader,
         );
+        dynamic_binary_storage::DynamicProcessUpdate::set_update_client(
+            self.load_driver,
+            dynamic_app_loader,
+        );
         dynamic_app_loader
     }
 }
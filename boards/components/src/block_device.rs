@@ -0,0 +1,242 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Components for block devices.
+//!
+//! This provides four components:
+//!
+//! - `SDCardBlockDeviceComponent` provides a block device for an SD card.
+//! - `FlashBlockDeviceComponent` provides a block device for a range of pages
+//!   of a flash.
+//! - `MuxBlockDeviceComponent` shares a block device between several users.
+//! - `VirtualBlockDeviceComponent` provides a block device for one user of a
+//!   `MuxBlockDevice`, limited to a region of the device.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let sdcard_block_device =
+//!     components::block_device::SDCardBlockDeviceComponent::new(sdcard).finalize(
+//!         components::sdcard_block_device_component_static!(
+//!             capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<
+//!                 'static,
+//!                 nrf52840::rtc::Rtc,
+//!             >
+//!         ),
+//!     );
+//! let mux_block_device =
+//!     components::block_device::MuxBlockDeviceComponent::new(sdcard_block_device).finalize(
+//!         components::mux_block_device_component_static!(
+//!             components::block_device::SDCardBlockDeviceComponentType<
+//!                 capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<
+//!                     'static,
+//!                     nrf52840::rtc::Rtc,
+//!                 >,
+//!             >
+//!         ),
+//!     );
+//! let data_partition = components::block_device::VirtualBlockDeviceComponent::new(
+//!     mux_block_device,
+//!     capsules_extra::virtualizers::virtual_block_device::Region::Partition(0),
+//! )
+//! .finalize(components::virtual_block_device_component_static!(
+//!     components::block_device::SDCardBlockDeviceComponentType<
+//!         capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<
+//!             'static,
+//!             nrf52840::rtc::Rtc,
+//!         >,
+//!     >
+//! ));
+//!
+//! let flash_block_device = components::block_device::FlashBlockDeviceComponent::new(
+//!     &base_peripherals.nvmc,
+//!     0x60000 / 4096, // First page
+//!     32,             // Number of pages
+//! )
+//! .finalize(components::flash_block_device_component_static!(
+//!     nrf52840::nvmc::Nvmc
+//! ));
+//! ```
+
+use capsules_extra::flash_block_device::FlashBlockDevice;
+use capsules_extra::sdcard::SDCard;
+use capsules_extra::sdcard_block_device::SDCardBlockDevice;
+use capsules_extra::virtualizers::virtual_block_device::{
+    MuxBlockDevice, Region, VirtualBlockDevice, TABLE_BUFFER_LEN,
+};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil;
+use kernel::hil::block_device::BlockDevice;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! sdcard_block_device_component_static {
+    ($A:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::sdcard_block_device::SDCardBlockDevice<'static, $A>)
+    };};
+}
+
+#[macro_export]
+macro_rules! flash_block_device_component_static {
+    ($F:ty $(,)?) => {{
+        let page = kernel::static_buf!(<$F as kernel::hil::flash::Flash>::Page);
+        let device =
+            kernel::static_buf!(capsules_extra::flash_block_device::FlashBlockDevice<'static, $F>);
+
+        (page, device)
+    };};
+}
+
+#[macro_export]
+macro_rules! mux_block_device_component_static {
+    ($B:ty $(,)?) => {{
+        let buffer = kernel::static_buf!(
+            [u8; capsules_extra::virtualizers::virtual_block_device::TABLE_BUFFER_LEN]
+        );
+        let mux = kernel::static_buf!(
+            capsules_extra::virtualizers::virtual_block_device::MuxBlockDevice<'static, $B>
+        );
+
+        (buffer, mux)
+    };};
+}
+
+#[macro_export]
+macro_rules! virtual_block_device_component_static {
+    ($B:ty $(,)?) => {{
+        kernel::static_buf!(
+            capsules_extra::virtualizers::virtual_block_device::VirtualBlockDevice<'static, $B>
+        )
+    };};
+}
+
+pub type SDCardBlockDeviceComponentType<A> = SDCardBlockDevice<'static, A>;
+pub type FlashBlockDeviceComponentType<F> = FlashBlockDevice<'static, F>;
+pub type MuxBlockDeviceComponentType<B> = MuxBlockDevice<'static, B>;
+pub type VirtualBlockDeviceComponentType<B> = VirtualBlockDevice<'static, B>;
+
+pub struct SDCardBlockDeviceComponent<A: 'static + hil::time::Alarm<'static>> {
+    sdcard: &'static SDCard<'static, A>,
+}
+
+impl<A: 'static + hil::time::Alarm<'static>> SDCardBlockDeviceComponent<A> {
+    pub fn new(sdcard: &'static SDCard<'static, A>) -> Self {
+        Self { sdcard }
+    }
+}
+
+impl<A: 'static + hil::time::Alarm<'static>> Component for SDCardBlockDeviceComponent<A> {
+    type StaticInput = &'static mut MaybeUninit<SDCardBlockDevice<'static, A>>;
+    type Output = &'static SDCardBlockDevice<'static, A>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let device = static_buffer.write(SDCardBlockDevice::new(self.sdcard));
+        device.register();
+        self.sdcard.set_client(device);
+
+        device
+    }
+}
+
+pub struct FlashBlockDeviceComponent<
+    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, FlashBlockDevice<'static, F>>,
+> {
+    flash: &'static F,
+    first_page: usize,
+    page_count: u32,
+}
+
+impl<
+        F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, FlashBlockDevice<'static, F>>,
+    > FlashBlockDeviceComponent<F>
+{
+    pub fn new(flash: &'static F, first_page: usize, page_count: u32) -> Self {
+        Self {
+            flash,
+            first_page,
+            page_count,
+        }
+    }
+}
+
+impl<
+        F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, FlashBlockDevice<'static, F>>,
+    > Component for FlashBlockDeviceComponent<F>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<<F as hil::flash::Flash>::Page>,
+        &'static mut MaybeUninit<FlashBlockDevice<'static, F>>,
+    );
+    type Output = &'static FlashBlockDevice<'static, F>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let page = static_buffer
+            .0
+            .write(<F as hil::flash::Flash>::Page::default());
+        let device = static_buffer.1.write(FlashBlockDevice::new(
+            self.flash,
+            self.first_page,
+            self.page_count,
+            page,
+        ));
+        device.register();
+        hil::flash::HasClient::set_client(self.flash, device);
+
+        device
+    }
+}
+
+pub struct MuxBlockDeviceComponent<B: 'static + BlockDevice<'static>> {
+    device: &'static B,
+}
+
+impl<B: 'static + BlockDevice<'static>> MuxBlockDeviceComponent<B> {
+    pub fn new(device: &'static B) -> Self {
+        Self { device }
+    }
+}
+
+impl<B: 'static + BlockDevice<'static>> Component for MuxBlockDeviceComponent<B> {
+    type StaticInput = (
+        &'static mut MaybeUninit<[u8; TABLE_BUFFER_LEN]>,
+        &'static mut MaybeUninit<MuxBlockDevice<'static, B>>,
+    );
+    type Output = &'static MuxBlockDevice<'static, B>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let buffer = static_buffer.0.write([0; TABLE_BUFFER_LEN]);
+        let mux = static_buffer
+            .1
+            .write(MuxBlockDevice::new(self.device, buffer));
+        mux.register();
+        self.device.set_client(mux);
+
+        mux
+    }
+}
+
+pub struct VirtualBlockDeviceComponent<B: 'static + BlockDevice<'static>> {
+    mux: &'static MuxBlockDevice<'static, B>,
+    region: Region,
+}
+
+impl<B: 'static + BlockDevice<'static>> VirtualBlockDeviceComponent<B> {
+    pub fn new(mux: &'static MuxBlockDevice<'static, B>, region: Region) -> Self {
+        Self { mux, region }
+    }
+}
+
+impl<B: 'static + BlockDevice<'static>> Component for VirtualBlockDeviceComponent<B> {
+    type StaticInput = &'static mut MaybeUninit<VirtualBlockDevice<'static, B>>;
+    type Output = &'static VirtualBlockDevice<'static, B>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let device = static_buffer.write(VirtualBlockDevice::new(self.mux, self.region));
+        device.setup();
+
+        device
+    }
+}
@@ -0,0 +1,171 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component to initialize the userland CoAP driver.
+//!
+//! This provides one Component, CoapComponent. This component binds the CoAP
+//! port on the UDP mux and lets apps serve resources and send CoAP requests.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let coap_driver = components::coap::CoapComponent::new(
+//!        board_kernel,
+//!        capsules_extra::net::coap::DRIVER_NUM,
+//!        udp_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        mux_alarm,
+//!    )
+//!    .finalize(components::coap_component_static!(nrf52840::rtc::Rtc));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::net::coap::driver::{CoapDriver, COAP_PORT};
+use capsules_extra::net::ipv6::ipv6_send::IP6SendUser;
+use capsules_extra::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::udp::udp_port_table::UdpPortManager;
+use capsules_extra::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+
+const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! coap_component_static {
+    ($A:ty $(,)?) => {{
+        use components::udp_mux::MAX_PAYLOAD_LEN;
+
+        let udp_send = kernel::static_buf!(
+            capsules_extra::net::udp::udp_send::UDPSendStruct<
+                'static,
+                capsules_extra::net::ipv6::ipv6_send::IP6SendUser<'static>,
+            >
+        );
+        let udp_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+        let coap_driver = kernel::static_buf!(
+            capsules_extra::net::coap::CoapDriver<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let buffer = kernel::static_buf!([u8; MAX_PAYLOAD_LEN]);
+        let udp_recv =
+            kernel::static_buf!(capsules_extra::net::udp::udp_recv::UDPReceiver<'static>);
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+
+        (
+            udp_send,
+            udp_vis_cap,
+            net_cap,
+            coap_driver,
+            buffer,
+            udp_recv,
+            alarm,
+        )
+    };};
+}
+
+pub type CoapComponentType<A> = CoapDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+pub struct CoapComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    udp_send_mux: &'static MuxUdpSender<'static, IP6SendUser<'static>>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static>> CoapComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        udp_send_mux: &'static MuxUdpSender<'static, IP6SendUser<'static>>,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static>> Component for CoapComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<UDPSendStruct<'static, IP6SendUser<'static>>>,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<NetworkCapability>,
+        &'static mut MaybeUninit<CoapDriver<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; MAX_PAYLOAD_LEN]>,
+        &'static mut MaybeUninit<UDPReceiver<'static>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+    );
+    type Output = &'static CoapDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+
+        let coap_alarm = s.6.write(VirtualMuxAlarm::new(self.alarm_mux));
+        coap_alarm.setup();
+
+        let udp_vis = s.1.write(UdpVisibilityCapability::new(&create_cap));
+        let udp_send = s.0.write(UDPSendStruct::new(self.udp_send_mux, udp_vis));
+        let net_cap = s.2.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let buffer = s.4.write([0; MAX_PAYLOAD_LEN]);
+
+        let coap_driver = s.3.write(CoapDriver::new(
+            udp_send,
+            coap_alarm,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            kernel::utilities::leasable_buffer::SubSliceMut::new(buffer),
+            net_cap,
+        ));
+        coap_alarm.set_alarm_client(coap_driver);
+        udp_send.set_client(coap_driver);
+
+        let udp_rcvr = s.5.write(UDPReceiver::new());
+        udp_rcvr.set_client(coap_driver);
+
+        // The CoAP port is bound before any app runs, so binding only fails
+        // if the board already bound it, or ran out of sockets.
+        let socket = self.port_table.create_socket().unwrap();
+        let (tx_bind, rx_bind) = self
+            .port_table
+            .bind(socket, COAP_PORT, net_cap)
+            .unwrap_or_else(|_| panic!("CoAP port {} already bound", COAP_PORT));
+        udp_rcvr.set_binding(rx_bind);
+        udp_send.set_binding(tx_bind);
+
+        self.udp_recv_mux.add_client(udp_rcvr);
+
+        coap_driver
+    }
+}
@@ -0,0 +1,89 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the read-only FAT filesystem driver.
+//!
+//! The driver becomes the client of the block device. To share an SD card with
+//! other users, pass it a `VirtualBlockDevice`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let fat = components::fat::FatComponent::new(
+//!     board_kernel,
+//!     capsules_extra::fat::DRIVER_NUM,
+//!     sdcard_block_device,
+//! )
+//! .finalize(components::fat_component_static!(
+//!     capsules_extra::sdcard_block_device::SDCardBlockDevice<
+//!         'static,
+//!         capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<
+//!             'static,
+//!             nrf52840::rtc::Rtc,
+//!         >,
+//!     >
+//! ));
+//! ```
+
+use capsules_extra::fat::{FatDriver, SECTOR_SIZE};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::block_device::BlockDevice;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! fat_component_static {
+    ($B:ty $(,)?) => {{
+        let buffer = kernel::static_buf!([u8; capsules_extra::fat::SECTOR_SIZE]);
+        let fat = kernel::static_buf!(capsules_extra::fat::FatDriver<'static, $B>);
+
+        (buffer, fat)
+    };};
+}
+
+pub type FatComponentType<B> = FatDriver<'static, B>;
+
+pub struct FatComponent<B: 'static + BlockDevice<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    device: &'static B,
+}
+
+impl<B: 'static + BlockDevice<'static>> FatComponent<B> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        device: &'static B,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            device,
+        }
+    }
+}
+
+impl<B: 'static + BlockDevice<'static>> Component for FatComponent<B> {
+    type StaticInput = (
+        &'static mut MaybeUninit<[u8; SECTOR_SIZE]>,
+        &'static mut MaybeUninit<FatDriver<'static, B>>,
+    );
+    type Output = &'static FatDriver<'static, B>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let buffer = static_buffer.0.write([0; SECTOR_SIZE]);
+        let fat = static_buffer.1.write(FatDriver::new(
+            self.device,
+            buffer,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        self.device.set_client(fat);
+
+        fat
+    }
+}
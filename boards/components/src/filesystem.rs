@@ -0,0 +1,113 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the wear-leveling filesystem driver.
+//!
+//! The filesystem is stored in a memory mapped flash volume. `PAGES` must be
+//! at least the number of flash pages in the volume.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! kernel::storage_volume!(FS_VOLUME, 32);
+//!
+//! let filesystem = components::filesystem::FileSystemComponent::new(
+//!     board_kernel,
+//!     capsules_extra::filesystem::DRIVER_NUM,
+//!     &base_peripherals.nvmc,
+//!     &FS_VOLUME,
+//! )
+//! .finalize(components::filesystem_component_static!(
+//!     nrf52840::nvmc::Nvmc,
+//!     8
+//! ));
+//! ```
+
+use capsules_extra::filesystem::{FileSystem, PageState};
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! filesystem_component_static {
+    ($F:ty, $PAGES:expr $(,)?) => {{
+        let page = kernel::static_buf!(<$F as kernel::hil::flash::Flash>::Page);
+        let pages =
+            kernel::static_buf!([core::cell::Cell<capsules_extra::filesystem::PageState>; $PAGES]);
+        let fs = kernel::static_buf!(capsules_extra::filesystem::FileSystem<'static, $F>);
+
+        (page, pages, fs)
+    };};
+}
+
+pub type FileSystemComponentType<F> = FileSystem<'static, F>;
+
+pub struct FileSystemComponent<
+    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, FileSystem<'static, F>>,
+    const PAGES: usize,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    flash: &'static F,
+    volume: &'static [u8],
+}
+
+impl<
+        F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, FileSystem<'static, F>>,
+        const PAGES: usize,
+    > FileSystemComponent<F, PAGES>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        flash: &'static F,
+        volume: &'static [u8],
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            flash,
+            volume,
+        }
+    }
+}
+
+impl<
+        F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, FileSystem<'static, F>>,
+        const PAGES: usize,
+    > Component for FileSystemComponent<F, PAGES>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<<F as hil::flash::Flash>::Page>,
+        &'static mut MaybeUninit<[Cell<PageState>; PAGES]>,
+        &'static mut MaybeUninit<FileSystem<'static, F>>,
+    );
+    type Output = &'static FileSystem<'static, F>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let flash_pagebuffer = static_buffer
+            .0
+            .write(<F as hil::flash::Flash>::Page::default());
+        let pages = static_buffer
+            .1
+            .write(core::array::from_fn(|_| Cell::new(PageState::Free)));
+
+        let filesystem = static_buffer.2.write(FileSystem::new(
+            self.flash,
+            self.volume,
+            pages,
+            flash_pagebuffer,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        hil::flash::HasClient::set_client(self.flash, filesystem);
+
+        filesystem
+    }
+}
@@ -0,0 +1,160 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the ICMPv6 echo responder and userspace ping driver.
+//!
+//! This provides one Component, ICMP6EchoComponent. It attaches the echo
+//! responder and ping driver to the shared IPv6 layer set up by
+//! `IP6MuxComponent`, through its own `IP6SendUser` and an `IP6RecvUser` for
+//! ICMPv6. Destination Unreachable errors can be passed to the UDP driver
+//! with `set_error_client()`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let ping = ICMP6EchoComponent::new(
+//!        board_kernel,
+//!        capsules_extra::net::icmpv6::icmpv6_echo::DRIVER_NUM,
+//!        ip6_send_mux,
+//!        ip6_recv_mux,
+//!        local_ip_ifaces,
+//!        mux_alarm,
+//!     )
+//!     .finalize(components::icmp6_echo_component_static!(nrf52840::rtc::Rtc));
+//!    ping.set_error_client(udp_driver);
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::net::icmpv6::icmpv6_echo::{ICMP6Echo, BUFFER_LEN};
+use capsules_extra::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use capsules_extra::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvUser, MuxIP6Receiver};
+use capsules_extra::net::ipv6::ipv6_send::{IP6SendUser, IPSender, MuxIP6Sender};
+use capsules_extra::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! icmp6_echo_component_static {
+    ($A:ty $(,)?) => {{
+        use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
+        use capsules_extra::net::icmpv6::icmpv6_echo::BUFFER_LEN;
+
+        let echo_alarm = kernel::static_buf!(VirtualMuxAlarm<'static, $A>);
+        let ip6_send =
+            kernel::static_buf!(capsules_extra::net::ipv6::ipv6_send::IP6SendUser<'static>);
+        let ip6_receive =
+            kernel::static_buf!(capsules_extra::net::ipv6::ipv6_recv::IP6RecvUser<'static>);
+        let echo = kernel::static_buf!(
+            capsules_extra::net::icmpv6::icmpv6_echo::ICMP6Echo<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        let ip_payload = kernel::static_buf!([u8; BUFFER_LEN]);
+        let buffer = kernel::static_buf!([u8; BUFFER_LEN]);
+
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+
+        (
+            echo_alarm,
+            ip6_send,
+            ip6_receive,
+            echo,
+            ip_payload,
+            buffer,
+            net_cap,
+        )
+    };};
+}
+
+pub type ICMP6EchoComponentType<A> = ICMP6Echo<'static, VirtualMuxAlarm<'static, A>>;
+
+pub struct ICMP6EchoComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    ip6_send_mux: &'static MuxIP6Sender<'static>,
+    ip6_recv_mux: &'static MuxIP6Receiver<'static>,
+    interface_list: &'static [Cell<IPAddr>],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> ICMP6EchoComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        ip6_send_mux: &'static MuxIP6Sender<'static>,
+        ip6_recv_mux: &'static MuxIP6Receiver<'static>,
+        interface_list: &'static [Cell<IPAddr>],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            ip6_send_mux,
+            ip6_recv_mux,
+            interface_list,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for ICMP6EchoComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<IP6SendUser<'static>>,
+        &'static mut MaybeUninit<IP6RecvUser<'static>>,
+        &'static mut MaybeUninit<ICMP6Echo<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; BUFFER_LEN]>,
+        &'static mut MaybeUninit<[u8; BUFFER_LEN]>,
+        &'static mut MaybeUninit<NetworkCapability>,
+    );
+    type Output = &'static ICMP6Echo<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+
+        // The echo driver only reads the time from its alarm.
+        let echo_virtual_alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        echo_virtual_alarm.setup();
+
+        let ip_payload_buffer = s.4.write([0; BUFFER_LEN]);
+        let ip_send =
+            s.1.write(IP6SendUser::new(self.ip6_send_mux, ip_payload_buffer));
+
+        // Echo messages are ICMPv6 messages, which are also received by
+        // Neighbor Discovery if there is one.
+        let ip_receive = s.2.write(IP6RecvUser::new(ip6_nh::ICMP));
+        self.ip6_recv_mux.add_user(ip_receive);
+
+        let net_cap = s.6.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let buffer = s.5.write([0; BUFFER_LEN]);
+
+        let echo = s.3.write(ICMP6Echo::new(
+            ip_send,
+            echo_virtual_alarm,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            self.interface_list,
+            buffer,
+            net_cap,
+        ));
+        ip_send.set_client(echo);
+        ip_receive.set_client(echo);
+
+        echo
+    }
+}
@@ -0,0 +1,380 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Components for the IPv4 stack over Ethernet.
+//!
+//! This provides two Components:
+//!
+//! - IP4InterfaceComponent sets up the IPv4 interface on an Ethernet adapter
+//!   and the UDP mux on top of it. Like `UDPMuxComponent` for 6LoWPAN, it
+//!   returns the UDP sender and receiver muxes and the port table, which the
+//!   userspace UDP driver (`UDPDriverComponent` with
+//!   `ip4_udp_driver_component_static!`) and kernel UDP clients use.
+//!   `ARP_ENTRIES` is the size of the ARP cache. The interface holds its
+//!   IPv4-mapped address in the first entry of the interface list.
+//! - DhcpClientComponent starts a DHCP client configuring the interface.
+//!   Boards with a static configuration use `IP4Configure::set_config()`
+//!   instead.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (ip4_interface, udp_send_mux, udp_recv_mux, udp_port_table) =
+//!        components::ipv4::IP4InterfaceComponent::new(
+//!            ethernet_device,
+//!            mac_address,
+//!            local_ip_ifaces,
+//!            mux_alarm,
+//!        )
+//!        .finalize(components::ip4_interface_component_static!(
+//!            qemu_rv32_virt_chip::chip::QemuRv32VirtClint,
+//!            VirtIONet<'static>,
+//!            8
+//!        ));
+//!
+//!    components::ipv4::DhcpClientComponent::new(
+//!        ip4_interface,
+//!        udp_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        mux_alarm,
+//!    )
+//!    .finalize(components::dhcp_client_component_static!(
+//!        qemu_rv32_virt_chip::chip::QemuRv32VirtClint,
+//!        VirtIONet<'static>,
+//!    ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::net::ipv4::dhcp::{DhcpClient, DHCP_CLIENT_PORT, DHCP_MSG_LEN};
+use capsules_extra::net::ipv4::ip4_interface::{ArpEntry, IP4Interface, MAX_FRAME_LEN};
+use capsules_extra::net::ipv4::ip4_utils::EthernetAddr;
+use capsules_extra::net::ipv6::ip_utils::IPAddr;
+use capsules_extra::net::ipv6::ipv6_send::IPSender;
+use capsules_extra::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::udp::udp_port_table::{
+    SocketBindingEntry, UdpPortManager, MAX_NUM_BOUND_PORTS,
+};
+use capsules_extra::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::ethernet::EthernetAdapterDatapath;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! ip4_interface_component_static {
+    ($A:ty, $E:ty, $ARP_ENTRIES:expr $(,)?) => {{
+        use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
+        use capsules_extra::net::ipv4::ip4_interface::{IP4Interface, MAX_FRAME_LEN};
+
+        let alarm = kernel::static_buf!(VirtualMuxAlarm<'static, $A>);
+        let arp_cache = kernel::static_buf!(
+            [core::cell::Cell<Option<capsules_extra::net::ipv4::ip4_interface::ArpEntry>>;
+                $ARP_ENTRIES]
+        );
+        let interface =
+            kernel::static_buf!(IP4Interface<'static, $E, VirtualMuxAlarm<'static, $A>>);
+        let tx_buf = kernel::static_buf!([u8; MAX_FRAME_LEN]);
+        let control_buf = kernel::static_buf!([u8; MAX_FRAME_LEN]);
+        let mux_udp_send = kernel::static_buf!(
+            capsules_extra::net::udp::udp_send::MuxUdpSender<
+                'static,
+                IP4Interface<'static, $E, VirtualMuxAlarm<'static, $A>>,
+            >
+        );
+        let mux_udp_recv =
+            kernel::static_buf!(capsules_extra::net::udp::udp_recv::MuxUdpReceiver<'static>);
+        let udp_port_manager =
+            kernel::static_buf!(capsules_extra::net::udp::udp_port_table::UdpPortManager);
+        let used_ports = kernel::static_buf!(
+            [Option<capsules_extra::net::udp::udp_port_table::SocketBindingEntry>;
+                capsules_extra::net::udp::udp_port_table::MAX_NUM_BOUND_PORTS]
+        );
+        let udp_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let ip_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::IpVisibilityCapability);
+
+        (
+            alarm,
+            arp_cache,
+            interface,
+            tx_buf,
+            control_buf,
+            mux_udp_send,
+            mux_udp_recv,
+            udp_port_manager,
+            used_ports,
+            udp_vis_cap,
+            ip_vis_cap,
+        )
+    };};
+}
+
+/// Static space for the userspace UDP driver on top of the IPv4 interface,
+/// for `UDPDriverComponent`.
+#[macro_export]
+macro_rules! ip4_udp_driver_component_static {
+    ($A:ty, $E:ty $(,)?) => {{
+        $crate::udp_driver_component_static!(
+            @sender capsules_extra::net::ipv4::ip4_interface::IP4Interface<
+                'static,
+                $E,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        )
+    };};
+}
+
+#[macro_export]
+macro_rules! dhcp_client_component_static {
+    ($A:ty, $E:ty $(,)?) => {{
+        use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
+        use capsules_extra::net::ipv4::ip4_interface::IP4Interface;
+
+        let udp_send = kernel::static_buf!(
+            capsules_extra::net::udp::udp_send::UDPSendStruct<
+                'static,
+                IP4Interface<'static, $E, VirtualMuxAlarm<'static, $A>>,
+            >
+        );
+        let udp_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+        let dhcp_client = kernel::static_buf!(
+            capsules_extra::net::ipv4::dhcp::DhcpClient<'static, VirtualMuxAlarm<'static, $A>>
+        );
+        let buffer = kernel::static_buf!([u8; capsules_extra::net::ipv4::dhcp::DHCP_MSG_LEN]);
+        let udp_recv =
+            kernel::static_buf!(capsules_extra::net::udp::udp_recv::UDPReceiver<'static>);
+        let alarm = kernel::static_buf!(VirtualMuxAlarm<'static, $A>);
+
+        (
+            udp_send,
+            udp_vis_cap,
+            net_cap,
+            dhcp_client,
+            buffer,
+            udp_recv,
+            alarm,
+        )
+    };};
+}
+
+pub type IP4InterfaceComponentType<A, E> = IP4Interface<'static, E, VirtualMuxAlarm<'static, A>>;
+
+pub type DhcpClientComponentType<A> = DhcpClient<'static, VirtualMuxAlarm<'static, A>>;
+
+pub struct IP4InterfaceComponent<
+    A: Alarm<'static> + 'static,
+    E: EthernetAdapterDatapath<'static> + 'static,
+    const ARP_ENTRIES: usize,
+> {
+    ethernet: &'static E,
+    mac_address: EthernetAddr,
+    interface_list: &'static [Cell<IPAddr>],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<
+        A: Alarm<'static> + 'static,
+        E: EthernetAdapterDatapath<'static> + 'static,
+        const ARP_ENTRIES: usize,
+    > IP4InterfaceComponent<A, E, ARP_ENTRIES>
+{
+    pub fn new(
+        ethernet: &'static E,
+        mac_address: EthernetAddr,
+        interface_list: &'static [Cell<IPAddr>],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            ethernet,
+            mac_address,
+            interface_list,
+            alarm_mux,
+        }
+    }
+}
+
+impl<
+        A: Alarm<'static> + 'static,
+        E: EthernetAdapterDatapath<'static> + 'static,
+        const ARP_ENTRIES: usize,
+    > Component for IP4InterfaceComponent<A, E, ARP_ENTRIES>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<[Cell<Option<ArpEntry>>; ARP_ENTRIES]>,
+        &'static mut MaybeUninit<IP4Interface<'static, E, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; MAX_FRAME_LEN]>,
+        &'static mut MaybeUninit<[u8; MAX_FRAME_LEN]>,
+        &'static mut MaybeUninit<
+            MuxUdpSender<'static, IP4Interface<'static, E, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<MuxUdpReceiver<'static>>,
+        &'static mut MaybeUninit<UdpPortManager>,
+        &'static mut MaybeUninit<[Option<SocketBindingEntry>; MAX_NUM_BOUND_PORTS]>,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<IpVisibilityCapability>,
+    );
+    type Output = (
+        &'static IP4Interface<'static, E, VirtualMuxAlarm<'static, A>>,
+        &'static MuxUdpSender<'static, IP4Interface<'static, E, VirtualMuxAlarm<'static, A>>>,
+        &'static MuxUdpReceiver<'static>,
+        &'static UdpPortManager,
+    );
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let udp_vis = s.9.write(UdpVisibilityCapability::new(&create_cap));
+        let ip_vis = s.10.write(IpVisibilityCapability::new(&create_cap));
+
+        let ip4_alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        ip4_alarm.setup();
+
+        let arp_cache = s.1.write(core::array::from_fn(|_| Cell::new(None)));
+        let tx_buf = s.3.write([0; MAX_FRAME_LEN]);
+        let control_buf = s.4.write([0; MAX_FRAME_LEN]);
+
+        // The interface is unconfigured until the board or the DHCP client
+        // sets its address.
+        self.interface_list[0].set(IPAddr::new());
+        let interface = s.2.write(IP4Interface::new(
+            self.ethernet,
+            ip4_alarm,
+            self.mac_address,
+            &self.interface_list[0],
+            arp_cache,
+            tx_buf,
+            control_buf,
+            ip_vis,
+        ));
+        ip4_alarm.set_alarm_client(interface);
+
+        let udp_send_mux = s.5.write(MuxUdpSender::new(interface));
+        interface.set_client(udp_send_mux);
+        let udp_recv_mux = s.6.write(MuxUdpReceiver::new());
+        interface.set_receive_client(udp_recv_mux);
+
+        let kernel_ports = s.8.write([None; MAX_NUM_BOUND_PORTS]);
+        let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
+        let udp_port_table = s.7.write(UdpPortManager::new(
+            &create_table_cap,
+            kernel_ports,
+            udp_vis,
+        ));
+
+        self.ethernet.set_client(interface);
+        self.ethernet.enable_receive();
+
+        (interface, udp_send_mux, udp_recv_mux, udp_port_table)
+    }
+}
+
+pub struct DhcpClientComponent<
+    A: Alarm<'static> + 'static,
+    E: EthernetAdapterDatapath<'static> + 'static,
+> {
+    interface: &'static IP4Interface<'static, E, VirtualMuxAlarm<'static, A>>,
+    udp_send_mux:
+        &'static MuxUdpSender<'static, IP4Interface<'static, E, VirtualMuxAlarm<'static, A>>>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static, E: EthernetAdapterDatapath<'static> + 'static>
+    DhcpClientComponent<A, E>
+{
+    pub fn new(
+        interface: &'static IP4Interface<'static, E, VirtualMuxAlarm<'static, A>>,
+        udp_send_mux: &'static MuxUdpSender<
+            'static,
+            IP4Interface<'static, E, VirtualMuxAlarm<'static, A>>,
+        >,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            interface,
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static, E: EthernetAdapterDatapath<'static> + 'static> Component
+    for DhcpClientComponent<A, E>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<
+            UDPSendStruct<'static, IP4Interface<'static, E, VirtualMuxAlarm<'static, A>>>,
+        >,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<NetworkCapability>,
+        &'static mut MaybeUninit<DhcpClient<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; DHCP_MSG_LEN]>,
+        &'static mut MaybeUninit<UDPReceiver<'static>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+    );
+    type Output = &'static DhcpClient<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+
+        let dhcp_alarm = s.6.write(VirtualMuxAlarm::new(self.alarm_mux));
+        dhcp_alarm.setup();
+
+        let udp_vis = s.1.write(UdpVisibilityCapability::new(&create_cap));
+        let udp_send = s.0.write(UDPSendStruct::new(self.udp_send_mux, udp_vis));
+        let net_cap = s.2.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let buffer = s.4.write([0; DHCP_MSG_LEN]);
+
+        let dhcp_client = s.3.write(DhcpClient::new(
+            self.interface,
+            udp_send,
+            dhcp_alarm,
+            kernel::utilities::leasable_buffer::SubSliceMut::new(buffer),
+            net_cap,
+        ));
+        dhcp_alarm.set_alarm_client(dhcp_client);
+        udp_send.set_client(dhcp_client);
+
+        let udp_rcvr = s.5.write(UDPReceiver::new());
+        udp_rcvr.set_client(dhcp_client);
+
+        // The DHCP client port is bound before any app runs, so binding only
+        // fails if the board already bound it, or ran out of sockets.
+        let socket = self.port_table.create_socket().unwrap();
+        let (tx_bind, rx_bind) = self
+            .port_table
+            .bind(socket, DHCP_CLIENT_PORT, net_cap)
+            .unwrap_or_else(|_| panic!("DHCP client port {} already bound", DHCP_CLIENT_PORT));
+        udp_rcvr.set_binding(rx_bind);
+        udp_send.set_binding(tx_bind);
+
+        self.udp_recv_mux.add_client(udp_rcvr);
+        let _ = dhcp_client.start();
+
+        dhcp_client
+    }
+}
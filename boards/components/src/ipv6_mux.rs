@@ -0,0 +1,232 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component to initialize the IPv6/6LoWPAN layer shared by the UDP, TCP and
+//! ICMPv6 stacks.
+//!
+//! This provides one Component, IP6MuxComponent. It sets up a single MAC user,
+//! 6LoWPAN instance, IPv6 sender and IPv6 receiver on top of the MAC layer,
+//! and returns the `MuxIP6Sender` and `MuxIP6Receiver` that share them. The
+//! UDP, TCP, Neighbor Discovery and ICMPv6 echo components attach to these
+//! muxes, so every received frame is reassembled and decompressed once and
+//! then passed to the protocol of its next header.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (ip6_send_mux, ip6_recv_mux) = IP6MuxComponent::new(
+//!        mux_mac,
+//!        DEFAULT_CTX_PREFIX_LEN,
+//!        DEFAULT_CTX_PREFIX,
+//!        DST_MAC_ADDR,
+//!        src_mac_from_serial_num,
+//!        mux_alarm,
+//!    )
+//!    .finalize(components::ip6_mux_component_static!(
+//!        nrf52840::rtc::Rtc,
+//!        Ieee802154MacDevice,
+//!    ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::ieee802154::device::MacDevice;
+use capsules_extra::net::ieee802154::MacAddress;
+use capsules_extra::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct, MuxIP6Receiver};
+use capsules_extra::net::ipv6::ipv6_send::{IP6SendStruct, IPSender, MuxIP6Sender};
+use capsules_extra::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules_extra::net::network_capabilities::IpVisibilityCapability;
+use capsules_extra::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules_extra::net::udp::UDPHeader;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+
+/// The largest transport payload sent by any user of the IPv6 sender.
+pub const MAX_IP6_PAYLOAD_LEN: usize = 200;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! ip6_mux_component_static {
+    ($A:ty, $M:ty $(,)?) => {{
+        use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
+        use capsules_extra::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+        use components::ipv6_mux::MAX_IP6_PAYLOAD_LEN;
+
+        let alarm = kernel::static_buf!(VirtualMuxAlarm<'static, $A>);
+        let mac_user =
+            kernel::static_buf!(capsules_extra::ieee802154::virtual_mac::MacUser<'static, $M>);
+        let sixlowpan = kernel::static_buf!(
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+                sixlowpan_compression::Context,
+            >
+        );
+        let rx_state = kernel::static_buf!(sixlowpan_state::RxState<'static>);
+        let ip6_send = kernel::static_buf!(
+            capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+            >
+        );
+        let ip6_send_mux =
+            kernel::static_buf!(capsules_extra::net::ipv6::ipv6_send::MuxIP6Sender<'static>);
+        let ip6_packet = kernel::static_buf!(capsules_extra::net::ipv6::IP6Packet<'static>);
+        let ip6_receive =
+            kernel::static_buf!(capsules_extra::net::ipv6::ipv6_recv::IP6RecvStruct<'static>);
+        let ip6_recv_mux =
+            kernel::static_buf!(capsules_extra::net::ipv6::ipv6_recv::MuxIP6Receiver<'static>);
+
+        let radio_buf = kernel::static_buf!([u8; kernel::hil::radio::MAX_BUF_SIZE]);
+        let sixlowpan_rx = kernel::static_buf!([u8; 1280]);
+        let ip6_payload = kernel::static_buf!([u8; MAX_IP6_PAYLOAD_LEN]);
+
+        let ip_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::IpVisibilityCapability);
+
+        (
+            alarm,
+            mac_user,
+            sixlowpan,
+            rx_state,
+            ip6_send,
+            ip6_send_mux,
+            ip6_packet,
+            ip6_receive,
+            ip6_recv_mux,
+            radio_buf,
+            sixlowpan_rx,
+            ip6_payload,
+            ip_vis_cap,
+        )
+    };};
+}
+
+pub struct IP6MuxComponent<A: Alarm<'static> + 'static, M: MacDevice<'static> + 'static> {
+    mux_mac: &'static capsules_extra::ieee802154::virtual_mac::MuxMac<'static, M>,
+    ctx_pfix_len: u8,
+    ctx_pfix: [u8; 16],
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static, M: MacDevice<'static>> IP6MuxComponent<A, M> {
+    pub fn new(
+        mux_mac: &'static capsules_extra::ieee802154::virtual_mac::MuxMac<'static, M>,
+        ctx_pfix_len: u8,
+        ctx_pfix: [u8; 16],
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            mux_mac,
+            ctx_pfix_len,
+            ctx_pfix,
+            dst_mac_addr,
+            src_mac_addr,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static, M: MacDevice<'static>> Component for IP6MuxComponent<A, M> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<capsules_extra::ieee802154::virtual_mac::MacUser<'static, M>>,
+        &'static mut MaybeUninit<
+            sixlowpan_state::Sixlowpan<
+                'static,
+                VirtualMuxAlarm<'static, A>,
+                sixlowpan_compression::Context,
+            >,
+        >,
+        &'static mut MaybeUninit<sixlowpan_state::RxState<'static>>,
+        &'static mut MaybeUninit<IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<MuxIP6Sender<'static>>,
+        &'static mut MaybeUninit<IP6Packet<'static>>,
+        &'static mut MaybeUninit<IP6RecvStruct<'static>>,
+        &'static mut MaybeUninit<MuxIP6Receiver<'static>>,
+        &'static mut MaybeUninit<[u8; radio::MAX_BUF_SIZE]>,
+        &'static mut MaybeUninit<[u8; 1280]>,
+        &'static mut MaybeUninit<[u8; MAX_IP6_PAYLOAD_LEN]>,
+        &'static mut MaybeUninit<IpVisibilityCapability>,
+    );
+    type Output = (
+        &'static MuxIP6Sender<'static>,
+        &'static MuxIP6Receiver<'static>,
+    );
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let ipsender_virtual_alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        ipsender_virtual_alarm.setup();
+
+        let ip6_mac =
+            s.1.write(capsules_extra::ieee802154::virtual_mac::MacUser::new(
+                self.mux_mac,
+            ));
+        self.mux_mac.add_user(ip6_mac);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = s.12.write(IpVisibilityCapability::new(&create_cap));
+
+        let sixlowpan = s.2.write(sixlowpan_state::Sixlowpan::new(
+            sixlowpan_compression::Context {
+                prefix: self.ctx_pfix,
+                prefix_len: self.ctx_pfix_len,
+                id: 0,
+                compress: false,
+            },
+            ipsender_virtual_alarm, // OK to reuse bc only used to get time, not set alarms
+        ));
+
+        let sixlowpan_rx_buffer = s.10.write([0; 1280]);
+        let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
+        let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
+        let default_rx_state =
+            s.3.write(sixlowpan_state::RxState::new(sixlowpan_rx_buffer));
+        sixlowpan_state.add_rx_state(default_rx_state);
+        ip6_mac.set_receive_client(sixlowpan);
+
+        // The transport header is replaced by that of each packet sent.
+        let ip6_payload_buffer = s.11.write([0; MAX_IP6_PAYLOAD_LEN]);
+        let ip_pyld: IPPayload = IPPayload {
+            header: TransportHeader::UDP(UDPHeader::new()),
+            payload: ip6_payload_buffer,
+        };
+        let ip6_dg = s.6.write(IP6Packet::new(ip_pyld));
+
+        let radio_buf = s.9.write([0; radio::MAX_BUF_SIZE]);
+
+        // All users share the IP sender, which holds the destination mac
+        // address. This means all packets are sent to the same mac address
+        // unless the board sets up Neighbor Discovery (see `ipv6_nd.rs`) as
+        // the next hop of the sender.
+        let ip_send = s.4.write(IP6SendStruct::new(
+            ip6_dg,
+            ipsender_virtual_alarm,
+            radio_buf,
+            sixlowpan_tx,
+            ip6_mac,
+            self.dst_mac_addr,
+            self.src_mac_addr,
+            ip_vis,
+        ));
+        ipsender_virtual_alarm.set_alarm_client(ip_send);
+        ip6_mac.set_transmit_client(ip_send);
+
+        let ip6_send_mux = s.5.write(MuxIP6Sender::new(ip_send));
+        ip_send.set_client(ip6_send_mux);
+
+        let ip_receive = s.7.write(IP6RecvStruct::new());
+        sixlowpan_state.set_rx_client(ip_receive);
+        let ip6_recv_mux = s.8.write(MuxIP6Receiver::new());
+        ip_receive.set_client(ip6_recv_mux);
+
+        (ip6_send_mux, ip6_recv_mux)
+    }
+}
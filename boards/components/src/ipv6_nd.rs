@@ -0,0 +1,173 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for IPv6 Neighbor Discovery and address autoconfiguration.
+//!
+//! This provides one Component, NeighborDiscoveryComponent. It attaches
+//! Neighbor Discovery to the shared IPv6 layer set up by `IP6MuxComponent`,
+//! through its own `IP6SendUser` and an `IP6RecvUser` for ICMPv6. Neighbor
+//! Discovery adds autoconfigured addresses to free (unspecified) slots of the
+//! interface list, and is set as the next hop of the shared IPv6 sender, so
+//! it is used for the packets of every protocol. `NEIGHBORS` is the size of
+//! the neighbor cache.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let nd = NeighborDiscoveryComponent::new(
+//!        ip6_send_mux,
+//!        ip6_recv_mux,
+//!        eui64_driver,
+//!        local_ip_ifaces,
+//!        mux_alarm,
+//!     )
+//!     .finalize(components::neighbor_discovery_component_static!(
+//!         nrf52840::rtc::Rtc,
+//!         8
+//!     ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::eui64::Eui64;
+use capsules_extra::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use capsules_extra::net::ipv6::ipv6_nd::{Neighbor, NeighborDiscovery, BUFFER_LEN};
+use capsules_extra::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvUser, MuxIP6Receiver};
+use capsules_extra::net::ipv6::ipv6_send::{IP6SendUser, IP6Sender, IPSender, MuxIP6Sender};
+use capsules_extra::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! neighbor_discovery_component_static {
+    ($A:ty, $NEIGHBORS:expr $(,)?) => {{
+        use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
+        use capsules_extra::net::ipv6::ipv6_nd::BUFFER_LEN;
+
+        let nd_alarm = kernel::static_buf!(VirtualMuxAlarm<'static, $A>);
+        let ip6_send =
+            kernel::static_buf!(capsules_extra::net::ipv6::ipv6_send::IP6SendUser<'static>);
+        let ip6_receive =
+            kernel::static_buf!(capsules_extra::net::ipv6::ipv6_recv::IP6RecvUser<'static>);
+        let neighbors = kernel::static_buf!(
+            [core::cell::Cell<Option<capsules_extra::net::ipv6::ipv6_nd::Neighbor>>; $NEIGHBORS]
+        );
+        let nd = kernel::static_buf!(
+            capsules_extra::net::ipv6::ipv6_nd::NeighborDiscovery<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+            >
+        );
+
+        let ip_payload = kernel::static_buf!([u8; BUFFER_LEN]);
+        let buffer = kernel::static_buf!([u8; BUFFER_LEN]);
+
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+
+        (
+            nd_alarm,
+            ip6_send,
+            ip6_receive,
+            neighbors,
+            nd,
+            ip_payload,
+            buffer,
+            net_cap,
+        )
+    };};
+}
+
+pub type NeighborDiscoveryComponentType<A> =
+    NeighborDiscovery<'static, VirtualMuxAlarm<'static, A>>;
+
+pub struct NeighborDiscoveryComponent<A: Alarm<'static> + 'static, const NEIGHBORS: usize> {
+    ip6_send_mux: &'static MuxIP6Sender<'static>,
+    ip6_recv_mux: &'static MuxIP6Receiver<'static>,
+    eui64: &'static Eui64,
+    interface_list: &'static [Cell<IPAddr>],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static, const NEIGHBORS: usize> NeighborDiscoveryComponent<A, NEIGHBORS> {
+    pub fn new(
+        ip6_send_mux: &'static MuxIP6Sender<'static>,
+        ip6_recv_mux: &'static MuxIP6Receiver<'static>,
+        eui64: &'static Eui64,
+        interface_list: &'static [Cell<IPAddr>],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            ip6_send_mux,
+            ip6_recv_mux,
+            eui64,
+            interface_list,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static, const NEIGHBORS: usize> Component
+    for NeighborDiscoveryComponent<A, NEIGHBORS>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<IP6SendUser<'static>>,
+        &'static mut MaybeUninit<IP6RecvUser<'static>>,
+        &'static mut MaybeUninit<[Cell<Option<Neighbor>>; NEIGHBORS]>,
+        &'static mut MaybeUninit<NeighborDiscovery<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; BUFFER_LEN]>,
+        &'static mut MaybeUninit<[u8; BUFFER_LEN]>,
+        &'static mut MaybeUninit<NetworkCapability>,
+    );
+    type Output = &'static NeighborDiscovery<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+
+        let nd_virtual_alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        nd_virtual_alarm.setup();
+
+        let ip_payload_buffer = s.5.write([0; BUFFER_LEN]);
+        let ip_send =
+            s.1.write(IP6SendUser::new(self.ip6_send_mux, ip_payload_buffer));
+
+        // Neighbor Discovery messages are ICMPv6 messages, which are also
+        // received by the ICMPv6 echo responder if there is one.
+        let ip_receive = s.2.write(IP6RecvUser::new(ip6_nh::ICMP));
+        self.ip6_recv_mux.add_user(ip_receive);
+
+        let net_cap = s.7.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let neighbors = s.3.write(core::array::from_fn(|_| Cell::new(None)));
+        let buffer = s.6.write([0; BUFFER_LEN]);
+
+        let nd = s.4.write(NeighborDiscovery::new(
+            ip_send,
+            nd_virtual_alarm,
+            self.eui64.eui64(),
+            self.interface_list,
+            neighbors,
+            buffer,
+            net_cap,
+        ));
+        nd_virtual_alarm.set_alarm_client(nd);
+        ip_send.set_client(nd);
+        // The next hop is that of the shared sender, used by every protocol.
+        ip_send.set_next_hop(nd);
+        ip_receive.set_client(nd);
+        let _ = nd.start();
+
+        nd
+    }
+}
@@ -4,16 +4,23 @@
 
 //! Components for KV stack capsules.
 
+use capsules_core::virtualizers::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
 use capsules_extra::kv_driver::KVStoreDriver;
+use capsules_extra::kv_store_encryption::KVStoreEncryption;
 use capsules_extra::kv_store_permissions::KVStorePermissions;
 use capsules_extra::tickv::{KVSystem, KeyType};
 use capsules_extra::tickv_kv_store::TicKVKVStore;
 use capsules_extra::virtualizers::virtual_kv::{MuxKVPermissions, VirtualKVPermissions};
+use core::cell::Cell;
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
+use kernel::deferred_call::DeferredCallClient;
 use kernel::hil;
+use kernel::hil::symmetric_encryption::{
+    AES128Ctr, AES128, AES128CBC, AES128CCM, AES128ECB, AES128_KEY_SIZE,
+};
 
 ///////////////////////
 // KV Userspace Driver
@@ -158,32 +165,50 @@ impl<V: hil::kv::KVPermissions<'static> + 'static> Component for VirtualKVPermis
 #[macro_export]
 macro_rules! kv_store_permissions_component_static {
     ($V:ty $(,)?) => {{
+        $crate::kv_store_permissions_component_static!(
+            $V,
+            capsules_extra::kv_store_permissions::DEFAULT_WRITE_ID_COUNTERS
+        )
+    };};
+    ($V:ty, $COUNTERS:expr $(,)?) => {{
         let buffer = kernel::static_buf!([u8; capsules_extra::kv_store_permissions::HEADER_LENGTH]);
+        let scan_key =
+            kernel::static_buf!([u8; capsules_extra::kv_store_permissions::SCAN_KEY_LENGTH]);
+        let counters =
+            kernel::static_buf!([capsules_extra::kv_store_permissions::WriteIdCounter; $COUNTERS]);
         let kv_store = kernel::static_buf!(
             capsules_extra::kv_store_permissions::KVStorePermissions<'static, $V>
         );
 
-        (kv_store, buffer)
+        (kv_store, buffer, scan_key, counters)
     };};
 }
 
 pub type KVStorePermissionsComponentType<V> =
     capsules_extra::kv_store_permissions::KVStorePermissions<'static, V>;
 
-pub struct KVStorePermissionsComponent<V: hil::kv::KV<'static> + 'static> {
+/// `COUNTERS` is the number of `write_id`s whose storage usage is counted
+/// for quotas.
+pub struct KVStorePermissionsComponent<V: hil::kv::KV<'static> + 'static, const COUNTERS: usize> {
     kv: &'static V,
 }
 
-impl<V: hil::kv::KV<'static> + 'static> KVStorePermissionsComponent<V> {
+impl<V: hil::kv::KV<'static> + 'static, const COUNTERS: usize>
+    KVStorePermissionsComponent<V, COUNTERS>
+{
     pub fn new(kv: &'static V) -> Self {
         Self { kv }
     }
 }
 
-impl<V: hil::kv::KV<'static> + 'static> Component for KVStorePermissionsComponent<V> {
+impl<V: hil::kv::KV<'static> + 'static, const COUNTERS: usize> Component
+    for KVStorePermissionsComponent<V, COUNTERS>
+{
     type StaticInput = (
         &'static mut MaybeUninit<KVStorePermissions<'static, V>>,
         &'static mut MaybeUninit<[u8; capsules_extra::kv_store_permissions::HEADER_LENGTH]>,
+        &'static mut MaybeUninit<[u8; capsules_extra::kv_store_permissions::SCAN_KEY_LENGTH]>,
+        &'static mut MaybeUninit<[capsules_extra::kv_store_permissions::WriteIdCounter; COUNTERS]>,
     );
     type Output = &'static KVStorePermissions<'static, V>;
 
@@ -191,12 +216,18 @@ impl<V: hil::kv::KV<'static> + 'static> Component for KVStorePermissionsComponen
         let buffer = static_buffer
             .1
             .write([0; capsules_extra::kv_store_permissions::HEADER_LENGTH]);
+        let scan_key = static_buffer
+            .2
+            .write([0; capsules_extra::kv_store_permissions::SCAN_KEY_LENGTH]);
+
+        let counters = static_buffer.3.write([const { Cell::new(None) }; COUNTERS]);
 
         let kv_store_permissions = static_buffer
             .0
-            .write(KVStorePermissions::new(self.kv, buffer));
+            .write(KVStorePermissions::new(self.kv, buffer, scan_key, counters));
 
         self.kv.set_client(kv_store_permissions);
+        kv_store_permissions.register();
 
         kv_store_permissions
     }
@@ -251,3 +282,123 @@ impl<K: 'static + KVSystem<'static, K = T>, T: 'static + KeyType + Default> Comp
         kv_store
     }
 }
+
+/////////////////////
+// KV Store Encryption
+/////////////////////
+
+/// Length of the longest value that can be stored.
+pub const KV_STORE_ENCRYPTION_VALUE_LENGTH: usize = 512;
+/// Length of the longest key that can be stored, which matches the key buffer
+/// of the KV driver.
+pub const KV_STORE_ENCRYPTION_KEY_LENGTH: usize = 64;
+const ENCRYPTED_VALUE_LENGTH: usize = KV_STORE_ENCRYPTION_KEY_LENGTH
+    + KV_STORE_ENCRYPTION_VALUE_LENGTH
+    + capsules_extra::kv_store_encryption::OVERHEAD;
+const CRYPT_SIZE: usize = ENCRYPTED_VALUE_LENGTH + 2 * hil::symmetric_encryption::AES128_BLOCK_SIZE;
+
+#[macro_export]
+macro_rules! kv_store_encryption_component_static {
+    ($V:ty, $A:ty, $R:ty $(,)?) => {{
+        let virtual_aes = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_aes_ccm::VirtualAES128CCM<'static, $A>
+        );
+        let crypt_buf = kernel::static_buf!(
+            [u8; $crate::kv::KV_STORE_ENCRYPTION_KEY_LENGTH
+                + $crate::kv::KV_STORE_ENCRYPTION_VALUE_LENGTH
+                + capsules_extra::kv_store_encryption::OVERHEAD
+                + 2 * kernel::hil::symmetric_encryption::AES128_BLOCK_SIZE]
+        );
+        let buffer = kernel::static_buf!(
+            [u8; $crate::kv::KV_STORE_ENCRYPTION_KEY_LENGTH
+                + $crate::kv::KV_STORE_ENCRYPTION_VALUE_LENGTH
+                + capsules_extra::kv_store_encryption::OVERHEAD]
+        );
+        let kv_store = kernel::static_buf!(
+            capsules_extra::kv_store_encryption::KVStoreEncryption<
+                'static,
+                $V,
+                capsules_core::virtualizers::virtual_aes_ccm::VirtualAES128CCM<'static, $A>,
+                $R,
+            >
+        );
+
+        (kv_store, virtual_aes, crypt_buf, buffer)
+    };};
+}
+
+pub type KVStoreEncryptionComponentType<V, A, R> =
+    capsules_extra::kv_store_encryption::KVStoreEncryption<
+        'static,
+        V,
+        VirtualAES128CCM<'static, A>,
+        R,
+    >;
+
+pub struct KVStoreEncryptionComponent<
+    V: hil::kv::KV<'static> + 'static,
+    A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB,
+    R: hil::rng::Rng<'static> + 'static,
+> {
+    kv: &'static V,
+    aes_mux: &'static MuxAES128CCM<'static, A>,
+    rng: &'static R,
+    key: [u8; AES128_KEY_SIZE],
+}
+
+impl<
+        V: hil::kv::KV<'static> + 'static,
+        A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB,
+        R: hil::rng::Rng<'static> + 'static,
+    > KVStoreEncryptionComponent<V, A, R>
+{
+    /// `key` is the AES-128 key used to encrypt the values. It should be
+    /// unique to each device.
+    pub fn new(
+        kv: &'static V,
+        aes_mux: &'static MuxAES128CCM<'static, A>,
+        rng: &'static R,
+        key: [u8; AES128_KEY_SIZE],
+    ) -> Self {
+        Self {
+            kv,
+            aes_mux,
+            rng,
+            key,
+        }
+    }
+}
+
+impl<
+        V: hil::kv::KV<'static> + 'static,
+        A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB,
+        R: hil::rng::Rng<'static> + 'static,
+    > Component for KVStoreEncryptionComponent<V, A, R>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<KVStoreEncryptionComponentType<V, A, R>>,
+        &'static mut MaybeUninit<VirtualAES128CCM<'static, A>>,
+        &'static mut MaybeUninit<[u8; CRYPT_SIZE]>,
+        &'static mut MaybeUninit<[u8; ENCRYPTED_VALUE_LENGTH]>,
+    );
+    type Output = &'static KVStoreEncryptionComponentType<V, A, R>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let crypt_buf = static_buffer.2.write([0; CRYPT_SIZE]);
+        let aes_ccm = static_buffer
+            .1
+            .write(VirtualAES128CCM::new(self.aes_mux, crypt_buf));
+        aes_ccm.setup();
+
+        let buffer = static_buffer.3.write([0; ENCRYPTED_VALUE_LENGTH]);
+        let kv_store_encryption = static_buffer.0.write(KVStoreEncryption::new(
+            self.kv, aes_ccm, self.rng, self.key, buffer,
+        ));
+
+        self.kv.set_client(kv_store_encryption);
+        AES128CCM::set_client(aes_ccm, kv_store_encryption);
+        self.rng.set_client(kv_store_encryption);
+
+        kv_store_encryption
+    }
+}
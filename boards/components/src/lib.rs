@@ -16,6 +16,7 @@ pub mod app_loader;
 pub mod appid;
 pub mod atecc508a;
 pub mod ble;
+pub mod block_device;
 pub mod bme280;
 pub mod bmm150;
 pub mod bmp280;
@@ -25,6 +26,7 @@ pub mod can;
 pub mod ccs811;
 pub mod cdc;
 pub mod chirp_i2c_moisture;
+pub mod coap;
 pub mod console;
 pub mod crc;
 pub mod ctap;
@@ -34,6 +36,8 @@ pub mod debug_writer;
 pub mod dfrobot_rainfall_sensor;
 pub mod dynamic_binary_storage;
 pub mod eui64;
+pub mod fat;
+pub mod filesystem;
 pub mod flash;
 pub mod fm25cl;
 pub mod ft6x06;
@@ -45,7 +49,11 @@ pub mod hs3003;
 pub mod hts221;
 pub mod humidity;
 pub mod i2c;
+pub mod icmpv6_echo;
 pub mod ieee802154;
+pub mod ipv4;
+pub mod ipv6_mux;
+pub mod ipv6_nd;
 pub mod isl29035;
 pub mod isolated_nonvolatile_storage;
 pub mod keyboard_hid;
@@ -62,8 +70,10 @@ pub mod lsm303agr;
 pub mod lsm303dlhc;
 pub mod lsm6dsox;
 pub mod ltc294x;
+pub mod message_ipc;
 pub mod mlx90614;
 pub mod moisture;
+pub mod monotonic_counter;
 pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage;
@@ -72,8 +82,11 @@ pub mod panic_button;
 pub mod pressure;
 pub mod process_array;
 pub mod process_console;
+pub mod process_fault_dump;
 pub mod process_info_driver;
+pub mod process_policies;
 pub mod process_printer;
+pub mod process_watchdog;
 pub mod proximity;
 pub mod pwm;
 pub mod rainfall;
@@ -96,6 +109,7 @@ pub mod spi;
 pub mod ssd1306;
 pub mod st77xx;
 pub mod storage_permissions;
+pub mod tcp_driver;
 pub mod temperature;
 pub mod temperature_rp2040;
 pub mod temperature_stm;
@@ -106,4 +120,5 @@ pub mod tickv;
 pub mod touch;
 pub mod udp_driver;
 pub mod udp_mux;
+pub mod update_rollback;
 pub mod usb;
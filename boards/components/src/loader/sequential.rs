@@ -24,8 +24,9 @@ macro_rules! process_loader_sequential_component_static {
         let process_binary_array = kernel::static_buf!(
             [Option<kernel::process::ProcessBinary>; $NUMPROCS]
         );
+        let spare_memory_array = kernel::static_buf!([Option<&'static mut [u8]>; $NUMPROCS]);
 
-       (loader, process_binary_array)
+       (loader, process_binary_array, spare_memory_array)
     };};
 }
 
@@ -79,6 +80,7 @@ impl<C: Chip, D: ProcessStandardDebug, const NUM_PROCS: usize> Component
     type StaticInput = (
         &'static mut MaybeUninit<kernel::process::SequentialProcessLoaderMachine<'static, C, D>>,
         &'static mut MaybeUninit<[Option<kernel::process::ProcessBinary>; NUM_PROCS]>,
+        &'static mut MaybeUninit<[Option<&'static mut [u8]>; NUM_PROCS]>,
     );
 
     type Output = &'static kernel::process::SequentialProcessLoaderMachine<'static, C, D>;
@@ -89,11 +91,13 @@ impl<C: Chip, D: ProcessStandardDebug, const NUM_PROCS: usize> Component
 
         const ARRAY_REPEAT_VALUE: Option<kernel::process::ProcessBinary> = None;
         let process_binary_array = s.1.write([ARRAY_REPEAT_VALUE; NUM_PROCS]);
+        let spare_memory_array = s.2.write([const { None }; NUM_PROCS]);
 
         let loader =
             s.0.write(kernel::process::SequentialProcessLoaderMachine::new(
                 self.checker,
                 process_binary_array,
+                spare_memory_array,
                 self.kernel,
                 self.chip,
                 self.app_flash,
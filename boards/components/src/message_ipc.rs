@@ -0,0 +1,96 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component for message passing IPC.
+//!
+//! `MAX_MESSAGE_LEN` is the maximum length of a message in bytes.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let message_ipc = components::message_ipc::MessageIpcComponent::new(
+//!     board_kernel,
+//!     kernel::ipc_message::DRIVER_NUM,
+//!     mux_alarm,
+//! )
+//! .finalize(components::message_ipc_component_static!(nrf52840::rtc::Rtc, 64));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::{self, Alarm};
+use kernel::ipc_message::MessageIpc;
+
+#[macro_export]
+macro_rules! message_ipc_component_static {
+    ($A:ty, $L:expr $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let message_ipc = kernel::static_buf!(
+            kernel::ipc_message::MessageIpc<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                $L,
+            >
+        );
+
+        (alarm, message_ipc)
+    };};
+}
+
+pub type MessageIpcComponentType<A, const L: usize> =
+    MessageIpc<'static, VirtualMuxAlarm<'static, A>, L>;
+
+pub struct MessageIpcComponent<A: 'static + time::Alarm<'static>, const L: usize> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: 'static + time::Alarm<'static>, const L: usize> MessageIpcComponent<A, L> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>, const L: usize> Component for MessageIpcComponent<A, L> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<MessageIpc<'static, VirtualMuxAlarm<'static, A>, L>>,
+    );
+    type Output = &'static MessageIpc<'static, VirtualMuxAlarm<'static, A>, L>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let process_management_cap = create_capability!(capabilities::ProcessManagementCapability);
+
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let message_ipc = static_buffer.1.write(MessageIpc::new(
+            self.board_kernel,
+            self.driver_num,
+            alarm,
+            &grant_cap,
+        ));
+
+        alarm.set_alarm_client(message_ipc);
+        self.board_kernel
+            .set_process_termination_client(message_ipc, &process_management_cap);
+        message_ipc
+    }
+}
@@ -0,0 +1,156 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Components for persistent monotonic counters.
+//!
+//! This provides two components:
+//!
+//! - `FlashCounterComponent` stores monotonic counters in a memory mapped
+//!   flash volume of at least two pages. `COUNTERS` is the number of counters
+//!   that can be stored.
+//! - `MonotonicCounterComponent` provides the counters to userspace.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! kernel::storage_volume!(COUNTER_VOLUME, 2);
+//!
+//! let flash_counter = components::monotonic_counter::FlashCounterComponent::new(
+//!     &base_peripherals.nvmc,
+//!     &COUNTER_VOLUME,
+//! )
+//! .finalize(components::flash_counter_component_static!(
+//!     nrf52840::nvmc::Nvmc,
+//!     32
+//! ));
+//! let monotonic_counter = components::monotonic_counter::MonotonicCounterComponent::new(
+//!     board_kernel,
+//!     capsules_extra::monotonic_counter::DRIVER_NUM,
+//!     flash_counter,
+//! )
+//! .finalize(components::monotonic_counter_component_static!(
+//!     components::monotonic_counter::FlashCounterComponentType<nrf52840::nvmc::Nvmc>
+//! ));
+//! ```
+
+use capsules_extra::flash_counter::{Counter, FlashCounter};
+use capsules_extra::monotonic_counter::MonotonicCounterDriver;
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil;
+use kernel::hil::counter::MonotonicCounter;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! flash_counter_component_static {
+    ($F:ty, $COUNTERS:expr $(,)?) => {{
+        let page = kernel::static_buf!(<$F as kernel::hil::flash::Flash>::Page);
+        let counters = kernel::static_buf!(
+            [core::cell::Cell<capsules_extra::flash_counter::Counter>; $COUNTERS]
+        );
+        let counter = kernel::static_buf!(capsules_extra::flash_counter::FlashCounter<'static, $F>);
+
+        (page, counters, counter)
+    };};
+}
+
+#[macro_export]
+macro_rules! monotonic_counter_component_static {
+    ($C:ty $(,)?) => {{
+        kernel::static_buf!(capsules_extra::monotonic_counter::MonotonicCounterDriver<'static, $C>)
+    };};
+}
+
+pub type FlashCounterComponentType<F> = FlashCounter<'static, F>;
+pub type MonotonicCounterComponentType<C> = MonotonicCounterDriver<'static, C>;
+
+pub struct FlashCounterComponent<
+    F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, FlashCounter<'static, F>>,
+    const COUNTERS: usize,
+> {
+    flash: &'static F,
+    volume: &'static [u8],
+}
+
+impl<
+        F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, FlashCounter<'static, F>>,
+        const COUNTERS: usize,
+    > FlashCounterComponent<F, COUNTERS>
+{
+    pub fn new(flash: &'static F, volume: &'static [u8]) -> Self {
+        Self { flash, volume }
+    }
+}
+
+impl<
+        F: 'static + hil::flash::Flash + hil::flash::HasClient<'static, FlashCounter<'static, F>>,
+        const COUNTERS: usize,
+    > Component for FlashCounterComponent<F, COUNTERS>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<<F as hil::flash::Flash>::Page>,
+        &'static mut MaybeUninit<[Cell<Counter>; COUNTERS]>,
+        &'static mut MaybeUninit<FlashCounter<'static, F>>,
+    );
+    type Output = &'static FlashCounter<'static, F>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let page = static_buffer
+            .0
+            .write(<F as hil::flash::Flash>::Page::default());
+        let counters = static_buffer
+            .1
+            .write(core::array::from_fn(|_| Cell::new(Counter::default())));
+
+        let counter =
+            static_buffer
+                .2
+                .write(FlashCounter::new(self.flash, self.volume, counters, page));
+        counter.register();
+        hil::flash::HasClient::set_client(self.flash, counter);
+
+        counter
+    }
+}
+
+pub struct MonotonicCounterComponent<C: 'static + MonotonicCounter<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    counter: &'static C,
+}
+
+impl<C: 'static + MonotonicCounter<'static>> MonotonicCounterComponent<C> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        counter: &'static C,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            counter,
+        }
+    }
+}
+
+impl<C: 'static + MonotonicCounter<'static>> Component for MonotonicCounterComponent<C> {
+    type StaticInput = &'static mut MaybeUninit<MonotonicCounterDriver<'static, C>>;
+    type Output = &'static MonotonicCounterDriver<'static, C>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let driver = static_buffer.write(MonotonicCounterDriver::new(
+            self.counter,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+        ));
+        self.counter.set_client(driver);
+
+        driver
+    }
+}
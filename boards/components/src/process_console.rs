@@ -14,6 +14,15 @@
 //! let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux, alarm_mux, process_printer, Some(reset_function))
 //!     .finalize(process_console_component_static!());
 //! ```
+//!
+//! The `mem`, `regs` and `stack` commands show the memory of processes and are
+//! disabled by default. Debug boards can enable them with:
+//!
+//! ```rust
+//! struct MemoryInspectionCapability;
+//! unsafe impl capabilities::ProcessMemoryInspectionCapability for MemoryInspectionCapability {}
+//! pconsole.enable_memory_inspection(&MemoryInspectionCapability);
+//! ```
 
 // Author: Philip Levis <pal@cs.stanford.edu>
 // Last modified: 6/20/2018
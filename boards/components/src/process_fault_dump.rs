@@ -0,0 +1,114 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component for recording process fault dumps in nonvolatile storage.
+//!
+//! This provides one component, ProcessFaultDumpComponent, which wraps the
+//! board's fault policy, stores a record of every process fault in a reserved
+//! region of nonvolatile storage, and provides a system call interface to read
+//! the records back to the processes in `trusted_apps`. The returned object must
+//! be used as the fault policy when loading processes.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let fault_dump = components::process_fault_dump::ProcessFaultDumpComponent::new(
+//!     board_kernel,
+//!     capsules_extra::process_fault_dump::DRIVER_NUM,
+//!     nv_to_page,
+//!     0x60000,
+//!     0x2000,
+//!     &FAULT_POLICY,
+//!     &TRUSTED_APPS,
+//! )
+//! .finalize(components::process_fault_dump_component_static!(
+//!     NonvolatileToPages<'static, nrf52840::nvmc::Nvmc>
+//! ));
+//! process_console.set_fault_dumps(fault_dump);
+//! fault_dump.set_client(process_console);
+//! ```
+
+use capsules_extra::process_fault_dump::{ProcessFaultDump, DUMP_LEN};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::process::{ProcessFaultPolicy, ShortId};
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! process_fault_dump_component_static {
+    ($S:ty $(,)?) => {{
+        let fault_dump =
+            kernel::static_buf!(capsules_extra::process_fault_dump::ProcessFaultDump<'static, $S>);
+        let dump_buffer = kernel::static_buf!([u8; capsules_extra::process_fault_dump::DUMP_LEN]);
+        let io_buffer = kernel::static_buf!([u8; capsules_extra::process_fault_dump::DUMP_LEN]);
+
+        (fault_dump, dump_buffer, io_buffer)
+    };};
+}
+
+pub struct ProcessFaultDumpComponent<S: NonvolatileStorage<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    storage: &'static S,
+    region_start: usize,
+    region_len: usize,
+    fault_policy: &'static dyn ProcessFaultPolicy,
+    trusted_apps: &'static [ShortId],
+}
+
+impl<S: NonvolatileStorage<'static>> ProcessFaultDumpComponent<S> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        storage: &'static S,
+        region_start: usize,
+        region_len: usize,
+        fault_policy: &'static dyn ProcessFaultPolicy,
+        trusted_apps: &'static [ShortId],
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            storage,
+            region_start,
+            region_len,
+            fault_policy,
+            trusted_apps,
+        }
+    }
+}
+
+impl<S: NonvolatileStorage<'static>> Component for ProcessFaultDumpComponent<S> {
+    type StaticInput = (
+        &'static mut MaybeUninit<ProcessFaultDump<'static, S>>,
+        &'static mut MaybeUninit<[u8; DUMP_LEN]>,
+        &'static mut MaybeUninit<[u8; DUMP_LEN]>,
+    );
+    type Output = &'static ProcessFaultDump<'static, S>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let dump_buffer = static_buffer.1.write([0; DUMP_LEN]);
+        let io_buffer = static_buffer.2.write([0; DUMP_LEN]);
+
+        let fault_dump = static_buffer.0.write(ProcessFaultDump::new(
+            self.storage,
+            self.fault_policy,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            self.trusted_apps,
+            self.region_start,
+            self.region_len,
+            dump_buffer,
+            io_buffer,
+        ));
+        self.storage.set_client(fault_dump);
+        let _ = fault_dump.init();
+
+        fault_dump
+    }
+}
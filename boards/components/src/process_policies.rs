@@ -0,0 +1,116 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Components for process policies that need resources of the board.
+//!
+//! This provides one component, BackoffRestartFaultPolicyComponent, which
+//! creates a fault policy that restarts faulting processes after an
+//! exponentially growing delay. The returned object must be used as the fault
+//! policy when loading processes.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let fault_policy = components::process_policies::BackoffRestartFaultPolicyComponent::new(
+//!     board_kernel,
+//!     mux_alarm,
+//!     100,    // ms before the first restart
+//!     60000,  // maximum ms between restarts
+//!     10000,  // ms of running without a fault to reset the delay
+//! )
+//! .finalize(components::backoff_restart_fault_policy_component_static!(
+//!     nrf52840::rtc::Rtc,
+//!     NUM_PROCS
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_system::process_policies::BackoffRestartFaultPolicy;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! backoff_restart_fault_policy_component_static {
+    ($A:ty, $N:expr $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let policy = kernel::static_buf!(
+            capsules_system::process_policies::BackoffRestartFaultPolicy<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                components::process_policies::Capability,
+                $N,
+            >
+        );
+
+        (alarm, policy)
+    };};
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+pub struct BackoffRestartFaultPolicyComponent<A: Alarm<'static> + 'static, const NUM_PROCS: usize> {
+    board_kernel: &'static kernel::Kernel,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    base_delay_ms: u32,
+    max_delay_ms: u32,
+    healthy_ms: u32,
+}
+
+impl<A: Alarm<'static>, const NUM_PROCS: usize> BackoffRestartFaultPolicyComponent<A, NUM_PROCS> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        base_delay_ms: u32,
+        max_delay_ms: u32,
+        healthy_ms: u32,
+    ) -> Self {
+        Self {
+            board_kernel,
+            alarm_mux,
+            base_delay_ms,
+            max_delay_ms,
+            healthy_ms,
+        }
+    }
+}
+
+impl<A: Alarm<'static>, const NUM_PROCS: usize> Component
+    for BackoffRestartFaultPolicyComponent<A, NUM_PROCS>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<
+            BackoffRestartFaultPolicy<'static, VirtualMuxAlarm<'static, A>, Capability, NUM_PROCS>,
+        >,
+    );
+    type Output = &'static BackoffRestartFaultPolicy<
+        'static,
+        VirtualMuxAlarm<'static, A>,
+        Capability,
+        NUM_PROCS,
+    >;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let policy = static_buffer.1.write(BackoffRestartFaultPolicy::new(
+            alarm,
+            self.board_kernel,
+            Capability,
+            self.base_delay_ms,
+            self.max_delay_ms,
+            self.healthy_ms,
+        ));
+        alarm.set_alarm_client(policy);
+
+        policy
+    }
+}
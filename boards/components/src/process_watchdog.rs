@@ -0,0 +1,119 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for the process watchdog.
+//!
+//! This provides one component, ProcessWatchdogComponent, which wraps the
+//! board's hardware watchdog and only tickles it while all processes
+//! registered with the watchdog driver check in on time. The returned object
+//! must be returned from `KernelResources::watchdog()` and registered as a
+//! syscall driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let process_watchdog = components::process_watchdog::ProcessWatchdogComponent::new(
+//!     board_kernel,
+//!     capsules_extra::process_watchdog::DRIVER_NUM,
+//!     mux_alarm,
+//!     &base_peripherals.wdt,
+//!     100,
+//!     capsules_extra::process_watchdog::MissedCheckInAction::Restart,
+//! )
+//! .finalize(components::process_watchdog_component_static!(
+//!     nrf52840::rtc::Rtc,
+//!     nrf52840::wdt::Wdt,
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::process_watchdog::{MissedCheckInAction, ProcessWatchdog};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+use kernel::platform::watchdog::WatchDog;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! process_watchdog_component_static {
+    ($A:ty, $W:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let process_watchdog = kernel::static_buf!(
+            capsules_extra::process_watchdog::ProcessWatchdog<
+                'static,
+                capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                $W,
+                components::process_watchdog::Capability,
+            >
+        );
+
+        (alarm, process_watchdog)
+    };};
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+pub struct ProcessWatchdogComponent<A: Alarm<'static> + 'static, W: WatchDog + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    watchdog: &'static W,
+    check_interval_ms: u32,
+    action: MissedCheckInAction,
+}
+
+impl<A: Alarm<'static>, W: WatchDog> ProcessWatchdogComponent<A, W> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        watchdog: &'static W,
+        check_interval_ms: u32,
+        action: MissedCheckInAction,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            alarm_mux,
+            watchdog,
+            check_interval_ms,
+            action,
+        }
+    }
+}
+
+impl<A: Alarm<'static>, W: WatchDog> Component for ProcessWatchdogComponent<A, W> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<
+            ProcessWatchdog<'static, VirtualMuxAlarm<'static, A>, W, Capability>,
+        >,
+    );
+    type Output = &'static ProcessWatchdog<'static, VirtualMuxAlarm<'static, A>, W, Capability>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let process_watchdog = static_buffer.1.write(ProcessWatchdog::new(
+            alarm,
+            self.watchdog,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            self.board_kernel,
+            Capability,
+            self.check_interval_ms,
+            self.action,
+        ));
+        alarm.set_alarm_client(process_watchdog);
+
+        process_watchdog
+    }
+}
@@ -0,0 +1,112 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for creating a storage permissions policy that limits the amount
+//! of storage applications may use based on the board configuration.
+//!
+//! This wraps another storage permissions policy.
+//!
+//! ```rust
+//! let storage_permissions_policy =
+//!     components::storage_permissions::quota::StoragePermissionsQuotaComponent::new(
+//!         tbf_header_policy,
+//!         &[(0x1234, 2048)],
+//!         Some(1024),
+//!     )
+//!     .finalize(components::storage_permissions_quota_component_static!(
+//!         nrf52840dk_lib::Chip,
+//!         kernel::process::ProcessStandardDebugFull,
+//!         components::storage_permissions::tbf_header::StoragePermissionsTbfHeaderComponentType<
+//!             nrf52840dk_lib::Chip,
+//!             kernel::process::ProcessStandardDebugFull,
+//!         >,
+//!     ));
+//! ```
+
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::platform::chip::Chip;
+use kernel::process::ProcessStandardDebug;
+use kernel::process::ProcessStandardStoragePermissionsPolicy;
+
+#[macro_export]
+macro_rules! storage_permissions_quota_component_static {
+    ($C:ty, $D:ty, $P:ty $(,)?) => {{
+        kernel::static_buf!(
+            capsules_system::storage_permissions::quota::QuotaStoragePermissions<
+                'static,
+                $C,
+                $D,
+                $P,
+                components::storage_permissions::quota::AppStoreCapability,
+            >
+        )
+    };};
+}
+
+pub struct AppStoreCapability;
+unsafe impl kernel::capabilities::ApplicationStorageCapability for AppStoreCapability {}
+
+pub type StoragePermissionsQuotaComponentType<C, D, P> =
+    capsules_system::storage_permissions::quota::QuotaStoragePermissions<
+        'static,
+        C,
+        D,
+        P,
+        AppStoreCapability,
+    >;
+
+pub struct StoragePermissionsQuotaComponent<
+    C: Chip + 'static,
+    D: ProcessStandardDebug + 'static,
+    P: ProcessStandardStoragePermissionsPolicy<C, D> + 'static,
+> {
+    policy: &'static P,
+    quotas: &'static [(u32, usize)],
+    default_quota: Option<usize>,
+    _chip: core::marker::PhantomData<C>,
+    _debug: core::marker::PhantomData<D>,
+}
+
+impl<
+        C: Chip + 'static,
+        D: ProcessStandardDebug + 'static,
+        P: ProcessStandardStoragePermissionsPolicy<C, D> + 'static,
+    > StoragePermissionsQuotaComponent<C, D, P>
+{
+    pub fn new(
+        policy: &'static P,
+        quotas: &'static [(u32, usize)],
+        default_quota: Option<usize>,
+    ) -> Self {
+        Self {
+            policy,
+            quotas,
+            default_quota,
+            _chip: core::marker::PhantomData,
+            _debug: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<
+        C: Chip + 'static,
+        D: ProcessStandardDebug + 'static,
+        P: ProcessStandardStoragePermissionsPolicy<C, D> + 'static,
+    > Component for StoragePermissionsQuotaComponent<C, D, P>
+{
+    type StaticInput = &'static mut MaybeUninit<StoragePermissionsQuotaComponentType<C, D, P>>;
+    type Output = &'static StoragePermissionsQuotaComponentType<C, D, P>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        s.write(
+            capsules_system::storage_permissions::quota::QuotaStoragePermissions::new(
+                self.policy,
+                self.quotas,
+                self.default_quota,
+                AppStoreCapability,
+            ),
+        )
+    }
+}
@@ -0,0 +1,179 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Component to initialize the userland TCP driver.
+//!
+//! This provides one Component, TCPDriverComponent. This component attaches
+//! TCP to the IPv6 layer shared with UDP (see `IP6MuxComponent`), and
+//! initializes a userspace TCP driver on top of it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let tcp_driver = TCPDriverComponent::new(
+//!        board_kernel,
+//!        capsules_extra::net::tcp::DRIVER_NUM,
+//!        ip6_send_mux,
+//!        ip6_recv_mux,
+//!        local_ip_ifaces,
+//!        mux_alarm,
+//!     )
+//!     .finalize(components::tcp_driver_component_static!(nrf52840::rtc::Rtc));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_extra::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use capsules_extra::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvUser, MuxIP6Receiver};
+use capsules_extra::net::ipv6::ipv6_send::{IP6SendUser, IP6Sender, IPSender, MuxIP6Sender};
+use capsules_extra::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules_extra::net::tcp::tcp_port_table::{TcpPortBinding, TcpPortTable, MAX_NUM_BOUND_PORTS};
+use capsules_extra::net::tcp::TCPDriver;
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+
+/// The maximum number of payload bytes per TCP segment. A full segment spans
+/// several 802.15.4 frames after fragmentation.
+pub const MAX_SEGMENT_LEN: usize = 200;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! tcp_driver_component_static {
+    ($A:ty $(,)?) => {{
+        use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
+        use capsules_extra::net::tcp::tcp_port_table::{TcpPortBinding, MAX_NUM_BOUND_PORTS};
+        use components::tcp_driver::MAX_SEGMENT_LEN;
+
+        let tcp_alarm = kernel::static_buf!(VirtualMuxAlarm<'static, $A>);
+        let ip6_send =
+            kernel::static_buf!(capsules_extra::net::ipv6::ipv6_send::IP6SendUser<'static>);
+        let ip6_receive =
+            kernel::static_buf!(capsules_extra::net::ipv6::ipv6_recv::IP6RecvUser<'static>);
+        let port_bindings =
+            kernel::static_buf!([core::cell::Cell<Option<TcpPortBinding>>; MAX_NUM_BOUND_PORTS]);
+        let port_table =
+            kernel::static_buf!(capsules_extra::net::tcp::tcp_port_table::TcpPortTable);
+        let tcp_driver = kernel::static_buf!(
+            capsules_extra::net::tcp::TCPDriver<'static, VirtualMuxAlarm<'static, $A>>
+        );
+
+        let tcp_segment = kernel::static_buf!([u8; MAX_SEGMENT_LEN]);
+        let buffer = kernel::static_buf!([u8; MAX_SEGMENT_LEN]);
+
+        let port_vis_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
+        let net_cap =
+            kernel::static_buf!(capsules_extra::net::network_capabilities::NetworkCapability);
+
+        (
+            tcp_alarm,
+            ip6_send,
+            ip6_receive,
+            port_bindings,
+            port_table,
+            tcp_driver,
+            tcp_segment,
+            buffer,
+            port_vis_cap,
+            net_cap,
+        )
+    };};
+}
+
+pub struct TCPDriverComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    ip6_send_mux: &'static MuxIP6Sender<'static>,
+    ip6_recv_mux: &'static MuxIP6Receiver<'static>,
+    interface_list: &'static [Cell<IPAddr>],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> TCPDriverComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        ip6_send_mux: &'static MuxIP6Sender<'static>,
+        ip6_recv_mux: &'static MuxIP6Receiver<'static>,
+        interface_list: &'static [Cell<IPAddr>],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            ip6_send_mux,
+            ip6_recv_mux,
+            interface_list,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for TCPDriverComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<IP6SendUser<'static>>,
+        &'static mut MaybeUninit<IP6RecvUser<'static>>,
+        &'static mut MaybeUninit<[Cell<Option<TcpPortBinding>>; MAX_NUM_BOUND_PORTS]>,
+        &'static mut MaybeUninit<TcpPortTable>,
+        &'static mut MaybeUninit<TCPDriver<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<[u8; MAX_SEGMENT_LEN]>,
+        &'static mut MaybeUninit<[u8; MAX_SEGMENT_LEN]>,
+        &'static mut MaybeUninit<UdpVisibilityCapability>,
+        &'static mut MaybeUninit<NetworkCapability>,
+    );
+    type Output = &'static TCPDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+
+        let tcp_virtual_alarm = s.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        tcp_virtual_alarm.setup();
+
+        let port_vis = s.8.write(UdpVisibilityCapability::new(&create_cap));
+
+        let tcp_segment_buffer = s.6.write([0; MAX_SEGMENT_LEN]);
+        let ip_send =
+            s.1.write(IP6SendUser::new(self.ip6_send_mux, tcp_segment_buffer));
+        ip_send.set_addr(self.interface_list[0].get());
+
+        let ip_receive = s.2.write(IP6RecvUser::new(ip6_nh::TCP));
+        self.ip6_recv_mux.add_user(ip_receive);
+
+        let port_bindings = s.3.write(core::array::from_fn(|_| Cell::new(None)));
+        let port_table = s.4.write(TcpPortTable::new(port_bindings));
+
+        let net_cap = s.9.write(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &create_cap,
+        ));
+
+        let buffer = s.7.write([0; MAX_SEGMENT_LEN]);
+
+        let tcp_driver = s.5.write(TCPDriver::new(
+            ip_send,
+            tcp_virtual_alarm,
+            self.board_kernel.create_grant(self.driver_num, &grant_cap),
+            self.interface_list,
+            MAX_SEGMENT_LEN,
+            kernel::utilities::leasable_buffer::SubSliceMut::new(buffer),
+            net_cap,
+            port_vis,
+            port_table,
+        ));
+        tcp_virtual_alarm.set_alarm_client(tcp_driver);
+        ip_send.set_client(tcp_driver);
+        ip_receive.set_client(tcp_driver);
+
+        tcp_driver
+    }
+}
@@ -28,7 +28,7 @@
 
 use capsules_core::virtualizers::virtual_aes_ccm::MuxAES128CCM;
 use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
-use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules_extra::net::ipv6::ipv6_send::IP6SendUser;
 use capsules_extra::net::network_capabilities::{
     AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
 };
@@ -60,10 +60,7 @@ macro_rules! thread_network_component_static {
         let udp_send = kernel::static_buf!(
             capsules_extra::net::udp::udp_send::UDPSendStruct<
                 'static,
-                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
-                    'static,
-                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
-                >,
+                capsules_extra::net::ipv6::ipv6_send::IP6SendUser<'static>,
             >
         );
         let udp_vis_cap =
@@ -106,8 +103,7 @@ pub struct ThreadNetworkComponent<
 > {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
-    udp_send_mux:
-        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_send_mux: &'static MuxUdpSender<'static, IP6SendUser<'static>>,
     udp_recv_mux: &'static MuxUdpReceiver<'static>,
     port_table: &'static UdpPortManager,
     aes_mux: &'static MuxAES128CCM<'static, B>,
@@ -123,10 +119,7 @@ impl<
     pub fn new(
         board_kernel: &'static kernel::Kernel,
         driver_num: usize,
-        udp_send_mux: &'static MuxUdpSender<
-            'static,
-            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-        >,
+        udp_send_mux: &'static MuxUdpSender<'static, IP6SendUser<'static>>,
         udp_recv_mux: &'static MuxUdpReceiver<'static>,
         port_table: &'static UdpPortManager,
         aes_mux: &'static MuxAES128CCM<'static, B>,
@@ -153,13 +146,7 @@ impl<
 {
     type StaticInput = (
         &'static mut MaybeUninit<
-            UDPSendStruct<
-                'static,
-                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
-                    'static,
-                    VirtualMuxAlarm<'static, A>,
-                >,
-            >,
+            UDPSendStruct<'static, capsules_extra::net::ipv6::ipv6_send::IP6SendUser<'static>>,
         >,
         &'static mut MaybeUninit<
             capsules_extra::net::network_capabilities::UdpVisibilityCapability,
@@ -21,9 +21,8 @@
 //!     .finalize(components::udp_driver_component_static!());
 //! ```
 
-use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
 use capsules_extra::net::ipv6::ip_utils::IPAddr;
-use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules_extra::net::ipv6::ipv6_send::IPSender;
 use capsules_extra::net::network_capabilities::{
     AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
 };
@@ -31,30 +30,29 @@ use capsules_extra::net::udp::udp_port_table::UdpPortManager;
 use capsules_extra::net::udp::udp_recv::MuxUdpReceiver;
 use capsules_extra::net::udp::udp_recv::UDPReceiver;
 use capsules_extra::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use core::cell::Cell;
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::capabilities::NetworkCapabilityCreationCapability;
 use kernel::component::Component;
 use kernel::create_capability;
-use kernel::hil::time::Alarm;
 
 const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
 
 // Setup static space for the objects.
 #[macro_export]
 macro_rules! udp_driver_component_static {
-    ($A:ty $(,)?) => {{
+    () => {{
+        $crate::udp_driver_component_static!(
+            @sender capsules_extra::net::ipv6::ipv6_send::IP6SendUser<'static>
+        )
+    };};
+    // The driver on top of the IP sender `$S` of the UDP mux.
+    (@sender $S:ty $(,)?) => {{
         use components::udp_mux::MAX_PAYLOAD_LEN;
 
-        let udp_send = kernel::static_buf!(
-            capsules_extra::net::udp::udp_send::UDPSendStruct<
-                'static,
-                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
-                    'static,
-                    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>,
-                >,
-            >
-        );
+        let udp_send =
+            kernel::static_buf!(capsules_extra::net::udp::udp_send::UDPSendStruct<'static, $S>);
         let udp_vis_cap =
             kernel::static_buf!(capsules_extra::net::network_capabilities::UdpVisibilityCapability);
         let net_cap =
@@ -68,27 +66,25 @@ macro_rules! udp_driver_component_static {
     };};
 }
 
-pub struct UDPDriverComponent<A: Alarm<'static> + 'static> {
+/// `S` is the IP sender of the UDP mux, the `IP6SendUser` of the 6LoWPAN
+/// stack or the `IP4Interface` of the IPv4 stack.
+pub struct UDPDriverComponent<S: IPSender<'static> + 'static> {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
-    udp_send_mux:
-        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_send_mux: &'static MuxUdpSender<'static, S>,
     udp_recv_mux: &'static MuxUdpReceiver<'static>,
     port_table: &'static UdpPortManager,
-    interface_list: &'static [IPAddr],
+    interface_list: &'static [Cell<IPAddr>],
 }
 
-impl<A: Alarm<'static>> UDPDriverComponent<A> {
+impl<S: IPSender<'static>> UDPDriverComponent<S> {
     pub fn new(
         board_kernel: &'static kernel::Kernel,
         driver_num: usize,
-        udp_send_mux: &'static MuxUdpSender<
-            'static,
-            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-        >,
+        udp_send_mux: &'static MuxUdpSender<'static, S>,
         udp_recv_mux: &'static MuxUdpReceiver<'static>,
         port_table: &'static UdpPortManager,
-        interface_list: &'static [IPAddr],
+        interface_list: &'static [Cell<IPAddr>],
     ) -> Self {
         Self {
             board_kernel,
@@ -101,17 +97,9 @@ impl<A: Alarm<'static>> UDPDriverComponent<A> {
     }
 }
 
-impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
+impl<S: IPSender<'static>> Component for UDPDriverComponent<S> {
     type StaticInput = (
-        &'static mut MaybeUninit<
-            UDPSendStruct<
-                'static,
-                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
-                    'static,
-                    VirtualMuxAlarm<'static, A>,
-                >,
-            >,
-        >,
+        &'static mut MaybeUninit<UDPSendStruct<'static, S>>,
         &'static mut MaybeUninit<
             capsules_extra::net::network_capabilities::UdpVisibilityCapability,
         >,
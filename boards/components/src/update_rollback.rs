@@ -0,0 +1,173 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Component for automatically rolling back failed process updates.
+//!
+//! This provides two components:
+//!
+//! - `UpdateRollbackFaultPolicyComponent` wraps the fault policy of the
+//!   board, so that faults are counted. The returned object must be used as
+//!   the fault policy when loading processes.
+//! - `UpdateRollbackComponent` wraps the dynamic binary storage and commits or
+//!   rolls back process updates depending on whether the updated process keeps
+//!   faulting. The returned object must be passed to the app loader as the
+//!   loader, and its `start()` method must be called once the processes have
+//!   been loaded at boot, so that an update that was pending when the board
+//!   rebooted is watched again.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let fault_policy =
+//!     components::update_rollback::UpdateRollbackFaultPolicyComponent::new(board_fault_policy)
+//!         .finalize(components::update_rollback_fault_policy_component_static!());
+//!
+//! // Load the processes with `fault_policy` and create the dynamic binary
+//! // storage.
+//!
+//! let update_rollback = components::update_rollback::UpdateRollbackComponent::new(
+//!     board_kernel,
+//!     mux_alarm,
+//!     dynamic_binary_storage,
+//!     fault_policy,
+//!     3,     // faults before rolling back
+//!     10000, // ms to watch the process after an update
+//! )
+//! .finalize(components::update_rollback_component_static!(
+//!     nrf52840::rtc::Rtc,
+//!     DynamicBinaryStorage<'static>,
+//! ));
+//!
+//! let dynamic_app_loader = components::app_loader::AppLoaderComponent::new(
+//!     board_kernel,
+//!     capsules_extra::app_loader::DRIVER_NUM,
+//!     dynamic_binary_storage,
+//!     update_rollback,
+//! )
+//! .finalize(components::app_loader_component_static!(
+//!     DynamicBinaryStorage<'static>,
+//!     components::update_rollback::UpdateRollbackType<
+//!         nrf52840::rtc::Rtc,
+//!         DynamicBinaryStorage<'static>,
+//!     >,
+//! ));
+//! ```
+
+use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules_system::update_rollback::{UpdateRollback, UpdateRollbackFaultPolicy};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::dynamic_binary_storage::{DynamicProcessLoad, DynamicProcessUpdate};
+use kernel::hil::time::Alarm;
+use kernel::process::ProcessFaultPolicy;
+
+pub type UpdateRollbackType<A, U> =
+    UpdateRollback<'static, VirtualMuxAlarm<'static, A>, U, Capability>;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! update_rollback_component_static {
+    ($A:ty, $U:ty $(,)?) => {{
+        let alarm = kernel::static_buf!(
+            capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<'static, $A>
+        );
+        let update_rollback =
+            kernel::static_buf!(components::update_rollback::UpdateRollbackType<$A, $U>);
+
+        (alarm, update_rollback)
+    };};
+}
+
+#[macro_export]
+macro_rules! update_rollback_fault_policy_component_static {
+    () => {{
+        kernel::static_buf!(capsules_system::update_rollback::UpdateRollbackFaultPolicy<'static>)
+    };};
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+pub struct UpdateRollbackFaultPolicyComponent {
+    policy: &'static dyn ProcessFaultPolicy,
+}
+
+impl UpdateRollbackFaultPolicyComponent {
+    pub fn new(policy: &'static dyn ProcessFaultPolicy) -> Self {
+        Self { policy }
+    }
+}
+
+impl Component for UpdateRollbackFaultPolicyComponent {
+    type StaticInput = &'static mut MaybeUninit<UpdateRollbackFaultPolicy<'static>>;
+    type Output = &'static UpdateRollbackFaultPolicy<'static>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        static_buffer.write(UpdateRollbackFaultPolicy::new(self.policy))
+    }
+}
+
+pub struct UpdateRollbackComponent<
+    A: Alarm<'static> + 'static,
+    U: DynamicProcessLoad + DynamicProcessUpdate + 'static,
+> {
+    board_kernel: &'static kernel::Kernel,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    updater: &'static U,
+    fault_policy: &'static UpdateRollbackFaultPolicy<'static>,
+    max_faults: usize,
+    window_ms: u32,
+}
+
+impl<A: Alarm<'static>, U: DynamicProcessLoad + DynamicProcessUpdate>
+    UpdateRollbackComponent<A, U>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        updater: &'static U,
+        fault_policy: &'static UpdateRollbackFaultPolicy<'static>,
+        max_faults: usize,
+        window_ms: u32,
+    ) -> Self {
+        Self {
+            board_kernel,
+            alarm_mux,
+            updater,
+            fault_policy,
+            max_faults,
+            window_ms,
+        }
+    }
+}
+
+impl<A: Alarm<'static>, U: DynamicProcessLoad + DynamicProcessUpdate> Component
+    for UpdateRollbackComponent<A, U>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<UpdateRollbackType<A, U>>,
+    );
+    type Output = &'static UpdateRollbackType<A, U>;
+
+    fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let alarm = static_buffer.0.write(VirtualMuxAlarm::new(self.alarm_mux));
+        alarm.setup();
+
+        let update_rollback = static_buffer.1.write(UpdateRollback::new(
+            self.updater,
+            alarm,
+            self.board_kernel,
+            Capability,
+            self.max_faults,
+            self.window_ms,
+        ));
+        alarm.set_alarm_client(update_rollback);
+        self.updater.set_update_client(update_rollback);
+        self.fault_policy.set_client(update_rollback);
+
+        update_rollback
+    }
+}
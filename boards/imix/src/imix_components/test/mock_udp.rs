@@ -9,7 +9,7 @@
 #![allow(dead_code)] // Components are intended to be conditionally included
 
 use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
-use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules_extra::net::ipv6::ipv6_send::IP6SendUser;
 use capsules_extra::net::network_capabilities::{NetworkCapability, UdpVisibilityCapability};
 use capsules_extra::net::udp::udp_port_table::UdpPortManager;
 use capsules_extra::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
@@ -27,13 +27,7 @@ macro_rules! mock_udp_component_static {
         use capsules_extra::net::udp::udp_recv::UDPReceiver;
         use capsules_extra::net::udp::udp_send::UDPSendStruct;
         let udp_send = kernel::static_buf!(
-            UDPSendStruct<
-                'static,
-                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
-                    'static,
-                    VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
-                >,
-            >
+            UDPSendStruct<'static, capsules_extra::net::ipv6::ipv6_send::IP6SendUser<'static>>
         );
 
         let udp_recv = kernel::static_buf!(UDPReceiver<'static>);
@@ -47,10 +41,7 @@ macro_rules! mock_udp_component_static {
 }
 
 pub struct MockUDPComponent {
-    udp_send_mux: &'static MuxUdpSender<
-        'static,
-        IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
-    >,
+    udp_send_mux: &'static MuxUdpSender<'static, IP6SendUser<'static>>,
     udp_recv_mux: &'static MuxUdpReceiver<'static>,
     bound_port_table: &'static UdpPortManager,
     alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
@@ -63,10 +54,7 @@ pub struct MockUDPComponent {
 
 impl MockUDPComponent {
     pub fn new(
-        udp_send_mux: &'static MuxUdpSender<
-            'static,
-            IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
-        >,
+        udp_send_mux: &'static MuxUdpSender<'static, IP6SendUser<'static>>,
         udp_recv_mux: &'static MuxUdpReceiver<'static>,
         bound_port_table: &'static UdpPortManager,
         alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
@@ -93,13 +81,7 @@ impl MockUDPComponent {
 impl Component for MockUDPComponent {
     type StaticInput = (
         &'static mut MaybeUninit<
-            UDPSendStruct<
-                'static,
-                capsules_extra::net::ipv6::ipv6_send::IP6SendStruct<
-                    'static,
-                    VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
-                >,
-            >,
+            UDPSendStruct<'static, capsules_extra::net::ipv6::ipv6_send::IP6SendUser<'static>>,
         >,
         &'static mut MaybeUninit<UDPReceiver<'static>>,
         &'static mut MaybeUninit<VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
@@ -19,7 +19,7 @@ use capsules_extra::net::icmpv6::icmpv6_send::{ICMP6SendStruct, ICMP6Sender};
 use capsules_extra::net::icmpv6::{ICMP6Header, ICMP6Type};
 use capsules_extra::net::ieee802154::MacAddress;
 use capsules_extra::net::ipv6::ip_utils::IPAddr;
-use capsules_extra::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules_extra::net::ipv6::ipv6_send::{IP6SendStruct, IPSender};
 use capsules_extra::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
 use capsules_extra::net::network_capabilities::{
     AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
@@ -123,7 +123,7 @@ use super::super::imix_components::test::mock_udp::MockUDPComponent;
 use crate::mock_udp_component_static;
 use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules_extra::net::ipv6::ip_utils::IPAddr;
-use capsules_extra::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules_extra::net::ipv6::ipv6_send::IP6SendUser;
 use capsules_extra::net::network_capabilities::{
     AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
 };
@@ -168,10 +168,7 @@ pub struct LowpanTest<'a, A: time::Alarm<'a>> {
 }
 
 pub unsafe fn initialize_all(
-    udp_send_mux: &'static MuxUdpSender<
-        'static,
-        IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
-    >,
+    udp_send_mux: &'static MuxUdpSender<'static, IP6SendUser<'static>>,
     udp_recv_mux: &'static MuxUdpReceiver<'static>,
     port_table: &'static UdpPortManager,
     mux_alarm: &'static MuxAlarm<'static, sam4l::ast::Ast>,
@@ -10,6 +10,7 @@
 #![no_main]
 #![deny(missing_docs)]
 
+use core::cell::Cell;
 use core::ptr::addr_of;
 
 use kernel::capabilities;
@@ -593,36 +594,39 @@ pub unsafe fn start() -> (
     use capsules_extra::net::ipv6::ip_utils::IPAddr;
 
     let local_ip_ifaces = static_init!(
-        [IPAddr; 3],
+        [Cell<IPAddr>; 3],
         [
-            IPAddr([
+            Cell::new(IPAddr([
                 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
                 0x0e, 0x0f,
-            ]),
-            IPAddr([
+            ])),
+            Cell::new(IPAddr([
                 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
                 0x1e, 0x1f,
-            ]),
-            IPAddr::generate_from_mac(capsules_extra::net::ieee802154::MacAddress::Short(
-                device_id_bottom_16
+            ])),
+            Cell::new(IPAddr::generate_from_mac(
+                capsules_extra::net::ieee802154::MacAddress::Short(device_id_bottom_16)
             )),
         ]
     );
 
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
+    let (ip6_send_mux, ip6_recv_mux) = components::ipv6_mux::IP6MuxComponent::new(
         mux_mac,
         DEFAULT_CTX_PREFIX_LEN,
         DEFAULT_CTX_PREFIX,
         DST_MAC_ADDR,
         MacAddress::Short(device_id_bottom_16),
-        local_ip_ifaces,
         mux_alarm,
     )
-    .finalize(components::udp_mux_component_static!(
+    .finalize(components::ip6_mux_component_static!(
         nrf52840::rtc::Rtc,
         Ieee802154MacDevice
     ));
 
+    let (udp_send_mux, udp_recv_mux, udp_port_table) =
+        components::udp_mux::UDPMuxComponent::new(ip6_send_mux, ip6_recv_mux, local_ip_ifaces)
+            .finalize(components::udp_mux_component_static!());
+
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
         board_kernel,
@@ -632,7 +636,7 @@ pub unsafe fn start() -> (
         udp_port_table,
         local_ip_ifaces,
     )
-    .finalize(components::udp_driver_component_static!(nrf52840::rtc::Rtc));
+    .finalize(components::udp_driver_component_static!());
 
     //--------------------------------------------------------------------------
     // APP ID CHECKING
@@ -70,6 +70,7 @@
 #![no_std]
 #![deny(missing_docs)]
 
+use core::cell::Cell;
 use core::ptr::addr_of;
 
 use capsules_core::virtualizers::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
@@ -194,6 +195,24 @@ pub type Ieee802154Driver = components::ieee802154::Ieee802154ComponentType<
 /// Userspace EUI64 driver.
 pub type Eui64Driver = components::eui64::Eui64ComponentType;
 
+// ICMPv6
+/// ICMPv6 echo responder and userspace ping driver.
+pub type PingDriver = components::icmpv6_echo::ICMP6EchoComponentType<nrf52840::rtc::Rtc<'static>>;
+
+// CoAP
+/// Userspace CoAP driver.
+pub type CoapDriver = components::coap::CoapComponentType<nrf52840::rtc::Rtc<'static>>;
+
+// TCP
+/// Userspace TCP driver.
+pub type TcpDriver = capsules_extra::net::tcp::TCPDriver<
+    'static,
+    capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm<
+        'static,
+        nrf52840::rtc::Rtc<'static>,
+    >,
+>;
+
 /// Supported drivers by the platform
 pub struct Platform {
     ble_radio: &'static capsules_extra::ble_advertising_driver::BLE<
@@ -298,7 +317,8 @@ impl KernelResources<Chip> for Platform {
     }
 }
 
-/// Create the capsules needed for the in-kernel UDP and 15.4 stack.
+/// Create the capsules needed for the in-kernel UDP, TCP, ICMPv6, CoAP and
+/// 15.4 stack.
 pub unsafe fn ieee802154_udp(
     board_kernel: &'static kernel::Kernel,
     nrf52840_peripherals: &'static Nrf52840DefaultPeripherals<'static>,
@@ -307,6 +327,9 @@ pub unsafe fn ieee802154_udp(
     &'static Eui64Driver,
     &'static Ieee802154Driver,
     &'static capsules_extra::net::udp::UDPDriver<'static>,
+    &'static PingDriver,
+    &'static CoapDriver,
+    &'static TcpDriver,
 ) {
     //--------------------------------------------------------------------------
     // AES
@@ -347,33 +370,54 @@ pub unsafe fn ieee802154_udp(
     //--------------------------------------------------------------------------
 
     let local_ip_ifaces = static_init!(
-        [IPAddr; 3],
+        [Cell<IPAddr>; 4],
         [
-            IPAddr::generate_from_mac(capsules_extra::net::ieee802154::MacAddress::Long(device_id)),
-            IPAddr([
+            Cell::new(IPAddr::generate_from_mac(
+                capsules_extra::net::ieee802154::MacAddress::Long(device_id)
+            )),
+            Cell::new(IPAddr([
                 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
                 0x1e, 0x1f,
-            ]),
-            IPAddr::generate_from_mac(capsules_extra::net::ieee802154::MacAddress::Short(
-                device_id_bottom_16
+            ])),
+            Cell::new(IPAddr::generate_from_mac(
+                capsules_extra::net::ieee802154::MacAddress::Short(device_id_bottom_16)
             )),
+            // Free slot for the address autoconfigured by Neighbor Discovery.
+            Cell::new(IPAddr::new()),
         ]
     );
 
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
+    // The IPv6/6LoWPAN layer shared by UDP, TCP and ICMPv6.
+    let (ip6_send_mux, ip6_recv_mux) = components::ipv6_mux::IP6MuxComponent::new(
         mux_mac,
         DEFAULT_CTX_PREFIX_LEN,
         DEFAULT_CTX_PREFIX,
         DST_MAC_ADDR,
         MacAddress::Long(device_id),
-        local_ip_ifaces,
         mux_alarm,
     )
-    .finalize(components::udp_mux_component_static!(
+    .finalize(components::ip6_mux_component_static!(
         nrf52840::rtc::Rtc,
         Ieee802154MacDevice
     ));
 
+    let (udp_send_mux, udp_recv_mux, udp_port_table) =
+        components::udp_mux::UDPMuxComponent::new(ip6_send_mux, ip6_recv_mux, local_ip_ifaces)
+            .finalize(components::udp_mux_component_static!());
+
+    // Neighbor Discovery is the next hop of the shared IPv6 sender.
+    components::ipv6_nd::NeighborDiscoveryComponent::new(
+        ip6_send_mux,
+        ip6_recv_mux,
+        eui64_driver,
+        local_ip_ifaces,
+        mux_alarm,
+    )
+    .finalize(components::neighbor_discovery_component_static!(
+        nrf52840::rtc::Rtc,
+        8
+    ));
+
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
         board_kernel,
@@ -383,9 +427,59 @@ pub unsafe fn ieee802154_udp(
         udp_port_table,
         local_ip_ifaces,
     )
-    .finalize(components::udp_driver_component_static!(nrf52840::rtc::Rtc));
+    .finalize(components::udp_driver_component_static!());
 
-    (eui64_driver, ieee802154_driver, udp_driver)
+    //--------------------------------------------------------------------------
+    // TCP
+    //--------------------------------------------------------------------------
+
+    let tcp_driver = components::tcp_driver::TCPDriverComponent::new(
+        board_kernel,
+        capsules_extra::net::tcp::DRIVER_NUM,
+        ip6_send_mux,
+        ip6_recv_mux,
+        local_ip_ifaces,
+        mux_alarm,
+    )
+    .finalize(components::tcp_driver_component_static!(nrf52840::rtc::Rtc));
+
+    //--------------------------------------------------------------------------
+    // ICMPv6
+    //--------------------------------------------------------------------------
+
+    let ping_driver = components::icmpv6_echo::ICMP6EchoComponent::new(
+        board_kernel,
+        capsules_extra::net::icmpv6::icmpv6_echo::DRIVER_NUM,
+        ip6_send_mux,
+        ip6_recv_mux,
+        local_ip_ifaces,
+        mux_alarm,
+    )
+    .finalize(components::icmp6_echo_component_static!(nrf52840::rtc::Rtc));
+    ping_driver.set_error_client(udp_driver);
+
+    //--------------------------------------------------------------------------
+    // CoAP
+    //--------------------------------------------------------------------------
+
+    let coap_driver = components::coap::CoapComponent::new(
+        board_kernel,
+        capsules_extra::net::coap::DRIVER_NUM,
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        mux_alarm,
+    )
+    .finalize(components::coap_component_static!(nrf52840::rtc::Rtc));
+
+    (
+        eui64_driver,
+        ieee802154_driver,
+        udp_driver,
+        ping_driver,
+        coap_driver,
+        tcp_driver,
+    )
 }
 
 /// This is in a separate, inline(never) function so that its stack frame is
@@ -22,6 +22,9 @@ struct Platform {
     eui64_driver: &'static nrf52840dk_lib::Eui64Driver,
     ieee802154_driver: &'static nrf52840dk_lib::Ieee802154Driver,
     udp_driver: &'static capsules_extra::net::udp::UDPDriver<'static>,
+    ping_driver: &'static nrf52840dk_lib::PingDriver,
+    coap_driver: &'static nrf52840dk_lib::CoapDriver,
+    tcp_driver: &'static nrf52840dk_lib::TcpDriver,
 }
 
 impl SyscallDriverLookup for Platform {
@@ -33,6 +36,9 @@ impl SyscallDriverLookup for Platform {
             capsules_extra::eui64::DRIVER_NUM => f(Some(self.eui64_driver)),
             capsules_extra::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
             capsules_extra::ieee802154::DRIVER_NUM => f(Some(self.ieee802154_driver)),
+            capsules_extra::net::icmpv6::icmpv6_echo::DRIVER_NUM => f(Some(self.ping_driver)),
+            capsules_extra::net::coap::DRIVER_NUM => f(Some(self.coap_driver)),
+            capsules_extra::net::tcp::DRIVER_NUM => f(Some(self.tcp_driver)),
             _ => self.base.with_driver(driver_num, f),
         }
     }
@@ -80,10 +86,10 @@ pub unsafe fn main() {
         nrf52840dk_lib::start();
 
     //--------------------------------------------------------------------------
-    // IEEE 802.15.4 and UDP
+    // IEEE 802.15.4, UDP, TCP, ICMPv6 and CoAP
     //--------------------------------------------------------------------------
 
-    let (eui64_driver, ieee802154_driver, udp_driver) =
+    let (eui64_driver, ieee802154_driver, udp_driver, ping_driver, coap_driver, tcp_driver) =
         nrf52840dk_lib::ieee802154_udp(board_kernel, default_peripherals, mux_alarm);
 
     let platform = Platform {
@@ -91,6 +97,9 @@ pub unsafe fn main() {
         eui64_driver,
         ieee802154_driver,
         udp_driver,
+        ping_driver,
+        coap_driver,
+        tcp_driver,
     };
 
     // These symbols are defined in the linker script.
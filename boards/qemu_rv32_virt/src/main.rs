@@ -80,6 +80,7 @@ struct QemuRv32VirtPlatform {
         >,
     >,
     virtio_gpu_screen: Option<&'static capsules_extra::screen::screen::Screen<'static>>,
+    udp_driver: Option<&'static capsules_extra::net::udp::UDPDriver<'static>>,
 }
 
 /// Mapping of integer syscalls to objects that implement syscalls.
@@ -106,6 +107,13 @@ impl SyscallDriverLookup for QemuRv32VirtPlatform {
                     f(None)
                 }
             }
+            capsules_extra::net::udp::DRIVER_NUM => {
+                if let Some(udp_driver) = self.udp_driver {
+                    f(Some(udp_driver))
+                } else {
+                    f(None)
+                }
+            }
             capsules_extra::screen::screen::DRIVER_NUM => {
                 if let Some(screen_driver) = self.virtio_gpu_screen {
                     f(Some(screen_driver))
@@ -494,16 +502,10 @@ unsafe fn start() -> (
     };
 
     // If there is a VirtIO NetworkCard present, use the appropriate VirtIONet
-    // driver, and expose this device through the Ethernet Tap driver
-    // (forwarding raw Ethernet frames from and to userspace).
-    let virtio_ethernet_tap: Option<
-        &'static capsules_extra::ethernet_tap::EthernetTapDriver<
-            'static,
-            qemu_rv32_virt_chip::virtio::devices::virtio_net::VirtIONet<'static>,
-        >,
+    // driver.
+    let virtio_net: Option<
+        &'static qemu_rv32_virt_chip::virtio::devices::virtio_net::VirtIONet<'static>,
     > = if let Some(net_idx) = virtio_net_idx {
-        use capsules_extra::ethernet_tap::EthernetTapDriver;
-        use kernel::hil::ethernet::EthernetAdapterDatapath;
         use qemu_rv32_virt_chip::virtio::devices::virtio_net::VirtIONet;
         use qemu_rv32_virt_chip::virtio::queues::split_queue::{
             SplitVirtqueue, VirtqueueAvailableRing, VirtqueueDescriptors, VirtqueueUsedRing,
@@ -564,6 +566,25 @@ unsafe fn start() -> (
             .initialize(virtio_net, mmio_queues)
             .unwrap();
 
+        Some(virtio_net as &'static VirtIONet<'static>)
+    } else {
+        // No VirtIO NetworkCard discovered
+        None
+    };
+
+    // By default, expose the VirtIO NetworkCard through the Ethernet Tap
+    // driver (forwarding raw Ethernet frames from and to userspace).
+    #[cfg(not(feature = "ipv4"))]
+    let virtio_ethernet_tap: Option<
+        &'static capsules_extra::ethernet_tap::EthernetTapDriver<
+            'static,
+            qemu_rv32_virt_chip::virtio::devices::virtio_net::VirtIONet<'static>,
+        >,
+    > = if let Some(virtio_net) = virtio_net {
+        use capsules_extra::ethernet_tap::EthernetTapDriver;
+        use kernel::hil::ethernet::EthernetAdapterDatapath;
+        use qemu_rv32_virt_chip::virtio::devices::virtio_net::VirtIONet;
+
         // Instantiate the userspace tap network driver over this device:
         let virtio_ethernet_tap_tx_buffer = static_init!(
             [u8; capsules_extra::ethernet_tap::MAX_MTU],
@@ -587,9 +608,85 @@ unsafe fn start() -> (
 
         Some(virtio_ethernet_tap as &'static EthernetTapDriver<'static, VirtIONet<'static>>)
     } else {
-        // No VirtIO NetworkCard discovered
         None
     };
+    #[cfg(feature = "ipv4")]
+    let virtio_ethernet_tap = None;
+
+    // With the `ipv4` feature, run the kernel IPv4 stack on the VirtIO
+    // NetworkCard instead, and expose it to userspace through the UDP driver.
+    #[cfg(feature = "ipv4")]
+    let udp_driver: Option<&'static capsules_extra::net::udp::UDPDriver<'static>> =
+        if let Some(virtio_net) = virtio_net {
+            use capsules_extra::net::ipv6::ip_utils::IPAddr;
+            use core::cell::Cell;
+            use qemu_rv32_virt_chip::chip::QemuRv32VirtClint;
+            use qemu_rv32_virt_chip::virtio::devices::virtio_net::VirtIONet;
+
+            // The VirtIONet driver does not read the MAC address from the
+            // device, so this has to match QEMU's default address.
+            const VIRTIO_NET_MAC_ADDRESS: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+            let local_ip_ifaces = static_init!([Cell<IPAddr>; 1], [Cell::new(IPAddr::new())]);
+
+            let (ip4_interface, udp_send_mux, udp_recv_mux, udp_port_table) =
+                components::ipv4::IP4InterfaceComponent::new(
+                    virtio_net,
+                    VIRTIO_NET_MAC_ADDRESS,
+                    local_ip_ifaces,
+                    mux_alarm,
+                )
+                .finalize(components::ip4_interface_component_static!(
+                    QemuRv32VirtClint,
+                    VirtIONet<'static>,
+                    8
+                ));
+
+            // Without DHCP, use the address QEMU's user-mode networking
+            // (SLIRP) is configured with in the Makefile.
+            #[cfg(not(feature = "ipv4_dhcp"))]
+            {
+                use capsules_extra::net::ipv4::ip4_interface::{IP4Config, IP4Configure};
+                use capsules_extra::net::ipv4::ip4_utils::IP4Addr;
+
+                ip4_interface.set_config(Some(IP4Config {
+                    addr: IP4Addr([192, 168, 1, 50]),
+                    netmask: IP4Addr([255, 255, 255, 0]),
+                    gateway: IP4Addr([192, 168, 1, 2]),
+                }));
+            }
+            #[cfg(feature = "ipv4_dhcp")]
+            components::ipv4::DhcpClientComponent::new(
+                ip4_interface,
+                udp_send_mux,
+                udp_recv_mux,
+                udp_port_table,
+                mux_alarm,
+            )
+            .finalize(components::dhcp_client_component_static!(
+                QemuRv32VirtClint,
+                VirtIONet<'static>,
+            ));
+
+            let udp_driver = components::udp_driver::UDPDriverComponent::new(
+                board_kernel,
+                capsules_extra::net::udp::DRIVER_NUM,
+                udp_send_mux,
+                udp_recv_mux,
+                udp_port_table,
+                local_ip_ifaces,
+            )
+            .finalize(components::ip4_udp_driver_component_static!(
+                QemuRv32VirtClint,
+                VirtIONet<'static>
+            ));
+
+            Some(udp_driver)
+        } else {
+            None
+        };
+    #[cfg(not(feature = "ipv4"))]
+    let udp_driver = None;
 
     let virtio_keyboard: Option<
         &'static qemu_rv32_virt_chip::virtio::devices::virtio_input::VirtIOInput,
@@ -731,6 +828,7 @@ unsafe fn start() -> (
         virtio_rng: virtio_rng_driver,
         virtio_ethernet_tap,
         virtio_gpu_screen,
+        udp_driver,
         ipc: kernel::ipc::IPC::new(
             board_kernel,
             kernel::ipc::DRIVER_NUM,
@@ -758,6 +856,8 @@ unsafe fn start() -> (
     }
     if virtio_ethernet_tap.is_some() {
         debug!("- Found VirtIO NetworkCard device, enabling EthernetTapDriver");
+    } else if udp_driver.is_some() {
+        debug!("- Found VirtIO NetworkCard device, enabling IPv4 stack and UDPDriver");
     } else {
         debug!("- VirtIO NetworkCard device not found, disabling EthernetTapDriver");
     }
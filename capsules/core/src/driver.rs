@@ -28,6 +28,9 @@ pub enum NUM {
     Ipc                   = 0x10000,
     AppLoader             = 0x10001,
     ProcessInfo           = 0x10002,
+    ProcessFaultDump      = 0x10003,
+    MessageIpc            = 0x10004,
+    ProcessWatchdog       = 0x10005,
 
     // HW Buses
     Spi                   = 0x20001,
@@ -46,6 +49,9 @@ pub enum NUM {
     Thread                = 0x30005,
     Eui64                 = 0x30006,
     EthernetTap           = 0x30007,
+    Tcp                   = 0x30008,
+    Ping                  = 0x30009,
+    Coap                  = 0x3000A,
 
     // Cryptography
     Rng                   = 0x40001,
@@ -61,6 +67,9 @@ pub enum NUM {
     SdCard                = 0x50002,
     Kv                    = 0x50003,
     IsolatedNvmStorage    = 0x50004,
+    FileSystem            = 0x50005,
+    FatFileSystem         = 0x50006,
+    MonotonicCounter      = 0x50007,
 
     // Sensors
     Temperature           = 0x60000,
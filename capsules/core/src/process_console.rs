@@ -12,9 +12,11 @@ use core::fmt;
 use core::fmt::write;
 use core::str;
 use kernel::capabilities::ProcessManagementCapability;
+use kernel::capabilities::ProcessMemoryInspectionCapability;
 use kernel::capabilities::ProcessStartCapability;
 use kernel::hil::time::ConvertTicks;
 use kernel::utilities::cells::MapCell;
+use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
 use kernel::ProcessId;
 
@@ -22,8 +24,12 @@ use kernel::debug;
 use kernel::hil::time::{Alarm, AlarmClient};
 use kernel::hil::uart;
 use kernel::introspection::KernelInfo;
+use kernel::process::{ProcessFaultDumps, ProcessFaultDumpsClient};
 use kernel::process::{ProcessPrinter, ProcessPrinterContext, State};
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::syscall_trace::SyscallTraceControl;
 use kernel::utilities::binary_write::BinaryWrite;
+use kernel::utilities::binary_write::WriteToBinaryOffsetWrapper;
 use kernel::ErrorCode;
 use kernel::Kernel;
 
@@ -44,7 +50,7 @@ pub const DEFAULT_COMMAND_HISTORY_LEN: usize = 10;
 /// List of valid commands for printing help. Consolidated as these are
 /// displayed in a few different cases.
 const VALID_COMMANDS_STR: &[u8] =
-    b"help status list stop start fault boot terminate process kernel reset panic console-start console-stop\r\n";
+    b"help status list stop start fault boot terminate process grants mem regs stack kernel trace faultdump reset panic console-start console-stop\r\n";
 
 /// Escape character for ANSI escape sequences.
 const ESC: u8 = b'\x1B';
@@ -70,6 +76,14 @@ const NLINE: u8 = b'\x0A';
 /// Upper limit for ASCII characters
 const ASCII_LIMIT: u8 = 128;
 
+/// Parse a decimal number, or a hexadecimal number prefixed with `0x`.
+fn parse_number(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
 /// States used for state machine to allow printing large strings asynchronously
 /// across multiple calls. This reduces the size of the buffer needed to print
 /// each section of the debug message.
@@ -91,6 +105,28 @@ enum WriterState {
         index: isize,
         total: isize,
     },
+    Trace {
+        index: isize,
+        total: isize,
+    },
+    Grants {
+        process_id: ProcessId,
+        index: isize,
+        total: isize,
+    },
+    /// Hexdump of process memory from `address` up to `end`.
+    Memory {
+        process_id: ProcessId,
+        address: usize,
+        end: usize,
+    },
+    /// Stored registers of a process, continuing at `offset` in the output.
+    Registers {
+        process_id: ProcessId,
+        offset: usize,
+    },
+    /// Waiting for a fault dump to be read from storage.
+    FaultDump,
 }
 
 /// Key that can be part from an escape sequence.
@@ -271,6 +307,18 @@ pub struct ProcessConsole<
     /// Function used to reset the device in bootloader mode
     reset_function: Option<fn() -> !>,
 
+    /// Optional system call trace that can be controlled and dumped with the
+    /// `trace` command.
+    syscall_trace: OptionalCell<&'a dyn SyscallTraceControl>,
+
+    /// Optional stored process fault dumps that can be shown and erased with
+    /// the `faultdump` command.
+    fault_dumps: OptionalCell<&'a dyn ProcessFaultDumps<'a>>,
+
+    /// Whether the `mem`, `regs` and `stack` commands may look inside
+    /// processes.
+    memory_inspection: Cell<bool>,
+
     /// This capsule needs to use potentially dangerous APIs related to
     /// processes, and requires a capability to access those APIs.
     capability: C,
@@ -477,10 +525,30 @@ impl<
             kernel,
             kernel_addresses,
             reset_function,
+            syscall_trace: OptionalCell::empty(),
+            fault_dumps: OptionalCell::empty(),
+            memory_inspection: Cell::new(false),
             capability,
         }
     }
 
+    /// Set the system call trace used by the `trace` command.
+    pub fn set_syscall_trace(&self, syscall_trace: &'a dyn SyscallTraceControl) {
+        self.syscall_trace.set(syscall_trace);
+    }
+
+    /// Set the process fault dumps used by the `faultdump` command. The
+    /// console must also be set as the client of `fault_dumps`.
+    pub fn set_fault_dumps(&self, fault_dumps: &'a dyn ProcessFaultDumps<'a>) {
+        self.fault_dumps.set(fault_dumps);
+    }
+
+    /// Enable the `mem`, `regs` and `stack` commands, which show the memory
+    /// and registers of processes.
+    pub fn enable_memory_inspection(&self, _capability: &dyn ProcessMemoryInspectionCapability) {
+        self.memory_inspection.set(true);
+    }
+
     /// Start the process console listening for user commands.
     pub fn start(&self) -> Result<(), ErrorCode> {
         if self.mode.get() == ProcessConsoleState::Off {
@@ -563,6 +631,44 @@ impl<
                     }
                 }
             }
+            WriterState::Trace { index, total } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::Trace {
+                        index: index + 1,
+                        total,
+                    }
+                }
+            }
+            WriterState::Grants {
+                process_id,
+                index,
+                total,
+            } => {
+                if index + 1 == total {
+                    WriterState::Empty
+                } else {
+                    WriterState::Grants {
+                        process_id,
+                        index: index + 1,
+                        total,
+                    }
+                }
+            }
+            WriterState::Memory {
+                process_id,
+                address,
+                end,
+            } => WriterState::Memory {
+                process_id,
+                address,
+                end,
+            },
+            WriterState::Registers { process_id, offset } => {
+                WriterState::Registers { process_id, offset }
+            }
+            WriterState::FaultDump => WriterState::FaultDump,
             WriterState::Empty => WriterState::Empty,
         }
     }
@@ -747,6 +853,152 @@ impl<
                         }
                     });
             }
+            WriterState::Trace { index, total: _ } => {
+                self.syscall_trace.map(|trace| {
+                    if let Some(entry) = trace.get_entry(index as usize) {
+                        let mut console_writer = ConsoleWriter::new();
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!(
+                                "{:>10} {:<7?} {:?} = {:?}\r\n",
+                                entry.timestamp_us, entry.processid, entry.syscall, entry.result,
+                            ),
+                        );
+                        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                    }
+                });
+            }
+            WriterState::Grants {
+                process_id,
+                index,
+                total,
+            } => {
+                let info: KernelInfo = KernelInfo::new(self.kernel);
+                // Skip over grants the process has not allocated.
+                let next = (index..total).find_map(|grant_num| {
+                    info.app_grant_allocation(process_id, grant_num as usize, &self.capability)
+                        .map(|grant| (grant_num, grant))
+                });
+                match next {
+                    Some((grant_num, grant)) => {
+                        let mut console_writer = ConsoleWriter::new();
+                        let _ = write(
+                            &mut console_writer,
+                            format_args!(
+                                " {:<#10x}{:6}{:9}{:4}{:4}\r\n",
+                                grant.driver_num,
+                                grant.size,
+                                grant.upcalls,
+                                grant.allow_ro,
+                                grant.allow_rw,
+                            ),
+                        );
+                        self.writer_state.replace(WriterState::Grants {
+                            process_id,
+                            index: grant_num,
+                            total,
+                        });
+                        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                    }
+                    None => {
+                        self.writer_state.replace(WriterState::Empty);
+                        // As setting the next state here to Empty does not
+                        // go through this match again before reading a new command,
+                        // we have to print the prompt here.
+                        self.prompt();
+                    }
+                }
+            }
+            WriterState::Memory {
+                process_id,
+                address,
+                end,
+            } => {
+                let mut line = [0u8; 16];
+                let len = cmp::min(line.len(), end - address);
+                let mut read = false;
+                self.kernel
+                    .process_each_capability(&self.capability, |process| {
+                        if process.processid() == process_id {
+                            read = process
+                                .build_readonly_process_buffer(address as *const u8, len)
+                                .is_ok_and(|buffer| {
+                                    buffer.enter(|memory| {
+                                        memory.copy_to_slice_or_err(&mut line[..len]).is_ok()
+                                    }) == Ok(true)
+                                });
+                        }
+                    });
+
+                if read {
+                    let mut console_writer = ConsoleWriter::new();
+                    let _ = write(&mut console_writer, format_args!(" {:#010x} ", address));
+                    for byte in &line[..len] {
+                        let _ = write(&mut console_writer, format_args!(" {:02x}", byte));
+                    }
+                    for _ in len..line.len() {
+                        let _ = write(&mut console_writer, format_args!("   "));
+                    }
+                    let _ = write(&mut console_writer, format_args!("  |"));
+                    for byte in &line[..len] {
+                        let c = if byte.is_ascii_graphic() || *byte == SPACE {
+                            *byte as char
+                        } else {
+                            '.'
+                        };
+                        let _ = write(&mut console_writer, format_args!("{}", c));
+                    }
+                    let _ = write(&mut console_writer, format_args!("|\r\n"));
+
+                    if address + len < end {
+                        self.writer_state.replace(WriterState::Memory {
+                            process_id,
+                            address: address + len,
+                            end,
+                        });
+                    } else {
+                        self.writer_state.replace(WriterState::Empty);
+                    }
+                    let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                } else {
+                    // The process went away or changed its memory layout.
+                    self.writer_state.replace(WriterState::Empty);
+                    let _ = self.write_bytes(b"Memory is no longer accessible.\r\n");
+                }
+            }
+            WriterState::Registers { process_id, offset } => {
+                let mut console_writer = ConsoleWriter::new();
+                let mut next_offset = None;
+                self.kernel
+                    .process_each_capability(&self.capability, |process| {
+                        if process.processid() == process_id {
+                            let mut bww = WriteToBinaryOffsetWrapper::new(&mut console_writer);
+                            bww.set_offset(offset);
+                            process.print_context(&mut bww);
+                            if bww.bytes_remaining() {
+                                next_offset = Some(bww.get_index());
+                            }
+                        }
+                    });
+
+                match next_offset {
+                    Some(offset) => {
+                        self.writer_state
+                            .replace(WriterState::Registers { process_id, offset });
+                    }
+                    None => {
+                        self.writer_state.replace(WriterState::Empty);
+                    }
+                }
+                if console_writer.size > 0 {
+                    let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                } else {
+                    // As setting the next state here to Empty does not
+                    // go through this match again before reading a new command,
+                    // we have to print the prompt here.
+                    self.prompt();
+                }
+            }
             WriterState::Empty => {
                 self.prompt();
             }
@@ -939,6 +1191,15 @@ impl<
                                 ),
                             );
                             let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                            console_writer.clear();
+                            let _ = write(
+                                &mut console_writer,
+                                format_args!(
+                                    "Budget overruns: {}\r\n",
+                                    info.budget_overruns(&self.capability)
+                                ),
+                            );
+                            let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
                         } else if clean_str.starts_with("process") {
                             let argument = clean_str.split_whitespace().nth(1);
                             argument.map(|name| {
@@ -977,6 +1238,14 @@ impl<
                                         }
                                     });
                             });
+                        } else if clean_str.starts_with("grants") {
+                            self.grants_command(clean_str);
+                        } else if clean_str.starts_with("mem") {
+                            self.mem_command(clean_str);
+                        } else if clean_str.starts_with("regs") {
+                            self.regs_command(clean_str);
+                        } else if clean_str.starts_with("stack") {
+                            self.stack_command(clean_str);
                         } else if clean_str.starts_with("kernel") {
                             let mut console_writer = ConsoleWriter::new();
                             let _ = write(
@@ -994,6 +1263,10 @@ impl<
                             // Prints kernel memory by moving the writer to the
                             // start state.
                             self.writer_state.replace(WriterState::KernelStart);
+                        } else if clean_str.starts_with("trace") {
+                            self.trace_command(clean_str);
+                        } else if clean_str.starts_with("faultdump") {
+                            self.fault_dump_command(clean_str);
                         } else if clean_str.starts_with("reset") {
                             self.reset_function.map_or_else(
                                 || {
@@ -1030,6 +1303,294 @@ impl<
         }
     }
 
+    /// Find the process with the PID in `argument`. Prints the usage or an
+    /// error and returns `None` if there is no such process.
+    fn process_id_argument(&self, argument: Option<&str>, usage: &[u8]) -> Option<ProcessId> {
+        let Some(pid) = argument.and_then(|pid| pid.parse::<usize>().ok()) else {
+            let _ = self.write_bytes(b"Usage: ");
+            let _ = self.write_bytes(usage);
+            let _ = self.write_bytes(b"\r\n");
+            return None;
+        };
+
+        let mut process_id = None;
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.processid().id() == pid {
+                    process_id = Some(process.processid());
+                }
+            });
+        if process_id.is_none() {
+            let _ = self.write_bytes(b"No process with that PID.\r\n");
+        }
+        process_id
+    }
+
+    /// Check that the commands looking inside processes are enabled, and print
+    /// an error if not.
+    fn check_memory_inspection(&self) -> bool {
+        if !self.memory_inspection.get() {
+            let _ = self.write_bytes(b"Memory inspection is not enabled.\r\n");
+        }
+        self.memory_inspection.get()
+    }
+
+    /// Handle the `mem <pid> <addr> <len>` command, which prints a hexdump of
+    /// memory the process can access.
+    fn mem_command(&self, command: &str) {
+        if !self.check_memory_inspection() {
+            return;
+        }
+
+        const USAGE: &[u8] = b"mem <pid> <addr> <len>";
+        let mut arguments = command.split_whitespace().skip(1);
+        let Some(process_id) = self.process_id_argument(arguments.next(), USAGE) else {
+            return;
+        };
+        let (Some(address), Some(len)) = (
+            arguments.next().and_then(parse_number),
+            arguments.next().and_then(parse_number),
+        ) else {
+            let _ = self.write_bytes(b"Usage: mem <pid> <addr> <len>\r\n");
+            return;
+        };
+
+        // Only show memory the MPU gives the process access to.
+        let mut accessible = false;
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.processid() == process_id {
+                    accessible = len > 0
+                        && process
+                            .build_readonly_process_buffer(address as *const u8, len)
+                            .is_ok();
+                }
+            });
+        if !accessible {
+            let _ = self.write_bytes(b"Memory is not accessible by the process.\r\n");
+            return;
+        }
+
+        self.writer_state.replace(WriterState::Memory {
+            process_id,
+            address,
+            end: address + len,
+        });
+        self.create_state_buffer(self.writer_state.get());
+    }
+
+    /// Handle the `regs <pid>` command, which prints the registers stored when
+    /// the process last stopped executing.
+    fn regs_command(&self, command: &str) {
+        if !self.check_memory_inspection() {
+            return;
+        }
+
+        let mut arguments = command.split_whitespace().skip(1);
+        let Some(process_id) = self.process_id_argument(arguments.next(), b"regs <pid>") else {
+            return;
+        };
+
+        self.writer_state.replace(WriterState::Registers {
+            process_id,
+            offset: 0,
+        });
+        self.create_state_buffer(self.writer_state.get());
+    }
+
+    /// Handle the `stack <pid>` command, which prints the stack pointer and
+    /// the lowest stack address seen by the kernel.
+    fn stack_command(&self, command: &str) {
+        if !self.check_memory_inspection() {
+            return;
+        }
+
+        let mut arguments = command.split_whitespace().skip(1);
+        let Some(process_id) = self.process_id_argument(arguments.next(), b"stack <pid>") else {
+            return;
+        };
+
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.processid() == process_id {
+                    let addresses = process.get_addresses();
+                    let mut console_writer = ConsoleWriter::new();
+                    let _ = write(&mut console_writer, format_args!(" Stack start:    "));
+                    let _ = match addresses.sram_stack_top {
+                        Some(top) => write(&mut console_writer, format_args!("{:#010x}\r\n", top)),
+                        None => write(&mut console_writer, format_args!("unknown\r\n")),
+                    };
+                    let _ = write(&mut console_writer, format_args!(" Stack pointer:  "));
+                    let _ = match addresses.sram_stack_pointer {
+                        Some(sp) => write(&mut console_writer, format_args!("{:#010x}\r\n", sp)),
+                        None => write(&mut console_writer, format_args!("unknown\r\n")),
+                    };
+                    let _ = write(&mut console_writer, format_args!(" High-water mark: "));
+                    let _ = match (addresses.sram_stack_top, addresses.sram_stack_bottom) {
+                        (Some(top), Some(bottom)) => write(
+                            &mut console_writer,
+                            format_args!(
+                                "{:#010x} ({} bytes)\r\n",
+                                bottom,
+                                top.saturating_sub(bottom)
+                            ),
+                        ),
+                        (None, Some(bottom)) => {
+                            write(&mut console_writer, format_args!("{:#010x}\r\n", bottom))
+                        }
+                        (_, None) => write(&mut console_writer, format_args!("unknown\r\n")),
+                    };
+                    let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                }
+            });
+    }
+
+    /// Handle the `grants <pid>` command, which lists the memory the process
+    /// allocated for each driver's grant.
+    fn grants_command(&self, command: &str) {
+        let mut arguments = command.split_whitespace().skip(1);
+        let Some(process_id) = self.process_id_argument(arguments.next(), b"grants <pid>") else {
+            return;
+        };
+
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        let (grants_used, grants_total) = info.number_app_grant_uses(process_id, &self.capability);
+        if grants_used > 0 {
+            let _ = self.write_bytes(b" Driver     Bytes  Upcalls  RO  RW\r\n");
+            // Start the state machine to print each grant separately.
+            self.write_state(WriterState::Grants {
+                process_id,
+                index: -1,
+                total: grants_total as isize,
+            });
+        } else {
+            let _ = self.write_bytes(b"No grants allocated.\r\n");
+        }
+    }
+
+    /// Handle the `trace` command, which controls and dumps the system call
+    /// trace.
+    fn trace_command(&self, command: &str) {
+        let Some(trace) = self.syscall_trace.get() else {
+            let _ = self.write_bytes(b"Syscall tracing is not available.\r\n");
+            return;
+        };
+
+        let mut arguments = command.split_whitespace().skip(1);
+        match (arguments.next(), arguments.next(), arguments.next()) {
+            (Some("on"), None, None) => {
+                trace.set_enabled(true);
+                let _ = self.write_bytes(b"Syscall tracing enabled.\r\n");
+            }
+            (Some("off"), None, None) => {
+                trace.set_enabled(false);
+                let _ = self.write_bytes(b"Syscall tracing disabled.\r\n");
+            }
+            (Some("clear"), None, None) => {
+                trace.clear();
+                let _ = self.write_bytes(b"Syscall trace cleared.\r\n");
+            }
+            (Some("dump"), None, None) => {
+                let count = trace.entry_count();
+                if count > 0 {
+                    let _ = self.write_bytes(b" Time (us) PID     Syscall = Result\r\n");
+                    // Start the state machine to print each entry separately.
+                    self.write_state(WriterState::Trace {
+                        index: -1,
+                        total: count as isize,
+                    });
+                } else {
+                    let _ = self.write_bytes(b"Syscall trace is empty.\r\n");
+                }
+            }
+            (Some("filter"), Some("process"), Some("all")) => {
+                trace.set_process_filter(None);
+            }
+            (Some("filter"), Some("process"), pid) => {
+                if let Some(processid) =
+                    self.process_id_argument(pid, b"trace filter process <pid>|all")
+                {
+                    trace.set_process_filter(Some(processid));
+                }
+            }
+            (Some("filter"), Some("driver"), Some("all")) => {
+                trace.set_driver_filter(None);
+            }
+            (Some("filter"), Some("driver"), Some(number)) => match parse_number(number) {
+                Some(driver_num) => trace.set_driver_filter(Some(driver_num)),
+                None => {
+                    let _ = self.write_bytes(b"Invalid driver number.\r\n");
+                }
+            },
+            (None, None, None) => {
+                let mut console_writer = ConsoleWriter::new();
+                let _ = write(
+                    &mut console_writer,
+                    format_args!(
+                        "Syscall tracing is {}, {} entries recorded.\r\n",
+                        if trace.is_enabled() { "on" } else { "off" },
+                        trace.entry_count(),
+                    ),
+                );
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+            }
+            _ => {
+                let _ = self.write_bytes(
+                    b"Usage: trace [on|off|clear|dump|filter process <pid>|all|filter driver <num>|all]\r\n",
+                );
+            }
+        }
+    }
+
+    /// Handle the `faultdump` command, which shows and erases stored process
+    /// fault dumps.
+    fn fault_dump_command(&self, command: &str) {
+        let Some(fault_dumps) = self.fault_dumps.get() else {
+            let _ = self.write_bytes(b"Fault dumps are not available.\r\n");
+            return;
+        };
+
+        let mut arguments = command.split_whitespace().skip(1);
+        let result = match (arguments.next(), arguments.next()) {
+            (None, None) => {
+                let mut console_writer = ConsoleWriter::new();
+                let _ = write(
+                    &mut console_writer,
+                    format_args!(
+                        "{} fault dumps stored. Use `faultdump <n>` to show dump n, 0 is the most recent.\r\n",
+                        fault_dumps.dump_count(),
+                    ),
+                );
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                return;
+            }
+            (Some("clear"), None) => fault_dumps.clear_dumps(),
+            (Some(index), None) => match index.parse::<usize>() {
+                Ok(index) => fault_dumps.read_dump(index),
+                Err(_) => Err(ErrorCode::INVAL),
+            },
+            _ => Err(ErrorCode::INVAL),
+        };
+
+        match result {
+            // Wait for the operation to finish before showing the prompt.
+            Ok(()) => self.writer_state.replace(WriterState::FaultDump),
+            Err(ErrorCode::INVAL) => {
+                let _ = self.write_bytes(b"Usage: faultdump [<n>|clear]\r\n");
+                WriterState::Empty
+            }
+            Err(e) => {
+                let mut console_writer = ConsoleWriter::new();
+                let _ = write(
+                    &mut console_writer,
+                    format_args!("Fault dump operation failed: {:?}\r\n", e),
+                );
+                let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+                WriterState::Empty
+            }
+        };
+    }
+
     fn prompt(&self) {
         // Only display the prompt in active mode.
         if self.mode.get() == ProcessConsoleState::Active {
@@ -1489,3 +2050,42 @@ impl<
         let _ = self.uart.receive_buffer(read_buf, 1);
     }
 }
+
+impl<
+        'a,
+        const COMMAND_HISTORY_LEN: usize,
+        A: Alarm<'a>,
+        C: ProcessManagementCapability + ProcessStartCapability,
+    > ProcessFaultDumpsClient for ProcessConsole<'a, COMMAND_HISTORY_LEN, A, C>
+{
+    fn dump_read(&self, result: Result<&dyn fmt::Display, ErrorCode>) {
+        let mut console_writer = ConsoleWriter::new();
+        match result {
+            Ok(summary) => {
+                let _ = write(&mut console_writer, format_args!("{}\r\n", summary));
+            }
+            Err(e) => {
+                let _ = write(
+                    &mut console_writer,
+                    format_args!("Reading fault dump failed: {:?}\r\n", e),
+                );
+            }
+        }
+        self.writer_state.replace(WriterState::Empty);
+        let _ = self.write_bytes(&(console_writer.buf)[..console_writer.size]);
+        self.prompt();
+    }
+
+    fn dumps_cleared(&self, result: Result<(), ErrorCode>) {
+        self.writer_state.replace(WriterState::Empty);
+        match result {
+            Ok(()) => {
+                let _ = self.write_bytes(b"Fault dumps erased.\r\n");
+            }
+            Err(_) => {
+                let _ = self.write_bytes(b"Erasing fault dumps failed.\r\n");
+            }
+        }
+        self.prompt();
+    }
+}
@@ -10,6 +10,11 @@
 //! written. Then the app is actually written to flash. Finally, the
 //! the userspace app sends a request for the app to be loaded.
 //!
+//! Instead of loading the written app as a new process, the userspace app can
+//! request that it replaces the running process of the same app with an older
+//! version. The update can then be committed, which removes the older binary,
+//! or rolled back to the older binary.
+//!
 //!
 //! Here is a diagram of the expected stack with this capsule:
 //! Boxes are components and between the boxes are the traits that are the
@@ -29,6 +34,7 @@
 //! +-----------------------------------------------------------------+
 //!         kernel::dynamic_binary_storage::DynamicBinaryStore
 //!         kernel::dynamic_binary_storage::DynamicProcessLoad
+//!         kernel::dynamic_binary_storage::DynamicProcessUpdate
 //! +-----------------------------------------------------------------+
 //! |                                     |                           |
 //! |  Physical Nonvolatile Storage       |           Kernel          |
@@ -62,7 +68,8 @@
 //!
 //! NOTE:
 //! 1. This capsule is not virtualized, and can only serve one app at a time.
-//! 2. This implementation currently only loads new apps. It does not update apps.
+//! 2. An update replaces the running process, but the memory of that process
+//!    is not reused until the board reboots.
 //! ```
 
 use core::cell::Cell;
@@ -94,8 +101,14 @@ mod upcall {
     pub const LOAD_DONE: usize = 3;
     /// Abort done callback.
     pub const ABORT_DONE: usize = 4;
+    /// Update done callback.
+    pub const UPDATE_DONE: usize = 5;
+    /// Commit done callback.
+    pub const COMMIT_DONE: usize = 6;
+    /// Rollback done callback.
+    pub const ROLLBACK_DONE: usize = 7;
     /// Number of upcalls.
-    pub const COUNT: u8 = 5;
+    pub const COUNT: u8 = 8;
 }
 
 // Ids for read-only allow buffers
@@ -117,7 +130,9 @@ pub struct App {
 
 pub struct AppLoader<
     S: dynamic_binary_storage::DynamicBinaryStore + 'static,
-    L: dynamic_binary_storage::DynamicProcessLoad + 'static,
+    L: dynamic_binary_storage::DynamicProcessLoad
+        + dynamic_binary_storage::DynamicProcessUpdate
+        + 'static,
 > {
     // The underlying driver for the process flashing and loading.
     storage_driver: &'static S,
@@ -139,7 +154,9 @@ pub struct AppLoader<
 
 impl<
         S: dynamic_binary_storage::DynamicBinaryStore + 'static,
-        L: dynamic_binary_storage::DynamicProcessLoad + 'static,
+        L: dynamic_binary_storage::DynamicProcessLoad
+            + dynamic_binary_storage::DynamicProcessUpdate
+            + 'static,
     > AppLoader<S, L>
 {
     pub fn new(
@@ -163,6 +180,17 @@ impl<
         }
     }
 
+    /// Release the driver and signal the completion of the current operation
+    /// with upcall `upcall_num`.
+    fn signal_done(&self, upcall_num: usize, result: Result<(), ErrorCode>) {
+        self.current_process.take().map(|processid| {
+            let _ = self.apps.enter(processid, move |app, kernel_data| {
+                app.pending_command = false;
+                let _ = kernel_data.schedule_upcall(upcall_num, (into_statuscode(result), 0, 0));
+            });
+        });
+    }
+
     /// Copy data from the shared buffer with app and request kernel to
     /// write the app data to flash.
     fn write(&self, offset: usize, length: usize, processid: ProcessId) -> Result<(), ErrorCode> {
@@ -237,7 +265,9 @@ impl<
 
 impl<
         S: dynamic_binary_storage::DynamicBinaryStore + 'static,
-        L: dynamic_binary_storage::DynamicProcessLoad + 'static,
+        L: dynamic_binary_storage::DynamicProcessLoad
+            + dynamic_binary_storage::DynamicProcessUpdate
+            + 'static,
     > dynamic_binary_storage::DynamicBinaryStoreClient for AppLoader<S, L>
 {
     /// Let the requesting app know we are done setting up for the new app
@@ -300,7 +330,9 @@ impl<
 
 impl<
         S: dynamic_binary_storage::DynamicBinaryStore + 'static,
-        L: dynamic_binary_storage::DynamicProcessLoad + 'static,
+        L: dynamic_binary_storage::DynamicProcessLoad
+            + dynamic_binary_storage::DynamicProcessUpdate
+            + 'static,
     > dynamic_binary_storage::DynamicProcessLoadClient for AppLoader<S, L>
 {
     /// Let the requesting app know we are done loading the new process
@@ -345,10 +377,35 @@ impl<
     }
 }
 
+impl<
+        S: dynamic_binary_storage::DynamicBinaryStore + 'static,
+        L: dynamic_binary_storage::DynamicProcessLoad
+            + dynamic_binary_storage::DynamicProcessUpdate
+            + 'static,
+    > dynamic_binary_storage::DynamicProcessUpdateClient for AppLoader<S, L>
+{
+    /// Let the requesting app know we are done updating the process
+    fn update_done(&self, result: Result<(), ErrorCode>) {
+        self.signal_done(upcall::UPDATE_DONE, result);
+    }
+
+    /// Let the requesting app know we are done committing the update
+    fn commit_done(&self, result: Result<(), ErrorCode>) {
+        self.signal_done(upcall::COMMIT_DONE, result);
+    }
+
+    /// Let the requesting app know we are done rolling back the update
+    fn rollback_done(&self, result: Result<(), ErrorCode>) {
+        self.signal_done(upcall::ROLLBACK_DONE, result);
+    }
+}
+
 /// Provide an interface for userland.
 impl<
         S: dynamic_binary_storage::DynamicBinaryStore + 'static,
-        L: dynamic_binary_storage::DynamicProcessLoad + 'static,
+        L: dynamic_binary_storage::DynamicProcessLoad
+            + dynamic_binary_storage::DynamicProcessUpdate
+            + 'static,
     > SyscallDriver for AppLoader<S, L>
 {
     /// Command interface.
@@ -387,6 +444,16 @@ impl<
     ///  - Returns ErrorCode::BUSY when the abort fails
     ///  (due to padding app being unable to be written, so try again)
     ///  - Returns ErrorCode::FAIL if the driver is not dedicated to this process
+    /// - `6`: Request kernel to update the running process of the same app to
+    ///   the finalized app instead of loading it as a new process.
+    ///  - Returns Ok(()) when the update has started
+    ///  - Returns ErrorCode::INVAL if no process of the same app is running
+    ///  - Returns ErrorCode::ALREADY if the running process is not older
+    ///  - Returns ErrorCode::BUSY if a previous update is still pending
+    /// - `7`: Request kernel to commit the pending update, removing the
+    ///   previous version of the app.
+    /// - `8`: Request kernel to roll back the pending update to the previous
+    ///   version of the app.
     ///
     /// The driver returns ErrorCode::INVAL if any operation is called before the
     /// preceeding operation was invoked. For example, `write()` cannot be called before
@@ -510,6 +577,35 @@ impl<
                     }
                 }
             }
+            6 => {
+                // Request kernel to update a running process to the new app.
+                let res = self.load_driver.update();
+                self.new_app_length.set(0);
+                match res {
+                    Ok(()) => CommandReturn::success(),
+                    Err(e) => {
+                        self.current_process.take();
+                        CommandReturn::failure(e)
+                    }
+                }
+            }
+
+            7 | 8 => {
+                // Request kernel to commit or roll back the pending update.
+                let res = if command_num == 7 {
+                    self.load_driver.commit()
+                } else {
+                    self.load_driver.rollback()
+                };
+                match res {
+                    Ok(()) => CommandReturn::success(),
+                    Err(e) => {
+                        self.current_process.take();
+                        CommandReturn::failure(e)
+                    }
+                }
+            }
+
             // Unsupported command numbers.
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
@@ -18,6 +18,11 @@ impl Eui64 {
     pub fn new(eui64: u64) -> Eui64 {
         Eui64 { eui64 }
     }
+
+    /// Returns the EUI-64 of the device.
+    pub fn eui64(&self) -> u64 {
+        self.eui64
+    }
 }
 
 impl SyscallDriver for Eui64 {
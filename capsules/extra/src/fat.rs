@@ -0,0 +1,1929 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Read-only FAT12/16/32 filesystem on a block device, such as an SD card.
+//!
+//! This capsule reads files from a FAT volume on a block device so
+//! applications can open a path and read it without parsing the filesystem
+//! themselves.
+//!
+//! ```text
+//! +------------------------------------------------------------------------+
+//! |                             userspace                                  |
+//! +------------------------------------------------------------------------+
+//!                             kernel::Driver
+//! +------------------------------------------------------------------------+
+//! |                        fat::FatDriver (this)                           |
+//! |                        fat::FatReader                                  |
+//! +------------------------------------------------------------------------+
+//!                             hil::block_device
+//! +------------------------------------------------------------------------+
+//! |    sdcard_block_device::SDCardBlockDevice, virtual_block_device, ...   |
+//! +------------------------------------------------------------------------+
+//! ```
+//!
+//! The volume is found by checking whether the first sector of the device is a
+//! FAT boot sector (a device without a partition table). Otherwise the
+//! partition table is read with `partition_table::PartitionTable` and the
+//! first FAT partition in the MBR is used, or if the device has a GPT, the
+//! first basic data or EFI system partition. Long file names are supported, and names are
+//! matched without regard to ASCII case.
+//!
+//! The filesystem logic is in `FatReader`, which does not access the device
+//! itself. Instead, each operation returns a `Step` saying which sector it
+//! needs next, and `FatDriver` reads sectors from the device and passes them
+//! back to the reader. `FatDriver` keeps the most recently read sector so that
+//! consecutive accesses to the same sector do not read the device again.
+//! Only devices with 512 byte blocks are supported. If a read fails with
+//! `UNINSTALLED`, the volume is unmounted and must be mounted again once a
+//! card is inserted.
+//!
+//! `FatDriver` is the client of the block device. To share an SD card with
+//! other users, pass it a `virtual_block_device::VirtualBlockDevice`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let fat = components::fat::FatComponent::new(
+//!     board_kernel,
+//!     capsules_extra::fat::DRIVER_NUM,
+//!     sdcard_block_device,
+//! )
+//! .finalize(components::fat_component_static!(
+//!     SDCardBlockDevice<'static, VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>>
+//! ));
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::ops::Range;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::block_device::{BlockDevice, BlockDeviceClient};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+
+use crate::partition_table::{PartitionTable, PartitionType};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::FatFileSystem as usize;
+
+/// IDs for subscribed upcalls.
+mod upcall {
+    /// Mount, open, read or read directory done callback.
+    pub const DONE: usize = 0;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// The path of the file or directory to open.
+    pub const PATH: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// The buffer to read file data into.
+    pub const READ: usize = 0;
+    /// The buffer to store a directory entry name into.
+    pub const NAME: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Size of a sector. Only volumes with this sector size are supported.
+pub const SECTOR_SIZE: usize = 512;
+/// Maximum length of a path provided by an application.
+pub const MAX_PATH_LEN: usize = 128;
+/// Number of files each application can have open at once.
+pub const MAX_OPEN_FILES: usize = 4;
+
+/// Maximum length of a long file name, in UTF-16 code units.
+const MAX_LFN_LEN: usize = 255;
+/// Number of UTF-16 code units in each long file name entry.
+const LFN_ENTRY_CHARS: usize = 13;
+/// Offsets of the UTF-16 code units in a long file name entry.
+const LFN_CHAR_OFFSETS: [usize; LFN_ENTRY_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// Flag in the sequence number of the last long file name entry.
+const LFN_LAST: u8 = 0x40;
+const LFN_SEQUENCE_MASK: u8 = 0x1F;
+
+const DIR_ENTRY_LEN: usize = 32;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0F;
+const ATTR_LONG_NAME_MASK: u8 = 0x3F;
+
+/// First byte of the entry after the last entry in a directory.
+const ENTRY_END: u8 = 0x00;
+/// First byte of a deleted entry.
+const ENTRY_DELETED: u8 = 0xE5;
+/// A short name starting with 0xE5 is stored starting with 0x05.
+const ENTRY_E5: u8 = 0x05;
+
+/// Flags in the case byte of a short entry.
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXT: u8 = 0x10;
+
+/// The first cluster of the fixed root directory of FAT12 and FAT16 volumes.
+/// Cluster numbers are at most 28 bits, so this is never a real cluster.
+const FIXED_ROOT: u32 = u32::MAX;
+
+/// MBR partition types for FAT volumes.
+const MBR_FAT_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E];
+
+/// Microsoft basic data partition type GUID, as stored on disk.
+const GPT_BASIC_DATA: [u8; 16] = [
+    0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+];
+/// EFI system partition type GUID, as stored on disk.
+const GPT_EFI_SYSTEM: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+
+fn u16_at(b: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([b[i], b[i + 1]])
+}
+
+fn u32_at(b: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+}
+
+/// The variant of FAT a volume uses.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// The layout of a mounted volume. Sector numbers are from the start of the
+/// device.
+#[derive(Clone, Copy, Debug)]
+struct Volume {
+    fat_type: FatType,
+    sectors_per_cluster: u32,
+    /// First sector of the first FAT.
+    fat_start: u32,
+    /// First sector of the fixed root directory (FAT12 and FAT16).
+    root_dir_start: u32,
+    root_dir_sectors: u32,
+    /// Sector holding cluster 2.
+    data_start: u32,
+    cluster_count: u32,
+    /// First cluster of the root directory, `FIXED_ROOT` for FAT12 and FAT16.
+    root_cluster: u32,
+}
+
+/// Where the next sector of a file or directory is.
+enum Location {
+    /// The data is in this sector.
+    Sector(u32),
+    /// The next cluster must be looked up in this sector of the FAT.
+    Fat(u32),
+    /// The end of the cluster chain or the fixed root directory.
+    End,
+}
+
+impl Volume {
+    /// Parse the boot sector of a volume that starts at sector `start`,
+    /// returning `None` if it is not a supported FAT volume.
+    fn parse(boot: &[u8], start: u32) -> Option<Self> {
+        // Boot sectors start with a jump instruction.
+        if boot[0] != 0xEB && boot[0] != 0xE9 {
+            return None;
+        }
+
+        let bytes_per_sector = u16_at(boot, 11) as usize;
+        let sectors_per_cluster = boot[13] as u32;
+        let reserved = u16_at(boot, 14) as u32;
+        let fats = boot[16] as u32;
+        let root_entries = u16_at(boot, 17) as u32;
+        let total = match u16_at(boot, 19) {
+            0 => u32_at(boot, 32),
+            n => n as u32,
+        };
+        let fat_size = match u16_at(boot, 22) {
+            0 => u32_at(boot, 36),
+            n => n as u32,
+        };
+        if bytes_per_sector != SECTOR_SIZE
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved == 0
+            || fats == 0
+            || fat_size == 0
+        {
+            return None;
+        }
+        start.checked_add(total)?;
+
+        let root_dir_sectors = (root_entries * DIR_ENTRY_LEN as u32).div_ceil(SECTOR_SIZE as u32);
+        let fat_sectors = fats.checked_mul(fat_size)?;
+        let meta = reserved
+            .checked_add(fat_sectors)?
+            .checked_add(root_dir_sectors)?;
+        let cluster_count = total.checked_sub(meta)? / sectors_per_cluster;
+
+        // The FAT type is determined only by the number of clusters.
+        let fat_type = if cluster_count < 4085 {
+            FatType::Fat12
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+        let root_cluster = match fat_type {
+            FatType::Fat32 if root_entries == 0 => u32_at(boot, 44),
+            FatType::Fat12 | FatType::Fat16 if root_entries != 0 => FIXED_ROOT,
+            _ => return None,
+        };
+
+        let volume = Self {
+            fat_type,
+            sectors_per_cluster,
+            fat_start: start + reserved,
+            root_dir_start: start + reserved + fat_sectors,
+            root_dir_sectors,
+            data_start: start + meta,
+            cluster_count,
+            root_cluster,
+        };
+        if root_cluster != FIXED_ROOT && !volume.is_data_cluster(root_cluster) {
+            return None;
+        }
+        Some(volume)
+    }
+
+    fn cluster_bytes(&self) -> u32 {
+        self.sectors_per_cluster * SECTOR_SIZE as u32
+    }
+
+    /// Whether `cluster` holds data, rather than marking the end of a chain, a
+    /// bad cluster or a free cluster.
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster - 2 < self.cluster_count
+    }
+
+    /// The first sector of `cluster`.
+    fn cluster_sector(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    /// Offset in bytes of the FAT entry for `cluster` from the start of the FAT.
+    fn fat_offset(&self, cluster: u32) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => cluster + cluster / 2,
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4,
+        }
+    }
+
+    /// The sector of the FAT holding the entry for `cluster`.
+    fn fat_sector(&self, cluster: u32) -> u32 {
+        self.fat_start + self.fat_offset(cluster) / SECTOR_SIZE as u32
+    }
+
+    /// The root directory.
+    fn root(&self) -> File {
+        File::new(self.root_cluster, 0, true)
+    }
+
+    /// Find the sector holding the current position of `cursor`, moving it to
+    /// the right cluster if that cluster is already known.
+    fn locate(&self, cursor: &mut Cursor) -> Location {
+        if cursor.first == FIXED_ROOT {
+            let sector = cursor.position / SECTOR_SIZE as u32;
+            return if sector < self.root_dir_sectors {
+                Location::Sector(self.root_dir_start + sector)
+            } else {
+                Location::End
+            };
+        }
+
+        let target = cursor.position / self.cluster_bytes();
+        if target < cursor.index {
+            // Cluster chains can only be followed forwards.
+            cursor.cluster = cursor.first;
+            cursor.index = 0;
+        }
+        if !self.is_data_cluster(cursor.cluster) {
+            Location::End
+        } else if cursor.index == target {
+            let offset = (cursor.position % self.cluster_bytes()) / SECTOR_SIZE as u32;
+            Location::Sector(self.cluster_sector(cursor.cluster) + offset)
+        } else {
+            Location::Fat(self.fat_sector(cursor.cluster))
+        }
+    }
+}
+
+/// A position in a file or directory.
+#[derive(Clone, Copy, Debug)]
+struct Cursor {
+    /// The first cluster, or `FIXED_ROOT`.
+    first: u32,
+    /// The cluster most recently found in the chain.
+    cluster: u32,
+    /// The index of `cluster` in the chain.
+    index: u32,
+    /// Offset in bytes from the start.
+    position: u32,
+}
+
+/// A file or directory on the volume, and a position in it.
+#[derive(Clone, Copy, Debug)]
+pub struct File {
+    cursor: Cursor,
+    size: u32,
+    is_dir: bool,
+}
+
+impl File {
+    fn new(first: u32, size: u32, is_dir: bool) -> Self {
+        Self {
+            cursor: Cursor {
+                first,
+                cluster: first,
+                index: 0,
+                position: 0,
+            },
+            // The size of directories is not stored.
+            size: if is_dir { 0 } else { size },
+            is_dir,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    pub fn position(&self) -> u32 {
+        self.cursor.position
+    }
+
+    /// Set the position in the file. The position can be at most the size of
+    /// the file, and directories can only be rewound to the start.
+    pub fn seek(&mut self, position: u32) -> Result<(), ErrorCode> {
+        let max = if self.is_dir { 0 } else { self.size };
+        if position > max {
+            return Err(ErrorCode::INVAL);
+        }
+        self.cursor.position = position;
+        Ok(())
+    }
+}
+
+/// What the reader needs to make progress on an operation.
+#[derive(Clone, Debug)]
+pub enum Step {
+    /// Read this sector and pass it to `FatReader::sector_read()`.
+    Read(u32),
+    /// These bytes of the sector just passed to `FatReader::sector_read()` are
+    /// file data. Call `FatReader::resume()` once they have been used.
+    Data(Range<usize>),
+    /// The operation finished. This is the root directory for mount, the file
+    /// or directory for open, and the file or directory with its position
+    /// updated for read and read directory.
+    Done(Result<File, ErrorCode>),
+}
+
+/// The operation in progress.
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Idle,
+    /// Reading the first sector of the device.
+    Mount,
+    /// Reading the partition table.
+    Table,
+    /// Reading the boot sector of a partition.
+    Boot {
+        start: u32,
+    },
+    /// Looking for the current path component in `dir`.
+    Lookup {
+        dir: File,
+    },
+    /// Looking for the next entry in `dir`.
+    ReadDir {
+        dir: File,
+    },
+    /// Reading up to `remaining` bytes of `file`.
+    Read {
+        file: File,
+        remaining: u32,
+    },
+}
+
+/// What the sector being read is for, for operations that use a `Cursor`.
+#[derive(Clone, Copy, Debug)]
+enum Awaiting {
+    /// Data from the file or directory.
+    Data,
+    /// The FAT entry for the current cluster.
+    Fat,
+    /// The second byte of a FAT12 entry that spans two sectors.
+    FatHigh(u8),
+}
+
+/// The filesystem logic for reading a FAT volume, independent of how sectors
+/// are read. Only one operation can be in progress at a time.
+pub struct FatReader {
+    volume: Option<Volume>,
+    op: Op,
+    table: PartitionTable,
+    awaiting: Awaiting,
+
+    /// The path being opened.
+    path: [u8; MAX_PATH_LEN],
+    path_len: usize,
+    /// The path component being looked up.
+    component: (usize, usize),
+
+    /// The long name being assembled from long name entries.
+    lfn: [u16; MAX_LFN_LEN],
+    lfn_len: usize,
+    lfn_checksum: u8,
+    /// Sequence number of the next long name entry expected, or `None` if
+    /// there is no valid long name.
+    lfn_next: Option<u8>,
+    /// Whether the long name belongs to the short entry in `short`.
+    has_lfn: bool,
+    /// The name and case flags of the last short entry.
+    short: [u8; 11],
+    case: u8,
+
+    /// The entry found by the last read directory operation.
+    entry: Option<File>,
+}
+
+impl Default for FatReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FatReader {
+    pub const fn new() -> Self {
+        Self {
+            volume: None,
+            op: Op::Idle,
+            table: PartitionTable::new(),
+            awaiting: Awaiting::Data,
+            path: [0; MAX_PATH_LEN],
+            path_len: 0,
+            component: (0, 0),
+            lfn: [0; MAX_LFN_LEN],
+            lfn_len: 0,
+            lfn_checksum: 0,
+            lfn_next: None,
+            has_lfn: false,
+            short: [b' '; 11],
+            case: 0,
+            entry: None,
+        }
+    }
+
+    /// The type of the mounted volume, if there is one.
+    pub fn fat_type(&self) -> Option<FatType> {
+        self.volume.map(|volume| volume.fat_type)
+    }
+
+    /// Forget the mounted volume, for example because the card was removed.
+    pub fn unmount(&mut self) {
+        self.volume = None;
+        self.op = Op::Idle;
+    }
+
+    /// Find and mount the volume on the device. Finishes with the root
+    /// directory.
+    pub fn mount(&mut self) -> Step {
+        self.volume = None;
+        self.op = Op::Mount;
+        Step::Read(0)
+    }
+
+    /// Look up `path`, which is relative to the root directory and uses `/` as
+    /// a separator. Finishes with the file or directory.
+    pub fn open(&mut self, path: &[u8]) -> Step {
+        let Some(volume) = self.volume else {
+            return self.finish(Err(ErrorCode::OFF));
+        };
+        if path.len() > MAX_PATH_LEN {
+            return self.finish(Err(ErrorCode::SIZE));
+        }
+        self.path[..path.len()].copy_from_slice(path);
+        self.path_len = path.len();
+        self.component = (0, 0);
+        self.descend(volume.root())
+    }
+
+    /// Find the next entry in `dir` from its current position. Finishes with
+    /// `dir` positioned after the entry, and the entry is available from
+    /// `entry()` and `name()`. The operation fails with `NOSUPPORT` at the end
+    /// of the directory.
+    pub fn read_dir(&mut self, dir: File) -> Step {
+        if self.volume.is_none() {
+            return self.finish(Err(ErrorCode::OFF));
+        }
+        if !dir.is_dir {
+            return self.finish(Err(ErrorCode::INVAL));
+        }
+        self.entry = None;
+        self.lfn_next = None;
+        self.op = Op::ReadDir { dir };
+        self.advance()
+    }
+
+    /// Read up to `length` bytes of `file` from its current position. The data
+    /// is provided with `Step::Data`, and the operation finishes with `file`
+    /// positioned after the data.
+    pub fn read(&mut self, file: File, length: u32) -> Step {
+        if self.volume.is_none() {
+            return self.finish(Err(ErrorCode::OFF));
+        }
+        if file.is_dir {
+            return self.finish(Err(ErrorCode::INVAL));
+        }
+        self.op = Op::Read {
+            file,
+            remaining: length,
+        };
+        self.advance()
+    }
+
+    /// Continue the operation with the contents of the sector requested with
+    /// `Step::Read`.
+    pub fn sector_read(&mut self, sector: &[u8]) -> Step {
+        if sector.len() < SECTOR_SIZE {
+            return self.finish(Err(ErrorCode::SIZE));
+        }
+
+        match self.op {
+            Op::Idle => Step::Done(Err(ErrorCode::FAIL)),
+            Op::Mount => self.first_sector_read(sector),
+            Op::Table => self.table_read(sector),
+            Op::Boot { start } => match Volume::parse(sector, start) {
+                Some(volume) => {
+                    self.volume = Some(volume);
+                    self.finish(Ok(volume.root()))
+                }
+                None => self.finish(Err(ErrorCode::NOSUPPORT)),
+            },
+            Op::Lookup { .. } | Op::ReadDir { .. } | Op::Read { .. } => match self.awaiting {
+                Awaiting::Data => self.data_read(sector),
+                Awaiting::Fat => self.fat_read(sector, None),
+                Awaiting::FatHigh(low) => self.fat_read(sector, Some(low)),
+            },
+        }
+    }
+
+    /// Continue a read operation after the data from `Step::Data` was used.
+    pub fn resume(&mut self) -> Step {
+        self.advance()
+    }
+
+    /// The entry found by the last read directory operation.
+    pub fn entry(&self) -> Option<File> {
+        self.entry
+    }
+
+    /// The name of the entry found by the last read directory operation,
+    /// encoded as UTF-8.
+    pub fn name(&self) -> impl Iterator<Item = u8> + '_ {
+        let (long, short) = if self.has_lfn {
+            (self.lfn_len, 0)
+        } else {
+            (0, usize::MAX)
+        };
+        char::decode_utf16(self.lfn[..long].iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .flat_map(|c| {
+                let mut bytes = [0; 4];
+                let len = c.encode_utf8(&mut bytes).len();
+                bytes.into_iter().take(len)
+            })
+            .chain(self.short_name().take(short))
+    }
+
+    /// The 8.3 name of the last short entry, with the padding removed.
+    fn short_name(&self) -> impl Iterator<Item = u8> + '_ {
+        let trimmed = |s: &[u8]| s.iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+        let base = &self.short[..trimmed(&self.short[..8])];
+        let ext = &self.short[8..8 + trimmed(&self.short[8..])];
+        let case = self.case;
+        let lower = move |flag: u8| {
+            move |c: u8| {
+                if case & flag != 0 {
+                    c.to_ascii_lowercase()
+                } else {
+                    c
+                }
+            }
+        };
+
+        base.iter()
+            .enumerate()
+            .map(|(i, &c)| if i == 0 && c == ENTRY_E5 { 0xE5 } else { c })
+            .map(lower(CASE_LOWER_BASE))
+            .chain((!ext.is_empty()).then_some(b'.'))
+            .chain(ext.iter().copied().map(lower(CASE_LOWER_EXT)))
+    }
+
+    fn finish(&mut self, result: Result<File, ErrorCode>) -> Step {
+        self.op = Op::Idle;
+        Step::Done(result)
+    }
+
+    /// Check the first sector of the device for a boot sector or a partition
+    /// table.
+    fn first_sector_read(&mut self, sector: &[u8]) -> Step {
+        // A device without a partition table holds a single volume.
+        if let Some(volume) = Volume::parse(sector, 0) {
+            self.volume = Some(volume);
+            return self.finish(Ok(volume.root()));
+        }
+        self.table.start();
+        self.table_read(sector)
+    }
+
+    /// Look for the first FAT partition in a block of the partition table.
+    fn table_read(&mut self, sector: &[u8]) -> Step {
+        let mut start = None;
+        let next = self.table.block_read(sector, |partition| {
+            let is_fat = match partition.kind {
+                PartitionType::Mbr(kind) => MBR_FAT_TYPES.contains(&kind),
+                PartitionType::Gpt(kind) => kind == GPT_BASIC_DATA || kind == GPT_EFI_SYSTEM,
+            };
+            // A partition at sector 0 would overlap the partition table.
+            let usable = is_fat && partition.start != 0;
+            if usable {
+                start = Some(partition.start);
+            }
+            !usable
+        });
+
+        match (start, next) {
+            (Some(start), _) => {
+                self.op = Op::Boot { start };
+                Step::Read(start)
+            }
+            (None, Some(sector)) => {
+                self.op = Op::Table;
+                Step::Read(sector)
+            }
+            (None, None) => self.finish(Err(ErrorCode::NOSUPPORT)),
+        }
+    }
+
+    /// The cursor of the operation in progress.
+    fn cursor(&self) -> Option<Cursor> {
+        match self.op {
+            Op::Lookup { dir } | Op::ReadDir { dir } => Some(dir.cursor),
+            Op::Read { file, .. } => Some(file.cursor),
+            _ => None,
+        }
+    }
+
+    fn set_cursor(&mut self, cursor: Cursor) {
+        match &mut self.op {
+            Op::Lookup { dir } | Op::ReadDir { dir } => dir.cursor = cursor,
+            Op::Read { file, .. } => file.cursor = cursor,
+            _ => {}
+        }
+    }
+
+    /// Request the next sector needed by the operation in progress.
+    fn advance(&mut self) -> Step {
+        let (Some(volume), Some(mut cursor)) = (self.volume, self.cursor()) else {
+            return self.finish(Err(ErrorCode::FAIL));
+        };
+        if let Op::Read { file, remaining } = self.op {
+            if remaining == 0 || file.cursor.position >= file.size {
+                return self.finish(Ok(file));
+            }
+        }
+
+        let location = volume.locate(&mut cursor);
+        self.set_cursor(cursor);
+        match location {
+            Location::Sector(sector) => {
+                self.awaiting = Awaiting::Data;
+                Step::Read(sector)
+            }
+            Location::Fat(sector) => {
+                self.awaiting = Awaiting::Fat;
+                Step::Read(sector)
+            }
+            Location::End => match self.op {
+                // The directory ended without the entry.
+                Op::Lookup { .. } | Op::ReadDir { .. } => self.finish(Err(ErrorCode::NOSUPPORT)),
+                // The cluster chain is shorter than the file.
+                _ => self.finish(Err(ErrorCode::FAIL)),
+            },
+        }
+    }
+
+    /// Follow the FAT entry for the current cluster to the next cluster.
+    fn fat_read(&mut self, sector: &[u8], low: Option<u8>) -> Step {
+        let (Some(volume), Some(mut cursor)) = (self.volume, self.cursor()) else {
+            return self.finish(Err(ErrorCode::FAIL));
+        };
+        let offset = (volume.fat_offset(cursor.cluster) % SECTOR_SIZE as u32) as usize;
+
+        let next = match volume.fat_type {
+            FatType::Fat12 => {
+                let bytes = match low {
+                    Some(low) => [low, sector[0]],
+                    None if offset == SECTOR_SIZE - 1 => {
+                        // The entry continues in the next sector.
+                        self.awaiting = Awaiting::FatHigh(sector[offset]);
+                        return Step::Read(volume.fat_sector(cursor.cluster) + 1);
+                    }
+                    None => [sector[offset], sector[offset + 1]],
+                };
+                let entry = u16::from_le_bytes(bytes) as u32;
+                if cursor.cluster & 1 == 1 {
+                    entry >> 4
+                } else {
+                    entry & 0xFFF
+                }
+            }
+            FatType::Fat16 => u16_at(sector, offset) as u32,
+            FatType::Fat32 => u32_at(sector, offset) & 0x0FFF_FFFF,
+        };
+
+        cursor.cluster = next;
+        cursor.index += 1;
+        self.set_cursor(cursor);
+        self.advance()
+    }
+
+    /// Use a sector of data from the file or directory being read.
+    fn data_read(&mut self, sector: &[u8]) -> Step {
+        match self.op {
+            Op::Read {
+                mut file,
+                remaining,
+            } => {
+                let offset = file.cursor.position as usize % SECTOR_SIZE;
+                let len = cmp::min(remaining, file.size - file.cursor.position) as usize;
+                let len = cmp::min(SECTOR_SIZE - offset, len);
+                file.cursor.position += len as u32;
+                self.op = Op::Read {
+                    file,
+                    remaining: remaining - len as u32,
+                };
+                Step::Data(offset..offset + len)
+            }
+            Op::Lookup { dir } | Op::ReadDir { dir } => self.scan_entries(sector, dir),
+            _ => self.finish(Err(ErrorCode::FAIL)),
+        }
+    }
+
+    /// Process the directory entries in `sector` from the position of `dir`.
+    fn scan_entries(&mut self, sector: &[u8], mut dir: File) -> Step {
+        let volume = match self.volume {
+            Some(volume) => volume,
+            None => return self.finish(Err(ErrorCode::FAIL)),
+        };
+
+        let mut offset = dir.cursor.position as usize % SECTOR_SIZE;
+        while offset < SECTOR_SIZE {
+            let entry = &sector[offset..offset + DIR_ENTRY_LEN];
+            if entry[0] == ENTRY_END {
+                return self.finish(Err(ErrorCode::NOSUPPORT));
+            }
+            offset += DIR_ENTRY_LEN;
+            dir.cursor.position += DIR_ENTRY_LEN as u32;
+
+            let attributes = entry[11];
+            if entry[0] == ENTRY_DELETED {
+                self.lfn_next = None;
+                continue;
+            }
+            if attributes & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME {
+                self.long_name_entry(entry);
+                continue;
+            }
+            if attributes & ATTR_VOLUME_ID != 0 {
+                self.lfn_next = None;
+                continue;
+            }
+
+            self.short_entry(entry);
+            let mut cluster = u16_at(entry, 26) as u32;
+            if volume.fat_type == FatType::Fat32 {
+                cluster |= (u16_at(entry, 20) as u32) << 16;
+            }
+            let is_dir = attributes & ATTR_DIRECTORY != 0;
+            let found = if is_dir && cluster == 0 {
+                // ".." entries in directories in the root use cluster 0.
+                volume.root()
+            } else {
+                File::new(cluster, u32_at(entry, 28), is_dir)
+            };
+
+            match self.op {
+                Op::Lookup { .. } if self.matches_component() => return self.descend(found),
+                Op::ReadDir { .. } if !self.is_dot_entry() => {
+                    self.entry = Some(found);
+                    return self.finish(Ok(dir));
+                }
+                _ => {}
+            }
+        }
+
+        match &mut self.op {
+            Op::Lookup { dir: current } | Op::ReadDir { dir: current } => *current = dir,
+            _ => {}
+        }
+        self.advance()
+    }
+
+    /// Add a long name entry to the long name being assembled.
+    fn long_name_entry(&mut self, entry: &[u8]) {
+        let sequence = entry[0] & LFN_SEQUENCE_MASK;
+        if entry[0] & LFN_LAST != 0 {
+            // This is the first entry of a long name, which holds the end of
+            // the name.
+            self.lfn_next = Some(sequence);
+            self.lfn_checksum = entry[13];
+            self.lfn_len = sequence as usize * LFN_ENTRY_CHARS;
+        }
+
+        match self.lfn_next {
+            Some(next)
+                if next != 0
+                    && sequence == next
+                    && entry[13] == self.lfn_checksum
+                    && self.lfn_len <= MAX_LFN_LEN + LFN_ENTRY_CHARS =>
+            {
+                let base = (sequence as usize - 1) * LFN_ENTRY_CHARS;
+                for (i, &char_offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                    let c = u16_at(entry, char_offset);
+                    if c == 0 && base + i < self.lfn_len {
+                        self.lfn_len = base + i;
+                    }
+                    if let Some(slot) = self.lfn.get_mut(base + i) {
+                        *slot = c;
+                    }
+                }
+                self.lfn_next = Some(next - 1);
+            }
+            _ => self.lfn_next = None,
+        }
+    }
+
+    /// Record the name of a short entry and whether the preceding long name
+    /// belongs to it.
+    fn short_entry(&mut self, entry: &[u8]) {
+        self.short.copy_from_slice(&entry[..11]);
+        self.case = entry[12];
+
+        let checksum = self
+            .short
+            .iter()
+            .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c));
+        self.has_lfn = self.lfn_next == Some(0)
+            && self.lfn_checksum == checksum
+            && self.lfn_len > 0
+            && self.lfn_len <= MAX_LFN_LEN;
+        self.lfn_next = None;
+    }
+
+    /// Whether the last short entry is the "." or ".." entry.
+    fn is_dot_entry(&self) -> bool {
+        self.short == *b".          " || self.short == *b"..         "
+    }
+
+    /// Whether the name of the last entry matches the current path component,
+    /// ignoring ASCII case. Both the long and the short name can match.
+    fn matches_component(&self) -> bool {
+        let component = &self.path[self.component.0..self.component.1];
+        let matches = |name: &mut dyn Iterator<Item = u8>| {
+            name.map(|c| c.to_ascii_lowercase())
+                .eq(component.iter().map(|c| c.to_ascii_lowercase()))
+        };
+        (self.has_lfn && matches(&mut self.name())) || matches(&mut self.short_name())
+    }
+
+    /// Move to the next component of the path, skipping empty and "."
+    /// components. Returns `false` at the end of the path.
+    fn next_component(&mut self) -> bool {
+        let mut start = self.component.1;
+        while start < self.path_len {
+            let end = self.path[start..self.path_len]
+                .iter()
+                .position(|&c| c == b'/')
+                .map_or(self.path_len, |i| start + i);
+            self.component = (start, end);
+            if end > start && self.path[start..end] != *b"." {
+                return true;
+            }
+            start = end + 1;
+        }
+        false
+    }
+
+    /// Continue a lookup in `found`, which matched the current path component
+    /// (or is the root directory).
+    fn descend(&mut self, found: File) -> Step {
+        if !self.next_component() {
+            return self.finish(Ok(found));
+        }
+        if !found.is_dir {
+            return self.finish(Err(ErrorCode::INVAL));
+        }
+        self.lfn_next = None;
+        self.op = Op::Lookup { dir: found };
+        self.advance()
+    }
+}
+
+/// A file an application has open.
+#[derive(Clone, Copy, Debug)]
+struct OpenFile {
+    file: File,
+    /// The mount the file was opened on.
+    mount: u32,
+}
+
+/// An operation that reads from the device.
+#[derive(Clone, Copy, Debug)]
+enum Command {
+    Mount,
+    Open,
+    Read { fd: usize },
+    ReadDir { fd: usize },
+}
+
+/// State stored in the grant region on behalf of each app.
+#[derive(Default)]
+pub struct App {
+    files: [Option<OpenFile>; MAX_OPEN_FILES],
+    /// Operation that will be handled once the device is free.
+    pending: Option<Command>,
+}
+
+pub struct FatDriver<'a, B: BlockDevice<'a>> {
+    device: &'a B,
+    reader: MapCell<FatReader>,
+    /// Buffer for sectors read from the device.
+    buffer: TakeCell<'static, [u8]>,
+    /// The sector being read from the device.
+    reading: Cell<u32>,
+    /// The sector held in `buffer`, if it is valid.
+    cached: OptionalCell<u32>,
+
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+
+    /// The command in progress and the app it is for.
+    current: OptionalCell<(ProcessId, Command)>,
+    /// Changed on every mount and card removal so files opened on a previous
+    /// volume can no longer be used.
+    mount: Cell<u32>,
+    /// Number of bytes copied to the app by the read in progress.
+    copied: Cell<usize>,
+}
+
+impl<'a, B: BlockDevice<'a>> FatDriver<'a, B> {
+    pub fn new(
+        device: &'a B,
+        buffer: &'static mut [u8; SECTOR_SIZE],
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> Self {
+        Self {
+            device,
+            reader: MapCell::new(FatReader::new()),
+            buffer: TakeCell::new(buffer),
+            reading: Cell::new(0),
+            cached: OptionalCell::empty(),
+            apps: grant,
+            current: OptionalCell::empty(),
+            mount: Cell::new(0),
+            copied: Cell::new(0),
+        }
+    }
+
+    /// Forget the mounted volume and invalidate all open files.
+    fn unmount(&self) {
+        self.reader.map(|reader| reader.unmount());
+        self.cached.clear();
+        self.mount.set(self.mount.get().wrapping_add(1));
+    }
+
+    /// Get the file open as `fd` on the current volume.
+    fn open_file(&self, app: &App, fd: usize) -> Result<File, ErrorCode> {
+        app.files
+            .get(fd)
+            .copied()
+            .flatten()
+            .filter(|open| open.mount == self.mount.get())
+            .map(|open| open.file)
+            .ok_or(ErrorCode::INVAL)
+    }
+
+    /// Store the new position of the file open as `fd`, unless it was closed.
+    fn update_file(app: &mut App, fd: usize, file: File) {
+        if let Some(Some(open)) = app.files.get_mut(fd) {
+            open.file = file;
+        }
+    }
+
+    /// Start `command`, returning the first step.
+    fn start_command(
+        &self,
+        app: &App,
+        kernel_data: &GrantKernelData,
+        command: Command,
+    ) -> Result<Step, ErrorCode> {
+        let step = match command {
+            Command::Mount => {
+                self.unmount();
+                if self.device.block_size() != SECTOR_SIZE {
+                    return Err(ErrorCode::NOSUPPORT);
+                }
+                self.reader.map(|reader| reader.mount())
+            }
+            Command::Open => {
+                let mut path = [0; MAX_PATH_LEN];
+                let len = kernel_data
+                    .get_readonly_processbuffer(ro_allow::PATH)
+                    .and_then(|buffer| {
+                        buffer.enter(|app_path| {
+                            // The path ends at the first NUL, if there is one.
+                            let len = app_path
+                                .iter()
+                                .position(|c| c.get() == 0)
+                                .unwrap_or(app_path.len());
+                            if len > MAX_PATH_LEN {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            app_path[..len].copy_to_slice(&mut path[..len]);
+                            Ok(len)
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+                self.reader.map(|reader| reader.open(&path[..len]))
+            }
+            Command::Read { fd } => {
+                let file = self.open_file(app, fd)?;
+                let length = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::READ)
+                    .map_or(0, |buffer| buffer.len());
+                self.copied.set(0);
+                self.reader.map(|reader| reader.read(file, length as u32))
+            }
+            Command::ReadDir { fd } => {
+                let file = self.open_file(app, fd)?;
+                self.reader.map(|reader| reader.read_dir(file))
+            }
+        };
+        step.ok_or(ErrorCode::FAIL)
+    }
+
+    /// Read `sector` from the device.
+    fn read_sector(&self, sector: u32) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        self.cached.clear();
+        self.reading.set(sector);
+        self.device
+            .read_blocks(buffer, sector, 1)
+            .map_err(|(e, buffer)| {
+                self.buffer.replace(buffer);
+                e
+            })
+    }
+
+    /// Finish the current command after reading from the device failed. The
+    /// volume is unmounted if the card was removed, since another card may
+    /// be inserted.
+    fn read_failed(&self, error: ErrorCode) -> Step {
+        if error == ErrorCode::UNINSTALLED {
+            self.unmount();
+        }
+        Step::Done(Err(error))
+    }
+
+    /// Carry out steps of the current operation until a sector must be read
+    /// from the device or the operation finishes.
+    fn run(&self, mut step: Step) {
+        loop {
+            step = match step {
+                Step::Read(sector) if self.cached.contains(&sector) => self
+                    .buffer
+                    .map(|buffer| self.reader.map(|reader| reader.sector_read(buffer)))
+                    .flatten()
+                    .unwrap_or(Step::Done(Err(ErrorCode::FAIL))),
+                Step::Read(sector) => match self.read_sector(sector) {
+                    Ok(()) => return,
+                    Err(e) => self.read_failed(e),
+                },
+                Step::Data(range) => {
+                    self.copy_data(range);
+                    self.reader
+                        .map(|reader| reader.resume())
+                        .unwrap_or(Step::Done(Err(ErrorCode::FAIL)))
+                }
+                Step::Done(result) => {
+                    self.finish(result);
+                    return;
+                }
+            };
+        }
+    }
+
+    /// Copy file data from the buffer to the app's read buffer.
+    fn copy_data(&self, range: Range<usize>) {
+        let Some((processid, _)) = self.current.get() else {
+            return;
+        };
+        let copied = self.copied.get();
+        let len = range.len();
+        let _ = self.apps.enter(processid, |_, kernel_data| {
+            let _ = kernel_data
+                .get_readwrite_processbuffer(rw_allow::READ)
+                .and_then(|buffer| {
+                    buffer.mut_enter(|dest| {
+                        self.buffer.map(|data| {
+                            // The buffer can change while the read is in
+                            // progress.
+                            if let Some(dest) = dest.get(copied..copied + len) {
+                                dest.copy_from_slice(&data[range]);
+                            }
+                        });
+                    })
+                });
+        });
+        self.copied.set(copied + len);
+    }
+
+    /// Copy the name of the entry found by the last read directory operation
+    /// to the app's name buffer, returning the length of the name.
+    fn copy_name(&self, kernel_data: &GrantKernelData) -> Result<usize, ErrorCode> {
+        kernel_data
+            .get_readwrite_processbuffer(rw_allow::NAME)
+            .and_then(|buffer| {
+                buffer.mut_enter(|dest| {
+                    self.reader
+                        .map(|reader| {
+                            let mut len = 0;
+                            for c in reader.name() {
+                                dest.get(len).ok_or(ErrorCode::SIZE)?.set(c);
+                                len += 1;
+                            }
+                            Ok(len)
+                        })
+                        .unwrap_or(Err(ErrorCode::FAIL))
+                })
+            })
+            .unwrap_or(Err(ErrorCode::RESERVE))
+    }
+
+    /// Complete the current command and start the next one.
+    fn finish(&self, result: Result<File, ErrorCode>) {
+        let Some((processid, command)) = self.current.take() else {
+            return;
+        };
+        let mount = self.mount.get();
+
+        let _ = self.apps.enter(processid, |app, kernel_data| {
+            app.pending = None;
+            let ret = result.and_then(|file| match command {
+                Command::Mount => Ok((0, 0)),
+                Command::Open => {
+                    let fd = app
+                        .files
+                        .iter()
+                        .position(|open| open.is_none_or(|open| open.mount != mount))
+                        .ok_or(ErrorCode::NOMEM)?;
+                    app.files[fd] = Some(OpenFile { file, mount });
+                    Ok((fd, 0))
+                }
+                Command::Read { fd } => {
+                    Self::update_file(app, fd, file);
+                    Ok((self.copied.get(), 0))
+                }
+                Command::ReadDir { fd } => {
+                    let len = self.copy_name(kernel_data)?;
+                    let entry = self.reader.map(|reader| reader.entry()).flatten();
+                    // The position only moves once the name is copied, so the
+                    // entry can be read again with a larger buffer.
+                    Self::update_file(app, fd, file);
+                    Ok((len, entry.is_some_and(|entry| entry.is_dir()) as usize))
+                }
+            });
+
+            let (value, extra) = ret.unwrap_or((0, 0));
+            let _ = kernel_data.schedule_upcall(
+                upcall::DONE,
+                (into_statuscode(ret.map(|_| ())), value, extra),
+            );
+        });
+
+        self.check_queue();
+    }
+
+    /// Start the next pending command if the device is idle.
+    fn check_queue(&self) {
+        for app in self.apps.iter() {
+            if self.current.is_some() {
+                return;
+            }
+
+            let processid = app.processid();
+            let step = app.enter(|app, kernel_data| {
+                let command = app.pending?;
+                self.current.set((processid, command));
+                match self.start_command(app, kernel_data, command) {
+                    Ok(step) => Some(step),
+                    Err(e) => {
+                        self.current.clear();
+                        app.pending = None;
+                        let _ = kernel_data
+                            .schedule_upcall(upcall::DONE, (into_statuscode(Err(e)), 0, 0));
+                        None
+                    }
+                }
+            });
+
+            // Steps enter the grant, so they are run outside of it.
+            if let Some(step) = step {
+                self.run(step);
+            }
+        }
+    }
+
+    /// Queue a command that reads from the device.
+    fn enqueue_command(&self, processid: ProcessId, command: Command) -> CommandReturn {
+        let res = self
+            .apps
+            .enter(processid, |app, _| {
+                if app.pending.is_some() {
+                    return Err(ErrorCode::BUSY);
+                }
+                app.pending = Some(command);
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        match res {
+            Ok(()) => {
+                self.check_queue();
+                CommandReturn::success()
+            }
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+}
+
+impl<'a, B: BlockDevice<'a>> BlockDeviceClient for FatDriver<'a, B> {
+    fn read_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+        let step = match result {
+            Ok(()) => {
+                self.cached.set(self.reading.get());
+                self.reader
+                    .map(|reader| reader.sector_read(buffer))
+                    .unwrap_or(Step::Done(Err(ErrorCode::FAIL)))
+            }
+            Err(e) => self.read_failed(e),
+        };
+        self.buffer.replace(buffer);
+        self.run(step);
+    }
+
+    fn write_complete(&self, buffer: &'static mut [u8], _result: Result<(), ErrorCode>) {
+        // Nothing is written to the device.
+        self.buffer.replace(buffer);
+    }
+
+    fn erase_complete(&self, _result: Result<(), ErrorCode>) {}
+
+    fn flush_complete(&self, _result: Result<(), ErrorCode>) {}
+}
+
+/// Provide an interface for userland.
+impl<'a, B: BlockDevice<'a>> SyscallDriver for FatDriver<'a, B> {
+    /// Command interface.
+    ///
+    /// Commands that read from the device complete with an upcall.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return Ok(()) if this driver is included on the platform.
+    /// - `1`: Mount the volume on the device. This closes all open files.
+    /// - `2`: Open the file or directory at the path in read-only allow 0. The
+    ///   upcall provides the file descriptor.
+    /// - `3`: Close the file descriptor `data1`.
+    /// - `4`: Read from file descriptor `data1` into read-write allow 0. The
+    ///   upcall provides the number of bytes read.
+    /// - `5`: Set the position of file descriptor `data1` to `data2`.
+    /// - `6`: Get the size and type (1 for a directory) of file descriptor
+    ///   `data1`.
+    /// - `7`: Copy the name of the next entry in the directory open as file
+    ///   descriptor `data1` into read-write allow 1. The upcall provides the
+    ///   length of the name and the type of the entry.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => self.enqueue_command(processid, Command::Mount),
+            2 => self.enqueue_command(processid, Command::Open),
+            4 => self.enqueue_command(processid, Command::Read { fd: data1 }),
+            7 => self.enqueue_command(processid, Command::ReadDir { fd: data1 }),
+
+            3 => self
+                .apps
+                .enter(processid, |app, _| match app.files.get_mut(data1) {
+                    Some(file) if file.is_some() => {
+                        *file = None;
+                        CommandReturn::success()
+                    }
+                    _ => CommandReturn::failure(ErrorCode::INVAL),
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            5 => self
+                .apps
+                .enter(processid, |app, _| {
+                    let result = self.open_file(app, data1).and_then(|mut file| {
+                        file.seek(data2.try_into().map_err(|_| ErrorCode::INVAL)?)?;
+                        Self::update_file(app, data1, file);
+                        Ok(())
+                    });
+                    CommandReturn::from(result)
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            6 => self
+                .apps
+                .enter(processid, |app, _| match self.open_file(app, data1) {
+                    Ok(file) => CommandReturn::success_u32_u32(file.size(), file.is_dir() as u32),
+                    Err(e) => CommandReturn::failure(e),
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::partition_table::{
+        GPT_SIGNATURE, MBR_GPT_PROTECTIVE, MBR_PARTITIONS, MBR_SIGNATURE,
+    };
+
+    const MAX_SECTORS: usize = 24;
+
+    /// A sparse in-memory disk image. Sectors that were never written read as
+    /// zeros.
+    struct Disk {
+        sectors: [(u32, [u8; SECTOR_SIZE]); MAX_SECTORS],
+        used: usize,
+    }
+
+    impl Disk {
+        fn new() -> Self {
+            Self {
+                sectors: [(0, [0; SECTOR_SIZE]); MAX_SECTORS],
+                used: 0,
+            }
+        }
+
+        fn sector(&self, sector: u32) -> [u8; SECTOR_SIZE] {
+            self.sectors[..self.used]
+                .iter()
+                .find(|(n, _)| *n == sector)
+                .map_or([0; SECTOR_SIZE], |(_, data)| *data)
+        }
+
+        fn write(&mut self, sector: u32, offset: usize, data: &[u8]) {
+            for (i, &byte) in data.iter().enumerate() {
+                let n = sector + ((offset + i) / SECTOR_SIZE) as u32;
+                let index = match self.sectors[..self.used].iter().position(|(s, _)| *s == n) {
+                    Some(index) => index,
+                    None => {
+                        self.sectors[self.used] = (n, [0; SECTOR_SIZE]);
+                        self.used += 1;
+                        self.used - 1
+                    }
+                };
+                self.sectors[index].1[(offset + i) % SECTOR_SIZE] = byte;
+            }
+        }
+    }
+
+    struct Layout {
+        start: u32,
+        sectors_per_cluster: u8,
+        reserved: u16,
+        fat_size: u32,
+        root_entries: u16,
+        total: u32,
+    }
+
+    /// Write a boot sector and return the volume it describes.
+    fn format(disk: &mut Disk, layout: &Layout) -> Volume {
+        let mut boot = [0; SECTOR_SIZE];
+        boot[..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        boot[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        boot[13] = layout.sectors_per_cluster;
+        boot[14..16].copy_from_slice(&layout.reserved.to_le_bytes());
+        boot[16] = 2;
+        boot[17..19].copy_from_slice(&layout.root_entries.to_le_bytes());
+        boot[32..36].copy_from_slice(&layout.total.to_le_bytes());
+        if layout.root_entries == 0 {
+            boot[36..40].copy_from_slice(&layout.fat_size.to_le_bytes());
+            boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+        } else {
+            boot[22..24].copy_from_slice(&(layout.fat_size as u16).to_le_bytes());
+        }
+        boot[510..].copy_from_slice(&MBR_SIGNATURE);
+        disk.write(layout.start, 0, &boot);
+        Volume::parse(&boot, layout.start).unwrap()
+    }
+
+    fn set_fat(disk: &mut Disk, volume: &Volume, cluster: u32, value: u32) {
+        let offset = volume.fat_offset(cluster) as usize;
+        match volume.fat_type {
+            FatType::Fat12 => {
+                let sector = volume.fat_start + (offset / SECTOR_SIZE) as u32;
+                let (lo, hi) = (offset % SECTOR_SIZE, offset % SECTOR_SIZE + 1);
+                let old = u16::from_le_bytes([
+                    disk.sector(sector)[lo],
+                    disk.sector(sector + (hi / SECTOR_SIZE) as u32)[hi % SECTOR_SIZE],
+                ]);
+                let new = if cluster & 1 == 1 {
+                    (old & 0x000F) | (value as u16) << 4
+                } else {
+                    (old & 0xF000) | value as u16
+                };
+                disk.write(sector, lo, &new.to_le_bytes());
+            }
+            FatType::Fat16 => disk.write(volume.fat_start, offset, &(value as u16).to_le_bytes()),
+            FatType::Fat32 => disk.write(volume.fat_start, offset, &value.to_le_bytes()),
+        }
+    }
+
+    /// Link `clusters` into a chain and fill them with `data`.
+    fn write_chain(disk: &mut Disk, volume: &Volume, clusters: &[u32], data: &[u8]) {
+        let end = match volume.fat_type {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        };
+        for (i, &cluster) in clusters.iter().enumerate() {
+            set_fat(disk, volume, cluster, *clusters.get(i + 1).unwrap_or(&end));
+            let bytes = volume.cluster_bytes() as usize;
+            if let Some(chunk) = data.chunks(bytes).nth(i) {
+                disk.write(volume.cluster_sector(cluster), 0, chunk);
+            }
+        }
+    }
+
+    fn short_entry(name: &[u8; 11], attributes: u8, case: u8, cluster: u32, size: u32) -> [u8; 32] {
+        let mut entry = [0; 32];
+        entry[..11].copy_from_slice(name);
+        entry[11] = attributes;
+        entry[12] = case;
+        entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+        entry
+    }
+
+    /// Write the long name entries for `name`, followed by `short`, to the
+    /// directory starting at `slot`. Returns the next free slot.
+    fn write_entries(
+        disk: &mut Disk,
+        volume: &Volume,
+        dir: &[u32],
+        mut slot: usize,
+        name: Option<&str>,
+        short: [u8; 32],
+    ) -> usize {
+        let mut entries = [[0u8; 32]; 5];
+        let mut count = 0;
+        if let Some(name) = name {
+            let mut units = [0u16; 52];
+            let mut len: usize = 0;
+            for (unit, c) in units.iter_mut().zip(name.encode_utf16()) {
+                *unit = c;
+                len += 1;
+            }
+            let checksum = short[..11]
+                .iter()
+                .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c));
+            count = len.div_ceil(LFN_ENTRY_CHARS);
+            for k in 0..count {
+                let entry = &mut entries[count - 1 - k];
+                entry[0] = (k + 1) as u8 | if k + 1 == count { LFN_LAST } else { 0 };
+                entry[11] = ATTR_LONG_NAME;
+                entry[13] = checksum;
+                for (i, &offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                    let index = k * LFN_ENTRY_CHARS + i;
+                    let c = match index.cmp(&len) {
+                        cmp::Ordering::Less => units[index],
+                        cmp::Ordering::Equal => 0,
+                        cmp::Ordering::Greater => 0xFFFF,
+                    };
+                    entry[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+                }
+            }
+        }
+        entries[count] = short;
+
+        for entry in &entries[..=count] {
+            let offset = slot * DIR_ENTRY_LEN;
+            let sector = match dir {
+                [] => volume.root_dir_start,
+                clusters => {
+                    let bytes = volume.cluster_bytes() as usize;
+                    volume.cluster_sector(clusters[offset / bytes])
+                        + ((offset % bytes) / SECTOR_SIZE) as u32
+                }
+            };
+            disk.write(sector, offset % SECTOR_SIZE, entry);
+            slot += 1;
+        }
+        slot
+    }
+
+    /// Run `step` and the steps after it against `disk`, copying file data to
+    /// `out`. Returns the result and the number of bytes copied.
+    fn run(
+        reader: &mut FatReader,
+        disk: &Disk,
+        mut step: Step,
+        out: &mut [u8],
+    ) -> (Result<File, ErrorCode>, usize) {
+        let mut sector = [0; SECTOR_SIZE];
+        let mut copied = 0;
+        loop {
+            step = match step {
+                Step::Read(n) => {
+                    sector = disk.sector(n);
+                    reader.sector_read(&sector)
+                }
+                Step::Data(range) => {
+                    out[copied..copied + range.len()].copy_from_slice(&sector[range.clone()]);
+                    copied += range.len();
+                    reader.resume()
+                }
+                Step::Done(result) => return (result, copied),
+            }
+        }
+    }
+
+    fn open(reader: &mut FatReader, disk: &Disk, path: &str) -> Result<File, ErrorCode> {
+        let step = reader.open(path.as_bytes());
+        run(reader, disk, step, &mut []).0
+    }
+
+    /// Read all of `file` in chunks of `chunk` bytes.
+    fn read_all(
+        reader: &mut FatReader,
+        disk: &Disk,
+        mut file: File,
+        chunk: usize,
+        out: &mut [u8],
+    ) -> usize {
+        let mut total = 0;
+        loop {
+            let step = reader.read(file, chunk as u32);
+            let (result, copied) = run(reader, disk, step, &mut out[total..]);
+            file = result.unwrap();
+            total += copied;
+            if copied == 0 {
+                return total;
+            }
+        }
+    }
+
+    /// Read the names of the entries in `dir` into `names`.
+    fn list(reader: &mut FatReader, disk: &Disk, mut dir: File, names: &mut [[u8; 32]]) -> usize {
+        let mut count = 0;
+        loop {
+            let step = reader.read_dir(dir);
+            match run(reader, disk, step, &mut []).0 {
+                Ok(next) => dir = next,
+                Err(e) => {
+                    assert_eq!(e, ErrorCode::NOSUPPORT);
+                    return count;
+                }
+            }
+            for (slot, c) in names[count].iter_mut().zip(reader.name()) {
+                *slot = c;
+            }
+            count += 1;
+        }
+    }
+
+    fn name(bytes: &[u8; 32]) -> &[u8] {
+        &bytes[..bytes.iter().position(|&c| c == 0).unwrap_or(32)]
+    }
+
+    fn pattern(len: usize) -> impl Iterator<Item = u8> {
+        (0..len).map(|i| (i * 7 + i / 251) as u8)
+    }
+
+    #[test]
+    fn mbr_fat16_long_names_and_directories() {
+        let mut disk = Disk::new();
+        let mut mbr = [0; SECTOR_SIZE];
+        mbr[MBR_PARTITIONS + 4] = 0x06;
+        mbr[MBR_PARTITIONS + 8..MBR_PARTITIONS + 12].copy_from_slice(&64u32.to_le_bytes());
+        mbr[MBR_PARTITIONS + 12..MBR_PARTITIONS + 16].copy_from_slice(&40000u32.to_le_bytes());
+        mbr[510..].copy_from_slice(&MBR_SIGNATURE);
+        disk.write(0, 0, &mbr);
+
+        let volume = format(
+            &mut disk,
+            &Layout {
+                start: 64,
+                sectors_per_cluster: 4,
+                reserved: 4,
+                fat_size: 40,
+                root_entries: 512,
+                total: 40000,
+            },
+        );
+        assert_eq!(volume.fat_type, FatType::Fat16);
+
+        let mut data = [0; 5000];
+        data.iter_mut().zip(pattern(5000)).for_each(|(d, p)| *d = p);
+        write_chain(&mut disk, &volume, &[5, 7, 6], &data);
+        write_chain(&mut disk, &volume, &[3], b"Hello, world!\n");
+        write_chain(&mut disk, &volume, &[10], &[]);
+        write_chain(&mut disk, &volume, &[11], b"# Docs\n");
+
+        let mut slot = 0;
+        let label = short_entry(b"SDCARD     ", ATTR_VOLUME_ID, 0, 0, 0);
+        slot = write_entries(&mut disk, &volume, &[], slot, None, label);
+        let hello = short_entry(
+            b"HELLO   TXT",
+            0x20,
+            CASE_LOWER_BASE | CASE_LOWER_EXT,
+            3,
+            14,
+        );
+        slot = write_entries(&mut disk, &volume, &[], slot, None, hello);
+        let mut deleted = short_entry(b"OLD     TXT", 0x20, 0, 4, 1);
+        deleted[0] = ENTRY_DELETED;
+        slot = write_entries(&mut disk, &volume, &[], slot, None, deleted);
+        let long = short_entry(b"ALONGF~1TXT", 0x20, 0, 5, 5000);
+        slot = write_entries(
+            &mut disk,
+            &volume,
+            &[],
+            slot,
+            Some("A long file name.txt"),
+            long,
+        );
+        let docs = short_entry(b"DOCS       ", ATTR_DIRECTORY, CASE_LOWER_BASE, 10, 0);
+        write_entries(&mut disk, &volume, &[], slot, None, docs);
+
+        let mut slot = 0;
+        let dot = short_entry(b".          ", ATTR_DIRECTORY, 0, 10, 0);
+        slot = write_entries(&mut disk, &volume, &[10], slot, None, dot);
+        let dotdot = short_entry(b"..         ", ATTR_DIRECTORY, 0, 0, 0);
+        slot = write_entries(&mut disk, &volume, &[10], slot, None, dotdot);
+        let readme = short_entry(b"README  MD ", 0x20, 0, 11, 7);
+        write_entries(&mut disk, &volume, &[10], slot, Some("readme.md"), readme);
+
+        let mut reader = FatReader::new();
+        assert_eq!(
+            open(&mut reader, &disk, "hello.txt").err(),
+            Some(ErrorCode::OFF)
+        );
+        let step = reader.mount();
+        let root = run(&mut reader, &disk, step, &mut []).0.unwrap();
+        assert!(root.is_dir());
+        assert_eq!(reader.fat_type(), Some(FatType::Fat16));
+
+        let hello = open(&mut reader, &disk, "hello.txt").unwrap();
+        assert_eq!((hello.size(), hello.is_dir()), (14, false));
+        let mut out = [0; 64];
+        assert_eq!(read_all(&mut reader, &disk, hello, 64, &mut out), 14);
+        assert_eq!(&out[..14], b"Hello, world!\n");
+
+        // Long names match without regard to case, as do short names.
+        let long = open(&mut reader, &disk, "/A LONG FILE NAME.TXT").unwrap();
+        assert_eq!(long.size(), 5000);
+        let mut out = [0; 5000];
+        assert_eq!(read_all(&mut reader, &disk, long, 100, &mut out), 5000);
+        assert_eq!(out, data);
+        assert!(open(&mut reader, &disk, "alongf~1.txt").is_ok());
+
+        let readme = open(&mut reader, &disk, "docs/readme.md").unwrap();
+        let mut out = [0; 16];
+        assert_eq!(read_all(&mut reader, &disk, readme, 16, &mut out), 7);
+        assert_eq!(&out[..7], b"# Docs\n");
+        assert!(open(&mut reader, &disk, "/docs/./../hello.txt").is_ok());
+        assert!(open(&mut reader, &disk, "docs/").unwrap().is_dir());
+
+        assert_eq!(
+            open(&mut reader, &disk, "missing").err(),
+            Some(ErrorCode::NOSUPPORT)
+        );
+        assert_eq!(
+            open(&mut reader, &disk, "old.txt").err(),
+            Some(ErrorCode::NOSUPPORT)
+        );
+        assert_eq!(
+            open(&mut reader, &disk, "hello.txt/x").err(),
+            Some(ErrorCode::INVAL)
+        );
+
+        let mut names = [[0; 32]; 8];
+        assert_eq!(list(&mut reader, &disk, root, &mut names), 3);
+        assert_eq!(name(&names[0]), b"hello.txt");
+        assert_eq!(name(&names[1]), b"A long file name.txt");
+        assert_eq!(name(&names[2]), b"docs");
+
+        let docs = open(&mut reader, &disk, "docs").unwrap();
+        let mut names = [[0; 32]; 8];
+        assert_eq!(list(&mut reader, &disk, docs, &mut names), 1);
+        assert_eq!(name(&names[0]), b"readme.md");
+    }
+
+    #[test]
+    fn fat12_without_partition_table() {
+        let mut disk = Disk::new();
+        let volume = format(
+            &mut disk,
+            &Layout {
+                start: 0,
+                sectors_per_cluster: 1,
+                reserved: 1,
+                fat_size: 9,
+                root_entries: 224,
+                total: 2880,
+            },
+        );
+        assert_eq!(volume.fat_type, FatType::Fat12);
+
+        // The FAT entry for cluster 341 spans the first two sectors of the FAT.
+        let mut data = [0; 1536];
+        data.iter_mut().zip(pattern(1536)).for_each(|(d, p)| *d = p);
+        write_chain(&mut disk, &volume, &[340, 341, 342], &data);
+        let file = short_entry(b"DATA    BIN", 0x20, 0, 340, 1536);
+        let slot = write_entries(&mut disk, &volume, &[], 0, Some("Grüße.bin"), file);
+
+        // A long name with the wrong checksum is ignored.
+        let mut other = short_entry(b"README  TXT", 0x20, 0, 0, 0);
+        let slot = write_entries(&mut disk, &volume, &[], slot, Some("Not the name"), other);
+        other[0] = b'X';
+        disk.write(
+            volume.root_dir_start,
+            (slot - 1) * DIR_ENTRY_LEN,
+            &other[..1],
+        );
+
+        let mut reader = FatReader::new();
+        let step = reader.mount();
+        let root = run(&mut reader, &disk, step, &mut []).0.unwrap();
+        assert_eq!(reader.fat_type(), Some(FatType::Fat12));
+
+        let mut file = open(&mut reader, &disk, "grüße.BIN").unwrap();
+        let mut out = [0; 1536];
+        assert_eq!(read_all(&mut reader, &disk, file, 1000, &mut out), 1536);
+        assert_eq!(out, data);
+
+        // Seeking backwards follows the chain again from the start.
+        file.seek(1530).unwrap();
+        let step = reader.read(file, 100);
+        let (result, copied) = run(&mut reader, &disk, step, &mut out);
+        assert_eq!(copied, 6);
+        assert_eq!(out[..6], data[1530..]);
+        file = result.unwrap();
+        file.seek(600).unwrap();
+        let step = reader.read(file, 10);
+        assert_eq!(run(&mut reader, &disk, step, &mut out).1, 10);
+        assert_eq!(out[..10], data[600..610]);
+        assert_eq!(file.seek(1537), Err(ErrorCode::INVAL));
+
+        let mut names = [[0; 32]; 4];
+        assert_eq!(list(&mut reader, &disk, root, &mut names), 2);
+        assert_eq!(name(&names[0]), "Grüße.bin".as_bytes());
+        assert_eq!(name(&names[1]), b"XEADME.TXT");
+    }
+
+    #[test]
+    fn gpt_fat32_root_spans_clusters() {
+        let mut disk = Disk::new();
+        let mut mbr = [0; SECTOR_SIZE];
+        mbr[MBR_PARTITIONS + 4] = MBR_GPT_PROTECTIVE;
+        mbr[MBR_PARTITIONS + 8..MBR_PARTITIONS + 12].copy_from_slice(&1u32.to_le_bytes());
+        mbr[510..].copy_from_slice(&MBR_SIGNATURE);
+        disk.write(0, 0, &mbr);
+
+        let mut header = [0; SECTOR_SIZE];
+        header[..8].copy_from_slice(GPT_SIGNATURE);
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        disk.write(1, 0, &header);
+
+        // The first partition is not a FAT partition, and the FAT partition
+        // is in the second sector of entries.
+        let mut entry = [0; 128];
+        entry[..16].copy_from_slice(&[0x11; 16]);
+        entry[32..40].copy_from_slice(&100u64.to_le_bytes());
+        entry[40..48].copy_from_slice(&199u64.to_le_bytes());
+        disk.write(2, 0, &entry);
+        entry[..16].copy_from_slice(&GPT_BASIC_DATA);
+        entry[32..40].copy_from_slice(&2048u64.to_le_bytes());
+        entry[40..48].copy_from_slice(&72047u64.to_le_bytes());
+        disk.write(3, 128, &entry);
+
+        let volume = format(
+            &mut disk,
+            &Layout {
+                start: 2048,
+                sectors_per_cluster: 1,
+                reserved: 32,
+                fat_size: 548,
+                root_entries: 0,
+                total: 70000,
+            },
+        );
+        assert_eq!(volume.fat_type, FatType::Fat32);
+
+        // The root directory is two clusters long, and the file uses clusters
+        // above 65535.
+        write_chain(&mut disk, &volume, &[2, 3], &[]);
+        let mut data = [0; 700];
+        data.iter_mut().zip(pattern(700)).for_each(|(d, p)| *d = p);
+        write_chain(&mut disk, &volume, &[65600, 65601], &data);
+        let mut slot = 0;
+        for _ in 0..16 {
+            let mut deleted = short_entry(b"GONE       ", 0x20, 0, 0, 0);
+            deleted[0] = ENTRY_DELETED;
+            slot = write_entries(&mut disk, &volume, &[2, 3], slot, None, deleted);
+        }
+        let file = short_entry(b"TARGET  TXT", 0x20, 0, 65600, 700);
+        write_entries(&mut disk, &volume, &[2, 3], slot, Some("target.txt"), file);
+
+        let mut reader = FatReader::new();
+        let step = reader.mount();
+        let root = run(&mut reader, &disk, step, &mut []).0.unwrap();
+        assert_eq!(reader.fat_type(), Some(FatType::Fat32));
+
+        let file = open(&mut reader, &disk, "target.txt").unwrap();
+        let mut out = [0; 700];
+        assert_eq!(read_all(&mut reader, &disk, file, 512, &mut out), 700);
+        assert_eq!(out, data);
+
+        let mut names = [[0; 32]; 4];
+        assert_eq!(list(&mut reader, &disk, root, &mut names), 1);
+        assert_eq!(name(&names[0]), b"target.txt");
+    }
+
+    #[test]
+    fn unformatted_card() {
+        let disk = Disk::new();
+        let mut reader = FatReader::new();
+        let step = reader.mount();
+        assert_eq!(
+            run(&mut reader, &disk, step, &mut []).0.err(),
+            Some(ErrorCode::NOSUPPORT)
+        );
+        assert_eq!(reader.fat_type(), None);
+    }
+}
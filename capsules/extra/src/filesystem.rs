@@ -0,0 +1,1710 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Small wear-leveling filesystem with a userspace file API.
+//!
+//! This capsule provides named files and directories on top of a flash volume,
+//! and gives each application its own root directory. The filesystem is safe
+//! against power loss and spreads writes over the entire volume.
+//!
+//! ```text
+//! +------------------------------------------------------------------------+
+//! |                             userspace                                  |
+//! +------------------------------------------------------------------------+
+//!                             kernel::Driver
+//! +------------------------------------------------------------------------+
+//! |                   filesystem::FileSystem (this)                        |
+//! +------------------------------------------------------------------------+
+//!                             hil::flash::Flash
+//! +------------------------------------------------------------------------+
+//! |                   Physical flash or virtual flash                      |
+//! +------------------------------------------------------------------------+
+//! ```
+//!
+//! Storage Layout
+//! --------------
+//!
+//! The volume is a set of flash pages, and every page that is in use holds a
+//! single record:
+//!
+//! ```text
+//! +-------+-------+-----+------+-------+----+-------+-----+------+---------+
+//! | CRC32 | Magic | Seq | Kind | Flags | ID | Index | Len | Size | Payload |
+//! |  u32  |  u32  | u32 |  u8  |  u8   | u16|  u16  | u16 | u32  |  Len    |
+//! +-------+-------+-----+------+-------+----+-------+-----+------+---------+
+//! ```
+//!
+//! - An *inode* record describes a file or a directory. `ID` identifies the
+//!   file, `Index` is the ID of the parent directory, `Size` is the length of
+//!   the file and the payload is the name.
+//! - A *data* record holds the contents of one block of a file. `ID` is the
+//!   file, `Index` is the block number and the payload is the data.
+//!
+//! `Seq` is a sequence number that is incremented for every record written.
+//! Records are never modified in place. Instead, a new copy of the record is
+//! written to a free page and the old page becomes stale. When the volume is
+//! mounted the record with the highest sequence number wins. A record is only
+//! valid if its CRC (which covers everything after the CRC field) matches, so a
+//! write interrupted by power loss leaves the previous version of the record in
+//! place. Deleting a file writes a deleted inode (a tombstone), which is kept
+//! until no other records for the file remain.
+//!
+//! The CRC of a record is only checked when the volume is mounted and when the
+//! record is written. The header of the record in each page is then kept in
+//! RAM, so finding a record does not parse or check the pages again.
+//!
+//! Each page write is atomic. A write that spans multiple blocks updates the
+//! data blocks first and the file size last, so after a power loss the file
+//! contains the old or the new data for each block, and data past the old end
+//! of the file is only visible once the new size has been written.
+//!
+//! The volume must be memory mapped, as records are read directly from the
+//! volume.
+//!
+//! Wear Leveling
+//! -------------
+//!
+//! Pages are allocated round-robin: a new record is written to the next page
+//! after the most recently written one that is not holding a current record,
+//! erasing it first if it holds stale data. This spreads erases evenly over all
+//! pages that are not holding long-lived data.
+//!
+//! Application Roots
+//! -----------------
+//!
+//! Each application's files are stored in a directory in the root of the volume
+//! named after the application's `write_id` (from its `StoragePermissions`) as
+//! eight hexadecimal digits. Paths used by applications are relative to this
+//! directory, and applications cannot access files outside of it. Reading
+//! requires read permission for the application's own `write_id` and modifying
+//! requires modify permission. If the application's `StoragePermissions`
+//! include a quota, the total size of the application's files is limited to the
+//! quota.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! storage_volume!(FS_VOLUME, 32);
+//!
+//! let filesystem = components::filesystem::FileSystemComponent::new(
+//!     board_kernel,
+//!     capsules_extra::filesystem::DRIVER_NUM,
+//!     flash,
+//!     &FS_VOLUME,
+//! )
+//! .finalize(components::filesystem_component_static!(FlashType, 8));
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::flash::{self, Flash};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::helpers::crc32_posix;
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::FileSystem as usize;
+
+/// IDs for subscribed upcalls.
+mod upcall {
+    /// Open, write, unlink or make directory done callback.
+    pub const DONE: usize = 0;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// The path of the file or directory to operate on.
+    pub const PATH: usize = 0;
+    /// The data to write to a file.
+    pub const WRITE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// The buffer to read file data into.
+    pub const READ: usize = 0;
+    /// The buffer to store a directory entry name into.
+    pub const NAME: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Flag for the open command to create the file if it does not exist.
+pub const OPEN_CREATE: usize = 1 << 0;
+
+/// Length of the record header at the start of each page.
+pub const HEADER_LEN: usize = 24;
+/// Maximum length of a file or directory name.
+pub const NAME_LEN: usize = 32;
+/// Maximum length of a path provided by an application.
+pub const MAX_PATH_LEN: usize = 128;
+/// Number of files each application can have open at once.
+pub const MAX_OPEN_FILES: usize = 4;
+
+/// Identifies a filesystem record.
+const MAGIC: u32 = 0x5346_4c54;
+/// Maximum number of directories between a file and the root of the volume.
+const MAX_DEPTH: usize = 16;
+/// ID of the root of the volume, which is never stored.
+const ROOT_ID: u16 = 0;
+
+const KIND_INODE: u8 = 1;
+const KIND_DATA: u8 = 2;
+
+const FLAG_DIR: u8 = 1 << 0;
+const FLAG_DELETED: u8 = 1 << 7;
+
+/// What is stored in a page of the volume.
+///
+/// The header of each record is checked when the volume is mounted or the
+/// record is written, and kept here so that the CRC of a page is only computed
+/// once.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PageState {
+    /// The page is erased.
+    Free,
+    /// The page holds a current record.
+    Valid(RecordHeader),
+    /// The page holds a record that has been replaced or deleted.
+    Stale(RecordHeader),
+    /// The page holds something that is not a valid record.
+    Garbage,
+}
+
+/// The header of a record.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RecordHeader {
+    seq: u32,
+    kind: u8,
+    flags: u8,
+    id: u16,
+    index: u16,
+    len: u16,
+    size: u32,
+}
+
+impl RecordHeader {
+    /// Read the header from the start of `page`, returning `None` if the page
+    /// does not hold a valid record.
+    fn from_page(page: &[u8]) -> Option<Self> {
+        let u32_at =
+            |i: usize| u32::from_le_bytes([page[i], page[i + 1], page[i + 2], page[i + 3]]);
+        let u16_at = |i: usize| u16::from_le_bytes([page[i], page[i + 1]]);
+
+        if page.len() < HEADER_LEN || u32_at(4) != MAGIC {
+            return None;
+        }
+
+        let header = RecordHeader {
+            seq: u32_at(8),
+            kind: page[12],
+            flags: page[13],
+            id: u16_at(14),
+            index: u16_at(16),
+            len: u16_at(18),
+            size: u32_at(20),
+        };
+
+        let end = HEADER_LEN + header.len as usize;
+        if (header.kind != KIND_INODE && header.kind != KIND_DATA)
+            || end > page.len()
+            || crc32_posix(&page[4..end]) != u32_at(0)
+        {
+            return None;
+        }
+
+        Some(header)
+    }
+
+    /// Write the header to the start of `page`. The payload must already be
+    /// in place so that it is covered by the CRC.
+    fn write_to_page(&self, page: &mut [u8]) {
+        page[4..8].copy_from_slice(&MAGIC.to_le_bytes());
+        page[8..12].copy_from_slice(&self.seq.to_le_bytes());
+        page[12] = self.kind;
+        page[13] = self.flags;
+        page[14..16].copy_from_slice(&self.id.to_le_bytes());
+        page[16..18].copy_from_slice(&self.index.to_le_bytes());
+        page[18..20].copy_from_slice(&self.len.to_le_bytes());
+        page[20..24].copy_from_slice(&self.size.to_le_bytes());
+        let crc = crc32_posix(&page[4..HEADER_LEN + self.len as usize]);
+        page[0..4].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    fn is_inode(&self) -> bool {
+        self.kind == KIND_INODE
+    }
+
+    fn is_dir(&self) -> bool {
+        self.flags & FLAG_DIR != 0
+    }
+
+    fn is_deleted(&self) -> bool {
+        self.flags & FLAG_DELETED != 0
+    }
+}
+
+/// A file an application has open.
+#[derive(Clone, Copy, Debug)]
+struct OpenFile {
+    id: u16,
+    position: usize,
+}
+
+/// An operation that may need to write to flash.
+#[derive(Clone, Copy, Debug)]
+enum Command {
+    Open { flags: usize },
+    Write { fd: usize },
+    Unlink,
+    MakeDir,
+}
+
+/// State stored in the grant region on behalf of each app.
+#[derive(Default)]
+pub struct App {
+    files: [Option<OpenFile>; MAX_OPEN_FILES],
+    /// Operation that will be handled once the filesystem is free.
+    pending: Option<Command>,
+}
+
+/// The operation in progress and the record being written for it.
+#[derive(Clone, Copy, Debug)]
+enum Job {
+    /// Creating the app's root directory before running `command`.
+    CreateRoot {
+        processid: ProcessId,
+        command: Command,
+    },
+    /// Creating a file (which is opened) or a directory.
+    Create { processid: ProcessId, id: u16 },
+    /// Writing the tombstone for a file or directory.
+    Unlink {
+        processid: ProcessId,
+        id: u16,
+        old_page: usize,
+    },
+    /// Writing a block of data to a file.
+    Write {
+        processid: ProcessId,
+        fd: usize,
+        id: u16,
+        position: usize,
+        written: usize,
+        length: usize,
+        old_page: Option<usize>,
+    },
+    /// Writing the new size of the file after writing the data.
+    WriteSize {
+        processid: ProcessId,
+        fd: usize,
+        id: u16,
+        position: usize,
+        written: usize,
+        old_page: usize,
+    },
+}
+
+/// The result of looking up a path.
+struct Lookup {
+    /// The directory containing the last component of the path.
+    parent: u16,
+    name: [u8; NAME_LEN],
+    name_len: usize,
+    /// The page of the inode, if it exists.
+    page: Option<usize>,
+}
+
+/// The records stored in a flash volume and the state of each of its pages.
+struct Volume<'a> {
+    /// The memory mapped flash volume the filesystem is stored in.
+    data: &'a [u8],
+    page_size: usize,
+    /// The state of each page of the volume.
+    pages: &'a [Cell<PageState>],
+    /// Sequence number for the next record.
+    seq: Cell<u32>,
+    /// ID for the next file or directory.
+    next_id: Cell<u16>,
+    /// Where to start looking for a page for the next record.
+    cursor: Cell<usize>,
+}
+
+impl<'a> Volume<'a> {
+    fn new(data: &'a [u8], page_size: usize, pages: &'a [Cell<PageState>]) -> Self {
+        let page_count = cmp::min(pages.len(), data.len() / page_size);
+        Self {
+            data,
+            page_size,
+            pages: &pages[..page_count],
+            seq: Cell::new(0),
+            next_id: Cell::new(ROOT_ID + 1),
+            cursor: Cell::new(0),
+        }
+    }
+
+    /// The contents of page `page` of the volume.
+    fn page(&self, page: usize) -> &'a [u8] {
+        &self.data[page * self.page_size..(page + 1) * self.page_size]
+    }
+
+    /// The header of the record in page `page`, if it is valid or stale.
+    fn header(&self, page: usize) -> Option<RecordHeader> {
+        match self.pages[page].get() {
+            PageState::Valid(header) | PageState::Stale(header) => Some(header),
+            PageState::Free | PageState::Garbage => None,
+        }
+    }
+
+    /// The header of the record in page `page`, if it is current.
+    fn valid_header(&self, page: usize) -> Option<RecordHeader> {
+        match self.pages[page].get() {
+            PageState::Valid(header) => Some(header),
+            _ => None,
+        }
+    }
+
+    /// The payload of the record in page `page`.
+    fn payload(&self, page: usize, header: &RecordHeader) -> &'a [u8] {
+        &self.page(page)[HEADER_LEN..HEADER_LEN + header.len as usize]
+    }
+
+    /// The number of bytes of file data stored in each page.
+    fn block_size(&self) -> usize {
+        self.page_size - HEADER_LEN
+    }
+
+    /// Mark the record in page `page` as replaced.
+    fn set_stale(&self, page: usize) {
+        if let PageState::Valid(header) = self.pages[page].get() {
+            self.pages[page].set(PageState::Stale(header));
+        }
+    }
+
+    /// Mark the data of file `id` as replaced.
+    fn set_data_stale(&self, id: u16) {
+        for page in 0..self.pages.len() {
+            if self
+                .valid_header(page)
+                .is_some_and(|h| !h.is_inode() && h.id == id)
+            {
+                self.set_stale(page);
+            }
+        }
+    }
+
+    /// Check the record just written to page `page`, returning `false` if it
+    /// is not valid.
+    fn record_written(&self, page: usize) -> bool {
+        RecordHeader::from_page(self.page(page))
+            .map(|header| self.pages[page].set(PageState::Valid(header)))
+            .is_some()
+    }
+
+    /// Determine the state of every page from the contents of the volume.
+    fn mount(&self) {
+        let count = self.pages.len();
+
+        // Find the pages that hold valid records.
+        for page in 0..count {
+            let state = if self.page(page).iter().all(|b| *b == 0xFF) {
+                PageState::Free
+            } else {
+                RecordHeader::from_page(self.page(page))
+                    .map_or(PageState::Garbage, PageState::Valid)
+            };
+            self.pages[page].set(state);
+        }
+
+        // Only the newest copy of each record is current.
+        for page in 0..count {
+            let Some(header) = self.valid_header(page) else {
+                continue;
+            };
+            let newer_exists = (0..count).any(|other| {
+                self.header(other).is_some_and(|h| {
+                    h.kind == header.kind
+                        && h.id == header.id
+                        && (h.is_inode() || h.index == header.index)
+                        && h.seq > header.seq
+                })
+            });
+            if newer_exists {
+                self.set_stale(page);
+            }
+        }
+
+        // Data for files that have been deleted, or never had their inode
+        // written, is stale.
+        for page in 0..count {
+            if let Some(header) = self.valid_header(page) {
+                if !header.is_inode() && self.find_inode(header.id).is_none() {
+                    self.set_stale(page);
+                }
+            }
+        }
+
+        // Continue after the newest record.
+        let mut max_seq = None;
+        let mut max_id = ROOT_ID;
+        for page in 0..count {
+            if let Some(header) = self.header(page) {
+                if max_seq.is_none_or(|(seq, _)| header.seq > seq) {
+                    max_seq = Some((header.seq, page));
+                }
+                max_id = cmp::max(max_id, header.id);
+            }
+        }
+        if let Some((seq, page)) = max_seq {
+            self.seq.set(seq.wrapping_add(1));
+            self.cursor.set((page + 1) % count);
+        }
+        self.next_id.set(max_id.saturating_add(1));
+    }
+
+    /// Find the page of the current inode for `id`, if it exists and has not
+    /// been deleted.
+    fn find_inode(&self, id: u16) -> Option<usize> {
+        (0..self.pages.len()).find(|page| {
+            self.valid_header(*page)
+                .is_some_and(|h| h.is_inode() && h.id == id && !h.is_deleted())
+        })
+    }
+
+    /// Find the page holding block `block` of file `id`.
+    fn find_data(&self, id: u16, block: usize) -> Option<usize> {
+        (0..self.pages.len()).find(|page| {
+            self.valid_header(*page)
+                .is_some_and(|h| !h.is_inode() && h.id == id && h.index as usize == block)
+        })
+    }
+
+    /// Iterate over the pages of the inodes in directory `parent`.
+    fn children(&self, parent: u16) -> impl Iterator<Item = usize> + '_ {
+        (0..self.pages.len()).filter(move |page| {
+            self.valid_header(*page)
+                .is_some_and(|h| h.is_inode() && !h.is_deleted() && h.index == parent)
+        })
+    }
+
+    /// Find the page of the inode called `name` in directory `parent`.
+    fn find_child(&self, parent: u16, name: &[u8]) -> Option<usize> {
+        self.children(parent).find(|page| {
+            self.valid_header(*page)
+                .is_some_and(|h| self.payload(*page, &h) == name)
+        })
+    }
+
+    /// The name of the root directory for the app with `write_id`.
+    fn root_name(write_id: u32) -> [u8; 8] {
+        let mut name = [0; 8];
+        for (i, c) in name.iter_mut().enumerate() {
+            let nibble = (write_id >> (28 - 4 * i)) & 0xF;
+            *c = b"0123456789abcdef"[nibble as usize];
+        }
+        name
+    }
+
+    /// The ID of the root directory for the app with `write_id`.
+    fn app_root(&self, write_id: u32) -> Option<u16> {
+        self.find_child(ROOT_ID, &Self::root_name(write_id))
+            .and_then(|page| self.valid_header(page))
+            .map(|h| h.id)
+    }
+
+    /// Check if `id` is inside the directory `dir`.
+    fn is_within(&self, id: u16, dir: u16) -> bool {
+        let mut current = id;
+        for _ in 0..MAX_DEPTH {
+            if current == dir {
+                return true;
+            }
+            match self
+                .find_inode(current)
+                .and_then(|page| self.valid_header(page))
+            {
+                Some(header) if current != ROOT_ID => current = header.index,
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// The total size of the files in the directory `root`.
+    fn usage(&self, root: u16) -> usize {
+        (0..self.pages.len())
+            .filter_map(|page| self.valid_header(page))
+            .filter(|h| h.is_inode() && !h.is_deleted() && !h.is_dir())
+            .filter(|h| self.is_within(h.index, root))
+            .map(|h| h.size as usize)
+            .sum()
+    }
+
+    /// Look up `path` relative to the directory `root`.
+    fn resolve(&self, root: u16, path: &[u8]) -> Result<Lookup, ErrorCode> {
+        let mut components = path.split(|c| *c == b'/').filter(|c| !c.is_empty());
+        let mut dir = root;
+        let mut current = components.next();
+
+        let Some(mut name) = current else {
+            // The path refers to the root directory itself.
+            return Ok(Lookup {
+                parent: ROOT_ID,
+                name: [0; NAME_LEN],
+                name_len: 0,
+                page: self.find_inode(root),
+            });
+        };
+
+        loop {
+            if name.len() > NAME_LEN {
+                return Err(ErrorCode::SIZE);
+            }
+            current = components.next();
+            match current {
+                Some(next) => {
+                    // All but the last component must be directories.
+                    let page = self.find_child(dir, name).ok_or(ErrorCode::NOSUPPORT)?;
+                    let header = self.valid_header(page).ok_or(ErrorCode::FAIL)?;
+                    if !header.is_dir() {
+                        return Err(ErrorCode::INVAL);
+                    }
+                    dir = header.id;
+                    name = next;
+                }
+                None => {
+                    let mut lookup = Lookup {
+                        parent: dir,
+                        name: [0; NAME_LEN],
+                        name_len: name.len(),
+                        page: self.find_child(dir, name),
+                    };
+                    lookup.name[..name.len()].copy_from_slice(name);
+                    return Ok(lookup);
+                }
+            }
+        }
+    }
+
+    /// Find a page for a new record, returning the page and if it must be
+    /// erased first.
+    fn allocate_page(&self) -> Option<(usize, bool)> {
+        let count = self.pages.len();
+        (0..count)
+            .map(|i| (self.cursor.get() + i) % count)
+            .find_map(|page| match self.pages[page].get() {
+                PageState::Free => Some((page, false)),
+                PageState::Stale(_) | PageState::Garbage => Some((page, true)),
+                PageState::Valid(header) => {
+                    // A tombstone can be removed once there is nothing left
+                    // that it needs to hide.
+                    let alone = header.is_deleted()
+                        && !(0..count).any(|other| {
+                            other != page && self.header(other).is_some_and(|h| h.id == header.id)
+                        });
+                    alone.then_some((page, true))
+                }
+            })
+            .inspect(|(page, _)| self.cursor.set((page + 1) % count))
+    }
+}
+
+pub struct FileSystem<'a, F: Flash + 'static> {
+    /// The underlying flash.
+    driver: &'a F,
+    /// The records in the flash volume.
+    volume: Volume<'a>,
+    /// Buffer for records being written.
+    buffer: TakeCell<'static, F::Page>,
+
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+
+    /// The operation in progress.
+    job: OptionalCell<Job>,
+    /// The page being erased or written.
+    target: OptionalCell<usize>,
+}
+
+impl<'a, F: Flash + 'static> FileSystem<'a, F> {
+    pub fn new(
+        driver: &'a F,
+        volume: &'static [u8],
+        pages: &'a [Cell<PageState>],
+        buffer: &'static mut F::Page,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> Self {
+        let page_size = buffer.as_mut().len();
+
+        let fs = Self {
+            driver,
+            volume: Volume::new(volume, page_size, pages),
+            buffer: TakeCell::new(buffer),
+            apps: grant,
+            job: OptionalCell::empty(),
+            target: OptionalCell::empty(),
+        };
+
+        fs.volume.mount();
+        fs
+    }
+
+    /// Copy the path the app allowed into `path`, returning its length.
+    fn copy_path(
+        kernel_data: &GrantKernelData,
+        path: &mut [u8; MAX_PATH_LEN],
+    ) -> Result<usize, ErrorCode> {
+        kernel_data
+            .get_readonly_processbuffer(ro_allow::PATH)
+            .and_then(|buffer| {
+                buffer.enter(|app_path| {
+                    // The path ends at the first NUL, if there is one.
+                    let len = app_path
+                        .iter()
+                        .position(|c| c.get() == 0)
+                        .unwrap_or(app_path.len());
+                    if len > MAX_PATH_LEN {
+                        return Err(ErrorCode::SIZE);
+                    }
+                    app_path[..len].copy_to_slice(&mut path[..len]);
+                    Ok(len)
+                })
+            })
+            .unwrap_or(Err(ErrorCode::RESERVE))
+    }
+
+    /// Check the app's storage permissions, returning its `write_id`.
+    fn check_permissions(processid: ProcessId, modify: bool) -> Result<u32, ErrorCode> {
+        let perms = processid
+            .get_storage_permissions()
+            .ok_or(ErrorCode::NOSUPPORT)?;
+        let write_id = perms.get_write_id().ok_or(ErrorCode::NOSUPPORT)?;
+        let allowed = if modify {
+            perms.check_modify_permission(write_id)
+        } else {
+            perms.check_read_permission(write_id)
+        };
+        allowed.then_some(write_id).ok_or(ErrorCode::NOSUPPORT)
+    }
+
+    /// Look up the path the app allowed in its root directory.
+    fn lookup_app_path(
+        &self,
+        processid: ProcessId,
+        kernel_data: &GrantKernelData,
+    ) -> Result<Lookup, ErrorCode> {
+        let write_id = Self::check_permissions(processid, false)?;
+        let root = self.volume.app_root(write_id).ok_or(ErrorCode::NOSUPPORT)?;
+        let mut path = [0; MAX_PATH_LEN];
+        let len = Self::copy_path(kernel_data, &mut path)?;
+        self.volume.resolve(root, &path[..len])
+    }
+
+    /// Get the ID and header of the live file open as `fd`.
+    fn open_file(&self, app: &App, fd: usize) -> Result<(OpenFile, RecordHeader), ErrorCode> {
+        let file = app
+            .files
+            .get(fd)
+            .copied()
+            .flatten()
+            .ok_or(ErrorCode::INVAL)?;
+        let header = self
+            .volume
+            .find_inode(file.id)
+            .and_then(|page| self.volume.valid_header(page))
+            .ok_or(ErrorCode::INVAL)?;
+        Ok((file, header))
+    }
+
+    /// Write the record in the buffer to a new page.
+    fn program(&self) -> Result<(), ErrorCode> {
+        let (page, erase) = self.volume.allocate_page().ok_or(ErrorCode::NOMEM)?;
+        self.target.set(page);
+        if erase {
+            self.driver
+                .erase_page(self.page_number(page))
+                .inspect_err(|_| self.target.clear())
+        } else {
+            self.write_target()
+        }
+    }
+
+    fn write_target(&self) -> Result<(), ErrorCode> {
+        let page = self.target.get().ok_or(ErrorCode::FAIL)?;
+        let buffer = self.buffer.take().ok_or(ErrorCode::NOMEM)?;
+        self.driver
+            .write_page(self.page_number(page), buffer)
+            .map_err(|(e, buffer)| {
+                self.buffer.replace(buffer);
+                self.target.clear();
+                e
+            })
+    }
+
+    /// The flash page number of page `page` of the volume.
+    fn page_number(&self, page: usize) -> usize {
+        let page_size = self.volume.page_size;
+        (self.volume.data.as_ptr() as usize + page * page_size) / page_size
+    }
+
+    /// Fill the buffer with a record. `fill` copies in the payload and returns
+    /// its length.
+    fn prepare_record(
+        &self,
+        kind: u8,
+        flags: u8,
+        id: u16,
+        index: u16,
+        size: u32,
+        fill: impl FnOnce(&mut [u8]) -> Result<usize, ErrorCode>,
+    ) -> Result<(), ErrorCode> {
+        self.buffer.map_or(Err(ErrorCode::NOMEM), |buffer| {
+            let page = buffer.as_mut();
+            page.iter_mut().for_each(|b| *b = 0xFF);
+            let len = fill(&mut page[HEADER_LEN..])?;
+            let header = RecordHeader {
+                seq: self.volume.seq.get(),
+                kind,
+                flags,
+                id,
+                index,
+                len: len as u16,
+                size,
+            };
+            header.write_to_page(page);
+            self.volume.seq.set(self.volume.seq.get().wrapping_add(1));
+            Ok(())
+        })
+    }
+
+    /// Write an inode for a new file or directory called `name` in `parent`.
+    fn write_new_inode(&self, parent: u16, name: &[u8], dir: bool) -> Result<u16, ErrorCode> {
+        let id = self.volume.next_id.get();
+        if id == u16::MAX {
+            return Err(ErrorCode::NOMEM);
+        }
+        let flags = if dir { FLAG_DIR } else { 0 };
+        self.prepare_record(KIND_INODE, flags, id, parent, 0, |payload| {
+            payload[..name.len()].copy_from_slice(name);
+            Ok(name.len())
+        })?;
+        self.program()?;
+        self.volume.next_id.set(id + 1);
+        Ok(id)
+    }
+
+    /// Write a copy of the inode in `page` with new flags and size.
+    fn rewrite_inode(&self, page: usize, flags: u8, size: u32) -> Result<(), ErrorCode> {
+        let header = self.volume.valid_header(page).ok_or(ErrorCode::FAIL)?;
+        let name = self.volume.payload(page, &header);
+        self.prepare_record(
+            KIND_INODE,
+            flags,
+            header.id,
+            header.index,
+            size,
+            |payload| {
+                payload[..name.len()].copy_from_slice(name);
+                Ok(name.len())
+            },
+        )?;
+        self.program()
+    }
+
+    /// Start running `command` for the app. Returns `Ok(Some(value))` if the
+    /// command completed without needing to write to flash.
+    fn start_command(
+        &self,
+        processid: ProcessId,
+        app: &mut App,
+        kernel_data: &GrantKernelData,
+        command: Command,
+    ) -> Result<Option<usize>, ErrorCode> {
+        let modify = match command {
+            Command::Open { flags } => flags & OPEN_CREATE != 0,
+            Command::Write { .. } | Command::Unlink | Command::MakeDir => true,
+        };
+        let write_id = Self::check_permissions(processid, modify)?;
+
+        // Files can only be created once the app has a root directory.
+        let Some(root) = self.volume.app_root(write_id) else {
+            if !modify {
+                return Err(ErrorCode::NOSUPPORT);
+            }
+            self.write_new_inode(ROOT_ID, &Volume::root_name(write_id), true)?;
+            self.job.set(Job::CreateRoot { processid, command });
+            return Ok(None);
+        };
+
+        match command {
+            Command::Open { flags } => {
+                let fd = app
+                    .files
+                    .iter()
+                    .position(|f| f.is_none())
+                    .ok_or(ErrorCode::NOMEM)?;
+                let mut path = [0; MAX_PATH_LEN];
+                let len = Self::copy_path(kernel_data, &mut path)?;
+                let lookup = self.volume.resolve(root, &path[..len])?;
+
+                match lookup.page.and_then(|page| self.volume.valid_header(page)) {
+                    Some(header) if header.is_dir() => Err(ErrorCode::INVAL),
+                    Some(header) => {
+                        app.files[fd] = Some(OpenFile {
+                            id: header.id,
+                            position: 0,
+                        });
+                        Ok(Some(fd))
+                    }
+                    None if flags & OPEN_CREATE != 0 => {
+                        let id = self.write_new_inode(
+                            lookup.parent,
+                            &lookup.name[..lookup.name_len],
+                            false,
+                        )?;
+                        self.job.set(Job::Create { processid, id });
+                        Ok(None)
+                    }
+                    None => Err(ErrorCode::NOSUPPORT),
+                }
+            }
+
+            Command::MakeDir => {
+                let mut path = [0; MAX_PATH_LEN];
+                let len = Self::copy_path(kernel_data, &mut path)?;
+                let lookup = self.volume.resolve(root, &path[..len])?;
+                if lookup.page.is_some() {
+                    return Err(ErrorCode::ALREADY);
+                }
+                let id =
+                    self.write_new_inode(lookup.parent, &lookup.name[..lookup.name_len], true)?;
+                self.job.set(Job::Create { processid, id });
+                Ok(None)
+            }
+
+            Command::Unlink => {
+                let mut path = [0; MAX_PATH_LEN];
+                let len = Self::copy_path(kernel_data, &mut path)?;
+                let lookup = self.volume.resolve(root, &path[..len])?;
+                let page = lookup.page.ok_or(ErrorCode::NOSUPPORT)?;
+                let header = self.volume.valid_header(page).ok_or(ErrorCode::FAIL)?;
+
+                // The app's root directory cannot be removed, and directories
+                // must be empty.
+                if header.id == root || self.volume.children(header.id).next().is_some() {
+                    return Err(ErrorCode::INVAL);
+                }
+
+                self.rewrite_inode(page, header.flags | FLAG_DELETED, 0)?;
+                self.job.set(Job::Unlink {
+                    processid,
+                    id: header.id,
+                    old_page: page,
+                });
+                Ok(None)
+            }
+
+            Command::Write { fd } => {
+                let (file, header) = self.open_file(app, fd)?;
+                let length = kernel_data
+                    .get_readonly_processbuffer(ro_allow::WRITE)
+                    .map_or(0, |buffer| buffer.len());
+                if length == 0 {
+                    return Ok(Some(0));
+                }
+
+                // Check that the app's files will not exceed its quota.
+                let end = file.position + length;
+                if end > u32::MAX as usize || end / self.volume.block_size() > u16::MAX as usize {
+                    return Err(ErrorCode::SIZE);
+                }
+                let growth = end.saturating_sub(header.size as usize);
+                if let Some(quota) = processid
+                    .get_storage_permissions()
+                    .and_then(|perms| perms.get_quota())
+                {
+                    if growth > 0 && self.volume.usage(root) + growth > quota {
+                        return Err(ErrorCode::NOMEM);
+                    }
+                }
+
+                let job = Job::Write {
+                    processid,
+                    fd,
+                    id: file.id,
+                    position: file.position,
+                    written: 0,
+                    length,
+                    old_page: None,
+                };
+                self.write_block(job, kernel_data)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Write the next block of data for a `Job::Write`.
+    fn write_block(&self, job: Job, kernel_data: &GrantKernelData) -> Result<(), ErrorCode> {
+        let Job::Write {
+            processid,
+            fd,
+            id,
+            position,
+            written,
+            length,
+            ..
+        } = job
+        else {
+            return Err(ErrorCode::FAIL);
+        };
+
+        let block = position / self.volume.block_size();
+        let offset = position % self.volume.block_size();
+        let chunk = cmp::min(self.volume.block_size() - offset, length - written);
+        let old_page = self.volume.find_data(id, block);
+
+        self.prepare_record(KIND_DATA, 0, id, block as u16, 0, |payload| {
+            // Start with the existing contents of the block.
+            let old_len = match old_page {
+                Some(page) => {
+                    let header = self.volume.valid_header(page).ok_or(ErrorCode::FAIL)?;
+                    let old = self.volume.payload(page, &header);
+                    payload[..old.len()].copy_from_slice(old);
+                    old.len()
+                }
+                None => 0,
+            };
+            if offset > old_len {
+                payload[old_len..offset].iter_mut().for_each(|b| *b = 0);
+            }
+
+            kernel_data
+                .get_readonly_processbuffer(ro_allow::WRITE)
+                .and_then(|buffer| {
+                    buffer.enter(|data| {
+                        let data = data.get(written..written + chunk).ok_or(ErrorCode::SIZE)?;
+                        data.copy_to_slice(&mut payload[offset..offset + chunk]);
+                        Ok(())
+                    })
+                })
+                .unwrap_or(Err(ErrorCode::RESERVE))?;
+
+            Ok(cmp::max(old_len, offset + chunk))
+        })?;
+        self.program()?;
+
+        self.job.set(Job::Write {
+            processid,
+            fd,
+            id,
+            position,
+            written,
+            length,
+            old_page,
+        });
+        Ok(())
+    }
+
+    /// Handle a record being written to `page` for the current job.
+    fn record_written(&self, page: usize) {
+        let Some(job) = self.job.take() else {
+            return;
+        };
+
+        match job {
+            Job::CreateRoot { processid, command } => {
+                // Now that the root exists run the original command.
+                let ret = self
+                    .apps
+                    .enter(processid, |app, kernel_data| {
+                        self.start_command(processid, app, kernel_data, command)
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                match ret {
+                    Ok(None) => {}
+                    Ok(Some(value)) => self.finish(processid, Ok(value)),
+                    Err(e) => self.finish(processid, Err(e)),
+                }
+            }
+
+            Job::Create { processid, id } => {
+                let dir = self.volume.valid_header(page).is_some_and(|h| h.is_dir());
+                let ret = if dir {
+                    Ok(0)
+                } else {
+                    // Open the file that was just created.
+                    self.apps
+                        .enter(processid, |app, _| {
+                            let fd = app
+                                .files
+                                .iter()
+                                .position(|f| f.is_none())
+                                .ok_or(ErrorCode::NOMEM)?;
+                            app.files[fd] = Some(OpenFile { id, position: 0 });
+                            Ok(fd)
+                        })
+                        .unwrap_or_else(|err| Err(err.into()))
+                };
+                self.finish(processid, ret);
+            }
+
+            Job::Unlink {
+                processid,
+                id,
+                old_page,
+            } => {
+                self.volume.set_stale(old_page);
+                // All of the data is now stale.
+                self.volume.set_data_stale(id);
+                self.finish(processid, Ok(0));
+            }
+
+            Job::Write {
+                processid,
+                fd,
+                id,
+                position,
+                written,
+                length,
+                old_page,
+            } => {
+                if let Some(old_page) = old_page {
+                    self.volume.set_stale(old_page);
+                }
+                let chunk = cmp::min(
+                    self.volume.block_size() - position % self.volume.block_size(),
+                    length - written,
+                );
+                let position = position + chunk;
+                let written = written + chunk;
+
+                let ret = if written < length {
+                    let job = Job::Write {
+                        processid,
+                        fd,
+                        id,
+                        position,
+                        written,
+                        length,
+                        old_page: None,
+                    };
+                    self.apps
+                        .enter(processid, |_, kernel_data| {
+                            self.write_block(job, kernel_data)
+                        })
+                        .unwrap_or_else(|err| Err(err.into()))
+                } else {
+                    // Update the size of the file if it grew.
+                    match self.volume.find_inode(id) {
+                        Some(inode) => match self.volume.valid_header(inode) {
+                            Some(header) if position > header.size as usize => self
+                                .rewrite_inode(inode, header.flags, position as u32)
+                                .map(|()| {
+                                    self.job.set(Job::WriteSize {
+                                        processid,
+                                        fd,
+                                        id,
+                                        position,
+                                        written,
+                                        old_page: inode,
+                                    });
+                                }),
+                            _ => {
+                                self.write_done(processid, fd, id, position, written);
+                                return;
+                            }
+                        },
+                        None => Err(ErrorCode::FAIL),
+                    }
+                };
+
+                if let Err(e) = ret {
+                    self.finish(processid, Err(e));
+                }
+            }
+
+            Job::WriteSize {
+                processid,
+                fd,
+                id,
+                position,
+                written,
+                old_page,
+            } => {
+                self.volume.set_stale(old_page);
+                self.write_done(processid, fd, id, position, written);
+            }
+        }
+    }
+
+    /// Update the position of the file after a write and notify the app.
+    fn write_done(
+        &self,
+        processid: ProcessId,
+        fd: usize,
+        id: u16,
+        position: usize,
+        written: usize,
+    ) {
+        let _ = self.apps.enter(processid, |app, _| {
+            if let Some(Some(file)) = app.files.get_mut(fd) {
+                if file.id == id {
+                    file.position = position;
+                }
+            }
+        });
+        self.finish(processid, Ok(written));
+    }
+
+    /// Complete the app's pending command and start the next one.
+    fn finish(&self, processid: ProcessId, result: Result<usize, ErrorCode>) {
+        self.job.clear();
+        let _ = self.apps.enter(processid, |app, kernel_data| {
+            app.pending = None;
+            let _ = kernel_data.schedule_upcall(
+                upcall::DONE,
+                (into_statuscode(result.map(|_| ())), result.unwrap_or(0), 0),
+            );
+        });
+        self.check_queue();
+    }
+
+    /// Start the next pending command if the filesystem is idle.
+    fn check_queue(&self) {
+        for app in self.apps.iter() {
+            if self.job.is_some() {
+                return;
+            }
+
+            let processid = app.processid();
+            app.enter(|app, kernel_data| {
+                if let Some(command) = app.pending {
+                    let ret = self.start_command(processid, app, kernel_data, command);
+                    let result = match ret {
+                        // The command is writing to flash.
+                        Ok(None) => return,
+                        Ok(Some(value)) => Ok(value),
+                        Err(e) => Err(e),
+                    };
+                    app.pending = None;
+                    let _ = kernel_data.schedule_upcall(
+                        upcall::DONE,
+                        (into_statuscode(result.map(|_| ())), result.unwrap_or(0), 0),
+                    );
+                }
+            });
+        }
+    }
+
+    /// Queue a command that may need to write to flash.
+    fn enqueue_command(&self, processid: ProcessId, command: Command) -> CommandReturn {
+        let res = self
+            .apps
+            .enter(processid, |app, _| {
+                if app.pending.is_some() {
+                    return Err(ErrorCode::BUSY);
+                }
+                app.pending = Some(command);
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        match res {
+            Ok(()) => {
+                self.check_queue();
+                CommandReturn::success()
+            }
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+
+    /// Read from the file open as `fd` into the app's read buffer.
+    fn read(
+        &self,
+        app: &mut App,
+        kernel_data: &GrantKernelData,
+        fd: usize,
+    ) -> Result<usize, ErrorCode> {
+        let (file, header) = self.open_file(app, fd)?;
+        let size = header.size as usize;
+
+        let read = kernel_data
+            .get_readwrite_processbuffer(rw_allow::READ)
+            .and_then(|buffer| {
+                buffer.mut_enter(|dest| {
+                    let length = cmp::min(dest.len(), size.saturating_sub(file.position));
+                    let mut done = 0;
+                    while done < length {
+                        let position = file.position + done;
+                        let block = position / self.volume.block_size();
+                        let offset = position % self.volume.block_size();
+                        let chunk = cmp::min(self.volume.block_size() - offset, length - done);
+
+                        // Blocks that were never written read as zeros.
+                        let data = self.volume.find_data(file.id, block).and_then(|page| {
+                            self.volume
+                                .valid_header(page)
+                                .map(|h| self.volume.payload(page, &h))
+                        });
+                        for i in 0..chunk {
+                            let byte = data.and_then(|d| d.get(offset + i)).copied().unwrap_or(0);
+                            dest[done + i].set(byte);
+                        }
+                        done += chunk;
+                    }
+                    length
+                })
+            })
+            .map_err(ErrorCode::from)?;
+
+        if let Some(Some(f)) = app.files.get_mut(fd) {
+            f.position += read;
+        }
+        Ok(read)
+    }
+
+    /// Copy the name of entry `index` in the directory at the allowed path
+    /// into the app's name buffer.
+    fn read_dir(
+        &self,
+        processid: ProcessId,
+        kernel_data: &GrantKernelData,
+        index: usize,
+    ) -> Result<(usize, bool, u32), ErrorCode> {
+        let lookup = self.lookup_app_path(processid, kernel_data)?;
+        let dir = lookup
+            .page
+            .and_then(|page| self.volume.valid_header(page))
+            .ok_or(ErrorCode::NOSUPPORT)?;
+        if !dir.is_dir() {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let page = self
+            .volume
+            .children(dir.id)
+            .nth(index)
+            .ok_or(ErrorCode::NOSUPPORT)?;
+        let header = self.volume.valid_header(page).ok_or(ErrorCode::FAIL)?;
+        let name = self.volume.payload(page, &header);
+
+        kernel_data
+            .get_readwrite_processbuffer(rw_allow::NAME)
+            .and_then(|buffer| {
+                buffer.mut_enter(|dest| {
+                    if dest.len() < name.len() {
+                        return Err(ErrorCode::SIZE);
+                    }
+                    dest[..name.len()].copy_from_slice(name);
+                    Ok(())
+                })
+            })
+            .unwrap_or(Err(ErrorCode::RESERVE))?;
+
+        Ok((name.len(), header.is_dir(), header.size))
+    }
+}
+
+impl<F: Flash + 'static> flash::Client<F> for FileSystem<'_, F> {
+    fn read_complete(&self, read_buffer: &'static mut F::Page, _result: Result<(), flash::Error>) {
+        // Records are read from the memory mapped volume.
+        self.buffer.replace(read_buffer);
+    }
+
+    fn write_complete(&self, write_buffer: &'static mut F::Page, result: Result<(), flash::Error>) {
+        self.buffer.replace(write_buffer);
+
+        let Some(page) = self.target.take() else {
+            return;
+        };
+
+        // Check the record made it to flash intact.
+        if result.is_err() || !self.volume.record_written(page) {
+            // The page may be partially written.
+            self.volume.pages[page].set(PageState::Garbage);
+            if let Some(processid) = self.job.map(|job| job.processid()) {
+                self.finish(processid, Err(ErrorCode::FAIL));
+            }
+            return;
+        }
+
+        self.record_written(page);
+    }
+
+    fn erase_complete(&self, result: Result<(), flash::Error>) {
+        let ret = match result {
+            Ok(()) => {
+                self.target
+                    .map(|page| self.volume.pages[page].set(PageState::Free));
+                self.write_target()
+            }
+            Err(_) => {
+                self.target.clear();
+                Err(ErrorCode::FAIL)
+            }
+        };
+
+        if let Err(e) = ret {
+            if let Some(processid) = self.job.map(|job| job.processid()) {
+                self.finish(processid, Err(e));
+            }
+        }
+    }
+}
+
+impl Job {
+    fn processid(&self) -> ProcessId {
+        match *self {
+            Job::CreateRoot { processid, .. }
+            | Job::Create { processid, .. }
+            | Job::Unlink { processid, .. }
+            | Job::Write { processid, .. }
+            | Job::WriteSize { processid, .. } => processid,
+        }
+    }
+}
+
+/// Provide an interface for userland.
+impl<F: Flash + 'static> SyscallDriver for FileSystem<'_, F> {
+    /// Command interface.
+    ///
+    /// Paths are provided with read-only allow 0 and are relative to the app's
+    /// root directory. Commands that may write to flash complete with an upcall.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return Ok(()) if this driver is included on the platform.
+    /// - `1`: Open the file at the path. `data1` holds flags (`OPEN_CREATE` to
+    ///   create the file if it does not exist). The upcall provides the file
+    ///   descriptor.
+    /// - `2`: Close the file descriptor `data1`.
+    /// - `3`: Read from file descriptor `data1` into read-write allow 0.
+    ///   Returns the number of bytes read.
+    /// - `4`: Write read-only allow 1 to file descriptor `data1`. The upcall
+    ///   provides the number of bytes written.
+    /// - `5`: Set the position of file descriptor `data1` to `data2`.
+    /// - `6`: Remove the file or empty directory at the path.
+    /// - `7`: Get the size and type (1 for a directory) of the path.
+    /// - `8`: Create a directory at the path.
+    /// - `9`: Copy the name of entry `data1` in the directory at the path into
+    ///   read-write allow 1. Returns the name length, type and size.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => self.enqueue_command(processid, Command::Open { flags: data1 }),
+            4 => self.enqueue_command(processid, Command::Write { fd: data1 }),
+            6 => self.enqueue_command(processid, Command::Unlink),
+            8 => self.enqueue_command(processid, Command::MakeDir),
+
+            2 => self
+                .apps
+                .enter(processid, |app, _| match app.files.get_mut(data1) {
+                    Some(file) if file.is_some() => {
+                        *file = None;
+                        CommandReturn::success()
+                    }
+                    _ => CommandReturn::failure(ErrorCode::INVAL),
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            3 => {
+                self.apps
+                    .enter(processid, |app, kernel_data| match Self::check_permissions(
+                        processid, false,
+                    )
+                    .and_then(|_| self.read(app, kernel_data, data1))
+                    {
+                        Ok(read) => CommandReturn::success_u32(read as u32),
+                        Err(e) => CommandReturn::failure(e),
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+
+            5 => self
+                .apps
+                .enter(processid, |app, _| match self.open_file(app, data1) {
+                    Ok((_, header)) if data2 <= header.size as usize => {
+                        if let Some(Some(file)) = app.files.get_mut(data1) {
+                            file.position = data2;
+                        }
+                        CommandReturn::success()
+                    }
+                    Ok(_) => CommandReturn::failure(ErrorCode::INVAL),
+                    Err(e) => CommandReturn::failure(e),
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            7 => self
+                .apps
+                .enter(processid, |_, kernel_data| {
+                    match self
+                        .lookup_app_path(processid, kernel_data)
+                        .and_then(|lookup| {
+                            lookup
+                                .page
+                                .and_then(|page| self.volume.valid_header(page))
+                                .ok_or(ErrorCode::NOSUPPORT)
+                        }) {
+                        Ok(header) => {
+                            CommandReturn::success_u32_u32(header.size, header.is_dir() as u32)
+                        }
+                        Err(e) => CommandReturn::failure(e),
+                    }
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            9 => self
+                .apps
+                .enter(processid, |_, kernel_data| {
+                    match self.read_dir(processid, kernel_data, data1) {
+                        Ok((name_len, dir, size)) => {
+                            CommandReturn::success_u32_u32_u32(name_len as u32, dir as u32, size)
+                        }
+                        Err(e) => CommandReturn::failure(e),
+                    }
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PAGE_SIZE: usize = 64;
+    const PAGES: usize = 8;
+
+    /// An in-memory flash volume, initially erased.
+    struct Image([u8; PAGE_SIZE * PAGES]);
+
+    impl Image {
+        fn new() -> Self {
+            Self([0xFF; PAGE_SIZE * PAGES])
+        }
+
+        /// Write a record holding `payload` to `page`.
+        fn write(&mut self, page: usize, header: RecordHeader, payload: &[u8]) -> &mut [u8] {
+            let data = &mut self.0[page * PAGE_SIZE..(page + 1) * PAGE_SIZE];
+            data[HEADER_LEN..HEADER_LEN + payload.len()].copy_from_slice(payload);
+            RecordHeader {
+                len: payload.len() as u16,
+                ..header
+            }
+            .write_to_page(data);
+            data
+        }
+
+        fn mount<'a>(&'a self, pages: &'a [Cell<PageState>; PAGES]) -> Volume<'a> {
+            let volume = Volume::new(&self.0, PAGE_SIZE, pages);
+            volume.mount();
+            volume
+        }
+    }
+
+    fn inode(seq: u32, id: u16, parent: u16, flags: u8, size: u32) -> RecordHeader {
+        RecordHeader {
+            seq,
+            kind: KIND_INODE,
+            flags,
+            id,
+            index: parent,
+            len: 0,
+            size,
+        }
+    }
+
+    fn data(seq: u32, id: u16, block: u16) -> RecordHeader {
+        RecordHeader {
+            seq,
+            kind: KIND_DATA,
+            flags: 0,
+            id,
+            index: block,
+            len: 0,
+            size: 0,
+        }
+    }
+
+    fn pages() -> [Cell<PageState>; PAGES] {
+        core::array::from_fn(|_| Cell::new(PageState::Free))
+    }
+
+    /// An app root directory (ID 1) with the file `file` (ID 2) holding
+    /// `hello` in pages 0 to 2.
+    fn image_with_file() -> Image {
+        let mut image = Image::new();
+        image.write(0, inode(0, 1, ROOT_ID, FLAG_DIR, 0), b"0000000a");
+        image.write(1, inode(1, 2, 1, 0, 5), b"file");
+        image.write(2, data(2, 2, 0), b"hello");
+        image
+    }
+
+    #[test]
+    fn mount_empty() {
+        let image = Image::new();
+        let pages = pages();
+        let volume = image.mount(&pages);
+
+        assert!(pages.iter().all(|page| page.get() == PageState::Free));
+        assert_eq!(volume.seq.get(), 0);
+        assert_eq!(volume.next_id.get(), ROOT_ID + 1);
+        assert_eq!(volume.app_root(0xa), None);
+        assert_eq!(volume.allocate_page(), Some((0, false)));
+    }
+
+    #[test]
+    fn mount_file() {
+        let mut image = image_with_file();
+        // Something that is not a record.
+        image.0[4 * PAGE_SIZE] = 0;
+        let pages = pages();
+        let volume = image.mount(&pages);
+
+        assert_eq!(volume.app_root(0xa), Some(1));
+        let lookup = volume.resolve(1, b"/file").unwrap();
+        assert_eq!(lookup.parent, 1);
+        assert_eq!(lookup.page, Some(1));
+        assert_eq!(volume.valid_header(1).map(|h| h.size), Some(5));
+        let page = volume.find_data(2, 0).unwrap();
+        assert_eq!(
+            volume.payload(page, &volume.valid_header(page).unwrap()),
+            b"hello"
+        );
+        assert_eq!(volume.usage(1), 5);
+        assert_eq!(pages[3].get(), PageState::Free);
+        assert_eq!(pages[4].get(), PageState::Garbage);
+
+        // New records continue after the newest one.
+        assert_eq!(volume.seq.get(), 3);
+        assert_eq!(volume.next_id.get(), 3);
+        assert_eq!(volume.allocate_page(), Some((3, false)));
+        assert_eq!(volume.allocate_page(), Some((4, true)));
+    }
+
+    #[test]
+    fn overwrite_keeps_newest() {
+        let mut image = image_with_file();
+        // A newer copy of the data and then the inode, in pages before and
+        // after the old ones.
+        image.write(5, data(3, 2, 0), b"HELLO world");
+        image.write(3, inode(4, 2, 1, 0, 11), b"file");
+        let pages = pages();
+        let volume = image.mount(&pages);
+
+        assert_eq!(volume.find_inode(2), Some(3));
+        assert_eq!(volume.valid_header(3).map(|h| h.size), Some(11));
+        assert_eq!(volume.find_data(2, 0), Some(5));
+        assert!(matches!(pages[1].get(), PageState::Stale(_)));
+        assert!(matches!(pages[2].get(), PageState::Stale(_)));
+        assert_eq!(volume.usage(1), 11);
+        assert_eq!(volume.children(1).count(), 1);
+
+        // Stale pages are reused once the free pages after the newest record
+        // have been used.
+        assert_eq!(volume.allocate_page(), Some((4, false)));
+        assert_eq!(volume.allocate_page(), Some((6, false)));
+        assert_eq!(volume.allocate_page(), Some((7, false)));
+        assert_eq!(volume.allocate_page(), Some((1, true)));
+    }
+
+    #[test]
+    fn unlink_hides_file() {
+        let mut image = image_with_file();
+        image.write(3, inode(3, 2, 1, FLAG_DELETED, 0), b"file");
+        let pages = pages();
+        let volume = image.mount(&pages);
+
+        assert_eq!(volume.find_inode(2), None);
+        assert_eq!(volume.resolve(1, b"file").unwrap().page, None);
+        assert_eq!(volume.find_data(2, 0), None);
+        assert!(matches!(pages[1].get(), PageState::Stale(_)));
+        assert!(matches!(pages[2].get(), PageState::Stale(_)));
+        assert_eq!(volume.children(1).count(), 0);
+        assert_eq!(volume.usage(1), 0);
+        // IDs are not reused while the tombstone exists.
+        assert_eq!(volume.next_id.get(), 3);
+
+        // The tombstone is kept while stale records of the file remain.
+        for page in 4..PAGES {
+            assert_eq!(volume.allocate_page(), Some((page, false)));
+        }
+        assert_eq!(volume.allocate_page(), Some((1, true)));
+        assert_eq!(volume.allocate_page(), Some((2, true)));
+        assert_eq!(volume.allocate_page(), Some((4, false)));
+
+        // Once they have been erased it can be removed.
+        pages[1].set(PageState::Free);
+        pages[2].set(PageState::Free);
+        volume.cursor.set(3);
+        assert_eq!(volume.allocate_page(), Some((3, true)));
+    }
+
+    #[test]
+    fn unlink_marks_data_stale() {
+        let image = image_with_file();
+        let pages = pages();
+        let volume = image.mount(&pages);
+
+        volume.set_stale(1);
+        volume.set_data_stale(2);
+
+        assert_eq!(volume.find_inode(2), None);
+        assert!(matches!(pages[2].get(), PageState::Stale(_)));
+        assert_eq!(volume.valid_header(0).map(|h| h.id), Some(1));
+    }
+
+    #[test]
+    fn interrupted_write() {
+        let mut image = image_with_file();
+        // The new copy of the data block lost power before the payload was
+        // fully written.
+        let page = image.write(3, data(3, 2, 0), b"HELLO");
+        page[HEADER_LEN + 4] = 0xFF;
+        // The second block of a write was written, but not the new size.
+        image.write(4, data(4, 2, 1), b"more");
+        let pages = pages();
+        let volume = image.mount(&pages);
+
+        assert_eq!(pages[3].get(), PageState::Garbage);
+        assert_eq!(volume.find_data(2, 0), Some(2));
+        assert_eq!(volume.find_data(2, 1), Some(4));
+        assert_eq!(volume.valid_header(1).map(|h| h.size), Some(5));
+        assert_eq!(volume.seq.get(), 5);
+        assert!(!volume.record_written(3));
+        assert!(volume.record_written(4));
+    }
+}
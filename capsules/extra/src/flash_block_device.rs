@@ -0,0 +1,319 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Block device interface for flash.
+//!
+//! `FlashBlockDevice` provides `hil::block_device::BlockDevice` on top of a
+//! range of pages of a `hil::flash::Flash` device. Each block is one flash
+//! page, so the block size is the page size of the flash. Blocks are read and
+//! written one page at a time through a page buffer, and writes go directly to
+//! the flash so flushing completes immediately.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let flash_block_device = components::block_device::FlashBlockDeviceComponent::new(
+//!     &base_peripherals.nvmc,
+//!     0x60000 / 4096, // First page
+//!     32,             // Number of pages
+//! )
+//! .finalize(components::flash_block_device_component_static!(
+//!     nrf52840::nvmc::Nvmc
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil;
+use kernel::hil::block_device::{BlockDevice, BlockDeviceClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Op {
+    Idle,
+    /// `done` of `count` blocks starting at `block` have been processed.
+    Read {
+        block: u32,
+        count: u32,
+        done: u32,
+    },
+    Write {
+        block: u32,
+        count: u32,
+        done: u32,
+    },
+    Erase {
+        block: u32,
+        count: u32,
+        done: u32,
+    },
+    Flush,
+}
+
+pub struct FlashBlockDevice<'a, F: hil::flash::Flash + 'static> {
+    flash: &'a F,
+    first_page: usize,
+    page_count: u32,
+    page_size: usize,
+    page: TakeCell<'static, F::Page>,
+    client: OptionalCell<&'a dyn BlockDeviceClient>,
+    buffer: TakeCell<'static, [u8]>,
+    operation: Cell<Op>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a, F: hil::flash::Flash> FlashBlockDevice<'a, F> {
+    /// Create a block device for `page_count` pages of `flash` starting at
+    /// page `first_page`.
+    pub fn new(
+        flash: &'a F,
+        first_page: usize,
+        page_count: u32,
+        page: &'static mut F::Page,
+    ) -> Self {
+        Self {
+            flash,
+            first_page,
+            page_count,
+            page_size: page.as_mut().len(),
+            page: TakeCell::new(page),
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            operation: Cell::new(Op::Idle),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    fn check(&self, block: u32, count: u32) -> Result<(), ErrorCode> {
+        if self.operation.get() != Op::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if count == 0
+            || block
+                .checked_add(count)
+                .is_none_or(|end| end > self.page_count)
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        Ok(())
+    }
+
+    /// Start processing the next block of the current operation.
+    fn next(&self) -> Result<(), ErrorCode> {
+        match self.operation.get() {
+            Op::Read { block, done, .. } => {
+                let page = self.page.take().ok_or(ErrorCode::FAIL)?;
+                self.flash
+                    .read_page(self.first_page + (block + done) as usize, page)
+                    .map_err(|(e, page)| {
+                        self.page.replace(page);
+                        e
+                    })
+            }
+            Op::Write { block, done, .. } => {
+                let page = self.page.take().ok_or(ErrorCode::FAIL)?;
+                let offset = done as usize * self.page_size;
+                self.buffer.map(|buffer| {
+                    page.as_mut()
+                        .copy_from_slice(&buffer[offset..offset + self.page_size]);
+                });
+                self.flash
+                    .write_page(self.first_page + (block + done) as usize, page)
+                    .map_err(|(e, page)| {
+                        self.page.replace(page);
+                        e
+                    })
+            }
+            Op::Erase { block, done, .. } => self
+                .flash
+                .erase_page(self.first_page + (block + done) as usize),
+            Op::Idle | Op::Flush => Err(ErrorCode::FAIL),
+        }
+    }
+
+    /// Record that a block finished, and either start the next one or call
+    /// the client.
+    fn block_done(&self, result: Result<(), hil::flash::Error>) {
+        let op = match self.operation.get() {
+            Op::Read { block, count, done } => Op::Read {
+                block,
+                count,
+                done: done + 1,
+            },
+            Op::Write { block, count, done } => Op::Write {
+                block,
+                count,
+                done: done + 1,
+            },
+            Op::Erase { block, count, done } => Op::Erase {
+                block,
+                count,
+                done: done + 1,
+            },
+            op => op,
+        };
+        self.operation.set(op);
+
+        let finished = match op {
+            Op::Read { count, done, .. }
+            | Op::Write { count, done, .. }
+            | Op::Erase { count, done, .. } => done == count,
+            _ => true,
+        };
+        let result = match result {
+            Ok(()) if finished => Ok(()),
+            Ok(()) => match self.next() {
+                Ok(()) => return,
+                Err(e) => Err(e),
+            },
+            Err(_) => Err(ErrorCode::FAIL),
+        };
+        self.complete(result);
+    }
+
+    fn complete(&self, result: Result<(), ErrorCode>) {
+        let op = self.operation.replace(Op::Idle);
+        self.client.map(|client| match op {
+            Op::Read { .. } => {
+                self.buffer
+                    .take()
+                    .map(|buffer| client.read_complete(buffer, result));
+            }
+            Op::Write { .. } => {
+                self.buffer
+                    .take()
+                    .map(|buffer| client.write_complete(buffer, result));
+            }
+            Op::Erase { .. } => client.erase_complete(result),
+            Op::Flush => client.flush_complete(result),
+            Op::Idle => {}
+        });
+    }
+
+    /// Start a read or write of `count` blocks.
+    fn start(
+        &self,
+        op: Op,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.buffer.replace(buffer);
+        self.operation.set(op);
+        self.next().map_err(|e| {
+            self.operation.set(Op::Idle);
+            (e, self.buffer.take().unwrap())
+        })
+    }
+}
+
+impl<'a, F: hil::flash::Flash> BlockDevice<'a> for FlashBlockDevice<'a, F> {
+    fn set_client(&self, client: &'a dyn BlockDeviceClient) {
+        self.client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn block_count(&self) -> u32 {
+        self.page_count
+    }
+
+    fn read_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if let Err(e) = self.check(block, count) {
+            return Err((e, buffer));
+        }
+        if buffer.len() < count as usize * self.page_size {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        self.start(
+            Op::Read {
+                block,
+                count,
+                done: 0,
+            },
+            buffer,
+        )
+    }
+
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if let Err(e) = self.check(block, count) {
+            return Err((e, buffer));
+        }
+        if buffer.len() < count as usize * self.page_size {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        self.start(
+            Op::Write {
+                block,
+                count,
+                done: 0,
+            },
+            buffer,
+        )
+    }
+
+    fn erase_blocks(&self, block: u32, count: u32) -> Result<(), ErrorCode> {
+        self.check(block, count)?;
+        self.operation.set(Op::Erase {
+            block,
+            count,
+            done: 0,
+        });
+        self.next().inspect_err(|_| self.operation.set(Op::Idle))
+    }
+
+    fn flush(&self) -> Result<(), ErrorCode> {
+        if self.operation.get() != Op::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.operation.set(Op::Flush);
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl<F: hil::flash::Flash> hil::flash::Client<F> for FlashBlockDevice<'_, F> {
+    fn read_complete(&self, page: &'static mut F::Page, result: Result<(), hil::flash::Error>) {
+        if let Op::Read { done, .. } = self.operation.get() {
+            let offset = done as usize * self.page_size;
+            self.buffer.map(|buffer| {
+                buffer[offset..offset + self.page_size].copy_from_slice(page.as_mut());
+            });
+        }
+        self.page.replace(page);
+        self.block_done(result);
+    }
+
+    fn write_complete(&self, page: &'static mut F::Page, result: Result<(), hil::flash::Error>) {
+        self.page.replace(page);
+        self.block_done(result);
+    }
+
+    fn erase_complete(&self, result: Result<(), hil::flash::Error>) {
+        self.block_done(result);
+    }
+}
+
+impl<F: hil::flash::Flash> DeferredCallClient for FlashBlockDevice<'_, F> {
+    fn handle_deferred_call(&self) {
+        self.complete(Ok(()));
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
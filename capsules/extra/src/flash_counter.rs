@@ -0,0 +1,545 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Persistent monotonic counters stored in flash.
+//!
+//! `FlashCounter` implements `hil::counter::MonotonicCounter` on a memory
+//! mapped flash volume of at least two pages. The values of all counters are
+//! kept in RAM, and stored in flash as a snapshot followed by a tally.
+//!
+//! A snapshot holds the value of every counter when it was written, with a
+//! sequence number and a CRC. The rest of the page is the tally: each update
+//! is stored by writing one more word of the tally, which was left erased when
+//! the snapshot was written. Each tally word records one increment of one
+//! counter, so the value of a counter is its value in the snapshot plus the
+//! number of tally words for it. Storing an update only clears bits of the
+//! page, so most updates do not erase it. A new snapshot is only written to
+//! the next page once the tally is full, or when a counter is added or
+//! advanced by more than fits in the tally. The pages are used in turn, and
+//! snapshots are never written over the newest one.
+//!
+//! Each update writes tally words that are still erased, and a word that was
+//! only partially written is never a valid tally word, so an interrupted
+//! update either counts or is lost, and the updates stored before it are kept.
+//! This relies on the flash driver not erasing a page when a write only
+//! changes erased words, as is the case for the nRF52 NVMC. With a driver that
+//! erases the page before every write, the counters work but the page is
+//! erased for every update, and an interrupted update can lose the updates in
+//! the tally of the newest snapshot.
+//!
+//! Updates made while the counters are being written are written next, and
+//! the client is called once there are no more updates to write.
+//!
+//! Page format (little endian):
+//!
+//! ```text
+//! +-------+----------+-------+-------+------------------------------+-----------------+
+//! | magic | sequence | count | CRC32 | count * (key u64, value u64) | tally words u32 |
+//! +-------+----------+-------+-------+------------------------------+-----------------+
+//! ```
+//!
+//! The CRC covers the header and the values. A tally word holds the index of
+//! the counter in its lower 16 bits, and the complement of the index in its
+//! upper 16 bits.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! kernel::storage_volume!(COUNTER_VOLUME, 2);
+//!
+//! let flash_counter = components::monotonic_counter::FlashCounterComponent::new(
+//!     &base_peripherals.nvmc,
+//!     &COUNTER_VOLUME,
+//! )
+//! .finalize(components::flash_counter_component_static!(
+//!     nrf52840::nvmc::Nvmc,
+//!     32
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::counter::{MonotonicCounter, MonotonicCounterClient};
+use kernel::hil::flash::{self, Flash};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+use tickv::crc32::Crc32;
+
+/// Identifies a page holding a snapshot of the counters.
+const MAGIC: u32 = 0x544e_434d;
+/// Size of the snapshot header.
+const HEADER_SIZE: usize = 16;
+/// Size of each counter in a snapshot.
+const RECORD_SIZE: usize = 16;
+/// Size of each tally word.
+const TALLY_SIZE: usize = 4;
+/// Value of an erased tally word.
+const ERASED: u32 = 0xFFFF_FFFF;
+
+/// The value of one counter.
+#[derive(Clone, Copy, Default)]
+pub struct Counter {
+    key: u64,
+    value: u64,
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buffer[offset],
+        buffer[offset + 1],
+        buffer[offset + 2],
+        buffer[offset + 3],
+    ])
+}
+
+fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    u64::from(read_u32(buffer, offset)) | (u64::from(read_u32(buffer, offset + 4)) << 32)
+}
+
+/// Computes the CRC of a snapshot, covering everything but the CRC itself and
+/// the tally.
+fn snapshot_crc(page: &[u8], count: usize) -> u32 {
+    let crc = Crc32::new();
+    crc.update(&page[..12]);
+    crc.update(&page[HEADER_SIZE..HEADER_SIZE + count * RECORD_SIZE]);
+    crc.finalise()
+}
+
+/// The tally word recording an increment of the counter at `index`.
+fn tally_word(index: usize) -> u32 {
+    let index = index as u32 & 0xFFFF;
+    index | (!index & 0xFFFF) << 16
+}
+
+/// The index of the counter recorded by a tally word. Writing a word only
+/// clears bits, and every valid word has 16 bits set, so a word that was only
+/// partially written is not valid.
+fn tally_index(word: u32) -> Option<usize> {
+    let index = word & 0xFFFF;
+    (word >> 16 == !index & 0xFFFF).then_some(index as usize)
+}
+
+/// Calls `f` with the index of the counter of each valid tally word of the
+/// snapshot of `count` counters in `page`. Returns the offset of the first
+/// tally word after the last one written.
+fn read_tally(page: &[u8], count: usize, mut f: impl FnMut(usize)) -> usize {
+    let start = HEADER_SIZE + count * RECORD_SIZE;
+    let mut end = start;
+    for (i, word) in page[start..].chunks_exact(TALLY_SIZE).enumerate() {
+        let word = read_u32(word, 0);
+        if word != ERASED {
+            end = start + (i + 1) * TALLY_SIZE;
+            if let Some(index) = tally_index(word).filter(|index| *index < count) {
+                f(index);
+            }
+        }
+    }
+    end
+}
+
+/// The number of tally words to add to the snapshot and tally of `count`
+/// counters in `page` to store `counter` at `index`, if the key matches.
+fn increments(page: &[u8], count: usize, index: usize, counter: Counter) -> Option<usize> {
+    let offset = HEADER_SIZE + index * RECORD_SIZE;
+    if read_u64(page, offset) != counter.key {
+        return None;
+    }
+    let increments = counter
+        .value
+        .checked_sub(stored_value(page, count, index))?;
+    usize::try_from(increments).ok()
+}
+
+/// The value of the counter at `index` stored in the snapshot and tally of
+/// `count` counters in `page`.
+fn stored_value(page: &[u8], count: usize, index: usize) -> u64 {
+    let mut value = read_u64(page, HEADER_SIZE + index * RECORD_SIZE + 8);
+    read_tally(page, count, |counter| {
+        if counter == index {
+            value = value.saturating_add(1);
+        }
+    });
+    value
+}
+
+pub struct FlashCounter<'a, F: Flash + 'static> {
+    driver: &'a F,
+    volume: &'static [u8],
+    page_size: usize,
+    page_count: usize,
+    /// Values of the counters, the first `count` are used.
+    counters: &'a [Cell<Counter>],
+    count: Cell<usize>,
+    buffer: TakeCell<'static, F::Page>,
+    /// Sequence number of the newest snapshot.
+    sequence: Cell<u32>,
+    /// Index of the page holding the newest snapshot.
+    current: Cell<usize>,
+    /// Whether `buffer` holds the contents of the page with the newest
+    /// snapshot, so updates can be added to its tally.
+    buffer_current: Cell<bool>,
+    /// Whether the counters are being written.
+    writing: Cell<bool>,
+    /// Whether the write in progress is a new snapshot rather than an update
+    /// of the tally of the newest one.
+    writing_snapshot: Cell<bool>,
+    /// Whether counters were updated after the snapshot being written was
+    /// created.
+    dirty: Cell<bool>,
+    /// Error from starting a write, reported from a deferred call.
+    error: Cell<Option<ErrorCode>>,
+    client: OptionalCell<&'a dyn MonotonicCounterClient>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a, F: Flash> FlashCounter<'a, F> {
+    pub fn new(
+        driver: &'a F,
+        volume: &'static [u8],
+        counters: &'a [Cell<Counter>],
+        buffer: &'static mut F::Page,
+    ) -> Self {
+        let page_size = buffer.as_mut().len();
+        let page_count = volume.len() / page_size;
+        let counter = Self {
+            driver,
+            volume,
+            page_size,
+            page_count,
+            counters,
+            count: Cell::new(0),
+            buffer: TakeCell::new(buffer),
+            sequence: Cell::new(0),
+            current: Cell::new(page_count.saturating_sub(1)),
+            buffer_current: Cell::new(false),
+            writing: Cell::new(false),
+            writing_snapshot: Cell::new(false),
+            dirty: Cell::new(false),
+            error: Cell::new(None),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        };
+        counter.load();
+        counter
+    }
+
+    /// Returns the number of counters stored in the valid snapshot in the
+    /// page, if there is one.
+    fn snapshot_count(&self, page: &[u8]) -> Option<usize> {
+        let count = read_u32(page, 8) as usize;
+        if read_u32(page, 0) != MAGIC
+            || count > (self.page_size - HEADER_SIZE) / RECORD_SIZE
+            || read_u32(page, 12) != snapshot_crc(page, count)
+        {
+            return None;
+        }
+        Some(count)
+    }
+
+    /// Loads the counters from the newest valid snapshot.
+    fn load(&self) {
+        let mut newest = None;
+        for index in 0..self.page_count {
+            let page = &self.volume[index * self.page_size..][..self.page_size];
+            if let Some(count) = self.snapshot_count(page) {
+                let sequence = read_u32(page, 4);
+                if newest.is_none_or(|(_, _, newest_sequence)| sequence > newest_sequence) {
+                    newest = Some((index, count, sequence));
+                }
+            }
+        }
+
+        if let Some((index, count, sequence)) = newest {
+            let page = &self.volume[index * self.page_size..][..self.page_size];
+            // Boards must keep space for at least as many counters as were
+            // stored, counters that do not fit are lost.
+            let loaded = count.min(self.counters.len());
+            for (i, counter) in self.counters[..loaded].iter().enumerate() {
+                let offset = HEADER_SIZE + i * RECORD_SIZE;
+                counter.set(Counter {
+                    key: read_u64(page, offset),
+                    value: read_u64(page, offset + 8),
+                });
+            }
+            read_tally(page, count, |i| {
+                if let Some(counter) = self.counters.get(i) {
+                    let mut value = counter.get();
+                    value.value = value.value.saturating_add(1);
+                    counter.set(value);
+                }
+            });
+            self.count.set(loaded);
+            self.sequence.set(sequence);
+            self.current.set(index);
+
+            // Keep a copy of the page to add updates to its tally.
+            self.buffer.map(|buffer| {
+                buffer.as_mut().copy_from_slice(page);
+                self.buffer_current.set(loaded == count);
+            });
+        }
+    }
+
+    fn find(&self, key: u64) -> Option<&Cell<Counter>> {
+        self.counters[..self.count.get()]
+            .iter()
+            .find(|counter| counter.get().key == key)
+    }
+
+    /// Returns the counter `key`, adding it if it does not exist yet.
+    fn find_or_add(&self, key: u64) -> Result<&Cell<Counter>, ErrorCode> {
+        if let Some(counter) = self.find(key) {
+            return Ok(counter);
+        }
+        let count = self.count.get();
+        let counter = self
+            .counters
+            .get(count)
+            .filter(|_| count < (self.page_size - HEADER_SIZE) / RECORD_SIZE)
+            .ok_or(ErrorCode::NOMEM)?;
+        counter.set(Counter { key, value: 0 });
+        self.count.set(count + 1);
+        Ok(counter)
+    }
+
+    /// Write the counters to flash, or mark them to be written once the
+    /// current write finishes.
+    fn store(&self) {
+        if self.writing.get() {
+            self.dirty.set(true);
+            return;
+        }
+        if let Err(e) = self.write() {
+            self.error.set(Some(e));
+            self.deferred_call.set();
+        }
+    }
+
+    /// Add the updates since the newest snapshot was written to its tally in
+    /// `page`, if they fit. Returns whether they were added.
+    fn add_tally(&self, page: &mut [u8]) -> bool {
+        let count = self.count.get();
+        if read_u32(page, 8) as usize != count {
+            // Counters were added.
+            return false;
+        }
+
+        let mut needed: usize = 0;
+        for (i, counter) in self.counters[..count].iter().enumerate() {
+            match increments(page, count, i, counter.get()).and_then(|n| needed.checked_add(n)) {
+                Some(total) => needed = total,
+                None => return false,
+            }
+        }
+        let mut offset = read_tally(page, count, |_| {});
+        if needed > (page.len() - offset) / TALLY_SIZE {
+            return false;
+        }
+
+        for (i, counter) in self.counters[..count].iter().enumerate() {
+            for _ in 0..increments(page, count, i, counter.get()).unwrap_or(0) {
+                page[offset..offset + TALLY_SIZE].copy_from_slice(&tally_word(i).to_le_bytes());
+                offset += TALLY_SIZE;
+            }
+        }
+        true
+    }
+
+    /// Fill `page` with a new snapshot of the counters and an empty tally.
+    fn fill_snapshot(&self, page: &mut [u8]) {
+        let count = self.count.get();
+        page.fill(0xFF);
+        page[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        page[4..8].copy_from_slice(&self.sequence.get().wrapping_add(1).to_le_bytes());
+        page[8..12].copy_from_slice(&(count as u32).to_le_bytes());
+        for (i, counter) in self.counters[..count].iter().enumerate() {
+            let offset = HEADER_SIZE + i * RECORD_SIZE;
+            let counter = counter.get();
+            page[offset..offset + 8].copy_from_slice(&counter.key.to_le_bytes());
+            page[offset + 8..offset + 16].copy_from_slice(&counter.value.to_le_bytes());
+        }
+        let crc = snapshot_crc(page, count);
+        page[12..16].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Write the updates to the tally of the newest snapshot, or write a new
+    /// snapshot to the page after it.
+    fn write(&self) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        let tally = self.buffer_current.get() && self.add_tally(buffer.as_mut());
+        if !tally {
+            self.fill_snapshot(buffer.as_mut());
+        }
+        // The buffer only matches flash again once the write succeeds.
+        self.buffer_current.set(false);
+
+        let index = if tally {
+            self.current.get()
+        } else {
+            (self.current.get() + 1) % self.page_count
+        };
+        let page_number = (self.volume.as_ptr() as usize + index * self.page_size) / self.page_size;
+        match self.driver.write_page(page_number, buffer) {
+            Ok(()) => {
+                self.writing.set(true);
+                self.writing_snapshot.set(!tally);
+                self.dirty.set(false);
+                Ok(())
+            }
+            Err((e, buffer)) => {
+                self.buffer.replace(buffer);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<'a, F: Flash> MonotonicCounter<'a> for FlashCounter<'a, F> {
+    fn set_client(&self, client: &'a dyn MonotonicCounterClient) {
+        self.client.set(client);
+    }
+
+    fn get(&self, key: u64) -> u64 {
+        self.find(key).map_or(0, |counter| counter.get().value)
+    }
+
+    fn increment(&self, key: u64) -> Result<u64, ErrorCode> {
+        let value = self
+            .find(key)
+            .map_or(Some(1), |counter| counter.get().value.checked_add(1))
+            .ok_or(ErrorCode::SIZE)?;
+        self.find_or_add(key)?.set(Counter { key, value });
+        self.store();
+        Ok(value)
+    }
+
+    fn advance(&self, key: u64, value: u64) -> Result<(), ErrorCode> {
+        if self.get(key) >= value {
+            return Ok(());
+        }
+        self.find_or_add(key)?.set(Counter { key, value });
+        self.store();
+        Ok(())
+    }
+}
+
+impl<F: Flash> flash::Client<F> for FlashCounter<'_, F> {
+    fn read_complete(&self, _buffer: &'static mut F::Page, _result: Result<(), flash::Error>) {}
+
+    fn write_complete(&self, buffer: &'static mut F::Page, result: Result<(), flash::Error>) {
+        self.buffer.replace(buffer);
+        self.writing.set(false);
+
+        if result.is_ok() {
+            if self.writing_snapshot.get() {
+                self.sequence.set(self.sequence.get().wrapping_add(1));
+                self.current.set((self.current.get() + 1) % self.page_count);
+            }
+            self.buffer_current.set(true);
+            if self.dirty.get() {
+                self.store();
+                return;
+            }
+        }
+        // The counters are still updated in RAM after an error, and are
+        // written in a new snapshot with the next update.
+        self.dirty.set(false);
+        self.client.map(|client| {
+            client.updates_stored(result.or(Err(ErrorCode::FAIL)));
+        });
+    }
+
+    fn erase_complete(&self, _result: Result<(), flash::Error>) {}
+}
+
+impl<F: Flash> DeferredCallClient for FlashCounter<'_, F> {
+    fn handle_deferred_call(&self) {
+        if let Some(error) = self.error.take() {
+            self.client.map(|client| client.updates_stored(Err(error)));
+        }
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A page with a snapshot of `counters` and an empty tally.
+    fn snapshot(counters: &[(u64, u64)]) -> [u8; 128] {
+        let mut page = [0xFF; 128];
+        page[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        page[4..8].copy_from_slice(&1u32.to_le_bytes());
+        page[8..12].copy_from_slice(&(counters.len() as u32).to_le_bytes());
+        for (i, (key, value)) in counters.iter().enumerate() {
+            let offset = HEADER_SIZE + i * RECORD_SIZE;
+            page[offset..offset + 8].copy_from_slice(&key.to_le_bytes());
+            page[offset + 8..offset + 16].copy_from_slice(&value.to_le_bytes());
+        }
+        let crc = snapshot_crc(&page, counters.len());
+        page[12..16].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    fn write_tally(page: &mut [u8], offset: usize, word: u32) {
+        page[offset..offset + TALLY_SIZE].copy_from_slice(&word.to_le_bytes());
+    }
+
+    #[test]
+    fn tally_words() {
+        for index in [0, 1, 0x1234, 0xFFFF] {
+            assert_eq!(tally_index(tally_word(index)), Some(index));
+        }
+        assert_eq!(tally_index(ERASED), None);
+        assert_eq!(tally_index(0), None);
+        // Clearing any bit of a tally word makes it invalid.
+        let word = tally_word(3);
+        for bit in (0..32).filter(|bit| word & 1 << bit != 0) {
+            assert_eq!(tally_index(word & !(1 << bit)), None);
+        }
+    }
+
+    #[test]
+    fn tally_values() {
+        let mut page = snapshot(&[(10, 5), (20, 7)]);
+        let start = HEADER_SIZE + 2 * RECORD_SIZE;
+        assert_eq!(read_tally(&page, 2, |_| {}), start);
+
+        write_tally(&mut page, start, tally_word(1));
+        write_tally(&mut page, start + 4, tally_word(0));
+        write_tally(&mut page, start + 8, tally_word(1));
+        // Partially written and unknown words are used but not counted.
+        write_tally(&mut page, start + 12, tally_word(0) & !(1 << 16));
+        write_tally(&mut page, start + 16, tally_word(2));
+
+        assert_eq!(read_tally(&page, 2, |_| {}), start + 20);
+        assert_eq!(stored_value(&page, 2, 0), 6);
+        assert_eq!(stored_value(&page, 2, 1), 9);
+        // The CRC does not cover the tally.
+        assert_eq!(read_u32(&page, 12), snapshot_crc(&page, 2));
+    }
+
+    #[test]
+    fn tally_increments() {
+        let mut page = snapshot(&[(10, 5)]);
+        write_tally(&mut page, HEADER_SIZE + RECORD_SIZE, tally_word(0));
+
+        assert_eq!(
+            increments(&page, 1, 0, Counter { key: 10, value: 9 }),
+            Some(3)
+        );
+        assert_eq!(
+            increments(&page, 1, 0, Counter { key: 10, value: 6 }),
+            Some(0)
+        );
+        // Counters only go up, and a different key needs a new snapshot.
+        assert_eq!(increments(&page, 1, 0, Counter { key: 10, value: 5 }), None);
+        assert_eq!(increments(&page, 1, 0, Counter { key: 11, value: 9 }), None);
+    }
+}
@@ -11,6 +11,13 @@
 //! Each app is assigned a fixed amount of nonvolatile memory. This amount is
 //! set at compile time.
 //!
+//! If the app's `StoragePermissions` include a quota the app is limited to
+//! that many bytes instead. New regions are allocated with the smaller of the
+//! quota and the compile time size, and the region length is stored in the
+//! region header so it is preserved across reboots. Writes beyond the quota
+//! fail with `NOMEM`, and the get size command reports the number of bytes the
+//! app may use.
+//!
 //! ## Storage Layout
 //!
 //! Example nonvolatile storage layout (note that `|` indicates bitwise
@@ -453,18 +460,20 @@ impl<'a, const APP_REGION_SIZE: usize> IsolatedNonvolatileStorage<'a, APP_REGION
         // Get an app's write_id (same as ShortID) for saving to region header.
         // Note that if an app doesn't have the valid permissions, it will be
         // unable to create storage regions.
-        let write_id = processid
+        let perms = processid
             .get_storage_permissions()
-            .ok_or(ErrorCode::NOSUPPORT)?
-            .get_write_id()
             .ok_or(ErrorCode::NOSUPPORT)?;
+        let write_id = perms.get_write_id().ok_or(ErrorCode::NOSUPPORT)?;
 
         let region = AppRegion {
             version: CURRENT_HEADER_VERSION,
             // Have this region start where all the existing regions end.
             // Note that the app's actual region starts after the region header.
             absolute_address: new_header_addr + REGION_HEADER_LEN,
-            length: APP_REGION_SIZE,
+            // Don't hand out more storage than the app is allowed to use.
+            length: perms
+                .get_quota()
+                .map_or(APP_REGION_SIZE, |quota| cmp::min(quota, APP_REGION_SIZE)),
         };
 
         // fail if new region is outside userspace area
@@ -676,12 +685,34 @@ impl<'a, const APP_REGION_SIZE: usize> IsolatedNonvolatileStorage<'a, APP_REGION
         }
     }
 
+    // Get the number of bytes the app may use. This is the length of its
+    // region unless the region was allocated before the app was given a
+    // smaller quota.
+    fn usable_length(&self, processid: ProcessId, region: &AppRegion) -> usize {
+        processid
+            .get_storage_permissions()
+            .and_then(|perms| perms.get_quota())
+            .map_or(region.length, |quota| cmp::min(quota, region.length))
+    }
+
     fn check_userspace_access(
         &self,
-        offset: usize,
+        command: NvmCommand,
         length: usize,
         region: &AppRegion,
+        processid: ProcessId,
     ) -> Result<(), ErrorCode> {
+        let offset = command.offset();
+
+        // Writes that would store more than the app's quota fail because the
+        // app is out of space.
+        if let NvmCommand::Write { offset: _ } = command {
+            let usable_length = self.usable_length(processid, region);
+            if usable_length < region.length && offset.saturating_add(length) > usable_length {
+                return Err(ErrorCode::NOMEM);
+            }
+        }
+
         // Check that access is within this app's isolated nonvolatile region.
         // This is to prevent an app from reading/writing to another app's
         // nonvolatile storage.
@@ -804,7 +835,11 @@ impl<'a, const APP_REGION_SIZE: usize> IsolatedNonvolatileStorage<'a, APP_REGION
                         // signal app with the result
                         let _ = kernel_data.schedule_upcall(
                             upcall::GET_SIZE_DONE,
-                            (into_statuscode(Ok(())), region.length, 0),
+                            (
+                                into_statuscode(Ok(())),
+                                self.usable_length(processid, &region),
+                                0,
+                            ),
                         );
                         Ok(false)
                     }
@@ -836,7 +871,7 @@ impl<'a, const APP_REGION_SIZE: usize> IsolatedNonvolatileStorage<'a, APP_REGION
 
                 let command_offset = command.offset();
 
-                self.check_userspace_access(command_offset, allow_buf_len, app_region)?;
+                self.check_userspace_access(command, allow_buf_len, app_region, processid)?;
 
                 // Need to copy bytes if this is a write!
                 if let NvmCommand::Write { offset: _ } = command {
@@ -1080,7 +1115,8 @@ impl<const APP_REGION_SIZE: usize> SyscallDriver
     /// ### `command_num`
     ///
     /// - `0`: Return Ok(()) if this driver is included on the platform.
-    /// - `1`: Return the number of bytes available to each app.
+    /// - `1`: Return the number of bytes available to the app. This is limited
+    ///   by the app's storage quota if it has one.
     /// - `2`: Start a read from the nonvolatile storage.
     /// - `3`: Start a write to the nonvolatile_storage.
     fn command(
@@ -44,6 +44,7 @@ use capsules_core::driver;
 /// Syscall driver number.
 pub const DRIVER_NUM: usize = driver::NUM::Kv as usize;
 
+use core::cell::Cell;
 use core::cmp;
 use kernel::errorcode;
 use kernel::grant::Grant;
@@ -67,7 +68,7 @@ mod ro_allow {
 
 /// IDs for read-write allow buffers.
 mod rw_allow {
-    /// Output value for get.
+    /// Output value for get, or the hashed key for next key.
     pub const VALUE: usize = 0;
     /// The number of RW allow buffers the kernel stores for this grant.
     pub const COUNT: u8 = 1;
@@ -89,12 +90,16 @@ enum UserSpaceOp {
     Add,
     Update,
     GarbageCollect,
+    NextKey,
+    RemainingQuota,
 }
 
 /// Contents of the grant for each app.
 #[derive(Default)]
 pub struct App {
     op: OptionalCell<UserSpaceOp>,
+    /// Enumeration position for a pending next key operation.
+    position: Cell<usize>,
 }
 
 /// Capsule that provides userspace access to a key-value store.
@@ -141,8 +146,12 @@ impl<'a, V: kv::KVPermissions<'a>> KVStoreDriver<'a, V> {
         self.processid.map_or(Err(ErrorCode::RESERVE), |processid| {
             self.apps
                 .enter(processid, |app, kernel_data| {
-                    let key_len = if app.op.is_some() {
-                        // For all operations we need to copy in the key.
+                    let key_len = if app.op.is_some()
+                        && !app.op.contains(&UserSpaceOp::NextKey)
+                        && !app.op.contains(&UserSpaceOp::RemainingQuota)
+                    {
+                        // For all operations except enumerating keys and
+                        // querying the quota we need to copy in the key.
                         kernel_data
                             .get_readonly_processbuffer(ro_allow::KEY)
                             .and_then(|buffer| {
@@ -271,6 +280,36 @@ impl<'a, V: kv::KVPermissions<'a>> KVStoreDriver<'a, V> {
                             self.kv.garbage_collect()?;
                             return Ok(());
                         }
+                        Some(UserSpaceOp::RemainingQuota) => {
+                            let perms = processid
+                                .get_storage_permissions()
+                                .ok_or(ErrorCode::INVAL)?;
+                            self.kv.remaining_quota(perms)?;
+                            return Ok(());
+                        }
+                        Some(UserSpaceOp::NextKey) => {
+                            if let Some(Some(e)) = self.key_buffer.take().map(|key_buf| {
+                                self.value_buffer.take().map(|val_buf| {
+                                    let perms = processid
+                                        .get_storage_permissions()
+                                        .ok_or(ErrorCode::INVAL)?;
+
+                                    let key = SubSliceMut::new(key_buf);
+                                    let value = SubSliceMut::new(val_buf);
+
+                                    if let Err((key_ret, val_ret, e)) =
+                                        self.kv.next_key(app.position.get(), key, value, perms)
+                                    {
+                                        self.key_buffer.replace(key_ret.take());
+                                        self.value_buffer.replace(val_ret.take());
+                                        return Err(e);
+                                    }
+                                    Ok(())
+                                })
+                            }) {
+                                return e;
+                            }
+                        }
 
                         _ => {}
                     }
@@ -472,6 +511,63 @@ impl<'a, V: kv::KVPermissions<'a>> kv::KVClient for KVStoreDriver<'a, V> {
         self.check_queue();
     }
 
+    fn next_key_complete(
+        &self,
+        result: Result<usize, ErrorCode>,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.processid.map(|id| {
+            self.apps.enter(id, |app, upcalls| {
+                if app.op.contains(&UserSpaceOp::NextKey) {
+                    app.op.clear();
+
+                    match result {
+                        Err(e) => {
+                            let _ = upcalls.schedule_upcall(
+                                upcalls::VALUE,
+                                (errorcode::into_statuscode(e.into()), 0, 0),
+                            );
+                        }
+                        Ok(value_len) => {
+                            // Only the hashed key is returned to userspace,
+                            // the value can then be read with a get.
+                            let ret = upcalls
+                                .get_readwrite_processbuffer(rw_allow::VALUE)
+                                .and_then(|buffer| {
+                                    buffer.mut_enter(|appslice| {
+                                        if appslice.len() < key.len() {
+                                            Err(ErrorCode::SIZE)
+                                        } else {
+                                            appslice[..key.len()].copy_from_slice(&key[..]);
+                                            Ok(())
+                                        }
+                                    })
+                                })
+                                .unwrap_or(Err(ErrorCode::RESERVE));
+
+                            // Signal the upcall with the length of the value
+                            // and the position to continue enumerating from.
+                            let _ = upcalls.schedule_upcall(
+                                upcalls::VALUE,
+                                (errorcode::into_statuscode(ret), value_len, position),
+                            );
+                        }
+                    }
+                }
+            })
+        });
+
+        self.key_buffer.replace(key.take());
+        self.value_buffer.replace(value.take());
+
+        // We have completed the operation so see if there is a queued operation
+        // to run next.
+        self.processid.clear();
+        self.check_queue();
+    }
+
     fn garbage_collection_complete(&self, result: Result<(), ErrorCode>) {
         self.processid.map(move |id| {
             self.apps.enter(id, move |app, upcalls| {
@@ -490,13 +586,43 @@ impl<'a, V: kv::KVPermissions<'a>> kv::KVClient for KVStoreDriver<'a, V> {
         self.processid.clear();
         self.check_queue();
     }
+
+    fn commit_transaction_complete(&self, _result: Result<(), ErrorCode>) {
+        // Transactions are not part of the `KVPermissions` interface, so
+        // none are started.
+    }
+
+    fn remaining_quota_complete(&self, result: Result<usize, ErrorCode>) {
+        self.processid.map(move |id| {
+            self.apps.enter(id, move |app, upcalls| {
+                if app.op.contains(&UserSpaceOp::RemainingQuota) {
+                    app.op.clear();
+                    // Signal the upcall with the number of bytes the app may
+                    // still store.
+                    let _ = upcalls.schedule_upcall(
+                        upcalls::VALUE,
+                        (
+                            errorcode::into_statuscode(result.map(|_| ())),
+                            result.unwrap_or(0),
+                            0,
+                        ),
+                    );
+                }
+            })
+        });
+
+        // We have completed the operation so see if there is a queued operation
+        // to run next.
+        self.processid.clear();
+        self.check_queue();
+    }
 }
 
 impl<'a, V: kv::KVPermissions<'a>> SyscallDriver for KVStoreDriver<'a, V> {
     fn command(
         &self,
         command_num: usize,
-        _data1: usize,
+        data1: usize,
         _data2: usize,
         processid: ProcessId,
     ) -> CommandReturn {
@@ -504,8 +630,9 @@ impl<'a, V: kv::KVPermissions<'a>> SyscallDriver for KVStoreDriver<'a, V> {
             // check if present
             0 => CommandReturn::success(),
 
-            // get, set, delete, add, update, garbage collect
-            1 | 2 | 3 | 4 | 5 | 6 => {
+            // get, set, delete, add, update, garbage collect, next key,
+            // remaining quota
+            1 | 2 | 3 | 4 | 5 | 6 | 7 | 8 => {
                 if self.processid.is_none() {
                     // Nothing is using the KV store, so we can handle this
                     // request.
@@ -517,6 +644,11 @@ impl<'a, V: kv::KVPermissions<'a>> SyscallDriver for KVStoreDriver<'a, V> {
                         4 => app.op.set(UserSpaceOp::Add),
                         5 => app.op.set(UserSpaceOp::Update),
                         6 => app.op.set(UserSpaceOp::GarbageCollect),
+                        7 => {
+                            app.op.set(UserSpaceOp::NextKey);
+                            app.position.set(data1);
+                        }
+                        8 => app.op.set(UserSpaceOp::RemainingQuota),
                         _ => {}
                     });
                     let ret = self.run();
@@ -547,6 +679,11 @@ impl<'a, V: kv::KVPermissions<'a>> SyscallDriver for KVStoreDriver<'a, V> {
                                     4 => app.op.set(UserSpaceOp::Add),
                                     5 => app.op.set(UserSpaceOp::Update),
                                     6 => app.op.set(UserSpaceOp::GarbageCollect),
+                                    7 => {
+                                        app.op.set(UserSpaceOp::NextKey);
+                                        app.position.set(data1);
+                                    }
+                                    8 => app.op.set(UserSpaceOp::RemainingQuota),
                                     _ => {}
                                 }
                                 CommandReturn::success()
@@ -0,0 +1,1479 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Tock Key-Value store capsule with encryption at rest.
+//!
+//! This capsule implements the KV interface on top of another KV interface,
+//! encrypting and authenticating values with AES-128-CCM before they are
+//! stored. Users of the KV interface above see the plaintext values, while the
+//! K-V store below (and the flash) only see ciphertext.
+//!
+//! ```text
+//! +-----------------------+
+//! |  Capsule using K-V    |
+//! +-----------------------+
+//!
+//!    hil::kv::KV
+//!
+//! +-----------------------+
+//! | K-V store (this file) |
+//! +-----------------------+
+//!
+//!    hil::kv::KV
+//!
+//! +-----------------------+
+//! |  K-V store            |
+//! +-----------------------+
+//! ```
+//!
+//! Each stored value has the following format:
+//!
+//! ```text
+//! +---------+----------+------------------------------------------+----------+
+//! | version | nonce    | encrypted                                | MIC      |
+//! |         |          | +------------+-----------+-------------+ |          |
+//! |         |          | | key length | key       | value       | |          |
+//! |         |          | | 1 byte     | variable  | variable    | |          |
+//! |         |          | +------------+-----------+-------------+ |          |
+//! | 1 byte  | 13 bytes |                                          | 16 bytes |
+//! +---------+----------+------------------------------------------+----------+
+//! ```
+//!
+//! A new random nonce is generated for every write, so the same value
+//! written twice is stored as different ciphertexts. The version and the
+//! nonce are authenticated along with the value. The K-V key is encrypted and
+//! authenticated with the value, and must match the key the value is read
+//! with, so that a value can not be moved to another key. Keys are stored
+//! hashed below this layer, so `next_key()` only checks that the value is
+//! authentic. Values that fail authentication, for example because the flash
+//! was modified or the value was written with a different key, are reported
+//! as `FAIL` by `get()` and skipped by `next_key()`, so that one corrupt value
+//! does not end the iteration over all keys.
+//!
+//! The encryption key is provided by the board and should be unique to each
+//! device. The AES-128-CCM implementation needs a buffer large enough to hold
+//! the longest key and value plus two AES blocks.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let kv_store_encryption = components::kv::KVStoreEncryptionComponent::new(
+//!     tickv_kv_store,
+//!     aes_mux,
+//!     rng,
+//!     device_key,
+//! )
+//! .finalize(components::kv_store_encryption_component_static!(
+//!     TicKVKVStoreType,
+//!     nrf52840::aes::AesECB<'static>,
+//!     capsules_core::rng::RngDriver<'static>,
+//! ));
+//!
+//! let kv_store_permissions = components::kv::KVStorePermissionsComponent::new(
+//!     kv_store_encryption,
+//! )
+//! .finalize(components::kv_store_permissions_component_static!(
+//!     KVStoreEncryptionType
+//! ));
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::kv;
+use kernel::hil::rng;
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM, AES128_KEY_SIZE, CCM_NONCE_LENGTH};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operation {
+    Get,
+    Set,
+    Add,
+    Update,
+    Delete,
+    NextKey,
+    CommitTransaction,
+    GarbageCollect,
+}
+
+/// Current version of the encrypted value header.
+const HEADER_VERSION: u8 = 0;
+/// Length of the header: the version followed by the nonce.
+pub const HEADER_LENGTH: usize = 1 + CCM_NONCE_LENGTH;
+/// Length of the message integrity code stored after the encrypted value.
+pub const MIC_LENGTH: usize = 16;
+/// Length of the encrypted length of the key.
+const KEY_LENGTH_LENGTH: usize = 1;
+/// Number of bytes stored in addition to the key and the value.
+pub const OVERHEAD: usize = HEADER_LENGTH + KEY_LENGTH_LENGTH + MIC_LENGTH;
+
+/// Key-Value store that encrypts values before storing them.
+///
+/// Implements `KV` on top of `KV`.
+pub struct KVStoreEncryption<'a, K: kv::KV<'a>, A: AES128CCM<'a>, R: rng::Rng<'a>> {
+    kv: &'a K,
+    aes: &'a A,
+    rng: &'a R,
+    key: [u8; AES128_KEY_SIZE],
+
+    /// Holds the encrypted value while it is stored or retrieved.
+    buffer: TakeCell<'static, [u8]>,
+
+    client: OptionalCell<&'a dyn kv::KVClient>,
+    operation: OptionalCell<Operation>,
+
+    unencrypted_key: MapCell<SubSliceMut<'static, u8>>,
+    value: MapCell<SubSliceMut<'static, u8>>,
+
+    nonce: Cell<[u8; CCM_NONCE_LENGTH]>,
+    nonce_length: Cell<usize>,
+    message_length: Cell<usize>,
+    position: Cell<usize>,
+}
+
+impl<'a, K: kv::KV<'a>, A: AES128CCM<'a>, R: rng::Rng<'a>> KVStoreEncryption<'a, K, A, R> {
+    /// Create a new encrypting KV store.
+    ///
+    /// `buffer` limits the length of the keys and values that can be stored,
+    /// which together can be at most `buffer.len() - OVERHEAD` bytes long.
+    pub fn new(
+        kv: &'a K,
+        aes: &'a A,
+        rng: &'a R,
+        key: [u8; AES128_KEY_SIZE],
+        buffer: &'static mut [u8],
+    ) -> KVStoreEncryption<'a, K, A, R> {
+        Self {
+            kv,
+            aes,
+            rng,
+            key,
+            buffer: TakeCell::new(buffer),
+            client: OptionalCell::empty(),
+            operation: OptionalCell::empty(),
+            unencrypted_key: MapCell::empty(),
+            value: MapCell::empty(),
+            nonce: Cell::new([0; CCM_NONCE_LENGTH]),
+            nonce_length: Cell::new(0),
+            message_length: Cell::new(0),
+            position: Cell::new(0),
+        }
+    }
+
+    fn insert(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+        operation: Operation,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if self.operation.is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+
+        // The encrypted key and value have to fit in our buffer.
+        if key.len() > u8::MAX as usize
+            || key.len() + value.len() + OVERHEAD > self.buffer.map_or(0, |buffer| buffer.len())
+        {
+            return Err((key, value, ErrorCode::SIZE));
+        }
+
+        self.operation.set(operation);
+
+        // Every write uses a new random nonce, so first get the randomness.
+        self.nonce_length.set(0);
+        match self.rng.get() {
+            Ok(()) => {
+                self.unencrypted_key.replace(key);
+                self.value.replace(value);
+                Ok(())
+            }
+            Err(_) => {
+                self.operation.clear();
+                Err((key, value, ErrorCode::FAIL))
+            }
+        }
+    }
+
+    /// Encrypt the key and value being stored into `buffer` using the nonce
+    /// we generated.
+    fn encrypt(&self) -> Result<(), ErrorCode> {
+        let buffer = self.buffer.take().ok_or(ErrorCode::FAIL)?;
+        let nonce = self.nonce.get();
+
+        buffer[0] = HEADER_VERSION;
+        buffer[1..HEADER_LENGTH].copy_from_slice(&nonce);
+        let mut length = KEY_LENGTH_LENGTH;
+        self.unencrypted_key.map(|key| {
+            buffer[HEADER_LENGTH] = key.len() as u8;
+            buffer[HEADER_LENGTH + length..HEADER_LENGTH + length + key.len()]
+                .copy_from_slice(key.as_slice());
+            length += key.len();
+        });
+        self.value.map(|value| {
+            buffer[HEADER_LENGTH + length..HEADER_LENGTH + length + value.len()]
+                .copy_from_slice(value.as_slice());
+            length += value.len();
+        });
+        self.message_length.set(length);
+
+        self.crypt(buffer, &nonce, length, true)
+    }
+
+    /// Decrypt and authenticate the `length` long encrypted key and value in
+    /// `buffer`.
+    ///
+    /// The value must be `encrypted()`.
+    fn decrypt(&self, buffer: &'static mut [u8], length: usize) -> Result<(), ErrorCode> {
+        let mut nonce = [0; CCM_NONCE_LENGTH];
+        nonce.copy_from_slice(&buffer[1..HEADER_LENGTH]);
+        let length = length - HEADER_LENGTH - MIC_LENGTH;
+        self.message_length.set(length);
+
+        self.crypt(buffer, &nonce, length, false)
+    }
+
+    /// Whether the `length` long value in `buffer` has the format of an
+    /// encrypted value, and fits in `buffer` so it can be authenticated.
+    fn encrypted(buffer: &[u8], length: usize) -> bool {
+        length >= OVERHEAD && length <= buffer.len() && buffer[0] == HEADER_VERSION
+    }
+
+    /// Find the value in the decrypted key and value in `buffer`.
+    ///
+    /// Returns the range of the value in `buffer`, or `None` if the key
+    /// length is invalid or the key does not match `key`.
+    fn decrypted_value(
+        &self,
+        buffer: &[u8],
+        key: Option<&[u8]>,
+    ) -> Option<core::ops::Range<usize>> {
+        let message = &buffer[HEADER_LENGTH..HEADER_LENGTH + self.message_length.get()];
+        let (&key_length, rest) = message.split_first()?;
+        let (stored_key, value) = rest.split_at_checked(key_length as usize)?;
+        if key.is_some_and(|key| key != stored_key) {
+            return None;
+        }
+        let start = HEADER_LENGTH + KEY_LENGTH_LENGTH + stored_key.len();
+        Some(start..start + value.len())
+    }
+
+    fn crypt(
+        &self,
+        buffer: &'static mut [u8],
+        nonce: &[u8; CCM_NONCE_LENGTH],
+        length: usize,
+        encrypting: bool,
+    ) -> Result<(), ErrorCode> {
+        if let Err(e) = self
+            .aes
+            .set_key(&self.key)
+            .and_then(|()| self.aes.set_nonce(nonce))
+        {
+            self.buffer.replace(buffer);
+            return Err(e);
+        }
+
+        // The header is authenticated but not encrypted.
+        self.aes
+            .crypt(
+                buffer,
+                0,
+                HEADER_LENGTH,
+                length,
+                MIC_LENGTH,
+                true,
+                encrypting,
+            )
+            .map_err(|(e, buffer)| {
+                self.buffer.replace(buffer);
+                e
+            })
+    }
+
+    /// Finish the current operation with an error, returning the caller's
+    /// buffers.
+    fn complete_with_error(&self, operation: Operation, error: ErrorCode) {
+        self.operation.clear();
+
+        let key = self.unencrypted_key.take();
+        let value = self.value.take();
+
+        self.client.map(move |cb| match operation {
+            Operation::Get => {
+                key.zip(value).map(|(key, mut value)| {
+                    // Don't return any part of a value that wasn't
+                    // authenticated.
+                    value.as_mut_slice().iter_mut().for_each(|m| *m = 0);
+                    cb.get_complete(Err(error), key, value);
+                });
+            }
+            Operation::Set => {
+                key.zip(value)
+                    .map(|(key, value)| cb.set_complete(Err(error), key, value));
+            }
+            Operation::Add => {
+                key.zip(value)
+                    .map(|(key, value)| cb.add_complete(Err(error), key, value));
+            }
+            Operation::Update => {
+                key.zip(value)
+                    .map(|(key, value)| cb.update_complete(Err(error), key, value));
+            }
+            Operation::NextKey => {
+                let position = self.position.get();
+                key.zip(value).map(|(key, mut value)| {
+                    value.as_mut_slice().iter_mut().for_each(|m| *m = 0);
+                    cb.next_key_complete(Err(error), position, key, value);
+                });
+            }
+            Operation::Delete | Operation::CommitTransaction | Operation::GarbageCollect => {}
+        });
+    }
+
+    /// Continue `next_key()` after the value at the last position, which
+    /// failed authentication.
+    fn skip_next_key(&self) {
+        let (Some(mut key), Some(buffer)) = (self.unencrypted_key.take(), self.buffer.take())
+        else {
+            self.complete_with_error(Operation::NextKey, ErrorCode::FAIL);
+            return;
+        };
+
+        key.reset();
+        if let Err((key, buffer, e)) =
+            self.kv
+                .next_key(self.position.get(), key, SubSliceMut::new(buffer))
+        {
+            self.buffer.replace(buffer.take());
+            self.unencrypted_key.replace(key);
+            self.complete_with_error(Operation::NextKey, e);
+        }
+    }
+}
+
+impl<'a, K: kv::KV<'a>, A: AES128CCM<'a>, R: rng::Rng<'a>> kv::KV<'a>
+    for KVStoreEncryption<'a, K, A, R>
+{
+    fn set_client(&self, client: &'a dyn kv::KVClient) {
+        self.client.set(client);
+    }
+
+    fn get(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if self.operation.is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return Err((key, value, ErrorCode::FAIL)),
+        };
+
+        self.operation.set(Operation::Get);
+
+        match self.kv.get(key, SubSliceMut::new(buffer)) {
+            Ok(()) => {
+                self.value.replace(value);
+                Ok(())
+            }
+            Err((key, buffer, e)) => {
+                self.buffer.replace(buffer.take());
+                self.operation.clear();
+                Err((key, value, e))
+            }
+        }
+    }
+
+    fn set(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        self.insert(key, value, Operation::Set)
+    }
+
+    fn add(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        self.insert(key, value, Operation::Add)
+    }
+
+    fn update(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        self.insert(key, value, Operation::Update)
+    }
+
+    fn delete(
+        &self,
+        key: SubSliceMut<'static, u8>,
+    ) -> Result<(), (SubSliceMut<'static, u8>, ErrorCode)> {
+        if self.operation.is_some() {
+            return Err((key, ErrorCode::BUSY));
+        }
+
+        self.operation.set(Operation::Delete);
+
+        self.kv.delete(key).inspect_err(|_| {
+            self.operation.clear();
+        })
+    }
+
+    fn next_key(
+        &self,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if self.operation.is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return Err((key, value, ErrorCode::FAIL)),
+        };
+
+        self.operation.set(Operation::NextKey);
+
+        match self.kv.next_key(position, key, SubSliceMut::new(buffer)) {
+            Ok(()) => {
+                self.value.replace(value);
+                Ok(())
+            }
+            Err((key, buffer, e)) => {
+                self.buffer.replace(buffer.take());
+                self.operation.clear();
+                Err((key, value, e))
+            }
+        }
+    }
+
+    fn garbage_collect(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.operation.set(Operation::GarbageCollect);
+
+        self.kv.garbage_collect().inspect_err(|_| {
+            self.operation.clear();
+        })
+    }
+
+    fn begin_transaction(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.kv.begin_transaction()
+    }
+
+    fn commit_transaction(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.operation.set(Operation::CommitTransaction);
+
+        self.kv.commit_transaction().inspect_err(|_| {
+            self.operation.clear();
+        })
+    }
+
+    fn abort_transaction(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.kv.abort_transaction()
+    }
+}
+
+impl<'a, K: kv::KV<'a>, A: AES128CCM<'a>, R: rng::Rng<'a>> rng::Client
+    for KVStoreEncryption<'a, K, A, R>
+{
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: Result<(), ErrorCode>,
+    ) -> rng::Continue {
+        let operation = match self.operation.get() {
+            Some(op @ (Operation::Set | Operation::Add | Operation::Update)) => op,
+            _ => return rng::Continue::Done,
+        };
+
+        if error.is_err() {
+            self.complete_with_error(operation, ErrorCode::FAIL);
+            return rng::Continue::Done;
+        }
+
+        let mut nonce = self.nonce.get();
+        let mut length = self.nonce_length.get();
+        while length < CCM_NONCE_LENGTH {
+            match randomness.next() {
+                Some(random) => {
+                    for (dst, src) in nonce[length..].iter_mut().zip(random.to_le_bytes()) {
+                        *dst = src;
+                        length += 1;
+                    }
+                }
+                None => break,
+            }
+        }
+        self.nonce.set(nonce);
+        self.nonce_length.set(length);
+
+        if length < CCM_NONCE_LENGTH {
+            return rng::Continue::More;
+        }
+
+        if let Err(e) = self.encrypt() {
+            self.complete_with_error(operation, e);
+        }
+
+        rng::Continue::Done
+    }
+}
+
+impl<'a, K: kv::KV<'a>, A: AES128CCM<'a>, R: rng::Rng<'a>> CCMClient
+    for KVStoreEncryption<'a, K, A, R>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        let operation = match self.operation.get() {
+            Some(op) => op,
+            None => {
+                self.buffer.replace(buf);
+                return;
+            }
+        };
+        let length = self.message_length.get();
+
+        match operation {
+            Operation::Set | Operation::Add | Operation::Update => {
+                if res.is_err() {
+                    self.buffer.replace(buf);
+                    self.complete_with_error(operation, ErrorCode::FAIL);
+                    return;
+                }
+
+                let mut encrypted = SubSliceMut::new(buf);
+                encrypted.slice(..HEADER_LENGTH + length + MIC_LENGTH);
+
+                let ret = match self.unencrypted_key.take() {
+                    Some(key) => match operation {
+                        Operation::Set => self.kv.set(key, encrypted),
+                        Operation::Add => self.kv.add(key, encrypted),
+                        _ => self.kv.update(key, encrypted),
+                    },
+                    None => {
+                        self.buffer.replace(encrypted.take());
+                        self.complete_with_error(operation, ErrorCode::FAIL);
+                        return;
+                    }
+                };
+
+                if let Err((key, encrypted, e)) = ret {
+                    self.buffer.replace(encrypted.take());
+                    self.unencrypted_key.replace(key);
+                    self.complete_with_error(operation, e);
+                }
+            }
+            Operation::Get | Operation::NextKey => {
+                // The key of a value found by `next_key()` is the hashed key,
+                // so only the key of a value we get is checked.
+                let range = if res.is_ok() && tag_is_valid {
+                    self.unencrypted_key.map_or(None, |key| {
+                        let key = (operation == Operation::Get).then_some(key.as_slice());
+                        self.decrypted_value(buf, key)
+                    })
+                } else {
+                    None
+                };
+                let Some(range) = range else {
+                    buf.iter_mut().for_each(|m| *m = 0);
+                    self.buffer.replace(buf);
+                    if operation == Operation::NextKey && res.is_ok() {
+                        self.skip_next_key();
+                    } else {
+                        self.complete_with_error(operation, ErrorCode::FAIL);
+                    }
+                    return;
+                };
+                let length = range.len();
+
+                // Copy as much of the value as fits into the caller's buffer.
+                let mut fits = true;
+                self.value.map(|value| {
+                    let copy_length = core::cmp::min(length, value.len());
+                    value[..copy_length]
+                        .copy_from_slice(&buf[range.start..range.start + copy_length]);
+                    if copy_length < length {
+                        fits = false;
+                    } else {
+                        value.slice(..length);
+                    }
+                });
+
+                // Don't leave the decrypted value in our buffer.
+                buf.iter_mut().for_each(|m| *m = 0);
+                self.buffer.replace(buf);
+                self.operation.clear();
+
+                let key = self.unencrypted_key.take();
+                let value = self.value.take();
+                let position = self.position.get();
+                self.client.map(move |cb| {
+                    key.zip(value).map(|(key, value)| {
+                        if operation == Operation::Get {
+                            let result = if fits { Ok(()) } else { Err(ErrorCode::SIZE) };
+                            cb.get_complete(result, key, value);
+                        } else {
+                            cb.next_key_complete(Ok(length), position, key, value);
+                        }
+                    });
+                });
+            }
+            Operation::Delete | Operation::CommitTransaction | Operation::GarbageCollect => {
+                self.buffer.replace(buf);
+            }
+        }
+    }
+}
+
+impl<'a, K: kv::KV<'a>, A: AES128CCM<'a>, R: rng::Rng<'a>> kv::KVClient
+    for KVStoreEncryption<'a, K, A, R>
+{
+    fn get_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        let length = value.len();
+        self.unencrypted_key.replace(key);
+
+        let ret = match result {
+            Ok(()) if Self::encrypted(value.as_slice(), length) => {
+                self.decrypt(value.take(), length)
+            }
+            Ok(()) => {
+                self.buffer.replace(value.take());
+                Err(ErrorCode::FAIL)
+            }
+            // The value is too long for our buffer, so it can't be
+            // authenticated and none of it can be returned.
+            Err(ErrorCode::SIZE) => {
+                self.buffer.replace(value.take());
+                Err(ErrorCode::FAIL)
+            }
+            Err(e) => {
+                self.buffer.replace(value.take());
+                Err(e)
+            }
+        };
+
+        if let Err(e) = ret {
+            self.complete_with_error(Operation::Get, e);
+        }
+    }
+
+    fn set_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.buffer.replace(value.take());
+        self.operation.clear();
+        self.value.take().map(|value| {
+            self.client.map(move |cb| {
+                cb.set_complete(result, key, value);
+            });
+        });
+    }
+
+    fn add_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.buffer.replace(value.take());
+        self.operation.clear();
+        self.value.take().map(|value| {
+            self.client.map(move |cb| {
+                cb.add_complete(result, key, value);
+            });
+        });
+    }
+
+    fn update_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.buffer.replace(value.take());
+        self.operation.clear();
+        self.value.take().map(|value| {
+            self.client.map(move |cb| {
+                cb.update_complete(result, key, value);
+            });
+        });
+    }
+
+    fn delete_complete(&self, result: Result<(), ErrorCode>, key: SubSliceMut<'static, u8>) {
+        self.operation.clear();
+        self.client.map(move |cb| {
+            cb.delete_complete(result, key);
+        });
+    }
+
+    fn next_key_complete(
+        &self,
+        result: Result<usize, ErrorCode>,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.unencrypted_key.replace(key);
+        self.position.set(position);
+
+        let ret = match result {
+            Ok(length) if Self::encrypted(value.as_slice(), length) => {
+                self.decrypt(value.take(), length)
+            }
+            // Values that are not encrypted, or too long for our buffer to be
+            // authenticated, are skipped.
+            Ok(_) => {
+                self.buffer.replace(value.take());
+                self.skip_next_key();
+                return;
+            }
+            Err(e) => {
+                self.buffer.replace(value.take());
+                Err(e)
+            }
+        };
+
+        if let Err(e) = ret {
+            self.complete_with_error(Operation::NextKey, e);
+        }
+    }
+
+    fn garbage_collection_complete(&self, result: Result<(), ErrorCode>) {
+        self.operation.clear();
+        self.client.map(move |cb| {
+            cb.garbage_collection_complete(result);
+        });
+    }
+
+    fn commit_transaction_complete(&self, result: Result<(), ErrorCode>) {
+        self.operation.clear();
+        self.client.map(move |cb| {
+            cb.commit_transaction_complete(result);
+        });
+    }
+
+    fn remaining_quota_complete(&self, _result: Result<usize, ErrorCode>) {
+        // Quotas are not part of the `KV` interface, so none are queried.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use kernel::hil::kv::{KVClient, KV};
+    use kernel::hil::rng::Rng;
+
+    extern crate std;
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    const DEVICE_KEY: [u8; AES128_KEY_SIZE] = [0x42; AES128_KEY_SIZE];
+    const BUFFER_LENGTH: usize = 64;
+
+    fn leak<T>(value: T) -> &'static T {
+        Box::leak(Box::new(value))
+    }
+
+    fn buffer(contents: &[u8], length: usize) -> SubSliceMut<'static, u8> {
+        let mut buffer = vec![0; length];
+        buffer[..contents.len()].copy_from_slice(contents);
+        SubSliceMut::new(Box::leak(buffer.into_boxed_slice()))
+    }
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum Request {
+        Get,
+        Set,
+        NextKey(usize),
+    }
+
+    /// An in-memory K-V store that keeps keys unhashed. Requests only
+    /// complete when `complete()` is called.
+    struct TestKV {
+        objects: RefCell<Vec<(Vec<u8>, Vec<u8>)>>,
+        request: Cell<Option<Request>>,
+        key: MapCell<SubSliceMut<'static, u8>>,
+        value: MapCell<SubSliceMut<'static, u8>>,
+        client: OptionalCell<&'static dyn kv::KVClient>,
+    }
+
+    impl TestKV {
+        fn new() -> Self {
+            Self {
+                objects: RefCell::new(Vec::new()),
+                request: Cell::new(None),
+                key: MapCell::empty(),
+                value: MapCell::empty(),
+                client: OptionalCell::empty(),
+            }
+        }
+
+        fn stored(&self, key: &[u8]) -> Option<Vec<u8>> {
+            let objects = self.objects.borrow();
+            objects
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+        }
+
+        fn store(&self, key: &[u8], value: Vec<u8>) {
+            let mut objects = self.objects.borrow_mut();
+            objects.retain(|(k, _)| k != key);
+            objects.push((key.to_vec(), value));
+        }
+
+        fn start(
+            &self,
+            request: Request,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            if self.request.get().is_some() {
+                return Err((key, value, ErrorCode::BUSY));
+            }
+            self.request.set(Some(request));
+            self.key.replace(key);
+            self.value.replace(value);
+            Ok(())
+        }
+
+        /// Copy `stored` into `value` like TicKV, which only slices the
+        /// buffer to the value if it fits.
+        fn copy_value(stored: &[u8], value: &mut SubSliceMut<'static, u8>) -> bool {
+            let length = core::cmp::min(stored.len(), value.len());
+            value[..length].copy_from_slice(&stored[..length]);
+            if length == stored.len() {
+                value.slice(..length);
+            }
+            length == stored.len()
+        }
+
+        /// Carry out the request in progress and pass the result to the
+        /// client. Returns `false` if there was none.
+        fn complete(&self) -> bool {
+            let Some(request) = self.request.take() else {
+                return false;
+            };
+            let mut key = self.key.take().unwrap();
+            let mut value = self.value.take().unwrap();
+            let client = self.client.get().unwrap();
+            match request {
+                Request::Get => {
+                    let result = match self.stored(key.as_slice()) {
+                        Some(stored) if Self::copy_value(&stored, &mut value) => Ok(()),
+                        Some(_) => Err(ErrorCode::SIZE),
+                        None => Err(ErrorCode::NOSUPPORT),
+                    };
+                    client.get_complete(result, key, value);
+                }
+                Request::Set => {
+                    self.store(key.as_slice(), value.as_slice().to_vec());
+                    client.set_complete(Ok(()), key, value);
+                }
+                Request::NextKey(position) => {
+                    let object = self.objects.borrow().get(position).cloned();
+                    let result = match object {
+                        Some((stored_key, stored)) => {
+                            key.slice(..stored_key.len());
+                            key.as_mut_slice().copy_from_slice(&stored_key);
+                            Self::copy_value(&stored, &mut value);
+                            Ok(stored.len())
+                        }
+                        None => Err(ErrorCode::NOSUPPORT),
+                    };
+                    client.next_key_complete(result, position + 1, key, value);
+                }
+            }
+            true
+        }
+    }
+
+    impl KV<'static> for TestKV {
+        fn set_client(&self, client: &'static dyn kv::KVClient) {
+            self.client.set(client);
+        }
+
+        fn get(
+            &self,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            self.start(Request::Get, key, value)
+        }
+
+        fn set(
+            &self,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            self.start(Request::Set, key, value)
+        }
+
+        fn add(
+            &self,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            Err((key, value, ErrorCode::NOSUPPORT))
+        }
+
+        fn update(
+            &self,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            Err((key, value, ErrorCode::NOSUPPORT))
+        }
+
+        fn delete(
+            &self,
+            key: SubSliceMut<'static, u8>,
+        ) -> Result<(), (SubSliceMut<'static, u8>, ErrorCode)> {
+            Err((key, ErrorCode::NOSUPPORT))
+        }
+
+        fn next_key(
+            &self,
+            position: usize,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) -> Result<
+            (),
+            (
+                SubSliceMut<'static, u8>,
+                SubSliceMut<'static, u8>,
+                ErrorCode,
+            ),
+        > {
+            self.start(Request::NextKey(position), key, value)
+        }
+
+        fn garbage_collect(&self) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+
+        fn begin_transaction(&self) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+
+        fn commit_transaction(&self) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+
+        fn abort_transaction(&self) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+    }
+
+    /// A stand-in for AES-128-CCM: the message is XORed with the key and the
+    /// nonce, and the MIC mixes the key, the authenticated data and the
+    /// message, so any change to them makes authentication fail.
+    struct TestCCM {
+        key: Cell<[u8; AES128_KEY_SIZE]>,
+        nonce: Cell<[u8; CCM_NONCE_LENGTH]>,
+        request: Cell<Option<(usize, usize, usize, bool)>>,
+        buffer: TakeCell<'static, [u8]>,
+        client: OptionalCell<&'static dyn CCMClient>,
+    }
+
+    impl TestCCM {
+        fn new() -> Self {
+            Self {
+                key: Cell::new([0; AES128_KEY_SIZE]),
+                nonce: Cell::new([0; CCM_NONCE_LENGTH]),
+                request: Cell::new(None),
+                buffer: TakeCell::empty(),
+                client: OptionalCell::empty(),
+            }
+        }
+
+        fn mic(&self, data: &[u8]) -> [u8; MIC_LENGTH] {
+            let mut mic = [0u8; MIC_LENGTH];
+            for (i, byte) in self.key.get().iter().chain(data).enumerate() {
+                mic[i % MIC_LENGTH] = mic[i % MIC_LENGTH].rotate_left(3) ^ byte;
+            }
+            mic
+        }
+
+        fn xor(&self, message: &mut [u8]) {
+            let (key, nonce) = (self.key.get(), self.nonce.get());
+            for (i, byte) in message.iter_mut().enumerate() {
+                *byte ^= key[i % AES128_KEY_SIZE] ^ nonce[i % CCM_NONCE_LENGTH];
+            }
+        }
+
+        fn complete(&self) -> bool {
+            let Some((m_off, m_len, mic_len, encrypting)) = self.request.take() else {
+                return false;
+            };
+            let buffer = self.buffer.take().unwrap();
+            let end = m_off + m_len;
+            let tag_is_valid = if encrypting {
+                let mic = self.mic(&buffer[..end]);
+                buffer[end..end + mic_len].copy_from_slice(&mic[..mic_len]);
+                self.xor(&mut buffer[m_off..end]);
+                true
+            } else {
+                self.xor(&mut buffer[m_off..end]);
+                buffer[end..end + mic_len] == self.mic(&buffer[..end])[..mic_len]
+            };
+            self.client
+                .get()
+                .unwrap()
+                .crypt_done(buffer, Ok(()), tag_is_valid);
+            true
+        }
+    }
+
+    impl AES128CCM<'static> for TestCCM {
+        fn set_client(&'static self, client: &'static dyn CCMClient) {
+            self.client.set(client);
+        }
+
+        fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+            self.key.set(key.try_into().map_err(|_| ErrorCode::INVAL)?);
+            Ok(())
+        }
+
+        fn set_nonce(&self, nonce: &[u8]) -> Result<(), ErrorCode> {
+            self.nonce
+                .set(nonce.try_into().map_err(|_| ErrorCode::INVAL)?);
+            Ok(())
+        }
+
+        fn crypt(
+            &self,
+            buf: &'static mut [u8],
+            a_off: usize,
+            m_off: usize,
+            m_len: usize,
+            mic_len: usize,
+            _confidential: bool,
+            encrypting: bool,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            if a_off != 0 || m_off + m_len + mic_len > buf.len() {
+                return Err((ErrorCode::INVAL, buf));
+            }
+            self.request.set(Some((m_off, m_len, mic_len, encrypting)));
+            self.buffer.replace(buf);
+            Ok(())
+        }
+    }
+
+    /// Provides a counter as randomness, so every nonce is different.
+    struct TestRng {
+        requested: Cell<bool>,
+        next: Cell<u32>,
+        client: OptionalCell<&'static dyn rng::Client>,
+    }
+
+    impl TestRng {
+        fn complete(&self) -> bool {
+            if !self.requested.replace(false) {
+                return false;
+            }
+            let start = self.next.get();
+            self.next.set(start + 4);
+            let mut randomness = start..start + 4;
+            self.client
+                .get()
+                .unwrap()
+                .randomness_available(&mut randomness, Ok(()));
+            true
+        }
+    }
+
+    impl Rng<'static> for TestRng {
+        fn get(&self) -> Result<(), ErrorCode> {
+            self.requested.set(true);
+            Ok(())
+        }
+
+        fn cancel(&self) -> Result<(), ErrorCode> {
+            self.requested.set(false);
+            Ok(())
+        }
+
+        fn set_client(&'static self, client: &'static dyn rng::Client) {
+            self.client.set(client);
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Done {
+        Get(Result<(), ErrorCode>, Vec<u8>),
+        Set(Result<(), ErrorCode>),
+        NextKey(Result<usize, ErrorCode>, usize, Vec<u8>, Vec<u8>),
+    }
+
+    struct TestClient {
+        done: RefCell<Vec<Done>>,
+    }
+
+    impl KVClient for TestClient {
+        fn get_complete(
+            &self,
+            result: Result<(), ErrorCode>,
+            _key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) {
+            let done = Done::Get(result, value.as_slice().to_vec());
+            self.done.borrow_mut().push(done);
+        }
+
+        fn set_complete(
+            &self,
+            result: Result<(), ErrorCode>,
+            _key: SubSliceMut<'static, u8>,
+            _value: SubSliceMut<'static, u8>,
+        ) {
+            self.done.borrow_mut().push(Done::Set(result));
+        }
+
+        fn add_complete(
+            &self,
+            _result: Result<(), ErrorCode>,
+            _key: SubSliceMut<'static, u8>,
+            _value: SubSliceMut<'static, u8>,
+        ) {
+        }
+
+        fn update_complete(
+            &self,
+            _result: Result<(), ErrorCode>,
+            _key: SubSliceMut<'static, u8>,
+            _value: SubSliceMut<'static, u8>,
+        ) {
+        }
+
+        fn delete_complete(&self, _result: Result<(), ErrorCode>, _key: SubSliceMut<'static, u8>) {}
+
+        fn next_key_complete(
+            &self,
+            result: Result<usize, ErrorCode>,
+            position: usize,
+            key: SubSliceMut<'static, u8>,
+            value: SubSliceMut<'static, u8>,
+        ) {
+            let done = Done::NextKey(
+                result,
+                position,
+                key.as_slice().to_vec(),
+                value.as_slice().to_vec(),
+            );
+            self.done.borrow_mut().push(done);
+        }
+
+        fn garbage_collection_complete(&self, _result: Result<(), ErrorCode>) {}
+
+        fn commit_transaction_complete(&self, _result: Result<(), ErrorCode>) {}
+
+        fn remaining_quota_complete(&self, _result: Result<usize, ErrorCode>) {}
+    }
+
+    type Store = KVStoreEncryption<'static, TestKV, TestCCM, TestRng>;
+
+    struct Test {
+        kv: &'static TestKV,
+        aes: &'static TestCCM,
+        rng: &'static TestRng,
+        store: &'static Store,
+        client: &'static TestClient,
+    }
+
+    impl Test {
+        fn new() -> Self {
+            let kv = leak(TestKV::new());
+            let aes = leak(TestCCM::new());
+            let rng = leak(TestRng {
+                requested: Cell::new(false),
+                next: Cell::new(0),
+                client: OptionalCell::empty(),
+            });
+            let store = leak(KVStoreEncryption::new(
+                kv,
+                aes,
+                rng,
+                DEVICE_KEY,
+                buffer(&[], BUFFER_LENGTH).take(),
+            ));
+            let client = leak(TestClient {
+                done: RefCell::new(Vec::new()),
+            });
+            kv.set_client(store);
+            aes.set_client(store);
+            rng.set_client(store);
+            store.set_client(client);
+            Self {
+                kv,
+                aes,
+                rng,
+                store,
+                client,
+            }
+        }
+
+        /// Complete requests until the store is done, and return the
+        /// callback it issued.
+        fn run(&self) -> Done {
+            while self.rng.complete() || self.aes.complete() || self.kv.complete() {}
+            let mut done = self.client.done.borrow_mut();
+            assert_eq!(done.len(), 1);
+            done.pop().unwrap()
+        }
+
+        fn set(&self, key: &[u8], value: &[u8]) {
+            assert!(self
+                .store
+                .set(buffer(key, key.len()), buffer(value, value.len()))
+                .is_ok());
+            assert_eq!(self.run(), Done::Set(Ok(())));
+        }
+
+        fn get(&self, key: &[u8], length: usize) -> Done {
+            assert!(self
+                .store
+                .get(buffer(key, key.len()), buffer(&[], length))
+                .is_ok());
+            self.run()
+        }
+
+        fn next_key(&self, position: usize) -> Done {
+            assert!(self
+                .store
+                .next_key(position, buffer(&[], 8), buffer(&[], 16))
+                .is_ok());
+            self.run()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let test = Test::new();
+        test.set(b"key", b"value");
+
+        let stored = test.kv.stored(b"key").unwrap();
+        assert_eq!(stored.len(), OVERHEAD + 3 + 5);
+        assert_eq!(stored[0], HEADER_VERSION);
+        assert!(!stored.windows(5).any(|w| w == b"value"));
+
+        assert_eq!(test.get(b"key", 16), Done::Get(Ok(()), b"value".to_vec()));
+
+        // The same value is stored with a new nonce and ciphertext.
+        test.set(b"key", b"value");
+        let restored = test.kv.stored(b"key").unwrap();
+        assert_ne!(restored[1..HEADER_LENGTH], stored[1..HEADER_LENGTH]);
+        assert_ne!(restored[HEADER_LENGTH..], stored[HEADER_LENGTH..]);
+        assert_eq!(test.get(b"key", 16), Done::Get(Ok(()), b"value".to_vec()));
+    }
+
+    #[test]
+    fn tampered_mic() {
+        let test = Test::new();
+        test.set(b"key", b"value");
+        let mut stored = test.kv.stored(b"key").unwrap();
+        let last = stored.len() - 1;
+        stored[last] ^= 1;
+        test.kv.store(b"key", stored);
+
+        assert_eq!(
+            test.get(b"key", 8),
+            Done::Get(Err(ErrorCode::FAIL), vec![0; 8])
+        );
+    }
+
+    #[test]
+    fn tampered_value() {
+        let test = Test::new();
+        test.set(b"key", b"value");
+        let mut stored = test.kv.stored(b"key").unwrap();
+        stored[HEADER_LENGTH + 4] ^= 1;
+        test.kv.store(b"key", stored);
+
+        assert_eq!(
+            test.get(b"key", 8),
+            Done::Get(Err(ErrorCode::FAIL), vec![0; 8])
+        );
+    }
+
+    #[test]
+    fn moved_value() {
+        let test = Test::new();
+        test.set(b"key", b"value");
+        test.kv.store(b"other", test.kv.stored(b"key").unwrap());
+
+        assert_eq!(
+            test.get(b"other", 8),
+            Done::Get(Err(ErrorCode::FAIL), vec![0; 8])
+        );
+        assert_eq!(test.get(b"key", 8), Done::Get(Ok(()), b"value".to_vec()));
+    }
+
+    #[test]
+    fn unencrypted_value() {
+        let test = Test::new();
+        test.kv
+            .store(b"key", b"a value written before encryption".to_vec());
+
+        assert_eq!(
+            test.get(b"key", 8),
+            Done::Get(Err(ErrorCode::FAIL), vec![0; 8])
+        );
+    }
+
+    #[test]
+    fn truncated_value() {
+        let test = Test::new();
+        test.set(b"key", b"a longer value");
+
+        // As much of the value as fits is returned.
+        assert_eq!(
+            test.get(b"key", 8),
+            Done::Get(Err(ErrorCode::SIZE), b"a longer".to_vec())
+        );
+
+        // Values too long for the buffer of the store are refused.
+        let key = buffer(b"key", 3);
+        let value = buffer(&[], BUFFER_LENGTH - OVERHEAD - 2);
+        assert!(matches!(
+            test.store.set(key, value),
+            Err((_, _, ErrorCode::SIZE))
+        ));
+    }
+
+    #[test]
+    fn next_key_skips_unauthenticated_values() {
+        let test = Test::new();
+        test.set(b"one", b"first");
+        test.set(b"two", b"second");
+        test.set(b"six", b"third");
+        let mut stored = test.kv.stored(b"two").unwrap();
+        stored[HEADER_LENGTH + 4] ^= 1;
+        test.kv.store(b"two", stored);
+        test.kv.store(b"old", b"unencrypted".to_vec());
+
+        let first = Done::NextKey(Ok(5), 1, b"one".to_vec(), b"first".to_vec());
+        assert_eq!(test.next_key(0), first);
+        // The tampered value of "two", which was stored again at the end, is
+        // skipped, as is the unencrypted value.
+        let third = Done::NextKey(Ok(5), 2, b"six".to_vec(), b"third".to_vec());
+        assert_eq!(test.next_key(1), third);
+        assert!(matches!(
+            test.next_key(2),
+            Done::NextKey(Err(ErrorCode::NOSUPPORT), 5, _, _)
+        ));
+    }
+}
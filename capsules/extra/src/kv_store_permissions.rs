@@ -25,8 +25,33 @@
 //!
 //!    hil::flash
 //! ```
+//!
+//! Storage Quotas
+//! --------------
+//!
+//! If the `StoragePermissions` of a caller include a quota, this capsule limits
+//! the total size of the objects marked with the caller's `write_id` to the
+//! quota. The size of an object is the length of its value plus the Tock
+//! header. Inserts that would exceed the quota fail with `NOMEM`.
+//!
+//! The size does not include what the layers below add to each object: the
+//! key and `kv_store_encryption::OVERHEAD` bytes if the store is encrypted,
+//! and the object header and checksum of TicKV. The flash used by a
+//! `write_id` is therefore larger than its quota, by up to that overhead for
+//! each object it stores. Boards should take this into account when choosing
+//! the quotas, for example by leaving space for the overhead of the number of
+//! objects an app is expected to store.
+//!
+//! The bytes stored with each `write_id` are kept in a table of counters. The
+//! counters are computed from the objects in the store (by iterating over all
+//! keys) the first time a quota is checked after boot, and are then updated as
+//! objects are set and deleted. If the table is too small for all `write_id`s
+//! in the store, the usage of a `write_id` without a counter is computed by
+//! iterating over all keys each time it is needed.
 
+use core::cell::Cell;
 use core::mem;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil::kv;
 use kernel::storage_permissions::StoragePermissions;
 use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
@@ -40,12 +65,37 @@ enum Operation {
     Add,
     Update,
     Delete,
+    NextKey,
     GarbageCollect,
+    RemainingQuota,
 }
 
 /// Current version of the Tock K-V header.
 const HEADER_VERSION: u8 = 0;
 pub const HEADER_LENGTH: usize = mem::size_of::<KeyHeader>();
+/// Length of the buffer used to hold the hashed keys when computing the storage
+/// used by a caller.
+pub const SCAN_KEY_LENGTH: usize = mem::size_of::<u64>();
+/// Default number of `write_id`s whose storage usage is counted.
+pub const DEFAULT_WRITE_ID_COUNTERS: usize = 8;
+
+/// Counter of the bytes stored with a `write_id`.
+pub type WriteIdCounter = Cell<Option<(u32, usize)>>;
+
+/// State of the `write_id` usage counters.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Counters {
+    /// The store has not been scanned since boot.
+    NotCounted,
+    /// The store is being scanned, `complete` is false once a `write_id`
+    /// did not fit in the table.
+    Counting { complete: bool },
+    /// There is a counter for every `write_id` in the store.
+    Counted,
+    /// The table of counters was too small, `write_id`s without a counter
+    /// have an unknown usage.
+    Incomplete,
+}
 
 /// This is the header used for KV stores.
 #[repr(C, packed)]
@@ -80,26 +130,263 @@ impl KeyHeader {
 pub struct KVStorePermissions<'a, K: kv::KV<'a>> {
     kv: &'a K,
     header_value: TakeCell<'static, [u8]>,
+    scan_key: TakeCell<'static, [u8]>,
 
     client: OptionalCell<&'a dyn kv::KVClient>,
     operation: OptionalCell<Operation>,
 
+    key: MapCell<SubSliceMut<'static, u8>>,
     value: MapCell<SubSliceMut<'static, u8>>,
     valid_ids: OptionalCell<StoragePermissions>,
+
+    /// Bytes stored with the caller's `write_id`, valid once the usage for a
+    /// quota check is known.
+    usage: Cell<usize>,
+    counters: Cell<Counters>,
+    write_id_counters: &'static [WriteIdCounter],
+    /// Object removed and object stored, as `(write_id, bytes)`, by the
+    /// pending operation, applied to the counters once it succeeds.
+    change: Cell<(Option<(u32, usize)>, Option<(u32, usize)>)>,
+    deferred_call: DeferredCall,
 }
 
 impl<'a, K: kv::KV<'a>> KVStorePermissions<'a, K> {
     pub fn new(
         kv: &'a K,
         header_value: &'static mut [u8; HEADER_LENGTH],
+        scan_key: &'static mut [u8; SCAN_KEY_LENGTH],
+        write_id_counters: &'static [WriteIdCounter],
     ) -> KVStorePermissions<'a, K> {
         Self {
             kv,
             header_value: TakeCell::new(header_value),
+            scan_key: TakeCell::new(scan_key),
             client: OptionalCell::empty(),
             operation: OptionalCell::empty(),
+            key: MapCell::empty(),
             value: MapCell::empty(),
             valid_ids: OptionalCell::empty(),
+            usage: Cell::new(0),
+            counters: Cell::new(Counters::NotCounted),
+            write_id_counters,
+            change: Cell::new((None, None)),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Bytes stored with `write_id`, if the counters know it.
+    fn stored_bytes(&self, write_id: u32) -> Option<usize> {
+        let counted = self
+            .write_id_counters
+            .iter()
+            .find_map(|counter| counter.get().filter(|(id, _)| *id == write_id))
+            .map(|(_, bytes)| bytes);
+
+        match self.counters.get() {
+            Counters::Counted => Some(counted.unwrap_or(0)),
+            Counters::Incomplete => counted,
+            _ => None,
+        }
+    }
+
+    /// Add `bytes` to the counter of `write_id`, using a free counter if it
+    /// doesn't have one yet. Returns `false` if there is no free counter.
+    fn count_bytes(&self, write_id: u32, bytes: usize) -> bool {
+        if let Some(counter) = self
+            .write_id_counters
+            .iter()
+            .find(|counter| counter.get().is_some_and(|(id, _)| id == write_id))
+        {
+            counter.set(counter.get().map(|(id, count)| (id, count + bytes)));
+            true
+        } else if let Some(counter) = self
+            .write_id_counters
+            .iter()
+            .find(|counter| counter.get().is_none())
+        {
+            counter.set(Some((write_id, bytes)));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Update the counters with the change made by the operation that just
+    /// succeeded.
+    fn apply_change(&self) {
+        let (removed, stored) = self.change.replace((None, None));
+        let counters = self.counters.get();
+        if counters != Counters::Counted && counters != Counters::Incomplete {
+            return;
+        }
+
+        if let Some((write_id, bytes)) = removed {
+            self.write_id_counters.iter().for_each(|counter| {
+                if let Some((id, count)) = counter.get() {
+                    if id == write_id {
+                        let count = count.saturating_sub(bytes);
+                        // Without a counter a `write_id` has no usage once all
+                        // `write_id`s are counted.
+                        if count == 0 && counters == Counters::Counted {
+                            counter.set(None);
+                        } else {
+                            counter.set(Some((id, count)));
+                        }
+                    }
+                }
+            });
+        }
+
+        if let Some((write_id, bytes)) = stored {
+            if counters == Counters::Counted {
+                if !self.count_bytes(write_id, bytes) {
+                    self.counters.set(Counters::Incomplete);
+                }
+            } else if self.stored_bytes(write_id).is_some() {
+                // Only `write_id`s that have a counter are fully counted.
+                self.count_bytes(write_id, bytes);
+            }
+        }
+    }
+
+    /// Start computing how many bytes are stored with the `write_id` of the
+    /// current permissions. `scan_complete()` is called when done.
+    ///
+    /// If the counters are not known yet, they are computed by the same scan.
+    fn start_scan(&self) -> Result<(), ErrorCode> {
+        self.usage.set(0);
+        if self.counters.get() == Counters::NotCounted {
+            self.write_id_counters
+                .iter()
+                .for_each(|counter| counter.set(None));
+            self.counters.set(Counters::Counting { complete: true });
+        }
+
+        self.scan_next(0).inspect_err(|_| self.scan_failed())
+    }
+
+    /// Forget the counters if a scan computing them did not finish.
+    fn scan_failed(&self) {
+        if let Counters::Counting { .. } = self.counters.get() {
+            self.counters.set(Counters::NotCounted);
+        }
+    }
+
+    /// Count an object found by a scan.
+    fn scan_object(&self, write_id: u32, bytes: usize) {
+        if self.valid_ids.get().and_then(|perms| perms.get_write_id()) == Some(write_id) {
+            self.usage.set(self.usage.get() + bytes);
+        }
+
+        if let Counters::Counting { .. } = self.counters.get() {
+            if !self.count_bytes(write_id, bytes) {
+                self.counters.set(Counters::Counting { complete: false });
+            }
+        }
+    }
+
+    /// Apply the change of a finished operation to the counters if it
+    /// succeeded.
+    fn update_counters(&self, result: Result<(), ErrorCode>) {
+        if result.is_ok() {
+            self.apply_change();
+        } else {
+            self.change.set((None, None));
+        }
+    }
+
+    /// Finish a scan once the last key has been read.
+    fn scan_done(&self, result: Result<(), ErrorCode>) {
+        match (result, self.counters.get()) {
+            (Err(_), _) => self.scan_failed(),
+            (Ok(()), Counters::Counting { complete: true }) => self.counters.set(Counters::Counted),
+            (Ok(()), Counters::Counting { complete: false }) => {
+                self.counters.set(Counters::Incomplete)
+            }
+            _ => {}
+        }
+
+        self.scan_complete(result);
+    }
+
+    fn scan_next(&self, position: usize) -> Result<(), ErrorCode> {
+        let scan_key = self.scan_key.take().ok_or(ErrorCode::FAIL)?;
+        let header_value = match self.header_value.take() {
+            Some(header_value) => header_value,
+            None => {
+                self.scan_key.replace(scan_key);
+                return Err(ErrorCode::FAIL);
+            }
+        };
+
+        self.kv
+            .next_key(
+                position,
+                SubSliceMut::new(scan_key),
+                SubSliceMut::new(header_value),
+            )
+            .map_err(|(scan_key, header_value, e)| {
+                self.scan_key.replace(scan_key.take());
+                self.header_value.replace(header_value.take());
+                e
+            })
+    }
+
+    /// Check that replacing an object of `old_length` bytes with one of
+    /// `new_length` bytes stays within the quota.
+    ///
+    /// Lengths are values plus the Tock header, the per-object overhead of the
+    /// layers below is not counted.
+    fn within_quota(&self, old_length: usize, new_length: usize) -> bool {
+        self.valid_ids
+            .get()
+            .and_then(|perms| perms.get_quota())
+            .is_none_or(|quota| self.usage.get().saturating_sub(old_length) + new_length <= quota)
+    }
+
+    /// Continue the pending operation once the storage used by the caller is
+    /// known.
+    fn scan_complete(&self, result: Result<(), ErrorCode>) {
+        self.operation.map(|op| match op {
+            Operation::Set | Operation::Update | Operation::Add => {
+                if let (Some(key), Some(value)) = (self.key.take(), self.value.take()) {
+                    let ret = match result {
+                        Ok(()) => self.continue_insert(key, value, op),
+                        Err(e) => Err((key, value, e)),
+                    };
+
+                    if let Err((key, value, e)) = ret {
+                        self.key.replace(key);
+                        self.value.replace(value);
+                        self.insert_failed(op, e);
+                    }
+                }
+            }
+            Operation::RemainingQuota => {
+                self.operation.clear();
+                let remaining = result.map(|()| {
+                    self.valid_ids
+                        .get()
+                        .and_then(|perms| perms.get_quota())
+                        .unwrap_or(0)
+                        .saturating_sub(self.usage.get())
+                });
+                self.client.map(|cb| cb.remaining_quota_complete(remaining));
+            }
+            _ => {}
+        });
+    }
+
+    /// Return the buffers held for an insert operation to the client with an
+    /// error.
+    fn insert_failed(&self, op: Operation, e: ErrorCode) {
+        self.operation.clear();
+        if let (Some(key), Some(value)) = (self.key.take(), self.value.take()) {
+            self.client.map(move |cb| match op {
+                Operation::Set => cb.set_complete(Err(e), key, value),
+                Operation::Add => cb.add_complete(Err(e), key, value),
+                _ => cb.update_complete(Err(e), key, value),
+            });
         }
     }
 
@@ -142,11 +429,49 @@ impl<'a, K: kv::KV<'a>> KVStorePermissions<'a, K> {
         header.copy_to_buf(value.as_mut_slice());
 
         self.operation.set(operation);
+        self.valid_ids.set(permissions);
+
+        if permissions.get_quota().is_some() {
+            match self.stored_bytes(write_id) {
+                Some(bytes) => self.usage.set(bytes),
+                None => {
+                    // Before the object can be stored we need to know how much
+                    // the caller is already using.
+                    return match self.start_scan() {
+                        Ok(()) => {
+                            self.key.replace(key);
+                            self.value.replace(value);
+                            Ok(())
+                        }
+                        Err(e) => {
+                            self.operation.clear();
+                            Err((key, value, e))
+                        }
+                    };
+                }
+            }
+        }
 
+        self.continue_insert(key, value, operation)
+            .inspect_err(|_| self.operation.clear())
+    }
+
+    /// Store an object once the storage used by the caller is known.
+    fn continue_insert(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+        operation: Operation,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
         match operation {
             Operation::Set | Operation::Update => {
-                self.valid_ids.set(permissions);
-
                 // We first read the key to see if we are allowed to overwrite it.
                 match self.header_value.take() {
                     Some(header_value) => match self.kv.get(key, SubSliceMut::new(header_value)) {
@@ -156,7 +481,6 @@ impl<'a, K: kv::KV<'a>> KVStorePermissions<'a, K> {
                         }
                         Err((key, hvalue, e)) => {
                             self.header_value.replace(hvalue.take());
-                            self.operation.clear();
                             Err((key, value, e))
                         }
                     },
@@ -168,13 +492,15 @@ impl<'a, K: kv::KV<'a>> KVStorePermissions<'a, K> {
                 // Since add will only succeed if the key is not already there,
                 // we do not have to worry about overwriting and do not need to
                 // check permissions.
-                match self.kv.add(key, value) {
-                    Ok(()) => Ok(()),
-                    Err((key, val, e)) => {
-                        self.operation.clear();
-                        Err((key, val, e))
-                    }
+                if !self.within_quota(0, value.len()) {
+                    return Err((key, value, ErrorCode::NOMEM));
                 }
+
+                let write_id = self.valid_ids.get().and_then(|perms| perms.get_write_id());
+                let stored = write_id.map(|write_id| (write_id, value.len()));
+                self.kv
+                    .add(key, value)
+                    .inspect(|()| self.change.set((None, stored)))
             }
 
             _ => Err((key, value, ErrorCode::FAIL)),
@@ -289,6 +615,41 @@ impl<'a, K: kv::KV<'a>> kv::KVPermissions<'a> for KVStorePermissions<'a, K> {
         }
     }
 
+    fn next_key(
+        &self,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+        permissions: StoragePermissions,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if self.operation.is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+
+        // We need the header to check if the caller may read the key.
+        if value.len() < HEADER_LENGTH {
+            return Err((key, value, ErrorCode::SIZE));
+        }
+
+        self.operation.set(Operation::NextKey);
+        self.valid_ids.set(permissions);
+
+        match self.kv.next_key(position, key, value) {
+            Ok(()) => Ok(()),
+            Err((key, val, e)) => {
+                self.operation.clear();
+                Err((key, val, e))
+            }
+        }
+    }
+
     fn garbage_collect(&self) -> Result<(), ErrorCode> {
         if self.operation.is_some() {
             return Err(ErrorCode::BUSY);
@@ -299,6 +660,33 @@ impl<'a, K: kv::KV<'a>> kv::KVPermissions<'a> for KVStorePermissions<'a, K> {
         self.kv.garbage_collect()
     }
 
+    fn remaining_quota(&self, permissions: StoragePermissions) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        if permissions.get_write_id().is_none() || permissions.get_quota().is_none() {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+
+        self.operation.set(Operation::RemainingQuota);
+        self.valid_ids.set(permissions);
+
+        match permissions
+            .get_write_id()
+            .and_then(|id| self.stored_bytes(id))
+        {
+            Some(bytes) => {
+                self.usage.set(bytes);
+                self.deferred_call.set();
+                Ok(())
+            }
+            None => self.start_scan().inspect_err(|_| {
+                self.operation.clear();
+            }),
+        }
+    }
+
     fn header_size(&self) -> usize {
         HEADER_LENGTH
     }
@@ -316,6 +704,11 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
                 Operation::Set => {
                     // Need to determine if we have permission to set this key.
                     let mut access_allowed = false;
+                    // Bytes of the caller's quota freed by replacing the
+                    // existing object.
+                    let mut old_length = 0;
+                    // The existing object, as `(write_id, bytes)`.
+                    let mut old = None;
 
                     if result.is_ok() || result.err() == Some(ErrorCode::SIZE) {
                         let header = KeyHeader::new_from_buf(value.as_slice());
@@ -323,7 +716,11 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
                         if header.version == HEADER_VERSION {
                             self.valid_ids.map(|perms| {
                                 access_allowed = perms.check_modify_permission(header.write_id);
+                                if perms.get_write_id() == Some(header.write_id) {
+                                    old_length = header.length as usize + HEADER_LENGTH;
+                                }
                             });
+                            old = Some((header.write_id, header.length as usize + HEADER_LENGTH));
                         }
                     } else if result.err() == Some(ErrorCode::NOSUPPORT) {
                         // Key wasn't found, so we can create it fresh.
@@ -331,8 +728,19 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
                     }
 
                     self.header_value.replace(value.take());
+                    let new_length = self.value.map_or(0, |set_value| set_value.len());
 
-                    if access_allowed {
+                    if access_allowed && !self.within_quota(old_length, new_length) {
+                        self.operation.clear();
+                        self.value.take().map(|set_value| {
+                            self.client.map(move |cb| {
+                                cb.set_complete(Err(ErrorCode::NOMEM), key, set_value);
+                            });
+                        });
+                    } else if access_allowed {
+                        let write_id = self.valid_ids.get().and_then(|perms| perms.get_write_id());
+                        self.change
+                            .set((old, write_id.map(|write_id| (write_id, new_length))));
                         self.value
                             .take()
                             .map(|set_value| match self.kv.set(key, set_value) {
@@ -357,6 +765,11 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
                 Operation::Update => {
                     // Need to determine if we have permission to set this key.
                     let mut access_allowed = false;
+                    // Bytes of the caller's quota freed by replacing the
+                    // existing object.
+                    let mut old_length = 0;
+                    // The existing object, as `(write_id, bytes)`.
+                    let mut old = None;
 
                     if result.is_ok() || result.err() == Some(ErrorCode::SIZE) {
                         let header = KeyHeader::new_from_buf(value.as_slice());
@@ -364,13 +777,28 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
                         if header.version == HEADER_VERSION {
                             self.valid_ids.map(|perms| {
                                 access_allowed = perms.check_modify_permission(header.write_id);
+                                if perms.get_write_id() == Some(header.write_id) {
+                                    old_length = header.length as usize + HEADER_LENGTH;
+                                }
                             });
+                            old = Some((header.write_id, header.length as usize + HEADER_LENGTH));
                         }
                     }
 
                     self.header_value.replace(value.take());
+                    let new_length = self.value.map_or(0, |set_value| set_value.len());
 
-                    if access_allowed {
+                    if access_allowed && !self.within_quota(old_length, new_length) {
+                        self.operation.clear();
+                        self.value.take().map(|set_value| {
+                            self.client.map(move |cb| {
+                                cb.update_complete(Err(ErrorCode::NOMEM), key, set_value);
+                            });
+                        });
+                    } else if access_allowed {
+                        let write_id = self.valid_ids.get().and_then(|perms| perms.get_write_id());
+                        self.change
+                            .set((old, write_id.map(|write_id| (write_id, new_length))));
                         self.value
                             .take()
                             .map(|set_value| match self.kv.update(key, set_value) {
@@ -399,6 +827,8 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
                     // store the full value, so a `SIZE` error code is ok and we
                     // can continue to remove the object.
                     let mut access_allowed = false;
+                    // The object being deleted, as `(write_id, bytes)`.
+                    let mut old = None;
 
                     if result.is_ok() || result.err() == Some(ErrorCode::SIZE) {
                         let header = KeyHeader::new_from_buf(value.as_slice());
@@ -407,12 +837,14 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
                             self.valid_ids.map(|perms| {
                                 access_allowed = perms.check_modify_permission(header.write_id);
                             });
+                            old = Some((header.write_id, header.length as usize + HEADER_LENGTH));
                         }
                     }
 
                     self.header_value.replace(value.take());
 
                     if access_allowed {
+                        self.change.set((old, None));
                         match self.kv.delete(key) {
                             Ok(()) => {}
 
@@ -479,6 +911,7 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
         value: SubSliceMut<'static, u8>,
     ) {
         self.operation.clear();
+        self.update_counters(result);
         self.client.map(move |cb| {
             cb.set_complete(result, key, value);
         });
@@ -491,6 +924,7 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
         value: SubSliceMut<'static, u8>,
     ) {
         self.operation.clear();
+        self.update_counters(result);
         self.client.map(move |cb| {
             cb.add_complete(result, key, value);
         });
@@ -503,6 +937,7 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
         value: SubSliceMut<'static, u8>,
     ) {
         self.operation.clear();
+        self.update_counters(result);
         self.client.map(move |cb| {
             cb.update_complete(result, key, value);
         });
@@ -510,15 +945,115 @@ impl<'a, K: kv::KV<'a>> kv::KVClient for KVStorePermissions<'a, K> {
 
     fn delete_complete(&self, result: Result<(), ErrorCode>, key: SubSliceMut<'static, u8>) {
         self.operation.clear();
+        self.update_counters(result);
         self.client.map(move |cb| {
             cb.delete_complete(result, key);
         });
     }
 
+    fn next_key_complete(
+        &self,
+        result: Result<usize, ErrorCode>,
+        position: usize,
+        mut key: SubSliceMut<'static, u8>,
+        mut value: SubSliceMut<'static, u8>,
+    ) {
+        if self.operation.get() != Some(Operation::NextKey) {
+            // We are computing the storage used by the caller for a quota
+            // check.
+            if let Ok(value_length) = result {
+                if value_length >= HEADER_LENGTH && value.len() >= HEADER_LENGTH {
+                    let header = KeyHeader::new_from_buf(value.as_slice());
+
+                    if header.version == HEADER_VERSION {
+                        self.scan_object(header.write_id, value_length);
+                    }
+                }
+            }
+
+            self.scan_key.replace(key.take());
+            self.header_value.replace(value.take());
+
+            match result {
+                Ok(_) => {
+                    if let Err(e) = self.scan_next(position) {
+                        self.scan_done(Err(e));
+                    }
+                }
+                // There are no more keys.
+                Err(ErrorCode::NOSUPPORT) => self.scan_done(Ok(())),
+                Err(e) => self.scan_done(Err(e)),
+            }
+            return;
+        }
+
+        let mut read_allowed = false;
+        let mut length = 0;
+
+        if let Ok(value_length) = result {
+            if value_length >= HEADER_LENGTH && value.len() >= HEADER_LENGTH {
+                let header = KeyHeader::new_from_buf(value.as_slice());
+
+                if header.version == HEADER_VERSION {
+                    self.valid_ids.map(|perms| {
+                        read_allowed = perms.check_read_permission(header.write_id);
+                    });
+                    length = value_length - HEADER_LENGTH;
+                }
+            }
+
+            if !read_allowed {
+                // Skip over keys the caller may not read.
+                key.reset();
+                value.reset();
+                match self.kv.next_key(position, key, value) {
+                    Ok(()) => return,
+                    Err((key, mut value, e)) => {
+                        // Do not leak the value of the key we skipped.
+                        value.as_mut_slice().iter_mut().for_each(|m| *m = 0);
+                        self.operation.clear();
+                        self.client.map(move |cb| {
+                            cb.next_key_complete(Err(e), 0, key, value);
+                        });
+                        return;
+                    }
+                }
+            }
+
+            // Remove the header from the accessible portion of the buffer.
+            value.slice(HEADER_LENGTH..);
+        }
+
+        self.operation.clear();
+        self.client.map(move |cb| {
+            cb.next_key_complete(result.map(|_| length), position, key, value);
+        });
+    }
+
     fn garbage_collection_complete(&self, result: Result<(), ErrorCode>) {
         self.operation.clear();
         self.client.map(move |cb| {
             cb.garbage_collection_complete(result);
         });
     }
+
+    fn commit_transaction_complete(&self, _result: Result<(), ErrorCode>) {
+        // Transactions are not part of the `KVPermissions` interface, so
+        // none are started.
+    }
+
+    fn remaining_quota_complete(&self, _result: Result<usize, ErrorCode>) {
+        // Quotas are not part of the `KV` interface, so none are queried.
+    }
+}
+
+impl<'a, K: kv::KV<'a>> DeferredCallClient for KVStorePermissions<'a, K> {
+    fn handle_deferred_call(&self) {
+        // The usage of the caller was already counted.
+        self.scan_complete(Ok(()));
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
 }
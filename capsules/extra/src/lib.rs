@@ -41,6 +41,10 @@ pub mod dfrobot_rainfall_sensor;
 pub mod distance;
 pub mod ethernet_tap;
 pub mod eui64;
+pub mod fat;
+pub mod filesystem;
+pub mod flash_block_device;
+pub mod flash_counter;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700cq;
@@ -56,6 +60,7 @@ pub mod ieee802154;
 pub mod isl29035;
 pub mod isolated_nonvolatile_storage_driver;
 pub mod kv_driver;
+pub mod kv_store_encryption;
 pub mod kv_store_permissions;
 pub mod l3gd20;
 pub mod led_matrix;
@@ -72,15 +77,19 @@ pub mod max17205;
 pub mod mcp230xx;
 pub mod mlx90614;
 pub mod moisture;
+pub mod monotonic_counter;
 pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage_driver;
 pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod panic_button;
+pub mod partition_table;
 pub mod pca9544a;
 pub mod pressure;
+pub mod process_fault_dump;
 pub mod process_info_driver;
+pub mod process_watchdog;
 pub mod proximity;
 pub mod public_key_crypto;
 pub mod pwm;
@@ -90,6 +99,7 @@ pub mod rf233;
 pub mod rf233_const;
 pub mod screen;
 pub mod sdcard;
+pub mod sdcard_block_device;
 pub mod servo;
 pub mod seven_segment;
 pub mod sg90;
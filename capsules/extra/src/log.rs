@@ -24,7 +24,18 @@
 //! written to a 4 page log, then page #0 will now have an offset of 2048). Thus, the ID of an
 //! entry can be calculated by taking the offset of the page within the log and adding the offset
 //! of the entry within the page to find the position of the entry within the log (which is the
-//! ID). Entries also have a header of their own, which contains the length of the entry.
+//! ID). Entries also have a header of their own, which contains the length of the entry and a
+//! CRC32 of the length and the entry's data.
+//!
+//! The page header also contains a version marker after the page's offset. Pages written before
+//! the marker was added have a header with only the offset, and entry headers without a CRC. Such
+//! pages are still read after an upgrade, but new entries are always appended to a new page with
+//! the current layout, so the older pages are replaced as the log wraps around or is erased.
+//!
+//! When the log is reconstructed at startup, every entry is checked against its CRC. A write that
+//! was interrupted (e.g. by losing power) can leave a partially written entry behind, so the log is
+//! truncated at the first invalid entry. The number of bytes discarded this way is reported by
+//! `discarded_bytes()`.
 //!
 //! Logs support the following basic operations:
 //!     * Read:     Read back previously written entries in whole. Entries are read in their
@@ -71,18 +82,57 @@ use kernel::hil::flash::{self, Flash};
 use kernel::hil::log::{LogRead, LogReadClient, LogWrite, LogWriteClient};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::ErrorCode;
+use tickv::crc32::Crc32;
 
 /// Globally declare entry ID type.
 type EntryID = usize;
 
 /// Maximum page header size.
-pub const PAGE_HEADER_SIZE: usize = size_of::<EntryID>();
+pub const PAGE_HEADER_SIZE: usize = size_of::<EntryID>() + size_of::<u32>();
 /// Maximum entry header size.
-pub const ENTRY_HEADER_SIZE: usize = size_of::<usize>();
+pub const ENTRY_HEADER_SIZE: usize = size_of::<usize>() + size_of::<u32>();
+
+/// Version marker stored after the page ID in the page header. Unversioned pages have the length
+/// of their first entry there, which can never have this value as it is larger than any page.
+const PAGE_VERSION: u32 = 0x4C4F_4731;
 
 /// Byte used to pad the end of a page.
 const PAD_BYTE: u8 = 0xFF;
 
+/// Computes the CRC stored in the header of an entry.
+fn entry_crc(length: usize, data: &[u8]) -> u32 {
+    let crc = Crc32::new();
+    crc.update(&length.to_ne_bytes());
+    crc.update(data);
+    crc.finalise()
+}
+
+/// Layout of a page, depending on the version of the log that wrote it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum PageFormat {
+    /// Written before page headers had a version marker: the page header is only the page ID, and
+    /// entry headers are only the entry length.
+    Unversioned,
+    /// The page header ends with `PAGE_VERSION`, and entry headers have a CRC.
+    Versioned,
+}
+
+impl PageFormat {
+    const fn page_header_size(self) -> usize {
+        match self {
+            PageFormat::Unversioned => size_of::<EntryID>(),
+            PageFormat::Versioned => PAGE_HEADER_SIZE,
+        }
+    }
+
+    const fn entry_header_size(self) -> usize {
+        match self {
+            PageFormat::Unversioned => size_of::<usize>(),
+            PageFormat::Versioned => ENTRY_HEADER_SIZE,
+        }
+    }
+}
+
 /// Log state keeps track of any in-progress asynchronous operations.
 #[derive(Clone, Copy, PartialEq)]
 enum State {
@@ -120,6 +170,11 @@ pub struct Log<'a, F: Flash + 'static> {
     read_entry_id: Cell<EntryID>,
     /// Entry ID of next entry to append.
     append_entry_id: Cell<EntryID>,
+    /// Number of bytes discarded when reconstructing the log.
+    discarded_bytes: Cell<usize>,
+    /// Pages with an ID below this were found without a version marker when reconstructing the
+    /// log.
+    unversioned_end: Cell<EntryID>,
 
     /// Deferred call for deferring client callbacks.
     deferred_call: DeferredCall,
@@ -158,6 +213,8 @@ impl<'a, F: Flash + 'static> Log<'a, F> {
             oldest_entry_id: Cell::new(PAGE_HEADER_SIZE),
             read_entry_id: Cell::new(PAGE_HEADER_SIZE),
             append_entry_id: Cell::new(PAGE_HEADER_SIZE),
+            discarded_bytes: Cell::new(0),
+            unversioned_end: Cell::new(0),
             deferred_call: DeferredCall::new(),
             buffer: TakeCell::empty(),
             length: Cell::new(0),
@@ -200,8 +257,23 @@ impl<'a, F: Flash + 'static> Log<'a, F> {
         &buffer[offset..offset + num_bytes]
     }
 
+    /// Returns the layout of the page containing the given position in the log.
+    fn page_format(&self, pos: EntryID) -> PageFormat {
+        if pos < self.unversioned_end.get() {
+            PageFormat::Unversioned
+        } else {
+            PageFormat::Versioned
+        }
+    }
+
+    /// Returns the ID of the first entry in the page starting at the given position in the log.
+    fn first_entry_id(&self, page_id: EntryID) -> EntryID {
+        page_id + self.page_format(page_id).page_header_size()
+    }
+
     /// Resets a log back to an empty log. Returns whether or not the log was reset successfully.
     fn reset(&self) -> bool {
+        self.unversioned_end.set(0);
         self.oldest_entry_id.set(PAGE_HEADER_SIZE);
         self.read_entry_id.set(PAGE_HEADER_SIZE);
         self.append_entry_id.set(PAGE_HEADER_SIZE);
@@ -209,23 +281,80 @@ impl<'a, F: Flash + 'static> Log<'a, F> {
             for e in pagebuffer.as_mut().iter_mut() {
                 *e = 0;
             }
+            self.write_page_header(0, pagebuffer);
             self.pagebuffer.replace(pagebuffer);
             true
         })
     }
 
+    /// Reads the page ID from the header of the page at the given position in the volume.
+    fn read_page_id(&self, header_pos: usize) -> EntryID {
+        const ID_SIZE: usize = size_of::<EntryID>();
+        let id_bytes = &self.volume[header_pos..header_pos + ID_SIZE];
+        let id_bytes = <[u8; ID_SIZE]>::try_from(id_bytes).unwrap();
+        usize::from_ne_bytes(id_bytes)
+    }
+
+    /// Reads the layout of the page at the given position in the volume from its header.
+    fn read_page_format(&self, header_pos: usize) -> PageFormat {
+        let marker_bytes = &self.volume[header_pos + size_of::<EntryID>()..][..size_of::<u32>()];
+        if u32::from_ne_bytes(<[u8; 4]>::try_from(marker_bytes).unwrap()) == PAGE_VERSION {
+            PageFormat::Versioned
+        } else {
+            PageFormat::Unversioned
+        }
+    }
+
+    /// Walks the entries of a page in flash, checking each against its CRC. Returns the length of
+    /// the valid part of the page (including the page header) and whether the page ended cleanly,
+    /// i.e. it is full or the rest of it is padding rather than an invalid entry.
+    ///
+    /// Unversioned pages have no CRCs, so their entries are only checked to fit within the page,
+    /// and they end at the first entry starting with a zero or padding byte.
+    fn scan_page(&self, page_id: EntryID, format: PageFormat) -> (usize, bool) {
+        let page = &self.volume[page_id % self.volume.len()..][..self.page_size];
+        let entry_header_size = format.entry_header_size();
+        let mut page_len = format.page_header_size();
+        while page_len + entry_header_size <= self.page_size {
+            // Padding (or a never written header) marks the end of the entries in the page.
+            const LENGTH_SIZE: usize = size_of::<usize>();
+            let length_bytes = &page[page_len..page_len + LENGTH_SIZE];
+            let end = match format {
+                PageFormat::Unversioned => length_bytes[0] == PAD_BYTE || length_bytes[0] == 0,
+                PageFormat::Versioned => {
+                    length_bytes.iter().all(|byte| *byte == PAD_BYTE)
+                        || length_bytes.iter().all(|byte| *byte == 0)
+                }
+            };
+            if end {
+                break;
+            }
+            let length = usize::from_ne_bytes(<[u8; LENGTH_SIZE]>::try_from(length_bytes).unwrap());
+
+            // The entry must fit within the remainder of the page and match its CRC.
+            let data_pos = page_len + entry_header_size;
+            if length > self.page_size - data_pos {
+                return (page_len, false);
+            }
+            if format == PageFormat::Versioned {
+                let crc_bytes = &page[page_len + LENGTH_SIZE..data_pos];
+                let crc = u32::from_ne_bytes(<[u8; 4]>::try_from(crc_bytes).unwrap());
+                if crc != entry_crc(length, &page[data_pos..data_pos + length]) {
+                    return (page_len, false);
+                }
+            }
+            page_len = data_pos + length;
+        }
+        (page_len, true)
+    }
+
     /// Reconstructs a log from flash.
     fn reconstruct(&self) {
         // Read page headers, get IDs of oldest and newest pages.
         let mut oldest_page_id: EntryID = usize::MAX;
         let mut newest_page_id: EntryID = 0;
         for header_pos in (0..self.volume.len()).step_by(self.page_size) {
-            let page_id = {
-                const ID_SIZE: usize = size_of::<EntryID>();
-                let id_bytes = &self.volume[header_pos..header_pos + ID_SIZE];
-                let id_bytes = <[u8; ID_SIZE]>::try_from(id_bytes).unwrap();
-                usize::from_ne_bytes(id_bytes)
-            };
+            let page_id = self.read_page_id(header_pos);
 
             // Validate page ID read from header.
             if page_id % self.volume.len() == header_pos {
@@ -241,45 +370,70 @@ impl<'a, F: Flash + 'static> Log<'a, F> {
         // Reconstruct log if at least one valid page was found (meaning oldest page ID was set to
         // something not usize::MAX).
         if oldest_page_id != usize::MAX {
-            // Walk entries in last (newest) page to calculate last page length.
-            let mut last_page_len = PAGE_HEADER_SIZE;
-            loop {
-                // Check if next byte is start of valid entry.
-                let volume_offset = newest_page_id % self.volume.len() + last_page_len;
-                if self.volume[volume_offset] == 0 || self.volume[volume_offset] == PAD_BYTE {
-                    break;
+            // Walk entries from the oldest page onwards, stopping at the first invalid entry or
+            // page. Everything after that point is discarded. Unversioned pages can only come
+            // before versioned pages, as they were written by an older version of the log.
+            let mut page_id = oldest_page_id;
+            let mut format = self.read_page_format(page_id % self.volume.len());
+            let last_page_len = loop {
+                let (page_len, complete) = self.scan_page(page_id, format);
+                let next_page_id = page_id + self.page_size;
+                let next_pos = next_page_id % self.volume.len();
+                let next_format = self.read_page_format(next_pos);
+                if !complete
+                    || page_id == newest_page_id
+                    || self.read_page_id(next_pos) != next_page_id
+                    || (format, next_format) == (PageFormat::Versioned, PageFormat::Unversioned)
+                {
+                    break page_len;
+                }
+                if format == PageFormat::Unversioned && next_format == PageFormat::Versioned {
+                    self.unversioned_end.set(next_page_id);
                 }
+                page_id = next_page_id;
+                format = next_format;
+            };
+            if format == PageFormat::Unversioned {
+                self.unversioned_end.set(page_id + self.page_size);
+            }
 
-                // Get next entry length.
-                let entry_length = {
-                    const LENGTH_SIZE: usize = size_of::<usize>();
-                    let length_bytes = &self.volume[volume_offset..volume_offset + LENGTH_SIZE];
-                    let length_bytes = <[u8; LENGTH_SIZE]>::try_from(length_bytes).unwrap();
-                    usize::from_ne_bytes(length_bytes)
-                } + ENTRY_HEADER_SIZE;
-
-                // Add to page length if length is valid (fits within remainder of page.
-                if last_page_len + entry_length <= self.page_size {
-                    last_page_len += entry_length;
-                    if last_page_len == self.page_size {
-                        break;
-                    }
+            // Count the bytes written after the end of the log, ignoring page headers and
+            // padding.
+            let mut discarded_bytes = 0;
+            for discarded_page_id in (page_id..=newest_page_id).step_by(self.page_size) {
+                let pos = discarded_page_id % self.volume.len();
+                let page = &self.volume[pos..][..self.page_size];
+                let page_end = page
+                    .iter()
+                    .rposition(|byte| *byte != PAD_BYTE)
+                    .map_or(0, |pos| pos + 1);
+                let discarded_from = if discarded_page_id == page_id {
+                    last_page_len
                 } else {
-                    break;
-                }
+                    self.read_page_format(pos).page_header_size()
+                };
+                discarded_bytes += page_end.saturating_sub(discarded_from);
             }
+            self.discarded_bytes.set(discarded_bytes);
+
+            // New entries are never appended to an unversioned page, so it is treated as full.
+            let append_len = match format {
+                PageFormat::Unversioned => self.page_size,
+                PageFormat::Versioned => last_page_len,
+            };
 
             // Set tracked entry IDs.
-            self.oldest_entry_id.set(oldest_page_id + PAGE_HEADER_SIZE);
-            self.read_entry_id.set(oldest_page_id + PAGE_HEADER_SIZE);
-            self.append_entry_id.set(newest_page_id + last_page_len);
+            self.oldest_entry_id
+                .set(self.first_entry_id(oldest_page_id));
+            self.read_entry_id.set(self.first_entry_id(oldest_page_id));
+            self.append_entry_id.set(page_id + append_len);
 
             // Populate page buffer.
             self.pagebuffer
                 .take()
                 .map(move |pagebuffer| {
                     // Determine if pagebuffer should be reset or copied from flash.
-                    let mut copy_pagebuffer = last_page_len % self.page_size != 0;
+                    let mut copy_pagebuffer = append_len % self.page_size != 0;
                     if !copy_pagebuffer {
                         // Last page full, reset pagebuffer for next page.
                         copy_pagebuffer = !self.reset_pagebuffer(pagebuffer);
@@ -287,8 +441,7 @@ impl<'a, F: Flash + 'static> Log<'a, F> {
                     if copy_pagebuffer {
                         // Copy last page into pagebuffer.
                         for i in 0..self.page_size {
-                            pagebuffer.as_mut()[i] =
-                                self.volume[newest_page_id % self.volume.len() + i];
+                            pagebuffer.as_mut()[i] = self.volume[page_id % self.volume.len() + i];
                         }
                     }
                     self.pagebuffer.replace(pagebuffer);
@@ -310,11 +463,13 @@ impl<'a, F: Flash + 'static> Log<'a, F> {
             .map_or(Err(Err(ErrorCode::RESERVE)), move |pagebuffer| {
                 let mut entry_id = self.read_entry_id.get();
 
-                // Skip page header if at start of page or skip padded bytes if at end of page.
+                // Skip padded bytes if at end of page, and skip page header if at start of page.
+                if entry_id % self.page_size != 0 && self.get_byte(entry_id, pagebuffer) == PAD_BYTE
+                {
+                    entry_id += self.page_size - entry_id % self.page_size;
+                }
                 if entry_id % self.page_size == 0 {
-                    entry_id += PAGE_HEADER_SIZE;
-                } else if self.get_byte(entry_id, pagebuffer) == PAD_BYTE {
-                    entry_id += self.page_size - entry_id % self.page_size + PAGE_HEADER_SIZE;
+                    entry_id = self.first_entry_id(entry_id);
                 }
 
                 // Check if end of log was reached and return.
@@ -344,7 +499,11 @@ impl<'a, F: Flash + 'static> Log<'a, F> {
 
                 // Return length of next entry.
                 self.pagebuffer.replace(pagebuffer);
-                if length == 0 || length > self.page_size - PAGE_HEADER_SIZE - ENTRY_HEADER_SIZE {
+                let format = self.page_format(entry_id);
+                if length == 0
+                    || length
+                        > self.page_size - format.page_header_size() - format.entry_header_size()
+                {
                     Err(Err(ErrorCode::FAIL))
                 } else {
                     Ok(length)
@@ -372,7 +531,7 @@ impl<'a, F: Flash + 'static> Log<'a, F> {
                     self.pagebuffer.replace(pagebuffer);
                     return Err(Err(ErrorCode::SIZE));
                 }
-                let entry_id = entry_id + ENTRY_HEADER_SIZE;
+                let entry_id = entry_id + self.page_format(entry_id).entry_header_size();
 
                 // Copy data into client buffer.
                 let data = self.get_bytes(entry_id, entry_length, pagebuffer);
@@ -385,11 +544,23 @@ impl<'a, F: Flash + 'static> Log<'a, F> {
             })
     }
 
+    /// Writes the header of the page with the given ID to the start of a page.
+    fn write_page_header(&self, page_id: EntryID, pagebuffer: &mut F::Page) {
+        let header = page_id
+            .to_ne_bytes()
+            .into_iter()
+            .chain(PAGE_VERSION.to_ne_bytes());
+        for (offset, byte) in header.enumerate() {
+            pagebuffer.as_mut()[offset] = byte;
+        }
+    }
+
     /// Writes an entry header at the given position within a page. Must write at most
     /// ENTRY_HEADER_SIZE bytes.
-    fn write_entry_header(&self, length: usize, pos: usize, pagebuffer: &mut F::Page) {
-        for (offset, byte) in length.to_ne_bytes().iter().enumerate() {
-            pagebuffer.as_mut()[pos + offset] = *byte;
+    fn write_entry_header(&self, length: usize, crc: u32, pos: usize, pagebuffer: &mut F::Page) {
+        let header = length.to_ne_bytes().into_iter().chain(crc.to_ne_bytes());
+        for (offset, byte) in header.enumerate() {
+            pagebuffer.as_mut()[pos + offset] = byte;
         }
     }
 
@@ -406,7 +577,8 @@ impl<'a, F: Flash + 'static> Log<'a, F> {
         let mut page_offset = append_entry_id % self.page_size;
 
         // Write entry header to pagebuffer.
-        self.write_entry_header(length, page_offset, pagebuffer);
+        let crc = entry_crc(length, &buffer[..length]);
+        self.write_entry_header(length, crc, page_offset, pagebuffer);
         page_offset += ENTRY_HEADER_SIZE;
 
         // Copy data to pagebuffer.
@@ -420,7 +592,7 @@ impl<'a, F: Flash + 'static> Log<'a, F> {
         self.pagebuffer.replace(pagebuffer);
         self.buffer.replace(buffer);
         self.records_lost
-            .set(self.oldest_entry_id.get() != PAGE_HEADER_SIZE);
+            .set(self.oldest_entry_id.get() >= self.page_size);
         self.error.set(Ok(()));
         self.client_callback();
     }
@@ -450,13 +622,17 @@ impl<'a, F: Flash + 'static> Log<'a, F> {
         if read_entry_id / self.page_size == overwritten_page {
             // Move read entry ID to start of next page.
             self.read_entry_id.set(
-                read_entry_id + self.page_size + PAGE_HEADER_SIZE - read_entry_id % self.page_size,
+                self.first_entry_id(
+                    read_entry_id + self.page_size - read_entry_id % self.page_size,
+                ),
             );
         }
 
         let oldest_entry_id = self.oldest_entry_id.get();
         if oldest_entry_id / self.page_size == overwritten_page {
-            self.oldest_entry_id.set(oldest_entry_id + self.page_size);
+            self.oldest_entry_id.set(self.first_entry_id(
+                oldest_entry_id + self.page_size - oldest_entry_id % self.page_size,
+            ));
         }
 
         // Sync page to flash.
@@ -486,8 +662,7 @@ impl<'a, F: Flash + 'static> Log<'a, F> {
         }
 
         // Write page header to pagebuffer.
-        let id_bytes = append_entry_id.to_ne_bytes();
-        pagebuffer.as_mut()[..id_bytes.len()].copy_from_slice(&id_bytes[..]);
+        self.write_page_header(append_entry_id, pagebuffer);
 
         // Note: this is the only place where the append entry ID can cross page boundaries.
         self.append_entry_id.set(append_entry_id + PAGE_HEADER_SIZE);
@@ -653,6 +828,11 @@ impl<'a, F: Flash + 'static> LogRead<'a> for Log<'a, F> {
     fn get_size(&self) -> usize {
         self.capacity
     }
+
+    /// Get the number of bytes discarded because of invalid entries when the log was reconstructed.
+    fn discarded_bytes(&self) -> usize {
+        self.discarded_bytes.get()
+    }
 }
 
 impl<'a, F: Flash + 'static> LogWrite<'a> for Log<'a, F> {
@@ -894,3 +1074,233 @@ impl<F: Flash + 'static> DeferredCallClient for Log<'_, F> {
         self.deferred_call.register(self);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    extern crate std;
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    const PAGE_SIZE: usize = 256;
+    const PAGES: usize = 4;
+
+    struct TestPage([u8; PAGE_SIZE]);
+
+    impl Default for TestPage {
+        fn default() -> Self {
+            Self([0; PAGE_SIZE])
+        }
+    }
+
+    impl AsMut<[u8]> for TestPage {
+        fn as_mut(&mut self) -> &mut [u8] {
+            &mut self.0
+        }
+    }
+
+    /// Flash that is never accessed, as reconstructing a log reads the volume directly.
+    struct TestFlash;
+
+    impl Flash for TestFlash {
+        type Page = TestPage;
+
+        fn read_page(
+            &self,
+            _page_number: usize,
+            buf: &'static mut Self::Page,
+        ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+            Err((ErrorCode::FAIL, buf))
+        }
+
+        fn write_page(
+            &self,
+            _page_number: usize,
+            buf: &'static mut Self::Page,
+        ) -> Result<(), (ErrorCode, &'static mut Self::Page)> {
+            Err((ErrorCode::FAIL, buf))
+        }
+
+        fn erase_page(&self, _page_number: usize) -> Result<(), ErrorCode> {
+            Err(ErrorCode::FAIL)
+        }
+    }
+
+    /// Writes a page with the given ID and entries to the volume, padded like a flushed page.
+    /// Returns the ID of each entry.
+    fn write_page(volume: &mut [u8], page_id: usize, entries: &[&[u8]]) -> Vec<usize> {
+        let page = &mut volume[page_id % (PAGE_SIZE * PAGES)..][..PAGE_SIZE];
+        page.fill(PAD_BYTE);
+        page[..size_of::<usize>()].copy_from_slice(&page_id.to_ne_bytes());
+        page[size_of::<usize>()..PAGE_HEADER_SIZE].copy_from_slice(&PAGE_VERSION.to_ne_bytes());
+
+        let mut pos = PAGE_HEADER_SIZE;
+        let mut ids = Vec::new();
+        for entry in entries {
+            ids.push(page_id + pos);
+            let crc = entry_crc(entry.len(), entry);
+            page[pos..pos + size_of::<usize>()].copy_from_slice(&entry.len().to_ne_bytes());
+            page[pos + size_of::<usize>()..pos + ENTRY_HEADER_SIZE]
+                .copy_from_slice(&crc.to_ne_bytes());
+            page[pos + ENTRY_HEADER_SIZE..][..entry.len()].copy_from_slice(entry);
+            pos += ENTRY_HEADER_SIZE + entry.len();
+        }
+        ids.push(page_id + pos);
+        ids
+    }
+
+    /// Writes a page in the layout used before pages had a version marker: the page header is
+    /// only the page ID and entry headers are only the length. Returns the ID of each entry.
+    fn write_unversioned_page(volume: &mut [u8], page_id: usize, entries: &[&[u8]]) -> Vec<usize> {
+        let page = &mut volume[page_id % (PAGE_SIZE * PAGES)..][..PAGE_SIZE];
+        page.fill(PAD_BYTE);
+        page[..size_of::<usize>()].copy_from_slice(&page_id.to_ne_bytes());
+
+        let mut pos = size_of::<usize>();
+        let mut ids = Vec::new();
+        for entry in entries {
+            ids.push(page_id + pos);
+            page[pos..pos + size_of::<usize>()].copy_from_slice(&entry.len().to_ne_bytes());
+            pos += size_of::<usize>();
+            page[pos..pos + entry.len()].copy_from_slice(entry);
+            pos += entry.len();
+        }
+        ids.push(page_id + pos);
+        ids
+    }
+
+    /// Reads the remaining entries of the log.
+    fn read_all(log: &Log<'_, TestFlash>) -> Vec<Vec<u8>> {
+        let mut entries = Vec::new();
+        let mut buffer = [0; PAGE_SIZE];
+        while let Ok(length) = log.read_entry(&mut buffer, PAGE_SIZE) {
+            entries.push(buffer[..length].to_vec());
+        }
+        entries
+    }
+
+    struct TestAppendClient;
+
+    impl LogWriteClient for TestAppendClient {
+        fn append_done(
+            &self,
+            _buffer: &'static mut [u8],
+            _length: usize,
+            _records_lost: bool,
+            error: Result<(), ErrorCode>,
+        ) {
+            assert_eq!(error, Ok(()));
+        }
+
+        fn sync_done(&self, _error: Result<(), ErrorCode>) {}
+
+        fn erase_done(&self, _error: Result<(), ErrorCode>) {}
+    }
+
+    fn open(volume: Vec<u8>) -> Log<'static, TestFlash> {
+        let volume: &'static [u8] = Box::leak(volume.into_boxed_slice());
+        let pagebuffer = Box::leak(Box::new(TestPage::default()));
+        Log::new(volume, &TestFlash, pagebuffer, true)
+    }
+
+    #[test]
+    fn intact_log() {
+        let mut volume = vec![PAD_BYTE; PAGE_SIZE * PAGES];
+        write_page(&mut volume, 0, &[&[1; 100], &[2; 100]]);
+        let ids = write_page(&mut volume, PAGE_SIZE, &[&[3; 20], &[4; 30]]);
+
+        let log = open(volume);
+        assert_eq!(log.log_start(), PAGE_HEADER_SIZE);
+        assert_eq!(log.log_end(), ids[2]);
+        assert_eq!(log.discarded_bytes(), 0);
+    }
+
+    #[test]
+    fn torn_entry_in_newest_page() {
+        let mut volume = vec![PAD_BYTE; PAGE_SIZE * PAGES];
+        write_page(&mut volume, 0, &[&[1; 100], &[2; 100]]);
+        let ids = write_page(&mut volume, PAGE_SIZE, &[&[3; 20], &[4; 30], &[5; 40]]);
+        // The last entry was only partially written.
+        volume[ids[2] + ENTRY_HEADER_SIZE + 10..ids[3]].fill(PAD_BYTE);
+
+        let log = open(volume);
+        assert_eq!(log.log_start(), PAGE_HEADER_SIZE);
+        assert_eq!(log.log_end(), ids[2]);
+        assert_eq!(log.discarded_bytes(), ENTRY_HEADER_SIZE + 10);
+    }
+
+    #[test]
+    fn corrupt_entry_discards_later_pages() {
+        let mut volume = vec![PAD_BYTE; PAGE_SIZE * PAGES];
+        // Circular log that has wrapped around, the oldest page is page 2.
+        write_page(&mut volume, 2 * PAGE_SIZE, &[&[1; 100]]);
+        let ids = write_page(&mut volume, 3 * PAGE_SIZE, &[&[2; 100], &[3; 100]]);
+        let newest = write_page(&mut volume, 4 * PAGE_SIZE, &[&[4; 50]]);
+        volume[(ids[1] + ENTRY_HEADER_SIZE) % (PAGE_SIZE * PAGES)] ^= 1;
+
+        let log = open(volume);
+        assert_eq!(log.log_start(), 2 * PAGE_SIZE + PAGE_HEADER_SIZE);
+        assert_eq!(log.log_end(), ids[1]);
+        assert_eq!(
+            log.discarded_bytes(),
+            (ids[2] - ids[1]) + (newest[1] - newest[0])
+        );
+    }
+
+    #[test]
+    fn empty_volume() {
+        let log = open(vec![PAD_BYTE; PAGE_SIZE * PAGES]);
+        assert_eq!(log.log_end(), PAGE_HEADER_SIZE);
+        assert_eq!(log.discarded_bytes(), 0);
+    }
+
+    #[test]
+    fn unversioned_volume() {
+        let mut volume = vec![PAD_BYTE; PAGE_SIZE * PAGES];
+        let first = write_unversioned_page(&mut volume, 0, &[&[1; 100], &[2; 100]]);
+        write_unversioned_page(&mut volume, PAGE_SIZE, &[&[3; 20], &[4; 30]]);
+
+        let log = Box::leak(Box::new(open(volume)));
+        assert_eq!(log.log_start(), first[0]);
+        assert_eq!(log.discarded_bytes(), 0);
+        // The newest page is not appended to, new entries go to a versioned page.
+        assert_eq!(log.log_end(), 2 * PAGE_SIZE + PAGE_HEADER_SIZE);
+        assert_eq!(
+            read_all(log),
+            [vec![1; 100], vec![2; 100], vec![3; 20], vec![4; 30]]
+        );
+
+        log.set_append_client(&TestAppendClient);
+        let entry = Box::leak(Box::new([5; 40]));
+        assert!(log.append(entry, 40).is_ok());
+        assert_eq!(read_all(log), [vec![5; 40]]);
+        assert!(log.seek(log.log_start()).is_ok());
+        assert_eq!(read_all(log).len(), 5);
+    }
+
+    #[test]
+    fn unversioned_pages_before_versioned_pages() {
+        let mut volume = vec![PAD_BYTE; PAGE_SIZE * PAGES];
+        // A circular log that was upgraded after wrapping around.
+        let oldest = write_unversioned_page(&mut volume, 3 * PAGE_SIZE, &[&[1; 100]]);
+        write_unversioned_page(&mut volume, 4 * PAGE_SIZE, &[&[2; 60], &[3; 60]]);
+        let ids = write_page(&mut volume, 5 * PAGE_SIZE, &[&[4; 50], &[5; 50]]);
+
+        let log = open(volume);
+        assert_eq!(log.log_start(), oldest[0]);
+        assert_eq!(log.log_end(), ids[2]);
+        assert_eq!(log.discarded_bytes(), 0);
+        assert_eq!(
+            read_all(&log),
+            [
+                vec![1; 100],
+                vec![2; 60],
+                vec![3; 60],
+                vec![4; 50],
+                vec![5; 50]
+            ]
+        );
+    }
+}
@@ -0,0 +1,198 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Userspace access to persistent monotonic counters.
+//!
+//! Each app has its own set of counters, selected by an index. Counters are
+//! stored by the app's `ShortId`, so they persist across reboots and updates
+//! of the app, and an app without a fixed `ShortId` cannot use this driver.
+//! Indices of `0x8000_0000` and above are reserved for counters the kernel
+//! keeps about the app.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let monotonic_counter = components::monotonic_counter::MonotonicCounterComponent::new(
+//!     board_kernel,
+//!     capsules_extra::monotonic_counter::DRIVER_NUM,
+//!     flash_counter,
+//! )
+//! .finalize(components::monotonic_counter_component_static!(
+//!     components::monotonic_counter::FlashCounterComponentType<nrf52840::nvmc::Nvmc>
+//! ));
+//! ```
+//!
+//! Command
+//! -------
+//!
+//! - `0`: Check if the driver is present.
+//! - `1`: Get the value of counter `data1`, returned as a `u64`.
+//! - `2`: Increment counter `data1`.
+//! - `3`: Advance counter `data1` to at least `data2`.
+//!
+//! Increments and advances take effect immediately, and upcall 0 is called
+//! with `(status, value low, value high)` once the new value of the counter is
+//! persistent. Each app can have one operation waiting for its upcall.
+
+use core::cell::Cell;
+
+use kernel::errorcode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::counter::{MonotonicCounter, MonotonicCounterClient};
+use kernel::process::ShortId;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::MonotonicCounter as usize;
+
+/// Counter indices from this one on are reserved for the kernel.
+const RESERVED_INDICES: usize = 0x8000_0000;
+
+/// IDs for upcalls.
+mod upcalls {
+    /// Called when an update is persistent.
+    pub const STORED: usize = 0;
+    /// The number of upcalls the kernel stores for this grant.
+    pub const COUNT: u8 = 1;
+}
+
+/// Contents of the grant for each app.
+#[derive(Default)]
+pub struct App {
+    /// Counter index the app is waiting on to be stored.
+    pending: Option<u32>,
+}
+
+pub struct MonotonicCounterDriver<'a, C: MonotonicCounter<'a>> {
+    counter: &'a C,
+    apps: Grant<App, UpcallCount<{ upcalls::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    /// Whether updates made by this driver are not yet persistent.
+    storing: Cell<bool>,
+}
+
+impl<'a, C: MonotonicCounter<'a>> MonotonicCounterDriver<'a, C> {
+    pub fn new(
+        counter: &'a C,
+        grant: Grant<App, UpcallCount<{ upcalls::COUNT }>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> Self {
+        Self {
+            counter,
+            apps: grant,
+            storing: Cell::new(false),
+        }
+    }
+
+    /// Returns the key of counter `index` of the app.
+    fn key(processid: ProcessId, index: usize) -> Result<u64, ErrorCode> {
+        let ShortId::Fixed(id) = processid.short_app_id() else {
+            return Err(ErrorCode::NOSUPPORT);
+        };
+        if index >= RESERVED_INDICES {
+            return Err(ErrorCode::INVAL);
+        }
+        Ok((u64::from(id.get()) << 32) | index as u64)
+    }
+
+    /// Run an update of counter `index`, and record that the app waits for
+    /// it to be stored.
+    fn update(
+        &self,
+        processid: ProcessId,
+        index: usize,
+        update: impl FnOnce(u64) -> Result<(), ErrorCode>,
+    ) -> Result<(), ErrorCode> {
+        let key = Self::key(processid, index)?;
+        self.apps
+            .enter(processid, |app, upcalls| {
+                if app.pending.is_some() {
+                    return Err(ErrorCode::BUSY);
+                }
+                let before = self.counter.get(key);
+                update(key)?;
+                if self.counter.get(key) != before {
+                    self.storing.set(true);
+                }
+                if self.storing.get() {
+                    app.pending = Some(index as u32);
+                } else {
+                    // Nothing changed and nothing is being stored, so the
+                    // value is already persistent.
+                    let value = self.counter.get(key);
+                    let _ = upcalls.schedule_upcall(
+                        upcalls::STORED,
+                        (0, value as usize, (value >> 32) as usize),
+                    );
+                }
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+}
+
+impl<'a, C: MonotonicCounter<'a>> MonotonicCounterClient for MonotonicCounterDriver<'a, C> {
+    fn updates_stored(&self, result: Result<(), ErrorCode>) {
+        self.storing.set(false);
+        for app in self.apps.iter() {
+            let processid = app.processid();
+            app.enter(|app, upcalls| {
+                if let Some(index) = app.pending.take() {
+                    let value =
+                        Self::key(processid, index as usize).map_or(0, |key| self.counter.get(key));
+                    let _ = upcalls.schedule_upcall(
+                        upcalls::STORED,
+                        (
+                            errorcode::into_statuscode(result),
+                            value as usize,
+                            (value >> 32) as usize,
+                        ),
+                    );
+                }
+            });
+        }
+    }
+}
+
+impl<'a, C: MonotonicCounter<'a>> SyscallDriver for MonotonicCounterDriver<'a, C> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            // check if present
+            0 => CommandReturn::success(),
+
+            // get
+            1 => match Self::key(processid, data1) {
+                Ok(key) => CommandReturn::success_u64(self.counter.get(key)),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            // increment
+            2 => self
+                .update(processid, data1, |key| {
+                    self.counter.increment(key).map(|_| ())
+                })
+                .into(),
+
+            // advance
+            3 => self
+                .update(processid, data1, |key| {
+                    self.counter.advance(key, data2 as u64)
+                })
+                .into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
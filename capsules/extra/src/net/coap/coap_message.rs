@@ -0,0 +1,602 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! CoAP message encoding and decoding (RFC 7252 section 3), with the Block1
+//! and Block2 options of block-wise transfers (RFC 7959).
+//!
+//! ```text
+//!  0                   1                   2                   3
+//!  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |Ver| T |  TKL  |      Code     |          Message ID           |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |   Token (if any, TKL bytes) ...
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |   Options (if any) ...
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |1 1 1 1 1 1 1 1|    Payload (if any) ...
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! ```
+
+use kernel::ErrorCode;
+
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 4;
+pub const MAX_TOKEN_LEN: usize = 8;
+const PAYLOAD_MARKER: u8 = 0xff;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MessageType {
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+/// Message codes, as `class << 5 | detail`.
+pub mod code {
+    pub const EMPTY: u8 = 0x00;
+    pub const GET: u8 = 0x01;
+    pub const DELETE: u8 = 0x04;
+    /// 2.31 Continue
+    pub const CONTINUE: u8 = 0x5f;
+    /// 4.00 Bad Request
+    pub const BAD_REQUEST: u8 = 0x80;
+    /// 4.02 Bad Option
+    pub const BAD_OPTION: u8 = 0x82;
+    /// 4.04 Not Found
+    pub const NOT_FOUND: u8 = 0x84;
+    /// 4.08 Request Entity Incomplete
+    pub const REQUEST_ENTITY_INCOMPLETE: u8 = 0x88;
+    /// 4.13 Request Entity Too Large
+    pub const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d;
+    /// 5.03 Service Unavailable
+    pub const SERVICE_UNAVAILABLE: u8 = 0xa3;
+
+    pub fn is_request(code: u8) -> bool {
+        (GET..=DELETE).contains(&code)
+    }
+
+    pub fn is_response(code: u8) -> bool {
+        code >> 5 >= 2
+    }
+
+    pub fn is_success(code: u8) -> bool {
+        code >> 5 == 2
+    }
+}
+
+/// Option numbers.
+pub mod option {
+    pub const URI_HOST: u16 = 3;
+    pub const URI_PORT: u16 = 7;
+    pub const URI_PATH: u16 = 11;
+    pub const BLOCK2: u16 = 23;
+    pub const BLOCK1: u16 = 27;
+
+    /// Whether a message with an option it does not know must be rejected.
+    pub fn is_critical(number: u16) -> bool {
+        number & 1 == 1
+    }
+}
+
+/// Returns the segments of `path`, a path with segments separated by `/`.
+pub fn path_segments(path: &[u8]) -> impl Iterator<Item = &[u8]> {
+    path.split(|b| *b == b'/').filter(|s| !s.is_empty())
+}
+
+/// Value of a Block1 or Block2 option.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Block {
+    pub num: u32,
+    pub more: bool,
+    /// Size exponent, the block size is `16 << szx`.
+    pub szx: u8,
+}
+
+impl Block {
+    pub fn size(&self) -> usize {
+        16 << self.szx
+    }
+
+    pub fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+
+    fn decode(value: &[u8]) -> Option<Block> {
+        if value.len() > 3 {
+            return None;
+        }
+        let raw = value.iter().fold(0u32, |raw, b| (raw << 8) | *b as u32);
+        let szx = (raw & 0x7) as u8;
+        // A size exponent of 7 is reserved
+        if szx == 7 {
+            return None;
+        }
+        Some(Block {
+            num: raw >> 4,
+            more: raw & 0x8 != 0,
+            szx,
+        })
+    }
+
+    /// Encodes the value in `buf`, returning the length of the value.
+    fn encode(&self, buf: &mut [u8; 3]) -> usize {
+        let raw = (self.num << 4) | (u32::from(self.more) << 3) | u32::from(self.szx);
+        encode_uint(raw, buf)
+    }
+}
+
+/// Encodes `value` with the fewest bytes in `buf`, returning their number.
+fn encode_uint(value: u32, buf: &mut [u8; 3]) -> usize {
+    let bytes = value.to_be_bytes();
+    let len = 4 - (value.leading_zeros() / 8) as usize;
+    let len = len.min(3);
+    buf[..len].copy_from_slice(&bytes[4 - len..]);
+    len
+}
+
+/// Decodes the option starting at `buf`, following option number `number`.
+/// Returns the option number, its value and the rest of the options, or
+/// `None` at the end of the options.
+#[allow(clippy::type_complexity)]
+fn next_option(buf: &[u8], number: u16) -> Result<Option<(u16, &[u8], &[u8])>, ()> {
+    let Some((&first, mut rest)) = buf.split_first() else {
+        return Ok(None);
+    };
+    if first == PAYLOAD_MARKER {
+        return Ok(None);
+    }
+
+    let mut extended = |nibble: u8| -> Result<u16, ()> {
+        match nibble {
+            0..=12 => Ok(nibble as u16),
+            13 => {
+                let (&b, r) = rest.split_first().ok_or(())?;
+                rest = r;
+                Ok(b as u16 + 13)
+            }
+            14 => {
+                if rest.len() < 2 {
+                    return Err(());
+                }
+                let value = u16::from_be_bytes([rest[0], rest[1]]);
+                rest = &rest[2..];
+                value.checked_add(269).ok_or(())
+            }
+            _ => Err(()),
+        }
+    };
+    let delta = extended(first >> 4)?;
+    let len = extended(first & 0xf)? as usize;
+
+    let number = number.checked_add(delta).ok_or(())?;
+    if rest.len() < len {
+        return Err(());
+    }
+    Ok(Some((number, &rest[..len], &rest[len..])))
+}
+
+/// Iterator over the options of a message, as `(number, value)`.
+pub struct Options<'a> {
+    buf: &'a [u8],
+    number: u16,
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u16, &'a [u8])> {
+        // Options were checked when the message was decoded
+        let (number, value, rest) = next_option(self.buf, self.number).ok()??;
+        self.buf = rest;
+        self.number = number;
+        Some((number, value))
+    }
+}
+
+/// A decoded CoAP message, borrowing from the received datagram.
+pub struct Message<'a> {
+    pub mtype: MessageType,
+    pub code: u8,
+    pub message_id: u16,
+    pub token: &'a [u8],
+    options: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    /// Decodes a message, returning `None` if it is malformed.
+    pub fn decode(buf: &'a [u8]) -> Option<Message<'a>> {
+        if buf.len() < HEADER_LEN || buf[0] >> 6 != VERSION {
+            return None;
+        }
+        let mtype = match (buf[0] >> 4) & 0x3 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        };
+        let token_len = (buf[0] & 0xf) as usize;
+        if token_len > MAX_TOKEN_LEN || buf.len() < HEADER_LEN + token_len {
+            return None;
+        }
+        let token = &buf[HEADER_LEN..HEADER_LEN + token_len];
+        let options = &buf[HEADER_LEN + token_len..];
+
+        // Find the payload, checking that all options are well formed
+        let mut rest = options;
+        let mut number = 0;
+        while let Some((next_number, _, next_rest)) = next_option(rest, number).ok()? {
+            number = next_number;
+            rest = next_rest;
+        }
+        let payload = match rest.split_first() {
+            // A payload marker must be followed by a payload
+            Some((_, [])) => return None,
+            Some((_, payload)) => payload,
+            None => &[],
+        };
+
+        Some(Message {
+            mtype,
+            code: buf[1],
+            message_id: u16::from_be_bytes([buf[2], buf[3]]),
+            token,
+            options: &options[..options.len() - rest.len()],
+            payload,
+        })
+    }
+
+    pub fn options(&self) -> Options<'a> {
+        Options {
+            buf: self.options,
+            number: 0,
+        }
+    }
+
+    fn option(&self, number: u16) -> Option<&'a [u8]> {
+        self.options()
+            .find(|(n, _)| *n == number)
+            .map(|(_, value)| value)
+    }
+
+    pub fn block1(&self) -> Option<Block> {
+        self.option(option::BLOCK1).and_then(Block::decode)
+    }
+
+    pub fn block2(&self) -> Option<Block> {
+        self.option(option::BLOCK2).and_then(Block::decode)
+    }
+
+    /// Returns the number of the first critical option that is not in
+    /// `known`.
+    pub fn unknown_critical_option(&self, known: &[u16]) -> Option<u16> {
+        self.options()
+            .map(|(number, _)| number)
+            .find(|number| option::is_critical(*number) && !known.contains(number))
+    }
+
+    /// Whether the Uri-Path options of the message name `path`, a path
+    /// with segments separated by `/`.
+    pub fn uri_path_matches(&self, path: &[u8]) -> bool {
+        let mut segments = path_segments(path);
+        self.options()
+            .filter(|(number, _)| *number == option::URI_PATH)
+            .all(|(_, value)| segments.next() == Some(value))
+            && segments.next().is_none()
+    }
+}
+
+/// Writes a CoAP message into a buffer. Options must be added in increasing
+/// order of option number, before the payload.
+pub struct MessageWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    number: u16,
+}
+
+impl<'a> MessageWriter<'a> {
+    pub fn new(
+        buf: &'a mut [u8],
+        mtype: MessageType,
+        code: u8,
+        message_id: u16,
+        token: &[u8],
+    ) -> Result<MessageWriter<'a>, ErrorCode> {
+        let len = HEADER_LEN + token.len();
+        if token.len() > MAX_TOKEN_LEN || buf.len() < len {
+            return Err(ErrorCode::SIZE);
+        }
+        buf[0] = (VERSION << 6) | ((mtype as u8) << 4) | token.len() as u8;
+        buf[1] = code;
+        buf[2..4].copy_from_slice(&message_id.to_be_bytes());
+        buf[HEADER_LEN..len].copy_from_slice(token);
+        Ok(MessageWriter {
+            buf,
+            len,
+            number: 0,
+        })
+    }
+
+    pub fn option(&mut self, number: u16, value: &[u8]) -> Result<(), ErrorCode> {
+        fn nibble(value: u16, ext: &mut [u8; 2]) -> (u8, usize) {
+            match value {
+                0..=12 => (value as u8, 0),
+                13..=268 => {
+                    ext[0] = (value - 13) as u8;
+                    (13, 1)
+                }
+                _ => {
+                    ext.copy_from_slice(&(value - 269).to_be_bytes());
+                    (14, 2)
+                }
+            }
+        }
+
+        let delta = number.checked_sub(self.number).ok_or(ErrorCode::INVAL)?;
+        let (mut delta_ext, mut len_ext) = ([0; 2], [0; 2]);
+        let (delta_nibble, delta_ext_len) = nibble(delta, &mut delta_ext);
+        let (len_nibble, len_ext_len) = nibble(value.len() as u16, &mut len_ext);
+
+        let end = self.len + 1 + delta_ext_len + len_ext_len + value.len();
+        if end > self.buf.len() {
+            return Err(ErrorCode::SIZE);
+        }
+        let mut off = self.len;
+        self.buf[off] = (delta_nibble << 4) | len_nibble;
+        off += 1;
+        self.buf[off..off + delta_ext_len].copy_from_slice(&delta_ext[..delta_ext_len]);
+        off += delta_ext_len;
+        self.buf[off..off + len_ext_len].copy_from_slice(&len_ext[..len_ext_len]);
+        off += len_ext_len;
+        self.buf[off..end].copy_from_slice(value);
+
+        self.len = end;
+        self.number = number;
+        Ok(())
+    }
+
+    /// Adds a Uri-Path option for each segment of `path`.
+    pub fn uri_path(&mut self, path: &[u8]) -> Result<(), ErrorCode> {
+        path_segments(path).try_for_each(|segment| self.option(option::URI_PATH, segment))
+    }
+
+    pub fn block(&mut self, number: u16, block: Block) -> Result<(), ErrorCode> {
+        let mut value = [0; 3];
+        let len = block.encode(&mut value);
+        self.option(number, &value[..len])
+    }
+
+    /// Adds a payload of `len` bytes, returning the buffer to write it to.
+    pub fn payload(&mut self, len: usize) -> Result<&mut [u8], ErrorCode> {
+        if len == 0 {
+            return Ok(&mut []);
+        }
+        let start = self.len + 1;
+        if start + len > self.buf.len() {
+            return Err(ErrorCode::SIZE);
+        }
+        self.buf[self.len] = PAYLOAD_MARKER;
+        self.len = start + len;
+        Ok(&mut self.buf[start..start + len])
+    }
+
+    /// Returns the length of the message.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &[u8] = &[0xab, 0xcd];
+
+    fn writer(buf: &mut [u8]) -> MessageWriter<'_> {
+        MessageWriter::new(buf, MessageType::Confirmable, code::GET, 0x1234, TOKEN).unwrap()
+    }
+
+    /// A message with `options` (the bytes after the token) and no token.
+    fn message(options: &[u8]) -> ([u8; 64], usize) {
+        let mut buf = [0; 64];
+        buf[..HEADER_LEN].copy_from_slice(&[0x40, code::GET, 0x12, 0x34]);
+        buf[HEADER_LEN..HEADER_LEN + options.len()].copy_from_slice(options);
+        (buf, HEADER_LEN + options.len())
+    }
+
+    #[test]
+    fn header() {
+        let mut buf = [0; 16];
+        let len = writer(&mut buf).len();
+        assert_eq!(buf[..len], [0x42, code::GET, 0x12, 0x34, 0xab, 0xcd]);
+
+        let msg = Message::decode(&buf[..len]).unwrap();
+        assert_eq!(msg.mtype, MessageType::Confirmable);
+        assert_eq!(msg.code, code::GET);
+        assert_eq!(msg.message_id, 0x1234);
+        assert_eq!(msg.token, TOKEN);
+        assert_eq!(msg.options().next(), None);
+        assert!(msg.payload.is_empty());
+    }
+
+    #[test]
+    fn extended_option_deltas() {
+        let mut buf = [0; 64];
+        let mut w = writer(&mut buf);
+        // Deltas of 12, 13, 268, 269 and 1000, and lengths of 12 and 13.
+        w.option(12, &[1; 12]).unwrap();
+        w.option(25, &[2; 13]).unwrap();
+        w.option(293, &[]).unwrap();
+        w.option(562, &[3]).unwrap();
+        w.option(1562, &[]).unwrap();
+        let len = w.len();
+
+        let options = &buf[HEADER_LEN + TOKEN.len()..len];
+        assert_eq!(options[..13], [0xcc, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1]);
+        assert_eq!(options[13..16], [0xdd, 0x00, 0x00]);
+        assert_eq!(options[29..31], [0xd0, 0xff]);
+        assert_eq!(options[31..35], [0xe1, 0x00, 0x00, 3]);
+        assert_eq!(options[35..], [0xe0, 0x02, 0xdb]);
+
+        let msg = Message::decode(&buf[..len]).unwrap();
+        let expected: [(u16, &[u8]); 5] = [
+            (12, &[1; 12]),
+            (25, &[2; 13]),
+            (293, &[]),
+            (562, &[3]),
+            (1562, &[]),
+        ];
+        assert!(msg.options().eq(expected.iter().copied()));
+        assert!(msg.payload.is_empty());
+    }
+
+    #[test]
+    fn options_out_of_order() {
+        let mut buf = [0; 16];
+        let mut w = writer(&mut buf);
+        w.option(option::URI_PATH, b"a").unwrap();
+        assert_eq!(w.option(option::URI_HOST, b"b"), Err(ErrorCode::INVAL));
+        // An option can be repeated.
+        assert_eq!(w.option(option::URI_PATH, b"b"), Ok(()));
+    }
+
+    #[test]
+    fn payload_marker() {
+        let mut buf = [0; 32];
+        let mut w = writer(&mut buf);
+        w.uri_path(b"/sensors/temp").unwrap();
+        w.payload(3).unwrap().copy_from_slice(b"abc");
+        let len = w.len();
+        assert_eq!(buf[len - 4..len], [PAYLOAD_MARKER, b'a', b'b', b'c']);
+
+        let msg = Message::decode(&buf[..len]).unwrap();
+        assert!(msg.uri_path_matches(b"sensors/temp"));
+        assert!(!msg.uri_path_matches(b"sensors"));
+        assert_eq!(msg.payload, b"abc");
+
+        // An empty payload has no marker.
+        let mut buf = [0; 32];
+        let mut w = writer(&mut buf);
+        assert_eq!(w.payload(0), Ok(&mut [][..]));
+        assert_eq!(w.len(), HEADER_LEN + TOKEN.len());
+
+        // A marker without a payload is malformed.
+        let (buf, len) = message(&[0xb1, b'a', PAYLOAD_MARKER]);
+        assert!(Message::decode(&buf[..len]).is_none());
+        let (buf, len) = message(&[0xb1, b'a', PAYLOAD_MARKER, 0xff]);
+        assert_eq!(Message::decode(&buf[..len]).unwrap().payload, [0xff]);
+    }
+
+    #[test]
+    fn malformed_length() {
+        // Option values longer than the message.
+        let (buf, len) = message(&[0xb3, b'a', b'b']);
+        assert!(Message::decode(&buf[..len]).is_none());
+        let (buf, len) = message(&[0xbd, 0x00, b'a']);
+        assert!(Message::decode(&buf[..len]).is_none());
+        // Extended lengths and deltas cut off by the end of the message.
+        let (buf, len) = message(&[0xbd]);
+        assert!(Message::decode(&buf[..len]).is_none());
+        let (buf, len) = message(&[0xe0, 0x00]);
+        assert!(Message::decode(&buf[..len]).is_none());
+        // A nibble of 15 is only valid in the payload marker.
+        let (buf, len) = message(&[0xbf, b'a']);
+        assert!(Message::decode(&buf[..len]).is_none());
+        let (buf, len) = message(&[0xf0, b'a']);
+        assert!(Message::decode(&buf[..len]).is_none());
+        // Option numbers past 65535.
+        let (buf, len) = message(&[0xe0, 0xff, 0xff, 0xe0, 0xff, 0xff]);
+        assert!(Message::decode(&buf[..len]).is_none());
+
+        // Tokens longer than the message or than 8 bytes.
+        assert!(Message::decode(&[0x42, code::GET, 0x12, 0x34, 0xab]).is_none());
+        let mut buf = [0; 13];
+        buf[0] = 0x49;
+        assert!(Message::decode(&buf).is_none());
+        assert!(Message::decode(&[0x40, code::GET, 0x12]).is_none());
+        // Other versions.
+        assert!(Message::decode(&[0x80, code::GET, 0x12, 0x34]).is_none());
+
+        let mut buf = [0; 8];
+        let mut w = writer(&mut buf);
+        assert_eq!(w.option(option::URI_PATH, b"ab"), Err(ErrorCode::SIZE));
+        assert_eq!(w.payload(2).err(), Some(ErrorCode::SIZE));
+    }
+
+    #[test]
+    fn block_round_trip() {
+        let blocks = [
+            (
+                Block {
+                    num: 0,
+                    more: false,
+                    szx: 0,
+                },
+                0,
+            ),
+            (
+                Block {
+                    num: 0,
+                    more: true,
+                    szx: 6,
+                },
+                1,
+            ),
+            (
+                Block {
+                    num: 15,
+                    more: true,
+                    szx: 2,
+                },
+                1,
+            ),
+            (
+                Block {
+                    num: 16,
+                    more: false,
+                    szx: 6,
+                },
+                2,
+            ),
+            (
+                Block {
+                    num: 0xfffff,
+                    more: true,
+                    szx: 5,
+                },
+                3,
+            ),
+        ];
+        for (block, value_len) in blocks {
+            let mut buf = [0; 32];
+            let mut w = writer(&mut buf);
+            w.block(option::BLOCK2, block).unwrap();
+            w.block(option::BLOCK1, block).unwrap();
+            let len = w.len();
+
+            let msg = Message::decode(&buf[..len]).unwrap();
+            assert_eq!(msg.block1(), Some(block));
+            assert_eq!(msg.block2(), Some(block));
+            let mut options = msg.options();
+            assert_eq!(options.next().map(|(_, v)| v.len()), Some(value_len));
+        }
+
+        let block = Block {
+            num: 3,
+            more: false,
+            szx: 6,
+        };
+        assert_eq!(block.size(), 1024);
+        assert_eq!(block.offset(), 3072);
+
+        // A size exponent of 7 is reserved.
+        let (buf, len) = message(&[0xd1, 0x0a, 0x07]);
+        let msg = Message::decode(&buf[..len]).unwrap();
+        assert_eq!(msg.block2(), None);
+        assert_eq!(msg.unknown_critical_option(&[option::BLOCK2]), None);
+        assert_eq!(msg.unknown_critical_option(&[]), Some(option::BLOCK2));
+    }
+}
@@ -0,0 +1,1318 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! CoAP (RFC 7252) endpoint for processes, over the UDP mux.
+//!
+//! `CoapDriver` is bound to the CoAP port (5683) and lets processes both serve
+//! resources and send requests to other endpoints:
+//!
+//! - Processes register resource paths. Requests for a registered path are
+//!   passed to the process, and its response is piggybacked in the
+//!   acknowledgement if it arrives quickly enough. Otherwise the request is
+//!   acknowledged and the response follows in a non-confirmable message.
+//! - Processes send requests, one at a time. Confirmable requests are
+//!   retransmitted with exponential back-off until they are acknowledged, and
+//!   responses are matched to requests by their token.
+//! - Payloads larger than a block (64 bytes) are transferred block-wise
+//!   (RFC 7959) in both directions: request payloads with the Block1 option
+//!   and response payloads with the Block2 option.
+//! - Duplicate messages are detected by message ID. The response to a
+//!   retransmitted request is sent again.
+//!
+//! One request is served at a time: a request that arrives while a process
+//! has not answered the previous one is answered with 5.03 Service
+//! Unavailable. The kernel sends one message at a time, so an empty
+//! acknowledgement or reset is dropped if another one is already waiting to be
+//! sent; the peer retransmits its message.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let coap = components::coap::CoapComponent::new(
+//!     board_kernel,
+//!     capsules_extra::net::coap::DRIVER_NUM,
+//!     udp_send_mux,
+//!     udp_recv_mux,
+//!     udp_port_table,
+//!     mux_alarm,
+//! )
+//! .finalize(components::coap_component_static!(nrf52840::rtc::Rtc));
+//! ```
+//!
+//! Allow
+//! -----
+//!
+//! - Read-only `0`: The path of a resource to register, or of the target of
+//!   a request, with segments separated by `/`.
+//! - Read-only `1`: The destination address of requests (16 bytes).
+//! - Read-only `2`: The payload of requests.
+//! - Read-only `3`: The payload of responses to requests for the resources of
+//!   the process.
+//! - Read-write `0`: Receives the payload of requests for the resources of the
+//!   process.
+//! - Read-write `1`: Receives the payload of responses to the requests of the
+//!   process.
+//!
+//! The buffers of a request must stay allowed until its response arrived, and
+//! the response payload must stay allowed until all of its blocks were sent.
+//!
+//! Command
+//! -------
+//!
+//! - `0`: Check if the driver is present.
+//! - `1`: Register the resource at the path in read-only buffer `0` (`/` for
+//!   the root resource). Returns the index of the resource.
+//! - `2`: Unregister the resource with index `data1`.
+//! - `3`: Respond to the request being served with code `data1`
+//!   (`class << 5 | detail`) and the payload in read-only buffer `3`.
+//! - `4`: Send a request with method `data1 & 0xff`, confirmable if bit 8 of
+//!   `data1` is set, to port `data2` (5683 if `0`).
+//!
+//! Upcall
+//! ------
+//!
+//! - `0`: A request for a resource of the process arrived. The arguments are
+//!   `method | (resource index << 8)` and the length of the payload.
+//! - `1`: The request of the process completed. The arguments are the status,
+//!   the response code and the length of the response payload.
+
+use core::cell::Cell;
+use core::cmp;
+
+use crate::net::coap::coap_message::{
+    code, option, path_segments, Block, Message, MessageType, MessageWriter, MAX_TOKEN_LEN,
+};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::processbuffer::{ReadableProcessBuffer, ReadableProcessSlice, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Coap as usize;
+
+/// UDP port of CoAP.
+pub const COAP_PORT: u16 = 5683;
+
+/// Maximum length of the path of a resource or request.
+pub const PATH_LEN: usize = 32;
+/// Number of resources a process can register.
+pub const RESOURCES: usize = 4;
+
+/// Size exponent of the blocks of block-wise transfers (64 bytes).
+const BLOCK_SZX: u8 = 2;
+const TOKEN_LEN: usize = 4;
+
+/// Initial retransmission timeout of confirmable requests, to which up to
+/// `ACK_JITTER_MS` are added.
+const ACK_TIMEOUT_MS: u32 = 2000;
+const ACK_JITTER_MS: u32 = 1000;
+const MAX_RETRANSMIT: u8 = 4;
+/// How long to wait for a response once a request was acknowledged, or for
+/// the response to a non-confirmable request.
+const RESPONSE_TIMEOUT_MS: u32 = 10_000;
+/// How long to wait for a process to respond before acknowledging a
+/// confirmable request, and sending the response separately.
+const PIGGYBACK_TIMEOUT_MS: u32 = 1000;
+/// How long to wait for a process to respond before answering 5.03 Service
+/// Unavailable.
+const SERVER_RESPONSE_TIMEOUT_MS: u32 = 10_000;
+
+/// Number of received message IDs remembered to detect duplicates.
+const DEDUP_LEN: usize = 8;
+
+/// Options understood in requests.
+const KNOWN_OPTIONS: [u16; 5] = [
+    option::URI_HOST,
+    option::URI_PORT,
+    option::URI_PATH,
+    option::BLOCK2,
+    option::BLOCK1,
+];
+
+/// IDs for subscribed upcalls.
+mod upcall {
+    /// A request for a resource of the process arrived.
+    pub const REQUEST: usize = 0;
+    /// The request of the process completed.
+    pub const RESPONSE: usize = 1;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// Path of a resource or request.
+    pub const PATH: usize = 0;
+    /// Destination address of requests.
+    pub const DESTINATION: usize = 1;
+    /// Payload of requests.
+    pub const REQUEST: usize = 2;
+    /// Payload of responses.
+    pub const RESPONSE: usize = 3;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 4;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Payload of received requests.
+    pub const REQUEST: usize = 0;
+    /// Payload of received responses.
+    pub const RESPONSE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct Endpoint {
+    addr: IPAddr,
+    port: u16,
+}
+
+#[derive(Copy, Clone)]
+struct Token {
+    bytes: [u8; MAX_TOKEN_LEN],
+    len: u8,
+}
+
+impl Token {
+    fn new(token: &[u8]) -> Token {
+        let mut bytes = [0; MAX_TOKEN_LEN];
+        bytes[..token.len()].copy_from_slice(token);
+        Token {
+            bytes,
+            len: token.len() as u8,
+        }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Timeout<T: Ticks> {
+    start: T,
+    duration: T,
+}
+
+impl<T: Ticks> Timeout<T> {
+    fn remaining(&self, now: T) -> T {
+        let elapsed = now.wrapping_sub(self.start);
+        if elapsed >= self.duration {
+            T::from(0)
+        } else {
+            self.duration.wrapping_sub(elapsed)
+        }
+    }
+
+    fn expired(&self, now: T) -> bool {
+        self.remaining(now) == T::from(0)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Resource {
+    path: [u8; PATH_LEN],
+    len: u8,
+}
+
+impl Resource {
+    fn path(&self) -> &[u8] {
+        &self.path[..self.len as usize]
+    }
+}
+
+/// Block-wise upload of a request payload to a resource.
+#[derive(Copy, Clone)]
+struct Upload {
+    resource: u8,
+    peer: Endpoint,
+    /// Bytes received so far.
+    len: usize,
+}
+
+/// The last response of a process, whose later blocks are served without
+/// involving the process.
+#[derive(Copy, Clone)]
+struct Response {
+    resource: u8,
+    code: u8,
+}
+
+/// The outstanding request of a process.
+#[derive(Copy, Clone)]
+struct Request<T: Ticks> {
+    peer: Endpoint,
+    method: u8,
+    confirmable: bool,
+    token: [u8; TOKEN_LEN],
+    message_id: u16,
+    /// Number of times the current message was sent.
+    transmissions: u8,
+    acked: bool,
+    needs_send: bool,
+    /// Block of the request payload sent in the current message.
+    block1: Option<Block>,
+    /// Block of the response payload requested by the current message.
+    block2: Option<Block>,
+    timeout: Option<Timeout<T>>,
+}
+
+pub struct App<T: Ticks> {
+    resources: [Option<Resource>; RESOURCES],
+    request: Option<Request<T>>,
+    upload: Option<Upload>,
+    response: Option<Response>,
+}
+
+impl<T: Ticks> Default for App<T> {
+    fn default() -> App<T> {
+        App {
+            resources: [None; RESOURCES],
+            request: None,
+            upload: None,
+            response: None,
+        }
+    }
+}
+
+/// The request being served.
+#[derive(Copy, Clone)]
+struct Exchange<T: Ticks> {
+    processid: ProcessId,
+    resource: u8,
+    peer: Endpoint,
+    message_id: u16,
+    token: Token,
+    confirmable: bool,
+    /// Block1 option echoed in the response.
+    block1: Option<Block>,
+    /// Block2 option of the request.
+    block2: Option<Block>,
+    /// Code of the response, once known.
+    code: Option<u8>,
+    /// Whether the response carries the payload of the process.
+    payload: bool,
+    /// Whether an empty acknowledgement must be sent.
+    ack_pending: bool,
+    /// Whether the request was, or is about to be, acknowledged without the
+    /// response.
+    acked: bool,
+    sent: bool,
+    timeout: Option<Timeout<T>>,
+}
+
+/// A message without payload generated by the kernel.
+#[derive(Copy, Clone)]
+struct Control {
+    peer: Endpoint,
+    mtype: MessageType,
+    code: u8,
+    message_id: u16,
+    token: Token,
+    block1: Option<Block>,
+}
+
+impl Control {
+    fn empty(peer: Endpoint, mtype: MessageType, message_id: u16) -> Control {
+        Control {
+            peer,
+            mtype,
+            code: code::EMPTY,
+            message_id,
+            token: Token::new(&[]),
+            block1: None,
+        }
+    }
+}
+
+pub struct CoapDriver<'a, A: Alarm<'a>> {
+    sender: &'a dyn UDPSender<'a>,
+    alarm: &'a A,
+    apps: Grant<
+        App<A::Ticks>,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    send_buffer: MapCell<SubSliceMut<'static, u8>>,
+    /// Message waiting to be sent, such as an empty acknowledgement.
+    control: OptionalCell<Control>,
+    exchange: OptionalCell<Exchange<A::Ticks>>,
+    /// Recently received messages, to detect duplicates.
+    recent: [Cell<Option<(Endpoint, u16)>>; DEDUP_LEN],
+    recent_next: Cell<usize>,
+    next_message_id: Cell<u16>,
+    next_token: Cell<u32>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: Alarm<'a>> CoapDriver<'a, A> {
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        grant: Grant<
+            App<A::Ticks>,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        send_buffer: SubSliceMut<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> CoapDriver<'a, A> {
+        // Message IDs and tokens should not repeat those used before a
+        // reboot, start them from the current time.
+        let seed = alarm.now().into_u32();
+        CoapDriver {
+            sender,
+            alarm,
+            apps: grant,
+            send_buffer: MapCell::new(send_buffer),
+            control: OptionalCell::empty(),
+            exchange: OptionalCell::empty(),
+            recent: Default::default(),
+            recent_next: Cell::new(0),
+            next_message_id: Cell::new(seed as u16),
+            next_token: Cell::new(seed.rotate_left(16)),
+            net_cap,
+        }
+    }
+
+    fn message_id(&self) -> u16 {
+        let id = self.next_message_id.get();
+        self.next_message_id.set(id.wrapping_add(1));
+        id
+    }
+
+    fn token(&self) -> [u8; TOKEN_LEN] {
+        let token = self.next_token.get();
+        self.next_token.set(token.wrapping_add(1));
+        token.to_be_bytes()
+    }
+
+    fn timeout(&self, ms: u32) -> Timeout<A::Ticks> {
+        Timeout {
+            start: self.alarm.now(),
+            duration: self.alarm.ticks_from_ms(ms),
+        }
+    }
+
+    /// Retransmission timeout of a request after its `transmissions`th
+    /// transmission. The jitter is derived from the message ID, so that
+    /// endpoints that lost the same message do not retransmit together.
+    fn ack_timeout(&self, message_id: u16, transmissions: u8) -> Timeout<A::Ticks> {
+        let initial = ACK_TIMEOUT_MS + u32::from(message_id) % ACK_JITTER_MS;
+        self.timeout(initial << (transmissions - 1))
+    }
+
+    /// Returns whether the message was received recently, and remembers it
+    /// otherwise.
+    fn is_duplicate(&self, peer: Endpoint, message_id: u16) -> bool {
+        let key = Some((peer, message_id));
+        if self.recent.iter().any(|recent| recent.get() == key) {
+            return true;
+        }
+        let next = self.recent_next.get();
+        self.recent[next].set(key);
+        self.recent_next.set((next + 1) % DEDUP_LEN);
+        false
+    }
+
+    fn queue_control(&self, control: Control) {
+        if self.control.is_none() {
+            self.control.set(control);
+        }
+    }
+
+    /// Answers `msg` with a response without payload, piggybacked in the
+    /// acknowledgement if `msg` is confirmable.
+    fn reply(&self, peer: Endpoint, msg: &Message, code: u8, block1: Option<Block>) {
+        let (mtype, message_id) = if msg.mtype == MessageType::Confirmable {
+            (MessageType::Acknowledgement, msg.message_id)
+        } else {
+            (MessageType::NonConfirmable, self.message_id())
+        };
+        self.queue_control(Control {
+            peer,
+            mtype,
+            code,
+            message_id,
+            token: Token::new(msg.token),
+            block1,
+        });
+    }
+
+    fn complete(
+        app: &mut App<A::Ticks>,
+        kernel_data: &GrantKernelData,
+        result: Result<(), ErrorCode>,
+        code: u8,
+        len: usize,
+    ) {
+        app.request = None;
+        let _ = kernel_data.schedule_upcall(
+            upcall::RESPONSE,
+            (
+                kernel::errorcode::into_statuscode(result),
+                usize::from(code),
+                len,
+            ),
+        );
+    }
+
+    /// Copies as much of `data` as fits into read-write buffer `allow_num`
+    /// at `offset`, returning the number of bytes copied.
+    fn copy_to_process(
+        kernel_data: &GrantKernelData,
+        allow_num: usize,
+        offset: usize,
+        data: &[u8],
+    ) -> usize {
+        kernel_data
+            .get_readwrite_processbuffer(allow_num)
+            .and_then(|buffer| {
+                buffer.mut_enter(|buffer| {
+                    let len = cmp::min(buffer.len().saturating_sub(offset), data.len());
+                    match buffer.get(offset..offset + len) {
+                        Some(dest) => {
+                            dest.copy_from_slice(&data[..len]);
+                            len
+                        }
+                        None => 0,
+                    }
+                })
+            })
+            .unwrap_or(0)
+    }
+
+    fn readonly_len(kernel_data: &GrantKernelData, allow_num: usize) -> usize {
+        kernel_data
+            .get_readonly_processbuffer(allow_num)
+            .map_or(0, |buffer| buffer.len())
+    }
+
+    /// Prepares `request` to send its next message.
+    fn restart(&self, request: &mut Request<A::Ticks>) {
+        request.message_id = self.message_id();
+        request.transmissions = 0;
+        request.acked = false;
+        request.needs_send = true;
+        request.timeout = None;
+    }
+
+    fn send_request(
+        &self,
+        processid: ProcessId,
+        method: u8,
+        confirmable: bool,
+        port: u16,
+    ) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, kernel_data| {
+                if app.request.is_some() {
+                    return Err(ErrorCode::BUSY);
+                }
+                let mut addr = IPAddr::new();
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::DESTINATION)
+                    .and_then(|destination| {
+                        destination.enter(|destination| {
+                            destination
+                                .get(..16)
+                                .map(|destination| destination.copy_to_slice(&mut addr.0))
+                                .ok_or(ErrorCode::INVAL)
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::INVAL))?;
+                if Self::readonly_len(kernel_data, ro_allow::PATH) > PATH_LEN {
+                    return Err(ErrorCode::SIZE);
+                }
+
+                let block_size = 16 << BLOCK_SZX;
+                let block1 = (Self::readonly_len(kernel_data, ro_allow::REQUEST) > block_size)
+                    .then_some(Block {
+                        num: 0,
+                        more: true,
+                        szx: BLOCK_SZX,
+                    });
+                let mut request = Request {
+                    peer: Endpoint { addr, port },
+                    method,
+                    confirmable,
+                    token: self.token(),
+                    message_id: 0,
+                    transmissions: 0,
+                    acked: false,
+                    needs_send: true,
+                    block1,
+                    block2: None,
+                    timeout: None,
+                };
+                self.restart(&mut request);
+                app.request = Some(request);
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn register(&self, processid: ProcessId) -> Result<u32, ErrorCode> {
+        let mut resource = Resource {
+            path: [0; PATH_LEN],
+            len: 0,
+        };
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::PATH)
+                    .and_then(|path| {
+                        path.enter(|path| {
+                            if path.len() > PATH_LEN {
+                                return Err(ErrorCode::SIZE);
+                            } else if path.len() == 0 {
+                                return Err(ErrorCode::INVAL);
+                            }
+                            path.copy_to_slice(&mut resource.path[..path.len()]);
+                            resource.len = path.len() as u8;
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::INVAL))
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        let same_path = |other: &Option<Resource>| {
+            other
+                .is_some_and(|other| path_segments(other.path()).eq(path_segments(resource.path())))
+        };
+        for app in self.apps.iter() {
+            if app.enter(|app, _| app.resources.iter().any(same_path)) {
+                return Err(ErrorCode::ALREADY);
+            }
+        }
+
+        self.apps
+            .enter(processid, |app, _| {
+                let (index, slot) = app
+                    .resources
+                    .iter_mut()
+                    .enumerate()
+                    .find(|(_, slot)| slot.is_none())
+                    .ok_or(ErrorCode::NOMEM)?;
+                *slot = Some(resource);
+                Ok(index as u32)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn respond(&self, processid: ProcessId, code: u8) -> Result<(), ErrorCode> {
+        match self.exchange.get() {
+            Some(mut exchange) if exchange.processid == processid && exchange.code.is_none() => {
+                exchange.code = Some(code);
+                exchange.payload = true;
+                exchange.timeout = None;
+                self.exchange.set(exchange);
+                let _ = self.apps.enter(processid, |app, _| {
+                    app.response = Some(Response {
+                        resource: exchange.resource,
+                        code,
+                    });
+                });
+                Ok(())
+            }
+            _ => Err(ErrorCode::INVAL),
+        }
+    }
+
+    fn receive_request(&self, peer: Endpoint, msg: &Message) {
+        if self.is_duplicate(peer, msg.message_id) {
+            // The peer did not receive the reply to the request being served,
+            // send it again.
+            if let Some(mut exchange) = self.exchange.get() {
+                if exchange.peer == peer && exchange.message_id == msg.message_id {
+                    if exchange.acked {
+                        exchange.ack_pending = true;
+                    } else if exchange.sent {
+                        exchange.sent = false;
+                    }
+                    self.exchange.set(exchange);
+                }
+            }
+            return;
+        }
+
+        if msg.unknown_critical_option(&KNOWN_OPTIONS).is_some() {
+            self.reply(peer, msg, code::BAD_OPTION, None);
+            return;
+        }
+
+        for app in self.apps.iter() {
+            let processid = app.processid();
+            let served = app.enter(|app, kernel_data| {
+                let resource = app.resources.iter().position(|resource| {
+                    resource.is_some_and(|resource| msg.uri_path_matches(resource.path()))
+                });
+                match resource {
+                    Some(resource) => {
+                        self.serve(processid, app, kernel_data, resource as u8, peer, msg);
+                        true
+                    }
+                    None => false,
+                }
+            });
+            if served {
+                return;
+            }
+        }
+        self.reply(peer, msg, code::NOT_FOUND, None);
+    }
+
+    /// Serves a request for a resource of a process.
+    fn serve(
+        &self,
+        processid: ProcessId,
+        app: &mut App<A::Ticks>,
+        kernel_data: &GrantKernelData,
+        resource: u8,
+        peer: Endpoint,
+        msg: &Message,
+    ) {
+        if self.exchange.get().is_some_and(|exchange| !exchange.sent) {
+            self.reply(peer, msg, code::SERVICE_UNAVAILABLE, None);
+            return;
+        }
+
+        let mut exchange = Exchange {
+            processid,
+            resource,
+            peer,
+            message_id: msg.message_id,
+            token: Token::new(msg.token),
+            confirmable: msg.mtype == MessageType::Confirmable,
+            block1: None,
+            block2: msg.block2(),
+            code: None,
+            payload: false,
+            ack_pending: false,
+            acked: false,
+            sent: false,
+            timeout: None,
+        };
+
+        // Later blocks of the response are served from the response the
+        // process gave to the first block.
+        if exchange.block2.is_some_and(|block| block.num > 0) {
+            match app
+                .response
+                .filter(|response| response.resource == resource)
+            {
+                Some(response) => {
+                    exchange.code = Some(response.code);
+                    exchange.payload = true;
+                    self.exchange.set(exchange);
+                }
+                None => self.reply(peer, msg, code::BAD_REQUEST, None),
+            }
+            return;
+        }
+
+        let len = match msg.block1() {
+            Some(block) => {
+                if block.num == 0 {
+                    app.upload = Some(Upload {
+                        resource,
+                        peer,
+                        len: 0,
+                    });
+                }
+                let upload = app.upload.take().filter(|upload| {
+                    upload.resource == resource
+                        && upload.peer == peer
+                        && upload.len == block.offset()
+                });
+                let Some(mut upload) = upload else {
+                    self.reply(peer, msg, code::REQUEST_ENTITY_INCOMPLETE, None);
+                    return;
+                };
+                let copied =
+                    Self::copy_to_process(kernel_data, rw_allow::REQUEST, upload.len, msg.payload);
+                if copied < msg.payload.len() {
+                    self.reply(peer, msg, code::REQUEST_ENTITY_TOO_LARGE, None);
+                    return;
+                }
+                upload.len += copied;
+                let echo = Block {
+                    num: block.num,
+                    more: block.more,
+                    szx: cmp::min(block.szx, BLOCK_SZX),
+                };
+                if block.more {
+                    app.upload = Some(upload);
+                    self.reply(peer, msg, code::CONTINUE, Some(echo));
+                    return;
+                }
+                exchange.block1 = Some(echo);
+                upload.len
+            }
+            None => {
+                let copied = Self::copy_to_process(kernel_data, rw_allow::REQUEST, 0, msg.payload);
+                if copied < msg.payload.len() {
+                    self.reply(peer, msg, code::REQUEST_ENTITY_TOO_LARGE, None);
+                    return;
+                }
+                copied
+            }
+        };
+
+        exchange.timeout = Some(self.timeout(if exchange.confirmable {
+            PIGGYBACK_TIMEOUT_MS
+        } else {
+            SERVER_RESPONSE_TIMEOUT_MS
+        }));
+        self.exchange.set(exchange);
+        let _ = kernel_data.schedule_upcall(
+            upcall::REQUEST,
+            (usize::from(msg.code) | (usize::from(resource) << 8), len, 0),
+        );
+    }
+
+    /// Handles a response to the outstanding request of a process.
+    fn receive_response(
+        &self,
+        app: &mut App<A::Ticks>,
+        kernel_data: &GrantKernelData,
+        mut request: Request<A::Ticks>,
+        msg: &Message,
+    ) {
+        // The server asks for the next block of the request payload
+        if msg.code == code::CONTINUE {
+            if let Some(block) = request.block1.filter(|block| block.more) {
+                let szx = msg
+                    .block1()
+                    .map_or(block.szx, |block1| cmp::min(block1.szx, block.szx));
+                let offset = block.offset() + block.size();
+                let size = 16 << szx;
+                let len = Self::readonly_len(kernel_data, ro_allow::REQUEST);
+                request.block1 = Some(Block {
+                    num: (offset / size) as u32,
+                    more: offset + size < len,
+                    szx,
+                });
+                self.restart(&mut request);
+                app.request = Some(request);
+                return;
+            }
+        }
+
+        let offset = msg.block2().map_or(0, |block| block.offset());
+        let copied = Self::copy_to_process(kernel_data, rw_allow::RESPONSE, offset, msg.payload);
+        let len = offset + copied;
+        if copied < msg.payload.len() {
+            Self::complete(app, kernel_data, Err(ErrorCode::SIZE), msg.code, len);
+            return;
+        }
+
+        // Ask for the next block of the response payload
+        match msg
+            .block2()
+            .filter(|block| block.more && code::is_success(msg.code))
+        {
+            Some(block) => {
+                request.block1 = None;
+                request.block2 = Some(Block {
+                    num: block.num + 1,
+                    more: false,
+                    szx: block.szx,
+                });
+                self.restart(&mut request);
+                app.request = Some(request);
+            }
+            None => Self::complete(app, kernel_data, Ok(()), msg.code, len),
+        }
+    }
+
+    /// Handles an acknowledgement or reset of a request.
+    fn receive_reply(&self, peer: Endpoint, msg: &Message) {
+        for app in self.apps.iter() {
+            let matched = app.enter(|app, kernel_data| {
+                let Some(mut request) = app.request else {
+                    return false;
+                };
+                if request.peer != peer
+                    || request.message_id != msg.message_id
+                    || !request.confirmable
+                    || request.acked
+                    || request.transmissions == 0
+                {
+                    return false;
+                }
+                request.acked = true;
+                request.needs_send = false;
+                if msg.mtype == MessageType::Reset {
+                    Self::complete(app, kernel_data, Err(ErrorCode::FAIL), 0, 0);
+                } else if msg.code == code::EMPTY {
+                    // The response will follow separately
+                    request.timeout = Some(self.timeout(RESPONSE_TIMEOUT_MS));
+                    app.request = Some(request);
+                } else {
+                    self.receive_response(app, kernel_data, request, msg);
+                }
+                true
+            });
+            if matched {
+                return;
+            }
+        }
+    }
+
+    /// Handles a response sent separately from the acknowledgement.
+    fn receive_separate(&self, peer: Endpoint, msg: &Message) {
+        let duplicate = self.is_duplicate(peer, msg.message_id);
+        let mut matched = false;
+        if !duplicate {
+            for app in self.apps.iter() {
+                matched = app.enter(|app, kernel_data| {
+                    let Some(mut request) = app.request else {
+                        return false;
+                    };
+                    if request.peer != peer || request.token[..] != *msg.token {
+                        return false;
+                    }
+                    request.acked = true;
+                    request.needs_send = false;
+                    self.receive_response(app, kernel_data, request, msg);
+                    true
+                });
+                if matched {
+                    break;
+                }
+            }
+        }
+        if msg.mtype == MessageType::Confirmable {
+            let mtype = if matched || duplicate {
+                MessageType::Acknowledgement
+            } else {
+                MessageType::Reset
+            };
+            self.queue_control(Control::empty(peer, mtype, msg.message_id));
+        }
+    }
+
+    fn write_control(buf: &mut [u8], control: &Control) -> Result<usize, ErrorCode> {
+        let mut writer = MessageWriter::new(
+            buf,
+            control.mtype,
+            control.code,
+            control.message_id,
+            control.token.as_slice(),
+        )?;
+        if let Some(block) = control.block1 {
+            writer.block(option::BLOCK1, block)?;
+        }
+        Ok(writer.len())
+    }
+
+    fn write_request(
+        buf: &mut [u8],
+        kernel_data: &GrantKernelData,
+        request: &Request<A::Ticks>,
+    ) -> Result<usize, ErrorCode> {
+        let mtype = if request.confirmable {
+            MessageType::Confirmable
+        } else {
+            MessageType::NonConfirmable
+        };
+        let mut writer = MessageWriter::new(
+            buf,
+            mtype,
+            request.method,
+            request.message_id,
+            &request.token,
+        )?;
+        kernel_data
+            .get_readonly_processbuffer(ro_allow::PATH)
+            .and_then(|path| {
+                path.enter(|path| {
+                    let mut bytes = [0; PATH_LEN];
+                    let bytes = bytes.get_mut(..path.len()).ok_or(ErrorCode::SIZE)?;
+                    path.copy_to_slice(bytes);
+                    writer.uri_path(bytes)
+                })
+            })
+            .unwrap_or(Ok(()))?;
+        if let Some(block) = request.block2 {
+            writer.block(option::BLOCK2, block)?;
+        }
+        if let Some(block) = request.block1 {
+            writer.block(option::BLOCK1, block)?;
+        }
+        // Requests for later blocks of the response carry no payload
+        if request.block2.is_none() {
+            kernel_data
+                .get_readonly_processbuffer(ro_allow::REQUEST)
+                .and_then(|payload| {
+                    payload.enter(|payload| {
+                        let (start, end) = match request.block1 {
+                            Some(block) => (
+                                block.offset(),
+                                cmp::min(block.offset() + block.size(), payload.len()),
+                            ),
+                            None => (0, payload.len()),
+                        };
+                        let payload = payload.get(start..end).ok_or(ErrorCode::INVAL)?;
+                        writer
+                            .payload(payload.len())
+                            .map(|dest| payload.copy_to_slice(dest))
+                    })
+                })
+                .unwrap_or(Ok(()))?;
+        }
+        Ok(writer.len())
+    }
+
+    /// Writes the Block2 and Block1 options and the block of `payload` the
+    /// response carries.
+    fn write_response_payload(
+        writer: &mut MessageWriter,
+        exchange: &Exchange<A::Ticks>,
+        payload: &ReadableProcessSlice,
+    ) -> Result<(), ErrorCode> {
+        let szx = exchange
+            .block2
+            .map_or(BLOCK_SZX, |block| cmp::min(block.szx, BLOCK_SZX));
+        let size = 16 << szx;
+        let offset = exchange.block2.map_or(0, |block| block.offset());
+        if exchange.block2.is_some() || payload.len() > size {
+            writer.block(
+                option::BLOCK2,
+                Block {
+                    num: (offset / size) as u32,
+                    more: offset + size < payload.len(),
+                    szx,
+                },
+            )?;
+        }
+        if let Some(block) = exchange.block1 {
+            writer.block(option::BLOCK1, block)?;
+        }
+        let start = cmp::min(offset, payload.len());
+        let end = cmp::min(offset + size, payload.len());
+        if let Some(payload) = payload.get(start..end) {
+            payload.copy_to_slice(writer.payload(payload.len())?);
+        }
+        Ok(())
+    }
+
+    fn write_response(
+        &self,
+        buf: &mut [u8],
+        exchange: &Exchange<A::Ticks>,
+        code: u8,
+    ) -> Result<usize, ErrorCode> {
+        let (mtype, message_id) = if exchange.confirmable && !exchange.acked {
+            (MessageType::Acknowledgement, exchange.message_id)
+        } else {
+            (MessageType::NonConfirmable, self.message_id())
+        };
+        let mut writer =
+            MessageWriter::new(buf, mtype, code, message_id, exchange.token.as_slice())?;
+        if exchange.payload {
+            self.apps
+                .enter(exchange.processid, |_, kernel_data| {
+                    kernel_data
+                        .get_readonly_processbuffer(ro_allow::RESPONSE)
+                        .and_then(|payload| {
+                            payload.enter(|payload| {
+                                Self::write_response_payload(&mut writer, exchange, payload)
+                            })
+                        })
+                        .unwrap_or(Ok(()))
+                })
+                .unwrap_or(Ok(()))?;
+        }
+        Ok(writer.len())
+    }
+
+    /// Writes the next message to send into `buf`. Returns its destination,
+    /// length and the process whose request it is.
+    fn write_next(&self, buf: &mut [u8]) -> Option<(Endpoint, usize, Option<ProcessId>)> {
+        if let Some(control) = self.control.take() {
+            if let Ok(len) = Self::write_control(buf, &control) {
+                return Some((control.peer, len, None));
+            }
+        }
+
+        if let Some(mut exchange) = self.exchange.get() {
+            if exchange.ack_pending {
+                exchange.ack_pending = false;
+                self.exchange.set(exchange);
+                let ack = Control::empty(
+                    exchange.peer,
+                    MessageType::Acknowledgement,
+                    exchange.message_id,
+                );
+                if let Ok(len) = Self::write_control(buf, &ack) {
+                    return Some((exchange.peer, len, None));
+                }
+            }
+            if let (Some(code), false) = (exchange.code, exchange.sent) {
+                exchange.sent = true;
+                self.exchange.set(exchange);
+                if let Ok(len) = self.write_response(buf, &exchange, code) {
+                    return Some((exchange.peer, len, None));
+                }
+            }
+        }
+
+        for app in self.apps.iter() {
+            let processid = app.processid();
+            let next = app.enter(|app, kernel_data| {
+                let mut request = app.request.filter(|request| request.needs_send)?;
+                match Self::write_request(buf, kernel_data, &request) {
+                    Ok(len) => {
+                        request.needs_send = false;
+                        request.transmissions += 1;
+                        request.timeout = Some(if request.confirmable {
+                            self.ack_timeout(request.message_id, request.transmissions)
+                        } else {
+                            self.timeout(RESPONSE_TIMEOUT_MS)
+                        });
+                        app.request = Some(request);
+                        Some((request.peer, len, Some(processid)))
+                    }
+                    Err(e) => {
+                        Self::complete(app, kernel_data, Err(e), 0, 0);
+                        None
+                    }
+                }
+            });
+            if next.is_some() {
+                return next;
+            }
+        }
+        None
+    }
+
+    /// Sends the next message, if the send buffer is free.
+    fn run_sends(&self) {
+        while let Some(mut buffer) = self.send_buffer.take() {
+            buffer.reset();
+            let Some((peer, len, processid)) = self.write_next(buffer.as_mut_slice()) else {
+                self.send_buffer.replace(buffer);
+                return;
+            };
+            buffer.slice(..len);
+            match self
+                .sender
+                .send_to(peer.addr, peer.port, buffer, self.net_cap)
+            {
+                Ok(()) => return,
+                Err(buffer) => {
+                    self.send_buffer.replace(buffer);
+                    if let Some(processid) = processid {
+                        let _ = self.apps.enter(processid, |app, kernel_data| {
+                            Self::complete(app, kernel_data, Err(ErrorCode::FAIL), 0, 0);
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sets the alarm for the earliest timeout.
+    fn arm_alarm(&self) {
+        let now = self.alarm.now();
+        let mut next = self
+            .exchange
+            .get()
+            .and_then(|exchange| exchange.timeout)
+            .map(|timeout| timeout.remaining(now));
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if let Some(timeout) = app.request.and_then(|request| request.timeout) {
+                    let remaining = timeout.remaining(now);
+                    next = Some(next.map_or(remaining, |next| cmp::min(next, remaining)));
+                }
+            });
+        }
+        match next {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPRecvClient for CoapDriver<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        let Some(msg) = Message::decode(payload) else {
+            return;
+        };
+        let peer = Endpoint {
+            addr: src_addr,
+            port: src_port,
+        };
+        match msg.mtype {
+            MessageType::Acknowledgement | MessageType::Reset => self.receive_reply(peer, &msg),
+            _ if code::is_request(msg.code) => self.receive_request(peer, &msg),
+            _ if code::is_response(msg.code) => self.receive_separate(peer, &msg),
+            // Pings, and messages with unknown codes, are rejected
+            MessageType::Confirmable => {
+                self.queue_control(Control::empty(peer, MessageType::Reset, msg.message_id))
+            }
+            MessageType::NonConfirmable => {}
+        }
+        self.run_sends();
+        self.arm_alarm();
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPSendClient for CoapDriver<'a, A> {
+    fn send_done(&self, _result: Result<(), ErrorCode>, mut dgram: SubSliceMut<'static, u8>) {
+        // Lost requests are retransmitted or time out, and lost responses are
+        // sent again when the peer retransmits its request.
+        dgram.reset();
+        self.send_buffer.replace(dgram);
+        self.run_sends();
+        self.arm_alarm();
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for CoapDriver<'a, A> {
+    fn alarm(&self) {
+        let now = self.alarm.now();
+
+        if let Some(mut exchange) = self.exchange.get() {
+            if exchange.timeout.is_some_and(|timeout| timeout.expired(now)) {
+                if exchange.confirmable && !exchange.acked {
+                    // Acknowledge the request, the response follows later
+                    exchange.ack_pending = true;
+                    exchange.acked = true;
+                    exchange.timeout = Some(self.timeout(SERVER_RESPONSE_TIMEOUT_MS));
+                } else {
+                    exchange.code = Some(code::SERVICE_UNAVAILABLE);
+                    exchange.payload = false;
+                    exchange.timeout = None;
+                }
+                self.exchange.set(exchange);
+            }
+        }
+
+        for app in self.apps.iter() {
+            app.enter(|app, kernel_data| {
+                let Some(mut request) = app.request else {
+                    return;
+                };
+                if !request.timeout.is_some_and(|timeout| timeout.expired(now)) {
+                    return;
+                }
+                request.timeout = None;
+                if !request.confirmable || request.acked {
+                    Self::complete(app, kernel_data, Err(ErrorCode::FAIL), 0, 0);
+                } else if request.transmissions > MAX_RETRANSMIT {
+                    Self::complete(app, kernel_data, Err(ErrorCode::NOACK), 0, 0);
+                } else {
+                    request.needs_send = true;
+                    app.request = Some(request);
+                }
+            });
+        }
+
+        self.run_sends();
+        self.arm_alarm();
+    }
+}
+
+impl<'a, A: Alarm<'a>> SyscallDriver for CoapDriver<'a, A> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let result = match command_num {
+            // check if present
+            0 => return CommandReturn::success(),
+
+            // register resource
+            1 => {
+                return match self.register(processid) {
+                    Ok(index) => CommandReturn::success_u32(index),
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+
+            // unregister resource
+            2 => self
+                .apps
+                .enter(processid, |app, _| {
+                    match app.resources.get_mut(data1).and_then(Option::take) {
+                        Some(_) => {
+                            if app
+                                .upload
+                                .is_some_and(|upload| upload.resource as usize == data1)
+                            {
+                                app.upload = None;
+                            }
+                            Ok(())
+                        }
+                        None => Err(ErrorCode::INVAL),
+                    }
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+
+            // respond to the request being served
+            3 => match u8::try_from(data1) {
+                Ok(code) if code::is_response(code) => self.respond(processid, code),
+                _ => Err(ErrorCode::INVAL),
+            },
+
+            // send request
+            4 => {
+                let method = (data1 & 0xff) as u8;
+                let confirmable = data1 & (1 << 8) != 0;
+                let port = match data2 {
+                    0 => Ok(COAP_PORT),
+                    port => u16::try_from(port).map_err(|_| ErrorCode::INVAL),
+                };
+                match port {
+                    Ok(port) if code::is_request(method) => {
+                        self.send_request(processid, method, confirmable, port)
+                    }
+                    _ => Err(ErrorCode::INVAL),
+                }
+            }
+
+            _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+        };
+
+        if result.is_ok() {
+            self.run_sends();
+            self.arm_alarm();
+        }
+        result.into()
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
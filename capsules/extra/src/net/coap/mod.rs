@@ -0,0 +1,9 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+pub mod coap_message;
+pub mod driver;
+
+pub use self::driver::CoapDriver;
+pub use self::driver::DRIVER_NUM;
@@ -0,0 +1,403 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! ICMPv6 echo responder and userspace ping driver.
+//!
+//! `ICMP6Echo` receives the ICMPv6 messages of the node:
+//!
+//! - Echo Requests to an address of the node, or to the all-nodes multicast
+//!   address, are answered with an Echo Reply carrying the same data.
+//! - Processes can send Echo Requests and are notified of the matching reply,
+//!   with the round trip time.
+//! - Destination Unreachable errors are passed to an `ICMP6ErrorClient`, such
+//!   as the UDP driver, which tells the process that sent the packet.
+//!
+//! The responder shares the IPv6 layer with the other protocols. Replies and
+//! requests are sent one at a time, so a request that arrives while the
+//! previous message is still being sent is not answered.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let ping = components::icmpv6_echo::ICMP6EchoComponent::new(
+//!     board_kernel,
+//!     capsules_extra::net::icmpv6::icmpv6_echo::DRIVER_NUM,
+//!     ip6_send_mux,
+//!     ip6_recv_mux,
+//!     local_ip_ifaces,
+//!     mux_alarm,
+//! )
+//! .finalize(components::icmp6_echo_component_static!(nrf52840::rtc::Rtc));
+//! ping.set_error_client(udp_driver);
+//! ```
+//!
+//! Allow
+//! -----
+//!
+//! - Read-only `0`: The destination address (16 bytes), followed by the data
+//!   of the Echo Request.
+//!
+//! Command
+//! -------
+//!
+//! - `0`: Check if the driver is present.
+//! - `1`: Send an Echo Request with identifier `data1` and sequence number
+//!   `data2`. A process has one outstanding request, sending another one
+//!   replaces it.
+//!
+//! Upcall
+//! ------
+//!
+//! - `0`: The reply to the outstanding request arrived, or sending the request
+//!   failed. The arguments are the status, `(identifier << 16) | sequence`
+//!   and the round trip time in microseconds.
+
+use core::cell::Cell;
+use core::cmp;
+
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6Sender, IPSendClient};
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{Alarm, ConvertTicks, Ticks};
+use kernel::processbuffer::ReadableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Ping as usize;
+
+/// Size of the buffer for the data of echo messages. Longer requests are not
+/// answered.
+pub const BUFFER_LEN: usize = 128;
+
+const ALL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+
+/// IDs for subscribed upcalls.
+mod upcall {
+    /// The reply to the outstanding request arrived, or the request could not
+    /// be sent.
+    pub const REPLY: usize = 0;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// The destination address followed by the data of the request.
+    pub const REQUEST: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Receives the ICMPv6 errors about packets sent by the node.
+pub trait ICMP6ErrorClient {
+    /// A Destination Unreachable error with `code` arrived. `header` is the
+    /// IPv6 header of the packet that could not be delivered and `transport`
+    /// the start of its payload.
+    fn destination_unreachable(&self, code: u8, header: IP6Header, transport: &[u8]);
+}
+
+/// An outstanding Echo Request.
+#[derive(Copy, Clone)]
+struct Request<T: Ticks> {
+    id: u16,
+    seqno: u16,
+    sent: T,
+}
+
+pub struct App<T: Ticks> {
+    request: Option<Request<T>>,
+}
+
+impl<T: Ticks> Default for App<T> {
+    fn default() -> App<T> {
+        App { request: None }
+    }
+}
+
+pub struct ICMP6Echo<'a, A: Alarm<'a>> {
+    sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    apps: Grant<
+        App<A::Ticks>,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<0>,
+    >,
+    /// List of IP Addresses of the interfaces on the device
+    interface_list: &'static [Cell<IPAddr>],
+    buffer: TakeCell<'static, [u8]>,
+    /// Whether a message has been passed to the IPv6 sender and its
+    /// `send_done` is outstanding.
+    sending: Cell<bool>,
+    /// Process whose request is being sent.
+    current_app: OptionalCell<ProcessId>,
+    error_client: OptionalCell<&'a dyn ICMP6ErrorClient>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: Alarm<'a>> ICMP6Echo<'a, A> {
+    pub fn new(
+        sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        grant: Grant<
+            App<A::Ticks>,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<0>,
+        >,
+        interface_list: &'static [Cell<IPAddr>],
+        buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> Self {
+        Self {
+            sender,
+            alarm,
+            apps: grant,
+            interface_list,
+            buffer: TakeCell::new(buffer),
+            sending: Cell::new(false),
+            current_app: OptionalCell::empty(),
+            error_client: OptionalCell::empty(),
+            net_cap,
+        }
+    }
+
+    pub fn set_error_client(&self, client: &'a dyn ICMP6ErrorClient) {
+        self.error_client.set(client);
+    }
+
+    fn is_local(&self, addr: IPAddr) -> bool {
+        !addr.is_unspecified() && self.interface_list.iter().any(|a| a.get() == addr)
+    }
+
+    /// Returns the source address for messages that are not replies to a
+    /// unicast address. The IPv6 sender replaces a link-local address with a
+    /// global one for destinations off the link.
+    fn src_addr(&self) -> IPAddr {
+        let addresses = || self.interface_list.iter().map(|a| a.get());
+        addresses()
+            .find(|a| a.is_unicast_link_local())
+            .or_else(|| addresses().find(|a| !a.is_unspecified()))
+            .unwrap_or_else(IPAddr::new)
+    }
+
+    /// Sends an echo message whose data `fill` writes into the buffer.
+    fn send(
+        &self,
+        src: IPAddr,
+        dst: IPAddr,
+        options: ICMP6HeaderOptions,
+        fill: impl FnOnce(&mut [u8]) -> Result<usize, ErrorCode>,
+    ) -> Result<(), ErrorCode> {
+        if self.sending.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        let len = match fill(buffer) {
+            Ok(len) => len,
+            Err(e) => {
+                self.buffer.replace(buffer);
+                return Err(e);
+            }
+        };
+
+        let icmp_type = match options {
+            ICMP6HeaderOptions::Type128 { .. } => ICMP6Type::Type128,
+            _ => ICMP6Type::Type129,
+        };
+        let mut header = ICMP6Header::new(icmp_type);
+        header.set_options(options);
+        header.set_len((header.get_hdr_size() + len) as u16);
+
+        let mut payload = SubSliceMut::new(buffer);
+        payload.slice(..len);
+        self.sender.set_addr(src);
+        let result =
+            self.sender
+                .send_to(dst, TransportHeader::ICMP(header), &payload, self.net_cap);
+        payload.reset();
+        self.buffer.replace(payload.take());
+        if result.is_ok() {
+            self.sending.set(true);
+        }
+        result
+    }
+
+    fn send_request(&self, processid: ProcessId, id: u16, seqno: u16) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, kernel_data| {
+                let result = kernel_data
+                    .get_readonly_processbuffer(ro_allow::REQUEST)
+                    .and_then(|request| {
+                        request.enter(|request| {
+                            if request.len() < 16 {
+                                return Err(ErrorCode::INVAL);
+                            }
+                            let mut dst = IPAddr::new();
+                            request[..16].copy_to_slice(&mut dst.0);
+                            let data = &request[16..];
+                            self.send(
+                                self.src_addr(),
+                                dst,
+                                ICMP6HeaderOptions::Type128 { id, seqno },
+                                |buf| {
+                                    if data.len() > buf.len() {
+                                        return Err(ErrorCode::SIZE);
+                                    }
+                                    data.copy_to_slice(&mut buf[..data.len()]);
+                                    Ok(data.len())
+                                },
+                            )
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE));
+                result?;
+                app.request = Some(Request {
+                    id,
+                    seqno,
+                    sent: self.alarm.now(),
+                });
+                self.current_app.set(processid);
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn receive_request(&self, ip_header: &IP6Header, id: u16, seqno: u16, data: &[u8]) {
+        let dst = ip_header.get_dst_addr();
+        let src = if dst == ALL_NODES {
+            self.src_addr()
+        } else if self.is_local(dst) {
+            dst
+        } else {
+            return;
+        };
+        if data.len() > BUFFER_LEN {
+            return;
+        }
+        let _ = self.send(
+            src,
+            ip_header.get_src_addr(),
+            ICMP6HeaderOptions::Type129 { id, seqno },
+            |buf| {
+                buf[..data.len()].copy_from_slice(data);
+                Ok(data.len())
+            },
+        );
+    }
+
+    fn receive_reply(&self, id: u16, seqno: u16) {
+        let now = self.alarm.now();
+        for app in self.apps.iter() {
+            app.enter(|app, kernel_data| {
+                if let Some(request) = app.request {
+                    if request.id == id && request.seqno == seqno {
+                        app.request = None;
+                        let rtt = self.alarm.ticks_to_us(now.wrapping_sub(request.sent));
+                        let _ = kernel_data.schedule_upcall(
+                            upcall::REPLY,
+                            (
+                                0,
+                                (usize::from(id) << 16) | usize::from(seqno),
+                                rtt as usize,
+                            ),
+                        );
+                    }
+                }
+            });
+        }
+    }
+
+    fn receive_unreachable(&self, code: u8, body: &[u8]) {
+        if let Some((offset, header)) = IP6Header::decode(body).done() {
+            // The error carries as much of the packet as fits, so the
+            // payload may be shorter than the payload length of its header.
+            let end = cmp::min(body.len(), offset + usize::from(header.get_payload_len()));
+            self.error_client
+                .map(|client| client.destination_unreachable(code, header, &body[offset..end]));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6RecvClient for ICMP6Echo<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::ICMP {
+            return;
+        }
+        let Some((offset, icmp_header)) = ICMP6Header::decode(payload).done() else {
+            return;
+        };
+        let body = &payload[offset..];
+        let dst = ip_header.get_dst_addr();
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => {
+                self.receive_request(&ip_header, id, seqno, body)
+            }
+            ICMP6HeaderOptions::Type129 { id, seqno } if self.is_local(dst) => {
+                self.receive_reply(id, seqno)
+            }
+            ICMP6HeaderOptions::Type1 { .. } if self.is_local(dst) => {
+                self.receive_unreachable(icmp_header.get_code(), body)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> IPSendClient for ICMP6Echo<'a, A> {
+    fn send_done(&self, result: Result<(), ErrorCode>) {
+        self.sending.set(false);
+        if let Some(processid) = self.current_app.take() {
+            if let Err(e) = result {
+                let _ = self.apps.enter(processid, |app, kernel_data| {
+                    if let Some(request) = app.request.take() {
+                        let _ = kernel_data.schedule_upcall(
+                            upcall::REPLY,
+                            (
+                                kernel::errorcode::into_statuscode(Err(e)),
+                                (usize::from(request.id) << 16) | usize::from(request.seqno),
+                                0,
+                            ),
+                        );
+                    }
+                });
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> SyscallDriver for ICMP6Echo<'a, A> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            // check if present
+            0 => CommandReturn::success(),
+
+            // send an echo request
+            1 => self
+                .send_request(processid, data1 as u16, data2 as u16)
+                .into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -14,7 +14,7 @@
 
 use crate::net::icmpv6::ICMP6Header;
 use crate::net::ipv6::ip_utils::IPAddr;
-use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::ipv6_send::{IP6Sender, IPSendClient};
 use crate::net::ipv6::TransportHeader;
 use crate::net::network_capabilities::NetworkCapability;
 
@@ -97,7 +97,7 @@ impl<'a, T: IP6Sender<'a>> ICMP6Sender<'a> for ICMP6SendStruct<'a, T> {
     }
 }
 
-impl<'a, T: IP6Sender<'a>> IP6SendClient for ICMP6SendStruct<'a, T> {
+impl<'a, T: IP6Sender<'a>> IPSendClient for ICMP6SendStruct<'a, T> {
     /// Forwards callback received from the `IP6Sender` to the
     /// `ICMP6SendClient`.
     fn send_done(&self, result: Result<(), ErrorCode>) {
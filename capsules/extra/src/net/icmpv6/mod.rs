@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2022.
 
+pub mod icmpv6_echo;
 pub mod icmpv6_send;
 
 // Reexport the exports of the [`icmpv6`] module, to avoid redundant
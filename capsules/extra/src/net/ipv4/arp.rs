@@ -0,0 +1,141 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! This file contains the `ArpPacket` struct and its encoding and decoding
+//! (RFC 826), limited to IPv4 over Ethernet.
+
+use crate::net::ipv4::ip4_utils::{ethertype, EthernetAddr, IP4Addr};
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
+
+/// Length of an ARP packet for IPv4 over Ethernet.
+pub const ARP_LEN: usize = 28;
+
+const HTYPE_ETHERNET: u16 = 1;
+
+pub mod arp_op {
+    pub const REQUEST: u16 = 1;
+    pub const REPLY: u16 = 2;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ArpPacket {
+    pub op: u16,
+    pub sender_mac: EthernetAddr,
+    pub sender_addr: IP4Addr,
+    pub target_mac: EthernetAddr,
+    pub target_addr: IP4Addr,
+}
+
+impl ArpPacket {
+    /// This function decodes an ARP packet, failing if it is not for IPv4
+    /// over Ethernet.
+    pub fn decode(buf: &[u8]) -> SResult<ArpPacket> {
+        stream_len_cond!(buf, ARP_LEN);
+
+        let (off, htype) = dec_try!(buf, 0; decode_u16);
+        let (off, ptype) = dec_try!(buf, off; decode_u16);
+        let (off, hlen) = dec_try!(buf, off; decode_u8);
+        let (off, plen) = dec_try!(buf, off; decode_u8);
+        stream_cond!(htype == HTYPE_ETHERNET && ptype == ethertype::IP4 && hlen == 6 && plen == 4);
+
+        let mut packet = ArpPacket {
+            op: 0,
+            sender_mac: [0; 6],
+            sender_addr: IP4Addr::UNSPECIFIED,
+            target_mac: [0; 6],
+            target_addr: IP4Addr::UNSPECIFIED,
+        };
+        let (off, op) = dec_try!(buf, off; decode_u16);
+        packet.op = op;
+        let off = dec_consume!(buf, off; decode_bytes, &mut packet.sender_mac);
+        let off = dec_consume!(buf, off; decode_bytes, &mut packet.sender_addr.0);
+        let off = dec_consume!(buf, off; decode_bytes, &mut packet.target_mac);
+        let off = dec_consume!(buf, off; decode_bytes, &mut packet.target_addr.0);
+        stream_done!(off, packet);
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        stream_len_cond!(buf, ARP_LEN);
+
+        let mut off = enc_consume!(buf, 0; encode_u16, HTYPE_ETHERNET);
+        off = enc_consume!(buf, off; encode_u16, ethertype::IP4);
+        off = enc_consume!(buf, off; encode_u8, 6);
+        off = enc_consume!(buf, off; encode_u8, 4);
+        off = enc_consume!(buf, off; encode_u16, self.op);
+        off = enc_consume!(buf, off; encode_bytes, &self.sender_mac);
+        off = enc_consume!(buf, off; encode_bytes, &self.sender_addr.0);
+        off = enc_consume!(buf, off; encode_bytes, &self.target_mac);
+        off = enc_consume!(buf, off; encode_bytes, &self.target_addr.0);
+        stream_done!(off, off);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUEST: [u8; ARP_LEN] = [
+        0x00, 0x01, 0x08, 0x00, 6, 4, 0x00, 0x01, // Ethernet, IPv4, request
+        0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 10, 0, 0, 1, // sender
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 10, 0, 0, 2, // target
+    ];
+
+    #[test]
+    fn decode_request() {
+        let (off, packet) = ArpPacket::decode(&REQUEST).done().unwrap();
+        assert_eq!(off, ARP_LEN);
+        assert_eq!(packet.op, arp_op::REQUEST);
+        assert_eq!(packet.sender_mac, [0x02, 0, 0, 0, 0, 0x01]);
+        assert_eq!(packet.sender_addr, IP4Addr([10, 0, 0, 1]));
+        assert_eq!(packet.target_mac, [0; 6]);
+        assert_eq!(packet.target_addr, IP4Addr([10, 0, 0, 2]));
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let packet = ArpPacket {
+            op: arp_op::REPLY,
+            sender_mac: [0x02, 0, 0, 0, 0, 0x02],
+            sender_addr: IP4Addr([10, 0, 0, 2]),
+            target_mac: [0x02, 0, 0, 0, 0, 0x01],
+            target_addr: IP4Addr([10, 0, 0, 1]),
+        };
+        let mut buf = [0; ARP_LEN];
+        assert_eq!(packet.encode(&mut buf).done(), Some((ARP_LEN, ARP_LEN)));
+        assert_eq!(buf[..8], [0x00, 0x01, 0x08, 0x00, 6, 4, 0x00, 0x02]);
+
+        let (_, decoded) = ArpPacket::decode(&buf).done().unwrap();
+        assert_eq!(decoded.op, packet.op);
+        assert_eq!(decoded.sender_mac, packet.sender_mac);
+        assert_eq!(decoded.sender_addr, packet.sender_addr);
+        assert_eq!(decoded.target_mac, packet.target_mac);
+        assert_eq!(decoded.target_addr, packet.target_addr);
+
+        assert!(packet.encode(&mut [0; ARP_LEN - 1]).done().is_none());
+    }
+
+    #[test]
+    fn decode_truncated() {
+        assert!(ArpPacket::decode(&REQUEST[..ARP_LEN - 1]).done().is_none());
+        assert!(ArpPacket::decode(&[]).done().is_none());
+    }
+
+    #[test]
+    fn decode_other_protocols() {
+        // Hardware type other than Ethernet.
+        let mut packet = REQUEST;
+        packet[1] = 6;
+        assert!(ArpPacket::decode(&packet).done().is_none());
+        // IPv6 protocol type.
+        let mut packet = REQUEST;
+        packet[2..4].copy_from_slice(&[0x86, 0xdd]);
+        assert!(ArpPacket::decode(&packet).done().is_none());
+        // Address lengths.
+        let mut packet = REQUEST;
+        packet[5] = 16;
+        assert!(ArpPacket::decode(&packet).done().is_none());
+    }
+}
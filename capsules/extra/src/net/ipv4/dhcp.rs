@@ -0,0 +1,659 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! DHCP client for the IPv4 interface.
+//!
+//! This implements the client side of DHCP (RFC 2131) to configure an
+//! `IP4Configure` interface:
+//!
+//! - At start, the client broadcasts DHCPDISCOVER messages until a server
+//!   offers an address, and then requests the first offered address with a
+//!   DHCPREQUEST. Messages are retransmitted with exponential backoff.
+//! - A DHCPACK configures the interface with the address, the subnet mask and
+//!   the first router of the lease. Without a subnet mask option, the subnet
+//!   only contains the address.
+//! - At half of the lease time (T1), the client asks the server that granted
+//!   the lease to extend it, every minute until the lease ends. When the lease
+//!   ends, or the server answers with a DHCPNAK, the interface is
+//!   unconfigured and the client starts over.
+//!
+//! Rebinding through other servers (T2) and checking offered addresses with
+//! ARP are not implemented.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! // The component starts the client.
+//! let dhcp_client = components::ipv4::DhcpClientComponent::new(
+//!     ip4_interface,
+//!     udp_send_mux,
+//!     udp_recv_mux,
+//!     udp_port_table,
+//!     mux_alarm,
+//! )
+//! .finalize(components::dhcp_client_component_static!(
+//!     qemu_rv32_virt_chip::chip::QemuRv32VirtClint,
+//!     VirtIONet<'static>,
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use crate::net::ipv4::ip4_interface::{IP4Config, IP4Configure};
+use crate::net::ipv4::ip4_utils::{EthernetAddr, IP4Addr};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+pub const DHCP_CLIENT_PORT: u16 = 68;
+pub const DHCP_SERVER_PORT: u16 = 67;
+
+/// Length of the messages sent by the client, which is the minimum length of
+/// BOOTP messages.
+pub const DHCP_MSG_LEN: usize = 300;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+/// Asks servers to broadcast their replies.
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Offset of the options, after the fixed fields and the magic cookie.
+const OPTIONS_OFFSET: usize = 240;
+
+mod dhcp_option {
+    pub const PAD: u8 = 0;
+    pub const SUBNET_MASK: u8 = 1;
+    pub const ROUTER: u8 = 3;
+    pub const REQUESTED_ADDR: u8 = 50;
+    pub const LEASE_TIME: u8 = 51;
+    pub const MESSAGE_TYPE: u8 = 53;
+    pub const SERVER_ID: u8 = 54;
+    pub const PARAMETER_REQUEST: u8 = 55;
+    pub const RENEWAL_TIME: u8 = 58;
+    pub const END: u8 = 255;
+}
+
+mod message_type {
+    pub const DISCOVER: u8 = 1;
+    pub const OFFER: u8 = 2;
+    pub const REQUEST: u8 = 3;
+    pub const ACK: u8 = 5;
+    pub const NAK: u8 = 6;
+}
+
+/// Delay before the first retransmission, in seconds.
+const INITIAL_RETRANSMIT_DELAY: u32 = 4;
+/// Maximum delay between retransmissions, in seconds.
+const MAX_RETRANSMIT_DELAY: u32 = 64;
+/// Number of DHCPREQUEST messages sent for an offer before starting over.
+const REQUEST_ATTEMPTS: u8 = 4;
+/// Time between DHCPREQUEST messages to renew a lease, in seconds. This is
+/// also the longest interval of the alarm, which keeps the clock in seconds
+/// from missing wraparounds of the alarm ticks.
+const RENEW_INTERVAL: u32 = 60;
+/// Lease time of leases without lease time option.
+const INFINITE_LEASE: u32 = u32::MAX;
+
+#[derive(Clone, Copy, PartialEq)]
+struct Lease {
+    server: IP4Addr,
+    config: IP4Config,
+    /// Times at which the lease is renewed and ends, in seconds.
+    renew_at: u32,
+    expires_at: u32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Discovering servers, `attempt` is the number of the last DHCPDISCOVER.
+    Selecting {
+        attempt: u8,
+    },
+    /// Requesting the address `addr` offered by `server`.
+    Requesting {
+        server: IP4Addr,
+        addr: IP4Addr,
+        attempt: u8,
+    },
+    Bound(Lease),
+    /// Asking the server of the lease to extend it.
+    Renewing(Lease),
+}
+
+/// The fields of a received DHCP message that the client uses.
+struct Reply {
+    message_type: u8,
+    yiaddr: IP4Addr,
+    server: Option<IP4Addr>,
+    netmask: Option<IP4Addr>,
+    router: Option<IP4Addr>,
+    lease_time: Option<u32>,
+    renewal_time: Option<u32>,
+}
+
+pub struct DhcpClient<'a, A: Alarm<'a>> {
+    interface: &'a dyn IP4Configure,
+    udp_sender: &'a dyn UDPSender<'a>,
+    alarm: &'a A,
+    buffer: MapCell<SubSliceMut<'static, u8>>,
+    net_cap: &'static NetworkCapability,
+    state: Cell<State>,
+    /// Transaction ID of the messages of the current exchange.
+    xid: Cell<u32>,
+    /// Seconds since start, advanced from the alarm ticks.
+    seconds: Cell<u32>,
+    last_tick: OptionalCell<A::Ticks>,
+}
+
+impl<'a, A: Alarm<'a>> DhcpClient<'a, A> {
+    pub fn new(
+        interface: &'a dyn IP4Configure,
+        udp_sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        buffer: SubSliceMut<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Self {
+        Self {
+            interface,
+            udp_sender,
+            alarm,
+            buffer: MapCell::new(buffer),
+            net_cap,
+            state: Cell::new(State::Idle),
+            xid: Cell::new(0),
+            seconds: Cell::new(0),
+            last_tick: OptionalCell::empty(),
+        }
+    }
+
+    /// Starts discovering DHCP servers.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.last_tick.set(self.alarm.now());
+        self.discover(0);
+        Ok(())
+    }
+
+    fn now(&self) -> u32 {
+        if let Some(last) = self.last_tick.get() {
+            let seconds = self
+                .alarm
+                .ticks_to_seconds(self.alarm.now().wrapping_sub(last));
+            if seconds > 0 {
+                self.seconds.set(self.seconds.get().wrapping_add(seconds));
+                self.last_tick
+                    .set(last.wrapping_add(self.alarm.ticks_from_seconds(seconds)));
+            }
+        }
+        self.seconds.get()
+    }
+
+    fn set_alarm(&self, seconds: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_seconds(seconds));
+    }
+
+    fn retransmit_delay(attempt: u8) -> u32 {
+        // 4 << 4 is the maximum delay.
+        (INITIAL_RETRANSMIT_DELAY << attempt.min(4)).min(MAX_RETRANSMIT_DELAY)
+    }
+
+    fn discover(&self, attempt: u8) {
+        if attempt == 0 {
+            // Transaction IDs should be hard to guess for other hosts, so
+            // they are derived from the time as well as the MAC address.
+            let mac = self.interface.get_mac_address();
+            let xid =
+                u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]) ^ self.alarm.now().into_u32();
+            self.xid.set(xid.wrapping_add(self.xid.get()));
+        }
+        self.state.set(State::Selecting { attempt });
+        self.send(message_type::DISCOVER, None, None);
+        self.set_alarm(Self::retransmit_delay(attempt));
+    }
+
+    fn request(&self, server: IP4Addr, addr: IP4Addr, attempt: u8) {
+        self.state.set(State::Requesting {
+            server,
+            addr,
+            attempt,
+        });
+        self.send(message_type::REQUEST, Some(server), Some(addr));
+        self.set_alarm(Self::retransmit_delay(attempt));
+    }
+
+    fn renew(&self, lease: Lease) {
+        self.state.set(State::Renewing(lease));
+        self.send(message_type::REQUEST, None, None);
+        let remaining = lease.expires_at.saturating_sub(self.now());
+        self.set_alarm(remaining.clamp(1, RENEW_INTERVAL));
+    }
+
+    fn restart(&self) {
+        self.interface.set_config(None);
+        self.discover(0);
+    }
+
+    fn bind(&self, reply: Reply, server: IP4Addr) {
+        let now = self.now();
+        let lease_time = reply.lease_time.unwrap_or(INFINITE_LEASE);
+        let (renew_at, expires_at) = if lease_time == INFINITE_LEASE {
+            (u32::MAX, u32::MAX)
+        } else {
+            let renewal_time = reply
+                .renewal_time
+                .filter(|&t| t < lease_time)
+                .unwrap_or(lease_time / 2);
+            (
+                now.saturating_add(renewal_time),
+                now.saturating_add(lease_time),
+            )
+        };
+        let config = IP4Config {
+            addr: reply.yiaddr,
+            netmask: reply.netmask.unwrap_or(IP4Addr::BROADCAST),
+            gateway: reply.router.unwrap_or(IP4Addr::UNSPECIFIED),
+        };
+        self.interface.set_config(Some(config));
+        self.state.set(State::Bound(Lease {
+            server,
+            config,
+            renew_at,
+            expires_at,
+        }));
+        self.set_alarm(renew_at.saturating_sub(now).clamp(1, RENEW_INTERVAL));
+    }
+
+    /// Sends a message of `msg_type`. Messages are broadcast, except the
+    /// ones renewing a lease, which are sent to its server from its address.
+    fn send(&self, msg_type: u8, server: Option<IP4Addr>, requested: Option<IP4Addr>) {
+        let (dst, ciaddr) = match self.state.get() {
+            State::Renewing(lease) => (lease.server, lease.config.addr),
+            _ => (IP4Addr::BROADCAST, IP4Addr::UNSPECIFIED),
+        };
+        let mac = self.interface.get_mac_address();
+        let xid = self.xid.get();
+
+        // Messages are not queued; if the previous one is still being sent,
+        // this one is sent with the next retransmission.
+        if let Some(mut buf) = self.buffer.take() {
+            buf.reset();
+            write_message(
+                buf.as_mut_slice(),
+                msg_type,
+                xid,
+                mac,
+                ciaddr,
+                server,
+                requested,
+            );
+
+            if let Err(mut buf) =
+                self.udp_sender
+                    .send_to(dst.to_mapped(), DHCP_SERVER_PORT, buf, self.net_cap)
+            {
+                buf.reset();
+                self.buffer.replace(buf);
+            }
+        }
+    }
+
+    /// Parses a reply to the current exchange.
+    fn parse(&self, msg: &[u8]) -> Option<Reply> {
+        parse_reply(msg, self.xid.get(), self.interface.get_mac_address())
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPRecvClient for DhcpClient<'a, A> {
+    fn receive(
+        &self,
+        _src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        if src_port != DHCP_SERVER_PORT {
+            return;
+        }
+        let reply = match self.parse(payload) {
+            Some(reply) => reply,
+            None => return,
+        };
+        match (self.state.get(), reply.message_type) {
+            (State::Selecting { .. }, message_type::OFFER) => {
+                if let Some(server) = reply.server {
+                    self.request(server, reply.yiaddr, 0);
+                }
+            }
+            (State::Requesting { server, addr, .. }, message_type::ACK) => {
+                if reply.yiaddr == addr {
+                    self.bind(reply, server);
+                }
+            }
+            (State::Renewing(lease), message_type::ACK) => {
+                if reply.yiaddr == lease.config.addr {
+                    self.bind(reply, lease.server);
+                }
+            }
+            (State::Requesting { .. } | State::Renewing(_), message_type::NAK) => self.restart(),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> UDPSendClient for DhcpClient<'a, A> {
+    fn send_done(&self, _result: Result<(), ErrorCode>, mut dgram: SubSliceMut<'static, u8>) {
+        // Lost messages are retransmitted.
+        dgram.reset();
+        self.buffer.replace(dgram);
+    }
+}
+
+/// Writes a client message of `msg_type` into `msg`, which must be at least
+/// `DHCP_MSG_LEN` long.
+fn write_message(
+    msg: &mut [u8],
+    msg_type: u8,
+    xid: u32,
+    mac: EthernetAddr,
+    ciaddr: IP4Addr,
+    server: Option<IP4Addr>,
+    requested: Option<IP4Addr>,
+) {
+    msg.fill(0);
+    msg[0] = OP_REQUEST;
+    msg[1] = HTYPE_ETHERNET;
+    msg[2] = mac.len() as u8;
+    msg[4..8].copy_from_slice(&xid.to_be_bytes());
+    if ciaddr.is_unspecified() {
+        msg[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+    }
+    msg[12..16].copy_from_slice(&ciaddr.0);
+    msg[28..34].copy_from_slice(&mac);
+    msg[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+    let mut off = OPTIONS_OFFSET;
+    let mut option = |code: u8, value: &[u8]| {
+        msg[off] = code;
+        msg[off + 1] = value.len() as u8;
+        msg[off + 2..off + 2 + value.len()].copy_from_slice(value);
+        off += 2 + value.len();
+    };
+    option(dhcp_option::MESSAGE_TYPE, &[msg_type]);
+    if let Some(requested) = requested {
+        option(dhcp_option::REQUESTED_ADDR, &requested.0);
+    }
+    if let Some(server) = server {
+        option(dhcp_option::SERVER_ID, &server.0);
+    }
+    option(
+        dhcp_option::PARAMETER_REQUEST,
+        &[
+            dhcp_option::SUBNET_MASK,
+            dhcp_option::ROUTER,
+            dhcp_option::LEASE_TIME,
+        ],
+    );
+    msg[off] = dhcp_option::END;
+}
+
+/// Parses a reply with transaction ID `xid` to the client with address
+/// `mac`.
+fn parse_reply(msg: &[u8], xid: u32, mac: EthernetAddr) -> Option<Reply> {
+    if msg.len() < OPTIONS_OFFSET
+        || msg[0] != OP_REPLY
+        || msg[4..8] != xid.to_be_bytes()
+        || msg[28..34] != mac
+        || msg[236..240] != MAGIC_COOKIE
+    {
+        return None;
+    }
+    let mut reply = Reply {
+        message_type: 0,
+        yiaddr: IP4Addr::UNSPECIFIED,
+        server: None,
+        netmask: None,
+        router: None,
+        lease_time: None,
+        renewal_time: None,
+    };
+    reply.yiaddr.0.copy_from_slice(&msg[16..20]);
+
+    let addr =
+        |value: &[u8]| -> Option<IP4Addr> { Some(IP4Addr(value.get(0..4)?.try_into().ok()?)) };
+    let time = |value: &[u8]| -> Option<u32> {
+        Some(u32::from_be_bytes(value.get(0..4)?.try_into().ok()?))
+    };
+    let mut options = &msg[OPTIONS_OFFSET..];
+    while let Some(&code) = options.first() {
+        match code {
+            dhcp_option::PAD => {
+                options = &options[1..];
+                continue;
+            }
+            dhcp_option::END => break,
+            _ => {}
+        }
+        let len = *options.get(1)? as usize;
+        let value = options.get(2..2 + len)?;
+        match code {
+            dhcp_option::MESSAGE_TYPE => reply.message_type = *value.first()?,
+            dhcp_option::SERVER_ID => reply.server = addr(value),
+            dhcp_option::SUBNET_MASK => reply.netmask = addr(value),
+            // The first router is used as gateway.
+            dhcp_option::ROUTER => reply.router = addr(value),
+            dhcp_option::LEASE_TIME => reply.lease_time = time(value),
+            dhcp_option::RENEWAL_TIME => reply.renewal_time = time(value),
+            _ => {}
+        }
+        options = &options[2 + len..];
+    }
+    Some(reply)
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for DhcpClient<'a, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Idle => {}
+            State::Selecting { attempt } => self.discover(attempt.saturating_add(1)),
+            State::Requesting {
+                server,
+                addr,
+                attempt,
+            } => {
+                if attempt + 1 < REQUEST_ATTEMPTS {
+                    self.request(server, addr, attempt + 1);
+                } else {
+                    self.discover(0);
+                }
+            }
+            State::Bound(lease) | State::Renewing(lease) => {
+                let now = self.now();
+                if now >= lease.expires_at {
+                    self.restart();
+                } else if now >= lease.renew_at {
+                    self.renew(lease);
+                } else {
+                    self.set_alarm((lease.renew_at - now).min(RENEW_INTERVAL));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XID: u32 = 0x1234_5678;
+    const MAC: EthernetAddr = [0x02, 0, 0, 0, 0, 0x01];
+    const SERVER: IP4Addr = IP4Addr([10, 0, 0, 1]);
+    const ADDR: IP4Addr = IP4Addr([10, 0, 0, 2]);
+
+    /// A reply of the server to the client, with `options` after the magic
+    /// cookie.
+    fn message(options: &[u8]) -> [u8; DHCP_MSG_LEN] {
+        let mut msg = [0; DHCP_MSG_LEN];
+        msg[0] = OP_REPLY;
+        msg[1] = HTYPE_ETHERNET;
+        msg[2] = 6;
+        msg[4..8].copy_from_slice(&XID.to_be_bytes());
+        msg[16..20].copy_from_slice(&ADDR.0);
+        msg[28..34].copy_from_slice(&MAC);
+        msg[236..240].copy_from_slice(&MAGIC_COOKIE);
+        msg[OPTIONS_OFFSET..OPTIONS_OFFSET + options.len()].copy_from_slice(options);
+        msg
+    }
+
+    #[test]
+    fn write_discover() {
+        let mut msg = [0xaa; DHCP_MSG_LEN];
+        write_message(
+            &mut msg,
+            message_type::DISCOVER,
+            XID,
+            MAC,
+            IP4Addr::UNSPECIFIED,
+            None,
+            None,
+        );
+        assert_eq!(
+            msg[..12],
+            [1, 1, 6, 0, 0x12, 0x34, 0x56, 0x78, 0, 0, 0x80, 0]
+        );
+        assert_eq!(msg[12..28], [0; 16]);
+        assert_eq!(msg[28..34], MAC);
+        assert_eq!(msg[236..240], MAGIC_COOKIE);
+        assert_eq!(
+            msg[OPTIONS_OFFSET..OPTIONS_OFFSET + 9],
+            [53, 1, message_type::DISCOVER, 55, 3, 1, 3, 51, 255]
+        );
+        assert!(msg[OPTIONS_OFFSET + 9..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn write_request() {
+        #[rustfmt::skip]
+        const OPTIONS: &[u8] = &[
+            53, 1, message_type::REQUEST,
+            50, 4, 10, 0, 0, 2, // requested address
+            54, 4, 10, 0, 0, 1, // server
+            55, 3, 1, 3, 51, // parameters
+            255,
+        ];
+        let mut msg = [0; DHCP_MSG_LEN];
+        write_message(
+            &mut msg,
+            message_type::REQUEST,
+            XID,
+            MAC,
+            ADDR,
+            Some(SERVER),
+            Some(ADDR),
+        );
+        // Renewing clients do not ask for broadcast replies.
+        assert_eq!(msg[10..12], [0, 0]);
+        assert_eq!(msg[12..16], ADDR.0);
+        assert_eq!(
+            msg[OPTIONS_OFFSET..OPTIONS_OFFSET + OPTIONS.len()],
+            *OPTIONS
+        );
+    }
+
+    #[test]
+    fn parse_ack() {
+        #[rustfmt::skip]
+        const OPTIONS: &[u8] = &[
+            53, 1, message_type::ACK,
+            54, 4, 10, 0, 0, 1, // server
+            0, 0, // padding
+            1, 4, 255, 255, 255, 0, // subnet mask
+            3, 8, 10, 0, 0, 254, 10, 0, 0, 253, // routers
+            51, 4, 0, 0, 0x0e, 0x10, // lease time
+            58, 4, 0, 0, 0x07, 0x08, // renewal time
+            12, 3, b'a', b'b', b'c', // host name
+            255,
+        ];
+        let reply = parse_reply(&message(OPTIONS), XID, MAC).unwrap();
+        assert_eq!(reply.message_type, message_type::ACK);
+        assert_eq!(reply.yiaddr, ADDR);
+        assert_eq!(reply.server, Some(SERVER));
+        assert_eq!(reply.netmask, Some(IP4Addr([255, 255, 255, 0])));
+        assert_eq!(reply.router, Some(IP4Addr([10, 0, 0, 254])));
+        assert_eq!(reply.lease_time, Some(3600));
+        assert_eq!(reply.renewal_time, Some(1800));
+    }
+
+    #[test]
+    fn parse_stops_at_end() {
+        let msg = message(&[53, 1, message_type::NAK, 255, 54, 4, 10, 0, 0, 1]);
+        let reply = parse_reply(&msg, XID, MAC).unwrap();
+        assert_eq!(reply.message_type, message_type::NAK);
+        assert_eq!(reply.server, None);
+        assert_eq!(reply.netmask, None);
+        assert_eq!(reply.lease_time, None);
+    }
+
+    #[test]
+    fn parse_short_options() {
+        // Options too short for their values are ignored.
+        #[rustfmt::skip]
+        const OPTIONS: &[u8] = &[
+            53, 1, message_type::OFFER,
+            54, 2, 10, 0, // server
+            51, 3, 0, 0, 1, // lease time
+            255,
+        ];
+        let msg = message(OPTIONS);
+        let reply = parse_reply(&msg, XID, MAC).unwrap();
+        assert_eq!(reply.message_type, message_type::OFFER);
+        assert_eq!(reply.server, None);
+        assert_eq!(reply.lease_time, None);
+
+        // An empty message type is not.
+        let msg = message(&[53, 0, 255]);
+        assert!(parse_reply(&msg, XID, MAC).is_none());
+    }
+
+    #[test]
+    fn parse_truncated() {
+        let msg = message(&[53, 1, message_type::ACK, 255]);
+        assert!(parse_reply(&msg[..OPTIONS_OFFSET - 1], XID, MAC).is_none());
+
+        // Without an end option, the options end with the message.
+        let reply = parse_reply(&msg[..OPTIONS_OFFSET + 3], XID, MAC).unwrap();
+        assert_eq!(reply.message_type, message_type::ACK);
+
+        // Options that run past the end of the message.
+        assert!(parse_reply(&msg[..OPTIONS_OFFSET + 2], XID, MAC).is_none());
+        assert!(parse_reply(&msg[..OPTIONS_OFFSET + 1], XID, MAC).is_none());
+    }
+
+    #[test]
+    fn parse_other_messages() {
+        let msg = message(&[53, 1, message_type::ACK, 255]);
+        assert!(parse_reply(&msg, XID, MAC).is_some());
+        assert!(parse_reply(&msg, XID + 1, MAC).is_none());
+        assert!(parse_reply(&msg, XID, [0x02, 0, 0, 0, 0, 0x02]).is_none());
+
+        let mut request = msg;
+        request[0] = OP_REQUEST;
+        assert!(parse_reply(&request, XID, MAC).is_none());
+        let mut bad_cookie = msg;
+        bad_cookie[239] = 0;
+        assert!(parse_reply(&bad_cookie, XID, MAC).is_none());
+    }
+}
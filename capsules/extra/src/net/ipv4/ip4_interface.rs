@@ -0,0 +1,824 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! IPv4 interface over an Ethernet adapter.
+//!
+//! This implements a minimal IPv4 host (RFC 1122) on top of the
+//! `EthernetAdapterDatapath` HIL:
+//!
+//! - The interface is configured with an address, a netmask and an optional
+//!   gateway through the `IP4Configure` trait, either statically by the board
+//!   or by the DHCP client (see `dhcp.rs`). Until it is configured, it only
+//!   sends broadcasts and accepts packets to any address.
+//! - The MAC addresses of next hops are resolved with ARP (RFC 826) and kept
+//!   in an ARP cache, which replaces the least recently updated entry when it
+//!   is full. Entries expire after 5 minutes. ARP requests for the address of
+//!   the interface are answered.
+//! - ICMP echo requests are answered.
+//! - Received UDP packets are checked and passed to the UDP layer.
+//!   Fragmented packets are dropped.
+//!
+//! The interface implements `IPSender` and passes received packets to an
+//! `IPRecvClient`, the IP version independent sides of the IPv6 sender and
+//! receiver traits, so the UDP layer of the IPv6 stack (`MuxUdpSender`,
+//! `MuxUdpReceiver`, the port table and the userspace UDP driver) works on
+//! top of it unchanged. Its IPv6 addresses are IPv4-mapped addresses
+//! (`::ffff:a.b.c.d`), so network capabilities apply to IPv4 addresses in
+//! that form.
+//!
+//! The interface sends one UDP packet at a time, further packets wait in the
+//! UDP layer while the next hop of the current one is resolved. ARP messages
+//! and echo replies are sent from a separate buffer; requests that arrive
+//! while it is in use are not answered, which their senders recover from by
+//! retrying.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let (ip4_interface, udp_send_mux, udp_recv_mux, udp_port_table) =
+//!     components::ipv4::IP4InterfaceComponent::new(
+//!         ethernet_device,
+//!         mac_address,
+//!         local_ip_ifaces,
+//!         mux_alarm,
+//!     )
+//!     .finalize(components::ip4_interface_component_static!(
+//!         qemu_rv32_virt_chip::chip::QemuRv32VirtClint,
+//!         VirtIONet<'static>,
+//!         8
+//!     ));
+//! ```
+
+use core::cell::Cell;
+
+use crate::net::ipv4::arp::{arp_op, ArpPacket, ARP_LEN};
+use crate::net::ipv4::ip4_utils::{
+    checksum_add, checksum_finish, ethertype, ip4_proto, pseudo_header_sum, EthernetAddr, IP4Addr,
+    ETHERNET_BROADCAST, ETHERNET_HDR_LEN,
+};
+use crate::net::ipv4::{IP4Header, IP4_HDR_LEN};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_recv::IPRecvClient;
+use crate::net::ipv6::ipv6_send::{IPSendClient, IPSender};
+use crate::net::ipv6::{TransportHeader, UDP_HDR_LEN};
+use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
+use crate::net::udp::UDPHeader;
+
+use kernel::hil::ethernet::{EthernetAdapterDatapath, EthernetAdapterDatapathClient};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// Maximum length of an Ethernet frame without frame check sequence, which
+/// the frame buffers of the interface should have.
+pub const MAX_FRAME_LEN: usize = 1514;
+
+/// Frames are padded to the minimum Ethernet frame length.
+const MIN_FRAME_LEN: usize = 60;
+
+/// Time between ARP requests for a next hop, in seconds.
+const ARP_RETRY_INTERVAL: u32 = 1;
+/// Number of ARP requests sent before a packet is dropped.
+const ARP_ATTEMPTS: u8 = 3;
+/// Time after which ARP cache entries expire, in seconds.
+const ARP_CACHE_TIMEOUT: u32 = 300;
+/// Longest interval of the alarm, which keeps the clock in seconds from
+/// missing wraparounds of the alarm ticks.
+const MAX_ALARM_INTERVAL: u32 = 60;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+// Transmission identifiers of the frame buffers.
+const PACKET_FRAME: usize = 0;
+const CONTROL_FRAME: usize = 1;
+
+/// Configuration of the IPv4 interface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IP4Config {
+    pub addr: IP4Addr,
+    pub netmask: IP4Addr,
+    /// The default gateway, unspecified if there is none.
+    pub gateway: IP4Addr,
+}
+
+/// Configuration interface of the IPv4 interface, used by the board and the
+/// DHCP client.
+pub trait IP4Configure {
+    /// Sets the configuration of the interface, or removes it with `None`.
+    fn set_config(&self, config: Option<IP4Config>);
+
+    fn get_config(&self) -> Option<IP4Config>;
+
+    fn get_mac_address(&self) -> EthernetAddr;
+}
+
+/// An entry of the ARP cache.
+#[derive(Clone, Copy)]
+pub struct ArpEntry {
+    addr: IP4Addr,
+    mac: EthernetAddr,
+    /// Time the entry was last updated, in seconds.
+    updated: u32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TxState {
+    Idle,
+    /// The packet waits for the MAC address of `next_hop`, for which
+    /// `attempts` ARP requests were made.
+    Resolving {
+        next_hop: IP4Addr,
+        attempts: u8,
+    },
+    /// The packet waits for the adapter.
+    Queued,
+    Transmitting,
+}
+
+pub struct IP4Interface<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> {
+    ethernet: &'a E,
+    alarm: &'a A,
+    mac: EthernetAddr,
+    config: Cell<Option<IP4Config>>,
+    /// Interface list entry holding the IPv4-mapped address of the
+    /// interface.
+    interface: &'a Cell<IPAddr>,
+    arp_cache: &'a [Cell<Option<ArpEntry>>],
+    /// Frame of the UDP packet being sent.
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_len: Cell<u16>,
+    tx_state: Cell<TxState>,
+    /// Frame of ARP messages and echo replies.
+    control_buf: TakeCell<'static, [u8]>,
+    control_len: Cell<u16>,
+    control_queued: Cell<bool>,
+    /// Whether the adapter is transmitting a frame.
+    transmitting: Cell<bool>,
+    ip_id: Cell<u16>,
+    send_client: OptionalCell<&'a dyn IPSendClient>,
+    recv_client: OptionalCell<&'a dyn IPRecvClient>,
+    ip_vis: &'static IpVisibilityCapability,
+    /// Seconds since start, advanced from the alarm ticks.
+    seconds: Cell<u32>,
+    last_tick: OptionalCell<A::Ticks>,
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> IP4Interface<'a, E, A> {
+    pub fn new(
+        ethernet: &'a E,
+        alarm: &'a A,
+        mac: EthernetAddr,
+        interface: &'a Cell<IPAddr>,
+        arp_cache: &'a [Cell<Option<ArpEntry>>],
+        tx_buf: &'static mut [u8],
+        control_buf: &'static mut [u8],
+        ip_vis: &'static IpVisibilityCapability,
+    ) -> Self {
+        Self {
+            ethernet,
+            alarm,
+            mac,
+            config: Cell::new(None),
+            interface,
+            arp_cache,
+            tx_buf: TakeCell::new(tx_buf),
+            tx_len: Cell::new(0),
+            tx_state: Cell::new(TxState::Idle),
+            control_buf: TakeCell::new(control_buf),
+            control_len: Cell::new(0),
+            control_queued: Cell::new(false),
+            transmitting: Cell::new(false),
+            ip_id: Cell::new(0),
+            send_client: OptionalCell::empty(),
+            recv_client: OptionalCell::empty(),
+            ip_vis,
+            seconds: Cell::new(0),
+            last_tick: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the client receiving the UDP packets addressed to the interface,
+    /// usually the `MuxUdpReceiver`.
+    pub fn set_receive_client(&self, client: &'a dyn IPRecvClient) {
+        self.recv_client.set(client);
+    }
+
+    fn now(&self) -> u32 {
+        let now = self.alarm.now();
+        match self.last_tick.get() {
+            Some(last) => {
+                let seconds = self.alarm.ticks_to_seconds(now.wrapping_sub(last));
+                if seconds > 0 {
+                    self.seconds.set(self.seconds.get().wrapping_add(seconds));
+                    self.last_tick
+                        .set(last.wrapping_add(self.alarm.ticks_from_seconds(seconds)));
+                }
+            }
+            None => self.last_tick.set(now),
+        }
+        self.seconds.get()
+    }
+
+    fn set_alarm(&self, seconds: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_seconds(seconds));
+    }
+
+    /// Schedules the next ARP request, or the expiry of ARP cache entries.
+    fn schedule_alarm(&self) {
+        if let TxState::Resolving { .. } = self.tx_state.get() {
+            self.set_alarm(ARP_RETRY_INTERVAL);
+        } else if self.arp_cache.iter().any(|e| e.get().is_some()) {
+            self.set_alarm(MAX_ALARM_INTERVAL);
+        } else {
+            let _ = self.alarm.disarm();
+        }
+    }
+
+    fn next_ip_id(&self) -> u16 {
+        let id = self.ip_id.get();
+        self.ip_id.set(id.wrapping_add(1));
+        id
+    }
+
+    fn lookup(&self, addr: IP4Addr) -> Option<EthernetAddr> {
+        self.arp_cache
+            .iter()
+            .find_map(|e| e.get().filter(|e| e.addr == addr).map(|e| e.mac))
+    }
+
+    /// Updates the ARP cache entry of `addr`, adding one if `create` is set.
+    fn learn(&self, addr: IP4Addr, mac: EthernetAddr, create: bool) {
+        let updated = self.now();
+        let existing = self
+            .arp_cache
+            .iter()
+            .find(|e| e.get().is_some_and(|e| e.addr == addr));
+        let slot = match existing {
+            Some(slot) => Some(slot),
+            None if create => self
+                .arp_cache
+                .iter()
+                .find(|e| e.get().is_none())
+                .or_else(|| {
+                    // Replace the least recently updated entry.
+                    self.arp_cache
+                        .iter()
+                        .max_by_key(|e| e.get().map_or(0, |e| updated.wrapping_sub(e.updated)))
+                }),
+            None => None,
+        };
+        if let Some(slot) = slot {
+            slot.set(Some(ArpEntry { addr, mac, updated }));
+            if !self.alarm.is_armed() {
+                self.set_alarm(MAX_ALARM_INTERVAL);
+            }
+        }
+
+        // The packet waiting for this address can be sent now.
+        if let TxState::Resolving { next_hop, .. } = self.tx_state.get() {
+            if next_hop == addr {
+                self.tx_buf.map(|buf| buf[0..6].copy_from_slice(&mac));
+                self.tx_state.set(TxState::Queued);
+                self.transmit_queued();
+            }
+        }
+    }
+
+    fn expire(&self) {
+        let now = self.now();
+        for entry in self.arp_cache {
+            if entry
+                .get()
+                .is_some_and(|e| now.wrapping_sub(e.updated) >= ARP_CACHE_TIMEOUT)
+            {
+                entry.set(None);
+            }
+        }
+    }
+
+    /// Writes the Ethernet header of a frame.
+    fn write_ethernet_header(&self, buf: &mut [u8], dst: EthernetAddr, ethertype: u16) {
+        buf[0..6].copy_from_slice(&dst);
+        buf[6..12].copy_from_slice(&self.mac);
+        buf[12..14].copy_from_slice(&ethertype.to_be_bytes());
+    }
+
+    /// Sends an ARP message from the control buffer, unless it is in use.
+    fn send_arp(&self, op: u16, target_mac: EthernetAddr, target_addr: IP4Addr) {
+        let sender_addr = self
+            .config
+            .get()
+            .map_or(IP4Addr::UNSPECIFIED, |config| config.addr);
+        let dst = match op {
+            arp_op::REQUEST => ETHERNET_BROADCAST,
+            _ => target_mac,
+        };
+        let packet = ArpPacket {
+            op,
+            sender_mac: self.mac,
+            sender_addr,
+            target_mac,
+            target_addr,
+        };
+        self.send_control(|buf| {
+            self.write_ethernet_header(buf, dst, ethertype::ARP);
+            packet
+                .encode(&mut buf[ETHERNET_HDR_LEN..])
+                .done()
+                .map(|_| ETHERNET_HDR_LEN + ARP_LEN)
+        })
+    }
+
+    /// Writes a frame into the control buffer with `write`, which returns
+    /// its length, and queues it. Nothing is sent if the control buffer is
+    /// in use or the frame does not fit.
+    fn send_control<F: FnOnce(&mut [u8]) -> Option<usize>>(&self, write: F) {
+        if self.control_queued.get() {
+            return;
+        }
+        let len = self
+            .control_buf
+            .map(|buf| write(buf).map(|len| pad_frame(buf, len)))
+            .flatten();
+        if let Some(len) = len {
+            self.control_len.set(len as u16);
+            self.control_queued.set(true);
+            self.transmit_queued();
+        }
+    }
+
+    /// Passes the next queued frame to the adapter, if it is not
+    /// transmitting. Returns the error of the packet frame if it could not
+    /// be transmitted.
+    fn transmit_next(&self) -> Result<(), ErrorCode> {
+        if self.transmitting.get() {
+            return Ok(());
+        }
+        if self.control_queued.get() {
+            if let Some(buf) = self.control_buf.take() {
+                self.control_queued.set(false);
+                match self
+                    .ethernet
+                    .transmit_frame(buf, self.control_len.get(), CONTROL_FRAME)
+                {
+                    Ok(()) => {
+                        self.transmitting.set(true);
+                        return Ok(());
+                    }
+                    // The message is dropped, like one lost on the link.
+                    Err((_, buf)) => self.control_buf.replace(buf),
+                };
+            }
+        }
+        if self.tx_state.get() == TxState::Queued {
+            if let Some(buf) = self.tx_buf.take() {
+                match self
+                    .ethernet
+                    .transmit_frame(buf, self.tx_len.get(), PACKET_FRAME)
+                {
+                    Ok(()) => {
+                        self.transmitting.set(true);
+                        self.tx_state.set(TxState::Transmitting);
+                    }
+                    Err((ecode, buf)) => {
+                        self.tx_buf.replace(buf);
+                        self.tx_state.set(TxState::Idle);
+                        return Err(ecode);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `transmit_next`, for callers outside of `send_to`, which report
+    /// errors of the packet frame to the send client.
+    fn transmit_queued(&self) {
+        if let Err(ecode) = self.transmit_next() {
+            self.send_done(Err(ecode));
+        }
+    }
+
+    fn send_done(&self, result: Result<(), ErrorCode>) {
+        self.send_client.map(|client| client.send_done(result));
+    }
+
+    fn receive_arp(&self, payload: &[u8]) {
+        let packet = match ArpPacket::decode(payload).done() {
+            Some((_, packet)) => packet,
+            None => return,
+        };
+        let config = match self.config.get() {
+            Some(config) => config,
+            None => return,
+        };
+        // Probes from hosts without an address are not learned.
+        let for_us = packet.target_addr == config.addr;
+        if !packet.sender_addr.is_unspecified() {
+            self.learn(packet.sender_addr, packet.sender_mac, for_us);
+        }
+        if for_us && packet.op == arp_op::REQUEST {
+            self.send_arp(arp_op::REPLY, packet.sender_mac, packet.sender_addr);
+        }
+    }
+
+    fn receive_ip4(&self, src_mac: EthernetAddr, payload: &[u8]) {
+        let (hdr_len, header) = match IP4Header::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let total_len = header.total_len as usize;
+        if total_len > payload.len() || header.is_fragment() {
+            return;
+        }
+        let config = self.config.get();
+        let accept = config.is_none_or(|config| {
+            header.dst_addr == config.addr
+                || header.dst_addr.is_broadcast()
+                || header.dst_addr == config.addr.subnet_broadcast(config.netmask)
+        });
+        if !accept {
+            return;
+        }
+        let body = &payload[hdr_len..total_len];
+        match header.protocol {
+            ip4_proto::UDP => self.receive_udp(header, body),
+            ip4_proto::ICMP => {
+                if config.is_some_and(|config| header.dst_addr == config.addr) {
+                    self.receive_icmp(src_mac, header, body);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_udp(&self, header: IP4Header, body: &[u8]) {
+        let datagram = match check_udp(&header, body) {
+            Some(datagram) => datagram,
+            None => return,
+        };
+        self.recv_client.map(|client| {
+            client.receive(
+                header.src_addr.to_mapped(),
+                header.dst_addr.to_mapped(),
+                datagram,
+            )
+        });
+    }
+
+    /// Answers echo requests. The reply is sent to the MAC address the
+    /// request came from, so it does not need ARP.
+    fn receive_icmp(&self, src_mac: EthernetAddr, header: IP4Header, body: &[u8]) {
+        if body.len() < 8 || body[0] != ICMP_ECHO_REQUEST || body[1] != 0 {
+            return;
+        }
+        if checksum_finish(checksum_add(0, body)) != 0 {
+            return;
+        }
+        let mut reply = IP4Header::new();
+        reply.total_len = (IP4_HDR_LEN + body.len()) as u16;
+        reply.id = self.next_ip_id();
+        reply.protocol = ip4_proto::ICMP;
+        reply.src_addr = header.dst_addr;
+        reply.dst_addr = header.src_addr;
+        self.send_control(|buf| {
+            let icmp_off = ETHERNET_HDR_LEN + IP4_HDR_LEN;
+            let icmp = buf.get_mut(icmp_off..icmp_off + body.len())?;
+            icmp.copy_from_slice(body);
+            icmp[0] = ICMP_ECHO_REPLY;
+            icmp[2..4].copy_from_slice(&[0, 0]);
+            let cksum = checksum_finish(checksum_add(0, icmp));
+            icmp[2..4].copy_from_slice(&cksum.to_be_bytes());
+
+            self.write_ethernet_header(buf, src_mac, ethertype::IP4);
+            reply.encode(&mut buf[ETHERNET_HDR_LEN..]).done()?;
+            Some(icmp_off + body.len())
+        });
+    }
+
+    /// Writes the frame of a UDP packet into the packet buffer, leaving the
+    /// destination MAC address to be filled in.
+    fn write_udp_frame(
+        &self,
+        src: IP4Addr,
+        dst: IP4Addr,
+        udp_header: UDPHeader,
+        payload: &[u8],
+    ) -> Result<(), ErrorCode> {
+        let udp_len = UDP_HDR_LEN + payload.len();
+        let mut header = IP4Header::new();
+        header.total_len = (IP4_HDR_LEN + udp_len) as u16;
+        header.id = self.next_ip_id();
+        header.protocol = ip4_proto::UDP;
+        header.src_addr = src;
+        header.dst_addr = dst;
+
+        self.tx_buf
+            .map(|buf| {
+                let udp_off = ETHERNET_HDR_LEN + IP4_HDR_LEN;
+                if buf.len() < udp_off + udp_len {
+                    return Err(ErrorCode::SIZE);
+                }
+                self.write_ethernet_header(buf, ETHERNET_BROADCAST, ethertype::IP4);
+                header
+                    .encode(&mut buf[ETHERNET_HDR_LEN..])
+                    .done()
+                    .ok_or(ErrorCode::FAIL)?;
+
+                encode_udp(
+                    &mut buf[udp_off..udp_off + udp_len],
+                    src,
+                    dst,
+                    udp_header,
+                    payload,
+                );
+
+                self.tx_len.set(pad_frame(buf, udp_off + udp_len) as u16);
+                Ok(())
+            })
+            .unwrap_or(Err(ErrorCode::BUSY))
+    }
+}
+
+/// Returns the UDP datagram at the start of `body`, the payload of an IPv4
+/// packet with `header`, if its length and checksum are valid.
+fn check_udp<'b>(header: &IP4Header, body: &'b [u8]) -> Option<&'b [u8]> {
+    let (_, udp_header) = UDPHeader::decode(body).done()?;
+    let udp_len = udp_header.get_len() as usize;
+    if udp_len < UDP_HDR_LEN || udp_len > body.len() {
+        return None;
+    }
+    // A zero checksum means the sender did not compute one.
+    if udp_header.get_cksum() != 0 {
+        let sum = pseudo_header_sum(
+            header.src_addr,
+            header.dst_addr,
+            ip4_proto::UDP,
+            udp_len as u16,
+        );
+        if checksum_finish(checksum_add(sum, &body[..udp_len])) != 0 {
+            return None;
+        }
+    }
+    Some(&body[..udp_len])
+}
+
+/// Writes a UDP datagram with the ports of `udp_header` and `payload` into
+/// `udp`, which must be exactly as long as the datagram.
+fn encode_udp(udp: &mut [u8], src: IP4Addr, dst: IP4Addr, udp_header: UDPHeader, payload: &[u8]) {
+    let udp_len = udp.len() as u16;
+    udp[0..2].copy_from_slice(&udp_header.get_src_port().to_be_bytes());
+    udp[2..4].copy_from_slice(&udp_header.get_dst_port().to_be_bytes());
+    udp[4..6].copy_from_slice(&udp_len.to_be_bytes());
+    udp[6..8].copy_from_slice(&[0, 0]);
+    udp[UDP_HDR_LEN..].copy_from_slice(payload);
+    let sum = pseudo_header_sum(src, dst, ip4_proto::UDP, udp_len);
+    // A computed checksum of zero is sent as all ones.
+    let cksum = match checksum_finish(checksum_add(sum, udp)) {
+        0 => 0xffff,
+        cksum => cksum,
+    };
+    udp[6..8].copy_from_slice(&cksum.to_be_bytes());
+}
+
+/// Pads a frame of length `len` with zeros to the minimum frame length, and
+/// returns its new length.
+fn pad_frame(buf: &mut [u8], len: usize) -> usize {
+    let padded = len.max(MIN_FRAME_LEN).min(buf.len());
+    if padded > len {
+        buf[len..padded].fill(0);
+    }
+    padded.max(len)
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> IP4Configure for IP4Interface<'a, E, A> {
+    fn set_config(&self, config: Option<IP4Config>) {
+        if self.config.get() == config {
+            return;
+        }
+        self.config.set(config);
+        self.interface
+            .set(config.map_or(IPAddr::new(), |config| config.addr.to_mapped()));
+        // Neighbors of the previous address may not be reachable anymore.
+        for entry in self.arp_cache {
+            entry.set(None);
+        }
+    }
+
+    fn get_config(&self) -> Option<IP4Config> {
+        self.config.get()
+    }
+
+    fn get_mac_address(&self) -> EthernetAddr {
+        self.mac
+    }
+}
+
+/// The source address is configured through `IP4Configure`, and next hops are
+/// resolved with ARP.
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> IPSender<'a> for IP4Interface<'a, E, A> {
+    fn set_client(&self, client: &'a dyn IPSendClient) {
+        self.send_client.set(client);
+    }
+
+    fn send_to(
+        &'a self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &SubSliceMut<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        if !net_cap.remote_addr_valid(dst, self.ip_vis) {
+            return Err(ErrorCode::FAIL);
+        }
+        let udp_header = match transport_header {
+            TransportHeader::UDP(udp_header) => udp_header,
+            _ => return Err(ErrorCode::NOSUPPORT),
+        };
+        let dst = IP4Addr::from_mapped(dst).ok_or(ErrorCode::INVAL)?;
+        if dst.is_multicast() || dst.is_unspecified() {
+            return Err(ErrorCode::INVAL);
+        }
+        let payload_len = (udp_header.get_len() as usize)
+            .checked_sub(UDP_HDR_LEN)
+            .filter(|&len| len <= payload.len())
+            .ok_or(ErrorCode::SIZE)?;
+        if self.tx_state.get() != TxState::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+
+        // Without a configuration, only broadcasts can be sent, as DHCP
+        // does. `None` is the broadcast MAC address.
+        let config = self.config.get();
+        let next_hop = match config {
+            None if dst.is_broadcast() => None,
+            None => return Err(ErrorCode::OFF),
+            Some(config)
+                if dst.is_broadcast() || dst == config.addr.subnet_broadcast(config.netmask) =>
+            {
+                None
+            }
+            Some(config) if dst.in_subnet(config.addr, config.netmask) => Some(dst),
+            Some(config) if !config.gateway.is_unspecified() => Some(config.gateway),
+            Some(_) => return Err(ErrorCode::FAIL),
+        };
+        let src = config.map_or(IP4Addr::UNSPECIFIED, |config| config.addr);
+        self.write_udp_frame(src, dst, udp_header, &payload[..payload_len])?;
+
+        let mac = match next_hop {
+            None => ETHERNET_BROADCAST,
+            Some(next_hop) => match self.lookup(next_hop) {
+                Some(mac) => mac,
+                None => {
+                    self.send_arp(arp_op::REQUEST, [0; 6], next_hop);
+                    self.tx_state.set(TxState::Resolving {
+                        next_hop,
+                        attempts: 1,
+                    });
+                    self.set_alarm(ARP_RETRY_INTERVAL);
+                    return Ok(());
+                }
+            },
+        };
+        self.tx_buf.map(|buf| buf[0..6].copy_from_slice(&mac));
+        self.tx_state.set(TxState::Queued);
+        self.transmit_next()
+    }
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> EthernetAdapterDatapathClient
+    for IP4Interface<'a, E, A>
+{
+    fn transmit_frame_done(
+        &self,
+        err: Result<(), ErrorCode>,
+        frame_buffer: &'static mut [u8],
+        _len: u16,
+        transmission_identifier: usize,
+        _timestamp: Option<u64>,
+    ) {
+        self.transmitting.set(false);
+        if transmission_identifier == PACKET_FRAME {
+            self.tx_buf.replace(frame_buffer);
+            self.tx_state.set(TxState::Idle);
+            self.transmit_queued();
+            self.send_done(err);
+        } else {
+            self.control_buf.replace(frame_buffer);
+            self.transmit_queued();
+        }
+    }
+
+    fn received_frame(&self, frame: &[u8], _timestamp: Option<u64>) {
+        if frame.len() < ETHERNET_HDR_LEN {
+            return;
+        }
+        if frame[0..6] != self.mac && frame[0..6] != ETHERNET_BROADCAST {
+            return;
+        }
+        let mut src_mac = [0; 6];
+        src_mac.copy_from_slice(&frame[6..12]);
+        let payload = &frame[ETHERNET_HDR_LEN..];
+        match u16::from_be_bytes([frame[12], frame[13]]) {
+            ethertype::ARP => self.receive_arp(payload),
+            ethertype::IP4 => self.receive_ip4(src_mac, payload),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, E: EthernetAdapterDatapath<'a>, A: Alarm<'a>> AlarmClient for IP4Interface<'a, E, A> {
+    fn alarm(&self) {
+        if let TxState::Resolving { next_hop, attempts } = self.tx_state.get() {
+            if attempts < ARP_ATTEMPTS {
+                self.send_arp(arp_op::REQUEST, [0; 6], next_hop);
+                self.tx_state.set(TxState::Resolving {
+                    next_hop,
+                    attempts: attempts + 1,
+                });
+            } else {
+                self.tx_state.set(TxState::Idle);
+                self.send_done(Err(ErrorCode::FAIL));
+            }
+        }
+        self.expire();
+        self.schedule_alarm();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: IP4Addr = IP4Addr([10, 0, 0, 1]);
+    const DST: IP4Addr = IP4Addr([10, 0, 0, 2]);
+    const PAYLOAD: &[u8] = b"hello";
+    const UDP_LEN: usize = UDP_HDR_LEN + 5;
+
+    fn ip4_header() -> IP4Header {
+        let mut header = IP4Header::new();
+        header.total_len = (IP4_HDR_LEN + UDP_LEN) as u16;
+        header.protocol = ip4_proto::UDP;
+        header.src_addr = SRC;
+        header.dst_addr = DST;
+        header
+    }
+
+    /// A datagram from port 1000 to port 2000 carrying `PAYLOAD`, followed
+    /// by zero padding.
+    fn datagram() -> [u8; 16] {
+        let mut udp_header = UDPHeader::new();
+        udp_header.set_src_port(1000);
+        udp_header.set_dst_port(2000);
+        let mut buf = [0; 16];
+        encode_udp(&mut buf[..UDP_LEN], SRC, DST, udp_header, PAYLOAD);
+        buf
+    }
+
+    #[test]
+    fn encode_udp_round_trip() {
+        let buf = datagram();
+        assert_eq!(buf[..6], [0x03, 0xe8, 0x07, 0xd0, 0, UDP_LEN as u8]);
+        assert_eq!(&buf[UDP_HDR_LEN..UDP_LEN], PAYLOAD);
+
+        // The padding after the datagram is not part of it.
+        let udp = check_udp(&ip4_header(), &buf).unwrap();
+        assert_eq!(udp.len(), UDP_LEN);
+        let (_, udp_header) = UDPHeader::decode(udp).done().unwrap();
+        assert_eq!(udp_header.get_src_port(), 1000);
+        assert_eq!(udp_header.get_dst_port(), 2000);
+        assert_eq!(udp_header.get_len() as usize, UDP_LEN);
+    }
+
+    #[test]
+    fn check_udp_truncated() {
+        let buf = datagram();
+        assert!(check_udp(&ip4_header(), &buf[..UDP_LEN - 1]).is_none());
+        assert!(check_udp(&ip4_header(), &buf[..UDP_HDR_LEN - 1]).is_none());
+        // A length field below the header length.
+        let mut buf = datagram();
+        buf[4..8].copy_from_slice(&[0, UDP_HDR_LEN as u8 - 1, 0, 0]);
+        assert!(check_udp(&ip4_header(), &buf).is_none());
+    }
+
+    #[test]
+    fn check_udp_bad_checksum() {
+        let mut buf = datagram();
+        buf[UDP_HDR_LEN] ^= 1;
+        assert!(check_udp(&ip4_header(), &buf).is_none());
+
+        // The checksum covers the addresses of the pseudo-header.
+        let mut header = ip4_header();
+        header.src_addr = IP4Addr([10, 0, 0, 3]);
+        assert!(check_udp(&header, &datagram()).is_none());
+
+        // Without a checksum, the datagram is accepted.
+        buf[6..8].copy_from_slice(&[0, 0]);
+        assert!(check_udp(&ip4_header(), &buf).is_some());
+    }
+}
@@ -0,0 +1,119 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Utilities used by the components of the IPv4 stack.
+//!
+//! This file contains the definition of the [IP4Addr](struct.IP4Addr.html)
+//! struct, the Ethernet constants of the stack and the Internet checksum.
+//!
+//! The IPv4 stack shares the UDP layer with the IPv6 stack, which addresses
+//! packets with IPv6 addresses. IPv4 addresses are passed through it as
+//! IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`, RFC 4291).
+
+use crate::net::ipv6::ip_utils::IPAddr;
+
+pub mod ip4_proto {
+    pub const ICMP: u8 = 1;
+    pub const UDP: u8 = 17;
+}
+
+pub mod ethertype {
+    pub const IP4: u16 = 0x0800;
+    pub const ARP: u16 = 0x0806;
+}
+
+/// An Ethernet MAC address.
+pub type EthernetAddr = [u8; 6];
+
+pub const ETHERNET_BROADCAST: EthernetAddr = [0xff; 6];
+
+/// Length of an Ethernet header without VLAN tag.
+pub const ETHERNET_HDR_LEN: usize = 14;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct IP4Addr(pub [u8; 4]);
+
+impl IP4Addr {
+    pub const UNSPECIFIED: IP4Addr = IP4Addr([0; 4]);
+    pub const BROADCAST: IP4Addr = IP4Addr([255; 4]);
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0xf0 == 0xe0
+    }
+
+    /// Returns whether `self` and `other` are in the same subnet.
+    pub fn in_subnet(&self, other: IP4Addr, netmask: IP4Addr) -> bool {
+        (u32::from(*self) ^ u32::from(other)) & u32::from(netmask) == 0
+    }
+
+    /// Returns the broadcast address of the subnet of `self`.
+    pub fn subnet_broadcast(&self, netmask: IP4Addr) -> IP4Addr {
+        IP4Addr::from(u32::from(*self) | !u32::from(netmask))
+    }
+
+    /// Returns the IPv4-mapped IPv6 address of `self`.
+    pub fn to_mapped(&self) -> IPAddr {
+        let mut addr = IPAddr::new();
+        addr.0[10] = 0xff;
+        addr.0[11] = 0xff;
+        addr.0[12..].copy_from_slice(&self.0);
+        addr
+    }
+
+    /// Returns the IPv4 address of an IPv4-mapped IPv6 address, or `None`
+    /// if `addr` is not IPv4-mapped.
+    pub fn from_mapped(addr: IPAddr) -> Option<IP4Addr> {
+        if addr.0[..10] == [0; 10] && addr.0[10..12] == [0xff, 0xff] {
+            let mut ip4 = IP4Addr::UNSPECIFIED;
+            ip4.0.copy_from_slice(&addr.0[12..]);
+            Some(ip4)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<u32> for IP4Addr {
+    fn from(addr: u32) -> IP4Addr {
+        IP4Addr(addr.to_be_bytes())
+    }
+}
+
+impl From<IP4Addr> for u32 {
+    fn from(addr: IP4Addr) -> u32 {
+        u32::from_be_bytes(addr.0)
+    }
+}
+
+/// Adds `buf` to the ones' complement sum `sum` as 16-bit big-endian words,
+/// padding an odd trailing byte with zero.
+pub fn checksum_add(sum: u32, buf: &[u8]) -> u32 {
+    buf.chunks(2).fold(sum, |sum, word| {
+        sum + ((word[0] as u32) << 8 | word.get(1).map_or(0, |&b| b as u32))
+    })
+}
+
+/// Folds the carries of `sum` and returns its ones' complement, the value
+/// of an Internet checksum field (RFC 1071). Computed over data that
+/// includes a valid checksum, this yields 0.
+pub fn checksum_finish(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum >> 16) + (sum & 0xffff);
+    }
+    !(sum as u16)
+}
+
+/// Returns the sum of the IPv4 pseudo-header used by the UDP checksum.
+pub fn pseudo_header_sum(src: IP4Addr, dst: IP4Addr, protocol: u8, len: u16) -> u32 {
+    let sum = checksum_add(0, &src.0);
+    checksum_add(sum, &dst.0) + protocol as u32 + len as u32
+}
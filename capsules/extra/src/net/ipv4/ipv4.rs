@@ -0,0 +1,228 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! This file contains the `IP4Header` struct and its encoding and decoding
+//! (RFC 791). Options of received headers are skipped; headers are always
+//! sent without options.
+
+use crate::net::ipv4::ip4_utils::{checksum_add, checksum_finish, IP4Addr};
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
+
+/// Length of an IPv4 header without options.
+pub const IP4_HDR_LEN: usize = 20;
+
+const DEFAULT_TTL: u8 = 64;
+
+// Flags and fragment offset field.
+const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+const FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+const FRAGMENT_OFFSET_MASK: u16 = 0x1fff;
+
+#[derive(Copy, Clone, Debug)]
+pub struct IP4Header {
+    pub tos: u8,
+    pub total_len: u16,
+    pub id: u16,
+    pub flags_frag: u16,
+    pub ttl: u8,
+    pub protocol: u8,
+    pub src_addr: IP4Addr,
+    pub dst_addr: IP4Addr,
+}
+
+impl Default for IP4Header {
+    fn default() -> IP4Header {
+        IP4Header {
+            tos: 0,
+            total_len: IP4_HDR_LEN as u16,
+            id: 0,
+            flags_frag: FLAG_DONT_FRAGMENT,
+            ttl: DEFAULT_TTL,
+            protocol: 0,
+            src_addr: IP4Addr::UNSPECIFIED,
+            dst_addr: IP4Addr::UNSPECIFIED,
+        }
+    }
+}
+
+impl IP4Header {
+    pub fn new() -> IP4Header {
+        IP4Header::default()
+    }
+
+    /// Returns whether the packet is a fragment of a larger packet.
+    pub fn is_fragment(&self) -> bool {
+        self.flags_frag & (FLAG_MORE_FRAGMENTS | FRAGMENT_OFFSET_MASK) != 0
+    }
+
+    /// This function decodes an IPv4 header, verifying its version and
+    /// checksum. The returned offset is the length of the header including
+    /// its options.
+    pub fn decode(buf: &[u8]) -> SResult<IP4Header> {
+        stream_len_cond!(buf, IP4_HDR_LEN);
+
+        let mut ip4_header = Self::new();
+        let (off, version_ihl) = dec_try!(buf, 0; decode_u8);
+        let hdr_len = ((version_ihl & 0x0f) as usize) * 4;
+        stream_cond!(version_ihl >> 4 == 4 && hdr_len >= IP4_HDR_LEN);
+        stream_len_cond!(buf, hdr_len);
+        stream_cond!(checksum_finish(checksum_add(0, &buf[..hdr_len])) == 0);
+
+        let (off, tos) = dec_try!(buf, off; decode_u8);
+        ip4_header.tos = tos;
+        let (off, total_len) = dec_try!(buf, off; decode_u16);
+        stream_cond!(total_len as usize >= hdr_len);
+        ip4_header.total_len = total_len;
+        let (off, id) = dec_try!(buf, off; decode_u16);
+        ip4_header.id = id;
+        let (off, flags_frag) = dec_try!(buf, off; decode_u16);
+        ip4_header.flags_frag = flags_frag;
+        let (off, ttl) = dec_try!(buf, off; decode_u8);
+        ip4_header.ttl = ttl;
+        let (off, protocol) = dec_try!(buf, off; decode_u8);
+        ip4_header.protocol = protocol;
+        // Skip the checksum, which was verified above.
+        let off = off + 2;
+        let off = dec_consume!(buf, off; decode_bytes, &mut ip4_header.src_addr.0);
+        dec_consume!(buf, off; decode_bytes, &mut ip4_header.dst_addr.0);
+        stream_done!(hdr_len, ip4_header);
+    }
+
+    /// This function encodes the header, including its checksum.
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        stream_len_cond!(buf, IP4_HDR_LEN);
+
+        let mut off = enc_consume!(buf, 0; encode_u8, 0x45);
+        off = enc_consume!(buf, off; encode_u8, self.tos);
+        off = enc_consume!(buf, off; encode_u16, self.total_len);
+        off = enc_consume!(buf, off; encode_u16, self.id);
+        off = enc_consume!(buf, off; encode_u16, self.flags_frag);
+        off = enc_consume!(buf, off; encode_u8, self.ttl);
+        off = enc_consume!(buf, off; encode_u8, self.protocol);
+        let cksum_off = off;
+        off = enc_consume!(buf, off; encode_u16, 0);
+        off = enc_consume!(buf, off; encode_bytes, &self.src_addr.0);
+        off = enc_consume!(buf, off; encode_bytes, &self.dst_addr.0);
+        let cksum = checksum_finish(checksum_add(0, &buf[..off]));
+        enc_consume!(buf, cksum_off; encode_u16, cksum);
+        stream_done!(off, off);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipv4::ip4_utils::ip4_proto;
+
+    fn header() -> IP4Header {
+        let mut header = IP4Header::new();
+        header.total_len = 28;
+        header.id = 0x1234;
+        header.protocol = ip4_proto::UDP;
+        header.src_addr = IP4Addr([10, 0, 0, 1]);
+        header.dst_addr = IP4Addr([10, 0, 0, 2]);
+        header
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let mut buf = [0; IP4_HDR_LEN];
+        assert_eq!(
+            header().encode(&mut buf).done(),
+            Some((IP4_HDR_LEN, IP4_HDR_LEN))
+        );
+        assert_eq!(
+            buf[..12],
+            [
+                0x45,
+                0,
+                0,
+                28,
+                0x12,
+                0x34,
+                0x40,
+                0,
+                64,
+                ip4_proto::UDP,
+                0x14,
+                0x9b
+            ]
+        );
+        assert_eq!(checksum_finish(checksum_add(0, &buf)), 0);
+
+        let (off, decoded) = IP4Header::decode(&buf).done().unwrap();
+        assert_eq!(off, IP4_HDR_LEN);
+        assert_eq!(decoded.tos, 0);
+        assert_eq!(decoded.total_len, 28);
+        assert_eq!(decoded.id, 0x1234);
+        assert_eq!(decoded.flags_frag, FLAG_DONT_FRAGMENT);
+        assert_eq!(decoded.ttl, DEFAULT_TTL);
+        assert_eq!(decoded.protocol, ip4_proto::UDP);
+        assert_eq!(decoded.src_addr, IP4Addr([10, 0, 0, 1]));
+        assert_eq!(decoded.dst_addr, IP4Addr([10, 0, 0, 2]));
+        assert!(!decoded.is_fragment());
+
+        assert!(header().encode(&mut [0; IP4_HDR_LEN - 1]).done().is_none());
+    }
+
+    #[test]
+    fn decode_options() {
+        // A header with one 4 byte option, whose checksum covers the option.
+        let mut buf = [0; IP4_HDR_LEN + 4];
+        header().encode(&mut buf).done().unwrap();
+        buf[0] = 0x46;
+        buf[IP4_HDR_LEN..].copy_from_slice(&[0x94, 0x04, 0, 0]);
+        buf[10..12].copy_from_slice(&[0, 0]);
+        let cksum = checksum_finish(checksum_add(0, &buf));
+        buf[10..12].copy_from_slice(&cksum.to_be_bytes());
+
+        let (off, decoded) = IP4Header::decode(&buf).done().unwrap();
+        assert_eq!(off, IP4_HDR_LEN + 4);
+        assert_eq!(decoded.dst_addr, IP4Addr([10, 0, 0, 2]));
+        // The options are missing.
+        assert!(IP4Header::decode(&buf[..IP4_HDR_LEN]).done().is_none());
+    }
+
+    #[test]
+    fn decode_truncated() {
+        let mut buf = [0; IP4_HDR_LEN];
+        header().encode(&mut buf).done().unwrap();
+        assert!(IP4Header::decode(&buf[..IP4_HDR_LEN - 1]).done().is_none());
+        assert!(IP4Header::decode(&[]).done().is_none());
+    }
+
+    #[test]
+    fn decode_bad_checksum() {
+        let mut buf = [0; IP4_HDR_LEN];
+        header().encode(&mut buf).done().unwrap();
+        buf[8] -= 1;
+        assert!(IP4Header::decode(&buf).done().is_none());
+    }
+
+    #[test]
+    fn decode_bad_header() {
+        // Version 6, and a header length below the minimum, each with a
+        // valid checksum.
+        for version_ihl in [0x65, 0x44] {
+            let mut buf = [0; IP4_HDR_LEN];
+            header().encode(&mut buf).done().unwrap();
+            buf[0] = version_ihl;
+            buf[10..12].copy_from_slice(&[0, 0]);
+            let cksum = checksum_finish(checksum_add(0, &buf));
+            buf[10..12].copy_from_slice(&cksum.to_be_bytes());
+            assert!(IP4Header::decode(&buf).done().is_none());
+        }
+    }
+
+    #[test]
+    fn fragments() {
+        let mut header = header();
+        header.flags_frag = FLAG_MORE_FRAGMENTS;
+        assert!(header.is_fragment());
+        header.flags_frag = FLAG_DONT_FRAGMENT | 0x10;
+        assert!(header.is_fragment());
+    }
+}
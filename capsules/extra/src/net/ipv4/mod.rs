@@ -0,0 +1,16 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Modules for the IPv4 stack over Ethernet
+
+pub mod arp;
+pub mod dhcp;
+pub mod ip4_interface;
+pub mod ip4_utils;
+
+// Reexport the exports of the [`ipv4`] module, to avoid redundant
+// module paths (e.g. `capsules::net::ipv4::ipv4::IP4Header`)
+mod ipv4;
+pub use ipv4::IP4Header;
+pub use ipv4::IP4_HDR_LEN;
@@ -8,7 +8,6 @@
 //! [IPAddr](struct.IPAddr.html) struct and associated helper
 //! functions.
 
-use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::IP6Header;
 use crate::net::udp::UDPHeader;
@@ -186,48 +185,69 @@ pub fn compute_udp_checksum(
     sum as u16 //Return result as u16 in host byte order */
 }
 
-pub fn compute_icmp_checksum(
-    ipv6_header: &IP6Header,
-    icmp_header: &ICMP6Header,
-    payload: &[u8],
-) -> u16 {
-    let mut sum: u32 = 0;
+/// Computes the ICMPv6 checksum over the IPv6 pseudo-header and a message.
+///
+/// The message is given as the encoded header and the payload. When computed
+/// over a received message, a valid checksum yields 0.
+pub fn compute_icmp_checksum(ip6_header: &IP6Header, icmp_header: &[u8], payload: &[u8]) -> u16 {
+    let icmp_len = (icmp_header.len() + payload.len()) as u32;
+    let mut sum = compute_pseudo_header_sum(ip6_header, icmp_len, ip6_nh::ICMP);
 
-    // add ipv6 pseudo-header
-    sum += compute_ipv6_ph_sum(ipv6_header);
+    // add icmp header and payload
+    sum += compute_sum_padded(icmp_header);
+    sum += compute_sum_padded(payload);
 
-    // add type and code
-    let msb = (icmp_header.get_type_as_int() as u32) << 8;
-    let lsb = icmp_header.get_code() as u32;
-    sum += msb + lsb;
-
-    // add options
-    match icmp_header.get_options() {
-        ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
-            sum += unused >> 16; // upper 16 bits
-            sum += unused & 0xffff; // lower 16 bits
-        }
-        ICMP6HeaderOptions::Type128 { id, seqno } | ICMP6HeaderOptions::Type129 { id, seqno } => {
-            sum += id as u32;
-            sum += seqno as u32;
-        }
+    // carry overflow
+    while sum > 0xffff {
+        sum = (sum >> 16) + (sum & 0xffff);
     }
 
-    // add icmp payload
-    let payload_len = icmp_header.get_len() - icmp_header.get_hdr_size() as u16;
-    sum += compute_sum(payload, payload_len);
+    !sum as u16
+}
+
+/// Computes the TCP checksum over the IPv6 pseudo-header and a segment.
+///
+/// The segment is given as the encoded header (including any options) and
+/// the payload. When computed over a received segment, a valid checksum
+/// yields 0.
+pub fn compute_tcp_checksum(ip6_header: &IP6Header, tcp_header: &[u8], payload: &[u8]) -> u16 {
+    let tcp_len = (tcp_header.len() + payload.len()) as u32;
+    let mut sum = compute_pseudo_header_sum(ip6_header, tcp_len, ip6_nh::TCP);
+
+    // add tcp header and payload
+    sum += compute_sum_padded(tcp_header);
+    sum += compute_sum_padded(payload);
 
     // carry overflow
     while sum > 0xffff {
-        let sum_upper = sum >> 16;
-        let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
+        sum = (sum >> 16) + (sum & 0xffff);
     }
 
-    sum = !sum;
-    sum &= 0xffff;
+    !sum as u16
+}
+
+/// Sums the IPv6 pseudo-header for an upper-layer packet of `length` bytes
+/// with next header `next_header`.
+fn compute_pseudo_header_sum(ip6_header: &IP6Header, length: u32, next_header: u8) -> u32 {
+    let mut sum: u32 = 0;
+    let mut i = 0;
+    while i < 16 {
+        sum += (ip6_header.src_addr.0[i] as u32) << 8 | ip6_header.src_addr.0[i + 1] as u32;
+        sum += (ip6_header.dst_addr.0[i] as u32) << 8 | ip6_header.dst_addr.0[i + 1] as u32;
+        i += 2;
+    }
+    sum += length >> 16;
+    sum += length & 0xffff;
+    sum += next_header as u32;
+    sum
+}
 
-    sum as u16
+/// Sums `buf` as 16-bit big-endian words, padding an odd trailing byte with
+/// zero.
+fn compute_sum_padded(buf: &[u8]) -> u32 {
+    buf.chunks(2)
+        .map(|word| (word[0] as u32) << 8 | word.get(1).map_or(0, |&b| b as u32))
+        .sum()
 }
 
 pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
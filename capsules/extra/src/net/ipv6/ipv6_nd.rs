@@ -0,0 +1,643 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! IPv6 Neighbor Discovery and stateless address autoconfiguration.
+//!
+//! This implements the host side of Neighbor Discovery for 6LoWPAN
+//! (RFC 4861 as adapted by RFC 6775):
+//!
+//! - At startup, the link-local address derived from the EUI-64 goes through
+//!   duplicate address detection (DAD) and is then added to the interface
+//!   list. The device then sends up to three Router Solicitations.
+//! - Router Advertisements set the default router, which receives all
+//!   packets to non link-local destinations, and their prefix information
+//!   autoconfigures one global address from a /64 prefix (RFC 4862). The
+//!   global address also goes through DAD and is removed from the interface
+//!   list when its valid lifetime ends.
+//! - Neighbor Solicitations for addresses of the device are answered with
+//!   Neighbor Advertisements.
+//! - The link-layer addresses of neighbors that solicit or advertise are kept
+//!   in a neighbor cache, which replaces the least recently used entry when it
+//!   is full.
+//!
+//! The IPv6 senders of other protocols use this through the `IP6NextHop`
+//! trait to find the next hop of each packet and to use the global address as
+//! source address for packets leaving the link.
+//!
+//! Neighbor Discovery shares the IPv6 layer with the other protocols, and
+//! messages that arrive while its previous message is still being sent are
+//! not answered. Solicitations and DAD are retried by their senders, so this
+//! only delays them.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let nd = components::ipv6_nd::NeighborDiscoveryComponent::new(
+//!     ip6_send_mux,
+//!     ip6_recv_mux,
+//!     eui64_driver,
+//!     local_ip_ifaces,
+//!     mux_alarm,
+//! )
+//! .finalize(components::neighbor_discovery_component_static!(
+//!     nrf52840::rtc::Rtc,
+//!     8
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6NextHop, IP6Sender, IPSendClient};
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::thread::thread_utils::mac_from_ipv6;
+
+use kernel::debug;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// Size of the buffer for the body of Neighbor Discovery messages.
+pub const BUFFER_LEN: usize = 32;
+
+const ALL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]);
+
+/// Delay before the first message, in seconds.
+const START_DELAY: u32 = 1;
+/// Time to wait for an answer to a DAD Neighbor Solicitation, in seconds.
+const RETRANS_TIMER: u32 = 1;
+/// Time between Router Solicitations, in seconds.
+const RTR_SOLICITATION_INTERVAL: u32 = 4;
+const MAX_RTR_SOLICITATIONS: u8 = 3;
+/// Longest time between alarms, which keeps the clock from missing a
+/// wraparound of the ticks.
+const MAX_ALARM_INTERVAL: u32 = 60;
+
+/// Lifetime that never ends.
+const INFINITE_LIFETIME: u32 = 0xffff_ffff;
+
+mod option {
+    pub const SOURCE_LL_ADDR: u8 = 1;
+    pub const TARGET_LL_ADDR: u8 = 2;
+    pub const PREFIX_INFO: u8 = 3;
+}
+
+mod na_flags {
+    pub const SOLICITED: u32 = 0x4000_0000;
+    pub const OVERRIDE: u32 = 0x2000_0000;
+}
+
+/// Autonomous address-configuration flag of the prefix information option.
+const PREFIX_AUTONOMOUS: u8 = 0x40;
+
+/// An entry of the neighbor cache.
+#[derive(Clone, Copy)]
+pub struct Neighbor {
+    ip: IPAddr,
+    mac: MacAddress,
+    /// Time the entry was last used or updated, in seconds.
+    used: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Router {
+    ip: IPAddr,
+    mac: MacAddress,
+    expires: u32,
+}
+
+/// The autoconfigured global address.
+#[derive(Clone, Copy)]
+struct Global {
+    addr: IPAddr,
+    /// `None` if the address does not expire.
+    expires: Option<u32>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Starting,
+    /// DAD of `addr` is running, `expires` is the end of its valid lifetime
+    /// for a global address.
+    Dad {
+        addr: IPAddr,
+        expires: Option<u32>,
+        sent: bool,
+    },
+    /// `sent` Router Solicitations have been sent.
+    Soliciting(u8),
+    Running,
+    /// The link-local address is a duplicate and cannot be used.
+    Failed,
+}
+
+pub struct NeighborDiscovery<'a, A: Alarm<'a>> {
+    sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    /// Extended MAC address, from which the interface identifier is derived.
+    mac: [u8; 8],
+    /// Addresses of the interface. Unspecified addresses are free slots.
+    addresses: &'a [Cell<IPAddr>],
+    neighbors: &'a [Cell<Option<Neighbor>>],
+    router: Cell<Option<Router>>,
+    global: Cell<Option<Global>>,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    sending: Cell<bool>,
+    net_cap: &'static NetworkCapability,
+    /// Seconds since start, advanced from the alarm ticks.
+    seconds: Cell<u32>,
+    last_tick: OptionalCell<A::Ticks>,
+}
+
+impl<'a, A: Alarm<'a>> NeighborDiscovery<'a, A> {
+    /// `eui64` is the EUI-64 of the device, which is also its extended MAC
+    /// address in little endian byte order (as given to `Eui64Component`).
+    pub fn new(
+        sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        eui64: u64,
+        addresses: &'a [Cell<IPAddr>],
+        neighbors: &'a [Cell<Option<Neighbor>>],
+        buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> Self {
+        Self {
+            sender,
+            alarm,
+            mac: eui64.to_le_bytes(),
+            addresses,
+            neighbors,
+            router: Cell::new(None),
+            global: Cell::new(None),
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            sending: Cell::new(false),
+            net_cap,
+            seconds: Cell::new(0),
+            last_tick: OptionalCell::empty(),
+        }
+    }
+
+    /// Starts DAD of the link-local address, followed by router
+    /// solicitation.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.last_tick.set(self.alarm.now());
+        self.state.set(State::Starting);
+        self.set_alarm(START_DELAY);
+        Ok(())
+    }
+
+    fn link_local(&self) -> IPAddr {
+        IPAddr::generate_from_mac(MacAddress::Long(self.mac))
+    }
+
+    fn now(&self) -> u32 {
+        if let Some(last) = self.last_tick.get() {
+            let seconds = self
+                .alarm
+                .ticks_to_seconds(self.alarm.now().wrapping_sub(last));
+            if seconds > 0 {
+                self.seconds.set(self.seconds.get().wrapping_add(seconds));
+                self.last_tick
+                    .set(last.wrapping_add(self.alarm.ticks_from_seconds(seconds)));
+            }
+        }
+        self.seconds.get()
+    }
+
+    fn set_alarm(&self, seconds: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_seconds(seconds));
+    }
+
+    fn is_own_address(&self, addr: IPAddr) -> bool {
+        !addr.is_unspecified() && self.addresses.iter().any(|a| a.get() == addr)
+    }
+
+    fn add_address(&self, addr: IPAddr) {
+        if self.is_own_address(addr) {
+            return;
+        }
+        match self.addresses.iter().find(|a| a.get().is_unspecified()) {
+            Some(slot) => slot.set(addr),
+            None => debug!("IPv6 ND: no free interface address for {:?}", addr),
+        }
+    }
+
+    fn remove_address(&self, addr: IPAddr) {
+        for a in self.addresses.iter().filter(|a| a.get() == addr) {
+            a.set(IPAddr::new());
+        }
+    }
+
+    /// Adds or updates the link-layer address of a neighbor.
+    fn update_neighbor(&self, ip: IPAddr, mac: MacAddress) {
+        let used = self.now();
+        let slot = self
+            .neighbors
+            .iter()
+            .find(|n| n.get().is_some_and(|n| n.ip == ip))
+            .or_else(|| self.neighbors.iter().find(|n| n.get().is_none()))
+            .or_else(|| {
+                // Replace the least recently used entry.
+                self.neighbors
+                    .iter()
+                    .max_by_key(|n| n.get().map_or(0, |n| used.wrapping_sub(n.used)))
+            });
+        if let Some(slot) = slot {
+            slot.set(Some(Neighbor { ip, mac, used }));
+        }
+    }
+
+    fn start_dad(&self, addr: IPAddr, expires: Option<u32>) {
+        self.state.set(State::Dad {
+            addr,
+            expires,
+            sent: self.send_dad(addr).is_ok(),
+        });
+        self.set_alarm(RETRANS_TIMER);
+    }
+
+    fn dad_failed(&self, addr: IPAddr) {
+        debug!("IPv6 ND: duplicate address {:?}", addr);
+        if addr.is_unicast_link_local() {
+            self.state.set(State::Failed);
+            let _ = self.alarm.disarm();
+        } else {
+            self.state.set(State::Running);
+            self.set_alarm(MAX_ALARM_INTERVAL);
+        }
+    }
+
+    fn solicit(&self, sent: u8) {
+        if sent < MAX_RTR_SOLICITATIONS {
+            let _ = self.send_rs();
+            self.state.set(State::Soliciting(sent + 1));
+            self.set_alarm(RTR_SOLICITATION_INTERVAL);
+        } else {
+            self.state.set(State::Running);
+            self.set_alarm(MAX_ALARM_INTERVAL);
+        }
+    }
+
+    /// Removes the router and global address when their lifetimes end, and
+    /// sets the alarm for the next expiry.
+    fn expire(&self) {
+        let now = self.now();
+        let mut next = MAX_ALARM_INTERVAL;
+        if let Some(router) = self.router.get() {
+            let remaining = router.expires.wrapping_sub(now) as i32;
+            if remaining <= 0 {
+                self.router.set(None);
+                // Look for a new router.
+                self.solicit(0);
+                return;
+            }
+            next = next.min(remaining as u32);
+        }
+        if let Some(Global {
+            addr,
+            expires: Some(expires),
+        }) = self.global.get()
+        {
+            let remaining = expires.wrapping_sub(now) as i32;
+            if remaining <= 0 {
+                self.remove_address(addr);
+                self.global.set(None);
+            } else {
+                next = next.min(remaining as u32);
+            }
+        }
+        self.set_alarm(next);
+    }
+
+    /// Sends an ICMPv6 message whose body `fill` writes into the buffer.
+    fn send(
+        &self,
+        src: IPAddr,
+        dst: IPAddr,
+        icmp_type: ICMP6Type,
+        options: ICMP6HeaderOptions,
+        fill: impl FnOnce(&mut [u8]) -> usize,
+    ) -> Result<(), ErrorCode> {
+        if self.sending.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        let buffer = self.buffer.take().ok_or(ErrorCode::BUSY)?;
+        let len = fill(buffer);
+
+        let mut header = ICMP6Header::new(icmp_type);
+        header.set_options(options);
+        header.set_len((header.get_hdr_size() + len) as u16);
+
+        let mut payload = SubSliceMut::new(buffer);
+        payload.slice(..len);
+        self.sender.set_addr(src);
+        let result =
+            self.sender
+                .send_to(dst, TransportHeader::ICMP(header), &payload, self.net_cap);
+        payload.reset();
+        self.buffer.replace(payload.take());
+        if result.is_ok() {
+            self.sending.set(true);
+        }
+        result
+    }
+
+    /// Writes a link-layer address option for the MAC address of the device.
+    fn write_ll_option(&self, buf: &mut [u8], option_type: u8) -> usize {
+        buf[..16].fill(0);
+        buf[0] = option_type;
+        buf[1] = 2;
+        buf[2..10].copy_from_slice(&self.mac);
+        16
+    }
+
+    fn send_dad(&self, addr: IPAddr) -> Result<(), ErrorCode> {
+        let mut dst = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, 0, 0, 0]);
+        dst.0[13..].copy_from_slice(&addr.0[13..]);
+        self.send(
+            IPAddr::new(),
+            dst,
+            ICMP6Type::Type135,
+            ICMP6HeaderOptions::Type135 { reserved: 0 },
+            |buf| {
+                buf[..16].copy_from_slice(&addr.0);
+                16
+            },
+        )
+    }
+
+    fn send_rs(&self) -> Result<(), ErrorCode> {
+        self.send(
+            self.link_local(),
+            ALL_ROUTERS,
+            ICMP6Type::Type133,
+            ICMP6HeaderOptions::Type133 { reserved: 0 },
+            |buf| self.write_ll_option(buf, option::SOURCE_LL_ADDR),
+        )
+    }
+
+    fn send_na(&self, dst: IPAddr, target: IPAddr, solicited: bool) -> Result<(), ErrorCode> {
+        let mut flags = na_flags::OVERRIDE;
+        if solicited {
+            flags |= na_flags::SOLICITED;
+        }
+        self.send(
+            self.link_local(),
+            dst,
+            ICMP6Type::Type136,
+            ICMP6HeaderOptions::Type136 { flags },
+            |buf| {
+                buf[..16].copy_from_slice(&target.0);
+                16 + self.write_ll_option(&mut buf[16..], option::TARGET_LL_ADDR)
+            },
+        )
+    }
+
+    fn receive_ns(&self, src: IPAddr, body: &[u8]) {
+        let Some(target) = read_addr(body) else {
+            return;
+        };
+        if let State::Dad { addr, .. } = self.state.get() {
+            // Solicitations with a source address are address resolution,
+            // which is ignored for a tentative address.
+            if addr == target && src.is_unspecified() {
+                self.dad_failed(addr);
+                return;
+            }
+        }
+        if !self.is_own_address(target) {
+            return;
+        }
+        if src.is_unspecified() {
+            // Another node is running DAD on one of our addresses.
+            let _ = self.send_na(ALL_NODES, target, false);
+        } else {
+            if let Some(mac) = find_ll_option(&body[16..], option::SOURCE_LL_ADDR) {
+                self.update_neighbor(src, mac);
+            }
+            let _ = self.send_na(src, target, true);
+        }
+    }
+
+    fn receive_na(&self, body: &[u8]) {
+        let Some(target) = read_addr(body) else {
+            return;
+        };
+        if let State::Dad { addr, .. } = self.state.get() {
+            if addr == target {
+                self.dad_failed(addr);
+                return;
+            }
+        }
+        if let Some(mac) = find_ll_option(&body[16..], option::TARGET_LL_ADDR) {
+            self.update_neighbor(target, mac);
+        }
+    }
+
+    fn receive_ra(&self, src: IPAddr, router_lifetime: u16, body: &[u8]) {
+        if !src.is_unicast_link_local() || body.len() < 8 {
+            return;
+        }
+        let options = &body[8..];
+        let now = self.now();
+
+        let mac = find_ll_option(options, option::SOURCE_LL_ADDR)
+            .unwrap_or(MacAddress::Long(mac_from_ipv6(src)));
+        self.update_neighbor(src, mac);
+        if router_lifetime == 0 {
+            if self.router.get().is_some_and(|r| r.ip == src) {
+                self.router.set(None);
+            }
+        } else {
+            self.router.set(Some(Router {
+                ip: src,
+                mac,
+                expires: now.wrapping_add(router_lifetime.into()),
+            }));
+        }
+
+        let mut dad_started = false;
+        for (option_type, option) in Options(options) {
+            if option_type != option::PREFIX_INFO || option.len() != 32 {
+                continue;
+            }
+            let prefix_len = option[2];
+            let flags = option[3];
+            let valid = u32::from_be_bytes([option[4], option[5], option[6], option[7]]);
+            let preferred = u32::from_be_bytes([option[8], option[9], option[10], option[11]]);
+            if prefix_len != 64
+                || flags & PREFIX_AUTONOMOUS == 0
+                || valid == 0
+                || preferred > valid
+                || option[16..18] == [0xfe, 0x80]
+            {
+                continue;
+            }
+
+            let mut addr = self.link_local();
+            addr.set_prefix(&option[16..24], 64);
+            let expires = (valid != INFINITE_LIFETIME).then(|| now.wrapping_add(valid));
+            match self.global.get() {
+                Some(global) if global.addr == addr => {
+                    self.global.set(Some(Global { addr, expires }));
+                }
+                None if matches!(self.state.get(), State::Soliciting(_) | State::Running) => {
+                    self.start_dad(addr, expires);
+                    dad_started = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        if !dad_started && matches!(self.state.get(), State::Soliciting(_)) {
+            self.state.set(State::Running);
+        }
+        if self.state.get() == State::Running {
+            self.expire();
+        }
+    }
+}
+
+/// Reads the target address at the start of a Neighbor Solicitation or
+/// Advertisement body.
+fn read_addr(body: &[u8]) -> Option<IPAddr> {
+    let mut addr = IPAddr::new();
+    addr.0.copy_from_slice(body.get(..16)?);
+    Some(addr)
+}
+
+/// Returns the link-layer address of the first option of `option_type`. The
+/// address is 8 bytes long for extended addresses and 2 bytes long for short
+/// addresses (RFC 4944).
+fn find_ll_option(options: &[u8], option_type: u8) -> Option<MacAddress> {
+    Options(options)
+        .find(|(t, _)| *t == option_type)
+        .and_then(|(_, option)| match option.len() {
+            8 => Some(MacAddress::Short(u16::from_be_bytes([
+                option[2], option[3],
+            ]))),
+            16 => {
+                let mut mac = [0; 8];
+                mac.copy_from_slice(&option[2..10]);
+                Some(MacAddress::Long(mac))
+            }
+            _ => None,
+        })
+}
+
+/// Iterates over the Neighbor Discovery options in a buffer, returning the
+/// type and the whole option of each.
+struct Options<'b>(&'b [u8]);
+
+impl<'b> Iterator for Options<'b> {
+    type Item = (u8, &'b [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = usize::from(*self.0.get(1)?) * 8;
+        if len == 0 || len > self.0.len() {
+            return None;
+        }
+        let (option, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some((option[0], option))
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6NextHop for NeighborDiscovery<'a, A> {
+    fn next_hop(&self, dst: IPAddr) -> Option<MacAddress> {
+        let now = self.now();
+        for slot in self.neighbors {
+            if let Some(mut neighbor) = slot.get() {
+                if neighbor.ip == dst {
+                    neighbor.used = now;
+                    slot.set(Some(neighbor));
+                    return Some(neighbor.mac);
+                }
+            }
+        }
+        if dst.is_unicast_link_local() || dst.is_multicast() {
+            return None;
+        }
+        self.router.get().map(|router| router.mac)
+    }
+
+    fn src_addr(&self, _dst: IPAddr) -> Option<IPAddr> {
+        self.global.get().map(|global| global.addr)
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6RecvClient for NeighborDiscovery<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        // Neighbor Discovery messages must not have been forwarded.
+        if ip_header.get_next_header() != ip6_nh::ICMP || ip_header.get_hop_limit() != 255 {
+            return;
+        }
+        let Some((offset, icmp_header)) = ICMP6Header::decode(payload).done() else {
+            return;
+        };
+        if icmp_header.get_code() != 0 {
+            return;
+        }
+        let body = &payload[offset..];
+        let src = ip_header.get_src_addr();
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type134 {
+                router_lifetime, ..
+            } => self.receive_ra(src, router_lifetime, body),
+            ICMP6HeaderOptions::Type135 { .. } => self.receive_ns(src, body),
+            ICMP6HeaderOptions::Type136 { .. } => self.receive_na(body),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> IPSendClient for NeighborDiscovery<'a, A> {
+    fn send_done(&self, _result: Result<(), ErrorCode>) {
+        self.sending.set(false);
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for NeighborDiscovery<'a, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Idle | State::Failed => {}
+            State::Starting => self.start_dad(self.link_local(), None),
+            State::Dad {
+                addr,
+                expires,
+                sent,
+            } => {
+                if !sent {
+                    // The solicitation could not be sent, try again.
+                    self.start_dad(addr, expires);
+                } else if addr.is_unicast_link_local() {
+                    self.add_address(addr);
+                    self.solicit(0);
+                } else {
+                    self.add_address(addr);
+                    self.global.set(Some(Global { addr, expires }));
+                    self.state.set(State::Running);
+                    self.expire();
+                }
+            }
+            State::Soliciting(sent) => self.solicit(sent),
+            State::Running => self.expire(),
+        }
+    }
+}
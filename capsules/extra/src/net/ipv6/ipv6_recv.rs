@@ -2,9 +2,11 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2022.
 
+use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::ipv6::IP6Header;
 use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
 
+use kernel::collections::list::{List, ListLink, ListNode};
 use kernel::debug;
 use kernel::utilities::cells::OptionalCell;
 use kernel::ErrorCode;
@@ -27,7 +29,10 @@ use kernel::ErrorCode;
 - `sixlowpan_state` has a single rx_client, which in our case is a single struct that
   implements the `ip_receive ` trait.
 - the `ip_receive` implementing struct (`IP6RecvStruct`) has a single client, which is
-  udp_recv, a `UDPReceive` struct.
+  a `MuxIP6Receiver`. It passes every packet to the `IP6RecvUser`s of the
+  packet's next header: the UDP, TCP and ICMPv6 (echo, Neighbor Discovery)
+  receivers share this single IPv6/6LoWPAN receive path.
+- The UDP user has a single client, udp_recv, a `UDPReceive` struct.
 - The UDPReceive struct is a field of the UDPDriver, which ultimately passes the
   packets up to userland.
 */
@@ -36,11 +41,21 @@ pub trait IP6RecvClient {
     fn receive(&self, header: IP6Header, payload: &[u8]);
 }
 
+/// Client trait for receiving the transport packets of one protocol,
+/// independently of the IP version.
+///
+/// This is implemented by the UDP layer, which receives packets from the IPv6
+/// stack (as an `IP6RecvClient`) and from the IPv4 interface (see
+/// `ipv4::ip4_interface`). IPv4 addresses are given as IPv4-mapped addresses
+/// (`::ffff:a.b.c.d`).
+pub trait IPRecvClient {
+    fn receive(&self, src_addr: IPAddr, dst_addr: IPAddr, payload: &[u8]);
+}
+
 /// Receiver trait for IPv6.
 ///
-/// Currently only one implementation of this trait should exist,
-/// as we do not multiplex received packets based on the address.
-/// The receiver receives IP packets destined for any local address.
+/// Received packets are not multiplexed based on the address: the receiver
+/// receives IP packets destined for any local address.
 /// The receiver should drop any packets with destination addresses
 /// that are not among the local addresses of this device.
 pub trait IP6Receiver<'a> {
@@ -91,3 +106,63 @@ impl SixlowpanRxClient for IP6RecvStruct<'_> {
         }
     }
 }
+
+/// Passes received packets to the `IP6RecvUser`s of their next header.
+///
+/// This lets several transport protocols share one `IP6Receiver`. Several
+/// users can receive the same next header, for instance the ICMPv6 echo
+/// responder and Neighbor Discovery; each receives every packet of that
+/// next header and ignores the ones not meant for it.
+pub struct MuxIP6Receiver<'a> {
+    users: List<'a, IP6RecvUser<'a>>,
+}
+
+impl<'a> MuxIP6Receiver<'a> {
+    pub fn new() -> MuxIP6Receiver<'a> {
+        MuxIP6Receiver { users: List::new() }
+    }
+
+    pub fn add_user(&self, user: &'a IP6RecvUser<'a>) {
+        self.users.push_tail(user);
+    }
+}
+
+impl IP6RecvClient for MuxIP6Receiver<'_> {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        for user in self.users.iter() {
+            if user.next_header == header.get_next_header() {
+                user.client.map(|client| client.receive(header, payload));
+            }
+        }
+    }
+}
+
+/// A virtual `IP6Receiver` of a `MuxIP6Receiver`, receiving the packets of
+/// one next header.
+pub struct IP6RecvUser<'a> {
+    next_header: u8,
+    client: OptionalCell<&'a dyn IP6RecvClient>,
+    next: ListLink<'a, IP6RecvUser<'a>>,
+}
+
+impl<'a> IP6RecvUser<'a> {
+    pub fn new(next_header: u8) -> IP6RecvUser<'a> {
+        IP6RecvUser {
+            next_header,
+            client: OptionalCell::empty(),
+            next: ListLink::empty(),
+        }
+    }
+}
+
+impl<'a> ListNode<'a, IP6RecvUser<'a>> for IP6RecvUser<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, IP6RecvUser<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> IP6Receiver<'a> for IP6RecvUser<'a> {
+    fn set_client(&self, client: &'a dyn IP6RecvClient) {
+        self.client.set(client);
+    }
+}
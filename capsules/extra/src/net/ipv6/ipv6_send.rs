@@ -5,12 +5,18 @@
 //! This file contains the interface definition for sending an IPv6 packet.
 //!
 //! The [IP6Sender](trait.IP6Sender.html) trait provides an interface
-//! for sending IPv6 packets, while the [IP6SendClient](trait.IP6SendClient) trait
-//! must be implemented by upper layers to receive the `send_done` callback
-//! when a transmission has completed.
+//! for sending IPv6 packets. It extends the [IPSender](trait.IPSender.html)
+//! trait, which holds the methods that do not depend on the IP version and is
+//! also implemented by the IPv4 interface. The
+//! [IPSendClient](trait.IPSendClient) trait must be implemented by upper
+//! layers to receive the `send_done` callback when a transmission has
+//! completed.
 //!
 //! This file also includes an implementation of the `IP6Sender` trait, which
-//! sends an IPv6 packet using 6LoWPAN.
+//! sends an IPv6 packet using 6LoWPAN, and the `MuxIP6Sender`, which shares
+//! one such sender between the transport protocols (UDP, TCP, ICMPv6) of the
+//! stack. Each protocol sends through its own `IP6SendUser`, and the mux
+//! transmits the packets of its users one at a time, in FIFO order.
 
 // Additional Work and Known Problems
 // ----------------------------------
@@ -27,38 +33,76 @@ use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
 use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
 use crate::net::sixlowpan::sixlowpan_state::TxState;
-use crate::net::thread::thread_utils::{mac_from_ipv6, MULTICAST_IPV6};
+use crate::net::thread::thread_utils::mac_from_ipv6;
 
 use core::cell::Cell;
 
+use kernel::collections::list::{List, ListLink, ListNode};
 use kernel::debug;
 use kernel::hil::time::{self, ConvertTicks};
-use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
 use kernel::utilities::leasable_buffer::SubSliceMut;
 use kernel::ErrorCode;
 
 /// Client trait for receiving transmission completiong events.
 ///
-/// The upper layer must then call `IP6Sender.set_client` in order to
+/// The upper layer must then call `IPSender.set_client` in order to
 /// receive this callback.
-pub trait IP6SendClient {
+pub trait IPSendClient {
     fn send_done(&self, result: Result<(), ErrorCode>);
 }
 
-/// Provides a basic IPv6 sending interface.
+/// Provides the next hop and source address for IPv6 packets.
 ///
-/// It exposes basic configuration information for the IPv6 layer
-/// (setting the source address, setting the gateway MAC address), as
-/// well as a way to send an IPv6 packet.
-pub trait IP6Sender<'a> {
-    /// This method sets the `IP6SendClient` for the `IP6Sender` instance, which
+/// This is implemented by Neighbor Discovery (see `ipv6_nd`), which learns the
+/// link-layer addresses of neighbors and routers and autoconfigures global
+/// addresses.
+pub trait IP6NextHop {
+    /// Returns the MAC address of the next hop towards `dst`, if it is known.
+    fn next_hop(&self, dst: IPAddr) -> Option<MacAddress>;
+
+    /// Returns a global source address for packets to `dst`, if one has been
+    /// configured.
+    fn src_addr(&self, dst: IPAddr) -> Option<IPAddr>;
+}
+
+/// Provides the part of the IP sending interface that does not depend on the
+/// IP version: a way to send a transport header and payload to an address.
+///
+/// This is implemented by the IPv6 senders and by the IPv4 interface (see
+/// `ipv4::ip4_interface`), so the UDP layer runs on top of either. IPv4
+/// addresses are given as IPv4-mapped addresses (`::ffff:a.b.c.d`).
+pub trait IPSender<'a> {
+    /// This method sets the `IPSendClient` for the `IPSender` instance, which
     /// receives the `send_done` callback when transmission has finished.
     ///
     /// # Arguments
-    /// `client` - Client that implements the `IP6SendClient` trait to receive the
+    /// `client` - Client that implements the `IPSendClient` trait to receive the
     /// `send_done` callback
-    fn set_client(&self, client: &'a dyn IP6SendClient);
+    fn set_client(&self, client: &'a dyn IPSendClient);
 
+    /// This method sends the provided transport header and payload to the
+    /// given destination IP address
+    ///
+    /// # Arguments
+    /// `dst` - IP address to send the packet to
+    /// `transport_header` - The `TransportHeader` for the packet being sent
+    /// `payload` - The transport payload for the packet being sent
+    fn send_to(
+        &'a self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &SubSliceMut<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode>;
+}
+
+/// Provides a basic IPv6 sending interface.
+///
+/// In addition to sending packets through `IPSender`, it exposes basic
+/// configuration information for the IPv6 layer (setting the source address,
+/// setting the gateway MAC address and the next hop provider).
+pub trait IP6Sender<'a>: IPSender<'a> {
     /// This method sets the source address for packets sent from the
     /// `IP6Sender` instance.
     ///
@@ -74,27 +118,14 @@ pub trait IP6Sender<'a> {
     /// `gateway` - MAC address to send the constructed packet to
     fn set_gateway(&self, gateway: MacAddress);
 
-    /// This method sets the `IP6Header` for the `IP6Sender` instance
+    /// This method sets the `IP6NextHop` used to find the next hop for each
+    /// packet. Without one, packets to link-local addresses are sent to the
+    /// MAC address in the address and all other packets are sent to the
+    /// destination MAC address the sender was created with.
     ///
     /// # Arguments
-    /// `ip6_header` - New `IP6Header` that subsequent packets sent via this
-    /// `IP6Sender` instance will use
-    fn set_header(&mut self, ip6_header: IP6Header);
-
-    /// This method sends the provided transport header and payload to the
-    /// given destination IP address
-    ///
-    /// # Arguments
-    /// `dst` - IPv6 address to send the packet to
-    /// `transport_header` - The `TransportHeader` for the packet being sent
-    /// `payload` - The transport payload for the packet being sent
-    fn send_to(
-        &self,
-        dst: IPAddr,
-        transport_header: TransportHeader,
-        payload: &SubSliceMut<'static, u8>,
-        net_cap: &'static NetworkCapability,
-    ) -> Result<(), ErrorCode>;
+    /// `next_hop` - Provider of next hop MAC addresses and source addresses
+    fn set_next_hop(&self, next_hop: &'a dyn IP6NextHop);
 }
 
 /// This struct is a specific implementation of the `IP6Sender` trait. This
@@ -112,15 +143,12 @@ pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     radio: &'a dyn MacDevice<'a>,
     dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
-    client: OptionalCell<&'a dyn IP6SendClient>,
+    client: OptionalCell<&'a dyn IPSendClient>,
+    next_hop: OptionalCell<&'a dyn IP6NextHop>,
     ip_vis: &'static IpVisibilityCapability,
 }
 
 impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
-    fn set_client(&self, client: &'a dyn IP6SendClient) {
-        self.client.set(client);
-    }
-
     fn set_addr(&self, src_addr: IPAddr) {
         self.src_addr.set(src_addr);
     }
@@ -129,13 +157,18 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         self.gateway.set(gateway);
     }
 
-    fn set_header(&mut self, ip6_header: IP6Header) {
-        self.ip6_packet
-            .map(|ip6_packet| ip6_packet.header = ip6_header);
+    fn set_next_hop(&self, next_hop: &'a dyn IP6NextHop) {
+        self.next_hop.set(next_hop);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IPSender<'a> for IP6SendStruct<'a, A> {
+    fn set_client(&self, client: &'a dyn IPSendClient) {
+        self.client.set(client);
     }
 
     fn send_to(
-        &self,
+        &'a self,
         dst: IPAddr,
         transport_header: TransportHeader,
         payload: &SubSliceMut<'static, u8>,
@@ -151,9 +184,12 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         // but may conflict with some other or future protocol
         // that sits above and uses IPV6
         let dst_mac_addr;
-        if dst == MULTICAST_IPV6 {
+        if dst.is_multicast() {
             // use short multicast ipv6 for dst mac address
             dst_mac_addr = MacAddress::Short(0xFFFF)
+        } else if let Some(next_hop) = self.next_hop.get().and_then(|nh| nh.next_hop(dst)) {
+            // neighbor discovery knows where to send this packet
+            dst_mac_addr = next_hop
         } else if dst.0[0..8] == [0xfe, 0x80, 0, 0, 0, 0, 0, 0] {
             // ipv6 address is of form fe80::MAC; use mac_from_ipv6
             // helper function to determine ipv6 to send to
@@ -195,6 +231,7 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
             dst_mac_addr,
             src_mac_addr,
             client: OptionalCell::empty(),
+            next_hop: OptionalCell::empty(),
             ip_vis,
         }
     }
@@ -210,8 +247,19 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
                 debug!("init packet failed.");
             },
             |ip6_packet| {
+                // A link-local source address cannot be used for packets
+                // leaving the link, so use a global address if there is one.
+                let mut src_addr = self.src_addr.get();
+                if src_addr.is_unicast_link_local()
+                    && !dst_addr.is_unicast_link_local()
+                    && !dst_addr.is_multicast()
+                {
+                    if let Some(global) = self.next_hop.get().and_then(|nh| nh.src_addr(dst_addr)) {
+                        src_addr = global;
+                    }
+                }
                 ip6_packet.header = IP6Header::default();
-                ip6_packet.header.src_addr = self.src_addr.get();
+                ip6_packet.header.src_addr = src_addr;
                 ip6_packet.header.dst_addr = dst_addr;
                 ip6_packet.set_payload(transport_header, payload);
                 ip6_packet.set_transport_checksum();
@@ -304,3 +352,158 @@ impl<'a, A: time::Alarm<'a>> TxClient for IP6SendStruct<'a, A> {
         }
     }
 }
+
+/// Shares an `IP6Sender` between several `IP6SendUser`s.
+///
+/// Users with a packet to send are queued, and the packet of the user at the
+/// head of the queue is passed to the underlying sender. Each user can have a
+/// single outstanding packet.
+pub struct MuxIP6Sender<'a> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    sender_list: List<'a, IP6SendUser<'a>>,
+}
+
+impl<'a> MuxIP6Sender<'a> {
+    pub fn new(ip_sender: &'a dyn IP6Sender<'a>) -> MuxIP6Sender<'a> {
+        MuxIP6Sender {
+            ip_sender,
+            sender_list: List::new(),
+        }
+    }
+
+    fn send_to(&self, user: &'a IP6SendUser<'a>) -> Result<(), ErrorCode> {
+        let list_empty = self.sender_list.head().is_none();
+        self.sender_list.push_tail(user);
+        if !list_empty {
+            return Ok(());
+        }
+        let ret = self.send_head(user);
+        if ret.is_err() {
+            // No send_done will follow, so do not block the users queued
+            // after this one.
+            self.sender_list.pop_head();
+        }
+        ret
+    }
+
+    /// Passes the queued packet of `user`, at the head of the queue, to the
+    /// underlying sender.
+    fn send_head(&self, user: &IP6SendUser<'a>) -> Result<(), ErrorCode> {
+        let (dst, transport_header, net_cap) = user.pending.take().ok_or(ErrorCode::FAIL)?;
+        let buf = user.buffer.take().ok_or(ErrorCode::BUSY)?;
+        self.ip_sender.set_addr(user.src_addr.get());
+        let ret = self.ip_sender.send_to(dst, transport_header, &buf, net_cap);
+        user.buffer.replace(buf);
+        ret
+    }
+}
+
+impl IPSendClient for MuxIP6Sender<'_> {
+    fn send_done(&self, result: Result<(), ErrorCode>) {
+        let mut result = result;
+        // A queued packet that fails to start will never get a send_done of
+        // its own, so report it to its user here and move on to the next one
+        // until a send starts or the queue is empty.
+        loop {
+            let last_sender = self.sender_list.pop_head();
+            // Must be read before the callback, which may queue another
+            // packet of the same user.
+            let next_sender = self.sender_list.head();
+            last_sender
+                .map(|last_sender| last_sender.client.map(|client| client.send_done(result)));
+            match next_sender.map(|next_sender| self.send_head(next_sender)) {
+                Some(Err(e)) => result = Err(e),
+                _ => break,
+            }
+        }
+    }
+}
+
+/// A virtual `IP6Sender` of a `MuxIP6Sender`.
+///
+/// The payload of a packet is copied into the buffer of the user, so it must
+/// be large enough for the largest payload the user sends. The source address
+/// set with `set_addr()` only applies to packets of this user, while the
+/// gateway and next hop are those of the shared sender.
+pub struct IP6SendUser<'a> {
+    mux: &'a MuxIP6Sender<'a>,
+    src_addr: Cell<IPAddr>,
+    buffer: MapCell<SubSliceMut<'static, u8>>,
+    pending: OptionalCell<(IPAddr, TransportHeader, &'static NetworkCapability)>,
+    client: OptionalCell<&'a dyn IPSendClient>,
+    next: ListLink<'a, IP6SendUser<'a>>,
+}
+
+impl<'a> IP6SendUser<'a> {
+    pub fn new(mux: &'a MuxIP6Sender<'a>, buffer: &'static mut [u8]) -> IP6SendUser<'a> {
+        IP6SendUser {
+            mux,
+            src_addr: Cell::new(IPAddr::new()),
+            buffer: MapCell::new(SubSliceMut::new(buffer)),
+            pending: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+            next: ListLink::empty(),
+        }
+    }
+}
+
+impl<'a> ListNode<'a, IP6SendUser<'a>> for IP6SendUser<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, IP6SendUser<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> IP6Sender<'a> for IP6SendUser<'a> {
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
+    }
+
+    fn set_gateway(&self, gateway: MacAddress) {
+        self.mux.ip_sender.set_gateway(gateway);
+    }
+
+    fn set_next_hop(&self, next_hop: &'a dyn IP6NextHop) {
+        self.mux.ip_sender.set_next_hop(next_hop);
+    }
+}
+
+impl<'a> IPSender<'a> for IP6SendUser<'a> {
+    fn set_client(&self, client: &'a dyn IPSendClient) {
+        self.client.set(client);
+    }
+
+    fn send_to(
+        &'a self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &SubSliceMut<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        // The packet of a queued user is still waiting to be sent.
+        if self
+            .mux
+            .sender_list
+            .iter()
+            .any(|user| core::ptr::eq(user, self))
+        {
+            return Err(ErrorCode::BUSY);
+        }
+        self.buffer
+            .map(|buf| {
+                buf.reset();
+                if payload.len() > buf.len() {
+                    return Err(ErrorCode::SIZE);
+                }
+                buf[..payload.len()].copy_from_slice(payload.as_slice());
+                buf.slice(0..payload.len());
+                Ok(())
+            })
+            .unwrap_or(Err(ErrorCode::BUSY))?;
+        self.pending.set((dst, transport_header, net_cap));
+        let ret = self.mux.send_to(self);
+        if ret.is_err() {
+            self.pending.clear();
+        }
+        ret
+    }
+}
@@ -3,6 +3,7 @@
 // Copyright Tock Contributors 2022.
 
 pub mod ip_utils;
+pub mod ipv6_nd;
 pub mod ipv6_recv;
 pub mod ipv6_send;
 
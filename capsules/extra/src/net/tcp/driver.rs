@@ -0,0 +1,784 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! TCP userspace interface.
+//!
+//! Implements a userspace interface for TCP connections over the IPv6 stack.
+//! Each process can hold a single connection at a time, which it opens
+//! either actively (connect) or passively (listen, accepting the first
+//! incoming connection). The connection state lives in the grant of the
+//! process, so a process that exits or restarts drops its connection.
+//!
+//! Sent data is transmitted directly out of the read-only allow buffer of
+//! the process, which must not be modified until the send completes: the
+//! kernel keeps no copy of unacknowledged data and retransmits from the
+//! process buffer. Received data is appended to the read-write receive
+//! buffer, whose free space is advertised to the peer as the receive window.
+//! The process releases the received data with the `consume` command.
+//!
+//! Local ports are shared between all processes using this driver: a port
+//! used by a listening or connected process cannot be used by another
+//! process until the connection is closed. Bound ports are tracked in a
+//! `TcpPortTable`. Which ports and remote endpoints may be used is restricted
+//! by the `NetworkCapability` of the driver.
+
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6NextHop, IP6Sender, IPSendClient};
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::{NetworkCapability, UdpVisibilityCapability};
+use crate::net::tcp::tcp_connection::{reset_for, TcpConnection, TcpState, TIME_WAIT_MS};
+use crate::net::tcp::tcp_port_table::TcpPortTable;
+use crate::net::tcp::{tcp_flags, TCPHeader};
+use crate::net::util::host_slice_to_u16;
+
+use core::cell::Cell;
+use core::mem::size_of;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::{ErrorCode, ProcessId};
+
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Tcp as usize;
+
+/// First port handed out for connections that do not request a local port
+/// (the IANA dynamic port range).
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// Size of an endpoint in the config buffer: a 16 byte IPv6 address followed
+/// by a port in host byte order, as used by the UDP driver.
+const ENDPOINT_LEN: usize = size_of::<IPAddr>() + size_of::<u16>();
+
+/// IDs for subscribed upcalls.
+mod upcall {
+    /// The connection was established. For a passively opened connection,
+    /// the remote endpoint is written to the config buffer beforehand.
+    /// Argument 0 is the status (always success).
+    pub const CONNECTED: usize = 0;
+    /// Data was received. Argument 0 is the number of unconsumed bytes at
+    /// the start of the receive buffer.
+    pub const RECEIVED: usize = 1;
+    /// All data passed to the last `send` command was acknowledged by the
+    /// peer. Argument 0 is the status, argument 1 the number of bytes.
+    pub const SENT: usize = 2;
+    /// The peer closed its side of the connection; no more data will be
+    /// received.
+    pub const PEER_CLOSED: usize = 3;
+    /// The connection is closed. Argument 0 is the status: success for an
+    /// orderly close, `FAIL` if it was reset and `NOACK` if the peer stopped
+    /// acknowledging data.
+    pub const CLOSED: usize = 4;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 5;
+}
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// Write buffer. Contains the data to be sent.
+    pub const WRITE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Read buffer. Received data is appended to it.
+    pub const READ: usize = 0;
+    /// Config buffer. Holds the remote endpoint to connect to, or the
+    /// endpoint that connected to a listening process.
+    pub const CFG: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// A timer of a single connection, running from `reference` for `dt` ticks.
+#[derive(Copy, Clone)]
+struct Timer<T: Ticks> {
+    reference: T,
+    dt: T,
+    time_wait: bool,
+}
+
+pub struct App<T: Ticks> {
+    conn: TcpConnection,
+    /// Number of unconsumed bytes at the start of the receive buffer.
+    rx_len: usize,
+    /// Length of the data passed to the pending `send` command.
+    tx_len: usize,
+    timer: Option<Timer<T>>,
+    /// When the segment timed for round trip time estimation was sent.
+    rtt_start: Option<T>,
+}
+
+impl<T: Ticks> Default for App<T> {
+    fn default() -> App<T> {
+        App {
+            conn: TcpConnection::default(),
+            rx_len: 0,
+            tx_len: 0,
+            timer: None,
+            rtt_start: None,
+        }
+    }
+}
+
+pub struct TCPDriver<'a, A: Alarm<'a>> {
+    /// IPv6 sender used for all segments
+    sender: &'a dyn IP6Sender<'a>,
+
+    /// Alarm driving retransmissions and TIME-WAIT
+    alarm: &'a A,
+
+    /// Grant of apps that use this driver.
+    apps: Grant<
+        App<A::Ticks>,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+
+    /// Whether a segment has been passed to the IPv6 sender and its
+    /// `send_done` is outstanding.
+    tx_busy: Cell<bool>,
+
+    /// Reset to send in reply to a segment that matched no connection.
+    pending_reset: OptionalCell<(IPAddr, TCPHeader)>,
+
+    /// List of IP Addresses of the interfaces on the device
+    interface_list: &'static [Cell<IPAddr>],
+
+    /// Maximum payload of a single segment
+    max_tx_pyld_len: usize,
+
+    kernel_buffer: MapCell<SubSliceMut<'static, u8>>,
+
+    net_cap: &'static NetworkCapability,
+
+    /// Port ranges of a `NetworkCapability` apply to TCP as well as UDP.
+    port_vis: &'static UdpVisibilityCapability,
+
+    /// Local ports bound by connections
+    port_table: &'a TcpPortTable,
+
+    next_ephemeral_port: Cell<u16>,
+    iss_counter: Cell<u32>,
+}
+
+impl<'a, A: Alarm<'a>> TCPDriver<'a, A> {
+    pub fn new(
+        sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        grant: Grant<
+            App<A::Ticks>,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        interface_list: &'static [Cell<IPAddr>],
+        max_tx_pyld_len: usize,
+        kernel_buffer: SubSliceMut<'static, u8>,
+        net_cap: &'static NetworkCapability,
+        port_vis: &'static UdpVisibilityCapability,
+        port_table: &'a TcpPortTable,
+    ) -> TCPDriver<'a, A> {
+        TCPDriver {
+            sender,
+            alarm,
+            apps: grant,
+            tx_busy: Cell::new(false),
+            pending_reset: OptionalCell::empty(),
+            interface_list,
+            max_tx_pyld_len,
+            kernel_buffer: MapCell::new(kernel_buffer),
+            net_cap,
+            port_vis,
+            port_table,
+            next_ephemeral_port: Cell::new(EPHEMERAL_PORT_START),
+            iss_counter: Cell::new(0),
+        }
+    }
+
+    /// Sets the `IP6NextHop` of the IPv6 sender used for all segments.
+    pub fn set_next_hop(&self, next_hop: &'a dyn IP6NextHop) {
+        self.sender.set_next_hop(next_hop);
+    }
+
+    /// Returns a new initial sequence number. As in RFC 793, the sequence
+    /// number is driven by a clock, and perturbed by a counter so that
+    /// connections opened in quick succession do not share it.
+    fn generate_iss(&self) -> u32 {
+        let counter = self.iss_counter.get().wrapping_add(0x0001_0000);
+        self.iss_counter.set(counter);
+        self.alarm
+            .now()
+            .into_u32_left_justified()
+            .wrapping_mul(0x9e37_79b9)
+            .wrapping_add(counter)
+    }
+
+    /// Returns true if the connection of `processid` uses the local `port`.
+    fn uses_port(&self, processid: ProcessId, port: u16) -> bool {
+        self.apps
+            .enter(processid, |app, _| {
+                app.conn.is_bound() && app.conn.get_local_port() == port
+            })
+            .unwrap_or(false)
+    }
+
+    /// Returns true if `port` is the local port of any connection.
+    fn port_in_use(&self, port: u16) -> bool {
+        self.port_table
+            .is_bound(port, &|processid, port| self.uses_port(processid, port))
+    }
+
+    /// Binds the local `port` for a new connection of `processid`, returning
+    /// `BUSY` if the process already holds a connection or the port is in
+    /// use.
+    fn bind_port(&self, processid: ProcessId, port: u16) -> Result<(), ErrorCode> {
+        if self
+            .apps
+            .enter(processid, |app, _| app.conn.is_bound())
+            .map_err(ErrorCode::from)?
+        {
+            return Err(ErrorCode::BUSY);
+        }
+        self.port_table.bind(port, processid, &|processid, port| {
+            self.uses_port(processid, port)
+        })
+    }
+
+    /// Picks a free local port in the dynamic port range.
+    fn ephemeral_port(&self) -> Option<u16> {
+        let span = (u16::MAX - EPHEMERAL_PORT_START) as u32 + 1;
+        for _ in 0..span {
+            let port = self.next_ephemeral_port.get();
+            self.next_ephemeral_port
+                .set(port.checked_add(1).unwrap_or(EPHEMERAL_PORT_START));
+            if self.net_cap.local_port_valid(port, self.port_vis) && !self.port_in_use(port) {
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    fn parse_endpoint(buf: &[u8]) -> (IPAddr, u16) {
+        let (a, p) = buf.split_at(size_of::<IPAddr>());
+        let mut addr = IPAddr::new();
+        addr.0.copy_from_slice(a);
+        (addr, host_slice_to_u16(p))
+    }
+
+    /// Size of the free space in the receive buffer of a process.
+    fn rcv_window(app: &App<A::Ticks>, kernel_data: &GrantKernelData) -> usize {
+        kernel_data
+            .get_readwrite_processbuffer(rw_allow::READ)
+            .map_or(0, |read| read.len())
+            .saturating_sub(app.rx_len)
+    }
+
+    /// Updates the timer of a connection after its state changed.
+    fn update_timer(&self, app: &mut App<A::Ticks>, restart_retransmit: bool) {
+        if app.conn.is_time_wait() {
+            if !app.timer.is_some_and(|timer| timer.time_wait) {
+                app.timer = Some(Timer {
+                    reference: self.alarm.now(),
+                    dt: self.alarm.ticks_from_ms(TIME_WAIT_MS),
+                    time_wait: true,
+                });
+            }
+        } else if let Some(rto) = app.conn.retransmit_timeout_ms() {
+            if app.timer.is_none() || restart_retransmit {
+                app.timer = Some(Timer {
+                    reference: self.alarm.now(),
+                    dt: self.alarm.ticks_from_ms(rto),
+                    time_wait: false,
+                });
+            }
+        } else {
+            app.timer = None;
+        }
+    }
+
+    /// Programs the alarm for the earliest connection timer.
+    fn reschedule_alarm(&self) {
+        let now = self.alarm.now();
+        let mut earliest: Option<A::Ticks> = None;
+        self.apps.each(|_, app, _| {
+            if let Some(timer) = app.timer {
+                let expiration = timer.reference.wrapping_add(timer.dt);
+                let remaining = if now.within_range(timer.reference, expiration) {
+                    expiration.wrapping_sub(now)
+                } else {
+                    A::Ticks::from(0)
+                };
+                if earliest.is_none_or(|earliest| remaining < earliest) {
+                    earliest = Some(remaining);
+                }
+            }
+        });
+        match earliest {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Sends the next pending segment, if the IPv6 sender is idle. A pending
+    /// reset takes precedence over segments of connections.
+    fn do_next_tx(&self) {
+        if self.tx_busy.get() {
+            return;
+        }
+        if let Some((dst, rst)) = self.pending_reset.take() {
+            let _ = self.send_segment(dst, rst);
+            return;
+        }
+        let mut next = None;
+        for app in self.apps.iter() {
+            let processid = app.processid();
+            if app.enter(|app, _| app.conn.wants_to_send()) {
+                next = Some(processid);
+                break;
+            }
+        }
+        if let Some(processid) = next {
+            self.transmit(processid);
+        }
+    }
+
+    /// Builds the next segment of the connection of `processid` and passes it
+    /// to the IPv6 sender.
+    fn transmit(&self, processid: ProcessId) {
+        let segment = self
+            .apps
+            .enter(processid, |app, kernel_data| {
+                let rcv_window = Self::rcv_window(app, kernel_data);
+                let segment = app.conn.next_segment(self.max_tx_pyld_len, rcv_window)?;
+
+                let copied = self.kernel_buffer.map_or(Err(ErrorCode::NOMEM), |buf| {
+                    if segment.data_len == 0 {
+                        buf.slice(0..0);
+                        return Ok(());
+                    }
+                    kernel_data
+                        .get_readonly_processbuffer(ro_allow::WRITE)
+                        .and_then(|write| {
+                            write.enter(|data| {
+                                let end = segment.data_offset + segment.data_len;
+                                if end > data.len() || segment.data_len > buf.len() {
+                                    return Err(ErrorCode::SIZE);
+                                }
+                                data[segment.data_offset..end]
+                                    .copy_to_slice(&mut buf[..segment.data_len]);
+                                buf.slice(0..segment.data_len);
+                                Ok(())
+                            })
+                        })
+                        .unwrap_or(Err(ErrorCode::NOMEM))
+                });
+                if let Err(e) = copied {
+                    // The process shrank or revoked its send buffer.
+                    let reset = app.conn.abort();
+                    app.timer = None;
+                    let _ = kernel_data.schedule_upcall(
+                        upcall::CLOSED,
+                        (kernel::errorcode::into_statuscode(Err(e)), 0, 0),
+                    );
+                    return reset.map(|rst| (app.conn.get_remote_addr(), rst));
+                }
+
+                if segment.timed {
+                    app.rtt_start = Some(self.alarm.now());
+                }
+                self.update_timer(app, false);
+                Some((app.conn.get_remote_addr(), segment.header))
+            })
+            .unwrap_or(None);
+
+        self.reschedule_alarm();
+        match segment {
+            Some((dst, header)) => {
+                // Lost segments are recovered by the retransmission timer.
+                let _ = self.send_segment(dst, header);
+            }
+            None => {
+                if let Some(mut buf) = self.kernel_buffer.take() {
+                    buf.reset();
+                    self.kernel_buffer.replace(buf);
+                }
+            }
+        }
+    }
+
+    /// Passes a segment whose payload is in the kernel buffer to the IPv6
+    /// sender.
+    fn send_segment(&self, dst: IPAddr, header: TCPHeader) -> Result<(), ErrorCode> {
+        let mut buf = self.kernel_buffer.take().ok_or(ErrorCode::NOMEM)?;
+        if header.has_flags(tcp_flags::RST) {
+            buf.slice(0..0);
+        }
+        self.tx_busy.set(true);
+        // The IPv6 sender copies the payload, so the buffer can be reused
+        // right away.
+        let result = self
+            .sender
+            .send_to(dst, TransportHeader::TCP(header), &buf, self.net_cap);
+        buf.reset();
+        self.kernel_buffer.replace(buf);
+        if result.is_err() {
+            self.tx_busy.set(false);
+        }
+        result
+    }
+
+    fn connect(&self, processid: ProcessId, local_port: usize) -> Result<(), ErrorCode> {
+        let (remote_addr, remote_port) = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::CFG)
+                    .and_then(|cfg| {
+                        cfg.enter(|cfg| {
+                            if cfg.len() != ENDPOINT_LEN {
+                                return Err(ErrorCode::INVAL);
+                            }
+                            let mut endpoint = [0; ENDPOINT_LEN];
+                            cfg.copy_to_slice(&mut endpoint);
+                            Ok(Self::parse_endpoint(&endpoint))
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::INVAL))
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+        if remote_port == 0
+            || remote_addr.is_unspecified()
+            || remote_addr.is_multicast()
+            || !self.net_cap.remote_port_valid(remote_port, self.port_vis)
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let local_port = match local_port {
+            0 => self.ephemeral_port().ok_or(ErrorCode::BUSY)?,
+            port if port <= u16::MAX as usize => {
+                let port = port as u16;
+                if !self.net_cap.local_port_valid(port, self.port_vis) {
+                    return Err(ErrorCode::INVAL);
+                }
+                port
+            }
+            _ => return Err(ErrorCode::INVAL),
+        };
+        self.bind_port(processid, local_port)?;
+
+        let iss = self.generate_iss();
+        let result = self
+            .apps
+            .enter(processid, |app, _| {
+                app.conn
+                    .connect(local_port, remote_addr, remote_port, iss)?;
+                app.rx_len = 0;
+                app.tx_len = 0;
+                app.timer = None;
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        if result.is_err() {
+            self.port_table.unbind(processid);
+        }
+        result
+    }
+
+    fn listen(&self, processid: ProcessId, port: usize) -> Result<(), ErrorCode> {
+        if port == 0 || port > u16::MAX as usize {
+            return Err(ErrorCode::INVAL);
+        }
+        let port = port as u16;
+        if !self.net_cap.local_port_valid(port, self.port_vis) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.bind_port(processid, port)?;
+        let result = self
+            .apps
+            .enter(processid, |app, _| {
+                app.conn.listen(port)?;
+                app.rx_len = 0;
+                app.tx_len = 0;
+                app.timer = None;
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        if result.is_err() {
+            self.port_table.unbind(processid);
+        }
+        result
+    }
+}
+
+impl<'a, A: Alarm<'a>> SyscallDriver for TCPDriver<'a, A> {
+    /// TCP control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check.
+    /// - `1`: Listen on the port `arg1`. The first incoming connection is
+    ///   accepted, after which the process no longer listens. Returns INVAL
+    ///   if the port is 0 or not allowed, BUSY if the port is in use or the
+    ///   process already has a connection.
+    /// - `2`: Connect to the endpoint in the config buffer (16 byte IPv6
+    ///   address followed by the port in host byte order) from the local port
+    ///   `arg1`, or from a free port in the dynamic range if `arg1` is 0.
+    ///   Returns INVAL if the config buffer or endpoint is invalid, BUSY if
+    ///   the local port is in use or the process already has a connection.
+    /// - `3`: Send the first `arg1` bytes of the write buffer. The buffer must
+    ///   not be changed until the `SENT` upcall. Returns INVAL if the
+    ///   connection cannot send, BUSY if a send is pending and SIZE if the
+    ///   write buffer is shorter than `arg1`.
+    /// - `4`: Consume the received data. The process has copied the data out
+    ///   of the receive buffer, and further data is written from its start.
+    /// - `5`: Close the connection after all pending data is sent. The
+    ///   `CLOSED` upcall is scheduled once the close completes.
+    /// - `6`: Abort the connection, resetting it.
+    /// - `7`: Get the connection state (RFC 9293 state, 0 is CLOSED).
+    /// - `8`: Get the maximum number of bytes sent per segment.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        _: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let result = match command_num {
+            0 => return CommandReturn::success(),
+            1 => self.listen(processid, arg1),
+            2 => self.connect(processid, arg1),
+            3 => self
+                .apps
+                .enter(processid, |app, kernel_data| {
+                    let available = kernel_data
+                        .get_readonly_processbuffer(ro_allow::WRITE)
+                        .map_or(0, |write| write.len());
+                    if arg1 > available {
+                        return Err(ErrorCode::SIZE);
+                    }
+                    app.conn.send(arg1)?;
+                    app.tx_len = arg1;
+                    Ok(())
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            4 => self
+                .apps
+                .enter(processid, |app, _| {
+                    app.rx_len = 0;
+                    app.conn.window_opened();
+                })
+                .map_err(ErrorCode::from),
+            5 => self
+                .apps
+                .enter(processid, |app, kernel_data| {
+                    if app.conn.close()? {
+                        app.timer = None;
+                        let _ = kernel_data.schedule_upcall(upcall::CLOSED, (0, 0, 0));
+                    }
+                    Ok(())
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+            6 => self
+                .apps
+                .enter(processid, |app, _| {
+                    let remote = app.conn.get_remote_addr();
+                    if let Some(rst) = app.conn.abort() {
+                        self.pending_reset.set((remote, rst));
+                    }
+                    app.rx_len = 0;
+                    app.timer = None;
+                })
+                .map_err(ErrorCode::from),
+            7 => {
+                return self
+                    .apps
+                    .enter(processid, |app, _| app.conn.get_state() as u32)
+                    .map_or_else(
+                        |err| CommandReturn::failure(err.into()),
+                        CommandReturn::success_u32,
+                    )
+            }
+            8 => return CommandReturn::success_u32(self.max_tx_pyld_len as u32),
+            _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+        };
+
+        match result {
+            Ok(()) => {
+                self.reschedule_alarm();
+                self.do_next_tx();
+                CommandReturn::success()
+            }
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+impl<'a, A: Alarm<'a>> IPSendClient for TCPDriver<'a, A> {
+    fn send_done(&self, _result: Result<(), ErrorCode>) {
+        // Lost segments are recovered by the retransmission timer.
+        self.tx_busy.set(false);
+        // The sender may fail synchronously from within `send_segment()`,
+        // which still holds the kernel buffer. The next segment is then sent
+        // on the next event instead.
+        if self.kernel_buffer.is_some() {
+            self.do_next_tx();
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6RecvClient for TCPDriver<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::TCP
+            || !self
+                .interface_list
+                .iter()
+                .any(|addr| addr.get() == ip_header.get_dst_addr())
+        {
+            return;
+        }
+        let (offset, header) = match TCPHeader::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let data = &payload[offset..];
+        let src_addr = ip_header.get_src_addr();
+        let (src_port, dst_port) = (header.get_src_port(), header.get_dst_port());
+
+        // Established connections take precedence over listening ones.
+        let mut connection = None;
+        let mut listener = None;
+        for app in self.apps.iter() {
+            let processid = app.processid();
+            app.enter(|app, _| {
+                if app.conn.matches(src_addr, src_port, dst_port) {
+                    if app.conn.get_state() == TcpState::Listen {
+                        listener = Some(processid);
+                    } else {
+                        connection = Some(processid);
+                    }
+                }
+            });
+        }
+
+        match connection.or(listener) {
+            None => {
+                if !header.has_flags(tcp_flags::RST) {
+                    self.pending_reset
+                        .set((src_addr, reset_for(&header, data.len())));
+                }
+            }
+            Some(processid) => {
+                let iss = self.generate_iss();
+                let _ = self.apps.enter(processid, |app, kernel_data| {
+                    let rcv_window = Self::rcv_window(app, kernel_data);
+                    let completes_rtt = header.has_flags(tcp_flags::ACK)
+                        && app.conn.completes_rtt_sample(header.get_ack_num());
+                    let result = app
+                        .conn
+                        .receive(src_addr, &header, data.len(), rcv_window, iss);
+
+                    if result.acked && completes_rtt {
+                        if let Some(start) = app.rtt_start.take() {
+                            let elapsed = self.alarm.now().wrapping_sub(start);
+                            app.conn.rtt_sample(self.alarm.ticks_to_ms(elapsed));
+                        }
+                    }
+
+                    if result.accepted > 0 {
+                        let start = app.rx_len;
+                        let copied = kernel_data
+                            .get_readwrite_processbuffer(rw_allow::READ)
+                            .and_then(|read| {
+                                read.mut_enter(|rbuf| {
+                                    rbuf[start..start + result.accepted]
+                                        .copy_from_slice(&data[..result.accepted]);
+                                })
+                            });
+                        if copied.is_ok() {
+                            app.rx_len += result.accepted;
+                            let _ =
+                                kernel_data.schedule_upcall(upcall::RECEIVED, (app.rx_len, 0, 0));
+                        }
+                    }
+                    if result.reply_reset {
+                        self.pending_reset
+                            .set((src_addr, reset_for(&header, data.len())));
+                    }
+                    if result.connected {
+                        // Tell a listening process who connected.
+                        let mut endpoint = [0; ENDPOINT_LEN];
+                        endpoint[..size_of::<IPAddr>()].copy_from_slice(&src_addr.0);
+                        endpoint[size_of::<IPAddr>()..].copy_from_slice(&src_port.to_ne_bytes());
+                        let _ = kernel_data
+                            .get_readwrite_processbuffer(rw_allow::CFG)
+                            .and_then(|cfg| {
+                                cfg.mut_enter(|cfg| {
+                                    if cfg.len() == ENDPOINT_LEN {
+                                        cfg.copy_from_slice(&endpoint);
+                                    }
+                                })
+                            });
+                        let _ = kernel_data.schedule_upcall(upcall::CONNECTED, (0, 0, 0));
+                    }
+                    if result.send_done {
+                        let _ = kernel_data.schedule_upcall(upcall::SENT, (0, app.tx_len, 0));
+                        app.tx_len = 0;
+                    }
+                    if result.peer_closed {
+                        let _ = kernel_data.schedule_upcall(upcall::PEER_CLOSED, (0, 0, 0));
+                    }
+                    if let Some(closed) = result.closed {
+                        let _ = kernel_data.schedule_upcall(
+                            upcall::CLOSED,
+                            (kernel::errorcode::into_statuscode(closed), 0, 0),
+                        );
+                    }
+                    self.update_timer(app, result.acked);
+                });
+            }
+        }
+
+        self.reschedule_alarm();
+        self.do_next_tx();
+    }
+}
+
+impl<'a, A: Alarm<'a>> AlarmClient for TCPDriver<'a, A> {
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        self.apps.each(|_, app, kernel_data| {
+            let expired = app.timer.is_some_and(|timer| {
+                !now.within_range(timer.reference, timer.reference.wrapping_add(timer.dt))
+            });
+            if !expired {
+                return;
+            }
+            app.timer = None;
+            app.rtt_start = None;
+            if let Some(closed) = app.conn.timer_expired() {
+                let _ = kernel_data.schedule_upcall(
+                    upcall::CLOSED,
+                    (kernel::errorcode::into_statuscode(closed), 0, 0),
+                );
+            }
+        });
+        self.reschedule_alarm();
+        self.do_next_tx();
+    }
+}
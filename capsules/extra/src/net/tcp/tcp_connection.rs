@@ -0,0 +1,1100 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! The TCP connection state machine (RFC 9293).
+//!
+//! A `TcpConnection` is the transmission control block of a single
+//! connection. It tracks the connection state and the send and receive
+//! sequence spaces, decides which segment should be sent next and processes
+//! received segments. It does not own any buffers or timers: the caller
+//! keeps the data to be sent (so that it can be retransmitted) and the data
+//! received, and is responsible for arming the retransmission and TIME-WAIT
+//! timers when `retransmit_timeout_ms()` or `is_time_wait()` asks for it.
+//!
+//! Simplifications compared to a full TCP implementation:
+//!
+//! - Only in-order segments are accepted; out-of-order segments are
+//!   acknowledged with a duplicate ACK and dropped, so the peer retransmits
+//!   them.
+//! - Retransmission is go-back-N from the oldest unacknowledged byte.
+//! - Options (MSS, window scaling, SACK, timestamps) are neither sent nor
+//!   interpreted, so the peer uses the default MSS.
+//! - Urgent data is not supported.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::tcp::{tcp_flags, TCPHeader};
+
+use kernel::ErrorCode;
+
+/// Initial retransmission timeout, as recommended by RFC 6298.
+const INITIAL_RTO_MS: u32 = 1000;
+const MIN_RTO_MS: u32 = 200;
+const MAX_RTO_MS: u32 = 60_000;
+
+/// Number of retransmissions of the same data before the connection is
+/// aborted.
+const MAX_RETRANSMISSIONS: u8 = 6;
+
+/// How long a connection stays in TIME-WAIT (2 * MSL).
+///
+/// This is much shorter than the 2 minutes suggested by RFC 9293, as the state is held in the
+/// grant of the application and blocks the application from opening a new
+/// connection.
+pub const TIME_WAIT_MS: u32 = 4000;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TcpState {
+    #[default]
+    Closed = 0,
+    Listen = 1,
+    SynSent = 2,
+    SynReceived = 3,
+    Established = 4,
+    FinWait1 = 5,
+    FinWait2 = 6,
+    CloseWait = 7,
+    Closing = 8,
+    LastAck = 9,
+    TimeWait = 10,
+}
+
+/// A segment that the connection wants to send. `data_offset` and `data_len`
+/// select the bytes of the send buffer that make up the payload.
+#[derive(Copy, Clone, Debug)]
+pub struct OutgoingSegment {
+    pub header: TCPHeader,
+    pub data_offset: usize,
+    pub data_len: usize,
+    /// The round trip time of this segment is being measured: the caller
+    /// should record when it was sent and report the elapsed time through
+    /// `rtt_sample()` once `completes_rtt_sample()` returns true.
+    pub timed: bool,
+}
+
+/// The outcome of processing a received segment.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SegmentResult {
+    /// Number of payload bytes accepted. They must be appended to the
+    /// receive buffer.
+    pub accepted: usize,
+    /// The connection was established.
+    pub connected: bool,
+    /// New data or control flags were acknowledged, so the retransmission
+    /// timer must be restarted.
+    pub acked: bool,
+    /// All data passed to `send()` has been acknowledged.
+    pub send_done: bool,
+    /// The peer closed its side of the connection (a FIN was received).
+    pub peer_closed: bool,
+    /// The connection is now closed, either normally or with an error.
+    pub closed: Option<Result<(), ErrorCode>>,
+    /// The segment was not acceptable and must be answered with a reset.
+    pub reply_reset: bool,
+}
+
+/// Returns whether `a` precedes `b` in sequence space.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+/// Builds the reset that answers an unacceptable segment `hdr`, carrying
+/// `payload_len` bytes, received on a port with no matching connection.
+pub fn reset_for(hdr: &TCPHeader, payload_len: usize) -> TCPHeader {
+    let mut rst = TCPHeader::new();
+    rst.set_src_port(hdr.get_dst_port());
+    rst.set_dst_port(hdr.get_src_port());
+    if hdr.has_flags(tcp_flags::ACK) {
+        rst.set_seq_num(hdr.get_ack_num());
+        rst.set_flags(tcp_flags::RST);
+    } else {
+        let mut seg_len = payload_len as u32;
+        if hdr.has_flags(tcp_flags::SYN) {
+            seg_len += 1;
+        }
+        if hdr.has_flags(tcp_flags::FIN) {
+            seg_len += 1;
+        }
+        rst.set_ack_num(hdr.get_seq_num().wrapping_add(seg_len));
+        rst.set_flags(tcp_flags::RST | tcp_flags::ACK);
+    }
+    rst
+}
+
+/// Transmission control block of a single TCP connection.
+#[derive(Copy, Clone, Debug)]
+pub struct TcpConnection {
+    state: TcpState,
+    local_port: u16,
+    remote_addr: IPAddr,
+    remote_port: u16,
+
+    // Send sequence space
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: u16,
+
+    // Receive sequence space
+    rcv_nxt: u32,
+
+    // The send buffer holds `tx_len` bytes, the first of which has sequence
+    // number `tx_start`. Once they are acknowledged, `tx_start` is the
+    // sequence number following them, which is where a FIN goes.
+    tx_start: u32,
+    tx_len: u32,
+
+    fin_queued: bool,
+    ack_pending: bool,
+    retransmissions: u8,
+
+    // Round trip time estimation (RFC 6298). `rtt_seq` is the sequence
+    // number whose acknowledgment completes the current measurement.
+    rtt_seq: Option<u32>,
+    srtt_ms: u32,
+    rttvar_ms: u32,
+    rto_ms: u32,
+}
+
+impl Default for TcpConnection {
+    fn default() -> TcpConnection {
+        TcpConnection {
+            state: TcpState::Closed,
+            local_port: 0,
+            remote_addr: IPAddr::new(),
+            remote_port: 0,
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_wnd: 0,
+            rcv_nxt: 0,
+            tx_start: 0,
+            tx_len: 0,
+            fin_queued: false,
+            ack_pending: false,
+            retransmissions: 0,
+            rtt_seq: None,
+            srtt_ms: 0,
+            rttvar_ms: 0,
+            rto_ms: INITIAL_RTO_MS,
+        }
+    }
+}
+
+impl TcpConnection {
+    pub fn get_state(&self) -> TcpState {
+        self.state
+    }
+
+    pub fn get_local_port(&self) -> u16 {
+        self.local_port
+    }
+
+    pub fn get_remote_addr(&self) -> IPAddr {
+        self.remote_addr
+    }
+
+    pub fn get_remote_port(&self) -> u16 {
+        self.remote_port
+    }
+
+    /// Whether this connection owns its local port.
+    pub fn is_bound(&self) -> bool {
+        self.state != TcpState::Closed
+    }
+
+    /// Whether a received segment with the given ports and source address
+    /// belongs to this connection.
+    pub fn matches(&self, src_addr: IPAddr, src_port: u16, dst_port: u16) -> bool {
+        match self.state {
+            TcpState::Closed => false,
+            TcpState::Listen => dst_port == self.local_port,
+            _ => {
+                dst_port == self.local_port
+                    && src_port == self.remote_port
+                    && src_addr == self.remote_addr
+            }
+        }
+    }
+
+    /// Whether the connection is in TIME-WAIT, during which the caller must
+    /// run a `TIME_WAIT_MS` timer and then call `timer_expired()`.
+    pub fn is_time_wait(&self) -> bool {
+        self.state == TcpState::TimeWait
+    }
+
+    /// Whether data passed to `send()` is still waiting to be acknowledged.
+    pub fn is_sending(&self) -> bool {
+        self.tx_len > 0
+    }
+
+    /// If sent data or control flags are unacknowledged, the retransmission
+    /// timeout the caller should arm.
+    pub fn retransmit_timeout_ms(&self) -> Option<u32> {
+        if self.snd_una != self.snd_nxt && self.state != TcpState::TimeWait {
+            Some(self.rto_ms)
+        } else {
+            None
+        }
+    }
+
+    /// Start listening for connections on `port`.
+    pub fn listen(&mut self, port: u16) -> Result<(), ErrorCode> {
+        if self.state != TcpState::Closed {
+            return Err(ErrorCode::BUSY);
+        }
+        *self = TcpConnection::default();
+        self.local_port = port;
+        self.state = TcpState::Listen;
+        Ok(())
+    }
+
+    /// Actively open a connection from `local_port` to the remote endpoint,
+    /// using `iss` as the initial sequence number.
+    pub fn connect(
+        &mut self,
+        local_port: u16,
+        remote_addr: IPAddr,
+        remote_port: u16,
+        iss: u32,
+    ) -> Result<(), ErrorCode> {
+        if self.state != TcpState::Closed {
+            return Err(ErrorCode::BUSY);
+        }
+        *self = TcpConnection::default();
+        self.local_port = local_port;
+        self.remote_addr = remote_addr;
+        self.remote_port = remote_port;
+        self.init_send_sequence(iss);
+        self.state = TcpState::SynSent;
+        Ok(())
+    }
+
+    fn init_send_sequence(&mut self, iss: u32) {
+        self.iss = iss;
+        self.snd_una = iss;
+        self.snd_nxt = iss;
+        // The SYN occupies `iss`, so data starts right after it.
+        self.tx_start = iss.wrapping_add(1);
+        self.tx_len = 0;
+    }
+
+    /// Queue `len` bytes of the send buffer for transmission. The caller
+    /// must keep the send buffer unchanged until `send_done` is reported.
+    pub fn send(&mut self, len: usize) -> Result<(), ErrorCode> {
+        match self.state {
+            TcpState::Established | TcpState::CloseWait => {}
+            _ => return Err(ErrorCode::INVAL),
+        }
+        if self.tx_len > 0 {
+            return Err(ErrorCode::BUSY);
+        }
+        if len == 0 {
+            return Err(ErrorCode::SIZE);
+        }
+        self.tx_start = self.snd_nxt;
+        self.tx_len = len as u32;
+        Ok(())
+    }
+
+    /// Close the sending side of the connection once all queued data has
+    /// been sent. Returns `true` if the connection is now fully closed.
+    pub fn close(&mut self) -> Result<bool, ErrorCode> {
+        match self.state {
+            TcpState::Closed => Err(ErrorCode::ALREADY),
+            TcpState::Listen | TcpState::SynSent => {
+                self.state = TcpState::Closed;
+                Ok(true)
+            }
+            TcpState::SynReceived | TcpState::Established => {
+                self.fin_queued = true;
+                self.state = TcpState::FinWait1;
+                Ok(false)
+            }
+            TcpState::CloseWait => {
+                self.fin_queued = true;
+                self.state = TcpState::LastAck;
+                Ok(false)
+            }
+            _ => Err(ErrorCode::ALREADY),
+        }
+    }
+
+    /// Abort the connection. Returns the reset to send to the peer, if the
+    /// peer knows about the connection.
+    pub fn abort(&mut self) -> Option<TCPHeader> {
+        let reset = match self.state {
+            TcpState::Closed | TcpState::Listen | TcpState::SynSent | TcpState::TimeWait => None,
+            _ => {
+                let mut rst = self.header(tcp_flags::RST | tcp_flags::ACK, 0);
+                rst.set_seq_num(self.snd_nxt);
+                Some(rst)
+            }
+        };
+        self.state = TcpState::Closed;
+        self.tx_len = 0;
+        reset
+    }
+
+    /// Whether `next_segment()` would return a segment.
+    pub fn wants_to_send(&self) -> bool {
+        self.next_segment_info(u16::MAX as usize).is_some()
+    }
+
+    fn header(&self, flags: u8, window: u16) -> TCPHeader {
+        let mut hdr = TCPHeader::new();
+        hdr.set_src_port(self.local_port);
+        hdr.set_dst_port(self.remote_port);
+        hdr.set_seq_num(self.snd_nxt);
+        if flags & tcp_flags::ACK != 0 {
+            hdr.set_ack_num(self.rcv_nxt);
+        }
+        hdr.set_flags(flags);
+        hdr.set_window(window);
+        hdr
+    }
+
+    // Returns the flags, data offset and data length of the next segment
+    // without updating any state.
+    fn next_segment_info(&self, mss: usize) -> Option<(u8, usize, usize)> {
+        match self.state {
+            TcpState::Closed | TcpState::Listen => return None,
+            // Only acknowledge the FIN of the peer (again, if it was lost).
+            TcpState::TimeWait => return self.ack_pending.then_some((tcp_flags::ACK, 0, 0)),
+            TcpState::SynSent if self.snd_nxt == self.iss => {
+                return Some((tcp_flags::SYN, 0, 0));
+            }
+            TcpState::SynReceived if self.snd_nxt == self.iss => {
+                return Some((tcp_flags::SYN | tcp_flags::ACK, 0, 0));
+            }
+            TcpState::SynSent | TcpState::SynReceived => return None,
+            _ => {}
+        }
+
+        let tx_end = self.tx_start.wrapping_add(self.tx_len);
+        if self.tx_len > 0 && seq_lt(self.snd_nxt, tx_end) {
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una);
+            // Always allow a single byte to probe a zero window once nothing
+            // is in flight.
+            let window = core::cmp::max(self.snd_wnd as u32, u32::from(in_flight == 0));
+            let usable = window.saturating_sub(in_flight);
+            let remaining = tx_end.wrapping_sub(self.snd_nxt);
+            let len = core::cmp::min(core::cmp::min(remaining, usable), mss as u32) as usize;
+            if len > 0 {
+                let offset = self.snd_nxt.wrapping_sub(self.tx_start) as usize;
+                let mut flags = tcp_flags::ACK;
+                if len as u32 == remaining {
+                    flags |= tcp_flags::PSH;
+                }
+                return Some((flags, offset, len));
+            }
+        }
+
+        // The FIN follows the last byte of the send buffer.
+        if self.fin_queued && self.snd_nxt == tx_end {
+            return Some((tcp_flags::FIN | tcp_flags::ACK, 0, 0));
+        }
+
+        self.ack_pending.then_some((tcp_flags::ACK, 0, 0))
+    }
+
+    /// Returns the next segment to send and advances the send sequence
+    /// space accordingly. `mss` limits the payload size and `rcv_window` is
+    /// the free space in the receive buffer advertised to the peer.
+    pub fn next_segment(&mut self, mss: usize, rcv_window: usize) -> Option<OutgoingSegment> {
+        let (flags, data_offset, data_len) = self.next_segment_info(mss)?;
+        let window = core::cmp::min(rcv_window, u16::MAX as usize) as u16;
+        let header = self.header(flags, window);
+
+        let mut seg_len = data_len as u32;
+        if flags & (tcp_flags::SYN | tcp_flags::FIN) != 0 {
+            seg_len += 1;
+        }
+        let timed = seg_len > 0 && self.rtt_seq.is_none() && self.retransmissions == 0;
+        if timed {
+            self.rtt_seq = Some(self.snd_nxt.wrapping_add(seg_len));
+        }
+        self.snd_nxt = self.snd_nxt.wrapping_add(seg_len);
+        self.ack_pending = false;
+
+        Some(OutgoingSegment {
+            header,
+            data_offset,
+            data_len,
+            timed,
+        })
+    }
+
+    /// Called when the timer armed for this connection expires. Returns the
+    /// result the connection closed with, if it did.
+    pub fn timer_expired(&mut self) -> Option<Result<(), ErrorCode>> {
+        if self.state == TcpState::TimeWait {
+            self.state = TcpState::Closed;
+            return Some(Ok(()));
+        }
+        if self.snd_una == self.snd_nxt {
+            return None;
+        }
+        if self.retransmissions >= MAX_RETRANSMISSIONS {
+            self.state = TcpState::Closed;
+            self.tx_len = 0;
+            return Some(Err(ErrorCode::NOACK));
+        }
+        // Go back to the oldest unacknowledged byte. Karn's algorithm: do
+        // not time retransmitted segments.
+        self.retransmissions += 1;
+        self.rto_ms = core::cmp::min(self.rto_ms.saturating_mul(2), MAX_RTO_MS);
+        self.rtt_seq = None;
+        self.snd_nxt = self.snd_una;
+        if self.state == TcpState::SynSent || self.state == TcpState::SynReceived {
+            // Resend the SYN
+            self.snd_nxt = self.iss;
+        }
+        None
+    }
+
+    /// Called with the time elapsed since the segment completing the
+    /// current round trip time measurement was sent.
+    pub fn rtt_sample(&mut self, rtt_ms: u32) {
+        if self.srtt_ms == 0 {
+            self.srtt_ms = rtt_ms;
+            self.rttvar_ms = rtt_ms / 2;
+        } else {
+            let delta = self.srtt_ms.abs_diff(rtt_ms);
+            self.rttvar_ms = (3 * self.rttvar_ms + delta) / 4;
+            self.srtt_ms = (7 * self.srtt_ms + rtt_ms) / 8;
+        }
+        self.rto_ms = (self.srtt_ms + 4 * self.rttvar_ms).clamp(MIN_RTO_MS, MAX_RTO_MS);
+    }
+
+    /// Whether the acknowledgment number `ack` completes the current round
+    /// trip time measurement.
+    pub fn completes_rtt_sample(&self, ack: u32) -> bool {
+        self.rtt_seq.is_some_and(|seq| seq_le(seq, ack))
+    }
+
+    /// Mark that the receive window grew, so that a window update is sent.
+    pub fn window_opened(&mut self) {
+        match self.state {
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 => {
+                self.ack_pending = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Process a segment received from `src_addr` that matches this
+    /// connection. `rcv_window` is the free space in the receive buffer and
+    /// `iss` the initial sequence number to use if the segment opens a new
+    /// connection on a listening socket.
+    pub fn receive(
+        &mut self,
+        src_addr: IPAddr,
+        hdr: &TCPHeader,
+        payload_len: usize,
+        rcv_window: usize,
+        iss: u32,
+    ) -> SegmentResult {
+        let mut result = SegmentResult::default();
+        let seq = hdr.get_seq_num();
+        let ack = hdr.get_ack_num();
+        let has_ack = hdr.has_flags(tcp_flags::ACK);
+
+        match self.state {
+            TcpState::Closed => {
+                result.reply_reset = !hdr.has_flags(tcp_flags::RST);
+                return result;
+            }
+            TcpState::Listen => {
+                if hdr.has_flags(tcp_flags::RST) {
+                    return result;
+                }
+                if has_ack || !hdr.has_flags(tcp_flags::SYN) {
+                    result.reply_reset = true;
+                    return result;
+                }
+                self.remote_addr = src_addr;
+                self.remote_port = hdr.get_src_port();
+                self.rcv_nxt = seq.wrapping_add(1);
+                self.snd_wnd = hdr.get_window();
+                self.init_send_sequence(iss);
+                self.state = TcpState::SynReceived;
+                return result;
+            }
+            TcpState::SynSent => {
+                let ack_ok = has_ack && seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt);
+                if has_ack && !ack_ok {
+                    result.reply_reset = !hdr.has_flags(tcp_flags::RST);
+                    return result;
+                }
+                if hdr.has_flags(tcp_flags::RST) {
+                    if ack_ok {
+                        self.state = TcpState::Closed;
+                        result.closed = Some(Err(ErrorCode::FAIL));
+                    }
+                    return result;
+                }
+                if !hdr.has_flags(tcp_flags::SYN) {
+                    return result;
+                }
+                self.rcv_nxt = seq.wrapping_add(1);
+                self.snd_wnd = hdr.get_window();
+                self.ack_pending = true;
+                if ack_ok {
+                    self.snd_una = ack;
+                    self.retransmissions = 0;
+                    result.acked = true;
+                    self.rtt_seq = None;
+                    self.state = TcpState::Established;
+                    result.connected = true;
+                } else {
+                    // Simultaneous open: resend our SYN together with an ACK.
+                    self.snd_nxt = self.iss;
+                    self.state = TcpState::SynReceived;
+                }
+                return result;
+            }
+            _ => {}
+        }
+
+        // Only accept segments that start exactly at the next expected
+        // sequence number. Anything else is answered with a duplicate ACK.
+        if seq != self.rcv_nxt {
+            if !hdr.has_flags(tcp_flags::RST) {
+                self.ack_pending = true;
+            }
+            return result;
+        }
+
+        if hdr.has_flags(tcp_flags::RST) {
+            self.state = TcpState::Closed;
+            self.tx_len = 0;
+            result.closed = Some(Err(ErrorCode::FAIL));
+            return result;
+        }
+
+        if hdr.has_flags(tcp_flags::SYN) {
+            // A SYN in a synchronized state is an error (RFC 5961 suggests a
+            // challenge ACK; we keep the simpler behaviour of RFC 793).
+            let _ = self.abort();
+            result.reply_reset = true;
+            result.closed = Some(Err(ErrorCode::FAIL));
+            return result;
+        }
+
+        if !has_ack {
+            return result;
+        }
+
+        if self.state == TcpState::SynReceived {
+            if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+                self.state = TcpState::Established;
+                result.connected = true;
+            } else {
+                result.reply_reset = true;
+                return result;
+            }
+        }
+
+        // Process the acknowledgment.
+        if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+            self.snd_una = ack;
+            self.retransmissions = 0;
+            result.acked = true;
+            if self.rtt_seq.is_some_and(|rtt_seq| seq_le(rtt_seq, ack)) {
+                self.rtt_seq = None;
+            }
+            let tx_end = self.tx_start.wrapping_add(self.tx_len);
+            if self.tx_len > 0 && seq_le(tx_end, ack) {
+                self.tx_start = tx_end;
+                self.tx_len = 0;
+                result.send_done = true;
+            }
+        } else if seq_lt(self.snd_nxt, ack) {
+            // Acknowledges something not yet sent
+            self.ack_pending = true;
+            return result;
+        }
+        self.snd_wnd = hdr.get_window();
+
+        let fin_acked = self.fin_queued && self.snd_una == self.snd_nxt && !self.wants_fin();
+        match self.state {
+            TcpState::FinWait1 if fin_acked => self.state = TcpState::FinWait2,
+            TcpState::Closing if fin_acked => {
+                self.state = TcpState::TimeWait;
+            }
+            TcpState::LastAck if fin_acked => {
+                self.state = TcpState::Closed;
+                result.closed = Some(Ok(()));
+                return result;
+            }
+            _ => {}
+        }
+
+        // Process the payload.
+        match self.state {
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 => {
+                let accepted = core::cmp::min(payload_len, rcv_window);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(accepted as u32);
+                result.accepted = accepted;
+                if payload_len > 0 {
+                    self.ack_pending = true;
+                }
+                if accepted < payload_len {
+                    // The FIN (if any) is not in sequence
+                    return result;
+                }
+            }
+            _ => {}
+        }
+
+        // Process the FIN.
+        if hdr.has_flags(tcp_flags::FIN) {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.ack_pending = true;
+            match self.state {
+                TcpState::Established => {
+                    self.state = TcpState::CloseWait;
+                    result.peer_closed = true;
+                }
+                TcpState::FinWait1 => {
+                    self.state = if fin_acked {
+                        TcpState::TimeWait
+                    } else {
+                        TcpState::Closing
+                    };
+                    result.peer_closed = true;
+                }
+                TcpState::FinWait2 => {
+                    self.state = TcpState::TimeWait;
+                    result.peer_closed = true;
+                }
+                _ => {}
+            }
+        }
+
+        result
+    }
+
+    // Whether a queued FIN has not been sent yet.
+    fn wants_fin(&self) -> bool {
+        self.fin_queued
+            && matches!(
+                self.next_segment_info(u16::MAX as usize),
+                Some((flags, _, _)) if flags & tcp_flags::FIN != 0
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL_PORT: u16 = 4000;
+    const REMOTE_PORT: u16 = 80;
+    const REMOTE_ADDR: IPAddr = IPAddr([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+    const LOCAL_ISS: u32 = 1000;
+    const REMOTE_ISS: u32 = 5000;
+    const MSS: usize = 100;
+    const WINDOW: usize = 512;
+
+    fn segment(seq: u32, ack: Option<u32>, flags: u8) -> TCPHeader {
+        let mut hdr = TCPHeader::new();
+        hdr.set_src_port(REMOTE_PORT);
+        hdr.set_dst_port(LOCAL_PORT);
+        hdr.set_seq_num(seq);
+        let mut flags = flags;
+        if let Some(ack) = ack {
+            hdr.set_ack_num(ack);
+            flags |= tcp_flags::ACK;
+        }
+        hdr.set_flags(flags);
+        hdr.set_window(WINDOW as u16);
+        hdr
+    }
+
+    fn receive(conn: &mut TcpConnection, hdr: TCPHeader, payload_len: usize) -> SegmentResult {
+        conn.receive(REMOTE_ADDR, &hdr, payload_len, WINDOW, LOCAL_ISS)
+    }
+
+    fn next(conn: &mut TcpConnection) -> OutgoingSegment {
+        conn.next_segment(MSS, WINDOW)
+            .expect("connection should send a segment")
+    }
+
+    /// Returns a connection established by an active open.
+    fn established() -> TcpConnection {
+        let mut conn = TcpConnection::default();
+        conn.connect(LOCAL_PORT, REMOTE_ADDR, REMOTE_PORT, LOCAL_ISS)
+            .unwrap();
+        let syn = next(&mut conn);
+        assert_eq!(syn.header.get_flags(), tcp_flags::SYN);
+        assert_eq!(syn.header.get_seq_num(), LOCAL_ISS);
+        assert_eq!(conn.get_state(), TcpState::SynSent);
+
+        let result = receive(
+            &mut conn,
+            segment(REMOTE_ISS, Some(LOCAL_ISS + 1), tcp_flags::SYN),
+            0,
+        );
+        assert!(result.connected);
+        assert_eq!(conn.get_state(), TcpState::Established);
+
+        let ack = next(&mut conn);
+        assert_eq!(ack.header.get_flags(), tcp_flags::ACK);
+        assert_eq!(ack.header.get_seq_num(), LOCAL_ISS + 1);
+        assert_eq!(ack.header.get_ack_num(), REMOTE_ISS + 1);
+        assert!(!conn.wants_to_send());
+        conn
+    }
+
+    #[test]
+    fn active_open() {
+        let conn = established();
+        assert_eq!(conn.retransmit_timeout_ms(), None);
+        assert!(conn.matches(REMOTE_ADDR, REMOTE_PORT, LOCAL_PORT));
+        assert!(!conn.matches(REMOTE_ADDR, REMOTE_PORT + 1, LOCAL_PORT));
+    }
+
+    #[test]
+    fn passive_open() {
+        let mut conn = TcpConnection::default();
+        conn.listen(LOCAL_PORT).unwrap();
+        assert!(conn.matches(REMOTE_ADDR, REMOTE_PORT, LOCAL_PORT));
+
+        let result = receive(&mut conn, segment(REMOTE_ISS, None, tcp_flags::SYN), 0);
+        assert!(!result.connected);
+        assert_eq!(conn.get_state(), TcpState::SynReceived);
+        assert_eq!(conn.get_remote_addr(), REMOTE_ADDR);
+        assert_eq!(conn.get_remote_port(), REMOTE_PORT);
+
+        let syn_ack = next(&mut conn);
+        assert_eq!(syn_ack.header.get_flags(), tcp_flags::SYN | tcp_flags::ACK);
+        assert_eq!(syn_ack.header.get_seq_num(), LOCAL_ISS);
+        assert_eq!(syn_ack.header.get_ack_num(), REMOTE_ISS + 1);
+
+        let result = receive(
+            &mut conn,
+            segment(REMOTE_ISS + 1, Some(LOCAL_ISS + 1), 0),
+            0,
+        );
+        assert!(result.connected);
+        assert_eq!(conn.get_state(), TcpState::Established);
+    }
+
+    #[test]
+    fn listen_rejects_ack() {
+        let mut conn = TcpConnection::default();
+        conn.listen(LOCAL_PORT).unwrap();
+        let result = receive(&mut conn, segment(REMOTE_ISS, Some(1), 0), 0);
+        assert!(result.reply_reset);
+        assert_eq!(conn.get_state(), TcpState::Listen);
+    }
+
+    #[test]
+    fn send_is_split_and_acknowledged() {
+        let mut conn = established();
+        conn.send(250).unwrap();
+        assert_eq!(conn.send(1), Err(ErrorCode::BUSY));
+
+        let first = next(&mut conn);
+        assert_eq!((first.data_offset, first.data_len), (0, 100));
+        assert_eq!(first.header.get_flags(), tcp_flags::ACK);
+        let second = next(&mut conn);
+        assert_eq!((second.data_offset, second.data_len), (100, 100));
+        let third = next(&mut conn);
+        assert_eq!((third.data_offset, third.data_len), (200, 50));
+        assert_eq!(third.header.get_flags(), tcp_flags::ACK | tcp_flags::PSH);
+        assert!(conn.next_segment(MSS, WINDOW).is_none());
+        assert!(conn.retransmit_timeout_ms().is_some());
+
+        // A partial acknowledgment does not complete the send.
+        let result = receive(
+            &mut conn,
+            segment(REMOTE_ISS + 1, Some(LOCAL_ISS + 101), 0),
+            0,
+        );
+        assert!(result.acked);
+        assert!(!result.send_done);
+        assert!(conn.is_sending());
+
+        let result = receive(
+            &mut conn,
+            segment(REMOTE_ISS + 1, Some(LOCAL_ISS + 251), 0),
+            0,
+        );
+        assert!(result.send_done);
+        assert!(!conn.is_sending());
+        assert_eq!(conn.retransmit_timeout_ms(), None);
+    }
+
+    #[test]
+    fn receive_in_order_data() {
+        let mut conn = established();
+        let result = receive(
+            &mut conn,
+            segment(REMOTE_ISS + 1, Some(LOCAL_ISS + 1), 0),
+            40,
+        );
+        assert_eq!(result.accepted, 40);
+        let ack = next(&mut conn);
+        assert_eq!(ack.header.get_ack_num(), REMOTE_ISS + 41);
+
+        // An out of order segment is dropped and answered with a duplicate
+        // acknowledgment.
+        let result = receive(
+            &mut conn,
+            segment(REMOTE_ISS + 81, Some(LOCAL_ISS + 1), 0),
+            40,
+        );
+        assert_eq!(result.accepted, 0);
+        let ack = next(&mut conn);
+        assert_eq!(ack.header.get_ack_num(), REMOTE_ISS + 41);
+    }
+
+    #[test]
+    fn receive_is_limited_by_window() {
+        let mut conn = established();
+        let hdr = segment(REMOTE_ISS + 1, Some(LOCAL_ISS + 1), tcp_flags::FIN);
+        let result = conn.receive(REMOTE_ADDR, &hdr, 40, 10, LOCAL_ISS);
+        assert_eq!(result.accepted, 10);
+        // The FIN follows data that was not accepted, so it is ignored.
+        assert!(!result.peer_closed);
+        assert_eq!(conn.get_state(), TcpState::Established);
+    }
+
+    #[test]
+    fn active_close() {
+        let mut conn = established();
+        assert_eq!(conn.close(), Ok(false));
+        assert_eq!(conn.get_state(), TcpState::FinWait1);
+        let fin = next(&mut conn);
+        assert_eq!(fin.header.get_flags(), tcp_flags::FIN | tcp_flags::ACK);
+
+        receive(
+            &mut conn,
+            segment(REMOTE_ISS + 1, Some(LOCAL_ISS + 2), 0),
+            0,
+        );
+        assert_eq!(conn.get_state(), TcpState::FinWait2);
+
+        let result = receive(
+            &mut conn,
+            segment(REMOTE_ISS + 1, Some(LOCAL_ISS + 2), tcp_flags::FIN),
+            0,
+        );
+        assert!(result.peer_closed);
+        assert!(conn.is_time_wait());
+        let ack = next(&mut conn);
+        assert_eq!(ack.header.get_ack_num(), REMOTE_ISS + 2);
+
+        assert_eq!(conn.timer_expired(), Some(Ok(())));
+        assert_eq!(conn.get_state(), TcpState::Closed);
+    }
+
+    #[test]
+    fn simultaneous_close() {
+        let mut conn = established();
+        conn.close().unwrap();
+        next(&mut conn);
+        let result = receive(
+            &mut conn,
+            segment(REMOTE_ISS + 1, Some(LOCAL_ISS + 1), tcp_flags::FIN),
+            0,
+        );
+        assert!(result.peer_closed);
+        assert_eq!(conn.get_state(), TcpState::Closing);
+
+        receive(
+            &mut conn,
+            segment(REMOTE_ISS + 2, Some(LOCAL_ISS + 2), 0),
+            0,
+        );
+        assert!(conn.is_time_wait());
+    }
+
+    #[test]
+    fn passive_close() {
+        let mut conn = established();
+        let result = receive(
+            &mut conn,
+            segment(REMOTE_ISS + 1, Some(LOCAL_ISS + 1), tcp_flags::FIN),
+            0,
+        );
+        assert!(result.peer_closed);
+        assert_eq!(conn.get_state(), TcpState::CloseWait);
+        next(&mut conn);
+
+        // Data can still be sent after the peer closed its side.
+        conn.send(10).unwrap();
+        assert_eq!(conn.close(), Ok(false));
+        assert_eq!(conn.get_state(), TcpState::LastAck);
+        let data = next(&mut conn);
+        assert_eq!(data.data_len, 10);
+        let fin = next(&mut conn);
+        assert_eq!(fin.header.get_flags(), tcp_flags::FIN | tcp_flags::ACK);
+        assert_eq!(fin.header.get_seq_num(), LOCAL_ISS + 11);
+
+        let result = receive(
+            &mut conn,
+            segment(REMOTE_ISS + 2, Some(LOCAL_ISS + 12), 0),
+            0,
+        );
+        assert!(result.send_done);
+        assert_eq!(result.closed, Some(Ok(())));
+        assert_eq!(conn.get_state(), TcpState::Closed);
+    }
+
+    #[test]
+    fn reset_closes_connection() {
+        let mut conn = established();
+        let result = receive(&mut conn, segment(REMOTE_ISS + 1, None, tcp_flags::RST), 0);
+        assert_eq!(result.closed, Some(Err(ErrorCode::FAIL)));
+        assert_eq!(conn.get_state(), TcpState::Closed);
+    }
+
+    #[test]
+    fn reset_out_of_window_is_ignored() {
+        let mut conn = established();
+        let result = receive(
+            &mut conn,
+            segment(REMOTE_ISS + 100, None, tcp_flags::RST),
+            0,
+        );
+        assert_eq!(result.closed, None);
+        assert_eq!(conn.get_state(), TcpState::Established);
+    }
+
+    #[test]
+    fn reset_in_syn_sent() {
+        let mut conn = TcpConnection::default();
+        conn.connect(LOCAL_PORT, REMOTE_ADDR, REMOTE_PORT, LOCAL_ISS)
+            .unwrap();
+        next(&mut conn);
+        let result = receive(
+            &mut conn,
+            segment(0, Some(LOCAL_ISS + 1), tcp_flags::RST),
+            0,
+        );
+        assert_eq!(result.closed, Some(Err(ErrorCode::FAIL)));
+        assert_eq!(conn.get_state(), TcpState::Closed);
+    }
+
+    #[test]
+    fn abort_sends_reset() {
+        let mut conn = established();
+        let rst = conn.abort().expect("peer should be reset");
+        assert!(rst.has_flags(tcp_flags::RST));
+        assert_eq!(rst.get_seq_num(), LOCAL_ISS + 1);
+        assert_eq!(conn.get_state(), TcpState::Closed);
+    }
+
+    #[test]
+    fn reset_for_unmatched_segment() {
+        let rst = reset_for(&segment(REMOTE_ISS, None, tcp_flags::SYN), 0);
+        assert_eq!(rst.get_flags(), tcp_flags::RST | tcp_flags::ACK);
+        assert_eq!(rst.get_ack_num(), REMOTE_ISS + 1);
+        assert_eq!(rst.get_src_port(), LOCAL_PORT);
+        assert_eq!(rst.get_dst_port(), REMOTE_PORT);
+
+        let rst = reset_for(&segment(REMOTE_ISS, Some(77), 0), 10);
+        assert_eq!(rst.get_flags(), tcp_flags::RST);
+        assert_eq!(rst.get_seq_num(), 77);
+    }
+
+    #[test]
+    fn retransmit_after_timeout() {
+        let mut conn = established();
+        conn.send(50).unwrap();
+        let first = next(&mut conn);
+        assert!(first.timed);
+        assert_eq!(conn.retransmit_timeout_ms(), Some(INITIAL_RTO_MS));
+
+        assert_eq!(conn.timer_expired(), None);
+        assert_eq!(conn.retransmit_timeout_ms(), None);
+        let again = next(&mut conn);
+        assert_eq!(again.header.get_seq_num(), LOCAL_ISS + 1);
+        assert_eq!(again.data_len, 50);
+        // Retransmitted segments are not timed, and the timeout backs off.
+        assert!(!again.timed);
+        assert_eq!(conn.retransmit_timeout_ms(), Some(2 * INITIAL_RTO_MS));
+    }
+
+    #[test]
+    fn retransmit_syn() {
+        let mut conn = TcpConnection::default();
+        conn.connect(LOCAL_PORT, REMOTE_ADDR, REMOTE_PORT, LOCAL_ISS)
+            .unwrap();
+        next(&mut conn);
+        assert_eq!(conn.timer_expired(), None);
+        let syn = next(&mut conn);
+        assert_eq!(syn.header.get_flags(), tcp_flags::SYN);
+        assert_eq!(syn.header.get_seq_num(), LOCAL_ISS);
+    }
+
+    #[test]
+    fn too_many_retransmissions_abort() {
+        let mut conn = established();
+        conn.send(50).unwrap();
+        for _ in 0..MAX_RETRANSMISSIONS {
+            next(&mut conn);
+            assert_eq!(conn.timer_expired(), None);
+        }
+        next(&mut conn);
+        assert_eq!(conn.timer_expired(), Some(Err(ErrorCode::NOACK)));
+        assert_eq!(conn.get_state(), TcpState::Closed);
+        assert!(!conn.is_sending());
+    }
+
+    #[test]
+    fn rtt_sample_updates_timeout() {
+        let mut conn = established();
+        conn.send(10).unwrap();
+        next(&mut conn);
+        assert!(conn.completes_rtt_sample(LOCAL_ISS + 11));
+        assert!(!conn.completes_rtt_sample(LOCAL_ISS + 5));
+        conn.rtt_sample(100);
+        // srtt + 4 * rttvar = 100 + 4 * 50
+        receive(
+            &mut conn,
+            segment(REMOTE_ISS + 1, Some(LOCAL_ISS + 11), 0),
+            0,
+        );
+        assert_eq!(conn.rto_ms, 300);
+
+        conn.rtt_sample(10);
+        assert_eq!(
+            conn.rto_ms,
+            MIN_RTO_MS.max(conn.srtt_ms + 4 * conn.rttvar_ms)
+        );
+    }
+
+    #[test]
+    fn zero_window_probe() {
+        let mut conn = TcpConnection::default();
+        conn.connect(LOCAL_PORT, REMOTE_ADDR, REMOTE_PORT, LOCAL_ISS)
+            .unwrap();
+        next(&mut conn);
+        let mut syn_ack = segment(REMOTE_ISS, Some(LOCAL_ISS + 1), tcp_flags::SYN);
+        syn_ack.set_window(0);
+        receive(&mut conn, syn_ack, 0);
+        next(&mut conn);
+
+        conn.send(20).unwrap();
+        let probe = next(&mut conn);
+        assert_eq!(probe.data_len, 1);
+        assert!(conn.next_segment(MSS, WINDOW).is_none());
+    }
+
+    #[test]
+    fn sequence_numbers_wrap() {
+        assert!(seq_lt(u32::MAX, 0));
+        assert!(seq_lt(u32::MAX - 10, 5));
+        assert!(!seq_lt(5, u32::MAX - 10));
+        assert!(seq_le(7, 7));
+    }
+}
@@ -0,0 +1,90 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Table of the local TCP ports bound by processes.
+//!
+//! As for UDP (see `udp_port_table.rs`), a fixed size table tracks which
+//! ports are bound, so that checking whether a port is free does not require
+//! walking the grants of all processes. Each entry records the port and the
+//! process whose connection uses it.
+//!
+//! Connections live in process grants and can end at any time (the peer
+//! resets it, a timer expires, the process exits), so entries are not
+//! removed when a connection closes. Instead, the owner of an entry is asked
+//! whether it still uses the port when the entry is looked up, and stale
+//! entries are reused by later bindings.
+
+use core::cell::Cell;
+
+use kernel::{ErrorCode, ProcessId};
+
+/// Number of ports that can be bound at the same time. As every process
+/// holds at most one connection, this is the number of processes that can
+/// use TCP at the same time.
+pub const MAX_NUM_BOUND_PORTS: usize = 8;
+
+/// A port bound by the connection of a process.
+#[derive(Copy, Clone)]
+pub struct TcpPortBinding {
+    port: u16,
+    owner: ProcessId,
+}
+
+pub struct TcpPortTable {
+    bindings: &'static [Cell<Option<TcpPortBinding>>],
+}
+
+impl TcpPortTable {
+    pub fn new(bindings: &'static [Cell<Option<TcpPortBinding>>]) -> TcpPortTable {
+        TcpPortTable { bindings }
+    }
+
+    /// Returns true if `port` is bound by a process that still uses it.
+    /// `in_use` reports whether a process still uses a port, and is only
+    /// called for the owner of a matching entry.
+    pub fn is_bound(&self, port: u16, in_use: &dyn Fn(ProcessId, u16) -> bool) -> bool {
+        self.bindings.iter().any(|entry| {
+            entry
+                .get()
+                .is_some_and(|binding| binding.port == port && in_use(binding.owner, port))
+        })
+    }
+
+    /// Binds `port` to `owner`, which must not hold a connection. This
+    /// replaces any earlier binding of `owner`.
+    ///
+    /// Returns `BUSY` if the port is bound by another process and `NOMEM` if
+    /// the table is full.
+    pub fn bind(
+        &self,
+        port: u16,
+        owner: ProcessId,
+        in_use: &dyn Fn(ProcessId, u16) -> bool,
+    ) -> Result<(), ErrorCode> {
+        if self.is_bound(port, in_use) {
+            return Err(ErrorCode::BUSY);
+        }
+        self.unbind(owner);
+        let free = self
+            .bindings
+            .iter()
+            .find(|entry| {
+                entry
+                    .get()
+                    .is_none_or(|binding| !in_use(binding.owner, binding.port))
+            })
+            .ok_or(ErrorCode::NOMEM)?;
+        free.set(Some(TcpPortBinding { port, owner }));
+        Ok(())
+    }
+
+    /// Removes the binding of `owner`, if any.
+    pub fn unbind(&self, owner: ProcessId) {
+        for entry in self.bindings.iter() {
+            if entry.get().is_some_and(|binding| binding.owner == owner) {
+                entry.set(None);
+            }
+        }
+    }
+}
@@ -5,19 +5,33 @@
 //! Structs and methods associated with the Thread networking layer.
 //!
 //! This represents a first attempt in Tock to support Thread
-//! networking. The current implementation successfully joins a Tock
-//! device as a child node to a Thread parent (tested using
-//! OpenThread). This Thread capsule is a client to the UDP Mux.  The
-//! associated ThreadNetwork struct must be created in the
-//! `thread_network.rs` component.
+//! networking. The current implementation joins a Tock device as a child
+//! node to a Thread parent (tested using OpenThread) and keeps it attached.
+//! This Thread capsule is a client to the UDP Mux. The associated
+//! ThreadNetwork struct must be created in the `thread_network.rs` component.
 //!
-//! The Userland interface is incredibly simple at this juncture. An application
-//! can begin the Thread child/parent joining by issuing a syscall command
-//! with the MLE/MAC key as an argument. Only one userspace application can use/join
-//! the Thread network. Once a userspace application has joined the Thread network,
-//! the Thread network is considered locked. After the Thread network
-//! is "locked", other userspace applications attempting to join the network
-//! will return a failure. This is temporary and will eventually be replaced.
+//! The Userland interface is simple at this juncture. An application joins
+//! the Thread network by issuing a syscall command with the MLE/MAC key as an
+//! argument. The first application to join starts attaching to a parent.
+//! Applications that join later with the same key share the network, while
+//! ones with a different key fail with `BUSY`. The device leaves the network
+//! once the last application has left it.
+//!
+//! Attaching follows Thread spec v1.3.0 section 4.5.1: Parent Requests are
+//! sent twice soliciting only routers and then four times soliciting routers
+//! and REEDs, after which the device waits and starts over. Once attached,
+//! the child sends Child Update Requests to its parent before the child
+//! timeout expires. If the parent does not answer several requests in a row,
+//! or rejects the update, the parent is considered lost and the device
+//! attaches again. Applications are notified of changes of the role, parent
+//! and link quality.
+//!
+//! MLE messages carry the key sequence of the keys securing them in the key
+//! source of their auxiliary security header. As keys are derived in
+//! userland, applications are asked for the keys of a newer key sequence
+//! when a message uses one, and the device switches to these keys once a
+//! message secured with them is authenticated. Messages secured with the
+//! previous keys are still accepted.
 
 // ------------------------------------------------------------------------------
 // Current Limitations
@@ -25,9 +39,8 @@
 // (1) A majority of the TLV fields used in the parent request/child id request
 //     are hardcoded. Future implementations need to provide options for specifying
 //     varied security policies.
-// (2) Current implementation joins the Thread network sucessfully and consistently
-//     but does not send update/heart beat messages to the parent prior to the child
-//     timing out.
+// (2) The first Parent Response received is used, rather than selecting the
+//     parent with the best link quality.
 // (3) Currently no support for sending UDP messages across Thread interface. The
 //     current interface is unusable for sending data. It can only be used to
 //     join a network.
@@ -39,13 +52,15 @@ use crate::net::network_capabilities::NetworkCapability;
 
 use crate::net::ieee802154;
 use crate::net::thread::thread_utils::generate_src_ipv6;
-use crate::net::thread::thread_utils::ThreadState;
 use crate::net::thread::thread_utils::MULTICAST_IPV6;
 use crate::net::thread::thread_utils::THREAD_PORT_NUMBER;
 use crate::net::thread::thread_utils::{
-    encode_cryp_data, form_child_id_req, form_parent_req, mac_from_ipv6, MleCommand, NetworkKey,
-    AUTH_DATA_LEN, AUX_SEC_HEADER_LENGTH, IPV6_LEN, SECURITY_SUITE_LEN,
+    encode_cryp_data, find_leader_data, find_tlv, form_child_id_req, form_child_update_req,
+    form_parent_req, key_id, key_index, key_sequence, link_quality, mac_from_ipv6, LeaderData,
+    MleCommand, NetworkKey, ThreadRole, ThreadState, AUTH_DATA_LEN, AUX_SEC_HEADER_LENGTH,
+    CHILD_TIMEOUT_S, INVALID_RLOC16, IPV6_LEN, SECURITY_SUITE_LEN,
 };
+use crate::net::thread::tlv::{MulticastResponder, Tlv, TlvType};
 use crate::net::udp::udp_port_table::UdpPortManager;
 use crate::net::udp::udp_recv::UDPRecvClient;
 use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
@@ -55,19 +70,37 @@ use core::cell::Cell;
 
 use kernel::capabilities::UdpDriverCapability;
 use kernel::errorcode::into_statuscode;
-use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
 use kernel::hil::symmetric_encryption::CCMClient;
 use kernel::hil::symmetric_encryption::AES128CCM;
-use kernel::hil::time;
+use kernel::hil::time::{self, ConvertTicks};
 use kernel::processbuffer::ReadableProcessBuffer;
 use kernel::syscall::{CommandReturn, SyscallDriver};
-use kernel::utilities::cells::MapCell;
+use kernel::utilities::cells::{MapCell, OptionalCell};
 use kernel::utilities::leasable_buffer::SubSliceMut;
 use kernel::{ErrorCode, ProcessId};
 
 const SECURITY_SUITE_ENCRYP: u8 = 0;
 pub const DRIVER_NUM: usize = driver::NUM::Thread as usize;
 
+/// Number of Parent Requests sent before waiting for `ATTACH_BACKOFF_MS`.
+const PARENT_REQUEST_ATTEMPTS: u8 = 6;
+/// Number of Parent Requests that only solicit responses from routers.
+const ROUTER_ONLY_ATTEMPTS: u8 = 2;
+/// Time to wait for a Parent Response when only routers respond.
+const PARENT_RESPONSE_TIMEOUT_ROUTERS_MS: u32 = 750;
+/// Time to wait for a Parent Response when routers and REEDs respond.
+const PARENT_RESPONSE_TIMEOUT_MS: u32 = 1250;
+const CHILD_ID_RESPONSE_TIMEOUT_MS: u32 = 1250;
+/// Time to wait before starting over after all Parent Requests failed.
+const ATTACH_BACKOFF_MS: u32 = 20_000;
+/// Interval between Child Update Requests while the parent answers them.
+const CHILD_UPDATE_INTERVAL_MS: u32 = CHILD_TIMEOUT_S * 1000 / 2;
+const CHILD_UPDATE_RESPONSE_TIMEOUT_MS: u32 = 1000;
+/// Number of unanswered Child Update Requests after which the parent is
+/// considered lost.
+const CHILD_UPDATE_ATTEMPTS: u8 = 3;
+
 /// Ids for read-only allow buffers
 mod ro_allow {
     pub const WRITE: usize = 0;
@@ -87,10 +120,19 @@ mod ro_allow {
 /// IDs for subscribed upcalls.
 mod upcall {
     pub const JOINCOMPLETE: usize = 0;
+    pub const STATUS: usize = 1;
+    pub const KEY_SEQUENCE: usize = 2;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 3;
 }
 
 #[derive(Default)]
-pub struct App {}
+pub struct App {
+    /// Whether the app has joined the Thread network.
+    joined: bool,
+    /// Whether the app waits for the device to attach.
+    join_pending: bool,
+}
 
 #[allow(dead_code)]
 pub struct ThreadNetworkDriver<'a, A: time::Alarm<'a>> {
@@ -104,7 +146,12 @@ pub struct ThreadNetworkDriver<'a, A: time::Alarm<'a>> {
     alarm: &'a A,
 
     /// Grant of apps that use this thread driver.
-    apps: Grant<App, UpcallCount<1>, AllowRoCount<{ ro_allow::COUNT }>, AllowRwCount<0>>,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<0>,
+    >,
 
     /// mac address of device
     src_mac_addr: [u8; 8],
@@ -122,7 +169,25 @@ pub struct ThreadNetworkDriver<'a, A: time::Alarm<'a>> {
     recv_buffer: MapCell<SubSliceMut<'static, u8>>,
 
     /// state machine for the Thread device
-    state: MapCell<ThreadState>,
+    state: Cell<ThreadState>,
+
+    /// Number of Parent Requests sent in the current attach attempt
+    attach_attempt: Cell<u8>,
+
+    /// Number of Child Update Requests the parent has not answered
+    update_attempts: Cell<u8>,
+
+    /// RLOC16 assigned to the device by its parent
+    rloc16: Cell<u16>,
+
+    /// RLOC16 of the parent
+    parent_rloc16: Cell<u16>,
+
+    /// Link quality to the parent
+    link_quality: Cell<u8>,
+
+    /// Leader data last received from the parent
+    leader_data: OptionalCell<LeaderData>,
 
     /// UDP driver capability
     driver_send_cap: &'static dyn UdpDriverCapability,
@@ -136,23 +201,38 @@ pub struct ThreadNetworkDriver<'a, A: time::Alarm<'a>> {
     /// Stored Thread network containing mac/MLE key
     networkkey: MapCell<NetworkKey>,
 
+    /// Key sequence of `networkkey`
+    key_sequence: Cell<u32>,
+
+    /// Key sequence and keys used before `networkkey`
+    prev_networkkey: MapCell<(u32, NetworkKey)>,
+
+    /// Key sequence and keys provided by userland that are used once a
+    /// message secured with them is received
+    next_networkkey: MapCell<(u32, NetworkKey)>,
+
+    /// Key sequence userland was last asked to provide keys for
+    key_request: OptionalCell<u32>,
+
     /// Length of the message passed to the crypto engine
     crypto_sizelock: MapCell<usize>,
+
+    /// Whether the crypto engine secures a message to send, rather than
+    /// checking a received one
+    encrypting: Cell<bool>,
 }
 
-// Note: For now, we initialize the Thread state as empty.
-// We replace the Thread state when the first userspace
-// application calls the Thread capsule to initiate a Thread network.
-// This serves to "lock" the Thread capsule to only one application.
-// For now, Tock only supports one application using the Thread network.
-// After the network is "locked" to one application, other userspace
-// applications requesting to join a Thread network will fail.
 impl<'a, A: time::Alarm<'a>> ThreadNetworkDriver<'a, A> {
     pub fn new(
         sender: &'a dyn UDPSender<'a>,
         aes_crypto: &'a dyn AES128CCM<'a>,
         alarm: &'a A,
-        grant: Grant<App, UpcallCount<1>, AllowRoCount<{ ro_allow::COUNT }>, AllowRwCount<0>>,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<0>,
+        >,
         src_mac_addr: [u8; 8],
         max_tx_pyld_len: usize,
         port_table: &'static UdpPortManager,
@@ -171,12 +251,23 @@ impl<'a, A: time::Alarm<'a>> ThreadNetworkDriver<'a, A> {
             port_table,
             send_buffer: MapCell::new(send_buffer),
             recv_buffer: MapCell::new(recv_buffer),
-            state: MapCell::empty(),
+            state: Cell::new(ThreadState::Disabled),
+            attach_attempt: Cell::new(0),
+            update_attempts: Cell::new(0),
+            rloc16: Cell::new(INVALID_RLOC16),
+            parent_rloc16: Cell::new(INVALID_RLOC16),
+            link_quality: Cell::new(0),
+            leader_data: OptionalCell::empty(),
             driver_send_cap,
             net_cap,
             frame_count: Cell::new(5),
             networkkey: MapCell::empty(),
+            key_sequence: Cell::new(0),
+            prev_networkkey: MapCell::empty(),
+            next_networkkey: MapCell::empty(),
+            key_request: OptionalCell::empty(),
             crypto_sizelock: MapCell::empty(),
+            encrypting: Cell::new(false),
         }
     }
 
@@ -185,55 +276,186 @@ impl<'a, A: time::Alarm<'a>> ThreadNetworkDriver<'a, A> {
         self.networkkey.replace(NetworkKey { mle_key, mac_key });
     }
 
-    fn send_parent_req(&self) {
-        // UNCOMMENT TO DEBUG THREAD //
-        // kernel::debug!("[Thread] Sending parent request...");
+    /// Returns the keys for `key_sequence`, if they are known.
+    fn key_for_sequence(&self, key_sequence: u32) -> Option<NetworkKey> {
+        if key_sequence == self.key_sequence.get() {
+            return self.networkkey.get();
+        }
+        [self.prev_networkkey.get(), self.next_networkkey.get()]
+            .into_iter()
+            .flatten()
+            .find(|(sequence, _)| *sequence == key_sequence)
+            .map(|(_, key)| key)
+    }
 
-        // Panicking on unwrap indicates the state was taken without replacement
-        // (unreachable with proper state machine implementation)
-        let curr_state = self.state.take().unwrap();
-
-        match curr_state {
-            ThreadState::Detached => {
-                // A parent request can only begin from a detached state. We utilize
-                // helper functions to form the request and send the parent request
-                // to the multicast IP/Mac Address
-                self.state.replace(ThreadState::SendParentReq);
-                let parent_req_mle = form_parent_req();
-                let src_ipv6 = generate_src_ipv6(&self.src_mac_addr);
-                self.thread_mle_send(&parent_req_mle, MULTICAST_IPV6, src_ipv6)
-                    .err()
-                    .map(|code| {
-                        // Thread send failed sending parent req so we terminate and return
-                        // to a detached state.
-                        self.state.replace(ThreadState::Detached);
-
-                        // UNCOMMENT TO DEBUG THREAD //
-                        // kernel::debug!(
-                        //     "[Thread] Failed sending MLE parent request - crypto operation error."
-                        // );
-                        self.terminate_child_join(Err(code));
-                    });
+    /// Switches to the keys userland provided for `key_sequence`.
+    fn switch_key(&self, key_sequence: u32) {
+        if let Some((_, key)) = self
+            .next_networkkey
+            .take()
+            .filter(|(sequence, _)| *sequence == key_sequence)
+        {
+            if let Some(current) = self.networkkey.replace(key) {
+                self.prev_networkkey
+                    .replace((self.key_sequence.get(), current));
             }
-            ThreadState::SEDActive(_, _)
-            | ThreadState::SendUpdate(_, _)
-            | ThreadState::SendUDPMsg => {
-                // These states constitute a device that has previously sucessfully
-                // joined the network. There is no need to issue a new parent request.
-                // Replace state, and terminate.
-                self.state.replace(curr_state);
-                self.terminate_child_join(Err(ErrorCode::ALREADY));
+            self.key_sequence.set(key_sequence);
+            // The MLE frame counter restarts with each key sequence.
+            self.frame_count.set(0);
+            self.key_request.clear();
+        }
+    }
+
+    /// Asks the apps for the keys of `key_sequence`, once per key sequence.
+    fn request_key(&self, key_sequence: u32) {
+        if self.key_request.contains(&key_sequence) {
+            return;
+        }
+        self.key_request.set(key_sequence);
+        self.apps.each(|_, app, kernel_data| {
+            if app.joined {
+                let _ = kernel_data
+                    .schedule_upcall(upcall::KEY_SEQUENCE, (key_sequence as usize, 0, 0));
             }
-            _ => {
-                // All other Thread states indicate that the thread device has already
-                // begun the process of connecting to a parent device. Terminate the parent request.
-                // Replace state, and terminate.
-                self.state.replace(curr_state);
-                self.terminate_child_join(Err(ErrorCode::BUSY));
+        });
+    }
+
+    /// Reads the MLE and MAC keys the app shared in the read-only allow
+    /// buffer.
+    fn read_key(kernel_data: &GrantKernelData) -> Result<NetworkKey, ErrorCode> {
+        kernel_data
+            .get_readonly_processbuffer(ro_allow::WRITE)
+            .and_then(|ro_buf| {
+                ro_buf.enter(|src_key| {
+                    // src key consists of the mle and mac keys; Thread
+                    // hash is performed in userland and 32 byte hash is
+                    // passed to thread capsule and entered as mac/mle key
+                    // (For key generation see Thread spec v1.3.0 7.1.4)
+                    if src_key.len() != 32 {
+                        return Err(ErrorCode::SIZE);
+                    }
+                    let mut mle_key = [0u8; 16];
+                    let mut mac_key = [0u8; 16];
+                    src_key[..16].copy_to_slice(&mut mle_key);
+                    src_key[16..32].copy_to_slice(&mut mac_key);
+                    Ok(NetworkKey { mle_key, mac_key })
+                })
+            })
+            .unwrap_or(Err(ErrorCode::INVAL))
+    }
+
+    fn any_joined(&self) -> bool {
+        let mut joined = false;
+        self.apps.each(|_, app, _| joined |= app.joined);
+        joined
+    }
+
+    /// Returns the role, parent RLOC16 and link quality to the parent.
+    fn status(&self) -> (ThreadRole, u16, u8) {
+        match self.state.get() {
+            ThreadState::Child(_) => (
+                ThreadRole::Child,
+                self.parent_rloc16.get(),
+                self.link_quality.get(),
+            ),
+            state => (state.role(), INVALID_RLOC16, 0),
+        }
+    }
+
+    /// Runs `update` and notifies the apps if it changed the status.
+    fn update_status(&self, update: impl FnOnce()) {
+        let old_status = self.status();
+        update();
+        let (role, parent_rloc16, link_quality) = self.status();
+        if (role, parent_rloc16, link_quality) == old_status {
+            return;
+        }
+        self.apps.each(|_, app, kernel_data| {
+            if app.joined {
+                let _ = kernel_data.schedule_upcall(
+                    upcall::STATUS,
+                    (role as usize, parent_rloc16 as usize, link_quality as usize),
+                );
             }
+        });
+    }
+
+    fn set_state(&self, state: ThreadState) {
+        self.update_status(|| self.state.set(state));
+    }
+
+    fn set_timeout_ms(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Leaves the Thread network and forgets its keys.
+    fn disable(&self) {
+        let _ = self.alarm.disarm();
+        self.networkkey.take();
+        self.prev_networkkey.take();
+        self.next_networkkey.take();
+        self.key_request.clear();
+        self.leader_data.clear();
+        self.rloc16.set(INVALID_RLOC16);
+        self.set_state(ThreadState::Disabled);
+    }
+
+    /// Starts attaching to a parent.
+    fn attach(&self) {
+        self.attach_attempt.set(0);
+        self.send_parent_req();
+    }
+
+    fn send_parent_req(&self) {
+        // UNCOMMENT TO DEBUG THREAD //
+        // kernel::debug!("[Thread] Sending parent request...");
+
+        // The first attempts only solicit routers, the following ones
+        // routers and REEDs (Thread spec v1.3.0 4.5.1)
+        let (scan_mask, timeout) = if self.attach_attempt.get() < ROUTER_ONLY_ATTEMPTS {
+            (
+                MulticastResponder::Router as u8,
+                PARENT_RESPONSE_TIMEOUT_ROUTERS_MS,
+            )
+        } else {
+            (
+                MulticastResponder::Router as u8 | MulticastResponder::EndDevice as u8,
+                PARENT_RESPONSE_TIMEOUT_MS,
+            )
+        };
+
+        self.set_state(ThreadState::WaitingParentRsp);
+        self.set_timeout_ms(timeout);
+
+        // A parent request that fails to send is retried once the alarm
+        // fires, like one that received no response
+        let parent_req_mle = form_parent_req(scan_mask);
+        let src_ipv6 = generate_src_ipv6(&self.src_mac_addr);
+        let _ = self.thread_mle_send(&parent_req_mle, MULTICAST_IPV6, src_ipv6);
+    }
+
+    /// Moves on to the next Parent Request after no parent was attached to.
+    fn attach_failed(&self) {
+        let attempt = self.attach_attempt.get() + 1;
+        if attempt < PARENT_REQUEST_ATTEMPTS {
+            self.attach_attempt.set(attempt);
+            self.send_parent_req();
+        } else {
+            // UNCOMMENT TO DEBUG THREAD //
+            // kernel::debug!("[Thread] Failed to attach, waiting before retrying.");
+            self.attach_attempt.set(0);
+            self.set_state(ThreadState::Detached);
+            self.set_timeout_ms(ATTACH_BACKOFF_MS);
         }
     }
 
+    fn send_child_update_req(&self, parent: IPAddr) {
+        let (output, offset) = form_child_update_req(self.rloc16.get(), self.leader_data.get());
+        let src_ipv6 = generate_src_ipv6(&self.src_mac_addr);
+        let _ = self.thread_mle_send(&output[..offset], parent, src_ipv6);
+    }
+
     fn thread_mle_send(
         &self,
         mle_buf: &[u8],
@@ -247,74 +469,118 @@ impl<'a, A: time::Alarm<'a>> ThreadNetworkDriver<'a, A> {
             level: SecurityLevel::EncMic32,
             asn_in_nonce: false,
             frame_counter: Some(self.frame_count.get()),
-            key_id: KeyId::Source4Index([0, 0, 0, 0], 1),
+            key_id: key_id(self.key_sequence.get()),
         };
 
         // Begin cryptographic and sending procedure for the MLE message
         self.send_buffer
             .take()
             .map_or(Err(ErrorCode::NOMEM), |send_buffer| {
-                self.perform_crypt_op(src_addr, dest_addr, security, mle_buf, send_buffer.take())
-                    .map_err(|(code, buf)| {
-                        // Error occured with cryptographic operation, replace buffer
-                        // for future transmissions and return error code
-                        self.send_buffer.replace(SubSliceMut::new(buf));
-                        code
-                    })
+                self.perform_crypt_op(
+                    src_addr,
+                    dest_addr,
+                    security,
+                    mle_buf,
+                    send_buffer.take(),
+                    true,
+                )
+                .map_err(|(code, buf)| {
+                    // Error occured with cryptographic operation, replace buffer
+                    // for future transmissions and return error code
+                    self.send_buffer.replace(SubSliceMut::new(buf));
+                    code
+                })
             })
     }
 
-    fn recv_logic(&self, sender_ip: IPAddr) -> Result<(), ErrorCode> {
+    fn recv_logic(&self, sender_ip: IPAddr) {
         // This function is called once the received MLE payload has been placed
         // into the recv_buffer. The function handles the message and responds accordingly
+        if let Some(mut recv_buf) = self.recv_buffer.take() {
+            self.handle_mle(sender_ip, recv_buf.as_slice());
+            recv_buf.reset();
+            self.recv_buffer.replace(recv_buf);
+        }
+    }
 
-        self.recv_buffer
-            .take()
-            .map_or(Err(ErrorCode::NOMEM), |mut recv_buf| {
-                if recv_buf[0] == MleCommand::ParentResponse as u8 {
-                    // Received Parent Response -> form Child ID Request
+    fn handle_mle(&self, sender_ip: IPAddr, mle: &[u8]) {
+        let Some((&command, tlvs)) = mle.split_first() else {
+            return;
+        };
 
-                    // UNCOMMENT TO DEBUG THREAD //
-                    // kernel::debug!("[Thread] Received Parent Response.");
-                    // kernel::debug!("[Thread] Sending Child ID Request...");
-
-                    let src_ipv6 = generate_src_ipv6(&self.src_mac_addr);
-
-                    let (output, offset) =
-                        form_child_id_req(recv_buf.as_slice(), self.frame_count.get())?;
-
-                    // Advance state machine
-                    self.state.replace(ThreadState::SendChildIdReq(sender_ip));
-
-                    self.thread_mle_send(&output[..offset], sender_ip, src_ipv6)?;
-                } else if recv_buf[0] == MleCommand::ChildIdResponse as u8 {
-                    // Receive child id response -> advance state machine
-                    self.state.replace(ThreadState::SEDActive(
-                        sender_ip,
-                        MacAddress::Long(mac_from_ipv6(sender_ip)),
-                    ));
-
-                    // TODO: once heart beats are implemented, we will set
-                    // the timer here (as seen below)
-                    // let curr_time = self.alarm.now();
-                    // self.alarm.set_alarm(
-                    //     curr_time,
-                    //     time::ConvertTicks::ticks_from_seconds(self.alarm, 5),
-                    // );
+        match self.state.get() {
+            ThreadState::WaitingParentRsp if command == MleCommand::ParentResponse as u8 => {
+                // Received Parent Response -> form Child ID Request
+
+                // UNCOMMENT TO DEBUG THREAD //
+                // kernel::debug!("[Thread] Received Parent Response.");
+                // kernel::debug!("[Thread] Sending Child ID Request...");
+
+                let Ok((output, offset)) = form_child_id_req(mle, self.frame_count.get()) else {
+                    return;
+                };
+                if let Some(Tlv::SourceAddress(rloc16)) = find_tlv(tlvs, TlvType::SourceAddress) {
+                    self.parent_rloc16.set(rloc16);
+                }
+                if let Some(Tlv::LinkMargin(margin)) = find_tlv(tlvs, TlvType::LinkMargin) {
+                    self.link_quality.set(link_quality(margin));
                 }
 
-                recv_buf.reset();
-                self.recv_buffer.replace(recv_buf);
-                Ok(())
-            })
+                // Advance state machine
+                self.set_state(ThreadState::WaitingChildRsp(sender_ip));
+                self.set_timeout_ms(CHILD_ID_RESPONSE_TIMEOUT_MS);
+
+                let src_ipv6 = generate_src_ipv6(&self.src_mac_addr);
+                let _ = self.thread_mle_send(&output[..offset], sender_ip, src_ipv6);
+            }
+            ThreadState::WaitingChildRsp(parent)
+                if sender_ip == parent && command == MleCommand::ChildIdResponse as u8 =>
+            {
+                // Receive child id response -> attached to the parent
+                if let Some(Tlv::Address16(rloc16)) = find_tlv(tlvs, TlvType::Address16) {
+                    self.rloc16.set(rloc16);
+                }
+                self.leader_data.insert(find_leader_data(tlvs));
+                self.attach_attempt.set(0);
+                self.update_attempts.set(0);
+                self.set_state(ThreadState::Child(parent));
+                self.set_timeout_ms(CHILD_UPDATE_INTERVAL_MS);
+                self.join_complete();
+            }
+            ThreadState::Child(parent)
+                if sender_ip == parent && command == MleCommand::ChildUpdateResponse as u8 =>
+            {
+                if find_tlv(tlvs, TlvType::Status).is_some() {
+                    // The parent no longer has us as a child
+                    self.attach();
+                    return;
+                }
+                if let Some(leader_data) = find_leader_data(tlvs) {
+                    self.leader_data.set(leader_data);
+                }
+                if let Some(Tlv::LinkMargin(margin)) = find_tlv(tlvs, TlvType::LinkMargin) {
+                    self.update_status(|| self.link_quality.set(link_quality(margin)));
+                }
+                self.update_attempts.set(0);
+                self.set_timeout_ms(CHILD_UPDATE_INTERVAL_MS);
+            }
+            _ => {
+                // UNCOMMENT TO DEBUG THREAD //
+                // kernel::debug!("[Thread] Ignored MLE command {}.", command);
+            }
+        }
     }
 
-    fn terminate_child_join(&self, res: Result<(), ErrorCode>) {
-        // Function to schedule upcall to userland on parent request termination. Notifies
-        // userland of the reason for termination with the first argument.
+    fn join_complete(&self) {
+        // Function to schedule upcall to userland once the device attached to a
+        // parent, for the apps that were waiting for it.
 
-        self.apps.each(|_, _, kernel_data| {
-            let _ = kernel_data.schedule_upcall(upcall::JOINCOMPLETE, (into_statuscode(res), 0, 0));
+        self.apps.each(|_, app, kernel_data| {
+            if app.join_pending {
+                app.join_pending = false;
+                let _ = kernel_data
+                    .schedule_upcall(upcall::JOINCOMPLETE, (into_statuscode(Ok(())), 0, 0));
+            }
         });
     }
 
@@ -325,12 +591,14 @@ impl<'a, A: time::Alarm<'a>> ThreadNetworkDriver<'a, A> {
         security: Security,
         payload: &[u8],
         buf: &'static mut [u8],
+        encrypting: bool,
     ) -> Result<(), (ErrorCode, &'static mut [u8])> {
-        // Wrapper function for performing the AES-128CCM encryption. This function generates the nonce,
-        // sets the nonce/key for the crypto engine, generates the authenticated data, and initiates
-        // the crypto operation.
+        // Wrapper function for performing the AES-128CCM encryption/decryption. This function generates
+        // the nonce, sets the nonce/key for the crypto engine, generates the authenticated data, and
+        // initiates the crypto operation.
 
-        // Note: The payload argument does not include aux sec header
+        // Note: The payload argument does not include aux sec header. When decrypting, it includes
+        // the mic that is checked.
 
         // Obtain and unwrap frame counter
         let frame_counter = security.frame_counter;
@@ -346,7 +614,7 @@ impl<'a, A: time::Alarm<'a>> ThreadNetworkDriver<'a, A> {
             frame_counter.unwrap(),
             security.level,
         );
-        let mle_key = self.networkkey.get();
+        let mle_key = key_sequence(security.key_id).and_then(|seq| self.key_for_sequence(seq));
         let mic_len = security.level.mic_len();
         match mle_key {
             Some(netkey) => {
@@ -387,7 +655,11 @@ impl<'a, A: time::Alarm<'a>> ThreadNetworkDriver<'a, A> {
         let aux_sec_header = &mut [0u8; AUX_SEC_HEADER_LENGTH];
         Security::encode(&security, aux_sec_header);
 
-        let m_data_len = payload.len();
+        let m_data_len = if encrypting {
+            payload.len()
+        } else {
+            payload.len() - mic_len
+        };
 
         // Encode auth data and payload into `buf`
         let encode_res = encode_cryp_data(src_addr, dst_addr, aux_sec_header, payload, buf).done();
@@ -426,9 +698,14 @@ impl<'a, A: time::Alarm<'a>> ThreadNetworkDriver<'a, A> {
         }
 
         // Store the length of the payload.
-        self.crypto_sizelock.replace(offset + mic_len);
+        self.crypto_sizelock
+            .replace(if encrypting { offset + mic_len } else { offset });
+        self.encrypting.set(encrypting);
         self.aes_crypto
-            .crypt(buf, 0, AUTH_DATA_LEN, m_data_len, mic_len, true, true)
+            .crypt(buf, 0, AUTH_DATA_LEN, m_data_len, mic_len, true, encrypting)
+            .inspect_err(|_| {
+                self.crypto_sizelock.take();
+            })
     }
 }
 
@@ -436,13 +713,26 @@ impl<'a, A: time::Alarm<'a>> framer::KeyProcedure for ThreadNetworkDriver<'a, A>
     /// Gets the key corresponding to the key that matches the given security
     /// level `level` and key ID `key_id`. If no such key matches, returns
     /// `None`.
-    // TODO: This implementation only supports one key
-    fn lookup_key(&self, _level: SecurityLevel, _key_id: KeyId) -> Option<[u8; 16]> {
-        if let Some(netkey) = self.networkkey.get() {
-            Some(netkey.mac_key)
-        } else {
-            None
-        }
+    fn lookup_key(&self, _level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]> {
+        let index = match key_id {
+            KeyId::Implicit => return self.networkkey.get().map(|netkey| netkey.mac_key),
+            KeyId::Index(index) | KeyId::Source4Index(_, index) | KeyId::Source8Index(_, index) => {
+                index
+            }
+        };
+        let current = self
+            .networkkey
+            .get()
+            .map(|netkey| (self.key_sequence.get(), netkey));
+        [
+            current,
+            self.prev_networkkey.get(),
+            self.next_networkkey.get(),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|(sequence, _)| key_index(*sequence) == index)
+        .map(|(_, netkey)| netkey.mac_key)
     }
 }
 
@@ -459,61 +749,119 @@ impl<'a, A: time::Alarm<'a>> framer::DeviceProcedure for ThreadNetworkDriver<'a,
 impl<'a, A: time::Alarm<'a>> SyscallDriver for ThreadNetworkDriver<'a, A> {
     /// ### `command_num`
     /// - `0`: Driver Check
-    /// - `1`: Add a new mle/mac networkkey and initiate a parent request.
+    /// - `1`: Join the Thread network with the mle/mac networkkey of key
+    ///   sequence `arg1`. The first app to join initiates a parent request.
+    /// - `2`: Get the role, parent RLOC16 and link quality to the parent.
+    /// - `3`: Leave the Thread network.
+    /// - `4`: Provide the mle/mac networkkey of key sequence `arg1`.
 
     fn command(
         &self,
         command_num: usize,
-        _arg1: usize,
+        arg1: usize,
         _: usize,
         processid: ProcessId,
     ) -> CommandReturn {
         match command_num {
             0 => CommandReturn::success(),
 
-            1 => self
-                .apps
-                .enter(processid, |_, kernel_data| {
-                    kernel_data
-                        .get_readonly_processbuffer(ro_allow::WRITE)
-                        .and_then(|ro_buf| {
-                            ro_buf.enter(|src_key| {
-                                // check Thread state, if thread state is not empty,
-                                // another userspace application has control of the Thread
-                                // network and other requesting applications should fail.
-                                if self.state.is_some() {
-                                    return CommandReturn::failure(ErrorCode::BUSY);
-                                }
+            1 => {
+                // Apps may have exited without leaving the network
+                if !self.any_joined() {
+                    self.disable();
+                }
 
-                                // src key consists of the mle and mac keys; Thread
-                                // hash is performed in userland and 32 byte hash is
-                                // passed to thread capsule and entered as mac/mle key
-                                // (For key generation see Thread spec v1.3.0 7.1.4)
-                                if src_key.len() != 32 {
-                                    return CommandReturn::failure(ErrorCode::SIZE);
+                self.apps
+                    .enter(processid, |app, kernel_data| {
+                        if app.joined {
+                            return Err(ErrorCode::ALREADY);
+                        }
+                        let key_sequence = arg1 as u32;
+                        let netkey = Self::read_key(kernel_data)?;
+
+                        match self.state.get() {
+                            ThreadState::Disabled => {
+                                // The first app to join provides the keys
+                                self.networkkey.replace(netkey);
+                                self.key_sequence.set(key_sequence);
+                                self.frame_count.set(0);
+                                app.joined = true;
+                                app.join_pending = true;
+                                Ok(true)
+                            }
+                            state => {
+                                // Other apps share the network if they use
+                                // the same keys
+                                if self.key_for_sequence(key_sequence) != Some(netkey) {
+                                    return Err(ErrorCode::BUSY);
                                 }
-                                let mut mle_key = [0u8; 16];
-                                let mut mac_key = [0u8; 16];
-                                src_key[..16].copy_to_slice(&mut mle_key);
-                                src_key[16..32].copy_to_slice(&mut mac_key);
-                                self.set_networkkey(mle_key, mac_key);
-
-                                // Thread state begins as detached if sucessfully joined
-                                self.state.replace(ThreadState::Detached);
-                                CommandReturn::success()
-                            })
-                        })
-                        .unwrap_or(CommandReturn::failure(ErrorCode::INVAL))
-                })
-                .map_or_else(
-                    |err| CommandReturn::failure(err.into()),
-                    |ok_val| {
+                                app.joined = true;
+                                if let ThreadState::Child(_) = state {
+                                    let _ = kernel_data.schedule_upcall(
+                                        upcall::JOINCOMPLETE,
+                                        (into_statuscode(Ok(())), 0, 0),
+                                    );
+                                } else {
+                                    app.join_pending = true;
+                                }
+                                Ok(false)
+                            }
+                        }
+                    })
+                    .unwrap_or_else(|err| Err(err.into()))
+                    .map_or_else(CommandReturn::failure, |start| {
                         // If no failure in saving the mle/mac key, initiate
                         // sending the parent request
-                        self.send_parent_req();
-                        ok_val
-                    },
-                ),
+                        if start {
+                            self.attach();
+                        }
+                        CommandReturn::success()
+                    })
+            }
+
+            2 => {
+                let (role, parent_rloc16, link_quality) = self.status();
+                CommandReturn::success_u32_u32_u32(
+                    role as u32,
+                    parent_rloc16 as u32,
+                    link_quality as u32,
+                )
+            }
+
+            3 => {
+                let res = self
+                    .apps
+                    .enter(processid, |app, _| {
+                        if !app.joined {
+                            return Err(ErrorCode::INVAL);
+                        }
+                        app.joined = false;
+                        app.join_pending = false;
+                        Ok(())
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                if res.is_ok() && !self.any_joined() {
+                    self.disable();
+                }
+                res.into()
+            }
+
+            4 => self
+                .apps
+                .enter(processid, |app, kernel_data| {
+                    if !app.joined {
+                        return Err(ErrorCode::INVAL);
+                    }
+                    let key_sequence = arg1 as u32;
+                    if key_sequence <= self.key_sequence.get() {
+                        return Err(ErrorCode::INVAL);
+                    }
+                    let netkey = Self::read_key(kernel_data)?;
+                    self.next_networkkey.replace((key_sequence, netkey));
+                    Ok(())
+                })
+                .unwrap_or_else(|err| Err(err.into()))
+                .into(),
 
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
@@ -526,49 +874,39 @@ impl<'a, A: time::Alarm<'a>> SyscallDriver for ThreadNetworkDriver<'a, A> {
 
 impl<'a, A: time::Alarm<'a>> UDPSendClient for ThreadNetworkDriver<'a, A> {
     fn send_done(&self, _result: Result<(), ErrorCode>, mut dgram: SubSliceMut<'static, u8>) {
-        // TODO: handle result from send done and respond accordingly
-
-        // Panicking on unwrap indicates the state was taken without replacement
-        // (unreachable with proper state machine implementation)
-        let curr_state = self.state.take().unwrap();
-
-        // Advance state machine
-        let next_state = match curr_state {
-            ThreadState::SendUpdate(dst_ip, dst_mac) => ThreadState::SEDActive(dst_ip, dst_mac),
-            ThreadState::SendUDPMsg => unimplemented!(),
-            ThreadState::SendChildIdReq(_) => ThreadState::WaitingChildRsp,
-            ThreadState::SendParentReq => {
-                // UNCOMMENT TO DEBUG THREAD //
-                // kernel::debug!("[Thread] Completed sending parent request to multicast IP");
-                ThreadState::WaitingParentRsp
-            }
-            _ => panic!("Thread state machine diverged"),
-        };
-
+        // A failed send is handled like a message that received no answer,
+        // once the alarm for the answer fires
         self.frame_count.set(self.frame_count.get() + 1);
 
-        // Replace the returned buffer and state
+        // Replace the returned buffer
         dgram.reset();
         self.send_buffer.replace(dgram);
-        self.state.replace(next_state);
     }
 }
 
 impl<'a, A: time::Alarm<'a>> time::AlarmClient for ThreadNetworkDriver<'a, A> {
-    // TODO: This function is mainly here as a place holder as it will be needed
-    // for implementing timeouts/timing for sending heartbeat messages to the parent
-    // node
     fn alarm(&self) {
-        match self.state.take().unwrap() {
-            // TODO: Implement retries as defined in the thread spec (when timeouts occur)
-            ThreadState::Detached => unimplemented!("[Thread ALARM] Detached"),
-            ThreadState::SendParentReq => unimplemented!("[Thread ALARM] Send Parent Req"),
-            ThreadState::SendChildIdReq(_) => unimplemented!("[Thread ALARM] Send Child ID Req"),
-            ThreadState::SEDActive(_ipaddr, _mac) => {
-                // TODO: SEND HEARTBEAT to parent node
-                unimplemented!("[Thread ALARM] Send Heartbeat")
+        // Apps may have exited without leaving the network
+        if !self.any_joined() {
+            self.disable();
+            return;
+        }
+
+        match self.state.get() {
+            ThreadState::Disabled => (),
+            ThreadState::Detached => self.attach(),
+            ThreadState::WaitingParentRsp | ThreadState::WaitingChildRsp(_) => self.attach_failed(),
+            ThreadState::Child(parent) => {
+                if self.update_attempts.get() >= CHILD_UPDATE_ATTEMPTS {
+                    // UNCOMMENT TO DEBUG THREAD //
+                    // kernel::debug!("[Thread] Lost parent, attaching again.");
+                    self.attach();
+                } else {
+                    self.update_attempts.set(self.update_attempts.get() + 1);
+                    self.set_timeout_ms(CHILD_UPDATE_RESPONSE_TIMEOUT_MS);
+                    self.send_child_update_req(parent);
+                }
             }
-            _ => panic!(""),
         }
     }
 }
@@ -582,25 +920,44 @@ impl<'a, A: time::Alarm<'a>> UDPRecvClient for ThreadNetworkDriver<'a, A> {
         _dst_port: u16,
         payload: &[u8],
     ) {
-        if payload[0] != SECURITY_SUITE_ENCRYP {
+        if let ThreadState::Disabled = self.state.get() {
+            return;
+        }
+
+        if payload.first() != Some(&SECURITY_SUITE_ENCRYP) {
             // Tock's current implementation of Thread ignores all messages that do not possess MLE encryption. This
             // is due to the Thread spec stating "Except for when specifically indicated, incoming
             // messages that are not secured with either MLE or link-layer security SHOULD be ignored." (v.1.3.0 sect 4.10)
             // UNCOMMENT TO DEBUG THREAD //
             // kernel::debug!("[Thread] DROPPED PACKET - Received unencrypted MLE packet.");
+            return;
         }
 
         // decode aux security header from packet into Security data type
         let sec_res = ieee802154::Security::decode(&payload[1..]).done();
 
         // Guard statement for improperly formated aux sec header
-        if sec_res.is_none() {
+        let Some((_, security)) = sec_res else {
             // UNCOMMENT TO DEBUG THREAD //
             // kernel::debug!("[Thread] DROPPED PACKET - Malformed auxiliary security header.");
             return;
+        };
+        if payload.len() < SECURITY_SUITE_LEN + AUX_SEC_HEADER_LENGTH + security.level.mic_len() {
+            return;
         }
 
-        let security = sec_res.unwrap().1;
+        // Messages secured with keys of a newer key sequence can only be
+        // checked once userland provides the keys
+        if let Some(key_sequence) = key_sequence(security.key_id) {
+            if self.key_for_sequence(key_sequence).is_none() {
+                if key_sequence > self.key_sequence.get() {
+                    self.request_key(key_sequence);
+                }
+                // UNCOMMENT TO DEBUG THREAD //
+                // kernel::debug!("[Thread] DROPPED PACKET - Unknown key sequence.");
+                return;
+            }
+        }
 
         // Take the receive buffer and pass to the `perform_crypto_op` wrapper function. This
         // initiates encoding all relevant auth data, setting crypto engine and initiating the
@@ -615,9 +972,9 @@ impl<'a, A: time::Alarm<'a>> UDPRecvClient for ThreadNetworkDriver<'a, A> {
                     src_addr,
                     dst_addr,
                     security,
-                    &payload[SECURITY_SUITE_LEN + AUX_SEC_HEADER_LENGTH
-                        ..payload.len() - security.level.mic_len()],
+                    &payload[SECURITY_SUITE_LEN + AUX_SEC_HEADER_LENGTH..],
                     recv_buf.take(),
+                    false,
                 )
                 .map_or_else(
                     // Error check on crypto operation. If the crypto operation
@@ -639,12 +996,20 @@ impl<'a, A: time::Alarm<'a>> UDPRecvClient for ThreadNetworkDriver<'a, A> {
 }
 
 impl<'a, A: time::Alarm<'a>> CCMClient for ThreadNetworkDriver<'a, A> {
-    fn crypt_done(&self, buf: &'static mut [u8], _res: Result<(), ErrorCode>, _tag_is_valid: bool) {
-        // TODO: check validity of result/tag and handle accordingly
-
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
         // Obtain the length of the payload from the sizelock
         let buf_len = self.crypto_sizelock.take().unwrap();
 
+        if !self.encrypting.get() && (res.is_err() || !tag_is_valid) {
+            // UNCOMMENT TO DEBUG THREAD //
+            // kernel::debug!("[Thread] DROPPED PACKET - Authentication failed.");
+            self.recv_buffer.replace(SubSliceMut::new(buf));
+            return;
+        } else if res.is_err() {
+            self.send_buffer.replace(SubSliceMut::new(buf));
+            return;
+        }
+
         // The auth data contains the src_addr || dest_addr || aux_sec_header;
         // Recover src/dst addr from the auth data
         let mut src_ipv6 = [0u8; IPV6_LEN];
@@ -658,13 +1023,13 @@ impl<'a, A: time::Alarm<'a>> CCMClient for ThreadNetworkDriver<'a, A> {
         let auth_addr_offset = AUTH_DATA_LEN - AUX_SEC_HEADER_LENGTH;
         buf.copy_within(auth_addr_offset.., SECURITY_SUITE_LEN);
 
-        // Recover the length of the mic from the security information encoded in the aux_sec_header
-        let mic_len = ieee802154::Security::decode(&buf[SECURITY_SUITE_LEN..])
+        // Recover the length of the mic and the key ID from the security information encoded
+        // in the aux_sec_header
+        let security = ieee802154::Security::decode(&buf[SECURITY_SUITE_LEN..])
             .done()
             .unwrap()
-            .1
-            .level
-            .mic_len();
+            .1;
+        let mic_len = security.level.mic_len();
 
         // We hard code the security suite to `0` for now as all messages are
         // assumed to be encrypted for the current implementation
@@ -676,66 +1041,44 @@ impl<'a, A: time::Alarm<'a>> CCMClient for ThreadNetworkDriver<'a, A> {
         // We create a new subslice that we will slice accordingly depending on if we are sending/receiving
         let mut assembled_subslice = SubSliceMut::new(buf);
 
-        // Panicking on unwrap indicates the state was taken without replacement
-        // (unreachable with proper state machine implementation)
-        let curr_state = self.state.take().unwrap();
-
-        match curr_state {
-            ThreadState::SendParentReq | ThreadState::SendChildIdReq(_) => {
-                //TODO: Add alarm for timeouts
-
-                // To send, we need to send: security suite || aux sec header || mle payload || mic
-                // which correlates to the assembled_buf_len
-                assembled_subslice.slice(..assembled_buf_len);
-
-                let dest_ipv6 = match curr_state {
-                    // Determine destination IP depending on message type
-                    ThreadState::SendParentReq => MULTICAST_IPV6,
-                    ThreadState::SendChildIdReq(dst_ipv6) => dst_ipv6,
-                    _ => unreachable!(),
-                };
-
-                // we replace the state with the current state here
-                // because we cannot advance the state machine until
-                // after the `send_done` callback is received
-                self.state.replace(curr_state);
-
-                // Begin sending the transmission
-                self.sender
-                    .driver_send_to(
-                        dest_ipv6,
-                        THREAD_PORT_NUMBER,
-                        THREAD_PORT_NUMBER,
-                        assembled_subslice,
-                        self.driver_send_cap,
-                        self.net_cap,
-                    )
-                    .map_err(|buf| {
-                        // if the sending fails prior to transmission, replace
-                        // the buffer and pass error accordingly to terminate_child_join
-                        // in following unwrap statement
-                        self.send_buffer.replace(buf);
-                        ErrorCode::FAIL
-                    })
-                    .unwrap_or_else(|code| self.terminate_child_join(Err(code)));
-            }
-            ThreadState::WaitingChildRsp => {
-                // TODO: Receive child response
-            }
-            ThreadState::WaitingParentRsp => {
-                // Upon receiving messages, the receive logic only requires the MLE payload. Subsequently,
-                // we slice the assembled_subslice to exclude the security suite, aux sec header, and mic.
-                assembled_subslice
-                    .slice(AUX_SEC_HEADER_LENGTH + SECURITY_SUITE_LEN..assembled_buf_len - mic_len);
-
-                // Move the decrypted MLE message into the recv_buf and execute the receiving logic. Upon
-                // an error in `recv_logic`, joining the network fails and schedule termination upcall
-                self.recv_buffer.replace(assembled_subslice);
-                if let Err(code) = self.recv_logic(IPAddr(src_ipv6)) {
-                    self.terminate_child_join(Err(code))
+        if self.encrypting.get() {
+            // To send, we need to send: security suite || aux sec header || mle payload || mic
+            // which correlates to the assembled_buf_len
+            assembled_subslice.slice(..assembled_buf_len);
+
+            // Begin sending the transmission
+            let _ = self
+                .sender
+                .driver_send_to(
+                    IPAddr(dst_ipv6),
+                    THREAD_PORT_NUMBER,
+                    THREAD_PORT_NUMBER,
+                    assembled_subslice,
+                    self.driver_send_cap,
+                    self.net_cap,
+                )
+                .map_err(|buf| {
+                    // if the sending fails prior to transmission, replace
+                    // the buffer; the message is handled as unanswered
+                    self.send_buffer.replace(buf);
+                });
+        } else {
+            // The message was authenticated, so a newer key sequence used by
+            // it is now in use
+            if let Some(key_sequence) = key_sequence(security.key_id) {
+                if key_sequence > self.key_sequence.get() {
+                    self.switch_key(key_sequence);
                 }
             }
-            _ => (),
+
+            // Upon receiving messages, the receive logic only requires the MLE payload. Subsequently,
+            // we slice the assembled_subslice to exclude the security suite, aux sec header, and mic.
+            assembled_subslice
+                .slice(AUX_SEC_HEADER_LENGTH + SECURITY_SUITE_LEN..assembled_buf_len - mic_len);
+
+            // Move the decrypted MLE message into the recv_buf and execute the receiving logic.
+            self.recv_buffer.replace(assembled_subslice);
+            self.recv_logic(IPAddr(src_ipv6));
         }
     }
 }
@@ -2,9 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 // Copyright Tock Contributors 2023.
 
+use crate::net::ieee802154::KeyId;
+use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::stream::{encode_bytes, SResult};
-use crate::net::thread::tlv::{unwrap_tlv_offset, LinkMode, MulticastResponder, Tlv};
-use crate::net::{ieee802154::MacAddress, ipv6::ip_utils::IPAddr};
+use crate::net::thread::tlv::{unwrap_tlv_offset, LinkMode, Tlv, TlvType};
 pub const THREAD_PORT_NUMBER: u16 = 19788;
 
 use kernel::ErrorCode;
@@ -14,27 +15,64 @@ pub const AUX_SEC_HEADER_LENGTH: usize = 10;
 pub const AUTH_DATA_LEN: usize = 42;
 pub const IPV6_LEN: usize = 16;
 const PARENT_REQUEST_MLE_SIZE: usize = 21;
+const CHILD_UPDATE_REQUEST_MLE_SIZE: usize = 24;
+/// Child timeout requested from the parent, in seconds.
+pub const CHILD_TIMEOUT_S: u32 = 10;
+/// RLOC16 reported when the device has no parent.
+pub const INVALID_RLOC16: u16 = 0xfffe;
 pub const MULTICAST_IPV6: IPAddr = IPAddr([
     0xff, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
 ]);
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub struct NetworkKey {
     pub mle_key: [u8; 16],
     pub mac_key: [u8; 16],
 }
 
+/// Leader Data TLV value, as last received from the parent.
+#[derive(Clone, Copy)]
+pub struct LeaderData {
+    pub partition_id: u32,
+    pub weighting: u8,
+    pub data_version: u8,
+    pub stable_data_version: u8,
+    pub leader_router_id: u8,
+}
+
+#[derive(Clone, Copy)]
 pub enum ThreadState {
-    SendParentReq,
-    WaitingParentRsp,
-    RecvParentRsp(IPAddr),
-    SendChildIdReq(IPAddr),
-    WaitingChildRsp,
-    RecvChildRsp(IPAddr),
-    SEDActive(IPAddr, MacAddress),
-    SendUpdate(IPAddr, MacAddress),
-    SendUDPMsg,
+    /// No application has joined a Thread network.
+    Disabled,
+    /// Not attached; the next Parent Request is sent when the alarm fires.
     Detached,
+    /// A Parent Request was sent and we are waiting for a Parent Response.
+    WaitingParentRsp,
+    /// A Child ID Request was sent to the parent and we are waiting for
+    /// the Child ID Response.
+    WaitingChildRsp(IPAddr),
+    /// Attached as a child of the parent.
+    Child(IPAddr),
+}
+
+/// Role of the device in the Thread network, as reported to userspace.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ThreadRole {
+    Disabled = 0,
+    Detached = 1,
+    Child = 2,
+}
+
+impl ThreadState {
+    pub fn role(&self) -> ThreadRole {
+        match self {
+            ThreadState::Disabled => ThreadRole::Disabled,
+            ThreadState::Detached
+            | ThreadState::WaitingParentRsp
+            | ThreadState::WaitingChildRsp(_) => ThreadRole::Detached,
+            ThreadState::Child(_) => ThreadRole::Child,
+        }
+    }
 }
 
 pub enum MleCommand {
@@ -125,9 +163,80 @@ pub fn encode_cryp_data(
     stream_done!(off)
 }
 
-/// This helper function creates a parent request. For now,
-/// this implementation hard codes all values for the parent request
-pub fn form_parent_req() -> [u8; PARENT_REQUEST_MLE_SIZE] {
+/// Key index of the keys for `key_sequence`.
+pub fn key_index(key_sequence: u32) -> u8 {
+    (key_sequence & 0x7f) as u8 + 1
+}
+
+/// Key ID used in the auxiliary security header of MLE messages secured
+/// with the keys for `key_sequence`. The key source holds the key sequence.
+pub fn key_id(key_sequence: u32) -> KeyId {
+    // The key source is stored reversed, see `KeyId::decode`.
+    KeyId::Source4Index(key_sequence.to_le_bytes(), key_index(key_sequence))
+}
+
+/// Key sequence of the keys used to secure an MLE message with key ID
+/// `key_id`.
+pub fn key_sequence(key_id: KeyId) -> Option<u32> {
+    match key_id {
+        KeyId::Source4Index(source, _) => Some(u32::from_le_bytes(source)),
+        _ => None,
+    }
+}
+
+/// Converts the link margin (in dB) reported by a neighbor to a link
+/// quality between 0 and 3.
+pub fn link_quality(link_margin: u8) -> u8 {
+    match link_margin {
+        21.. => 3,
+        11..=20 => 2,
+        3..=10 => 1,
+        _ => 0,
+    }
+}
+
+/// Helper function to locate and decode the TLV of type `tlv_type` in
+/// the TLVs of a received MLE message.
+pub fn find_tlv(buf: &[u8], tlv_type: TlvType) -> Option<Tlv<'_>> {
+    let tlv_type = tlv_type as u8;
+    let mut index = 0;
+    while index + 2 <= buf.len() {
+        let end = index + 2 + buf[index + 1] as usize;
+        if end > buf.len() {
+            return None;
+        }
+        if buf[index] == tlv_type {
+            return Tlv::decode(&buf[index..end]).done().map(|(_, tlv)| tlv);
+        }
+        index = end;
+    }
+    None
+}
+
+/// Helper function to find the Leader Data TLV in a received MLE message.
+pub fn find_leader_data(buf: &[u8]) -> Option<LeaderData> {
+    match find_tlv(buf, TlvType::LeaderData) {
+        Some(Tlv::LeaderData {
+            partition_id,
+            weighting,
+            data_version,
+            stable_data_version,
+            leader_router_id,
+        }) => Some(LeaderData {
+            partition_id,
+            weighting,
+            data_version,
+            stable_data_version,
+            leader_router_id,
+        }),
+        _ => None,
+    }
+}
+
+/// This helper function creates a parent request soliciting responses from
+/// the devices in `scan_mask`. For now, this implementation hard codes all
+/// other values for the parent request
+pub fn form_parent_req(scan_mask: u8) -> [u8; PARENT_REQUEST_MLE_SIZE] {
     // TODO: form parent request from alterable values, generate
     // challenge from random number generator
     let mut output = [0u8; PARENT_REQUEST_MLE_SIZE];
@@ -157,7 +266,7 @@ pub fn form_parent_req() -> [u8; PARENT_REQUEST_MLE_SIZE] {
 
     // Scan Mask TLV //
     offset += unwrap_tlv_offset(Tlv::encode(
-        &Tlv::ScanMask(MulticastResponder::Router as u8),
+        &Tlv::ScanMask(scan_mask),
         &mut output[offset..],
     ));
 
@@ -227,7 +336,7 @@ pub fn form_child_id_req(
 
     // Timeout TLV //
     offset += unwrap_tlv_offset(Tlv::encode(
-        &Tlv::Timeout((10_u32).to_be()),
+        &Tlv::Timeout(CHILD_TIMEOUT_S.to_be()),
         &mut output[offset..],
     ));
 
@@ -246,8 +355,57 @@ pub fn form_child_id_req(
     Ok((output, offset))
 }
 
+/// This helper function creates a child update request, which an attached
+/// child sends to keep its parent from timing it out. `leader_data` is the
+/// one last received from the parent.
+pub fn form_child_update_req(
+    rloc16: u16,
+    leader_data: Option<LeaderData>,
+) -> ([u8; CHILD_UPDATE_REQUEST_MLE_SIZE], usize) {
+    let mut output = [0u8; CHILD_UPDATE_REQUEST_MLE_SIZE];
+    let mut offset = 0;
+
+    // Command: Child Update Request //
+    output[0..1].copy_from_slice(&[MleCommand::ChildUpdateRequest as u8]);
+    offset += 1;
+
+    // Mode TLV //
+    offset += unwrap_tlv_offset(Tlv::encode(
+        &Tlv::Mode(LinkMode::FullThreadDevice as u8 + LinkMode::ReceiverOnWhenIdle as u8),
+        &mut output[offset..],
+    ));
+
+    // Timeout TLV //
+    offset += unwrap_tlv_offset(Tlv::encode(
+        &Tlv::Timeout(CHILD_TIMEOUT_S.to_be()),
+        &mut output[offset..],
+    ));
+
+    // Source Address TLV //
+    offset += unwrap_tlv_offset(Tlv::encode(
+        &Tlv::SourceAddress(rloc16.to_be()),
+        &mut output[offset..],
+    ));
+
+    // Leader Data TLV //
+    if let Some(leader_data) = leader_data {
+        offset += unwrap_tlv_offset(Tlv::encode(
+            &Tlv::LeaderData {
+                partition_id: leader_data.partition_id.to_be(),
+                weighting: leader_data.weighting,
+                data_version: leader_data.data_version,
+                stable_data_version: leader_data.stable_data_version,
+                leader_router_id: leader_data.leader_router_id,
+            },
+            &mut output[offset..],
+        ));
+    }
+
+    (output, offset)
+}
+
 /*
-This is just here as a note for when retries are added
+Parent Request retries, as implemented by the Thread driver
 ==================================================================================================
 THREAD SPEC v1.3.0 -- section 4.5.1
 A Thread Device attempting to attach MUST first attempt to attach with the Scan Mask TLV of
@@ -7,10 +7,13 @@
 //! Implements a userspace interface for sending and receiving UDP messages.
 //! Processes use this driver to send UDP packets from a common interface
 //! and bind to UDP ports for receiving packets.
-//! Also exposes a list of interface addresses to the application (currently
-//! hard-coded).
+//! Also exposes the list of interface addresses to the application. Slots of
+//! the list that hold the unspecified address are unused, Neighbor Discovery
+//! fills them with autoconfigured addresses.
 
-use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::icmpv6::icmpv6_echo::ICMP6ErrorClient;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::IP6Header;
 use crate::net::network_capabilities::NetworkCapability;
 use crate::net::stream::encode_u16;
 use crate::net::stream::encode_u8;
@@ -21,8 +24,8 @@ use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
 use crate::net::util::host_slice_to_u16;
 
 use core::cell::Cell;
+use core::mem;
 use core::mem::size_of;
-use core::{cmp, mem};
 
 use kernel::capabilities::UdpDriverCapability;
 use kernel::debug;
@@ -47,8 +50,12 @@ mod upcall {
     /// currently pass information regarding whether packets were acked at the
     /// link layer.
     pub const PACKET_TRANSMITTED: usize = 1;
+    /// Callback for when an ICMPv6 Destination Unreachable error arrives for
+    /// a packet sent from the bound port. Arguments are the ICMPv6 code and
+    /// the destination port of the packet.
+    pub const DESTINATION_UNREACHABLE: usize = 2;
     /// Number of upcalls.
-    pub const COUNT: u8 = 2;
+    pub const COUNT: u8 = 3;
 }
 
 /// Ids for read-only allow buffers
@@ -133,7 +140,7 @@ pub struct UDPDriver<'a> {
     current_app: Cell<Option<ProcessId>>,
 
     /// List of IP Addresses of the interfaces on the device
-    interface_list: &'static [IPAddr],
+    interface_list: &'static [Cell<IPAddr>],
 
     /// Maximum length payload that an app can transmit via this driver
     max_tx_pyld_len: usize,
@@ -157,7 +164,7 @@ impl<'a> UDPDriver<'a> {
             AllowRoCount<{ ro_allow::COUNT }>,
             AllowRwCount<{ rw_allow::COUNT }>,
         >,
-        interface_list: &'static [IPAddr],
+        interface_list: &'static [Cell<IPAddr>],
         max_tx_pyld_len: usize,
         port_table: &'static UdpPortManager,
         kernel_buffer: SubSliceMut<'static, u8>,
@@ -400,15 +407,21 @@ impl SyscallDriver for UDPDriver<'_> {
                                     if cfg.len() != arg1 * size_of::<IPAddr>() {
                                         return CommandReturn::failure(ErrorCode::INVAL);
                                     }
-                                    let n_ifaces_to_copy =
-                                        cmp::min(arg1, self.interface_list.len());
                                     let iface_size = size_of::<IPAddr>();
-                                    for i in 0..n_ifaces_to_copy {
-                                        cfg[i * iface_size..(i + 1) * iface_size]
-                                            .copy_from_slice(&self.interface_list[i].0);
+                                    let mut n_ifaces = 0;
+                                    for iface in self.interface_list {
+                                        let addr = iface.get();
+                                        if addr.is_unspecified() {
+                                            continue;
+                                        }
+                                        if n_ifaces < arg1 {
+                                            cfg[n_ifaces * iface_size..(n_ifaces + 1) * iface_size]
+                                                .copy_from_slice(&addr.0);
+                                        }
+                                        n_ifaces += 1;
                                     }
                                     // Returns total number of interfaces
-                                    CommandReturn::success_u32(self.interface_list.len() as u32)
+                                    CommandReturn::success_u32(n_ifaces as u32)
                                 })
                             })
                             .unwrap_or(CommandReturn::failure(ErrorCode::INVAL))
@@ -503,12 +516,10 @@ impl SyscallDriver for UDPDriver<'_> {
                                 return Ok(None);
                             }
                             // Check that requested addr is a local interface
-                            let mut requested_is_local = false;
-                            for i in 0..self.interface_list.len() {
-                                if requested_addr.addr == self.interface_list[i] {
-                                    requested_is_local = true;
-                                }
-                            }
+                            let requested_is_local = self
+                                .interface_list
+                                .iter()
+                                .any(|iface| iface.get() == requested_addr.addr);
                             if !requested_is_local {
                                 return Err(Err(ErrorCode::INVAL));
                             }
@@ -632,6 +643,26 @@ impl UDPRecvClient for UDPDriver<'_> {
     }
 }
 
+impl ICMP6ErrorClient for UDPDriver<'_> {
+    fn destination_unreachable(&self, code: u8, header: IP6Header, transport: &[u8]) {
+        if header.get_next_header() != ip6_nh::UDP || transport.len() < 4 {
+            return;
+        }
+        let src_port = u16::from_be_bytes([transport[0], transport[1]]);
+        let dst_port = u16::from_be_bytes([transport[2], transport[3]]);
+        // Each port is bound by at most one app. The source address is not
+        // compared, as the IPv6 sender may have replaced a link-local one.
+        self.apps.each(|_, app, kernel_data| {
+            if app.bound_port.is_some_and(|bound| bound.port == src_port) {
+                let _ = kernel_data.schedule_upcall(
+                    upcall::DESTINATION_UNREACHABLE,
+                    (code as usize, dst_port as usize, 0),
+                );
+            }
+        });
+    }
+}
+
 impl PortQuery for UDPDriver<'_> {
     // Returns true if |port| is bound (on any iface), false otherwise.
     fn is_bound(&self, port: u16) -> bool {
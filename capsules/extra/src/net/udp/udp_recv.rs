@@ -12,8 +12,8 @@
 //! bindings of kernel apps to ensure correctness when dispatching
 //! received packets to the appropriate client.
 
-use crate::net::ipv6::ip_utils::IPAddr;
-use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::{IP6RecvClient, IPRecvClient};
 use crate::net::ipv6::IP6Header;
 use crate::net::udp::driver::UDPDriver;
 use crate::net::udp::udp_port_table::{PortQuery, UdpPortBindingRx};
@@ -47,6 +47,21 @@ impl<'a> MuxUdpReceiver<'a> {
 
 impl IP6RecvClient for MuxUdpReceiver<'_> {
     fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        // Other transport protocols share the IPv6 receive path
+        if ip_header.get_next_header() != ip6_nh::UDP {
+            return;
+        }
+        IPRecvClient::receive(
+            self,
+            ip_header.get_src_addr(),
+            ip_header.get_dst_addr(),
+            payload,
+        );
+    }
+}
+
+impl IPRecvClient for MuxUdpReceiver<'_> {
+    fn receive(&self, src_addr: IPAddr, dst_addr: IPAddr, payload: &[u8]) {
         if let Some((offset, udp_header)) = UDPHeader::decode(payload).done() {
             let len = udp_header.get_len() as usize;
             let dst_port = udp_header.get_dst_port();
@@ -60,8 +75,8 @@ impl IP6RecvClient for MuxUdpReceiver<'_> {
                         if binding.get_port() == dst_port {
                             rcvr.client.map(|client| {
                                 client.receive(
-                                    ip_header.get_src_addr(),
-                                    ip_header.get_dst_addr(),
+                                    src_addr,
+                                    dst_addr,
                                     udp_header.get_src_port(),
                                     udp_header.get_dst_port(),
                                     &payload[offset..],
@@ -77,8 +92,8 @@ impl IP6RecvClient for MuxUdpReceiver<'_> {
                         if let Some(driver) = self.driver.take() {
                             if driver.is_bound(dst_port) {
                                 driver.receive(
-                                    ip_header.get_src_addr(),
-                                    ip_header.get_dst_addr(),
+                                    src_addr,
+                                    dst_addr,
                                     udp_header.get_src_port(),
                                     udp_header.get_dst_port(),
                                     &payload[offset..],
@@ -28,7 +28,7 @@
 //! MuxUdpSender queue at a time.
 
 use crate::net::ipv6::ip_utils::IPAddr;
-use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::ipv6_send::{IPSendClient, IPSender};
 use crate::net::ipv6::TransportHeader;
 use crate::net::network_capabilities::{NetworkCapability, UdpVisibilityCapability};
 use crate::net::udp::udp_port_table::UdpPortBindingTx;
@@ -43,17 +43,17 @@ use kernel::utilities::cells::{MapCell, OptionalCell};
 use kernel::utilities::leasable_buffer::SubSliceMut;
 use kernel::ErrorCode;
 
-pub struct MuxUdpSender<'a, T: IP6Sender<'a>> {
+pub struct MuxUdpSender<'a, T: IPSender<'a>> {
     sender_list: List<'a, UDPSendStruct<'a, T>>,
-    ip_sender: &'a dyn IP6Sender<'a>,
+    ip_sender: &'a dyn IPSender<'a>,
 }
 
-impl<'a, T: IP6Sender<'a>> MuxUdpSender<'a, T> {
-    pub fn new(ip6_sender: &'a dyn IP6Sender<'a>) -> MuxUdpSender<'a, T> {
+impl<'a, T: IPSender<'a>> MuxUdpSender<'a, T> {
+    pub fn new(ip_sender: &'a dyn IPSender<'a>) -> MuxUdpSender<'a, T> {
         // similar to UdpSendStruct new()
         MuxUdpSender {
             sender_list: List::new(),
-            ip_sender: ip6_sender,
+            ip_sender,
         }
     }
 
@@ -77,6 +77,11 @@ impl<'a, T: IP6Sender<'a>> MuxUdpSender<'a, T> {
                         .ip_sender
                         .send_to(dest, transport_header, &buf, net_cap);
                     caller.tx_buffer.replace(buf); //Replace buffer as soon as sent.
+                    if ret.is_err() {
+                        // No send_done will follow, so do not block the
+                        // senders queued after this one.
+                        self.sender_list.pop_head();
+                    }
                     ret
                 }
                 None => {
@@ -95,64 +100,72 @@ impl<'a, T: IP6Sender<'a>> MuxUdpSender<'a, T> {
     }
 }
 
-/// This function implements the `IP6SendClient` trait for the `UDPSendStruct`,
+/// This function implements the `IPSendClient` trait for the `UDPSendStruct`,
 /// and is necessary to receive callbacks from the lower (IP) layer. When
 /// the UDP layer receives this callback, it forwards it to the `UDPSendClient`.
-impl<'a, T: IP6Sender<'a>> IP6SendClient for MuxUdpSender<'a, T> {
+impl<'a, T: IPSender<'a>> IPSendClient for MuxUdpSender<'a, T> {
     fn send_done(&self, result: Result<(), ErrorCode>) {
-        let last_sender = self.sender_list.pop_head();
-        let next_sender_option = self.sender_list.head(); // must check here, because udp driver
-                                                          // could queue addl. sends in response to
-                                                          // send_done.
-        last_sender.map(|last_sender| {
-            last_sender
-                .client
-                .map(|client| match last_sender.tx_buffer.take() {
-                    Some(buf) => {
-                        client.send_done(result, buf);
-                    }
-                    None => {
-                        debug!("ERROR: Missing buffer in send done.");
-                    }
-                })
-        });
+        let mut result = result;
+        // A queued packet that fails to start will never get a send_done of
+        // its own, so report it to its sender here and move on to the next
+        // one until a send starts or the queue is empty.
+        loop {
+            let last_sender = self.sender_list.pop_head();
+            let next_sender_option = self.sender_list.head(); // must check here, because udp driver
+                                                              // could queue addl. sends in response to
+                                                              // send_done.
+            last_sender.map(|last_sender| {
+                last_sender
+                    .client
+                    .map(|client| match last_sender.tx_buffer.take() {
+                        Some(buf) => {
+                            client.send_done(result, buf);
+                        }
+                        None => {
+                            debug!("ERROR: Missing buffer in send done.");
+                        }
+                    })
+            });
 
-        let success = match next_sender_option {
-            Some(next_sender) => {
-                //send next packet in queue
-                match next_sender.tx_buffer.take() {
-                    Some(buf) => match next_sender.next_th.take() {
-                        Some(th) => match next_sender.net_cap.take() {
-                            Some(net_cap) => {
-                                let ret = self.ip_sender.send_to(
-                                    next_sender.next_dest.get(),
-                                    th,
-                                    &buf,
-                                    net_cap,
-                                );
-                                next_sender.tx_buffer.replace(buf);
-                                if ret != Ok(()) {
-                                    debug!("IP send_to failed: {:?}", ret);
+            let success = match next_sender_option {
+                Some(next_sender) => {
+                    //send next packet in queue
+                    match next_sender.tx_buffer.take() {
+                        Some(buf) => match next_sender.next_th.take() {
+                            Some(th) => match next_sender.net_cap.take() {
+                                Some(net_cap) => {
+                                    let ret = self.ip_sender.send_to(
+                                        next_sender.next_dest.get(),
+                                        th,
+                                        &buf,
+                                        net_cap,
+                                    );
+                                    next_sender.tx_buffer.replace(buf);
+                                    if ret != Ok(()) {
+                                        debug!("IP send_to failed: {:?}", ret);
+                                    }
+                                    ret
                                 }
-                                ret
+                                None => Err(ErrorCode::FAIL),
+                            },
+                            None => {
+                                debug!("Missing transport header.");
+                                Err(ErrorCode::FAIL)
                             }
-                            None => Err(ErrorCode::FAIL),
                         },
                         None => {
-                            debug!("Missing transport header.");
+                            debug!("No buffer available to take.");
                             Err(ErrorCode::FAIL)
                         }
-                    },
-                    None => {
-                        debug!("No buffer available to take.");
-                        Err(ErrorCode::FAIL)
                     }
                 }
+                None => Ok(()), //No more packets queued.
+            };
+            if success == Ok(()) {
+                break;
             }
-            None => Ok(()), //No more packets queued.
-        };
-        if success != Ok(()) {
             debug!("Error in udp_send send_done() callback.");
+            result = success;
         }
     }
 }
@@ -253,7 +266,7 @@ pub trait UDPSender<'a> {
 /// This is a specific instantiation of the `UDPSender` trait. Note
 /// that this struct contains a reference to an `IP6Sender` which it
 /// forwards packets to (and receives callbacks from).
-pub struct UDPSendStruct<'a, T: IP6Sender<'a>> {
+pub struct UDPSendStruct<'a, T: IPSender<'a>> {
     udp_mux_sender: &'a MuxUdpSender<'a, T>,
     client: OptionalCell<&'a dyn UDPSendClient>,
     next: ListLink<'a, UDPSendStruct<'a, T>>,
@@ -265,7 +278,7 @@ pub struct UDPSendStruct<'a, T: IP6Sender<'a>> {
     net_cap: OptionalCell<&'static NetworkCapability>,
 }
 
-impl<'a, T: IP6Sender<'a>> ListNode<'a, UDPSendStruct<'a, T>> for UDPSendStruct<'a, T> {
+impl<'a, T: IPSender<'a>> ListNode<'a, UDPSendStruct<'a, T>> for UDPSendStruct<'a, T> {
     fn next(&'a self) -> &'a ListLink<'a, UDPSendStruct<'a, T>> {
         &self.next
     }
@@ -273,7 +286,7 @@ impl<'a, T: IP6Sender<'a>> ListNode<'a, UDPSendStruct<'a, T>> for UDPSendStruct<
 
 /// Below is the implementation of the `UDPSender` traits for the
 /// `UDPSendStruct`.
-impl<'a, T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
+impl<'a, T: IPSender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
     fn set_client(&self, client: &'a dyn UDPSendClient) {
         self.client.set(client);
     }
@@ -357,7 +370,7 @@ impl<'a, T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
     }
 }
 
-impl<'a, T: IP6Sender<'a>> UDPSendStruct<'a, T> {
+impl<'a, T: IPSender<'a>> UDPSendStruct<'a, T> {
     pub fn new(
         udp_mux_sender: &'a MuxUdpSender<'a, T>, /*binding: UdpPortBindingTx*/
         udp_vis: &'static UdpVisibilityCapability,
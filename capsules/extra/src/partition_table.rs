@@ -0,0 +1,243 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! MBR and GPT partition table parser.
+//!
+//! `PartitionTable` finds the partitions on a device with 512 byte blocks. It
+//! does not access the device itself: like `fat::FatReader`, each call says
+//! which block it needs next, and the caller reads that block and passes it
+//! back. This lets the same parser be used by drivers that own a device and by
+//! `virtual_block_device::MuxBlockDevice`.
+//!
+//! Block 0 is an MBR if it ends with the boot signature and the status byte of
+//! every entry is valid, which tells it apart from the boot sector of a device
+//! without a partition table. If an entry has the protective type `0xEE`, the
+//! partitions are read from the GPT instead. Partitions are reported in the
+//! order of the used entries of the table.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let mut table = PartitionTable::new();
+//! let mut next = Some(table.start());
+//! while let Some(block) = next {
+//!     let data = read_block(block);
+//!     next = table.block_read(&data, |partition| {
+//!         // Return false to stop reading the table.
+//!         true
+//!     });
+//! }
+//! ```
+
+/// Size of the blocks the partition table is read in.
+pub const BLOCK_SIZE: usize = 512;
+
+pub(crate) const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+pub(crate) const MBR_PARTITIONS: usize = 446;
+pub(crate) const MBR_PARTITION_LEN: usize = 16;
+/// MBR partition type used to protect a GPT.
+pub(crate) const MBR_GPT_PROTECTIVE: u8 = 0xEE;
+
+pub(crate) const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+
+fn u32_at(b: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+}
+
+fn u64_at(b: &[u8], i: usize) -> u64 {
+    u32_at(b, i) as u64 | (u32_at(b, i + 4) as u64) << 32
+}
+
+/// The type of a partition.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PartitionType {
+    /// The partition type byte of an MBR entry.
+    Mbr(u8),
+    /// The partition type GUID of a GPT entry, as stored on disk.
+    Gpt([u8; 16]),
+}
+
+/// A partition found in the partition table.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PartitionEntry {
+    pub kind: PartitionType,
+    /// The first block.
+    pub start: u32,
+    /// The number of blocks. The partition always ends within the first
+    /// `u32::MAX` blocks.
+    pub count: u32,
+}
+
+impl PartitionEntry {
+    /// The entry for `count` blocks at `start`, if they can be addressed with
+    /// 32 bit block numbers.
+    fn new(kind: PartitionType, start: u64, count: u64) -> Option<Self> {
+        let start = u32::try_from(start).ok()?;
+        let count = u32::try_from(count).ok().filter(|count| *count != 0)?;
+        start.checked_add(count)?;
+        Some(Self { kind, start, count })
+    }
+}
+
+/// The block being read.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// The MBR in block 0.
+    Mbr,
+    /// The GPT header in block 1.
+    GptHeader,
+    /// The GPT partition entries. `remaining` entries of `entry_size` bytes
+    /// are left, starting in `block`.
+    GptEntries {
+        block: u32,
+        remaining: u32,
+        entry_size: usize,
+    },
+}
+
+/// Reads the partition table one block at a time.
+#[derive(Clone, Copy, Debug)]
+pub struct PartitionTable {
+    state: State,
+}
+
+impl Default for PartitionTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartitionTable {
+    pub const fn new() -> Self {
+        Self { state: State::Idle }
+    }
+
+    /// Start reading the partition table. Returns the block to read and pass
+    /// to `block_read()`.
+    pub fn start(&mut self) -> u32 {
+        self.state = State::Mbr;
+        0
+    }
+
+    /// Continue with the contents of the block requested by `start()` or the
+    /// previous call. `found` is called with each partition in the table, and
+    /// reading stops when it returns false.
+    ///
+    /// Returns the next block to read, or `None` once the table has been read.
+    /// A device without a valid partition table has no partitions.
+    pub fn block_read(
+        &mut self,
+        block: &[u8],
+        found: impl FnMut(PartitionEntry) -> bool,
+    ) -> Option<u32> {
+        let state = core::mem::replace(&mut self.state, State::Idle);
+        if block.len() < BLOCK_SIZE {
+            return None;
+        }
+        let next = match state {
+            State::Idle => None,
+            State::Mbr => self.mbr_read(block, found),
+            State::GptHeader => self.gpt_header_read(block),
+            State::GptEntries {
+                block: current,
+                remaining,
+                entry_size,
+            } => self.gpt_entries_read(block, current, remaining, entry_size, found),
+        };
+        next.map(|(state, block)| {
+            self.state = state;
+            block
+        })
+    }
+
+    fn mbr_read(
+        &self,
+        block: &[u8],
+        mut found: impl FnMut(PartitionEntry) -> bool,
+    ) -> Option<(State, u32)> {
+        let entries = block[MBR_PARTITIONS..510].chunks(MBR_PARTITION_LEN);
+        if block[510..512] != MBR_SIGNATURE || entries.clone().any(|entry| entry[0] & 0x7F != 0) {
+            return None;
+        }
+        if entries.clone().any(|entry| entry[4] == MBR_GPT_PROTECTIVE) {
+            return Some((State::GptHeader, 1));
+        }
+
+        for entry in entries.filter(|entry| entry[4] != 0) {
+            let partition = PartitionEntry::new(
+                PartitionType::Mbr(entry[4]),
+                u32_at(entry, 8).into(),
+                u32_at(entry, 12).into(),
+            );
+            if partition.is_some_and(|partition| !found(partition)) {
+                break;
+            }
+        }
+        None
+    }
+
+    fn gpt_header_read(&self, block: &[u8]) -> Option<(State, u32)> {
+        let entries = u32::try_from(u64_at(block, 72)).ok()?;
+        let remaining = u32_at(block, 80);
+        let entry_size = u32_at(block, 84) as usize;
+        if block[..8] != *GPT_SIGNATURE
+            || entry_size < 128
+            || BLOCK_SIZE % entry_size != 0
+            || remaining == 0
+        {
+            return None;
+        }
+        Some((
+            State::GptEntries {
+                block: entries,
+                remaining,
+                entry_size,
+            },
+            entries,
+        ))
+    }
+
+    fn gpt_entries_read(
+        &self,
+        block: &[u8],
+        current: u32,
+        remaining: u32,
+        entry_size: usize,
+        mut found: impl FnMut(PartitionEntry) -> bool,
+    ) -> Option<(State, u32)> {
+        for entry in block[..BLOCK_SIZE]
+            .chunks(entry_size)
+            .take(remaining as usize)
+        {
+            // Unused entries have a zero partition type GUID.
+            let mut kind = [0; 16];
+            kind.copy_from_slice(&entry[..16]);
+            if kind == [0; 16] {
+                continue;
+            }
+            let first = u64_at(entry, 32);
+            let last = u64_at(entry, 40);
+            let partition = last
+                .checked_sub(first)
+                .and_then(|blocks| blocks.checked_add(1))
+                .and_then(|count| PartitionEntry::new(PartitionType::Gpt(kind), first, count));
+            if partition.is_some_and(|partition| !found(partition)) {
+                return None;
+            }
+        }
+
+        let remaining = remaining.saturating_sub((BLOCK_SIZE / entry_size) as u32);
+        let next = current.checked_add(1).filter(|_| remaining != 0)?;
+        Some((
+            State::GptEntries {
+                block: next,
+                remaining,
+                entry_size,
+            },
+            next,
+        ))
+    }
+}
@@ -0,0 +1,650 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Record the state of faulted processes in nonvolatile storage.
+//!
+//! When a process faults, the kernel prints its state to the debug console,
+//! which is lost if nobody is watching. This capsule wraps the board's
+//! [`ProcessFaultPolicy`] and, whenever a process faults, serializes the
+//! process' stored register state, the memory regions it could access, its
+//! memory layout and grant usage, and a copy of its stack into a record in the
+//! format of the `tock-fault-dump` library. The record is written to a reserved
+//! region of a [`NonvolatileStorage`] device, so it can be read back after a
+//! reboot with the process console (`faultdump` command) or from userspace
+//! with this driver, and decoded with the `tock-fault-dump` library.
+//!
+//! The region is split into slots of the size of the record buffer, which are
+//! used round robin, so the most recent `region_len / DUMP_LEN` records are
+//! kept. The record is written after the fault policy decided what to do with
+//! the process. If that decision is to panic, the board panics before the
+//! record can be written.
+//!
+//! Only one record is held in RAM at a time. If further processes fault before
+//! it is written, their faults are not recorded.
+//!
+//! Fault dumps contain the stack memory of processes, so the userspace driver
+//! is only available to the processes whose `ShortId` is in the list of
+//! trusted apps the board passes in. To all other processes the driver appears
+//! to not exist. Processes with a `LocallyUnique` `ShortId` are never trusted.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### Commands
+//!
+//! - 0: Check driver exists.
+//! - 1: Get the number of stored fault dumps.
+//! - 2: Read fault dump `data1` (0 is the most recent) into the read-write
+//!   allow buffer 0. Upcall 0 is scheduled with the status, the length of the
+//!   record and its sequence number when done.
+//! - 3: Erase all fault dumps. Upcall 1 is scheduled with the status when
+//!   done.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let fault_dump = components::process_fault_dump::ProcessFaultDumpComponent::new(
+//!     board_kernel,
+//!     capsules_extra::process_fault_dump::DRIVER_NUM,
+//!     nonvolatile_storage,
+//!     0x60000, // Start of the reserved region
+//!     0x2000,  // Length of the reserved region
+//!     &FAULT_POLICY,
+//!     &TRUSTED_APPS, // `ShortId`s of the apps allowed to use the driver
+//! )
+//! .finalize(components::process_fault_dump_component_static!(
+//!     NonvolatileToPages<'static, nrf52840::nvmc::Nvmc>
+//! ));
+//! // Use `fault_dump` as the fault policy when loading processes.
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::fmt;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::process::{self, Process, ProcessFaultDumps, ProcessFaultDumpsClient};
+use kernel::process::{ProcessFaultPolicy, ShortId};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::{ErrorCode, ProcessId};
+use tock_fault_dump::parse::{parse_fault_dump, parse_fault_dump_header, FaultDump};
+use tock_fault_dump::types::{
+    FaultAction, GrantUsage, MemoryLayout, MpuRegion, ProcessInfo, ADDRESS_UNKNOWN, HEADER_LEN,
+    PERMISSION_EXECUTE, PERMISSION_READ, PERMISSION_WRITE,
+};
+use tock_fault_dump::write::{set_sequence, FaultDumpWriter};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ProcessFaultDump as usize;
+
+/// Size of a record, and of each slot in the storage region.
+pub const DUMP_LEN: usize = 1024;
+
+/// The maximum number of memory regions recorded per process.
+const MAX_MPU_REGIONS: usize = 8;
+
+/// IDs for subscribed upcalls.
+mod upcall {
+    /// Read done callback.
+    pub const READ_DONE: usize = 0;
+    /// Erase done callback.
+    pub const CLEAR_DONE: usize = 1;
+    /// Number of upcalls.
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Buffer a fault dump is read into.
+    pub const READ: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Summary of a record passed to the kernel client, such as the process
+/// console.
+struct DumpSummary<'b>(FaultDump<'b>);
+
+impl fmt::Display for DumpSummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dump = &self.0;
+        write!(f, "Fault dump #{}", dump.sequence())?;
+        if let Some(info) = dump.process() {
+            write!(
+                f,
+                ": process {}, {:?} after {} restarts, {} syscalls",
+                info.name, info.fault_action, info.restart_count, info.syscall_count,
+            )?;
+        }
+        if let Some(memory) = dump.memory() {
+            write!(
+                f,
+                "\r\n Flash {:#010x}-{:#010x}, RAM {:#010x}-{:#010x}, app break {:#010x}, stack {:#010x}-{:#010x}",
+                memory.flash_start,
+                memory.flash_end,
+                memory.sram_start,
+                memory.sram_end,
+                memory.sram_app_brk,
+                memory.sram_stack_bottom,
+                memory.sram_stack_top,
+            )?;
+        }
+        if let Some(grants) = dump.grants() {
+            write!(
+                f,
+                "\r\n Grants: {} allocated ({:#010x})",
+                grants.allocated_grants, grants.allocated_grants_bitmap,
+            )?;
+        }
+        let mpu_regions = dump.mpu_regions().map_or(0, |regions| regions.len());
+        let stored_state = dump.stored_state().map_or(0, |state| state.len());
+        let stack = dump.stack().map_or(0, |(_, contents)| contents.len());
+        write!(
+            f,
+            "\r\n {} MPU regions, {} bytes of stored state, {} bytes of stack",
+            mpu_regions, stored_state, stack,
+        )
+    }
+}
+
+/// Who requested a read or erase operation.
+#[derive(Clone, Copy, PartialEq)]
+enum User {
+    Kernel,
+    App(ProcessId),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Reading the header of `slot` while looking for stored records after
+    /// boot.
+    Scanning {
+        slot: usize,
+    },
+    /// Writing the captured record with the sequence number `sequence`.
+    Writing {
+        sequence: u32,
+    },
+    /// Reading the record with the sequence number `sequence`.
+    Reading {
+        user: User,
+        sequence: u32,
+    },
+    /// Erasing the header of `slot`.
+    Clearing {
+        user: User,
+        slot: usize,
+    },
+}
+
+pub struct ProcessFaultDump<'a, S: NonvolatileStorage<'a>> {
+    storage: &'a S,
+    fault_policy: &'a dyn ProcessFaultPolicy,
+    apps: Grant<
+        (),
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<0>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// Processes allowed to use the userspace driver.
+    trusted_apps: &'a [ShortId],
+    /// Start address of the reserved storage region.
+    region_start: usize,
+    /// Number of record slots in the reserved storage region.
+    slot_count: usize,
+    /// Buffer a record is captured in when a process faults.
+    dump_buffer: TakeCell<'static, [u8]>,
+    /// The length of the captured record in `dump_buffer`, if it has not been
+    /// written yet.
+    dump_len: OptionalCell<usize>,
+    /// Buffer for reading and erasing records.
+    io_buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    /// Sequence number of the next record.
+    next_sequence: Cell<u32>,
+    /// Number of stored records, which have the sequence numbers right before
+    /// `next_sequence`.
+    count: Cell<usize>,
+    client: OptionalCell<&'a dyn ProcessFaultDumpsClient>,
+}
+
+impl<'a, S: NonvolatileStorage<'a>> ProcessFaultDump<'a, S> {
+    pub fn new(
+        storage: &'a S,
+        fault_policy: &'a dyn ProcessFaultPolicy,
+        grant: Grant<
+            (),
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<0>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        trusted_apps: &'a [ShortId],
+        region_start: usize,
+        region_len: usize,
+        dump_buffer: &'static mut [u8; DUMP_LEN],
+        io_buffer: &'static mut [u8; DUMP_LEN],
+    ) -> ProcessFaultDump<'a, S> {
+        ProcessFaultDump {
+            storage,
+            fault_policy,
+            apps: grant,
+            trusted_apps,
+            region_start,
+            slot_count: region_len / DUMP_LEN,
+            dump_buffer: TakeCell::new(dump_buffer),
+            dump_len: OptionalCell::empty(),
+            io_buffer: TakeCell::new(io_buffer),
+            state: Cell::new(State::Idle),
+            next_sequence: Cell::new(0),
+            count: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Look for the records stored in the region. This must be called once
+    /// before the records can be read.
+    pub fn init(&self) -> Result<(), ErrorCode> {
+        if self.slot_count == 0 {
+            return Err(ErrorCode::SIZE);
+        }
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.next_sequence.set(0);
+        self.count.set(0);
+        self.scan_slot(0)
+    }
+
+    fn slot_address(&self, slot: usize) -> usize {
+        self.region_start + slot * DUMP_LEN
+    }
+
+    fn sequence_slot(&self, sequence: u32) -> usize {
+        sequence as usize % self.slot_count
+    }
+
+    fn scan_slot(&self, slot: usize) -> Result<(), ErrorCode> {
+        let buffer = self.io_buffer.take().ok_or(ErrorCode::NOMEM)?;
+        self.state.set(State::Scanning { slot });
+        self.storage
+            .read(buffer, self.slot_address(slot), HEADER_LEN)
+            .inspect_err(|_| self.state.set(State::Idle))
+    }
+
+    fn clear_slot(&self, user: User, slot: usize) -> Result<(), ErrorCode> {
+        let buffer = self.io_buffer.take().ok_or(ErrorCode::NOMEM)?;
+        buffer[..HEADER_LEN].fill(0);
+        self.state.set(State::Clearing { user, slot });
+        self.storage
+            .write(buffer, self.slot_address(slot), HEADER_LEN)
+            .inspect_err(|_| self.state.set(State::Idle))
+    }
+
+    fn start_read(&self, user: User, index: usize) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if index >= self.count.get() {
+            return Err(ErrorCode::INVAL);
+        }
+        let buffer = self.io_buffer.take().ok_or(ErrorCode::NOMEM)?;
+        let sequence = self.next_sequence.get().wrapping_sub(1 + index as u32);
+        self.state.set(State::Reading { user, sequence });
+        self.storage
+            .read(
+                buffer,
+                self.slot_address(self.sequence_slot(sequence)),
+                DUMP_LEN,
+            )
+            .inspect_err(|_| self.state.set(State::Idle))
+    }
+
+    fn start_clear(&self, user: User) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.clear_slot(user, 0)
+    }
+
+    /// Write the captured record, if there is one and the storage is idle.
+    fn write_pending_dump(&self) {
+        if self.state.get() != State::Idle {
+            return;
+        }
+        let Some(len) = self.dump_len.take() else {
+            return;
+        };
+        self.dump_buffer.take().map(|buffer| {
+            let sequence = self.next_sequence.get();
+            set_sequence(buffer, sequence);
+            self.state.set(State::Writing { sequence });
+            let address = self.slot_address(self.sequence_slot(sequence));
+            if self.storage.write(buffer, address, len).is_err() {
+                self.state.set(State::Idle);
+            }
+        });
+    }
+
+    /// Capture the state of `process` in the record buffer.
+    fn capture(&self, process: &dyn Process, action: process::FaultAction) {
+        if self.slot_count == 0 || self.dump_len.is_some() {
+            return;
+        }
+        self.dump_buffer.map(|buffer| {
+            // The sequence number is set when the record is written.
+            let Some(mut writer) = FaultDumpWriter::new(buffer, 0) else {
+                return;
+            };
+
+            writer.process(&ProcessInfo {
+                short_id: match process.short_app_id() {
+                    ShortId::LocallyUnique => 0,
+                    ShortId::Fixed(id) => id.get(),
+                },
+                restart_count: process.get_restart_count() as u32,
+                fault_action: match action {
+                    process::FaultAction::Panic => FaultAction::Panic,
+                    process::FaultAction::Restart => FaultAction::Restart,
+                    process::FaultAction::Stop => FaultAction::Stop,
+                },
+                syscall_count: process.debug_syscall_count() as u32,
+                dropped_upcall_count: process.debug_dropped_upcall_count() as u32,
+                timeslice_expiration_count: process.debug_timeslice_expiration_count() as u32,
+                name: process.get_process_name(),
+            });
+
+            let addresses = process.get_addresses();
+            writer.memory(&MemoryLayout {
+                flash_start: addresses.flash_start as u32,
+                flash_end: addresses.flash_end as u32,
+                sram_start: addresses.sram_start as u32,
+                sram_app_brk: addresses.sram_app_brk as u32,
+                sram_grant_start: addresses.sram_grant_start as u32,
+                sram_end: addresses.sram_end as u32,
+                sram_heap_start: addresses
+                    .sram_heap_start
+                    .map_or(ADDRESS_UNKNOWN, |a| a as u32),
+                sram_stack_top: addresses
+                    .sram_stack_top
+                    .map_or(ADDRESS_UNKNOWN, |a| a as u32),
+                sram_stack_bottom: addresses
+                    .sram_stack_bottom
+                    .map_or(ADDRESS_UNKNOWN, |a| a as u32),
+            });
+
+            let sizes = process.get_sizes();
+            writer.grants(&GrantUsage {
+                allocated_grants: process.grant_allocated_count().unwrap_or(0) as u32,
+                allocated_grants_bitmap: (0..32).fold(0, |bitmap, grant_num| {
+                    if process.grant_is_allocated(grant_num) == Some(true) {
+                        bitmap | (1 << grant_num)
+                    } else {
+                        bitmap
+                    }
+                }),
+                grant_pointers_bytes: sizes.grant_pointers as u32,
+                upcall_list_bytes: sizes.upcall_list as u32,
+                process_control_block_bytes: sizes.process_control_block as u32,
+            });
+
+            // The regions the kernel configures the MPU with for the process:
+            // its flash, its RAM up to the app break, and any writeable flash
+            // regions from its TBF header.
+            let mut regions = [MpuRegion {
+                start: 0,
+                size: 0,
+                permissions: 0,
+            }; MAX_MPU_REGIONS];
+            regions[0] = MpuRegion {
+                start: addresses.flash_start as u32,
+                size: (addresses.flash_end - addresses.flash_start) as u32,
+                permissions: PERMISSION_READ | PERMISSION_EXECUTE,
+            };
+            regions[1] = MpuRegion {
+                start: addresses.sram_start as u32,
+                size: (addresses.sram_app_brk - addresses.sram_start) as u32,
+                permissions: PERMISSION_READ | PERMISSION_WRITE,
+            };
+            let mut region_count = 2;
+            for i in 0..process.number_writeable_flash_regions() {
+                if region_count == MAX_MPU_REGIONS {
+                    break;
+                }
+                let (offset, size) = process.get_writeable_flash_region(i);
+                regions[region_count] = MpuRegion {
+                    start: (addresses.flash_start + offset) as u32,
+                    size: size as u32,
+                    permissions: PERMISSION_READ | PERMISSION_WRITE,
+                };
+                region_count += 1;
+            }
+            writer.mpu_regions(&regions[..region_count]);
+
+            writer.stored_state(|out| process.get_stored_state(out).ok());
+
+            // Copy as much of the stack as fits, starting at the lowest stack
+            // pointer the kernel has seen.
+            if let Some(bottom) = addresses.sram_stack_bottom {
+                let top = addresses.sram_stack_top.unwrap_or(addresses.sram_app_brk);
+                writer.stack(bottom as u32, |out| {
+                    let len = cmp::min(top.saturating_sub(bottom), out.len());
+                    let stack = process
+                        .build_readonly_process_buffer(bottom as *const u8, len)
+                        .ok()?;
+                    stack
+                        .enter(|stack| stack.copy_to_slice_or_err(&mut out[..len]))
+                        .ok()?
+                        .ok()?;
+                    Some(len)
+                });
+            }
+
+            self.dump_len.set(writer.finish());
+        });
+        self.write_pending_dump();
+    }
+
+    /// Deliver the result of a read to whoever requested it.
+    fn read_complete(&self, user: User, result: Result<(u32, &[u8]), ErrorCode>) {
+        match user {
+            User::Kernel => {
+                // The record was validated when it was read.
+                let summary = result.and_then(|(_, record)| {
+                    parse_fault_dump(record)
+                        .map(DumpSummary)
+                        .map_err(|_| ErrorCode::FAIL)
+                });
+                self.client.map(|client| match summary {
+                    Ok(ref summary) => client.dump_read(Ok(summary)),
+                    Err(e) => client.dump_read(Err(e)),
+                });
+            }
+            User::App(processid) => {
+                let _ = self.apps.enter(processid, |_, kernel_data| {
+                    let (sequence, len) = match result {
+                        Ok((sequence, record)) => {
+                            let _ = kernel_data
+                                .get_readwrite_processbuffer(rw_allow::READ)
+                                .and_then(|read| {
+                                    read.mut_enter(|dest| {
+                                        let len = cmp::min(dest.len(), record.len());
+                                        dest[..len].copy_from_slice(&record[..len]);
+                                    })
+                                });
+                            (sequence as usize, record.len())
+                        }
+                        Err(_) => (0, 0),
+                    };
+                    let _ = kernel_data.schedule_upcall(
+                        upcall::READ_DONE,
+                        (
+                            kernel::errorcode::into_statuscode(result.map(|_| ())),
+                            len,
+                            sequence,
+                        ),
+                    );
+                });
+            }
+        }
+    }
+
+    fn clear_complete(&self, user: User, result: Result<(), ErrorCode>) {
+        match user {
+            User::Kernel => {
+                self.client.map(|client| client.dumps_cleared(result));
+            }
+            User::App(processid) => {
+                let _ = self.apps.enter(processid, |_, kernel_data| {
+                    let _ = kernel_data.schedule_upcall(
+                        upcall::CLEAR_DONE,
+                        (kernel::errorcode::into_statuscode(result), 0, 0),
+                    );
+                });
+            }
+        }
+    }
+}
+
+impl<'a, S: NonvolatileStorage<'a>> ProcessFaultPolicy for ProcessFaultDump<'a, S> {
+    fn action(&self, process: &dyn Process) -> process::FaultAction {
+        let action = self.fault_policy.action(process);
+        self.capture(process, action);
+        action
+    }
+}
+
+impl<'a, S: NonvolatileStorage<'a>> NonvolatileStorageClient for ProcessFaultDump<'a, S> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        match self.state.get() {
+            State::Scanning { slot } => {
+                if let Ok((sequence, len)) = parse_fault_dump_header(&buffer[..length]) {
+                    if len <= DUMP_LEN {
+                        // Slots are used round robin, so the stored records
+                        // are the ones right before the newest one.
+                        if self.count.get() == 0
+                            || sequence.wrapping_sub(self.next_sequence.get()) < u32::MAX / 2
+                        {
+                            self.next_sequence.set(sequence.wrapping_add(1));
+                        }
+                        self.count.set(self.count.get() + 1);
+                    }
+                }
+                self.io_buffer.replace(buffer);
+                self.state.set(State::Idle);
+                if slot + 1 < self.slot_count {
+                    let _ = self.scan_slot(slot + 1);
+                }
+            }
+            State::Reading { user, sequence } => {
+                self.state.set(State::Idle);
+                let result = match parse_fault_dump(&buffer[..length]) {
+                    Ok(dump) if dump.sequence() == sequence => {
+                        // The record is valid, so its length is in the header.
+                        let len = parse_fault_dump_header(buffer).map_or(0, |(_, len)| len);
+                        Ok((sequence, &buffer[..len]))
+                    }
+                    _ => Err(ErrorCode::FAIL),
+                };
+                self.read_complete(user, result);
+                self.io_buffer.replace(buffer);
+            }
+            _ => {
+                self.io_buffer.replace(buffer);
+                self.state.set(State::Idle);
+            }
+        }
+        self.write_pending_dump();
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        match self.state.get() {
+            State::Writing { sequence } => {
+                self.dump_buffer.replace(buffer);
+                self.state.set(State::Idle);
+                self.next_sequence.set(sequence.wrapping_add(1));
+                self.count
+                    .set(cmp::min(self.count.get() + 1, self.slot_count));
+            }
+            State::Clearing { user, slot } => {
+                self.io_buffer.replace(buffer);
+                self.state.set(State::Idle);
+                if slot + 1 < self.slot_count {
+                    if let Err(e) = self.clear_slot(user, slot + 1) {
+                        self.clear_complete(user, Err(e));
+                    }
+                } else {
+                    self.count.set(0);
+                    self.clear_complete(user, Ok(()));
+                }
+            }
+            _ => {
+                self.io_buffer.replace(buffer);
+                self.state.set(State::Idle);
+            }
+        }
+        self.write_pending_dump();
+    }
+}
+
+impl<'a, S: NonvolatileStorage<'a>> ProcessFaultDumps<'a> for ProcessFaultDump<'a, S> {
+    fn set_client(&self, client: &'a dyn ProcessFaultDumpsClient) {
+        self.client.set(client);
+    }
+
+    fn dump_count(&self) -> usize {
+        self.count.get()
+    }
+
+    fn read_dump(&self, index: usize) -> Result<(), ErrorCode> {
+        self.start_read(User::Kernel, index)
+    }
+
+    fn clear_dumps(&self) -> Result<(), ErrorCode> {
+        self.start_clear(User::Kernel)
+    }
+}
+
+impl<'a, S: NonvolatileStorage<'a>> SyscallDriver for ProcessFaultDump<'a, S> {
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        // Only trusted processes may read the fault dumps of other processes.
+        // A `LocallyUnique` process is never trusted, even if the board lists
+        // `ShortId::LocallyUnique` as a trusted app.
+        let trusted = match processid.short_app_id() {
+            short_id @ ShortId::Fixed(_) => self.trusted_apps.contains(&short_id),
+            ShortId::LocallyUnique => false,
+        };
+        if !trusted {
+            return CommandReturn::failure(ErrorCode::NODEVICE);
+        }
+
+        match command_num {
+            // Driver existence check
+            0 => CommandReturn::success(),
+
+            1 => CommandReturn::success_u32(self.count.get() as u32),
+
+            2 => self.start_read(User::App(processid), data1).into(),
+
+            3 => self.start_clear(User::App(processid)).into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -34,9 +34,21 @@
 //!     stopped=3, faulted=4, terminated=5).
 //! - 6: Change the process state. `data1` is the process ID, and `data2` is the
 //!   new state.(1=start, 2=stop, 3=fault, 4=terminate, 5=boot).
+//! - 7: Fill the allow RW buffer with the grants allocated by the process
+//!   specified by the process ID in `data1`. Each grant is described by five
+//!   `u32` values:
+//!   - The driver number.
+//!   - The number of bytes allocated.
+//!   - The number of upcall slots.
+//!   - The number of read-only allow slots.
+//!   - The number of read-write allow slots.
+//!
+//!   Returns the number of grants the process has allocated, which may be more
+//!   than fit in the buffer.
 
 use kernel::capabilities::{ProcessManagementCapability, ProcessStartCapability};
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::introspection::KernelInfo;
 use kernel::process;
 use kernel::processbuffer::WriteableProcessBuffer;
 use kernel::syscall::{CommandReturn, SyscallDriver};
@@ -280,6 +292,61 @@ impl<C: ProcessManagementCapability + ProcessStartCapability> SyscallDriver for
                 }
             }
 
+            7 => {
+                let mut target = None;
+                self.kernel
+                    .process_each_capability(&self.capability, |process| {
+                        if process.processid().id() == data1 {
+                            target = Some(process.processid());
+                        }
+                    });
+                let Some(target) = target else {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                };
+
+                let info = KernelInfo::new(self.kernel);
+                let (_, grants_total) = info.number_app_grant_uses(target, &self.capability);
+                self.apps
+                    .enter(process_id, |_app, kernel_data| {
+                        kernel_data
+                            .get_readwrite_processbuffer(rw_allow::INFO)
+                            .and_then(|shared| {
+                                shared.mut_enter(|s| {
+                                    let mut chunks = s.chunks(5 * size_of::<u32>());
+                                    let mut count = 0;
+                                    for grant_num in 0..grants_total {
+                                        if let Some(grant) = info.app_grant_allocation(
+                                            target,
+                                            grant_num,
+                                            &self.capability,
+                                        ) {
+                                            if let Some(chunk) = chunks.next() {
+                                                let values = [
+                                                    grant.driver_num,
+                                                    grant.size,
+                                                    grant.upcalls,
+                                                    grant.allow_ro,
+                                                    grant.allow_rw,
+                                                ];
+                                                for (dest, value) in
+                                                    chunk.chunks(size_of::<u32>()).zip(values)
+                                                {
+                                                    let _ = dest.copy_from_slice_or_err(
+                                                        &(value as u32).to_le_bytes(),
+                                                    );
+                                                }
+                                            }
+                                            count += 1;
+                                        }
+                                    }
+                                    CommandReturn::success_u32(count)
+                                })
+                            })
+                            .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+                    })
+                    .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+            }
+
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -0,0 +1,324 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Watchdog for userspace processes.
+//!
+//! The kernel tickles the hardware watchdog from its main loop, so a process
+//! that hangs does not trip it. With this capsule, critical processes register
+//! with a timeout and must check in through this driver before the timeout
+//! expires. The capsule wraps the board's [`WatchDog`] and only tickles it
+//! while all registered processes are live.
+//!
+//! When a process misses its check-in, the event is written to the debug log
+//! and the configured [`MissedCheckInAction`] is taken:
+//!
+//! - `Restart`: The process is restarted. This also unregisters it, so the
+//!   hardware watchdog is tickled again until the process registers anew.
+//! - `Reset`: The hardware watchdog is no longer tickled, so it resets the
+//!   board.
+//! - `Log`: Nothing else is done. The hardware watchdog is not tickled until
+//!   the process checks in again, so it eventually resets the board unless the
+//!   process recovers first.
+//!
+//! Processes are checked every `check_interval_ms`, so a missed check-in is
+//! noticed up to that long after the timeout.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### Commands
+//!
+//! - 0: Check driver exists.
+//! - 1: Register the process with a timeout of `data1` milliseconds, or change
+//!   the timeout of a registered process. This counts as a check-in.
+//! - 2: Check in. Returns `RESERVE` if the process is not registered.
+//! - 3: Unregister the process.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let process_watchdog = components::process_watchdog::ProcessWatchdogComponent::new(
+//!     board_kernel,
+//!     capsules_extra::process_watchdog::DRIVER_NUM,
+//!     mux_alarm,
+//!     &base_peripherals.wdt,
+//!     100, // Check processes every 100 ms
+//!     capsules_extra::process_watchdog::MissedCheckInAction::Restart,
+//! )
+//! .finalize(components::process_watchdog_component_static!(
+//!     nrf52840::rtc::Rtc,
+//!     nrf52840::wdt::Wdt,
+//! ));
+//! // Return `process_watchdog` from `KernelResources::watchdog()`.
+//! ```
+
+use core::cell::Cell;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::debug;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::platform::watchdog::WatchDog;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, Kernel, ProcessId};
+
+/// Syscall driver number.
+use capsules_core::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ProcessWatchdog as usize;
+
+/// What to do when a registered process misses its check-in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MissedCheckInAction {
+    /// Restart the process.
+    Restart,
+    /// Stop tickling the hardware watchdog so it resets the board.
+    Reset,
+    /// Only record the event in the debug log.
+    Log,
+}
+
+pub struct App<T: Ticks> {
+    /// The time the process has to check in, if it is registered.
+    timeout: Option<T>,
+    last_check_in: T,
+    /// Whether the missed check-in has already been handled.
+    missed: bool,
+}
+
+impl<T: Ticks> Default for App<T> {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            last_check_in: T::from(0),
+            missed: false,
+        }
+    }
+}
+
+pub struct ProcessWatchdog<'a, A: Alarm<'a>, W: WatchDog + 'a, C: ProcessManagementCapability> {
+    alarm: &'a A,
+    watchdog: &'a W,
+    apps: Grant<App<A::Ticks>, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>,
+    kernel: &'static Kernel,
+    capability: C,
+    check_interval_ms: u32,
+    action: MissedCheckInAction,
+    /// Whether all registered processes checked in on time.
+    live: Cell<bool>,
+    /// Set once a missed check-in with the `Reset` action happened.
+    resetting: Cell<bool>,
+    /// Whether the hardware watchdog was suspended by us.
+    suspended: Cell<bool>,
+    /// Whether processes are periodically checked.
+    checking: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> ProcessWatchdog<'a, A, W, C> {
+    pub fn new(
+        alarm: &'a A,
+        watchdog: &'a W,
+        grant: Grant<App<A::Ticks>, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>,
+        kernel: &'static Kernel,
+        capability: C,
+        check_interval_ms: u32,
+        action: MissedCheckInAction,
+    ) -> Self {
+        Self {
+            alarm,
+            watchdog,
+            apps: grant,
+            kernel,
+            capability,
+            check_interval_ms,
+            action,
+            live: Cell::new(true),
+            resetting: Cell::new(false),
+            suspended: Cell::new(false),
+            checking: Cell::new(false),
+        }
+    }
+
+    fn start_checking(&self) {
+        if !self.checking.get() {
+            self.checking.set(true);
+            self.alarm.set_alarm(
+                self.alarm.now(),
+                self.alarm.ticks_from_ms(self.check_interval_ms),
+            );
+        }
+    }
+
+    /// Restart all processes that missed their check-in.
+    fn restart_missed(&self) {
+        // Restarting a process frees its grant, so each process is only
+        // found once.
+        while let Some(processid) = self.apps.iter().find_map(|app| {
+            let processid = app.processid();
+            app.enter(|app, _| app.missed).then_some(processid)
+        }) {
+            self.kernel
+                .process_each_capability(&self.capability, |process| {
+                    if process.processid() == processid {
+                        process.try_restart(None);
+                    }
+                });
+            // Make sure a process that could not be restarted does not keep
+            // us looping.
+            let _ = self.apps.enter(processid, |app, _| {
+                app.timeout = None;
+                app.missed = false;
+            });
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> AlarmClient
+    for ProcessWatchdog<'a, A, W, C>
+{
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        let mut live = true;
+        let mut registered = false;
+        let mut newly_missed = false;
+        self.apps.each(|processid, app, _| {
+            if let Some(timeout) = app.timeout {
+                registered = true;
+                let deadline = app.last_check_in.wrapping_add(timeout);
+                if !now.within_range(app.last_check_in, deadline) {
+                    live = false;
+                    if !app.missed {
+                        app.missed = true;
+                        newly_missed = true;
+                        debug!(
+                            "Process {:?} missed its watchdog check-in ({:?}).",
+                            processid, self.action
+                        );
+                    }
+                }
+            }
+        });
+
+        if newly_missed {
+            match self.action {
+                MissedCheckInAction::Restart => {
+                    self.restart_missed();
+                    // The restarted processes are no longer registered.
+                    live = true;
+                }
+                MissedCheckInAction::Reset => self.resetting.set(true),
+                MissedCheckInAction::Log => {}
+            }
+        }
+        self.live.set(live && !self.resetting.get());
+
+        if registered {
+            self.alarm
+                .set_alarm(now, self.alarm.ticks_from_ms(self.check_interval_ms));
+        } else {
+            self.checking.set(false);
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> WatchDog
+    for ProcessWatchdog<'a, A, W, C>
+{
+    fn setup(&self) {
+        self.watchdog.setup();
+    }
+
+    fn tickle(&self) {
+        if self.live.get() {
+            self.watchdog.tickle();
+        }
+    }
+
+    fn suspend(&self) {
+        // Keep the hardware watchdog running while sleeping if a process is
+        // not live, so it still resets the board.
+        if self.live.get() {
+            self.suspended.set(true);
+            self.watchdog.suspend();
+        }
+    }
+
+    fn resume(&self) {
+        if self.suspended.take() {
+            self.watchdog.resume();
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, W: WatchDog, C: ProcessManagementCapability> SyscallDriver
+    for ProcessWatchdog<'a, A, W, C>
+{
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            // Register
+            1 => {
+                let Ok(timeout_ms) = u32::try_from(data1) else {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                };
+                if timeout_ms == 0 {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                let now = self.alarm.now();
+                let result = self
+                    .apps
+                    .enter(processid, |app, _| {
+                        app.timeout = Some(self.alarm.ticks_from_ms(timeout_ms));
+                        app.last_check_in = now;
+                        app.missed = false;
+                    })
+                    .map_err(ErrorCode::from);
+                if result.is_ok() {
+                    self.start_checking();
+                }
+                result.into()
+            }
+
+            // Check in
+            2 => {
+                let now = self.alarm.now();
+                self.apps
+                    .enter(processid, |app, _| {
+                        if app.timeout.is_some() {
+                            app.last_check_in = now;
+                            app.missed = false;
+                            Ok(())
+                        } else {
+                            Err(ErrorCode::RESERVE)
+                        }
+                    })
+                    .unwrap_or_else(|err| Err(err.into()))
+                    .into()
+            }
+
+            // Unregister
+            3 => self
+                .apps
+                .enter(processid, |app, _| {
+                    app.timeout = None;
+                    app.missed = false;
+                })
+                .map_err(ErrorCode::from)
+                .into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
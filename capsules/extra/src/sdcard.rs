@@ -1300,63 +1300,68 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
         }
     }
 
+    /// Take back the buffer passed to `read_blocks()` or `write_blocks()` after
+    /// the operation failed with an error callback.
+    pub fn take_buffer(&self) -> Option<&'static mut [u8]> {
+        self.client_buffer.take()
+    }
+
+    /// Take the transmit and receive buffers for a block operation, checking
+    /// that the card is ready.
+    fn take_spi_buffers(&self) -> Result<(&'static mut [u8], &'static mut [u8]), ErrorCode> {
+        // only if initialized and installed
+        if !self.is_installed() {
+            // sd card not installed
+            return Err(ErrorCode::UNINSTALLED);
+        }
+        if !self.is_initialized() {
+            // sd card not initialized
+            return Err(ErrorCode::RESERVE);
+        }
+        match (self.txbuffer.take(), self.rxbuffer.take()) {
+            (Some(txbuffer), Some(rxbuffer)) => Ok((txbuffer, rxbuffer)),
+            (txbuffer, rxbuffer) => {
+                txbuffer.map(|buffer| self.txbuffer.replace(buffer));
+                rxbuffer.map(|buffer| self.rxbuffer.replace(buffer));
+                Err(ErrorCode::NOMEM)
+            }
+        }
+    }
+
+    /// Convert block address to byte address for non-block access cards
+    fn card_address(&self, sector: u32) -> u32 {
+        if self.card_type.get() != SDCardType::SDv2BlockAddressable {
+            sector * 512
+        } else {
+            sector
+        }
+    }
+
     pub fn read_blocks(
         &self,
         buffer: &'static mut [u8],
         sector: u32,
         count: u32,
-    ) -> Result<(), ErrorCode> {
-        // only if initialized and installed
-        if self.is_installed() {
-            if self.is_initialized() {
-                self.txbuffer
-                    .take()
-                    .map_or(Err(ErrorCode::NOMEM), |txbuffer| {
-                        self.rxbuffer
-                            .take()
-                            .map_or(Err(ErrorCode::NOMEM), move |rxbuffer| {
-                                // save the user buffer for later
-                                self.client_buffer.replace(buffer);
-                                self.client_offset.set(0);
-
-                                // convert block address to byte address for non-block
-                                //  access cards
-                                let mut address = sector;
-                                if self.card_type.get() != SDCardType::SDv2BlockAddressable {
-                                    address *= 512;
-                                }
-
-                                self.state.set(SpiState::StartReadBlocks { count });
-                                if count == 1 {
-                                    self.send_command(
-                                        SDCmd::CMD17_ReadSingle,
-                                        address,
-                                        txbuffer,
-                                        rxbuffer,
-                                        10,
-                                    );
-                                } else {
-                                    self.send_command(
-                                        SDCmd::CMD18_ReadMultiple,
-                                        address,
-                                        txbuffer,
-                                        rxbuffer,
-                                        10,
-                                    );
-                                }
-
-                                // command started successfully
-                                Ok(())
-                            })
-                    })
-            } else {
-                // sd card not initialized
-                Err(ErrorCode::RESERVE)
-            }
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let (txbuffer, rxbuffer) = match self.take_spi_buffers() {
+            Ok(buffers) => buffers,
+            Err(e) => return Err((e, buffer)),
+        };
+
+        // save the user buffer for later
+        self.client_buffer.replace(buffer);
+        self.client_offset.set(0);
+
+        let address = self.card_address(sector);
+        self.state.set(SpiState::StartReadBlocks { count });
+        if count == 1 {
+            self.send_command(SDCmd::CMD17_ReadSingle, address, txbuffer, rxbuffer, 10);
         } else {
-            // sd card not installed
-            Err(ErrorCode::UNINSTALLED)
+            self.send_command(SDCmd::CMD18_ReadMultiple, address, txbuffer, rxbuffer, 10);
         }
+
+        // command started successfully
+        Ok(())
     }
 
     pub fn write_blocks(
@@ -1364,53 +1369,26 @@ impl<'a, A: hil::time::Alarm<'a>> SDCard<'a, A> {
         buffer: &'static mut [u8],
         sector: u32,
         count: u32,
-    ) -> Result<(), ErrorCode> {
-        // only if initialized and installed
-        if self.is_installed() {
-            if self.is_initialized() {
-                self.txbuffer
-                    .take()
-                    .map_or(Err(ErrorCode::NOMEM), |txbuffer| {
-                        self.rxbuffer
-                            .take()
-                            .map_or(Err(ErrorCode::NOMEM), move |rxbuffer| {
-                                // save the user buffer for later
-                                self.client_buffer.replace(buffer);
-                                self.client_offset.set(0);
-
-                                // convert block address to byte address for non-block
-                                //  access cards
-                                let mut address = sector;
-                                if self.card_type.get() != SDCardType::SDv2BlockAddressable {
-                                    address *= 512;
-                                }
-
-                                self.state.set(SpiState::StartWriteBlocks { count });
-                                if count == 1 {
-                                    self.send_command(
-                                        SDCmd::CMD24_WriteSingle,
-                                        address,
-                                        txbuffer,
-                                        rxbuffer,
-                                        10,
-                                    );
-
-                                    // command started successfully
-                                    Ok(())
-                                } else {
-                                    // can't write multiple blocks yet
-                                    Err(ErrorCode::NOSUPPORT)
-                                }
-                            })
-                    })
-            } else {
-                // sd card not initialized
-                Err(ErrorCode::RESERVE)
-            }
-        } else {
-            // sd card not installed
-            Err(ErrorCode::UNINSTALLED)
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if count != 1 {
+            // can't write multiple blocks yet
+            return Err((ErrorCode::NOSUPPORT, buffer));
         }
+        let (txbuffer, rxbuffer) = match self.take_spi_buffers() {
+            Ok(buffers) => buffers,
+            Err(e) => return Err((e, buffer)),
+        };
+
+        // save the user buffer for later
+        self.client_buffer.replace(buffer);
+        self.client_offset.set(0);
+
+        let address = self.card_address(sector);
+        self.state.set(SpiState::StartWriteBlocks { count });
+        self.send_command(SDCmd::CMD24_WriteSingle, address, txbuffer, rxbuffer, 10);
+
+        // command started successfully
+        Ok(())
     }
 }
 
@@ -1580,6 +1558,10 @@ impl<'a, A: hil::time::Alarm<'a>> SDCardClient for SDCardDriver<'a, A> {
     }
 
     fn error(&self, error: u32) {
+        if let Some(buffer) = self.sdcard.take_buffer() {
+            self.kernel_buf.replace(buffer);
+        }
+
         self.current_process.map(|process_id| {
             let _ = self.grants.enter(process_id, |_app, kernel_data| {
                 let _ = kernel_data.schedule_upcall(0, (4, error as usize, 0));
@@ -1630,8 +1612,12 @@ impl<'a, A: hil::time::Alarm<'a>> SyscallDriver for SDCardDriver<'a, A> {
             // read_block
             3 => self.kernel_buf.take().map_or(
                 CommandReturn::failure(ErrorCode::BUSY),
-                |kernel_buf| {
-                    CommandReturn::from(self.sdcard.read_blocks(kernel_buf, data as u32, 1))
+                |kernel_buf| match self.sdcard.read_blocks(kernel_buf, data as u32, 1) {
+                    Ok(()) => CommandReturn::success(),
+                    Err((e, kernel_buf)) => {
+                        self.kernel_buf.replace(kernel_buf);
+                        CommandReturn::failure(e)
+                    }
                 },
             ),
 
@@ -1659,7 +1645,12 @@ impl<'a, A: hil::time::Alarm<'a>> SyscallDriver for SDCardDriver<'a, A> {
                                             }
 
                                             // begin writing
-                                            self.sdcard.write_blocks(kernel_buf, data as u32, 1)
+                                            self.sdcard
+                                                .write_blocks(kernel_buf, data as u32, 1)
+                                                .map_err(|(e, kernel_buf)| {
+                                                    self.kernel_buf.replace(kernel_buf);
+                                                    e
+                                                })
                                         },
                                     )
                                 })
@@ -0,0 +1,299 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Block device interface for SD cards.
+//!
+//! `SDCardBlockDevice` provides `hil::block_device::BlockDevice` on top of
+//! `sdcard::SDCard`. The card is initialized when it is first accessed, so
+//! `block_count()` is 0 until then. SD cards manage erasing and write
+//! buffering themselves, so erasing and flushing complete immediately.
+//!
+//! `SDCardBlockDevice` is the client of the `SDCard`, so it cannot be used
+//! together with `sdcard::SDCardDriver`. `fat::FatDriver` uses the card
+//! through this interface.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let sdcard_block_device = components::block_device::SDCardBlockDeviceComponent::new(sdcard)
+//!     .finalize(components::sdcard_block_device_component_static!(
+//!         VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>
+//!     ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil;
+use kernel::hil::block_device::{BlockDevice, BlockDeviceClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use crate::sdcard::{SDCard, SDCardClient};
+
+/// Size of an SD card block.
+pub const BLOCK_SIZE: usize = 512;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Op {
+    Idle,
+    Read {
+        block: u32,
+        count: u32,
+    },
+    /// The card only writes single blocks, so blocks are written one at a
+    /// time. The block being written is always moved to the start of the
+    /// buffer, and `done` blocks have been written.
+    Write {
+        block: u32,
+        count: u32,
+        done: u32,
+    },
+    Erase,
+    Flush,
+}
+
+pub struct SDCardBlockDevice<'a, A: hil::time::Alarm<'a>> {
+    sdcard: &'a SDCard<'a, A>,
+    client: OptionalCell<&'a dyn BlockDeviceClient>,
+    block_count: Cell<u32>,
+    operation: Cell<Op>,
+    /// Buffer for an operation waiting for the card to be initialized.
+    buffer: TakeCell<'static, [u8]>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a, A: hil::time::Alarm<'a>> SDCardBlockDevice<'a, A> {
+    pub fn new(sdcard: &'a SDCard<'a, A>) -> Self {
+        Self {
+            sdcard,
+            client: OptionalCell::empty(),
+            block_count: Cell::new(0),
+            operation: Cell::new(Op::Idle),
+            buffer: TakeCell::empty(),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Check that `count` blocks starting at `block` are on the card, if the
+    /// size of the card is known.
+    fn check_range(&self, block: u32, count: u32) -> Result<(), ErrorCode> {
+        if count == 0
+            || (self.sdcard.is_initialized()
+                && block
+                    .checked_add(count)
+                    .is_none_or(|end| end > self.block_count.get()))
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        Ok(())
+    }
+
+    /// Start a read or write, initializing the card first if needed.
+    fn start(
+        &self,
+        op: Op,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.operation.get() != Op::Idle {
+            return Err((ErrorCode::BUSY, buffer));
+        }
+        if !self.sdcard.is_installed() {
+            return Err((ErrorCode::UNINSTALLED, buffer));
+        }
+
+        if self.sdcard.is_initialized() {
+            self.issue(op, buffer)?;
+        } else {
+            // The operation is issued once the card is initialized.
+            if let Err(e) = self.sdcard.initialize() {
+                return Err((e, buffer));
+            }
+            self.buffer.replace(buffer);
+        }
+        self.operation.set(op);
+        Ok(())
+    }
+
+    /// Pass a read or write to the card.
+    fn issue(
+        &self,
+        op: Op,
+        buffer: &'static mut [u8],
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        match op {
+            Op::Read { block, count } => match self.check_range(block, count) {
+                Ok(()) => self.sdcard.read_blocks(buffer, block, count),
+                Err(e) => Err((e, buffer)),
+            },
+            Op::Write { block, count, done } => match self.check_range(block, count) {
+                Ok(()) => self.sdcard.write_blocks(buffer, block + done, 1),
+                Err(e) => Err((e, buffer)),
+            },
+            _ => Err((ErrorCode::FAIL, buffer)),
+        }
+    }
+
+    /// Finish the operation in progress and call the client.
+    fn complete(&self, mut buffer: Option<&'static mut [u8]>, result: Result<(), ErrorCode>) {
+        let op = self.operation.replace(Op::Idle);
+        if let (Op::Write { count, done, .. }, Some(buffer)) = (op, buffer.as_mut()) {
+            // Restore the original order of the blocks.
+            let remaining = (count - done) % count;
+            buffer[..count as usize * BLOCK_SIZE].rotate_left(remaining as usize * BLOCK_SIZE);
+        }
+
+        self.client.map(|client| match (op, buffer) {
+            (Op::Read { .. }, Some(buffer)) => client.read_complete(buffer, result),
+            (Op::Write { .. }, Some(buffer)) => client.write_complete(buffer, result),
+            (Op::Erase, _) => client.erase_complete(result),
+            (Op::Flush, _) => client.flush_complete(result),
+            _ => {}
+        });
+    }
+
+    /// Start an erase or flush, which complete immediately.
+    fn start_immediate(&self, op: Op) -> Result<(), ErrorCode> {
+        if self.operation.get() != Op::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if !self.sdcard.is_installed() {
+            return Err(ErrorCode::UNINSTALLED);
+        }
+        self.operation.set(op);
+        self.deferred_call.set();
+        Ok(())
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> BlockDevice<'a> for SDCardBlockDevice<'a, A> {
+    fn set_client(&self, client: &'a dyn BlockDeviceClient) {
+        self.client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> u32 {
+        self.block_count.get()
+    }
+
+    fn read_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if buffer.len() < count as usize * BLOCK_SIZE {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        if let Err(e) = self.check_range(block, count) {
+            return Err((e, buffer));
+        }
+        self.start(Op::Read { block, count }, buffer)
+    }
+
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if buffer.len() < count as usize * BLOCK_SIZE {
+            return Err((ErrorCode::SIZE, buffer));
+        }
+        if let Err(e) = self.check_range(block, count) {
+            return Err((e, buffer));
+        }
+        self.start(
+            Op::Write {
+                block,
+                count,
+                done: 0,
+            },
+            buffer,
+        )
+    }
+
+    fn erase_blocks(&self, block: u32, count: u32) -> Result<(), ErrorCode> {
+        self.check_range(block, count)?;
+        self.start_immediate(Op::Erase)
+    }
+
+    fn flush(&self) -> Result<(), ErrorCode> {
+        self.start_immediate(Op::Flush)
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> SDCardClient for SDCardBlockDevice<'a, A> {
+    fn card_detection_changed(&self, installed: bool) {
+        if !installed {
+            self.block_count.set(0);
+        }
+    }
+
+    fn init_done(&self, block_size: u32, total_size: u64) {
+        let blocks = total_size / BLOCK_SIZE as u64;
+        self.block_count
+            .set(u32::try_from(blocks).unwrap_or(u32::MAX));
+
+        let Some(buffer) = self.buffer.take() else {
+            return;
+        };
+        let result = if block_size as usize != BLOCK_SIZE {
+            Err((ErrorCode::NOSUPPORT, buffer))
+        } else {
+            self.issue(self.operation.get(), buffer)
+        };
+        if let Err((e, buffer)) = result {
+            self.complete(Some(buffer), Err(e));
+        }
+    }
+
+    fn read_done(&self, data: &'static mut [u8], len: usize) {
+        let result = match self.operation.get() {
+            Op::Read { count, .. } if len >= count as usize * BLOCK_SIZE => Ok(()),
+            _ => Err(ErrorCode::FAIL),
+        };
+        self.complete(Some(data), result);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8]) {
+        let Op::Write { block, count, done } = self.operation.get() else {
+            self.complete(Some(buffer), Err(ErrorCode::FAIL));
+            return;
+        };
+
+        // Move the next block to the start of the buffer.
+        buffer[..count as usize * BLOCK_SIZE].rotate_left(BLOCK_SIZE);
+        let done = done + 1;
+        let op = Op::Write { block, count, done };
+        self.operation.set(op);
+
+        if done == count {
+            self.complete(Some(buffer), Ok(()));
+        } else if let Err((e, buffer)) = self.issue(op, buffer) {
+            self.complete(Some(buffer), Err(e));
+        }
+    }
+
+    fn error(&self, _error: u32) {
+        // The buffer is held by the card if a read or write failed, or by us
+        // if initialization failed.
+        let buffer = self.sdcard.take_buffer().or_else(|| self.buffer.take());
+        self.complete(buffer, Err(ErrorCode::FAIL));
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> DeferredCallClient for SDCardBlockDevice<'a, A> {
+    fn handle_deferred_call(&self) {
+        self.complete(None, Ok(()));
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
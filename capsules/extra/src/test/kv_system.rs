@@ -117,7 +117,7 @@ impl<S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> KVSystemClient<
         &self,
         result: Result<(), ErrorCode>,
         key: &'static mut T,
-        ret_buf: SubSliceMut<'static, u8>,
+        mut ret_buf: SubSliceMut<'static, u8>,
     ) {
         match result {
             Ok(()) => {
@@ -131,8 +131,9 @@ impl<S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> KVSystemClient<
                     debug!("Unable to find key: {:?}", key);
                     self.state.set(CurrentState::Normal);
 
-                    debug!("Let's start a garbage collection");
-                    self.kv_system.garbage_collect().unwrap();
+                    debug!("Let's list the remaining keys");
+                    ret_buf.reset();
+                    self.kv_system.next_key(0, key, ret_buf).unwrap();
                 } else {
                     panic!("Error finding key: {:?}", e);
                 }
@@ -140,6 +141,31 @@ impl<S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> KVSystemClient<
         }
     }
 
+    fn next_key_complete(
+        &self,
+        result: Result<usize, ErrorCode>,
+        position: usize,
+        key: &'static mut T,
+        mut ret_buf: SubSliceMut<'static, u8>,
+    ) {
+        match result {
+            Ok(value_length) => {
+                debug!("Found key: {:?} with {} byte value", key, value_length);
+                ret_buf.reset();
+                self.kv_system.next_key(position, key, ret_buf).unwrap();
+            }
+            Err(ErrorCode::NOSUPPORT) => {
+                debug!("Listed all keys");
+
+                debug!("Let's start a garbage collection");
+                self.kv_system.garbage_collect().unwrap();
+            }
+            Err(e) => {
+                panic!("Error listing keys: {:?}", e);
+            }
+        }
+    }
+
     fn invalidate_key_complete(&self, result: Result<(), ErrorCode>, key: &'static mut T) {
         match result {
             Ok(()) => {
@@ -157,6 +183,10 @@ impl<S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> KVSystemClient<
         }
     }
 
+    fn commit_transaction_complete(&self, _result: Result<(), ErrorCode>) {
+        unreachable!()
+    }
+
     fn garbage_collect_complete(&self, result: Result<(), ErrorCode>) {
         match result {
             Ok(()) => {
@@ -85,12 +85,32 @@ pub trait KVSystemClient<K: KeyType> {
         ret_buf: SubSliceMut<'static, u8>,
     );
 
+    /// This callback is called when the next_key operation completes.
+    ///
+    /// - `result`: The length of the value on success, 'ErrorCode' on error
+    /// - `position`: The position to pass to `next_key()` to find the next key
+    /// - `key`: The key buffer, containing the hashed key that was found
+    /// - `ret_buf`: The ret_buf buffer
+    fn next_key_complete(
+        &self,
+        result: Result<usize, ErrorCode>,
+        position: usize,
+        key: &'static mut K,
+        ret_buf: SubSliceMut<'static, u8>,
+    );
+
     /// This callback is called when the invalidate_key operation completes.
     ///
     /// - `result`: Nothing on success, 'ErrorCode' on error
     /// - `key`: The key buffer
     fn invalidate_key_complete(&self, result: Result<(), ErrorCode>, key: &'static mut K);
 
+    /// This callback is called when the commit_transaction operation
+    /// completes.
+    ///
+    /// - `result`: Nothing on success, 'ErrorCode' on error
+    fn commit_transaction_complete(&self, result: Result<(), ErrorCode>);
+
     /// This callback is called when the garbage_collect operation completes.
     ///
     /// - `result`: Nothing on success, 'ErrorCode' on error
@@ -162,6 +182,32 @@ pub trait KVSystem<'a> {
         ret_buf: SubSliceMut<'static, u8>,
     ) -> Result<(), (&'static mut Self::K, SubSliceMut<'static, u8>, ErrorCode)>;
 
+    /// Finds the next key in the store at or after `position`.
+    ///
+    /// Start with a `position` of 0 and then pass the `position` from
+    /// `next_key_complete()` to find the following key. The order of the keys
+    /// is up to the implementation.
+    ///
+    /// - `position`: Where to start looking for the next key.
+    /// - `key`: A buffer to store the hashed key that was found.
+    /// - `ret_buf`: A buffer to store the value to. If the value does not fit,
+    ///   only the start of it is copied.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key, ret_buf and a `Result<(), ErrorCode>` will be returned.
+    ///
+    /// The possible `Result<(), ErrorCode>`s are:
+    /// - `BUSY`: An operation is already in progress
+    /// - `INVAL`: An invalid parameter was passed
+    /// - `NODEVICE`: No KV store was setup
+    /// - `NOSUPPORT`: There are no more keys.
+    fn next_key(
+        &self,
+        position: usize,
+        key: &'static mut Self::K,
+        ret_buf: SubSliceMut<'static, u8>,
+    ) -> Result<(), (&'static mut Self::K, SubSliceMut<'static, u8>, ErrorCode)>;
+
     /// Invalidates the key in flash storage.
     ///
     /// - `key`: A hashed key. This key will be used to remove the `value`.
@@ -179,6 +225,45 @@ pub trait KVSystem<'a> {
         key: &'static mut Self::K,
     ) -> Result<(), (&'static mut Self::K, ErrorCode)>;
 
+    /// Starts a transaction.
+    ///
+    /// Keys appended and invalidated until the transaction is committed with
+    /// `commit_transaction()` are changed together. The changes are not
+    /// visible until the transaction is committed. This does not trigger a
+    /// callback.
+    ///
+    /// On success nothing will be returned.
+    /// On error a `Result<(), ErrorCode>` will be returned.
+    ///
+    /// The possible `Result<(), ErrorCode>`s are:
+    /// - `BUSY`: An operation is already in progress
+    /// - `ALREADY`: A transaction has already been started
+    /// - `NODEVICE`: No KV store was setup
+    fn begin_transaction(&self) -> Result<(), ErrorCode>;
+
+    /// Commits the transaction started with `begin_transaction()`.
+    ///
+    /// On success nothing will be returned.
+    /// On error a `Result<(), ErrorCode>` will be returned.
+    ///
+    /// The possible `Result<(), ErrorCode>`s are:
+    /// - `BUSY`: An operation is already in progress
+    /// - `INVAL`: No transaction has been started
+    /// - `NODEVICE`: No KV store was setup
+    fn commit_transaction(&self) -> Result<(), ErrorCode>;
+
+    /// Discards the changes made since `begin_transaction()`. This does not
+    /// trigger a callback.
+    ///
+    /// On success nothing will be returned.
+    /// On error a `Result<(), ErrorCode>` will be returned.
+    ///
+    /// The possible `Result<(), ErrorCode>`s are:
+    /// - `BUSY`: An operation is already in progress
+    /// - `INVAL`: No transaction has been started
+    /// - `NODEVICE`: No KV store was setup
+    fn abort_transaction(&self) -> Result<(), ErrorCode>;
+
     /// Perform a garbage collection on the KV Store.
     ///
     /// For implementations that don't require garbage collecting this should
@@ -201,7 +286,9 @@ enum Operation {
     Init,
     GetKey,
     AppendKey,
+    NextKey,
     InvalidateKey,
+    CommitTransaction,
     GarbageCollect,
 }
 
@@ -284,6 +371,9 @@ impl<F: Flash, const PAGE_SIZE: usize> tickv::flash_controller::FlashController<
 
 pub type TicKVKeyType = [u8; 8];
 
+/// The hash of `tickv::MAIN_KEY`, which TicKV stores internally.
+const HASHED_MAIN_KEY: u64 = 0x7bc9f7ff4f76f244;
+
 /// `TicKVSystem` implements `KVSystem` using the TicKV library.
 pub struct TicKVSystem<'a, F: Flash + 'static, H: Hasher<'a, 8>, const PAGE_SIZE: usize> {
     /// Underlying asynchronous TicKV implementation.
@@ -302,6 +392,8 @@ pub struct TicKVSystem<'a, F: Flash + 'static, H: Hasher<'a, 8>, const PAGE_SIZE
     /// Holder for a buffer containing a value being read from or written to the
     /// key-value store.
     value_buffer: MapCell<SubSliceMut<'static, u8>>,
+    /// Where to start looking for the next key.
+    position: Cell<usize>,
     /// Callback client when the `KVSystem` operation completes.
     client: OptionalCell<&'a dyn KVSystemClient<TicKVKeyType>>,
 }
@@ -329,19 +421,20 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> TicKVSystem<'a, F,
             unhashed_key_buffer: MapCell::empty(),
             key_buffer: TakeCell::empty(),
             value_buffer: MapCell::empty(),
+            position: Cell::new(0),
             client: OptionalCell::empty(),
         }
     }
 
     pub fn initialise(&self) {
-        let _ret = self.tickv.initialise(0x7bc9f7ff4f76f244);
+        let _ret = self.tickv.initialise(HASHED_MAIN_KEY);
         self.operation.set(Operation::Init);
     }
 
     fn complete_init(&self) {
         self.operation.set(Operation::None);
         match self.next_operation.get() {
-            Operation::None | Operation::Init => {}
+            Operation::None | Operation::Init | Operation::CommitTransaction => {}
             Operation::GetKey => {
                 if let Err((key, value, error)) = self.get_value(
                     self.key_buffer.take().unwrap(),
@@ -362,6 +455,17 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> TicKVSystem<'a, F,
                     });
                 }
             }
+            Operation::NextKey => {
+                if let Err((key, value, error)) = self.next_key(
+                    self.position.get(),
+                    self.key_buffer.take().unwrap(),
+                    self.value_buffer.take().unwrap(),
+                ) {
+                    self.client.map(move |cb| {
+                        cb.next_key_complete(Err(error), 0, key, value);
+                    });
+                }
+            }
             Operation::InvalidateKey => {
                 if let Err((key, error)) = self.invalidate_key(self.key_buffer.take().unwrap()) {
                     self.client.map(move |cb| {
@@ -379,6 +483,33 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> TicKVSystem<'a, F,
         }
         self.next_operation.set(Operation::None);
     }
+
+    fn continue_commit(
+        &self,
+        ret: Result<tickv::success_codes::SuccessCode, tickv::error_codes::ErrorCode>,
+    ) {
+        match ret {
+            Ok(tickv::success_codes::SuccessCode::Complete)
+            | Ok(tickv::success_codes::SuccessCode::Written) => {
+                self.operation.set(Operation::None);
+                self.client.map(|cb| {
+                    cb.commit_transaction_complete(Ok(()));
+                });
+            }
+            Ok(tickv::success_codes::SuccessCode::Queued)
+            | Err(tickv::error_codes::ErrorCode::ReadNotReady(_))
+            | Err(tickv::error_codes::ErrorCode::WriteNotReady(_))
+            | Err(tickv::error_codes::ErrorCode::EraseNotReady(_)) => {
+                // Need to do another flash operation.
+            }
+            Err(_e) => {
+                self.operation.set(Operation::None);
+                self.client.map(|cb| {
+                    cb.commit_transaction_complete(Err(ErrorCode::FAIL));
+                });
+            }
+        }
+    }
 }
 
 impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> hasher::Client<8>
@@ -517,9 +648,75 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> flash::Client<F>
                     }
                 }
             }
-            Operation::InvalidateKey => match ret {
+            Operation::NextKey => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
                 | Ok(tickv::success_codes::SuccessCode::Written) => {
+                    self.operation.set(Operation::None);
+                    let key = self.key_buffer.take().unwrap();
+                    let value = self.value_buffer.take().unwrap();
+                    match self.tickv.key_info() {
+                        Some(key_info)
+                            if key_info.hashed_key == HASHED_MAIN_KEY
+                                || key_info.hashed_key == tickv::tickv::TRANSACTION_KEY =>
+                        {
+                            // Skip the keys TicKV uses internally.
+                            if let Err((key, value, e)) = self.next_key(
+                                key_info.next_position,
+                                key,
+                                SubSliceMut::new(value.take()),
+                            ) {
+                                self.client.map(move |cb| {
+                                    cb.next_key_complete(Err(e), 0, key, value);
+                                });
+                            }
+                        }
+                        Some(key_info) => {
+                            *key = key_info.hashed_key.to_be_bytes();
+                            self.client.map(move |cb| {
+                                cb.next_key_complete(
+                                    Ok(key_info.value_length),
+                                    key_info.next_position,
+                                    key,
+                                    value,
+                                );
+                            });
+                        }
+                        None => {
+                            self.client.map(move |cb| {
+                                cb.next_key_complete(Err(ErrorCode::FAIL), 0, key, value);
+                            });
+                        }
+                    }
+                }
+                Err(tickv::error_codes::ErrorCode::ReadNotReady(_))
+                | Err(tickv::error_codes::ErrorCode::EraseNotReady(_))
+                | Ok(_) => {}
+                Err(e) => {
+                    let get_tock_err = match e {
+                        tickv::error_codes::ErrorCode::KeyNotFound => ErrorCode::NOSUPPORT,
+                        _ => ErrorCode::FAIL,
+                    };
+                    self.operation.set(Operation::None);
+                    self.client.map(|cb| {
+                        cb.next_key_complete(
+                            Err(get_tock_err),
+                            0,
+                            self.key_buffer.take().unwrap(),
+                            self.value_buffer.take().unwrap(),
+                        );
+                    });
+                }
+            },
+            Operation::InvalidateKey => match ret {
+                Ok(tickv::success_codes::SuccessCode::Complete) => {
+                    // In a transaction the key is only invalidated when the
+                    // transaction is committed, so nothing was written.
+                    self.operation.set(Operation::None);
+                    self.client.map(|cb| {
+                        cb.invalidate_key_complete(Ok(()), self.key_buffer.take().unwrap());
+                    });
+                }
+                Ok(tickv::success_codes::SuccessCode::Written) => {
                     // Need to wait for flash write to complete.
                     self.operation.set(Operation::None);
                 }
@@ -544,6 +741,7 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> flash::Client<F>
                     });
                 }
             },
+            Operation::CommitTransaction => self.continue_commit(ret),
             Operation::GarbageCollect => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
                 | Ok(tickv::success_codes::SuccessCode::Written) => {
@@ -567,7 +765,29 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> flash::Client<F>
 
         match self.operation.get() {
             Operation::Init => {
-                self.complete_init();
+                if self.tickv.operation_pending() {
+                    // Still recovering a transaction that was being committed
+                    // when power was lost.
+                    let (ret, _tickv_buf, _tickv_buf_len) = self.tickv.continue_operation();
+                    match ret {
+                        Ok(tickv::success_codes::SuccessCode::Complete)
+                        | Ok(tickv::success_codes::SuccessCode::Written) => {
+                            self.complete_init();
+                        }
+                        _ => {}
+                    }
+                } else {
+                    self.complete_init();
+                }
+            }
+            Operation::CommitTransaction => {
+                if self.tickv.operation_pending() {
+                    let (ret, _tickv_buf, _tickv_buf_len) = self.tickv.continue_operation();
+                    self.continue_commit(ret);
+                } else {
+                    // This was the last write of the commit.
+                    self.continue_commit(Ok(tickv::success_codes::SuccessCode::Complete));
+                }
             }
             Operation::AppendKey => {
                 self.operation.set(Operation::None);
@@ -726,6 +946,50 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> KVSystem<'a>
         }
     }
 
+    fn next_key(
+        &self,
+        position: usize,
+        key: &'static mut Self::K,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<(), (&'static mut [u8; 8], SubSliceMut<'static, u8>, ErrorCode)> {
+        if value.is_sliced() {
+            return Err((key, value, ErrorCode::SIZE));
+        }
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::NextKey);
+
+                match self.tickv.next_key(position, value.take()) {
+                    Ok(_ret) => {
+                        self.key_buffer.replace(key);
+                        Ok(())
+                    }
+                    Err((buf, e)) => {
+                        self.operation.set(Operation::None);
+                        let tock_error = match e {
+                            tickv::error_codes::ErrorCode::KeyNotFound => ErrorCode::NOSUPPORT,
+                            _ => ErrorCode::FAIL,
+                        };
+                        Err((key, SubSliceMut::new(buf), tock_error))
+                    }
+                }
+            }
+            Operation::Init => {
+                // The init process is still occurring.
+                // We can save this request and start it after init
+                self.next_operation.set(Operation::NextKey);
+                self.position.set(position);
+                self.key_buffer.replace(key);
+                self.value_buffer.replace(value);
+                Ok(())
+            }
+            _ => {
+                // An operation is already in process.
+                Err((key, value, ErrorCode::BUSY))
+            }
+        }
+    }
+
     fn invalidate_key(
         &self,
         key: &'static mut Self::K,
@@ -756,6 +1020,59 @@ impl<'a, F: Flash, H: Hasher<'a, 8>, const PAGE_SIZE: usize> KVSystem<'a>
         }
     }
 
+    fn begin_transaction(&self) -> Result<(), ErrorCode> {
+        match self.operation.get() {
+            Operation::None => self
+                .tickv
+                .begin_transaction()
+                .map(|_| ())
+                .map_err(|e| match e {
+                    tickv::error_codes::ErrorCode::TransactionActive => ErrorCode::ALREADY,
+                    _ => ErrorCode::FAIL,
+                }),
+            _ => {
+                // An operation is already in process.
+                Err(ErrorCode::BUSY)
+            }
+        }
+    }
+
+    fn commit_transaction(&self) -> Result<(), ErrorCode> {
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::CommitTransaction);
+                self.tickv.commit_transaction().map(|_| ()).map_err(|e| {
+                    self.operation.set(Operation::None);
+                    match e {
+                        tickv::error_codes::ErrorCode::NoTransaction => ErrorCode::INVAL,
+                        _ => ErrorCode::FAIL,
+                    }
+                })
+            }
+            _ => {
+                // An operation is already in process.
+                Err(ErrorCode::BUSY)
+            }
+        }
+    }
+
+    fn abort_transaction(&self) -> Result<(), ErrorCode> {
+        match self.operation.get() {
+            Operation::None => self
+                .tickv
+                .abort_transaction()
+                .map(|_| ())
+                .map_err(|e| match e {
+                    tickv::error_codes::ErrorCode::NoTransaction => ErrorCode::INVAL,
+                    _ => ErrorCode::FAIL,
+                }),
+            _ => {
+                // An operation is already in process.
+                Err(ErrorCode::BUSY)
+            }
+        }
+    }
+
     fn garbage_collect(&self) -> Result<(), ErrorCode> {
         match self.operation.get() {
             Operation::None => {
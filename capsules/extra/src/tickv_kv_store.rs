@@ -40,6 +40,8 @@ enum Operation {
     Add,
     Update,
     Delete,
+    NextKey,
+    CommitTransaction,
     GarbageCollect,
 }
 
@@ -211,6 +213,49 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> kv::KV<'a> for TicKVKVStore<'a, K,
         }
     }
 
+    fn next_key(
+        &self,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if self.operation.is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+
+        match self.hashed_key.take() {
+            Some(hashed_key) => {
+                // The hashed key that is found is copied into `key`.
+                if key.len() < hashed_key.as_ref().len() {
+                    self.hashed_key.replace(hashed_key);
+                    return Err((key, value, ErrorCode::SIZE));
+                }
+
+                self.operation.set(Operation::NextKey);
+
+                match self.kv.next_key(position, hashed_key, value) {
+                    Ok(()) => {
+                        self.unhashed_key.replace(key);
+                        Ok(())
+                    }
+                    Err((hashed_key, value, e)) => {
+                        self.hashed_key.replace(hashed_key);
+                        self.operation.clear();
+                        Err((key, value, e))
+                    }
+                }
+            }
+            None => Err((key, value, ErrorCode::FAIL)),
+        }
+    }
+
     fn garbage_collect(&self) -> Result<(), ErrorCode> {
         if self.operation.is_some() {
             return Err(ErrorCode::BUSY);
@@ -225,6 +270,37 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> kv::KV<'a> for TicKVKVStore<'a, K,
             Ok(())
         }
     }
+
+    fn begin_transaction(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.kv.begin_transaction()
+    }
+
+    fn commit_transaction(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.operation.set(Operation::CommitTransaction);
+
+        if let Err(e) = self.kv.commit_transaction() {
+            self.operation.clear();
+            Err(e)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn abort_transaction(&self) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.kv.abort_transaction()
+    }
 }
 
 impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<'a, K, T> {
@@ -276,7 +352,9 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
                             cb.delete_complete(Err(ErrorCode::FAIL), unhashed_key);
                         });
                     }
-                    Operation::GarbageCollect => {}
+                    Operation::NextKey
+                    | Operation::CommitTransaction
+                    | Operation::GarbageCollect => {}
                 }
             } else {
                 match op {
@@ -365,7 +443,9 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
                             });
                         }
                     },
-                    Operation::GarbageCollect => {}
+                    Operation::NextKey
+                    | Operation::CommitTransaction
+                    | Operation::GarbageCollect => {}
                 }
             }
         });
@@ -380,7 +460,7 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
         self.hashed_key.replace(key);
 
         self.operation.map(|op| match op {
-            Operation::Get | Operation::Delete => {}
+            Operation::Get | Operation::Delete | Operation::NextKey => {}
             Operation::Set => {
                 match result {
                     Err(ErrorCode::NOSUPPORT) => {
@@ -457,7 +537,7 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
                     });
                 });
             }
-            Operation::GarbageCollect => {}
+            Operation::CommitTransaction | Operation::GarbageCollect => {}
         });
     }
 
@@ -489,11 +569,45 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
         });
     }
 
+    fn next_key_complete(
+        &self,
+        result: Result<usize, ErrorCode>,
+        position: usize,
+        key: &'static mut T,
+        ret_buf: SubSliceMut<'static, u8>,
+    ) {
+        self.operation.map(|op| {
+            if op == Operation::NextKey {
+                self.operation.clear();
+
+                // This holds the caller's buffer for the hashed key.
+                self.unhashed_key.take().map(|mut key_buf| {
+                    if result.is_ok() {
+                        key_buf.slice(..key.as_ref().len());
+                        key_buf.as_mut_slice().copy_from_slice(key.as_ref());
+                    }
+                    self.client.map(move |cb| {
+                        cb.next_key_complete(
+                            result.map_err(|e| match e {
+                                ErrorCode::NOSUPPORT => ErrorCode::NOSUPPORT,
+                                _ => ErrorCode::FAIL,
+                            }),
+                            position,
+                            key_buf,
+                            ret_buf,
+                        );
+                    });
+                });
+            }
+        });
+        self.hashed_key.replace(key);
+    }
+
     fn invalidate_key_complete(&self, result: Result<(), ErrorCode>, key: &'static mut T) {
         self.hashed_key.replace(key);
 
         self.operation.map(|op| match op {
-            Operation::Get | Operation::Add => {}
+            Operation::Get | Operation::Add | Operation::NextKey => {}
             Operation::Set => {
                 // Now that we have deleted the existing key-value we can store
                 // our new key and value.
@@ -580,7 +694,14 @@ impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVSystemClient<T> for TicKVKVStore<
                     });
                 });
             }
-            Operation::GarbageCollect => {}
+            Operation::CommitTransaction | Operation::GarbageCollect => {}
+        });
+    }
+
+    fn commit_transaction_complete(&self, result: Result<(), ErrorCode>) {
+        self.operation.clear();
+        self.client.map(move |cb| {
+            cb.commit_transaction_complete(result);
         });
     }
 
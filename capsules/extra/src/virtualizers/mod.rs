@@ -3,4 +3,5 @@
 // Copyright Tock Contributors 2025.
 
 pub mod screen;
+pub mod virtual_block_device;
 pub mod virtual_kv;
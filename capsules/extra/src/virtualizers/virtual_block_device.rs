@@ -0,0 +1,896 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Block device virtualizer.
+//!
+//! `MuxBlockDevice` shares one block device between several users, each of
+//! which uses a `VirtualBlockDevice`. Requests are queued and passed to the
+//! device one at a time. Each user accesses a region of the device:
+//!
+//! - `Region::Device`: the whole device.
+//! - `Region::Blocks`: a fixed range of blocks, for boards that divide a
+//!   device between users themselves.
+//! - `Region::Partition`: a partition from the partition table of the device.
+//!   The table is read with `partition_table::PartitionTable`, so MBR and GPT
+//!   partition tables are supported. Partitions are numbered in the order of
+//!   the used entries of the table, starting at 0. The table is read the first
+//!   time a partition is accessed, and is only supported on devices with 512
+//!   byte blocks.
+//!
+//! Block numbers used by a user are relative to the start of its region, and
+//! requests outside of the region fail with `INVAL`.
+//!
+//! ```text
+//! +-------------------------+
+//! |  Filesystem, log, ...   |
+//! +-------------------------+
+//!
+//!    hil::block_device
+//!
+//! +-------------------------+
+//! | Virtualizer (this file) |
+//! +-------------------------+
+//!
+//!    hil::block_device
+//!
+//! +-------------------------+
+//! |  Block device           |
+//! +-------------------------+
+//! ```
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let mux_block_device = components::block_device::MuxBlockDeviceComponent::new(
+//!     sdcard_block_device,
+//! )
+//! .finalize(components::mux_block_device_component_static!(
+//!     SDCardBlockDevice<'static, VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>>
+//! ));
+//! let data_partition = components::block_device::VirtualBlockDeviceComponent::new(
+//!     mux_block_device,
+//!     capsules_extra::virtualizers::virtual_block_device::Region::Partition(1),
+//! )
+//! .finalize(components::virtual_block_device_component_static!(
+//!     SDCardBlockDevice<'static, VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>>
+//! ));
+//! ```
+
+use core::cell::Cell;
+
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::block_device::{BlockDevice, BlockDeviceClient};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use crate::partition_table::{self, PartitionEntry, PartitionTable};
+
+/// Size of the buffer used to read the partition table.
+pub const TABLE_BUFFER_LEN: usize = partition_table::BLOCK_SIZE;
+
+/// Maximum number of partitions that are read from the partition table.
+pub const MAX_PARTITIONS: usize = 8;
+
+/// A range of blocks on the device.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Partition {
+    /// The first block.
+    pub start: u32,
+    /// The number of blocks.
+    pub count: u32,
+}
+
+/// The part of the device accessed by a `VirtualBlockDevice`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Region {
+    /// The whole device.
+    Device,
+    /// A fixed range of blocks.
+    Blocks(Partition),
+    /// The partition with this index in the partition table.
+    Partition(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Table {
+    Unread,
+    /// Waiting for a block of the table read with `parser`.
+    Reading,
+    Read,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Op {
+    Idle,
+    Read { block: u32, count: u32 },
+    Write { block: u32, count: u32 },
+    Erase { block: u32, count: u32 },
+    Flush,
+}
+
+/// Shares a block device between `VirtualBlockDevice`s.
+pub struct MuxBlockDevice<'a, B: BlockDevice<'a>> {
+    device: &'a B,
+    users: List<'a, VirtualBlockDevice<'a, B>>,
+    inflight: OptionalCell<&'a VirtualBlockDevice<'a, B>>,
+    table: Cell<Table>,
+    parser: Cell<PartitionTable>,
+    table_buffer: TakeCell<'static, [u8]>,
+    partitions: Cell<[Partition; MAX_PARTITIONS]>,
+    partition_count: Cell<usize>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a, B: BlockDevice<'a>> MuxBlockDevice<'a, B> {
+    pub fn new(device: &'a B, table_buffer: &'static mut [u8; TABLE_BUFFER_LEN]) -> Self {
+        Self {
+            device,
+            users: List::new(),
+            inflight: OptionalCell::empty(),
+            table: Cell::new(Table::Unread),
+            parser: Cell::new(PartitionTable::new()),
+            table_buffer: TakeCell::new(table_buffer),
+            partitions: Cell::new([Partition::default(); MAX_PARTITIONS]),
+            partition_count: Cell::new(0),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// The range of blocks of `region`, or `None` if the partition table has
+    /// not been read yet.
+    fn resolve(&self, region: Region) -> Option<Partition> {
+        match region {
+            Region::Device => Some(Partition {
+                start: 0,
+                count: self.device.block_count(),
+            }),
+            Region::Blocks(blocks) => Some(blocks),
+            Region::Partition(index) => {
+                if self.table.get() != Table::Read {
+                    None
+                } else if index < self.partition_count.get() {
+                    Some(self.partitions.get()[index])
+                } else {
+                    Some(Partition::default())
+                }
+            }
+        }
+    }
+
+    fn table_busy(&self) -> bool {
+        self.table.get() == Table::Reading
+    }
+
+    /// Fail a request of `user` from a deferred call.
+    fn fail(&self, user: &VirtualBlockDevice<'a, B>, error: ErrorCode) {
+        user.failed.set(Some(error));
+        self.deferred_call.set();
+    }
+
+    /// Pass the next queued request to the device.
+    fn do_next_op(&self) {
+        if self.inflight.is_some() || self.table_busy() {
+            return;
+        }
+
+        for user in self.users.iter() {
+            if user.operation.get() == Op::Idle || user.failed.get().is_some() {
+                continue;
+            }
+
+            let region = match self.resolve(user.region) {
+                Some(region) => region,
+                None => {
+                    self.read_table();
+                    if self.table_busy() {
+                        return;
+                    }
+                    match self.resolve(user.region) {
+                        Some(region) => region,
+                        None => {
+                            // The table could not be read.
+                            self.fail(user, ErrorCode::FAIL);
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            match self.issue(user, region) {
+                Ok(()) => {
+                    self.inflight.set(user);
+                    return;
+                }
+                Err(e) => self.fail(user, e),
+            }
+        }
+    }
+
+    /// Translate the request of `user` to `region` and pass it to the device.
+    fn issue(&self, user: &VirtualBlockDevice<'a, B>, region: Partition) -> Result<(), ErrorCode> {
+        let translate = |block: u32, count: u32| {
+            block
+                .checked_add(count)
+                .filter(|end| *end <= region.count)
+                .map(|_| region.start + block)
+                .ok_or(ErrorCode::INVAL)
+        };
+
+        match user.operation.get() {
+            Op::Read { block, count } => {
+                let block = translate(block, count)?;
+                let buffer = user.buffer.take().ok_or(ErrorCode::FAIL)?;
+                self.device
+                    .read_blocks(buffer, block, count)
+                    .map_err(|(e, buffer)| {
+                        user.buffer.replace(buffer);
+                        e
+                    })
+            }
+            Op::Write { block, count } => {
+                let block = translate(block, count)?;
+                let buffer = user.buffer.take().ok_or(ErrorCode::FAIL)?;
+                self.device
+                    .write_blocks(buffer, block, count)
+                    .map_err(|(e, buffer)| {
+                        user.buffer.replace(buffer);
+                        e
+                    })
+            }
+            Op::Erase { block, count } => self.device.erase_blocks(translate(block, count)?, count),
+            Op::Flush => self.device.flush(),
+            Op::Idle => Err(ErrorCode::FAIL),
+        }
+    }
+
+    /// Start reading the partition table.
+    fn read_table(&self) {
+        if self.device.block_size() != TABLE_BUFFER_LEN {
+            self.table_done();
+            return;
+        }
+        let mut parser = PartitionTable::new();
+        let block = parser.start();
+        self.parser.set(parser);
+        self.partition_count.set(0);
+        self.read_table_block(block);
+    }
+
+    fn read_table_block(&self, block: u32) {
+        self.table_buffer
+            .take()
+            .map(|buffer| match self.device.read_blocks(buffer, block, 1) {
+                Ok(()) => self.table.set(Table::Reading),
+                Err((_, buffer)) => {
+                    self.table_buffer.replace(buffer);
+                    self.table.set(Table::Unread);
+                }
+            });
+    }
+
+    /// Add a partition, returning whether there is room for more.
+    fn add_partition(&self, entry: PartitionEntry) -> bool {
+        let index = self.partition_count.get();
+        if index < MAX_PARTITIONS {
+            let mut partitions = self.partitions.get();
+            partitions[index] = Partition {
+                start: entry.start,
+                count: entry.count,
+            };
+            self.partitions.set(partitions);
+            self.partition_count.set(index + 1);
+        }
+        self.partition_count.get() < MAX_PARTITIONS
+    }
+
+    fn table_done(&self) {
+        self.table.set(Table::Read);
+    }
+
+    /// Parse a block of the partition table, returning the next block to
+    /// read.
+    fn table_block_read(&self, buffer: &[u8]) -> Option<u32> {
+        let mut parser = self.parser.get();
+        let next = parser.block_read(buffer, |entry| self.add_partition(entry));
+        self.parser.set(parser);
+        next
+    }
+
+    /// Handle the completion of a read of the partition table.
+    fn table_read_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+        match result {
+            Ok(()) => {
+                let next = self.table_block_read(buffer);
+                self.table_buffer.replace(buffer);
+                match next {
+                    Some(block) => self.read_table_block(block),
+                    None => self.table_done(),
+                }
+            }
+            Err(_) => {
+                // Discard any partitions found so far and try again on the
+                // next request. Requests waiting for the table fail.
+                self.partition_count.set(0);
+                self.table.set(Table::Unread);
+                for user in self.users.iter() {
+                    if matches!(user.region, Region::Partition(_))
+                        && user.operation.get() != Op::Idle
+                    {
+                        self.fail(user, ErrorCode::FAIL);
+                    }
+                }
+                self.table_buffer.replace(buffer);
+            }
+        }
+        self.do_next_op();
+    }
+}
+
+impl<'a, B: BlockDevice<'a>> BlockDeviceClient for MuxBlockDevice<'a, B> {
+    fn read_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+        if self.table_busy() {
+            self.table_read_complete(buffer, result);
+            return;
+        }
+        self.inflight
+            .take()
+            .map(|user| user.complete(Some(buffer), result));
+        self.do_next_op();
+    }
+
+    fn write_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+        self.inflight
+            .take()
+            .map(|user| user.complete(Some(buffer), result));
+        self.do_next_op();
+    }
+
+    fn erase_complete(&self, result: Result<(), ErrorCode>) {
+        self.inflight.take().map(|user| user.complete(None, result));
+        self.do_next_op();
+    }
+
+    fn flush_complete(&self, result: Result<(), ErrorCode>) {
+        self.inflight.take().map(|user| user.complete(None, result));
+        self.do_next_op();
+    }
+}
+
+impl<'a, B: BlockDevice<'a>> DeferredCallClient for MuxBlockDevice<'a, B> {
+    fn handle_deferred_call(&self) {
+        for user in self.users.iter() {
+            if let Some(error) = user.failed.take() {
+                let buffer = user.buffer.take();
+                user.complete(buffer, Err(error));
+            }
+        }
+        self.do_next_op();
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+/// A user of a `MuxBlockDevice`.
+pub struct VirtualBlockDevice<'a, B: BlockDevice<'a>> {
+    mux: &'a MuxBlockDevice<'a, B>,
+    region: Region,
+    operation: Cell<Op>,
+    buffer: TakeCell<'static, [u8]>,
+    /// Error to report from the mux's deferred call.
+    failed: Cell<Option<ErrorCode>>,
+    next: ListLink<'a, VirtualBlockDevice<'a, B>>,
+    client: OptionalCell<&'a dyn BlockDeviceClient>,
+}
+
+impl<'a, B: BlockDevice<'a>> ListNode<'a, VirtualBlockDevice<'a, B>> for VirtualBlockDevice<'a, B> {
+    fn next(&'a self) -> &'a ListLink<'a, VirtualBlockDevice<'a, B>> {
+        &self.next
+    }
+}
+
+impl<'a, B: BlockDevice<'a>> VirtualBlockDevice<'a, B> {
+    pub fn new(mux: &'a MuxBlockDevice<'a, B>, region: Region) -> Self {
+        Self {
+            mux,
+            region,
+            operation: Cell::new(Op::Idle),
+            buffer: TakeCell::empty(),
+            failed: Cell::new(None),
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Call this method immediately after new() to link this to the mux,
+    /// otherwise requests will never be started.
+    pub fn setup(&'a self) {
+        self.mux.users.push_head(self);
+    }
+
+    fn complete(&self, buffer: Option<&'static mut [u8]>, result: Result<(), ErrorCode>) {
+        let op = self.operation.replace(Op::Idle);
+        self.client.map(|client| match (op, buffer) {
+            (Op::Read { .. }, Some(buffer)) => client.read_complete(buffer, result),
+            (Op::Write { .. }, Some(buffer)) => client.write_complete(buffer, result),
+            (Op::Erase { .. }, _) => client.erase_complete(result),
+            (Op::Flush, _) => client.flush_complete(result),
+            _ => {}
+        });
+    }
+
+    /// Check that a request can be queued. `len` is the length of the
+    /// buffer for reads and writes.
+    fn check(&self, op: Op, len: Option<usize>) -> Result<(), ErrorCode> {
+        if self.operation.get() != Op::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if let Op::Read { block, count } | Op::Write { block, count } | Op::Erase { block, count } =
+            op
+        {
+            if count == 0 {
+                return Err(ErrorCode::INVAL);
+            }
+            if len.is_some_and(|len| len < count as usize * self.mux.device.block_size()) {
+                return Err(ErrorCode::SIZE);
+            }
+            // Requests to partitions are checked once the partition table has
+            // been read.
+            if let Some(region) = self.mux.resolve(self.region) {
+                if block
+                    .checked_add(count)
+                    .is_none_or(|end| end > region.count)
+                {
+                    return Err(ErrorCode::INVAL);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn queue(&self, op: Op) {
+        self.operation.set(op);
+        self.mux.do_next_op();
+    }
+}
+
+impl<'a, B: BlockDevice<'a>> BlockDevice<'a> for VirtualBlockDevice<'a, B> {
+    fn set_client(&self, client: &'a dyn BlockDeviceClient) {
+        self.client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        self.mux.device.block_size()
+    }
+
+    fn block_count(&self) -> u32 {
+        self.mux
+            .resolve(self.region)
+            .map_or(0, |region| region.count)
+    }
+
+    fn read_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let op = Op::Read { block, count };
+        if let Err(e) = self.check(op, Some(buffer.len())) {
+            return Err((e, buffer));
+        }
+        self.buffer.replace(buffer);
+        self.queue(op);
+        Ok(())
+    }
+
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let op = Op::Write { block, count };
+        if let Err(e) = self.check(op, Some(buffer.len())) {
+            return Err((e, buffer));
+        }
+        self.buffer.replace(buffer);
+        self.queue(op);
+        Ok(())
+    }
+
+    fn erase_blocks(&self, block: u32, count: u32) -> Result<(), ErrorCode> {
+        let op = Op::Erase { block, count };
+        self.check(op, None)?;
+        self.queue(op);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), ErrorCode> {
+        self.check(Op::Flush, None)?;
+        self.queue(Op::Flush);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern crate std;
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    const BLOCK_SIZE: usize = 512;
+    const BLOCKS: usize = 64;
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum Request {
+        Read { block: u32, count: u32 },
+        Write { block: u32, count: u32 },
+        Erase { block: u32, count: u32 },
+    }
+
+    /// An in-memory device. Requests only complete when `complete()` is
+    /// called, so tests can check what the mux passes to the device.
+    struct TestDevice {
+        data: TakeCell<'static, [u8]>,
+        request: Cell<Option<Request>>,
+        buffer: TakeCell<'static, [u8]>,
+        /// Complete the next request with this error.
+        error: Cell<Option<ErrorCode>>,
+        client: OptionalCell<&'static dyn BlockDeviceClient>,
+    }
+
+    impl TestDevice {
+        /// A device where each byte of a block holds the block number.
+        fn new() -> Self {
+            let data: Vec<u8> = (0..BLOCKS * BLOCK_SIZE)
+                .map(|i| (i / BLOCK_SIZE) as u8)
+                .collect();
+            Self {
+                data: TakeCell::new(Box::leak(data.into_boxed_slice())),
+                request: Cell::new(None),
+                buffer: TakeCell::empty(),
+                error: Cell::new(None),
+                client: OptionalCell::empty(),
+            }
+        }
+
+        fn block(&self, block: u32) -> Vec<u8> {
+            let start = block as usize * BLOCK_SIZE;
+            self.data
+                .map(|data| data[start..start + BLOCK_SIZE].to_vec())
+                .unwrap()
+        }
+
+        fn set_block(&self, block: u32, contents: &[u8]) {
+            let start = block as usize * BLOCK_SIZE;
+            self.data
+                .map(|data| data[start..start + contents.len()].copy_from_slice(contents));
+        }
+
+        fn start(&self, request: Request) -> Result<(), ErrorCode> {
+            if self.request.get().is_some() {
+                return Err(ErrorCode::BUSY);
+            }
+            self.request.set(Some(request));
+            Ok(())
+        }
+
+        /// Carry out the request in progress and pass the result to the
+        /// client.
+        fn complete(&self) {
+            let request = self.request.take().expect("no request in progress");
+            let result = self.error.take().map_or(Ok(()), Err);
+            let range = |block: u32, count: u32| {
+                block as usize * BLOCK_SIZE..(block + count) as usize * BLOCK_SIZE
+            };
+            let client = self.client.get().unwrap();
+            match request {
+                Request::Read { block, count } => {
+                    let buffer = self.buffer.take().unwrap();
+                    if result.is_ok() {
+                        let len = count as usize * BLOCK_SIZE;
+                        self.data
+                            .map(|data| buffer[..len].copy_from_slice(&data[range(block, count)]));
+                    }
+                    client.read_complete(buffer, result);
+                }
+                Request::Write { block, count } => {
+                    let buffer = self.buffer.take().unwrap();
+                    if result.is_ok() {
+                        let len = count as usize * BLOCK_SIZE;
+                        self.data
+                            .map(|data| data[range(block, count)].copy_from_slice(&buffer[..len]));
+                    }
+                    client.write_complete(buffer, result);
+                }
+                Request::Erase { .. } => client.erase_complete(result),
+            }
+        }
+    }
+
+    impl BlockDevice<'static> for TestDevice {
+        fn set_client(&self, client: &'static dyn BlockDeviceClient) {
+            self.client.set(client);
+        }
+
+        fn block_size(&self) -> usize {
+            BLOCK_SIZE
+        }
+
+        fn block_count(&self) -> u32 {
+            BLOCKS as u32
+        }
+
+        fn read_blocks(
+            &self,
+            buffer: &'static mut [u8],
+            block: u32,
+            count: u32,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            match self.start(Request::Read { block, count }) {
+                Ok(()) => {
+                    self.buffer.replace(buffer);
+                    Ok(())
+                }
+                Err(e) => Err((e, buffer)),
+            }
+        }
+
+        fn write_blocks(
+            &self,
+            buffer: &'static mut [u8],
+            block: u32,
+            count: u32,
+        ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+            match self.start(Request::Write { block, count }) {
+                Ok(()) => {
+                    self.buffer.replace(buffer);
+                    Ok(())
+                }
+                Err(e) => Err((e, buffer)),
+            }
+        }
+
+        fn erase_blocks(&self, block: u32, count: u32) -> Result<(), ErrorCode> {
+            self.start(Request::Erase { block, count })
+        }
+
+        fn flush(&self) -> Result<(), ErrorCode> {
+            Err(ErrorCode::NOSUPPORT)
+        }
+    }
+
+    /// Records the result of the last request of a `VirtualBlockDevice`.
+    struct TestClient {
+        buffer: TakeCell<'static, [u8]>,
+        result: Cell<Option<Result<(), ErrorCode>>>,
+    }
+
+    impl BlockDeviceClient for TestClient {
+        fn read_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+            self.buffer.replace(buffer);
+            self.result.set(Some(result));
+        }
+
+        fn write_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>) {
+            self.buffer.replace(buffer);
+            self.result.set(Some(result));
+        }
+
+        fn erase_complete(&self, result: Result<(), ErrorCode>) {
+            self.result.set(Some(result));
+        }
+
+        fn flush_complete(&self, result: Result<(), ErrorCode>) {
+            self.result.set(Some(result));
+        }
+    }
+
+    type Mux = MuxBlockDevice<'static, TestDevice>;
+    type User = VirtualBlockDevice<'static, TestDevice>;
+
+    fn mux(device: TestDevice) -> (&'static TestDevice, &'static Mux) {
+        let device = Box::leak(Box::new(device));
+        let mux = Box::leak(Box::new(MuxBlockDevice::new(
+            device,
+            Box::leak(Box::new([0; TABLE_BUFFER_LEN])),
+        )));
+        device.set_client(mux);
+        (device, mux)
+    }
+
+    fn user(mux: &'static Mux, region: Region) -> (&'static User, &'static TestClient) {
+        let user = Box::leak(Box::new(VirtualBlockDevice::new(mux, region)));
+        user.setup();
+        let client = Box::leak(Box::new(TestClient {
+            buffer: TakeCell::empty(),
+            result: Cell::new(None),
+        }));
+        user.set_client(client);
+        (user, client)
+    }
+
+    fn buffer(blocks: usize) -> &'static mut [u8] {
+        Box::leak(vec![0; blocks * BLOCK_SIZE].into_boxed_slice())
+    }
+
+    /// An MBR with entries of (type, start, count).
+    fn mbr(entries: &[(u8, u32, u32)]) -> Vec<u8> {
+        let mut block = vec![0; BLOCK_SIZE];
+        for (i, (kind, start, count)) in entries.iter().enumerate() {
+            let entry = &mut block[446 + 16 * i..446 + 16 * (i + 1)];
+            entry[4] = *kind;
+            entry[8..12].copy_from_slice(&start.to_le_bytes());
+            entry[12..16].copy_from_slice(&count.to_le_bytes());
+        }
+        block[510..].copy_from_slice(&[0x55, 0xAA]);
+        block
+    }
+
+    #[test]
+    fn mbr_partitions() {
+        let device = TestDevice::new();
+        device.set_block(0, &mbr(&[(0x0C, 4, 8), (0x83, 16, 8)]));
+        let (device, mux) = mux(device);
+        let (second, client) = user(mux, Region::Partition(1));
+        let (missing, _) = user(mux, Region::Partition(2));
+
+        // The partition table is read before the first request to a
+        // partition is passed to the device.
+        assert_eq!(second.block_count(), 0);
+        assert!(second.read_blocks(buffer(2), 6, 2).is_ok());
+        assert_eq!(
+            device.request.get(),
+            Some(Request::Read { block: 0, count: 1 })
+        );
+        device.complete();
+        assert_eq!(
+            device.request.get(),
+            Some(Request::Read {
+                block: 22,
+                count: 2
+            })
+        );
+        device.complete();
+
+        assert_eq!(client.result.get(), Some(Ok(())));
+        let data = client.buffer.take().unwrap();
+        assert_eq!(data[..BLOCK_SIZE], device.block(22));
+        assert_eq!(data[BLOCK_SIZE..], device.block(23));
+
+        assert_eq!(second.block_count(), 8);
+        assert_eq!(missing.block_count(), 0);
+        assert_eq!(
+            second.read_blocks(data, 7, 2).map_err(|(e, _)| e),
+            Err(ErrorCode::INVAL)
+        );
+    }
+
+    #[test]
+    fn gpt_partitions() {
+        let device = TestDevice::new();
+        device.set_block(0, &mbr(&[(0xEE, 1, BLOCKS as u32 - 1)]));
+        let mut header = vec![0; BLOCK_SIZE];
+        header[..8].copy_from_slice(b"EFI PART");
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&8u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        device.set_block(1, &header);
+        // Four entries per block, with an unused entry before the second
+        // partition and the second partition in the next block.
+        let mut entries = vec![0; 2 * BLOCK_SIZE];
+        for (index, first, last) in [(0, 34u64, 39u64), (4, 40, 59)] {
+            let entry = &mut entries[index * 128..(index + 1) * 128];
+            entry[..16].fill(0xAB);
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+        }
+        device.set_block(2, &entries);
+        let (device, mux) = mux(device);
+        let (first, _) = user(mux, Region::Partition(0));
+        let (second, client) = user(mux, Region::Partition(1));
+
+        assert!(second.read_blocks(buffer(1), 19, 1).is_ok());
+        for block in 0..4 {
+            assert_eq!(
+                device.request.get(),
+                Some(Request::Read { block, count: 1 })
+            );
+            device.complete();
+        }
+        assert_eq!(
+            device.request.get(),
+            Some(Request::Read {
+                block: 59,
+                count: 1
+            })
+        );
+        device.complete();
+
+        assert_eq!(client.result.get(), Some(Ok(())));
+        assert_eq!(*client.buffer.take().unwrap(), device.block(59));
+        assert_eq!(first.block_count(), 6);
+        assert_eq!(second.block_count(), 20);
+    }
+
+    #[test]
+    fn blocks_are_translated_and_checked() {
+        let (device, mux) = mux(TestDevice::new());
+        let (blocks, client) = user(mux, Region::Blocks(Partition { start: 8, count: 4 }));
+        let (whole, whole_client) = user(mux, Region::Device);
+
+        assert_eq!(blocks.block_count(), 4);
+        assert_eq!(whole.block_count(), BLOCKS as u32);
+        assert_eq!(
+            blocks.read_blocks(buffer(2), 3, 2).map_err(|(e, _)| e),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(blocks.erase_blocks(0, 0), Err(ErrorCode::INVAL));
+        assert_eq!(
+            blocks.read_blocks(buffer(1), 0, 2).map_err(|(e, _)| e),
+            Err(ErrorCode::SIZE)
+        );
+
+        // Requests are passed to the device one at a time.
+        let data = buffer(1);
+        data.fill(0x5A);
+        assert!(blocks.write_blocks(data, 1, 1).is_ok());
+        assert!(whole.erase_blocks(60, 4).is_ok());
+        assert_eq!(
+            device.request.get(),
+            Some(Request::Write { block: 9, count: 1 })
+        );
+        device.complete();
+        assert_eq!(client.result.get(), Some(Ok(())));
+        assert_eq!(device.block(9), vec![0x5A; BLOCK_SIZE]);
+        assert_eq!(
+            device.request.get(),
+            Some(Request::Erase {
+                block: 60,
+                count: 4
+            })
+        );
+        device.complete();
+        assert_eq!(whole_client.result.get(), Some(Ok(())));
+    }
+
+    #[test]
+    fn failed_table_read() {
+        let device = TestDevice::new();
+        device.set_block(0, &mbr(&[(0x0C, 4, 8)]));
+        let (device, mux) = mux(device);
+        let (partition, client) = user(mux, Region::Partition(0));
+
+        // Requests waiting for the table fail if it cannot be read.
+        assert!(partition.read_blocks(buffer(1), 0, 1).is_ok());
+        device.error.set(Some(ErrorCode::FAIL));
+        device.complete();
+        assert_eq!(device.request.get(), None);
+        assert_eq!(client.result.get(), None);
+        mux.handle_deferred_call();
+        assert_eq!(client.result.get(), Some(Err(ErrorCode::FAIL)));
+
+        // The table is read again by the next request, which is only checked
+        // against the partition once it is known.
+        assert!(partition
+            .read_blocks(client.buffer.take().unwrap(), 8, 1)
+            .is_ok());
+        assert_eq!(
+            device.request.get(),
+            Some(Request::Read { block: 0, count: 1 })
+        );
+        device.complete();
+        assert_eq!(device.request.get(), None);
+        mux.handle_deferred_call();
+        assert_eq!(client.result.get(), Some(Err(ErrorCode::INVAL)));
+        assert_eq!(partition.block_count(), 8);
+    }
+}
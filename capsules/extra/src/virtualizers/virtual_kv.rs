@@ -32,6 +32,8 @@
 //!    hil::flash
 //! ```
 
+use core::cell::Cell;
+
 use kernel::collections::list::{List, ListLink, ListNode};
 
 use kernel::hil::kv;
@@ -48,7 +50,9 @@ enum Operation {
     Delete,
     Add,
     Update,
+    NextKey,
     GarbageCollect,
+    RemainingQuota,
 }
 
 pub struct VirtualKVPermissions<'a, V: kv::KVPermissions<'a>> {
@@ -61,6 +65,7 @@ pub struct VirtualKVPermissions<'a, V: kv::KVPermissions<'a>> {
     key: MapCell<SubSliceMut<'static, u8>>,
     value: MapCell<SubSliceMut<'static, u8>>,
     valid_ids: OptionalCell<StoragePermissions>,
+    position: Cell<usize>,
 }
 
 impl<'a, V: kv::KVPermissions<'a>> ListNode<'a, VirtualKVPermissions<'a, V>>
@@ -81,6 +86,7 @@ impl<'a, V: kv::KVPermissions<'a>> VirtualKVPermissions<'a, V> {
             key: MapCell::empty(),
             value: MapCell::empty(),
             valid_ids: OptionalCell::empty(),
+            position: Cell::new(0),
         }
     }
 
@@ -225,6 +231,35 @@ impl<'a, V: kv::KVPermissions<'a>> kv::KVPermissions<'a> for VirtualKVPermission
             .map_err(|e| (self.key.take().unwrap(), e))
     }
 
+    fn next_key(
+        &self,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+        permissions: StoragePermissions,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if self.operation.is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+
+        self.operation.set(Operation::NextKey);
+        self.valid_ids.set(permissions);
+        self.position.set(position);
+        self.key.replace(key);
+        self.value.replace(value);
+
+        self.mux_kv
+            .do_next_op(false)
+            .map_err(|e| (self.key.take().unwrap(), self.value.take().unwrap(), e))
+    }
+
     fn garbage_collect(&self) -> Result<(), ErrorCode> {
         if self.operation.is_some() {
             return Err(ErrorCode::BUSY);
@@ -235,6 +270,17 @@ impl<'a, V: kv::KVPermissions<'a>> kv::KVPermissions<'a> for VirtualKVPermission
         self.mux_kv.do_next_op(false)
     }
 
+    fn remaining_quota(&self, permissions: StoragePermissions) -> Result<(), ErrorCode> {
+        if self.operation.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.operation.set(Operation::RemainingQuota);
+        self.valid_ids.set(permissions);
+
+        self.mux_kv.do_next_op(false)
+    }
+
     fn header_size(&self) -> usize {
         self.mux_kv.kv.header_size()
     }
@@ -256,6 +302,11 @@ impl<'a, V: kv::KVPermissions<'a>> MuxKVPermissions<'a, V> {
     }
 
     fn do_next_op(&self, async_op: bool) -> Result<(), ErrorCode> {
+        // Only one operation can be outstanding with the underlying store.
+        if self.inflight.is_some() {
+            return Ok(());
+        }
+
         // Find a virtual device which has pending work.
         let mnode = self.users.iter().find(|node| node.operation.is_some());
 
@@ -283,6 +334,29 @@ impl<'a, V: kv::KVPermissions<'a>> MuxKVPermissions<'a, V> {
                     };
                 }
 
+                // RemainingQuota doesn't have a key either.
+                if op == Operation::RemainingQuota {
+                    return node.valid_ids.map_or(Ok(()), |perms| {
+                        match self.kv.remaining_quota(perms) {
+                            Ok(()) => {
+                                self.inflight.set(node);
+                                Ok(())
+                            }
+                            Err(e) => {
+                                node.operation.clear();
+                                if async_op {
+                                    node.client.map(move |cb| {
+                                        cb.remaining_quota_complete(Err(e));
+                                    });
+                                    Ok(())
+                                } else {
+                                    Err(e)
+                                }
+                            }
+                        }
+                    });
+                }
+
                 node.key.take().map_or(Ok(()), |key| match op {
                     Operation::Get => node.value.take().map_or(Ok(()), |value| {
                         node.valid_ids.map_or(Ok(()), |perms| {
@@ -397,7 +471,32 @@ impl<'a, V: kv::KVPermissions<'a>> MuxKVPermissions<'a, V> {
                                 }
                             })
                     }
-                    Operation::GarbageCollect => Err(ErrorCode::NOSUPPORT),
+                    Operation::NextKey => node.value.take().map_or(Ok(()), |value| {
+                        node.valid_ids.map_or(Ok(()), |perms| {
+                            match self.kv.next_key(node.position.get(), key, value, perms) {
+                                Ok(()) => {
+                                    self.inflight.set(node);
+                                    Ok(())
+                                }
+                                Err((key, value, e)) => {
+                                    node.operation.clear();
+                                    if async_op {
+                                        node.client.map(move |cb| {
+                                            cb.next_key_complete(Err(e), 0, key, value);
+                                        });
+                                        Ok(())
+                                    } else {
+                                        node.key.replace(key);
+                                        node.value.replace(value);
+                                        Err(e)
+                                    }
+                                }
+                            }
+                        })
+                    }),
+                    Operation::GarbageCollect | Operation::RemainingQuota => {
+                        Err(ErrorCode::NOSUPPORT)
+                    }
                 })
             })
         })
@@ -480,6 +579,23 @@ impl<'a, V: kv::KVPermissions<'a>> kv::KVClient for MuxKVPermissions<'a, V> {
         let _ = self.do_next_op(true);
     }
 
+    fn next_key_complete(
+        &self,
+        result: Result<usize, ErrorCode>,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) {
+        self.inflight.take().map(|node| {
+            node.operation.clear();
+            node.client.map(move |cb| {
+                cb.next_key_complete(result, position, key, value);
+            });
+        });
+
+        let _ = self.do_next_op(true);
+    }
+
     fn garbage_collection_complete(&self, result: Result<(), ErrorCode>) {
         self.inflight.take().map(|node| {
             node.operation.clear();
@@ -490,4 +606,20 @@ impl<'a, V: kv::KVPermissions<'a>> kv::KVClient for MuxKVPermissions<'a, V> {
 
         let _ = self.do_next_op(true);
     }
+
+    fn commit_transaction_complete(&self, _result: Result<(), ErrorCode>) {
+        // Transactions are not part of the `KVPermissions` interface, so
+        // none are started.
+    }
+
+    fn remaining_quota_complete(&self, result: Result<usize, ErrorCode>) {
+        self.inflight.take().map(|node| {
+            node.operation.clear();
+            node.client.map(move |cb| {
+                cb.remaining_quota_complete(result);
+            });
+        });
+
+        let _ = self.do_next_op(true);
+    }
 }
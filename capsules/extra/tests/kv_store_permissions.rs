@@ -0,0 +1,673 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2023.
+
+//! Tests for the storage quotas of `KVStorePermissions`.
+//!
+//! These are integration tests because creating `StoragePermissions` with a
+//! quota requires a capability, which capsules can not create.
+
+use capsules_extra::kv_store_permissions::{
+    KVStorePermissions, WriteIdCounter, DEFAULT_WRITE_ID_COUNTERS, HEADER_LENGTH, SCAN_KEY_LENGTH,
+};
+use core::cell::{Cell, RefCell};
+use core::num::NonZeroU32;
+use kernel::capabilities::ApplicationStorageCapability;
+use kernel::create_capability;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::kv::{self, KVClient, KVPermissions, KV};
+use kernel::storage_permissions::StoragePermissions;
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::SubSliceMut;
+use kernel::ErrorCode;
+
+/// Quota used when only the usage of a `write_id` is of interest.
+const LARGE_QUOTA: usize = 1000;
+
+fn leak<T>(value: T) -> &'static T {
+    Box::leak(Box::new(value))
+}
+
+fn buffer(contents: &[u8], length: usize) -> SubSliceMut<'static, u8> {
+    let mut buffer = vec![0; length];
+    buffer[..contents.len()].copy_from_slice(contents);
+    SubSliceMut::new(Box::leak(buffer.into_boxed_slice()))
+}
+
+/// A stored object with the Tock header and `length` bytes of value.
+fn object(write_id: u32, length: usize) -> Vec<u8> {
+    let mut object = vec![0; HEADER_LENGTH + length];
+    object[1..5].copy_from_slice(&(length as u32).to_le_bytes());
+    object[5..9].copy_from_slice(&write_id.to_le_bytes());
+    object
+}
+
+/// Permissions of the app with `write_id`, which may also modify the objects
+/// of `modify`.
+fn permissions(write_id: u32, modify: &'static [u32], quota: usize) -> StoragePermissions {
+    let cap = create_capability!(ApplicationStorageCapability);
+    StoragePermissions::new_listed(
+        NonZeroU32::new(write_id).unwrap(),
+        true,
+        true,
+        &[],
+        modify,
+        &cap,
+    )
+    .with_quota(quota, &cap)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Request {
+    Get,
+    Set,
+    Add,
+    Update,
+    Delete,
+    NextKey(usize),
+}
+
+/// An in-memory K-V store that keeps keys unhashed. Requests only complete
+/// when `complete()` is called.
+struct TestKV {
+    objects: RefCell<Vec<(Vec<u8>, Vec<u8>)>>,
+    requests: RefCell<Vec<Request>>,
+    request: Cell<Option<Request>>,
+    key: MapCell<SubSliceMut<'static, u8>>,
+    value: MapCell<SubSliceMut<'static, u8>>,
+    client: OptionalCell<&'static dyn kv::KVClient>,
+}
+
+impl TestKV {
+    fn new() -> Self {
+        Self {
+            objects: RefCell::new(Vec::new()),
+            requests: RefCell::new(Vec::new()),
+            request: Cell::new(None),
+            key: MapCell::empty(),
+            value: MapCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn stored(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let objects = self.objects.borrow();
+        objects
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+    }
+
+    fn store(&self, key: &[u8], value: Vec<u8>) {
+        let mut objects = self.objects.borrow_mut();
+        match objects.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => objects.push((key.to_vec(), value)),
+        }
+    }
+
+    /// Return the requests issued since the last call and forget them.
+    fn take_requests(&self) -> Vec<Request> {
+        self.requests.take()
+    }
+
+    fn start(
+        &self,
+        request: Request,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        if self.request.get().is_some() {
+            return Err((key, value, ErrorCode::BUSY));
+        }
+        self.request.set(Some(request));
+        self.requests.borrow_mut().push(request);
+        self.key.replace(key);
+        self.value.replace(value);
+        Ok(())
+    }
+
+    /// Copy `stored` into `value` like TicKV, which only slices the buffer to
+    /// the value if it fits.
+    fn copy_value(stored: &[u8], value: &mut SubSliceMut<'static, u8>) -> bool {
+        let length = core::cmp::min(stored.len(), value.len());
+        value[..length].copy_from_slice(&stored[..length]);
+        if length == stored.len() {
+            value.slice(..length);
+        }
+        length == stored.len()
+    }
+
+    /// Carry out the request in progress and pass the result to the client.
+    /// Returns `false` if there was none.
+    fn complete(&self) -> bool {
+        let Some(request) = self.request.take() else {
+            return false;
+        };
+        let mut key = self.key.take().unwrap();
+        let mut value = self.value.take().unwrap();
+        let client = self.client.get().unwrap();
+        let stored = self.stored(key.as_slice());
+        match request {
+            Request::Get => {
+                let result = match stored {
+                    Some(stored) if Self::copy_value(&stored, &mut value) => Ok(()),
+                    Some(_) => Err(ErrorCode::SIZE),
+                    None => Err(ErrorCode::NOSUPPORT),
+                };
+                client.get_complete(result, key, value);
+            }
+            Request::Set => {
+                self.store(key.as_slice(), value.as_slice().to_vec());
+                client.set_complete(Ok(()), key, value);
+            }
+            Request::Add => {
+                let result = match stored {
+                    Some(_) => Err(ErrorCode::NOSUPPORT),
+                    None => {
+                        self.store(key.as_slice(), value.as_slice().to_vec());
+                        Ok(())
+                    }
+                };
+                client.add_complete(result, key, value);
+            }
+            Request::Update => {
+                let result = match stored {
+                    Some(_) => {
+                        self.store(key.as_slice(), value.as_slice().to_vec());
+                        Ok(())
+                    }
+                    None => Err(ErrorCode::NOSUPPORT),
+                };
+                client.update_complete(result, key, value);
+            }
+            Request::Delete => {
+                let result = match stored {
+                    Some(_) => {
+                        self.objects
+                            .borrow_mut()
+                            .retain(|(k, _)| k != key.as_slice());
+                        Ok(())
+                    }
+                    None => Err(ErrorCode::NOSUPPORT),
+                };
+                client.delete_complete(result, key);
+            }
+            Request::NextKey(position) => {
+                let object = self.objects.borrow().get(position).cloned();
+                let result = match object {
+                    Some((stored_key, stored)) => {
+                        let length = core::cmp::min(stored_key.len(), key.len());
+                        key.slice(..length);
+                        key.as_mut_slice().copy_from_slice(&stored_key[..length]);
+                        Self::copy_value(&stored, &mut value);
+                        Ok(stored.len())
+                    }
+                    None => Err(ErrorCode::NOSUPPORT),
+                };
+                client.next_key_complete(result, position + 1, key, value);
+            }
+        }
+        true
+    }
+}
+
+impl KV<'static> for TestKV {
+    fn set_client(&self, client: &'static dyn kv::KVClient) {
+        self.client.set(client);
+    }
+
+    fn get(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        self.start(Request::Get, key, value)
+    }
+
+    fn set(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        self.start(Request::Set, key, value)
+    }
+
+    fn add(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        self.start(Request::Add, key, value)
+    }
+
+    fn update(
+        &self,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        self.start(Request::Update, key, value)
+    }
+
+    fn delete(
+        &self,
+        key: SubSliceMut<'static, u8>,
+    ) -> Result<(), (SubSliceMut<'static, u8>, ErrorCode)> {
+        self.start(Request::Delete, key, buffer(&[], 0))
+            .map_err(|(key, _, e)| (key, e))
+    }
+
+    fn next_key(
+        &self,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    > {
+        self.start(Request::NextKey(position), key, value)
+    }
+
+    fn garbage_collect(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn begin_transaction(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn commit_transaction(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn abort_transaction(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Done {
+    Set(Result<(), ErrorCode>),
+    Add(Result<(), ErrorCode>),
+    Update(Result<(), ErrorCode>),
+    Delete(Result<(), ErrorCode>),
+    RemainingQuota(Result<usize, ErrorCode>),
+}
+
+struct TestClient {
+    done: RefCell<Vec<Done>>,
+}
+
+impl KVClient for TestClient {
+    fn get_complete(
+        &self,
+        _result: Result<(), ErrorCode>,
+        _key: SubSliceMut<'static, u8>,
+        _value: SubSliceMut<'static, u8>,
+    ) {
+    }
+
+    fn set_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        _key: SubSliceMut<'static, u8>,
+        _value: SubSliceMut<'static, u8>,
+    ) {
+        self.done.borrow_mut().push(Done::Set(result));
+    }
+
+    fn add_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        _key: SubSliceMut<'static, u8>,
+        _value: SubSliceMut<'static, u8>,
+    ) {
+        self.done.borrow_mut().push(Done::Add(result));
+    }
+
+    fn update_complete(
+        &self,
+        result: Result<(), ErrorCode>,
+        _key: SubSliceMut<'static, u8>,
+        _value: SubSliceMut<'static, u8>,
+    ) {
+        self.done.borrow_mut().push(Done::Update(result));
+    }
+
+    fn delete_complete(&self, result: Result<(), ErrorCode>, _key: SubSliceMut<'static, u8>) {
+        self.done.borrow_mut().push(Done::Delete(result));
+    }
+
+    fn next_key_complete(
+        &self,
+        _result: Result<usize, ErrorCode>,
+        _position: usize,
+        _key: SubSliceMut<'static, u8>,
+        _value: SubSliceMut<'static, u8>,
+    ) {
+    }
+
+    fn garbage_collection_complete(&self, _result: Result<(), ErrorCode>) {}
+
+    fn commit_transaction_complete(&self, _result: Result<(), ErrorCode>) {}
+
+    fn remaining_quota_complete(&self, result: Result<usize, ErrorCode>) {
+        self.done.borrow_mut().push(Done::RemainingQuota(result));
+    }
+}
+
+struct Test {
+    kv: &'static TestKV,
+    store: &'static KVStorePermissions<'static, TestKV>,
+    client: &'static TestClient,
+}
+
+impl Test {
+    /// A store holding `objects`, with `counters` `write_id` counters.
+    fn new(objects: &[(&[u8], Vec<u8>)], counters: usize) -> Self {
+        let kv = leak(TestKV::new());
+        objects
+            .iter()
+            .for_each(|(key, value)| kv.store(key, value.clone()));
+        let write_id_counters: Vec<WriteIdCounter> =
+            (0..counters).map(|_| Cell::new(None)).collect();
+        let store = leak(KVStorePermissions::new(
+            kv,
+            Box::leak(Box::new([0; HEADER_LENGTH])),
+            Box::leak(Box::new([0; SCAN_KEY_LENGTH])),
+            Box::leak(write_id_counters.into_boxed_slice()),
+        ));
+        let client = leak(TestClient {
+            done: RefCell::new(Vec::new()),
+        });
+        kv.set_client(store);
+        store.set_client(client);
+        Self { kv, store, client }
+    }
+
+    /// Complete requests until the store is done, and return the callback it
+    /// issued.
+    fn run(&self) -> Done {
+        while self.kv.complete() {}
+        let mut done = self.client.done.borrow_mut();
+        assert_eq!(done.len(), 1);
+        done.pop().unwrap()
+    }
+
+    /// Whether the store was scanned since the last call.
+    fn scanned(&self) -> bool {
+        self.kv
+            .take_requests()
+            .iter()
+            .any(|request| matches!(request, Request::NextKey(_)))
+    }
+
+    /// A value of `length` bytes with space for the Tock header.
+    fn value(length: usize) -> SubSliceMut<'static, u8> {
+        buffer(&[], HEADER_LENGTH + length)
+    }
+
+    fn set(&self, key: &[u8], length: usize, permissions: StoragePermissions) -> Done {
+        assert!(self
+            .store
+            .set(buffer(key, key.len()), Self::value(length), permissions)
+            .is_ok());
+        self.run()
+    }
+
+    fn add(&self, key: &[u8], length: usize, permissions: StoragePermissions) -> Done {
+        assert!(self
+            .store
+            .add(buffer(key, key.len()), Self::value(length), permissions)
+            .is_ok());
+        self.run()
+    }
+
+    fn update(&self, key: &[u8], length: usize, permissions: StoragePermissions) -> Done {
+        assert!(self
+            .store
+            .update(buffer(key, key.len()), Self::value(length), permissions)
+            .is_ok());
+        self.run()
+    }
+
+    fn delete(&self, key: &[u8], permissions: StoragePermissions) -> Done {
+        assert!(self
+            .store
+            .delete(buffer(key, key.len()), permissions)
+            .is_ok());
+        self.run()
+    }
+
+    /// The bytes stored with `write_id`, as computed by the store.
+    fn usage(&self, write_id: u32) -> usize {
+        assert!(self
+            .store
+            .remaining_quota(permissions(write_id, &[], LARGE_QUOTA))
+            .is_ok());
+        while self.kv.complete() {}
+        if self.client.done.borrow().is_empty() {
+            // The usage was counted, the result is passed on from a deferred
+            // call.
+            self.store.handle_deferred_call();
+        }
+        match self.run() {
+            Done::RemainingQuota(Ok(remaining)) => LARGE_QUOTA - remaining,
+            done => panic!("unexpected {:?}", done),
+        }
+    }
+}
+
+#[test]
+fn first_use_scan() {
+    let test = Test::new(
+        &[
+            (b"a", object(1, 10)),
+            (b"b", object(2, 20)),
+            (b"c", object(1, 30)),
+        ],
+        DEFAULT_WRITE_ID_COUNTERS,
+    );
+
+    // The first quota check counts all objects in the store.
+    let used = 2 * HEADER_LENGTH + 40;
+    assert_eq!(test.usage(1), used);
+    assert_eq!(
+        test.kv.take_requests(),
+        [0, 1, 2, 3].map(Request::NextKey).to_vec()
+    );
+
+    // Later checks use the counters.
+    assert_eq!(test.usage(2), HEADER_LENGTH + 20);
+    assert_eq!(test.usage(3), 0);
+    assert_eq!(
+        test.add(b"d", 5, permissions(1, &[], LARGE_QUOTA)),
+        Done::Add(Ok(()))
+    );
+    assert_eq!(test.usage(1), used + HEADER_LENGTH + 5);
+    assert!(!test.scanned());
+}
+
+#[test]
+fn set_overwriting_another_writers_object() {
+    let test = Test::new(
+        &[(b"a", object(1, 11)), (b"b", object(2, 20))],
+        DEFAULT_WRITE_ID_COUNTERS,
+    );
+    let length = 5;
+
+    // Replacing the object of write_id 2 frees nothing of the quota of
+    // write_id 1.
+    let quota = HEADER_LENGTH + 11 + HEADER_LENGTH + length;
+    assert_eq!(
+        test.set(b"b", length, permissions(1, &[2], quota - 1)),
+        Done::Set(Err(ErrorCode::NOMEM))
+    );
+    assert_eq!(test.kv.stored(b"b"), Some(object(2, 20)));
+    assert_eq!(
+        test.set(b"b", length, permissions(1, &[2], quota)),
+        Done::Set(Ok(()))
+    );
+
+    // The object moved from write_id 2 to write_id 1.
+    assert_eq!(test.kv.stored(b"b"), Some(object(1, length)));
+    assert_eq!(test.usage(1), quota);
+    assert_eq!(test.usage(2), 0);
+
+    // Without permission to modify it the object is not replaced.
+    assert_eq!(
+        test.set(b"b", length, permissions(3, &[], LARGE_QUOTA)),
+        Done::Set(Err(ErrorCode::NOSUPPORT))
+    );
+    assert_eq!(test.kv.stored(b"b"), Some(object(1, length)));
+    assert_eq!(test.usage(1), quota);
+    assert_eq!(test.usage(3), 0);
+}
+
+#[test]
+fn delete() {
+    let test = Test::new(
+        &[(b"a", object(1, 10)), (b"b", object(1, 20))],
+        DEFAULT_WRITE_ID_COUNTERS,
+    );
+    assert_eq!(test.usage(1), 2 * HEADER_LENGTH + 30);
+    assert!(test.scanned());
+
+    assert_eq!(
+        test.delete(b"a", permissions(1, &[], LARGE_QUOTA)),
+        Done::Delete(Ok(()))
+    );
+    assert_eq!(test.kv.stored(b"a"), None);
+    assert_eq!(test.usage(1), HEADER_LENGTH + 20);
+
+    // A delete without permission leaves the counters alone.
+    assert_eq!(
+        test.delete(b"b", permissions(2, &[], LARGE_QUOTA)),
+        Done::Delete(Err(ErrorCode::NOSUPPORT))
+    );
+    assert_eq!(test.usage(1), HEADER_LENGTH + 20);
+
+    assert_eq!(
+        test.delete(b"b", permissions(1, &[], LARGE_QUOTA)),
+        Done::Delete(Ok(()))
+    );
+    assert_eq!(test.usage(1), 0);
+    assert!(!test.scanned());
+}
+
+#[test]
+fn table_overflow_is_incomplete() {
+    let test = Test::new(
+        &[
+            (b"a", object(1, 10)),
+            (b"b", object(2, 20)),
+            (b"c", object(3, 30)),
+        ],
+        2,
+    );
+    assert_eq!(
+        test.add(b"d", 1, permissions(1, &[], LARGE_QUOTA)),
+        Done::Add(Ok(()))
+    );
+    assert!(test.scanned());
+
+    // The write_ids that got a counter don't need a scan.
+    assert_eq!(test.usage(1), 2 * HEADER_LENGTH + 11);
+    assert_eq!(
+        test.add(b"e", 2, permissions(2, &[], LARGE_QUOTA)),
+        Done::Add(Ok(()))
+    );
+    assert_eq!(test.usage(2), 2 * HEADER_LENGTH + 22);
+    assert!(!test.scanned());
+
+    // The usage of a write_id without a counter is found by a scan each time
+    // it is needed, and is still enforced.
+    let used = HEADER_LENGTH + 30;
+    assert_eq!(test.usage(3), used);
+    assert!(test.scanned());
+    assert_eq!(
+        test.add(b"f", 3, permissions(3, &[], used + HEADER_LENGTH + 2)),
+        Done::Add(Err(ErrorCode::NOMEM))
+    );
+    assert!(test.scanned());
+    assert_eq!(
+        test.add(b"f", 3, permissions(3, &[], used + HEADER_LENGTH + 3)),
+        Done::Add(Ok(()))
+    );
+    assert!(test.scanned());
+    assert_eq!(test.usage(3), used + HEADER_LENGTH + 3);
+    assert!(test.scanned());
+}
+
+#[test]
+fn nomem_boundary() {
+    let test = Test::new(&[(b"a", object(1, 10))], DEFAULT_WRITE_ID_COUNTERS);
+    let quota = HEADER_LENGTH + 10 + HEADER_LENGTH + 20;
+
+    assert_eq!(
+        test.add(b"b", 21, permissions(1, &[], quota)),
+        Done::Add(Err(ErrorCode::NOMEM))
+    );
+    assert_eq!(test.kv.stored(b"b"), None);
+    assert_eq!(
+        test.add(b"b", 20, permissions(1, &[], quota)),
+        Done::Add(Ok(()))
+    );
+    assert_eq!(test.usage(1), quota);
+
+    // Replacing an object only needs space for the difference.
+    assert_eq!(
+        test.update(b"a", 11, permissions(1, &[], quota)),
+        Done::Update(Err(ErrorCode::NOMEM))
+    );
+    assert_eq!(test.kv.stored(b"a"), Some(object(1, 10)));
+    assert_eq!(
+        test.update(b"a", 10, permissions(1, &[], quota)),
+        Done::Update(Ok(()))
+    );
+    assert_eq!(
+        test.set(b"a", 4, permissions(1, &[], quota)),
+        Done::Set(Ok(()))
+    );
+    assert_eq!(test.usage(1), quota - 6);
+}
@@ -9,3 +9,4 @@ pub mod process_checker;
 pub mod process_policies;
 pub mod process_printer;
 pub mod storage_permissions;
+pub mod update_rollback;
@@ -8,9 +8,14 @@
 //! managing processes. For example, these policies control decisions such as
 //! whether a specific process should be restarted.
 
+use core::cell::Cell;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
 use kernel::process;
 use kernel::process::Process;
 use kernel::process::ProcessFaultPolicy;
+use kernel::Kernel;
 
 /// Simply panic the entire board if a process faults.
 pub struct PanicFaultPolicy {}
@@ -116,3 +121,189 @@ impl ProcessFaultPolicy for ThresholdRestartThenPanicFaultPolicy {
         }
     }
 }
+
+/// Back-off state of one application for [`BackoffRestartFaultPolicy`].
+#[derive(Clone, Copy)]
+struct Backoff<T: Ticks> {
+    /// Start of the application's binary in flash, which identifies the
+    /// application across restarts.
+    flash_start: usize,
+    /// Number of faults without a period of healthy running in between.
+    consecutive_faults: u32,
+    /// When the process was last restarted or faulted.
+    reference: T,
+    /// If a restart is pending, the delay after `reference` until it happens.
+    restart_delay: Option<T>,
+}
+
+/// Implementation of `ProcessFaultPolicy` that restarts faulting processes
+/// after an exponentially growing delay.
+///
+/// When a process faults it is stopped, and restarted after `base_delay_ms`.
+/// Every consecutive fault doubles the delay, up to `max_delay_ms`. Once a
+/// restarted process runs for `healthy_ms` without faulting, the delay goes
+/// back to `base_delay_ms`. This keeps a crash-looping process from starving
+/// the rest of the system.
+///
+/// The policy keeps track of up to `NUM_PROCS` applications. If more
+/// applications are faulting at the same time the process is stopped for
+/// good.
+pub struct BackoffRestartFaultPolicy<
+    'a,
+    A: Alarm<'a>,
+    C: ProcessManagementCapability,
+    const NUM_PROCS: usize,
+> {
+    alarm: &'a A,
+    kernel: &'static Kernel,
+    capability: C,
+    base_delay_ms: u32,
+    max_delay_ms: u32,
+    healthy_ms: u32,
+    backoffs: [Cell<Option<Backoff<A::Ticks>>>; NUM_PROCS],
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability, const NUM_PROCS: usize>
+    BackoffRestartFaultPolicy<'a, A, C, NUM_PROCS>
+{
+    pub fn new(
+        alarm: &'a A,
+        kernel: &'static Kernel,
+        capability: C,
+        base_delay_ms: u32,
+        max_delay_ms: u32,
+        healthy_ms: u32,
+    ) -> Self {
+        Self {
+            alarm,
+            kernel,
+            capability,
+            base_delay_ms,
+            max_delay_ms,
+            healthy_ms,
+            backoffs: [const { Cell::new(None) }; NUM_PROCS],
+        }
+    }
+
+    /// The delay before restarting a process after `consecutive_faults`
+    /// faults in a row.
+    fn delay_ms(&self, consecutive_faults: u32) -> u32 {
+        self.base_delay_ms
+            .saturating_mul(1 << consecutive_faults.min(31))
+            .min(self.max_delay_ms)
+    }
+
+    /// Find the back-off state for the application at `flash_start`, or a
+    /// free slot for it. Slots of applications without a pending restart are
+    /// reused if needed.
+    fn slot_for(&self, flash_start: usize) -> Option<&Cell<Option<Backoff<A::Ticks>>>> {
+        self.backoffs
+            .iter()
+            .find(|b| b.get().is_some_and(|b| b.flash_start == flash_start))
+            .or_else(|| self.backoffs.iter().find(|b| b.get().is_none()))
+            .or_else(|| {
+                self.backoffs
+                    .iter()
+                    .find(|b| b.get().is_some_and(|b| b.restart_delay.is_none()))
+            })
+    }
+
+    /// Set the alarm for the earliest pending restart, if there is one.
+    fn schedule(&self) {
+        let now = self.alarm.now();
+        let next = self
+            .backoffs
+            .iter()
+            .filter_map(|b| b.get())
+            .filter_map(|b| {
+                b.restart_delay.map(|delay| {
+                    let end = b.reference.wrapping_add(delay);
+                    if now.within_range(b.reference, end) {
+                        end.wrapping_sub(now)
+                    } else {
+                        A::Ticks::from(0)
+                    }
+                })
+            })
+            .min();
+        match next {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability, const NUM_PROCS: usize> ProcessFaultPolicy
+    for BackoffRestartFaultPolicy<'a, A, C, NUM_PROCS>
+{
+    fn action(&self, process: &dyn Process) -> process::FaultAction {
+        let flash_start = process.get_addresses().flash_start;
+        let Some(slot) = self.slot_for(flash_start) else {
+            kernel::debug!(
+                "Process {} faulted and was stopped.",
+                process.get_process_name()
+            );
+            return process::FaultAction::Stop;
+        };
+
+        let now = self.alarm.now();
+        let consecutive_faults = match slot.get() {
+            Some(backoff) if backoff.flash_start == flash_start => {
+                let healthy_end = backoff
+                    .reference
+                    .wrapping_add(self.alarm.ticks_from_ms(self.healthy_ms));
+                if now.within_range(backoff.reference, healthy_end) {
+                    backoff.consecutive_faults.saturating_add(1)
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        };
+        slot.set(Some(Backoff {
+            flash_start,
+            consecutive_faults,
+            reference: now,
+            restart_delay: Some(self.alarm.ticks_from_ms(self.delay_ms(consecutive_faults))),
+        }));
+        self.schedule();
+
+        process::FaultAction::Stop
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability, const NUM_PROCS: usize> AlarmClient
+    for BackoffRestartFaultPolicy<'a, A, C, NUM_PROCS>
+{
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        for slot in self.backoffs.iter() {
+            let Some(mut backoff) = slot.get() else {
+                continue;
+            };
+            let Some(delay) = backoff.restart_delay else {
+                continue;
+            };
+            if now.within_range(backoff.reference, backoff.reference.wrapping_add(delay)) {
+                continue;
+            }
+
+            backoff.reference = now;
+            backoff.restart_delay = None;
+            slot.set(Some(backoff));
+            self.kernel
+                .process_each_capability(&self.capability, |process| {
+                    // The process may have been started by other means in the
+                    // meantime, only restart it if it is still stopped.
+                    if process.get_addresses().flash_start == backoff.flash_start
+                        && process.get_state() == process::State::Faulted
+                    {
+                        process.try_restart(None);
+                    }
+                });
+        }
+        self.schedule();
+    }
+}
@@ -0,0 +1,89 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+use core::cmp;
+use kernel::capabilities::ApplicationStorageCapability;
+use kernel::platform::chip::Chip;
+use kernel::process::ProcessStandardStoragePermissionsPolicy;
+use kernel::storage_permissions::StoragePermissions;
+
+/// Limit the amount of storage applications may use based on the board
+/// configuration.
+///
+/// This wraps another storage permissions policy. Applications whose write ID
+/// is listed in `quotas` are limited to the listed number of bytes, and all
+/// other applications with a write ID are limited to `default_quota` (if it is
+/// `Some`). If the inner policy already assigned a quota (for example from the
+/// TBF header) the smaller of the two is used.
+pub struct QuotaStoragePermissions<
+    'a,
+    C: Chip,
+    D: kernel::process::ProcessStandardDebug,
+    P: ProcessStandardStoragePermissionsPolicy<C, D>,
+    CAP: ApplicationStorageCapability,
+> {
+    policy: &'a P,
+    quotas: &'a [(u32, usize)],
+    default_quota: Option<usize>,
+    cap: CAP,
+    _chip: core::marker::PhantomData<C>,
+    _debug: core::marker::PhantomData<D>,
+}
+
+impl<
+        'a,
+        C: Chip,
+        D: kernel::process::ProcessStandardDebug,
+        P: ProcessStandardStoragePermissionsPolicy<C, D>,
+        CAP: ApplicationStorageCapability,
+    > QuotaStoragePermissions<'a, C, D, P, CAP>
+{
+    pub fn new(
+        policy: &'a P,
+        quotas: &'a [(u32, usize)],
+        default_quota: Option<usize>,
+        cap: CAP,
+    ) -> Self {
+        Self {
+            policy,
+            quotas,
+            default_quota,
+            cap,
+            _chip: core::marker::PhantomData,
+            _debug: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<
+        C: Chip,
+        D: kernel::process::ProcessStandardDebug,
+        P: ProcessStandardStoragePermissionsPolicy<C, D>,
+        CAP: ApplicationStorageCapability,
+    > ProcessStandardStoragePermissionsPolicy<C, D> for QuotaStoragePermissions<'_, C, D, P, CAP>
+{
+    fn get_permissions(
+        &self,
+        process: &kernel::process::ProcessStandard<C, D>,
+    ) -> StoragePermissions {
+        let permissions = self.policy.get_permissions(process);
+
+        // Only applications that can write have anything to limit.
+        let board_quota = permissions.get_write_id().and_then(|write_id| {
+            self.quotas
+                .iter()
+                .find(|(id, _)| *id == write_id)
+                .map(|(_, quota)| *quota)
+                .or(self.default_quota)
+        });
+
+        match (permissions.get_quota(), board_quota) {
+            (Some(existing), Some(board)) => {
+                permissions.with_quota(cmp::min(existing, board), &self.cap)
+            }
+            (None, Some(board)) => permissions.with_quota(board, &self.cap),
+            (_, None) => permissions,
+        }
+    }
+}
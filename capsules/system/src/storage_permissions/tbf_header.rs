@@ -17,6 +17,9 @@ use kernel::storage_permissions::StoragePermissions;
 ///
 /// If the header is _not_ present, then the process will be assigned null
 /// permissions.
+///
+/// If the storage quota header is present, the number of bytes the process may
+/// store is limited to the quota.
 pub struct TbfHeaderStoragePermissions<
     C: Chip,
     D: kernel::process::ProcessStandardDebug,
@@ -57,7 +60,7 @@ impl<C: Chip, D: kernel::process::ProcessStandardDebug, CAP: ApplicationStorageC
                     let read_count_capped = cmp::min(read_count, 8);
                     let modify_count_capped = cmp::min(modify_count, 8);
 
-                    StoragePermissions::new_fixed_size(
+                    let permissions = StoragePermissions::new_fixed_size(
                         id,
                         write_allowed,
                         false,
@@ -66,7 +69,12 @@ impl<C: Chip, D: kernel::process::ProcessStandardDebug, CAP: ApplicationStorageC
                         modify_count_capped,
                         modify_ids,
                         &self.cap,
-                    )
+                    );
+
+                    match process.get_tbf_storage_quota() {
+                        Some(quota) => permissions.with_quota(quota as usize, &self.cap),
+                        None => permissions,
+                    }
                 } else {
                     StoragePermissions::new_null()
                 }
@@ -0,0 +1,373 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Automatic rollback of process updates.
+//!
+//! `UpdateRollback` sits between the app loader capsule and the dynamic binary
+//! storage. After a process was updated it watches the new process for a
+//! configurable window of time. If the process faults `max_faults` times
+//! within the window, or it is faulted or stopped when the window ends, the
+//! update is rolled back to the previous binary. Otherwise the update is
+//! committed once the window ends, which frees the flash of the previous
+//! binary.
+//!
+//! Faults are counted by [`UpdateRollbackFaultPolicy`], which must be the
+//! fault policy of the processes. It tells `UpdateRollback` about every fault
+//! and then applies the fault policy of the board, so this works with any
+//! fault policy. The process loader needs its fault policy before the dynamic
+//! binary storage, and so `UpdateRollback`, can be created, which is why the
+//! two are connected once both exist.
+//!
+//! An update stays pending across a reboot, as the previous binary is kept in
+//! flash until the update is committed. The board must call
+//! [`UpdateRollback::start`] once the processes have been loaded at boot,
+//! which watches the process of a pending update for a new window.
+//!
+//! ```text
+//! +-----------------------------------------------------------------+
+//! |               capsules::app_loader::AppLoader                   |
+//! +-----------------------------------------------------------------+
+//!         kernel::dynamic_binary_storage::DynamicProcessLoad
+//!         kernel::dynamic_binary_storage::DynamicProcessUpdate
+//! +-----------------------------------------------------------------+
+//! |               UpdateRollback (this)                             |
+//! +-----------------------------------------------------------------+
+//!         kernel::dynamic_binary_storage::DynamicProcessLoad
+//!         kernel::dynamic_binary_storage::DynamicProcessUpdate
+//! +-----------------------------------------------------------------+
+//! |               SequentialDynamicBinaryStorage                    |
+//! +-----------------------------------------------------------------+
+//! ```
+
+use core::cell::Cell;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::debug;
+use kernel::dynamic_binary_storage::{
+    DynamicProcessLoad, DynamicProcessLoadClient, DynamicProcessUpdate, DynamicProcessUpdateClient,
+};
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use kernel::process::{self, Process, ProcessFaultPolicy};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, Kernel};
+
+/// How long to wait before trying again to commit or roll back an update
+/// while the updater is busy.
+const RETRY_INTERVAL_MS: u32 = 100;
+
+/// Client of [`UpdateRollbackFaultPolicy`] that is told about process faults.
+pub trait ProcessFaultClient {
+    /// Called when `process` faulted, before the fault policy of the board
+    /// decides what happens to it.
+    fn process_faulted(&self, process: &dyn Process);
+}
+
+/// Fault policy that tells its client about every fault, and then applies
+/// `policy`.
+pub struct UpdateRollbackFaultPolicy<'a> {
+    policy: &'a dyn ProcessFaultPolicy,
+    client: OptionalCell<&'a dyn ProcessFaultClient>,
+}
+
+impl<'a> UpdateRollbackFaultPolicy<'a> {
+    pub fn new(policy: &'a dyn ProcessFaultPolicy) -> Self {
+        Self {
+            policy,
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn ProcessFaultClient) {
+        self.client.set(client);
+    }
+}
+
+impl ProcessFaultPolicy for UpdateRollbackFaultPolicy<'_> {
+    fn action(&self, process: &dyn Process) -> process::FaultAction {
+        self.client.map(|client| client.process_faulted(process));
+        self.policy.action(process)
+    }
+}
+
+/// What to do with the pending update.
+#[derive(Clone, Copy, PartialEq)]
+enum Decision {
+    Rollback,
+    Commit,
+}
+
+pub struct UpdateRollback<
+    'a,
+    A: Alarm<'a>,
+    U: DynamicProcessLoad + DynamicProcessUpdate + 'a,
+    C: ProcessManagementCapability,
+> {
+    updater: &'a U,
+    alarm: &'a A,
+    kernel: &'static Kernel,
+    capability: C,
+    /// Number of faults within the window that cause a rollback.
+    max_faults: usize,
+    /// Length of the window after an update.
+    window_ms: u32,
+    /// Start of the window of the pending update, if it is being watched.
+    window_start: OptionalCell<A::Ticks>,
+    /// Number of faults of the updated process within the window.
+    faults: Cell<usize>,
+    /// Decision about the pending update which the updater has not accepted
+    /// yet because it was busy.
+    decision: OptionalCell<Decision>,
+    /// Whether the current commit or rollback was requested by our client
+    /// rather than decided by us.
+    client_request: Cell<bool>,
+    update_client: OptionalCell<&'static dyn DynamicProcessUpdateClient>,
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        U: DynamicProcessLoad + DynamicProcessUpdate,
+        C: ProcessManagementCapability,
+    > UpdateRollback<'a, A, U, C>
+{
+    pub fn new(
+        updater: &'a U,
+        alarm: &'a A,
+        kernel: &'static Kernel,
+        capability: C,
+        max_faults: usize,
+        window_ms: u32,
+    ) -> Self {
+        Self {
+            updater,
+            alarm,
+            kernel,
+            capability,
+            max_faults,
+            window_ms,
+            window_start: OptionalCell::empty(),
+            faults: Cell::new(0),
+            decision: OptionalCell::empty(),
+            client_request: Cell::new(false),
+            update_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Watch the process of an update that was pending when the board
+    /// rebooted, if any. This must be called once the processes have been
+    /// loaded at boot.
+    pub fn start(&self) {
+        if self.updater.restore_pending_update().is_some() {
+            self.start_window();
+        }
+    }
+
+    /// Start watching the process of the pending update, until the end of
+    /// the window.
+    fn start_window(&self) {
+        let now = self.alarm.now();
+        self.window_start.set(now);
+        self.faults.set(0);
+        self.alarm
+            .set_alarm(now, self.alarm.ticks_from_ms(self.window_ms));
+    }
+
+    /// Whether the process for the binary at `flash_start` can run.
+    fn runnable(&self, flash_start: usize) -> bool {
+        let mut runnable = false;
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.get_addresses().flash_start == flash_start {
+                    runnable = !matches!(
+                        process.get_state(),
+                        process::State::Faulted
+                            | process::State::Terminated
+                            | process::State::Stopped(_)
+                    );
+                }
+            });
+        runnable
+    }
+
+    /// Decide what to do with the update to the binary at `flash_start`, if
+    /// the window is over or its process faulted too often.
+    fn decide(&self, flash_start: usize, window_start: A::Ticks) -> Option<Decision> {
+        let window_end = window_start.wrapping_add(self.alarm.ticks_from_ms(self.window_ms));
+        if self.faults.get() >= self.max_faults {
+            Some(Decision::Rollback)
+        } else if !self.alarm.now().within_range(window_start, window_end) {
+            // The update only succeeded if the process still runs.
+            if self.runnable(flash_start) {
+                Some(Decision::Commit)
+            } else {
+                Some(Decision::Rollback)
+            }
+        } else {
+            None
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        U: DynamicProcessLoad + DynamicProcessUpdate,
+        C: ProcessManagementCapability,
+    > ProcessFaultClient for UpdateRollback<'a, A, U, C>
+{
+    fn process_faulted(&self, process: &dyn Process) {
+        if self.window_start.is_none()
+            || self.updater.pending_update() != Some(process.get_addresses().flash_start)
+        {
+            return;
+        }
+        self.faults.set(self.faults.get() + 1);
+        if self.faults.get() == self.max_faults {
+            // Roll back from the alarm rather than while the kernel is
+            // handling the fault.
+            self.alarm.set_alarm(self.alarm.now(), A::Ticks::from(0));
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        U: DynamicProcessLoad + DynamicProcessUpdate,
+        C: ProcessManagementCapability,
+    > AlarmClient for UpdateRollback<'a, A, U, C>
+{
+    fn alarm(&self) {
+        let Some(window_start) = self.window_start.get() else {
+            return;
+        };
+        // Our client may have committed or rolled back the update already.
+        let Some(flash_start) = self.updater.pending_update() else {
+            self.window_start.clear();
+            self.decision.clear();
+            return;
+        };
+
+        if let Some(decision) = self
+            .decision
+            .get()
+            .or_else(|| self.decide(flash_start, window_start))
+        {
+            self.client_request.set(false);
+            let result = match decision {
+                Decision::Rollback => self.updater.rollback(),
+                Decision::Commit => self.updater.commit(),
+            };
+            match result {
+                Ok(()) => {
+                    self.window_start.clear();
+                    self.decision.clear();
+                }
+                // The updater is busy with something else, try again later.
+                Err(ErrorCode::BUSY) => {
+                    self.decision.set(decision);
+                    self.alarm.set_alarm(
+                        self.alarm.now(),
+                        self.alarm.ticks_from_ms(RETRY_INTERVAL_MS),
+                    );
+                }
+                Err(e) => {
+                    debug!("UpdateRollback: could not finish update: {:?}", e);
+                    self.window_start.clear();
+                    self.decision.clear();
+                }
+            }
+        } else {
+            // The alarm fired before the end of the window.
+            let window = self.alarm.ticks_from_ms(self.window_ms);
+            self.alarm.set_alarm(window_start, window);
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        U: DynamicProcessLoad + DynamicProcessUpdate,
+        C: ProcessManagementCapability,
+    > DynamicProcessUpdateClient for UpdateRollback<'a, A, U, C>
+{
+    fn update_done(&self, result: Result<(), ErrorCode>) {
+        if result.is_ok() {
+            self.start_window();
+        }
+        self.update_client.map(|client| client.update_done(result));
+    }
+
+    fn rollback_done(&self, result: Result<(), ErrorCode>) {
+        if self.client_request.take() {
+            self.update_client
+                .map(|client| client.rollback_done(result));
+        } else if result.is_err() {
+            debug!("UpdateRollback: rollback failed: {:?}", result);
+        }
+    }
+
+    fn commit_done(&self, result: Result<(), ErrorCode>) {
+        if self.client_request.take() {
+            self.update_client.map(|client| client.commit_done(result));
+        }
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        U: DynamicProcessLoad + DynamicProcessUpdate,
+        C: ProcessManagementCapability,
+    > DynamicProcessUpdate for UpdateRollback<'a, A, U, C>
+{
+    fn update(&self) -> Result<(), ErrorCode> {
+        self.updater.update()
+    }
+
+    fn rollback(&self) -> Result<(), ErrorCode> {
+        self.updater.rollback()?;
+        self.client_request.set(true);
+        self.window_start.clear();
+        self.decision.clear();
+        Ok(())
+    }
+
+    fn commit(&self) -> Result<(), ErrorCode> {
+        self.updater.commit()?;
+        self.client_request.set(true);
+        self.window_start.clear();
+        self.decision.clear();
+        Ok(())
+    }
+
+    fn pending_update(&self) -> Option<usize> {
+        self.updater.pending_update()
+    }
+
+    fn restore_pending_update(&self) -> Option<usize> {
+        self.updater.restore_pending_update()
+    }
+
+    fn set_update_client(&self, client: &'static dyn DynamicProcessUpdateClient) {
+        self.update_client.set(client);
+    }
+}
+
+impl<
+        'a,
+        A: Alarm<'a>,
+        U: DynamicProcessLoad + DynamicProcessUpdate,
+        C: ProcessManagementCapability,
+    > DynamicProcessLoad for UpdateRollback<'a, A, U, C>
+{
+    fn load(&self) -> Result<(), ErrorCode> {
+        self.updater.load()
+    }
+
+    fn set_load_client(&self, client: &'static dyn DynamicProcessLoadClient) {
+        self.updater.set_load_client(client);
+    }
+}
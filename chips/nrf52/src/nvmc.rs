@@ -325,9 +325,25 @@ impl Nvmc {
         page_number: usize,
         data: &'static mut NrfPage,
     ) -> Result<(), (ErrorCode, &'static mut NrfPage)> {
-        // Need to erase the page if the page is not filled
-        // with 0xFF.
-        if !self.is_page_blank(page_number) {
+        let word_at = |i: usize| -> u32 {
+            (data[i + 0] as u32) << 0
+                | (data[i + 1] as u32) << 8
+                | (data[i + 2] as u32) << 16
+                | (data[i + 3] as u32) << 24
+        };
+        let location_at = |i: usize| -> &VolatileCell<u32> {
+            let address = ((page_number * PAGE_SIZE) + i) as u32;
+            unsafe { &*(address as *const VolatileCell<u32>) }
+        };
+
+        // Words can only be written once between erases, so the page only
+        // needs to be erased if a word that changes has already been
+        // written. Otherwise only the words that change are written, which
+        // lets users such as `FlashCounter` update a page without wearing it.
+        let erase = (0..data.len())
+            .step_by(4)
+            .any(|i| location_at(i).get() != word_at(i) && location_at(i).get() != 0xFFFFFFFF);
+        if erase {
             self.erase_page_helper(page_number);
         }
 
@@ -335,15 +351,12 @@ impl Nvmc {
         self.registers.config.write(Configuration::WEN::Wen);
 
         for i in (0..data.len()).step_by(4) {
-            let word: u32 = (data[i + 0] as u32) << 0
-                | (data[i + 1] as u32) << 8
-                | (data[i + 2] as u32) << 16
-                | (data[i + 3] as u32) << 24;
-
-            let address = ((page_number * PAGE_SIZE) + i) as u32;
-            let location = unsafe { &*(address as *const VolatileCell<u32>) };
-            location.set(word);
-            while !self.registers.ready.is_set(Ready::READY) {}
+            let word = word_at(i);
+            let location = location_at(i);
+            if location.get() != word {
+                location.set(word);
+                while !self.registers.ready.is_set(Ready::READY) {}
+            }
         }
 
         // Make sure that the NVMC is done. The CPU should be blocked while the
@@ -62,6 +62,13 @@ pub unsafe trait ProcessManagementCapability {}
 /// so only modules which check this may do so.
 pub unsafe trait ProcessStartCapability {}
 
+/// The `ProcessMemoryInspectionCapability` allows the holder to read the
+/// memory and registers of processes for debugging.
+///
+/// This is separate from `ProcessManagementCapability` so that production
+/// boards can manage processes without exposing their memory.
+pub unsafe trait ProcessMemoryInspectionCapability {}
+
 /// The `MainLoopCapability` capability allows the holder to start executing as
 /// well as manage the main scheduler loop in Tock.
 ///
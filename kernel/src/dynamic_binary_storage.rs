@@ -6,15 +6,29 @@
 //!
 //! These functions facilitate dynamic application flashing and process creation
 //! during runtime without requiring the user to restart the device.
+//!
+//! A binary written this way can also be an update for a running process of
+//! the same application. The new binary is stored alongside the running one,
+//! and once its credentials have been checked and it is found to have a higher
+//! version, the running process is replaced by one for the new binary. Until
+//! the update is committed, the previous binary is kept in flash so the update
+//! can be rolled back to it.
+//!
+//! If a monotonic counter is set with `set_version_counter()`, the highest
+//! version loaded for each application with a fixed ShortId is stored in it.
+//! Binaries with a lower version are then refused, so that an application
+//! cannot be rolled back to an older, possibly vulnerable, version. An update
+//! only raises the stored version once it is committed.
 
 use core::cell::Cell;
 
 use crate::config;
 use crate::debug;
 use crate::deferred_call::{DeferredCall, DeferredCallClient};
+use crate::hil::counter::MonotonicCounter;
 use crate::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
 use crate::platform::chip::Chip;
-use crate::process::ProcessLoadingAsyncClient;
+use crate::process::{ProcessLoadingAsyncClient, ShortId};
 use crate::process_loading::{
     PaddingRequirement, ProcessLoadError, SequentialProcessLoaderMachine,
 };
@@ -38,6 +52,10 @@ pub enum State {
     Abort,
     PaddingWrite,
     Fail,
+    Update,
+    UpdateFail,
+    Rollback,
+    Commit,
 }
 
 /// Addresses of where the new process will be stored.
@@ -51,6 +69,21 @@ struct ProcessLoadMetadata {
     setup_padding: bool,
 }
 
+/// Binaries involved in an update that has not been committed yet.
+#[derive(Clone, Copy)]
+struct UpdateMetadata {
+    new_app_start_addr: usize,
+    new_app_length: usize,
+    previous_app_start_addr: usize,
+    previous_app_length: usize,
+    /// Index of the updated process in the processes array.
+    process_index: usize,
+    /// Whether the updated process has been loaded.
+    loaded: bool,
+    /// Version counter key and version of the new binary.
+    version: Option<(u64, u32)>,
+}
+
 /// This interface supports flashing binaries at runtime.
 pub trait DynamicBinaryStore {
     /// Call to request flashing a new binary.
@@ -113,6 +146,9 @@ pub trait DynamicBinaryStoreClient {
 /// This interface supports loading processes at runtime.
 pub trait DynamicProcessLoad {
     /// Call to request kernel to load a new process.
+    ///
+    /// Returns `ALREADY` if a newer version of the application has been
+    /// loaded before.
     fn load(&self) -> Result<(), ErrorCode>;
 
     /// Sets a client for the SequentialDynamicProcessLoading Object
@@ -128,6 +164,76 @@ pub trait DynamicProcessLoadClient {
     fn load_done(&self, result: Result<(), ProcessLoadError>);
 }
 
+/// This interface supports updating running processes at runtime.
+///
+/// An update replaces the running process of an application with a process
+/// for a newer binary of that application. The previous binary stays in flash
+/// until the update is committed, so that the update can be rolled back. Only
+/// one update can be pending at a time.
+pub trait DynamicProcessUpdate {
+    /// Call to request the kernel to update a running process to the binary
+    /// written with [`DynamicBinaryStore`] and finalized.
+    ///
+    /// The binary is an update for the running process of the same
+    /// application (same AppId or ShortId) if it has a higher version. The
+    /// credentials of the binary are checked before the running process is
+    /// replaced. The result is signalled with `update_done()`.
+    ///
+    /// Return value:
+    /// - `Ok(())`: The update has started.
+    /// - `Err(ErrorCode::INVAL)`: No binary was finalized, or no process of
+    ///   the same application is running.
+    /// - `Err(ErrorCode::ALREADY)`: The running process is not older than the
+    ///   new binary, or a newer version of the application has been loaded
+    ///   before.
+    /// - `Err(ErrorCode::BUSY)`: Another update has not been committed or
+    ///   rolled back yet.
+    fn update(&self) -> Result<(), ErrorCode>;
+
+    /// Roll back the pending update, replacing the updated process with one
+    /// for the previous binary and removing the new binary from flash.
+    fn rollback(&self) -> Result<(), ErrorCode>;
+
+    /// Keep the pending update, removing the previous binary from flash.
+    fn commit(&self) -> Result<(), ErrorCode>;
+
+    /// Return the flash address of the new binary of the pending update, if
+    /// its process has been loaded.
+    fn pending_update(&self) -> Option<usize>;
+
+    /// Find an update that was pending when the board rebooted, and make it
+    /// the pending update again.
+    ///
+    /// The previous binary of an update stays in flash until the update is
+    /// committed, and the newer binary is loaded at boot, so a pending update
+    /// is found from the binaries in flash. This must be called once the
+    /// processes have been loaded at boot.
+    ///
+    /// Returns the flash address of the new binary of the pending update.
+    fn restore_pending_update(&self) -> Option<usize>;
+
+    /// Sets a client for the DynamicProcessUpdate Object
+    ///
+    /// When the client operation is done, it calls the `update_done()`,
+    /// `rollback_done()` and `commit_done()` functions.
+    fn set_update_client(&self, client: &'static dyn DynamicProcessUpdateClient);
+}
+
+/// The callback for updating processes.
+pub trait DynamicProcessUpdateClient {
+    /// The updated process has been loaded, or the update failed and the new
+    /// binary was removed.
+    fn update_done(&self, result: Result<(), ErrorCode>);
+
+    /// The process for the previous binary has been loaded again.
+    fn rollback_done(&self, result: Result<(), ErrorCode>);
+
+    /// The previous binary has been removed from flash. The result is an
+    /// error if the version of the new binary could not be recorded by the
+    /// version counter, even though the update is committed.
+    fn commit_done(&self, result: Result<(), ErrorCode>);
+}
+
 /// Dynamic process loading machine.
 pub struct SequentialDynamicBinaryStorage<
     'a,
@@ -141,7 +247,15 @@ pub struct SequentialDynamicBinaryStorage<
     buffer: TakeCell<'static, [u8]>,
     storage_client: OptionalCell<&'static dyn DynamicBinaryStoreClient>,
     load_client: OptionalCell<&'static dyn DynamicProcessLoadClient>,
+    update_client: OptionalCell<&'static dyn DynamicProcessUpdateClient>,
     process_metadata: OptionalCell<ProcessLoadMetadata>,
+    update_metadata: OptionalCell<UpdateMetadata>,
+    /// Counter storing the highest version loaded of each application.
+    version_counter: OptionalCell<&'a dyn MonotonicCounter<'a>>,
+    /// Version counter key and version of the binary being loaded.
+    load_version: OptionalCell<(u64, u32)>,
+    /// Result of loading the process for an update or a rollback.
+    update_result: Cell<Result<(), ErrorCode>>,
     state: Cell<State>,
     deferred_call: DeferredCall,
 }
@@ -160,12 +274,60 @@ impl<'a, 'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: Nonvolatil
             buffer: TakeCell::new(buffer),
             storage_client: OptionalCell::empty(),
             load_client: OptionalCell::empty(),
+            update_client: OptionalCell::empty(),
             process_metadata: OptionalCell::empty(),
+            update_metadata: OptionalCell::empty(),
+            version_counter: OptionalCell::empty(),
+            load_version: OptionalCell::empty(),
+            update_result: Cell::new(Err(ErrorCode::FAIL)),
             state: Cell::new(State::Idle),
             deferred_call: DeferredCall::new(),
         }
     }
 
+    /// Set the counter used to refuse binaries older than the newest version
+    /// loaded of their application.
+    pub fn set_version_counter(&self, counter: &'a dyn MonotonicCounter<'a>) {
+        self.version_counter.set(counter);
+    }
+
+    /// Check that the binary at `app_address` with size `app_size` is not
+    /// older than the newest version loaded of its application.
+    ///
+    /// Returns the version counter key and version of the binary, or `None`
+    /// if versions are not tracked for it. Returns `ALREADY` if a newer
+    /// version has been loaded.
+    fn check_version(
+        &self,
+        app_address: usize,
+        app_size: usize,
+    ) -> Result<Option<(u64, u32)>, ErrorCode> {
+        let Some(counter) = self.version_counter.get() else {
+            return Ok(None);
+        };
+        let (short_id, version) = self
+            .loader_driver
+            .binary_short_id_and_version(app_address, app_size)?;
+        let ShortId::Fixed(id) = short_id else {
+            return Ok(None);
+        };
+        // The counters of each application are keyed by its ShortId, and the
+        // highest index is reserved for the kernel.
+        let key = (u64::from(id.get()) << 32) | u64::from(u32::MAX);
+        if counter.get(key) > u64::from(version) {
+            return Err(ErrorCode::ALREADY);
+        }
+        Ok(Some((key, version)))
+    }
+
+    /// Record that `version` of an application has been loaded.
+    fn advance_version(&self, version: Option<(u64, u32)>) -> Result<(), ErrorCode> {
+        match (version, self.version_counter.get()) {
+            (Some((key, version)), Some(counter)) => counter.advance(key, u64::from(version)),
+            _ => Ok(()),
+        }
+    }
+
     /// Function to reset variables and states.
     fn reset_process_loading_metadata(&self) {
         self.state.set(State::Idle);
@@ -210,7 +372,13 @@ impl<'a, 'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: Nonvolatil
             // If we are going to write the padding header, we already know
             // where to write in flash, so we don't have to add the start
             // address
-            State::Setup | State::Load | State::PaddingWrite | State::Abort => Ok(offset),
+            State::Setup
+            | State::Load
+            | State::PaddingWrite
+            | State::Abort
+            | State::UpdateFail
+            | State::Rollback
+            | State::Commit => Ok(offset),
             // We aren't supposed to be able to write unless we are in one of
             // the first two write states
             _ => Err(ErrorCode::FAIL),
@@ -426,7 +594,53 @@ impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileSto
                     client.abort_done(Ok(()));
                 });
             }
-            State::Idle => {
+            State::UpdateFail => {
+                // The new binary of the failed update has been removed.
+                self.buffer.replace(buffer);
+                self.update_metadata.take();
+                self.reset_process_loading_metadata();
+                self.update_client.map(|client| {
+                    client.update_done(self.update_result.get());
+                });
+            }
+            State::Rollback => {
+                // The new binary has been removed, so we can go back to the
+                // previous one.
+                self.buffer.replace(buffer);
+                if let Some(update) = self.update_metadata.get() {
+                    if self
+                        .loader_driver
+                        .replace_process_binary(
+                            update.previous_app_start_addr,
+                            update.previous_app_length,
+                            update.process_index,
+                        )
+                        .is_err()
+                    {
+                        self.update_metadata.take();
+                        self.state.set(State::Idle);
+                        self.update_client.map(|client| {
+                            client.rollback_done(Err(ErrorCode::FAIL));
+                        });
+                    }
+                }
+            }
+            State::Commit => {
+                // The previous binary has been removed.
+                self.buffer.replace(buffer);
+                // The update is committed either way, but the client is told
+                // if its version could not be recorded.
+                let result = self.advance_version(
+                    self.update_metadata
+                        .take()
+                        .and_then(|update| update.version),
+                );
+                self.state.set(State::Idle);
+                self.update_client.map(|client| {
+                    client.commit_done(result);
+                });
+            }
+            State::Idle | State::Update => {
                 self.buffer.replace(buffer);
             }
         }
@@ -438,15 +652,71 @@ impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileSto
     ProcessLoadingAsyncClient for SequentialDynamicBinaryStorage<'_, 'b, C, D, F>
 {
     fn process_loaded(&self, result: Result<(), ProcessLoadError>) {
-        self.load_client.map(|client| {
-            client.load_done(result);
-        });
+        match self.state.get() {
+            State::Update | State::Rollback => {
+                self.update_result.set(result.map_err(|e| match e {
+                    ProcessLoadError::NotEnoughMemory => ErrorCode::NOMEM,
+                    _ => ErrorCode::FAIL,
+                }));
+            }
+            _ => {
+                let version = self.load_version.take();
+                if result.is_ok() {
+                    // The process is loaded even if its version could not be
+                    // recorded, so this is not a load error.
+                    if let Err(e) = self.advance_version(version) {
+                        debug!("Failed to record the version of the loaded binary: {:?}", e);
+                    }
+                }
+                self.load_client.map(|client| {
+                    client.load_done(result);
+                });
+            }
+        }
     }
 
     fn process_loading_finished(&self) {
-        self.load_client.map(|client| {
-            client.load_done(Ok(()));
-        });
+        match self.state.get() {
+            State::Update => match self.update_result.get() {
+                Ok(()) => {
+                    if let Some(mut update) = self.update_metadata.get() {
+                        update.loaded = true;
+                        self.update_metadata.set(update);
+                    }
+                    self.reset_process_loading_metadata();
+                    self.update_client.map(|client| {
+                        client.update_done(Ok(()));
+                    });
+                }
+                Err(e) => {
+                    // Remove the new binary so that it is not loaded instead
+                    // of the running one after a reboot.
+                    self.state.set(State::UpdateFail);
+                    let removed = self.update_metadata.get().map(|update| {
+                        self.write_padding_app(update.new_app_length, update.new_app_start_addr)
+                    });
+                    if removed != Some(Ok(())) {
+                        self.update_metadata.take();
+                        self.reset_process_loading_metadata();
+                        self.update_client.map(|client| {
+                            client.update_done(Err(e));
+                        });
+                    }
+                }
+            },
+            State::Rollback => {
+                self.update_metadata.take();
+                self.state.set(State::Idle);
+                self.update_client.map(|client| {
+                    client.rollback_done(self.update_result.get());
+                });
+            }
+            _ => {
+                self.load_client.map(|client| {
+                    client.load_done(Ok(()));
+                });
+            }
+        }
     }
 }
 
@@ -651,12 +921,20 @@ impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileSto
         match self.state.get() {
             State::Load => {
                 if let Some(metadata) = self.process_metadata.get() {
+                    match self.check_version(metadata.new_app_start_addr, metadata.new_app_length) {
+                        Ok(version) => self.load_version.insert(version),
+                        Err(e) => {
+                            self.reset_process_loading_metadata();
+                            return Err(e);
+                        }
+                    }
                     let _ = match self.loader_driver.load_new_process_binary(
                         metadata.new_app_start_addr,
                         metadata.new_app_length,
                     ) {
                         Ok(()) => Ok::<(), ProcessLoadError>(()),
                         Err(_e) => {
+                            self.load_version.clear();
                             self.reset_process_loading_metadata();
                             return Err(ErrorCode::FAIL);
                         }
@@ -672,3 +950,130 @@ impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileSto
         }
     }
 }
+
+/// Update interface exposed to the app_loader capsule
+impl<'b, C: Chip + 'static, D: ProcessStandardDebug + 'static, F: NonvolatileStorage<'b>>
+    DynamicProcessUpdate for SequentialDynamicBinaryStorage<'_, 'b, C, D, F>
+{
+    fn set_update_client(&self, client: &'static dyn DynamicProcessUpdateClient) {
+        self.update_client.set(client);
+    }
+
+    fn update(&self) -> Result<(), ErrorCode> {
+        if self.update_metadata.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        match self.state.get() {
+            State::Load => {
+                let metadata = self.process_metadata.get().ok_or(ErrorCode::INVAL)?;
+                let result = self
+                    .check_version(metadata.new_app_start_addr, metadata.new_app_length)
+                    .and_then(|version| {
+                        self.loader_driver
+                            .find_process_to_update(
+                                metadata.new_app_start_addr,
+                                metadata.new_app_length,
+                            )
+                            .map(|process| (process, version))
+                    })
+                    .and_then(
+                        |(
+                            (process_index, previous_app_start_addr, previous_app_length),
+                            version,
+                        )| {
+                            self.loader_driver
+                                .replace_process_binary(
+                                    metadata.new_app_start_addr,
+                                    metadata.new_app_length,
+                                    process_index,
+                                )
+                                .or(Err(ErrorCode::FAIL))?;
+                            Ok(UpdateMetadata {
+                                new_app_start_addr: metadata.new_app_start_addr,
+                                new_app_length: metadata.new_app_length,
+                                previous_app_start_addr,
+                                previous_app_length,
+                                process_index,
+                                loaded: false,
+                                version,
+                            })
+                        },
+                    );
+                match result {
+                    Ok(update) => {
+                        self.update_metadata.set(update);
+                        self.update_result.set(Err(ErrorCode::FAIL));
+                        self.state.set(State::Update);
+                        Ok(())
+                    }
+                    Err(e) => {
+                        self.reset_process_loading_metadata();
+                        Err(e)
+                    }
+                }
+            }
+            _ => Err(ErrorCode::INVAL),
+        }
+    }
+
+    fn rollback(&self) -> Result<(), ErrorCode> {
+        let update = self
+            .update_metadata
+            .get()
+            .filter(|update| update.loaded)
+            .ok_or(ErrorCode::INVAL)?;
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        // Remove the new binary first, so that the previous one is used after
+        // a reboot even if we do not get to load it now.
+        self.state.set(State::Rollback);
+        self.update_result.set(Err(ErrorCode::FAIL));
+        self.write_padding_app(update.new_app_length, update.new_app_start_addr)
+            .inspect_err(|_| self.state.set(State::Idle))
+    }
+
+    fn commit(&self) -> Result<(), ErrorCode> {
+        let update = self
+            .update_metadata
+            .get()
+            .filter(|update| update.loaded)
+            .ok_or(ErrorCode::INVAL)?;
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.state.set(State::Commit);
+        self.write_padding_app(update.previous_app_length, update.previous_app_start_addr)
+            .inspect_err(|_| self.state.set(State::Idle))
+    }
+
+    fn pending_update(&self) -> Option<usize> {
+        self.update_metadata
+            .get()
+            .filter(|update| update.loaded)
+            .map(|update| update.new_app_start_addr)
+    }
+
+    fn restore_pending_update(&self) -> Option<usize> {
+        if self.update_metadata.is_none() {
+            let (process_index, previous_app_start_addr, previous_app_length) =
+                self.loader_driver.find_superseded_binary()?;
+            let (new_app_start_addr, new_app_length) =
+                self.loader_driver.process_binary_location(process_index)?;
+            let version = self
+                .check_version(new_app_start_addr, new_app_length)
+                .ok()
+                .flatten();
+            self.update_metadata.set(UpdateMetadata {
+                new_app_start_addr,
+                new_app_length,
+                previous_app_start_addr,
+                previous_app_length,
+                process_index,
+                loaded: true,
+                version,
+            });
+        }
+        self.pending_update()
+    }
+}
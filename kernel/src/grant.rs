@@ -839,6 +839,25 @@ fn enter_grant_kernel_managed(
     Ok(layout)
 }
 
+/// Return the number of upcalls, read-only allows and read-write allows the
+/// core kernel stores in the grant at `grant_base_ptr`.
+///
+/// # Safety
+///
+/// `grant_base_ptr` must point to an allocated grant whose kernel managed
+/// memory has been initialized.
+pub(crate) unsafe fn kernel_managed_counts(grant_base_ptr: NonNull<u8>) -> (usize, usize, usize) {
+    // Only the counters are read, so this is safe even if the grant is
+    // currently entered.
+    let counters_val = (grant_base_ptr.as_ptr() as *const usize).read();
+    let [_, allow_rw_num, allow_ro_num, upcalls_num] = u32::to_be_bytes(counters_val as u32);
+    (
+        upcalls_num as usize,
+        allow_ro_num as usize,
+        allow_rw_num as usize,
+    )
+}
+
 /// Subscribe to an upcall by saving the upcall in the grant region for the
 /// process and returning the existing upcall for the same UpcallId.
 pub(crate) fn subscribe(
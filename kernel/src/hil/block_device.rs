@@ -0,0 +1,125 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Interface for block storage devices.
+//!
+//! A block device stores data in fixed size blocks, which are always read and
+//! written whole. SD cards, flash chips and virtual disks can all be accessed
+//! through this interface, so filesystems, logs and other storage users can be
+//! written once and used on any of them.
+//!
+//! Blocks are numbered from 0 to `block_count() - 1`. Buffers passed to read
+//! and write operations must be at least `count * block_size()` bytes long.
+//!
+//! Writing a block replaces its contents; devices that need blocks to be
+//! erased before they are written take care of that themselves. Erasing
+//! blocks tells the device their contents are no longer needed, after which
+//! reading them returns unspecified data (all `0xFF` for flash). Writes may be
+//! buffered by the device, and are only guaranteed to be durable once a later
+//! flush completes.
+//!
+//! The expected setup inside Tock will look like this:
+//!
+//! ```text
+//! +-------------------------------+
+//! | Filesystem, log, ...          |
+//! +-------------------------------+
+//!
+//!    hil::block_device (this file)
+//!
+//! +-------------------------------+
+//! | Virtual block device (opt.)   |
+//! +-------------------------------+
+//!
+//!    hil::block_device (this file)
+//!
+//! +-------------------------------+
+//! | SD card, flash, ... adapter   |
+//! +-------------------------------+
+//! ```
+
+use crate::ErrorCode;
+
+/// A device that stores data in fixed size blocks.
+///
+/// Only one operation can be in progress at a time.
+pub trait BlockDevice<'a> {
+    /// Set the client for this block device. The client will be called when
+    /// operations complete.
+    fn set_client(&self, client: &'a dyn BlockDeviceClient);
+
+    /// The size of each block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// The number of blocks on the device. This is 0 if the size is not yet
+    /// known, for example because the device has not been initialized.
+    fn block_count(&self) -> u32;
+
+    /// Read `count` blocks starting at block `block` into `buffer`.
+    ///
+    /// ### Return Values
+    ///
+    /// - `Ok(())`: The read started and `read_complete()` will be called.
+    /// - `Err((ErrorCode, buffer))`: The read could not be started. Valid
+    ///   `ErrorCode`s:
+    ///   - `INVAL`: The blocks are outside of the device or `count` is 0.
+    ///   - `SIZE`: The buffer is too small to hold the blocks.
+    ///   - `BUSY`: Another operation is in progress.
+    ///   - `UNINSTALLED`: The device is not present (e.g. no SD card is
+    ///     inserted).
+    ///   - `FAIL`: The device could not be accessed.
+    fn read_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Write `count` blocks starting at block `block` from `buffer`.
+    ///
+    /// ### Return Values
+    ///
+    /// - `Ok(())`: The write started and `write_complete()` will be called.
+    /// - `Err((ErrorCode, buffer))`: The write could not be started. The
+    ///   `ErrorCode`s are the same as for `read_blocks()`.
+    fn write_blocks(
+        &self,
+        buffer: &'static mut [u8],
+        block: u32,
+        count: u32,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// Erase `count` blocks starting at block `block`.
+    ///
+    /// ### Return Values
+    ///
+    /// - `Ok(())`: The erase started and `erase_complete()` will be called.
+    /// - `Err(ErrorCode)`: The erase could not be started. The `ErrorCode`s are
+    ///   the same as for `read_blocks()`, except for `SIZE`.
+    fn erase_blocks(&self, block: u32, count: u32) -> Result<(), ErrorCode>;
+
+    /// Make all completed writes durable.
+    ///
+    /// ### Return Values
+    ///
+    /// - `Ok(())`: The flush started and `flush_complete()` will be called.
+    /// - `Err(ErrorCode)`: The flush could not be started. Valid `ErrorCode`s
+    ///   are `BUSY`, `UNINSTALLED` and `FAIL`.
+    fn flush(&self) -> Result<(), ErrorCode>;
+}
+
+/// Client interface for block devices.
+pub trait BlockDeviceClient {
+    /// A read finished. On success `buffer` holds the blocks that were read.
+    fn read_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>);
+
+    /// A write finished. On error some of the blocks may have been written.
+    fn write_complete(&self, buffer: &'static mut [u8], result: Result<(), ErrorCode>);
+
+    /// An erase finished.
+    fn erase_complete(&self, result: Result<(), ErrorCode>);
+
+    /// A flush finished.
+    fn flush_complete(&self, result: Result<(), ErrorCode>);
+}
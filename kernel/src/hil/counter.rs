@@ -0,0 +1,55 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2024.
+
+//! Interface for persistent monotonic counters.
+//!
+//! A monotonic counter can only ever increase, including across reboots. This
+//! makes counters suitable for preventing rollback to older software versions
+//! and replay of old messages.
+//!
+//! Counters are identified by a 64 bit key, and a counter that has never been
+//! advanced has the value 0. Updates are applied immediately, so `get()`
+//! returns the new value right away, but they only become persistent later.
+//! Once all updates so far are persistent the client's `updates_stored()` is
+//! called. A value must not be relied upon (for example used as a nonce) until
+//! then, as a reboot before that can lose the update.
+
+use crate::ErrorCode;
+
+/// A set of persistent monotonic counters.
+pub trait MonotonicCounter<'a> {
+    /// Set the client called when updates have been stored.
+    fn set_client(&self, client: &'a dyn MonotonicCounterClient);
+
+    /// Get the value of the counter `key`.
+    fn get(&self, key: u64) -> u64;
+
+    /// Increment the counter `key` and return its new value.
+    ///
+    /// ### Return Values
+    ///
+    /// - `Ok(value)`: The counter was incremented, and `updates_stored()`
+    ///   will be called once this is persistent.
+    /// - `Err(ErrorCode)`: The counter was not changed. Valid `ErrorCode`s:
+    ///   - `NOMEM`: There is no space left for another counter.
+    ///   - `SIZE`: The counter is already at its maximum value.
+    fn increment(&self, key: u64) -> Result<u64, ErrorCode>;
+
+    /// Advance the counter `key` to `value`, if it is lower than `value`.
+    ///
+    /// ### Return Values
+    ///
+    /// - `Ok(())`: The counter is at least `value`. If it was changed,
+    ///   `updates_stored()` will be called once this is persistent.
+    /// - `Err(ErrorCode)`: The counter was not changed. Valid `ErrorCode`s:
+    ///   - `NOMEM`: There is no space left for another counter.
+    fn advance(&self, key: u64, value: u64) -> Result<(), ErrorCode>;
+}
+
+/// Client interface for monotonic counters.
+pub trait MonotonicCounterClient {
+    /// All updates made before this call are persistent, or storing them
+    /// failed. After a failure the updates are retried with the next update.
+    fn updates_stored(&self, result: Result<(), ErrorCode>);
+}
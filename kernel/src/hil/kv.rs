@@ -76,7 +76,8 @@ pub trait KVClient {
     /// - `result`: `Ok(())` on success, `Err(ErrorCode)` on error. Valid
     ///   `ErrorCode`s:
     ///   - `NOSUPPORT`: The caller does not have permission to store this key.
-    ///   - `NOMEM`: The key could not be set because the KV store is full.
+    ///   - `NOMEM`: The key could not be set because the KV store is full or
+    ///     storing it would exceed the caller's quota.
     ///   - `SIZE`: The key could not be set because the key or value is too
     ///     many bytes.
     ///   - `FAIL`: An internal error occurred and the operation cannot be
@@ -97,7 +98,8 @@ pub trait KVClient {
     /// - `result`: `Ok(())` on success, `Err(ErrorCode)` on error. Valid
     ///   `ErrorCode`s:
     ///   - `NOSUPPORT`: The key already exists and cannot be added.
-    ///   - `NOMEM`: The key could not be added because the KV store is full.
+    ///   - `NOMEM`: The key could not be added because the KV store is full
+    ///     or storing it would exceed the caller's quota.
     ///   - `SIZE`: The key could not be set because the key or value is too
     ///     many bytes.
     ///   - `FAIL`: An internal error occurred and the operation cannot be
@@ -119,7 +121,8 @@ pub trait KVClient {
     ///   `ErrorCode`s:
     ///   - `NOSUPPORT`: The key does not already exist and cannot be modified
     ///     or the caller does not have permission to modify this key.
-    ///   - `NOMEM`: The key could not be updated because the KV store is full.
+    ///   - `NOMEM`: The key could not be updated because the KV store is full
+    ///     or storing it would exceed the caller's quota.
     ///   - `SIZE`: The key could not be set because the key or value is too
     ///     many bytes.
     ///   - `FAIL`: An internal error occurred and the operation cannot be
@@ -146,6 +149,29 @@ pub trait KVClient {
     /// - `key`: The key buffer.
     fn delete_complete(&self, result: Result<(), ErrorCode>, key: SubSliceMut<'static, u8>);
 
+    /// This callback is called when the next key operation completes.
+    ///
+    /// ### Return Values
+    ///
+    /// - `result`: `Ok(length)` on success, where `length` is the length of
+    ///   the value. If the value is longer than the `value` buffer only the
+    ///   start of the value is copied into it. `Err(ErrorCode)` on error. Valid
+    ///   `ErrorCode`s:
+    ///   - `NOSUPPORT`: There are no more keys the caller may read.
+    ///   - `FAIL`: An internal error occurred and the operation cannot be
+    ///     completed.
+    /// - `position`: The position to pass to `next_key()` to find the key
+    ///   after this one.
+    /// - `key`: The key buffer. On success it holds the hashed key.
+    /// - `value`: The value buffer.
+    fn next_key_complete(
+        &self,
+        result: Result<usize, ErrorCode>,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    );
+
     /// This callback is called when the garbage collection operation completes.
     ///
     /// ### Return Values
@@ -155,6 +181,35 @@ pub trait KVClient {
     ///   - `FAIL`: An internal error occurred and the operation cannot be
     ///     completed.
     fn garbage_collection_complete(&self, result: Result<(), ErrorCode>);
+
+    /// This callback is called when a transaction commit completes.
+    ///
+    /// ### Return Values
+    ///
+    /// - `result`: `Ok(())` on success, `Err(ErrorCode)` on error. Valid
+    ///   `ErrorCode`s:
+    ///   - `FAIL`: An internal error occurred. This can happen either before
+    ///     the transaction was recorded in storage or after:
+    ///     - Before, none of the changes were made and the transaction is
+    ///       still open, so it can be committed again or aborted.
+    ///     - After, the transaction is committed and closed, but some of the
+    ///       changes may only be visible once the storage is initialised
+    ///       again, for example after a reboot.
+    ///
+    ///     The two cases can be told apart by calling `abort_transaction()`,
+    ///     which only returns `INVAL` if the transaction was committed.
+    fn commit_transaction_complete(&self, result: Result<(), ErrorCode>);
+
+    /// This callback is called when a remaining quota query completes.
+    ///
+    /// ### Return Values
+    ///
+    /// - `result`: `Ok(remaining)` on success, where `remaining` is the number
+    ///   of bytes (including headers) the caller may still store.
+    ///   `Err(ErrorCode)` on error. Valid `ErrorCode`s:
+    ///   - `FAIL`: An internal error occurred and the operation cannot be
+    ///     completed.
+    fn remaining_quota_complete(&self, result: Result<usize, ErrorCode>);
 }
 
 /// Key-Value interface with permissions.
@@ -329,6 +384,45 @@ pub trait KVPermissions<'a> {
         permissions: StoragePermissions,
     ) -> Result<(), (SubSliceMut<'static, u8>, ErrorCode)>;
 
+    /// Find the next key after `position` that the caller may read.
+    ///
+    /// Keys are stored hashed, so this provides the hashed key rather than the
+    /// key that was used to store the object. Start with a `position` of 0
+    /// and continue with the `position` from `next_key_complete()` until it
+    /// reports `NOSUPPORT`. Keys that are added or deleted in the meantime may
+    /// or may not be found.
+    ///
+    /// ### Arguments
+    ///
+    /// - `position`: Where to continue looking for keys.
+    /// - `key`: Where the hashed key will be stored.
+    /// - `value`: Where the value will be stored.
+    /// - `permissions`: The read/write/modify permissions for this access.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`. A callback will be issued.
+    /// - On error, returns the buffers and:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `SIZE`: The `key` buffer cannot hold a hashed key or the `value`
+    ///     buffer cannot hold the permission header.
+    ///   - `FAIL`: An internal error occurred and the operation cannot be
+    ///     completed.
+    fn next_key(
+        &self,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+        permissions: StoragePermissions,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    >;
+
     /// Run garbage collection on the underlying Key/Value store.
     ///
     /// This is generally used to reclaim keys that have been removed with
@@ -343,6 +437,22 @@ pub trait KVPermissions<'a> {
     ///     completed.
     fn garbage_collect(&self) -> Result<(), ErrorCode>;
 
+    /// Query how many more bytes the caller may store.
+    ///
+    /// Applications can be limited in how much they store by the quota in
+    /// their `StoragePermissions`. The quota covers the objects marked with the
+    /// caller's `write_id`, including the header of each object.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`. A callback will be issued.
+    /// - On error, returns:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `NOSUPPORT`: The caller's storage is not limited by a quota.
+    ///   - `FAIL`: An internal error occurred and the operation cannot be
+    ///     completed.
+    fn remaining_quota(&self, permissions: StoragePermissions) -> Result<(), ErrorCode>;
+
     /// Returns the length of the key-value store's header in bytes.
     ///
     /// Room for this header must be accommodated in a `set`, `add`, or `update`
@@ -360,6 +470,8 @@ pub trait KVPermissions<'a> {
 /// - `add(key, value)`
 /// - `update(key, value)`
 /// - `delete(key)`
+///
+/// The stored keys can be listed with `next_key()`.
 pub trait KV<'a> {
     /// Configure the client for operation callbacks.
     fn set_client(&self, client: &'a dyn KVClient);
@@ -503,6 +615,42 @@ pub trait KV<'a> {
         key: SubSliceMut<'static, u8>,
     ) -> Result<(), (SubSliceMut<'static, u8>, ErrorCode)>;
 
+    /// Find the next key in the store after `position`.
+    ///
+    /// Keys are stored hashed, so this provides the hashed key rather than the
+    /// key that was used to store the object. Start with a `position` of 0
+    /// and continue with the `position` from `next_key_complete()` until it
+    /// reports `NOSUPPORT`. Keys that are added or deleted in the meantime may
+    /// or may not be found.
+    ///
+    /// ### Arguments
+    ///
+    /// - `position`: Where to continue looking for keys.
+    /// - `key`: Where the hashed key will be stored.
+    /// - `value`: Where the value will be stored.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`. A callback will be issued.
+    /// - On error, returns the buffers and:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `SIZE`: The `key` buffer cannot hold a hashed key.
+    ///   - `FAIL`: An internal error occurred and the operation cannot be
+    ///     completed.
+    fn next_key(
+        &self,
+        position: usize,
+        key: SubSliceMut<'static, u8>,
+        value: SubSliceMut<'static, u8>,
+    ) -> Result<
+        (),
+        (
+            SubSliceMut<'static, u8>,
+            SubSliceMut<'static, u8>,
+            ErrorCode,
+        ),
+    >;
+
     /// Run garbage collection on the underlying Key/Value store.
     ///
     /// This is generally used to reclaim keys that have been removed with
@@ -516,4 +664,43 @@ pub trait KV<'a> {
     ///   - `FAIL`: An internal error occurred and the operation cannot be
     ///     completed.
     fn garbage_collect(&self) -> Result<(), ErrorCode>;
+
+    /// Start a transaction.
+    ///
+    /// The `set()`, `add()`, `update()` and `delete()` operations issued
+    /// after this call are only applied when `commit_transaction()` is
+    /// called, and are then applied atomically: after a power loss either all
+    /// or none of them are visible. No callback is issued.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`.
+    /// - On error:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `ALREADY`: A transaction has already been started.
+    ///   - `FAIL`: An internal error occurred.
+    fn begin_transaction(&self) -> Result<(), ErrorCode>;
+
+    /// Atomically apply the operations issued since `begin_transaction()`.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`. A callback will be issued.
+    /// - On error:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `INVAL`: No transaction has been started.
+    ///   - `FAIL`: An internal error occurred.
+    fn commit_transaction(&self) -> Result<(), ErrorCode>;
+
+    /// Discard the operations issued since `begin_transaction()`. No callback
+    /// is issued.
+    ///
+    /// ### Return
+    ///
+    /// - On success returns `Ok(())`.
+    /// - On error:
+    ///   - `BUSY`: An operation is already in progress.
+    ///   - `INVAL`: No transaction has been started.
+    ///   - `FAIL`: An internal error occurred.
+    fn abort_transaction(&self) -> Result<(), ErrorCode>;
 }
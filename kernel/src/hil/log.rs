@@ -41,6 +41,11 @@ pub trait LogRead<'a> {
 
     /// Get approximate log capacity in bytes.
     fn get_size(&self) -> usize;
+
+    /// Returns the number of bytes that were discarded when the log was recovered at startup.
+    /// Entries that fail their integrity check (e.g. because a write was interrupted by a power
+    /// loss) are discarded along with everything written after them.
+    fn discarded_bytes(&self) -> usize;
 }
 
 /// Receive callbacks from `LogRead`.
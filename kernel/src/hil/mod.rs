@@ -7,9 +7,11 @@
 pub mod adc;
 pub mod analog_comparator;
 pub mod ble_advertising;
+pub mod block_device;
 pub mod bus8080;
 pub mod buzzer;
 pub mod can;
+pub mod counter;
 pub mod crc;
 pub mod dac;
 pub mod date_time;
@@ -17,11 +17,28 @@
 use core::cell::Cell;
 
 use crate::capabilities::ProcessManagementCapability;
+use crate::grant;
 use crate::kernel::Kernel;
 use crate::process;
 use crate::process::ProcessId;
 use crate::utilities::cells::NumericCellExt;
 
+/// Memory a process has allocated for the grant of one driver.
+#[derive(Clone, Copy, Debug)]
+pub struct GrantAllocation {
+    /// The syscall driver number the grant belongs to.
+    pub driver_num: usize,
+    /// The number of bytes allocated in the grant region of the process,
+    /// including the upcall and allow slots and any alignment padding.
+    pub size: usize,
+    /// The number of upcall slots stored in the grant.
+    pub upcalls: usize,
+    /// The number of read-only allow slots stored in the grant.
+    pub allow_ro: usize,
+    /// The number of read-write allow slots stored in the grant.
+    pub allow_rw: usize,
+}
+
 /// This struct provides the inspection functions.
 pub struct KernelInfo {
     kernel: &'static Kernel,
@@ -147,6 +164,36 @@ impl KernelInfo {
         (used, number_of_grants)
     }
 
+    /// Returns the memory allocated for the grant with number `grant_num`, if
+    /// the app has allocated it. Grant numbers range from 0 to the total
+    /// number of grants returned by `number_app_grant_uses()`.
+    pub fn app_grant_allocation(
+        &self,
+        app: ProcessId,
+        grant_num: usize,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<GrantAllocation> {
+        self.kernel.process_map_or(None, app, |process| {
+            process
+                .grant_allocation(grant_num)
+                .map(|(driver_num, size, grant_ptr)| {
+                    // # Safety
+                    //
+                    // The grant is allocated, and the kernel managed memory of
+                    // a grant is initialized right after it is allocated.
+                    let (upcalls, allow_ro, allow_rw) =
+                        unsafe { grant::kernel_managed_counts(grant_ptr) };
+                    GrantAllocation {
+                        driver_num,
+                        size,
+                        upcalls,
+                        allow_ro,
+                        allow_rw,
+                    }
+                })
+        })
+    }
+
     /// Returns the total number of times all processes have exceeded
     /// their timeslices.
     pub fn timeslice_expirations(&self, _capability: &dyn ProcessManagementCapability) -> usize {
@@ -156,4 +203,14 @@ impl KernelInfo {
         });
         count.get()
     }
+
+    /// Returns the total number of times all processes have exhausted the CPU
+    /// budget reserved for them by the scheduler.
+    pub fn budget_overruns(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            count.add(proc.debug_budget_overrun_count());
+        });
+        count.get()
+    }
 }
@@ -0,0 +1,795 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Message passing inter-process communication for Tock.
+//!
+//! Unlike [`crate::ipc`], which shares a whole allowed buffer of the client
+//! with a service, this is a special syscall driver with copy semantics: a
+//! client sends a bounded message which the kernel copies into the receive
+//! buffer of a service, and the service replies with a message which the
+//! kernel copies back into the reply buffer of the client. Neither process
+//! ever gets access to the memory of the other.
+//!
+//! Each client can have one request outstanding at a time. Until it is
+//! delivered, the request is kept in the grant of the client, so a message
+//! sent to a busy service is queued without the service needing any memory
+//! for it. A service handles one message at a time and receives the next
+//! queued message, in the order they were sent, once it has replied. A client
+//! can give a timeout after which it stops waiting for the reply, or cancel
+//! its request at any time.
+//!
+//! Services and clients are identified by descriptors, which are the unique
+//! identifiers of their processes. A restarted process gets a new
+//! descriptor, so a client must discover a restarted service again. Requests
+//! to a service that terminates or restarts before replying fail with
+//! `CANCEL` as soon as the service terminates, which the kernel tells this
+//! driver as its process termination client.
+//!
+//! A service can restrict which apps may send it messages with the IPC
+//! permissions TBF header, which lists the allowed apps by their fixed
+//! `ShortId` or their package name. Services without this header accept
+//! messages from every app.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let message_ipc = static_init!(
+//!     kernel::ipc_message::MessageIpc<'static, VirtualMuxAlarm<'static, Rtc>, 64>,
+//!     kernel::ipc_message::MessageIpc::new(
+//!         board_kernel,
+//!         kernel::ipc_message::DRIVER_NUM,
+//!         ipc_alarm,
+//!         &memory_allocation_capability,
+//!     )
+//! );
+//! ipc_alarm.set_alarm_client(message_ipc);
+//! board_kernel.set_process_termination_client(message_ipc, &process_management_capability);
+//! ```
+
+use core::cell::Cell;
+
+use crate::capabilities::MemoryAllocationCapability;
+use crate::errorcode::into_statuscode;
+use crate::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use crate::hil::time::{Alarm, AlarmClient, ConvertTicks, Ticks};
+use crate::kernel::Kernel;
+use crate::process::{self, Process, ProcessId, ProcessTerminationClient, ShortId};
+use crate::processbuffer::{
+    ReadableProcessBuffer, ReadableProcessSlice, WriteableProcessBuffer, WriteableProcessSlice,
+};
+use crate::syscall_driver::{CommandReturn, SyscallDriver};
+use crate::ErrorCode;
+use tock_tbf::types::TbfHeaderV2IpcPermissions;
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x10004;
+
+/// Ids for subscribed upcalls.
+mod upcall {
+    /// A message was copied into the receive buffer of a service. Arguments
+    /// are the client descriptor and the length of the message.
+    pub(super) const MESSAGE: usize = 0;
+    /// A request of a client completed. Arguments are the status code, the
+    /// length of the reply and the service descriptor.
+    pub(super) const REPLY: usize = 1;
+    /// The number of upcalls the kernel stores for this grant.
+    pub(super) const COUNT: u8 = 2;
+}
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// Package name of the service to discover.
+    pub(super) const SEARCH: usize = 0;
+    /// Message sent by a client, or reply sent by a service.
+    pub(super) const SEND: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant.
+    pub(super) const COUNT: u8 = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// Buffer of a service that messages are copied into.
+    pub(super) const RECEIVE: usize = 0;
+    /// Buffer of a client that replies are copied into.
+    pub(super) const REPLY: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant.
+    pub(super) const COUNT: u8 = 2;
+}
+
+/// Point in time after which a client stops waiting for a reply.
+#[derive(Clone, Copy)]
+struct Timeout<T: Ticks> {
+    reference: T,
+    dt: T,
+}
+
+impl<T: Ticks> Timeout<T> {
+    fn expired(&self, now: T) -> bool {
+        !now.within_range(self.reference, self.reference.wrapping_add(self.dt))
+    }
+
+    /// Ticks from `now` until the timeout expires.
+    fn remaining(&self, now: T) -> T {
+        if self.expired(now) {
+            T::from(0)
+        } else {
+            self.reference.wrapping_add(self.dt).wrapping_sub(now)
+        }
+    }
+}
+
+/// A request of a client which has not been replied to yet.
+#[derive(Clone, Copy)]
+struct Request<T: Ticks> {
+    /// The service process.
+    service: ProcessId,
+    /// Position of the request in the order messages were sent.
+    order: u32,
+    /// Whether the message was copied to the service already.
+    delivered: bool,
+    timeout: Option<Timeout<T>>,
+}
+
+/// State that is stored in each process's grant region to support message
+/// passing IPC.
+struct MessageIpcData<T: Ticks, const MAX_MESSAGE_LEN: usize> {
+    /// The outstanding request of this process as a client.
+    request: Option<Request<T>>,
+    /// Copy of the message of `request`, kept until it is delivered.
+    message: [u8; MAX_MESSAGE_LEN],
+    message_len: usize,
+    /// The client whose message this process is handling as a service.
+    serving: Option<ProcessId>,
+}
+
+impl<T: Ticks, const MAX_MESSAGE_LEN: usize> Default for MessageIpcData<T, MAX_MESSAGE_LEN> {
+    fn default() -> Self {
+        Self {
+            request: None,
+            message: [0; MAX_MESSAGE_LEN],
+            message_len: 0,
+            serving: None,
+        }
+    }
+}
+
+impl<T: Ticks, const MAX_MESSAGE_LEN: usize> MessageIpcData<T, MAX_MESSAGE_LEN> {
+    /// Copy the queued message into the receive buffer of the service and
+    /// mark the request as delivered. Returns the length of the message.
+    fn deliver_to(&mut self, receive: &WriteableProcessSlice) -> Result<usize, ErrorCode> {
+        let message = &self.message[..self.message_len];
+        receive
+            .get(..message.len())
+            .ok_or(ErrorCode::SIZE)?
+            .copy_from_slice(message);
+        if let Some(request) = self.request.as_mut() {
+            request.delivered = true;
+        }
+        Ok(message.len())
+    }
+}
+
+/// Check whether a client with `short_id` and `package_name` may send
+/// messages to a service with the IPC permissions header `permissions`.
+fn sender_allowed(
+    permissions: Option<TbfHeaderV2IpcPermissions>,
+    short_id: ShortId,
+    package_name: &str,
+) -> bool {
+    permissions.is_none_or(|permissions| {
+        let short_id_allowed = match short_id {
+            ShortId::Fixed(id) => permissions.allows_short_id(id.get()),
+            ShortId::LocallyUnique => false,
+        };
+        short_id_allowed || permissions.allows_package_name(package_name)
+    })
+}
+
+/// Find the client of the oldest request that is queued for `service`,
+/// given the requests of all clients and the order of the next request.
+fn oldest_queued<T: Ticks>(
+    service: ProcessId,
+    next_order: u32,
+    requests: impl Iterator<Item = (ProcessId, Option<Request<T>>)>,
+) -> Option<ProcessId> {
+    requests
+        .filter_map(|(client, request)| {
+            request
+                .filter(|request| request.service == service && !request.delivered)
+                .map(|request| (next_order.wrapping_sub(request.order), client))
+        })
+        .max_by_key(|(age, _)| *age)
+        .map(|(_, client)| client)
+}
+
+/// Ticks from `now` until the earliest timeout of `requests`, or `None` if
+/// none of them has a timeout.
+fn earliest_timeout<T: Ticks>(
+    now: T,
+    requests: impl Iterator<Item = Option<Request<T>>>,
+) -> Option<T> {
+    requests
+        .filter_map(|request| request.and_then(|request| request.timeout))
+        .map(|timeout| timeout.remaining(now))
+        .min()
+}
+
+/// Copy the reply in `src` into the reply buffer `dest` of the client.
+/// Returns the length of the reply.
+fn copy_reply(
+    src: &ReadableProcessSlice,
+    dest: &WriteableProcessSlice,
+) -> Result<usize, ErrorCode> {
+    let dest = dest.get(..src.len()).ok_or(ErrorCode::SIZE)?;
+    for (d, s) in dest.iter().zip(src.iter()) {
+        d.set(s.get());
+    }
+    Ok(src.len())
+}
+
+/// The message passing IPC mechanism struct.
+///
+/// `MAX_MESSAGE_LEN` is the largest message a client can send. Every process
+/// using this driver has a copy buffer of this size in its grant region.
+pub struct MessageIpc<'a, A: Alarm<'a>, const MAX_MESSAGE_LEN: usize> {
+    /// The grant regions for each process that holds the per-process IPC data.
+    data: Grant<
+        MessageIpcData<A::Ticks, MAX_MESSAGE_LEN>,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    /// Alarm used to expire the timeouts of requests.
+    alarm: &'a A,
+    /// Order assigned to the next request that is sent.
+    next_order: Cell<u32>,
+}
+
+impl<'a, A: Alarm<'a>, const MAX_MESSAGE_LEN: usize> MessageIpc<'a, A, MAX_MESSAGE_LEN> {
+    pub fn new(
+        kernel: &'static Kernel,
+        driver_num: usize,
+        alarm: &'a A,
+        capability: &dyn MemoryAllocationCapability,
+    ) -> Self {
+        Self {
+            data: kernel.create_grant(driver_num, capability),
+            alarm,
+            next_order: Cell::new(0),
+        }
+    }
+
+    /// Find the running process whose descriptor is `descriptor`.
+    fn process_with_descriptor(&self, descriptor: usize) -> Option<ProcessId> {
+        self.data.kernel.process_until(|p| {
+            let processid = p.processid();
+            (processid.id() == descriptor && p.is_running()).then_some(processid)
+        })
+    }
+
+    /// Check whether `client` may send messages to `service`.
+    fn permitted(&self, client: ProcessId, service: &dyn Process) -> bool {
+        self.data.kernel.process_map_or(false, client, |client| {
+            sender_allowed(
+                service.get_ipc_permissions(),
+                client.short_app_id(),
+                client.get_process_name(),
+            )
+        })
+    }
+
+    /// Complete the request of a client, telling it the outcome with an
+    /// upcall.
+    fn complete_request(
+        data: &mut MessageIpcData<A::Ticks, MAX_MESSAGE_LEN>,
+        kernel_data: &GrantKernelData,
+        result: Result<usize, ErrorCode>,
+    ) {
+        if let Some(request) = data.request.take() {
+            let (status, len) = match result {
+                Ok(len) => (into_statuscode(Ok(())), len),
+                Err(e) => (into_statuscode(Err(e)), 0),
+            };
+            let _ = kernel_data.schedule_upcall(upcall::REPLY, (status, len, request.service.id()));
+        }
+    }
+
+    /// Copy queued messages to `service` until it is busy or no messages are
+    /// left.
+    fn deliver(&self, service: ProcessId) {
+        loop {
+            let busy = self
+                .data
+                .enter(service, |data, _| data.serving.is_some())
+                .unwrap_or(true);
+            if busy {
+                return;
+            }
+            let requests = self
+                .data
+                .iter()
+                .map(|pg| (pg.processid(), pg.enter(|data, _| data.request)));
+            let Some(client) = oldest_queued(service, self.next_order.get(), requests) else {
+                return;
+            };
+            let _ = self.data.enter(client, |client_data, client_kernel_data| {
+                self.data
+                    .enter(service, |service_data, service_kernel_data| {
+                        let copied = service_kernel_data
+                            .get_readwrite_processbuffer(rw_allow::RECEIVE)
+                            .and_then(|receive| {
+                                receive.mut_enter(|dest| client_data.deliver_to(dest))
+                            })
+                            .unwrap_or(Err(ErrorCode::SIZE));
+                        match copied {
+                            Ok(len) => {
+                                service_data.serving = Some(client);
+                                let _ = service_kernel_data
+                                    .schedule_upcall(upcall::MESSAGE, (client.id(), len, 0));
+                            }
+                            Err(e) => {
+                                Self::complete_request(client_data, client_kernel_data, Err(e));
+                            }
+                        }
+                    })
+            });
+        }
+    }
+
+    /// Set the alarm for the earliest timeout of all outstanding requests, or
+    /// disarm it if none of them has a timeout.
+    fn update_alarm(&self) {
+        let now = self.alarm.now();
+        let requests = self.data.iter().map(|pg| pg.enter(|data, _| data.request));
+        match earliest_timeout(now, requests) {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Send the message in the send buffer of `processid` to the service
+    /// with descriptor `service_descriptor`.
+    fn send(
+        &self,
+        processid: ProcessId,
+        service_descriptor: usize,
+        timeout_ms: usize,
+    ) -> Result<(), ErrorCode> {
+        let service = self
+            .process_with_descriptor(service_descriptor)
+            .ok_or(ErrorCode::NODEVICE)?;
+        if service == processid {
+            return Err(ErrorCode::INVAL);
+        }
+        if !self
+            .data
+            .kernel
+            .process_map_or(false, service, |p| self.permitted(processid, p))
+        {
+            return Err(ErrorCode::NODEVICE);
+        }
+
+        let timeout = match timeout_ms {
+            0 => None,
+            ms => Some(Timeout {
+                reference: self.alarm.now(),
+                dt: self.alarm.ticks_from_ms(ms as u32),
+            }),
+        };
+        self.data
+            .enter(processid, |data, kernel_data| {
+                if data.request.is_some() {
+                    return Err(ErrorCode::BUSY);
+                }
+                let len = kernel_data
+                    .get_readonly_processbuffer(ro_allow::SEND)
+                    .and_then(|send| {
+                        send.enter(|src| {
+                            let dest = data.message.get_mut(..src.len()).ok_or(ErrorCode::SIZE)?;
+                            src.copy_to_slice(dest);
+                            Ok(src.len())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+                data.message_len = len;
+                data.request = Some(Request {
+                    service,
+                    order: self.next_order.get(),
+                    delivered: false,
+                    timeout,
+                });
+                self.next_order.set(self.next_order.get().wrapping_add(1));
+                Ok(())
+            })
+            .unwrap_or(Err(ErrorCode::NOMEM))?;
+
+        self.deliver(service);
+        self.update_alarm();
+        Ok(())
+    }
+
+    /// Withdraw the outstanding request of `processid`. If the message was
+    /// delivered already, the reply of the service fails with `CANCEL`.
+    fn cancel(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.data
+            .enter(processid, |data, _| data.request.take())
+            .map_err(ErrorCode::from)?
+            .ok_or(ErrorCode::ALREADY)?;
+        self.update_alarm();
+        Ok(())
+    }
+
+    /// Copy the reply in the send buffer of `processid` to the client with
+    /// descriptor `client_descriptor`, completing its request.
+    fn reply(&self, processid: ProcessId, client_descriptor: usize) -> Result<(), ErrorCode> {
+        let serving = self
+            .data
+            .enter(processid, |data, _| data.serving)
+            .map_err(ErrorCode::from)?;
+        let client = match serving {
+            Some(client) if client.id() == client_descriptor => client,
+            _ => return Err(ErrorCode::INVAL),
+        };
+
+        let result = self
+            .data
+            .enter(processid, |_, kernel_data| {
+                self.data
+                    .enter(client, |client_data, client_kernel_data| {
+                        match client_data.request {
+                            Some(request) if request.service == processid && request.delivered => {}
+                            // The client stopped waiting, for example because
+                            // its request timed out or it restarted.
+                            _ => return Err(ErrorCode::CANCEL),
+                        }
+                        // A reply that does not fit fails without completing
+                        // the request, so the service can send a shorter one.
+                        let len = kernel_data
+                            .get_readonly_processbuffer(ro_allow::SEND)
+                            .and_then(|send| {
+                                send.enter(|src| {
+                                    client_kernel_data
+                                        .get_readwrite_processbuffer(rw_allow::REPLY)
+                                        .and_then(|reply| {
+                                            reply.mut_enter(|dest| copy_reply(src, dest))
+                                        })
+                                        .unwrap_or(Err(ErrorCode::SIZE))
+                                })
+                            })
+                            .unwrap_or(Err(ErrorCode::RESERVE))?;
+                        Self::complete_request(client_data, client_kernel_data, Ok(len));
+                        Ok(())
+                    })
+                    .unwrap_or(Err(ErrorCode::CANCEL))
+            })
+            .unwrap_or(Err(ErrorCode::NOMEM));
+
+        // Unless the reply did not fit, the service is done with this client
+        // and can handle the next message.
+        if result != Err(ErrorCode::SIZE) {
+            let _ = self.data.enter(processid, |data, _| data.serving = None);
+            self.deliver(processid);
+        }
+        result
+    }
+}
+
+impl<'a, A: Alarm<'a>, const MAX_MESSAGE_LEN: usize> AlarmClient
+    for MessageIpc<'a, A, MAX_MESSAGE_LEN>
+{
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        self.data.each(|_, data, kernel_data| {
+            let expired = data
+                .request
+                .and_then(|request| request.timeout)
+                .is_some_and(|timeout| timeout.expired(now));
+            if expired {
+                Self::complete_request(data, kernel_data, Err(ErrorCode::NOACK));
+            }
+        });
+        self.update_alarm();
+    }
+}
+
+impl<'a, A: Alarm<'a>, const MAX_MESSAGE_LEN: usize> ProcessTerminationClient
+    for MessageIpc<'a, A, MAX_MESSAGE_LEN>
+{
+    /// Fail the requests to a service that terminated, as it will never
+    /// reply to them.
+    fn process_terminated(&self, processid: ProcessId) {
+        let mut cancelled = false;
+        self.data.each(|_, data, kernel_data| {
+            if data
+                .request
+                .is_some_and(|request| request.service == processid)
+            {
+                Self::complete_request(data, kernel_data, Err(ErrorCode::CANCEL));
+                cancelled = true;
+            }
+        });
+        if cancelled {
+            self.update_alarm();
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, const MAX_MESSAGE_LEN: usize> SyscallDriver
+    for MessageIpc<'a, A, MAX_MESSAGE_LEN>
+{
+    /// Discover services, send messages and reply to them.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver existence check, always returns Ok(())
+    /// - `1`: Perform discovery on the package name passed to `allow_readonly`
+    ///   0. Returns the service descriptor if the service is found and this
+    ///   process may send it messages, otherwise returns `NODEVICE`.
+    /// - `2`: Send the message in `allow_readonly` 1 to the service with
+    ///   descriptor `arg1`, waiting at most `arg2` milliseconds for the reply
+    ///   (0 waits forever). The reply is copied into `allow_readwrite` 1 and
+    ///   signalled with upcall 1. Returns `BUSY` if a request is outstanding
+    ///   already and `SIZE` if the message is longer than the maximum message
+    ///   length. If the message does not fit in the receive buffer of the
+    ///   service, upcall 1 reports `SIZE`, and if the timeout expires it
+    ///   reports `NOACK`.
+    /// - `3`: Reply with the message in `allow_readonly` 1 to the client with
+    ///   descriptor `arg1`, whose message was signalled with upcall 0. The
+    ///   next queued message is then copied into `allow_readwrite` 0. Returns
+    ///   `SIZE` if the reply does not fit in the reply buffer of the client,
+    ///   in which case a shorter reply can be sent, and `CANCEL` if the
+    ///   client is not waiting for the reply anymore.
+    /// - `4`: Cancel the outstanding request of this process. No upcall is
+    ///   signalled for it. Returns `ALREADY` if there is no outstanding
+    ///   request.
+    fn command(
+        &self,
+        command_number: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_number {
+            0 => CommandReturn::success(),
+            1 =>
+            /* Discover */
+            {
+                self.data
+                    .enter(processid, |_, kernel_data| {
+                        kernel_data
+                            .get_readonly_processbuffer(ro_allow::SEARCH)
+                            .and_then(|search| {
+                                search.enter(|slice| {
+                                    self.data
+                                        .kernel
+                                        .process_until(|p| {
+                                            let s = p.get_process_name().as_bytes();
+                                            if s.len() == slice.len()
+                                                && s.iter()
+                                                    .zip(slice.iter())
+                                                    .all(|(c1, c2)| *c1 == c2.get())
+                                                && self.permitted(processid, p)
+                                            {
+                                                Some(CommandReturn::success_u32(
+                                                    p.processid().id() as u32
+                                                ))
+                                            } else {
+                                                None
+                                            }
+                                        })
+                                        .unwrap_or(CommandReturn::failure(ErrorCode::NODEVICE))
+                                })
+                            })
+                            .unwrap_or(CommandReturn::failure(ErrorCode::INVAL))
+                    })
+                    .unwrap_or(CommandReturn::failure(ErrorCode::NOMEM))
+            }
+            2 => self.send(processid, arg1, arg2).into(),
+            3 => self.reply(processid, arg1).into(),
+            4 => self.cancel(processid).into(),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), process::Error> {
+        self.data.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::hil::time::Ticks32;
+    use core::num::NonZeroU32;
+    use std::boxed::Box;
+
+    const MAX_MESSAGE_LEN: usize = 8;
+
+    fn processid(identifier: usize) -> ProcessId {
+        let kernel = Box::leak(Box::new(Kernel::new(&[])));
+        ProcessId::new(kernel, identifier, identifier)
+    }
+
+    fn request(service: ProcessId, order: u32) -> Request<Ticks32> {
+        Request {
+            service,
+            order,
+            delivered: false,
+            timeout: None,
+        }
+    }
+
+    fn with_timeout(reference: u32, dt: u32) -> Request<Ticks32> {
+        Request {
+            timeout: Some(Timeout {
+                reference: reference.into(),
+                dt: dt.into(),
+            }),
+            ..request(processid(1), 0)
+        }
+    }
+
+    /// Permissions allowing the app with `ShortId` 7 and the app with package
+    /// name "client".
+    #[rustfmt::skip]
+    const PERMISSIONS: [u8; 15] = [
+        1, 0,               // Number of `ShortId`s.
+        1, 0,               // Number of package names.
+        7, 0, 0, 0,         // `ShortId` 7.
+        6, b'c', b'l', b'i', b'e', b'n', b't',
+    ];
+
+    #[test]
+    fn services_without_permissions_accept_everyone() {
+        assert!(sender_allowed(None, ShortId::LocallyUnique, "anyone"));
+        let id = ShortId::Fixed(NonZeroU32::new(8).unwrap());
+        assert!(sender_allowed(None, id, "anyone"));
+    }
+
+    #[test]
+    fn permissions_allow_listed_short_ids_and_package_names() {
+        let permissions = || Some(PERMISSIONS[..].try_into().unwrap());
+        let fixed = |id| ShortId::Fixed(NonZeroU32::new(id).unwrap());
+
+        assert!(sender_allowed(permissions(), fixed(7), "other"));
+        assert!(sender_allowed(permissions(), fixed(8), "client"));
+        assert!(sender_allowed(
+            permissions(),
+            ShortId::LocallyUnique,
+            "client"
+        ));
+        assert!(!sender_allowed(permissions(), fixed(8), "other"));
+        assert!(!sender_allowed(
+            permissions(),
+            ShortId::LocallyUnique,
+            "other"
+        ));
+        // Only whole package names match.
+        assert!(!sender_allowed(permissions(), fixed(8), "clien"));
+        assert!(!sender_allowed(permissions(), fixed(8), "clients"));
+    }
+
+    #[test]
+    fn oldest_queued_request_is_delivered_first() {
+        let service = processid(1);
+        let other_service = processid(2);
+        let clients = [processid(3), processid(4), processid(5), processid(6)];
+        let delivered = Request {
+            delivered: true,
+            ..request(service, 1)
+        };
+        let requests = [
+            (clients[0], Some(request(other_service, 0))),
+            (clients[1], Some(delivered)),
+            (clients[2], Some(request(service, 3))),
+            (clients[3], Some(request(service, 2))),
+        ];
+        assert_eq!(
+            oldest_queued(service, 4, requests.into_iter()),
+            Some(clients[3])
+        );
+        assert_eq!(
+            oldest_queued(other_service, 4, requests.into_iter()),
+            Some(clients[0])
+        );
+        assert_eq!(oldest_queued(processid(7), 4, requests.into_iter()), None);
+        assert_eq!(
+            oldest_queued::<Ticks32>(service, 4, core::iter::once((clients[0], None))),
+            None
+        );
+    }
+
+    #[test]
+    fn oldest_queued_request_across_order_wraparound() {
+        let service = processid(1);
+        let (old, new) = (processid(2), processid(3));
+        let requests = [
+            (new, Some(request(service, 1))),
+            (old, Some(request(service, u32::MAX))),
+        ];
+        assert_eq!(oldest_queued(service, 2, requests.into_iter()), Some(old));
+    }
+
+    #[test]
+    fn deliver_copies_message_into_receive_buffer() {
+        let mut data = MessageIpcData::<Ticks32, MAX_MESSAGE_LEN>::default();
+        data.message[..3].copy_from_slice(b"abc");
+        data.message_len = 3;
+        data.request = Some(request(processid(1), 0));
+
+        let mut receive = [0; 4];
+        assert_eq!(data.deliver_to((&mut receive[..]).into()), Ok(3));
+        assert_eq!(&receive, b"abc\0");
+        assert!(data.request.unwrap().delivered);
+    }
+
+    #[test]
+    fn deliver_fails_if_receive_buffer_is_too_small() {
+        let mut data = MessageIpcData::<Ticks32, MAX_MESSAGE_LEN>::default();
+        data.message[..3].copy_from_slice(b"abc");
+        data.message_len = 3;
+        data.request = Some(request(processid(1), 0));
+
+        let mut receive = [0; 2];
+        assert_eq!(
+            data.deliver_to((&mut receive[..]).into()),
+            Err(ErrorCode::SIZE)
+        );
+        assert_eq!(receive, [0; 2]);
+        assert!(!data.request.unwrap().delivered);
+    }
+
+    #[test]
+    fn reply_is_copied_if_it_fits() {
+        let mut reply = [0; 4];
+        assert_eq!(
+            copy_reply((&b"ok"[..]).into(), (&mut reply[..]).into()),
+            Ok(2)
+        );
+        assert_eq!(&reply, b"ok\0\0");
+        assert_eq!(
+            copy_reply((&b"too long"[..]).into(), (&mut reply[..]).into()),
+            Err(ErrorCode::SIZE)
+        );
+        assert_eq!(&reply, b"ok\0\0");
+    }
+
+    #[test]
+    fn alarm_is_only_armed_for_timeouts() {
+        let now = Ticks32::from(100);
+        let service = processid(1);
+        let requests = [None, Some(request(service, 0)), Some(request(service, 1))];
+        assert_eq!(earliest_timeout(now, requests.into_iter()), None);
+
+        let requests = [
+            Some(request(service, 0)),
+            Some(with_timeout(50, 100)),
+            Some(with_timeout(90, 200)),
+        ];
+        assert_eq!(
+            earliest_timeout(now, requests.into_iter()),
+            Some(Ticks32::from(50))
+        );
+    }
+
+    #[test]
+    fn expired_timeout_fires_immediately() {
+        let timeout = with_timeout(u32::MAX - 10, 20).timeout.unwrap();
+        assert!(!timeout.expired(Ticks32::from(5)));
+        assert_eq!(timeout.remaining(Ticks32::from(5)), Ticks32::from(4));
+        assert!(timeout.expired(Ticks32::from(20)));
+        assert_eq!(
+            earliest_timeout(
+                Ticks32::from(20),
+                core::iter::once(Some(with_timeout(u32::MAX - 10, 20)))
+            ),
+            Some(Ticks32::from(0))
+        );
+    }
+}
@@ -27,14 +27,15 @@ use crate::platform::platform::{ProcessFault, SyscallDriverLookup, SyscallFilter
 use crate::platform::scheduler_timer::SchedulerTimer;
 use crate::platform::watchdog::WatchDog;
 use crate::process::ProcessSlot;
-use crate::process::{self, ProcessId, Task};
+use crate::process::{self, ProcessId, ProcessTerminationClient, Task};
 use crate::scheduler::{Scheduler, SchedulingDecision};
 use crate::syscall::SyscallDriver;
 use crate::syscall::{ContextSwitchReason, SyscallReturn};
 use crate::syscall::{Syscall, YieldCall};
 use crate::syscall_driver::CommandReturn;
+use crate::syscall_trace::SyscallTracer;
 use crate::upcall::{Upcall, UpcallId};
-use crate::utilities::cells::NumericCellExt;
+use crate::utilities::cells::{NumericCellExt, OptionalCell};
 
 /// Threshold in microseconds to consider a process's timeslice to be exhausted.
 /// That is, Tock will skip re-scheduling a process if its remaining timeslice
@@ -60,6 +61,12 @@ pub struct Kernel {
     /// created and the data structures for grants have already been
     /// established.
     grants_finalized: Cell<bool>,
+
+    /// Optional hook that is told about every system call the kernel handles.
+    syscall_tracer: OptionalCell<&'static dyn SyscallTracer>,
+
+    /// Optional client that is told when a process terminates.
+    process_termination_client: OptionalCell<&'static dyn ProcessTerminationClient>,
 }
 
 /// Represents the different outcomes when trying to allocate a grant region
@@ -94,9 +101,44 @@ impl Kernel {
             process_identifier_max: Cell::new(0),
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            syscall_tracer: OptionalCell::empty(),
+            process_termination_client: OptionalCell::empty(),
         }
     }
 
+    /// Install a tracer that is told about every system call the kernel
+    /// handles and the value returned to the process.
+    ///
+    /// The tracer has access to the arguments of all system calls of all
+    /// processes, so this requires the `ProcessManagementCapability`.
+    pub fn set_syscall_tracer(
+        &self,
+        tracer: &'static dyn SyscallTracer,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.syscall_tracer.set(tracer);
+    }
+
+    /// Install a client that is told whenever a process terminates, including
+    /// when it is restarted.
+    ///
+    /// The client learns about every process, so this requires the
+    /// `ProcessManagementCapability`.
+    pub fn set_process_termination_client(
+        &self,
+        client: &'static dyn ProcessTerminationClient,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.process_termination_client.set(client);
+    }
+
+    /// Tell the process termination client, if any, that `processid`
+    /// terminated.
+    pub(crate) fn process_terminated(&self, processid: ProcessId) {
+        self.process_termination_client
+            .map(|client| client.process_terminated(processid));
+    }
+
     /// Helper function that moves all non-generic portions of process_map_or
     /// into a non-generic function to reduce code bloat from monomorphization.
     pub(crate) fn get_process(&self, processid: ProcessId) -> Option<&dyn process::Process> {
@@ -314,6 +356,12 @@ impl Kernel {
         Err(())
     }
 
+    /// Returns the slot at `index` in the processes array, so that the process
+    /// stored there can be replaced.
+    pub(crate) fn process_slot(&self, index: usize) -> Option<&ProcessSlot> {
+        self.processes.get(index)
+    }
+
     /// Cause all apps to fault.
     ///
     /// This will call `set_fault_state()` on each app, causing the app to enter
@@ -760,7 +808,7 @@ impl Kernel {
                 // Check all other syscalls for filtering.
                 if let Err(response) = resources.syscall_filter().filter_syscall(process, &syscall)
                 {
-                    process.set_syscall_return_value(SyscallReturn::Failure(response));
+                    self.set_syscall_return(process, &syscall, SyscallReturn::Failure(response));
 
                     if config::CONFIG.trace_syscalls {
                         debug!(
@@ -789,7 +837,7 @@ impl Kernel {
                         rval
                     );
                 }
-                process.set_syscall_return_value(rval);
+                self.set_syscall_return(process, &syscall, rval);
             }
             Syscall::Yield {
                 which,
@@ -799,6 +847,7 @@ impl Kernel {
                 if config::CONFIG.trace_syscalls {
                     debug!("[{:?}] yield. which: {}", process.processid(), which);
                 }
+                self.trace_syscall(process.processid(), &syscall, None);
                 match which.try_into() {
                     Ok(YieldCall::NoWait) => {
                         // If this is a `Yield-WaitFor` AND there are no pending
@@ -1021,7 +1070,7 @@ impl Kernel {
                             );
                         }
 
-                        process.set_syscall_return_value(rval);
+                        self.set_syscall_return(process, &syscall, rval);
                     }
                     Syscall::Command {
                         driver_number,
@@ -1047,7 +1096,7 @@ impl Kernel {
                                 res,
                             );
                         }
-                        process.set_syscall_return_value(res);
+                        self.set_syscall_return(process, &syscall, res);
                     }
                     Syscall::ReadWriteAllow {
                         driver_number,
@@ -1172,7 +1221,7 @@ impl Kernel {
                                 res
                             );
                         }
-                        process.set_syscall_return_value(res);
+                        self.set_syscall_return(process, &syscall, res);
                     }
                     Syscall::UserspaceReadableAllow {
                         driver_number,
@@ -1252,7 +1301,7 @@ impl Kernel {
                                 res
                             );
                         }
-                        process.set_syscall_return_value(res);
+                        self.set_syscall_return(process, &syscall, res);
                     }
                     Syscall::ReadOnlyAllow {
                         driver_number,
@@ -1378,7 +1427,7 @@ impl Kernel {
                             );
                         }
 
-                        process.set_syscall_return_value(res);
+                        self.set_syscall_return(process, &syscall, res);
                     }
                     Syscall::Yield { .. }
                     | Syscall::Exit { .. }
@@ -1420,7 +1469,31 @@ impl Kernel {
                         old_process_id, which, completion_code, optional_return_value,
                     );
                 }
+                self.trace_syscall(old_process_id, &syscall, optional_return_value.as_ref());
             }
         }
     }
+
+    /// Set the return value of `syscall` for `process` and report it to the
+    /// syscall tracer, if any.
+    fn set_syscall_return(
+        &self,
+        process: &dyn process::Process,
+        syscall: &Syscall,
+        rval: SyscallReturn,
+    ) {
+        process.set_syscall_return_value(rval);
+        self.trace_syscall(process.processid(), syscall, Some(&rval));
+    }
+
+    /// Report a handled system call to the syscall tracer, if any.
+    fn trace_syscall(
+        &self,
+        processid: ProcessId,
+        syscall: &Syscall,
+        result: Option<&SyscallReturn>,
+    ) {
+        self.syscall_tracer
+            .map(|tracer| tracer.syscall_traced(processid, syscall, result));
+    }
 }
@@ -171,6 +171,7 @@ pub mod grant;
 pub mod hil;
 pub mod introspection;
 pub mod ipc;
+pub mod ipc_message;
 pub mod platform;
 pub mod process;
 pub mod process_checker;
@@ -178,6 +179,7 @@ pub mod processbuffer;
 pub mod scheduler;
 pub mod storage_permissions;
 pub mod syscall;
+pub mod syscall_trace;
 pub mod upcall;
 pub mod utilities;
 
@@ -186,6 +188,7 @@ mod kernel;
 mod memop;
 mod process_array;
 mod process_binary;
+mod process_fault_dump;
 mod process_loading;
 mod process_policies;
 mod process_printer;
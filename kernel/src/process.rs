@@ -21,13 +21,14 @@ use crate::syscall::{self, Syscall, SyscallReturn};
 use crate::upcall::UpcallId;
 use crate::utilities::capability_ptr::CapabilityPtr;
 use crate::utilities::machine_register::MachineRegister;
-use tock_tbf::types::CommandPermissions;
+use tock_tbf::types::{CommandPermissions, TbfHeaderV2IpcPermissions};
 
 // Export all process related types via `kernel::process::`.
 pub use crate::process_array::{ProcessArray, ProcessSlot};
 pub use crate::process_binary::ProcessBinary;
 pub use crate::process_checker::AcceptedCredential;
 pub use crate::process_checker::{ProcessCheckerMachine, ProcessCheckerMachineClient};
+pub use crate::process_fault_dump::{ProcessFaultDumps, ProcessFaultDumpsClient};
 pub use crate::process_loading::load_processes;
 pub use crate::process_loading::ProcessLoadError;
 pub use crate::process_loading::SequentialProcessLoaderMachine;
@@ -667,6 +668,13 @@ pub trait Process {
     /// Returns `None` if the process has no storage permissions.
     fn get_storage_permissions(&self) -> storage_permissions::StoragePermissions;
 
+    /// Get the IPC permissions for the process, which list the apps that may
+    /// send IPC messages to it.
+    ///
+    /// Returns `None` if the process does not restrict which apps may send it
+    /// messages.
+    fn get_ipc_permissions(&self) -> Option<TbfHeaderV2IpcPermissions<'static>>;
+
     // mpu
 
     /// Configure the MPU to use the process's allocated regions.
@@ -787,6 +795,14 @@ pub trait Process {
     /// Useful for debugging/inspecting the system.
     fn grant_allocated_count(&self) -> Option<usize>;
 
+    /// Return the driver number, the number of bytes and the start of the
+    /// memory of the grant `grant_num`, if the process is active and the grant
+    /// is allocated. The number of bytes includes any padding after the grant.
+    /// This does not enter the grant.
+    ///
+    /// Useful for debugging/inspecting the system.
+    fn grant_allocation(&self, grant_num: usize) -> Option<(usize, usize, NonNull<u8>)>;
+
     /// Get the grant number (grant_num) associated with a given driver number
     /// if there is a grant associated with that driver_num.
     fn lookup_grant_from_driver_num(&self, driver_num: usize) -> Result<usize, Error>;
@@ -854,6 +870,10 @@ pub trait Process {
     /// and the state of the memory protection unit (MPU).
     fn print_full_process(&self, writer: &mut dyn Write);
 
+    /// Print out the context of the process, i.e. the architecture specific
+    /// registers stored when it last stopped executing.
+    fn print_context(&self, writer: &mut dyn Write);
+
     // debug
 
     /// Returns how many syscalls this app has called.
@@ -868,6 +888,13 @@ pub trait Process {
     /// Increment the number of times the process has exceeded its timeslice.
     fn debug_timeslice_expired(&self);
 
+    /// Returns how many times this process has exhausted the CPU budget
+    /// reserved for it by the scheduler.
+    fn debug_budget_overrun_count(&self) -> usize;
+
+    /// Increment the number of times the process has exhausted its CPU budget.
+    fn debug_budget_overrun(&self);
+
     /// Increment the number of times the process called a syscall and record
     /// the last syscall that was called.
     fn debug_syscall_called(&self, last_syscall: Syscall);
@@ -877,6 +904,19 @@ pub trait Process {
     fn debug_syscall_last(&self) -> Option<Syscall>;
 }
 
+/// Client that is told when a process terminates.
+///
+/// Capsules normally find out lazily that a process is gone, when entering
+/// its grant fails. This is for kernel mechanisms that keep state for a
+/// process in the grants of other processes, which must be cleaned up even if
+/// the terminated process is never accessed again.
+pub trait ProcessTerminationClient {
+    /// Called after the process `processid` terminated and its grants were
+    /// freed. This includes processes that are terminated to be restarted,
+    /// which get a new `ProcessId`.
+    fn process_terminated(&self, processid: ProcessId);
+}
+
 /// Opaque identifier for custom grants allocated dynamically from a process's
 /// grant region.
 ///
@@ -1162,6 +1202,9 @@ pub struct ProcessAddresses {
     /// have reached a lower address, this is only the lowest address seen when
     /// the process calls a syscall.
     pub sram_stack_bottom: Option<usize>,
+    /// The stack pointer of the process when it last stopped executing, if
+    /// known.
+    pub sram_stack_pointer: Option<usize>,
 }
 
 /// Collection of process state related to the size in memory of various process
@@ -36,6 +36,10 @@ impl ProcessSlot {
         self.proc.set(Some(process));
     }
 
+    pub(crate) fn clear(&self) {
+        self.proc.set(None);
+    }
+
     /// Return the underlying [`process::Process`] if the slot contains a
     /// process.
     pub fn get(&self) -> Option<&'static dyn process::Process> {
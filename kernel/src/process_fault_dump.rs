@@ -0,0 +1,43 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Interface for reading back the fault dumps of processes.
+
+use core::fmt;
+
+use crate::ErrorCode;
+
+/// Access to the records a fault dump implementation stored when processes
+/// faulted.
+///
+/// Records are typically kept in nonvolatile storage so they survive a reboot,
+/// which makes reading them asynchronous. The format of the records is up to
+/// the implementation, which also decodes them for clients.
+pub trait ProcessFaultDumps<'a> {
+    /// Set the client which receives the results of `read_dump()` and
+    /// `clear_dumps()`.
+    fn set_client(&self, client: &'a dyn ProcessFaultDumpsClient);
+
+    /// The number of records currently stored.
+    fn dump_count(&self) -> usize;
+
+    /// Read the stored record `index`, where 0 is the record of the most recent
+    /// fault. The record is passed to
+    /// [`ProcessFaultDumpsClient::dump_read()`].
+    fn read_dump(&self, index: usize) -> Result<(), ErrorCode>;
+
+    /// Erase all stored records.
+    fn clear_dumps(&self) -> Result<(), ErrorCode>;
+}
+
+/// Client of a [`ProcessFaultDumps`] implementation.
+pub trait ProcessFaultDumpsClient {
+    /// A record requested with `read_dump()` was read. The record is passed
+    /// as a human-readable summary, which is only valid for the duration of
+    /// this call.
+    fn dump_read(&self, result: Result<&dyn fmt::Display, ErrorCode>);
+
+    /// The stored records were erased.
+    fn dumps_cleared(&self, result: Result<(), ErrorCode>);
+}
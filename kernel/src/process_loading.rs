@@ -13,7 +13,9 @@
 //! features a particular board requires.
 
 use core::cell::Cell;
+use core::cmp;
 use core::fmt;
+use core::num::NonZeroU32;
 
 use crate::capabilities::ProcessManagementCapability;
 use crate::config;
@@ -21,7 +23,7 @@ use crate::debug;
 use crate::deferred_call::{DeferredCall, DeferredCallClient};
 use crate::kernel::Kernel;
 use crate::platform::chip::Chip;
-use crate::process::{Process, ShortId};
+use crate::process::{BinaryVersion, Process, ShortId};
 use crate::process_binary::{ProcessBinary, ProcessBinaryError};
 use crate::process_checker::AcceptedCredential;
 use crate::process_checker::{AppIdPolicy, ProcessCheckError, ProcessCheckerMachine};
@@ -30,6 +32,7 @@ use crate::process_policies::ProcessStandardStoragePermissionsPolicy;
 use crate::process_standard::ProcessStandard;
 use crate::process_standard::{ProcessStandardDebug, ProcessStandardDebugFull};
 use crate::utilities::cells::{MapCell, OptionalCell};
+use crate::ErrorCode;
 
 /// Errors that can occur when trying to load and create processes.
 pub enum ProcessLoadError {
@@ -488,6 +491,105 @@ pub enum PaddingRequirement {
     PreAndPostPad,
 }
 
+/// Memory left over when processes were replaced by ones that need less
+/// memory, kept separately for each process slot.
+///
+/// The spare memory of a slot directly follows the memory of the process in
+/// that slot. It is given back to that process's replacement, for example when
+/// an update is rolled back.
+struct SpareMemory {
+    slots: MapCell<&'static mut [Option<&'static mut [u8]>]>,
+}
+
+impl SpareMemory {
+    fn new(slots: &'static mut [Option<&'static mut [u8]>]) -> Self {
+        Self {
+            slots: MapCell::new(slots),
+        }
+    }
+
+    /// Returns the memory from `start` to `end` that a process in slot `index`
+    /// used, followed by the spare memory of the slot if it starts at `end`.
+    ///
+    /// # Safety
+    ///
+    /// The process using the memory must be stopped and must not be used while
+    /// the memory is in use by another process. This includes its
+    /// `ProcessStandard` object, which is stored in this memory: once the
+    /// returned slice is written to, that object is gone, so the process must
+    /// no longer be reachable through its process slot. It may only be used
+    /// again if the memory is given back with `restore()` while
+    /// `memory_untouched()` holds.
+    unsafe fn take(&self, index: usize, start: usize, end: usize) -> &'static mut [u8] {
+        let mut end = end;
+        if let Some(spare) = self.take_slot(index) {
+            if spare.as_ptr() as usize == end {
+                end += spare.len();
+            } else {
+                self.put(index, spare);
+            }
+        }
+        core::slice::from_raw_parts_mut(start as *mut u8, end - start)
+    }
+
+    /// Saves `memory`, the memory a new process in slot `index` did not use,
+    /// as the spare memory of the slot.
+    fn put(&self, index: usize, memory: &'static mut [u8]) {
+        self.slots.map(|slots| {
+            if let Some(slot) = slots.get_mut(index) {
+                *slot = Some(memory);
+            }
+        });
+    }
+
+    /// Gives the memory taken with `take()` back to the process in slot `index`
+    /// that used the memory up to `end`, because it is restarted. Only the
+    /// part of `memory` after `end` is saved as the spare memory of the slot,
+    /// the rest belongs to the restarted process again.
+    fn restore(&self, index: usize, end: usize, memory: &'static mut [u8]) {
+        let start = memory.as_ptr() as usize;
+        let spare = if start >= end {
+            memory
+        } else {
+            let used = cmp::min(end - start, memory.len());
+            memory.split_at_mut(used).1
+        };
+        if !spare.is_empty() {
+            self.put(index, spare);
+        }
+    }
+
+    fn take_slot(&self, index: usize) -> Option<&'static mut [u8]> {
+        self.slots
+            .map(|slots| slots.get_mut(index).and_then(Option::take))
+            .flatten()
+    }
+}
+
+/// Whether `load_process()` left the memory it was given untouched when it
+/// failed with `err`. Only then is the `ProcessStandard` object of a replaced
+/// process, which is stored in that memory, still valid.
+///
+/// Every error is listed so that a new one has to be considered here.
+fn memory_untouched(err: Option<&ProcessLoadError>) -> bool {
+    match err {
+        // No process was created, for example because the binary is padding.
+        None => true,
+        // Returned before `ProcessStandard::create()` writes to the memory.
+        Some(
+            ProcessLoadError::NotEnoughMemory
+            | ProcessLoadError::MpuInvalidFlashLength
+            | ProcessLoadError::MpuConfigurationError
+            | ProcessLoadError::MemoryAddressMismatch { .. }
+            | ProcessLoadError::NoProcessSlot
+            | ProcessLoadError::BinaryError(_)
+            | ProcessLoadError::CheckError(_),
+        ) => true,
+        // Returned after the grant region and the process object were set up.
+        Some(ProcessLoadError::InternalError) => false,
+    }
+}
+
 /// A machine for loading processes stored sequentially in a region of flash.
 ///
 /// Load processes (stored as TBF objects in flash) into runnable process
@@ -526,6 +628,12 @@ pub struct SequentialProcessLoaderMachine<'a, C: Chip + 'static, D: ProcessStand
     state: OptionalCell<SequentialProcessLoaderMachineState>,
     /// Current operating mode of the loading machine.
     run_mode: OptionalCell<SequentialProcessLoaderMachineRunMode>,
+    /// Index in the processes array of the process that the binary being
+    /// loaded at runtime replaces, if it is an update.
+    replace_index: OptionalCell<usize>,
+    /// Memory left over when processes were replaced by ones that need less
+    /// memory.
+    spare_memory: SpareMemory,
 }
 
 impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C, D> {
@@ -536,6 +644,7 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
     pub fn new(
         checker: &'static ProcessCheckerMachine,
         proc_binaries: &'static mut [Option<ProcessBinary>],
+        spare_memory: &'static mut [Option<&'static mut [u8]>],
         kernel: &'static Kernel,
         chip: &'static C,
         flash: &'static [u8],
@@ -561,6 +670,8 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
             fault_policy,
             storage_policy,
             state: OptionalCell::empty(),
+            replace_index: OptionalCell::empty(),
+            spare_memory: SpareMemory::new(spare_memory),
         }
     }
 
@@ -687,6 +798,11 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
                 // are already loaded, we just need to check if this process
                 // binary has the same AppID as an already loaded process.
                 for proc in self.kernel.get_process_iter() {
+                    // The process being updated does not block its
+                    // replacement.
+                    if self.replace_index.contains(&proc.processid().index) {
+                        continue;
+                    }
                     let blocked = self.is_blocked_from_loading_by_process(&process_binary, proc);
                     if blocked {
                         ok_to_load = false;
@@ -698,62 +814,121 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
                     continue;
                 }
 
-                // If we get here it is ok to load the process.
-                match self.kernel.next_available_process_slot() {
+                // If we get here it is ok to load the process. An update takes
+                // the slot of the process it replaces.
+                let available_slot = match self.replace_index.get() {
+                    Some(index) => self
+                        .kernel
+                        .process_slot(index)
+                        .map(|slot| (index, slot))
+                        .ok_or(()),
+                    None => self.kernel.next_available_process_slot(),
+                };
+                match available_slot {
                     Ok((index, slot)) => {
                         // Calculate the ShortId for this new process.
                         let short_app_id = self.policy.map_or(ShortId::LocallyUnique, |policy| {
                             policy.to_short_id(&process_binary)
                         });
 
+                        // A process replacing another one is created in the
+                        // memory of the replaced process, which must be
+                        // stopped first.
+                        let replaced = slot.get().filter(|_| self.replace_index.is_some());
+                        let memory = match replaced {
+                            Some(replaced) => {
+                                replaced.terminate(None);
+                                let addresses = replaced.get_addresses();
+                                // The `ProcessStandard` object of the replaced
+                                // process is stored in the memory that is
+                                // reused, so nothing may reach it through its
+                                // slot while the new process is created.
+                                slot.clear();
+                                // Safety: the replaced process is terminated
+                                // and no longer in its slot. It is only used
+                                // again if the new process could not be
+                                // created and left the memory untouched.
+                                unsafe {
+                                    self.spare_memory.take(
+                                        index,
+                                        addresses.sram_start,
+                                        addresses.sram_end,
+                                    )
+                                }
+                            }
+                            None => self.app_memory.take(),
+                        };
+
                         // Try to create a `Process` object.
                         let load_result = load_process(
                             self.kernel,
                             self.chip,
                             process_binary,
-                            self.app_memory.take(),
+                            memory,
                             short_app_id,
                             index,
                             self.fault_policy,
                             self.storage_policy,
                         );
-                        match load_result {
-                            Ok((new_mem, proc)) => {
-                                self.app_memory.set(new_mem);
-                                match proc {
-                                    Some(p) => {
-                                        if config::CONFIG.debug_load_processes {
-                                            debug!(
-                                                "Loading: Loaded process {}",
-                                                p.get_process_name()
-                                            )
-                                        }
-
-                                        // Store the `ProcessStandard` object in the `PROCESSES`
-                                        // array.
-                                        slot.set(p);
-                                        // Notify the client the process was loaded
-                                        // successfully.
-                                        self.get_current_client().map(|client| {
-                                            client.process_loaded(Ok(()));
-                                        });
-                                    }
-                                    None => {
-                                        if config::CONFIG.debug_load_processes {
-                                            debug!("No process loaded.");
-                                        }
-                                    }
+                        let (new_mem, load_result) = match load_result {
+                            Ok((new_mem, Some(p))) => (new_mem, Ok(p)),
+                            Ok((new_mem, None)) => {
+                                if config::CONFIG.debug_load_processes {
+                                    debug!("No process loaded.");
                                 }
+                                (new_mem, Err(None))
                             }
                             Err((new_mem, err)) => {
-                                self.app_memory.set(new_mem);
                                 if config::CONFIG.debug_load_processes {
                                     debug!("Could not load process: {:?}.", err);
                                 }
+                                (new_mem, Err(Some(err)))
+                            }
+                        };
+                        match load_result {
+                            Ok(p) => {
+                                self.return_memory(index, replaced.is_some(), new_mem);
+                                if config::CONFIG.debug_load_processes {
+                                    debug!("Loading: Loaded process {}", p.get_process_name())
+                                }
+
+                                // Store the `ProcessStandard` object in the `PROCESSES`
+                                // array.
+                                slot.set(p);
+                                // Notify the client the process was loaded
+                                // successfully.
                                 self.get_current_client().map(|client| {
-                                    client.process_loaded(Err(err));
+                                    client.process_loaded(Ok(()));
                                 });
                             }
+                            Err(err) => {
+                                match replaced {
+                                    // The memory is untouched, so the
+                                    // replaced process can run again. It gets
+                                    // its memory back, only the memory after
+                                    // it stays spare.
+                                    Some(replaced) if memory_untouched(err.as_ref()) => {
+                                        self.spare_memory.restore(
+                                            index,
+                                            replaced.get_addresses().sram_end,
+                                            new_mem,
+                                        );
+                                        slot.set(replaced);
+                                        replaced.try_restart(None);
+                                    }
+                                    // Creating the process failed after it
+                                    // started to use the memory, so the
+                                    // replaced process is lost and must stay
+                                    // out of its slot.
+                                    Some(_) => self.return_memory(index, true, new_mem),
+                                    None => self.return_memory(index, false, new_mem),
+                                }
+                                if let Some(err) = err {
+                                    self.get_current_client().map(|client| {
+                                        client.process_loaded(Err(err));
+                                    });
+                                }
+                            }
                         }
                     }
                     Err(()) => {
@@ -766,6 +941,7 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
             }
         }
         self.proc_binaries.put(proc_binaries);
+        self.replace_index.clear();
 
         // We have iterated all discovered `ProcessBinary`s and loaded what we
         // could so now we can signal that process loading is finished.
@@ -777,6 +953,17 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
         Ok(())
     }
 
+    /// Put back the memory that loading a process into slot `index` did not
+    /// use. Memory taken from a replaced process becomes the spare memory of
+    /// the slot, as it does not border `app_memory`.
+    fn return_memory(&self, index: usize, replacing: bool, memory: &'static mut [u8]) {
+        if replacing {
+            self.spare_memory.put(index, memory);
+        } else {
+            self.app_memory.set(memory);
+        }
+    }
+
     /// Check if `pb1` is blocked from running by `pb2`.
     ///
     /// `pb2` blocks `pb1` if:
@@ -1201,6 +1388,7 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
         app_address: usize,
         app_size: usize,
     ) -> Result<(), ProcessLoadError> {
+        self.replace_index.clear();
         let flash = self.flash_bank.get();
         let process_address = app_address - flash.as_ptr() as usize;
         let process_flash = flash.get(process_address..process_address + app_size);
@@ -1230,6 +1418,138 @@ impl<'a, C: Chip, D: ProcessStandardDebug> SequentialProcessLoaderMachine<'a, C,
             )),
         }
     }
+
+    /// Find the running process that the binary at address `app_address` with
+    /// size `app_size` is an update for.
+    ///
+    /// The binary is an update for a process if they have the same AppId or
+    /// ShortId and the binary has a higher version. Returns the index of the
+    /// process in the processes array and the start address and length of its
+    /// binary in flash. Returns `INVAL` if the binary is not valid or no
+    /// process of the same application is running, and `ALREADY` if the
+    /// running process is not older.
+    pub fn find_process_to_update(
+        &self,
+        app_address: usize,
+        app_size: usize,
+    ) -> Result<(usize, usize, usize), ErrorCode> {
+        let flash = self.flash_bank.get();
+        let process_address = app_address - flash.as_ptr() as usize;
+        let process_flash = flash
+            .get(process_address..process_address + app_size)
+            .ok_or(ErrorCode::INVAL)?;
+        let (_, pb) = discover_process_binary(process_flash).or(Err(ErrorCode::INVAL))?;
+        let version = NonZeroU32::new(pb.header.get_binary_version()).map(BinaryVersion::new);
+
+        let process = self
+            .kernel
+            .get_process_iter()
+            .find(|proc| self.is_blocked_from_loading_by_process(&pb, *proc))
+            .ok_or(ErrorCode::INVAL)?;
+        if version <= process.binary_version() {
+            return Err(ErrorCode::ALREADY);
+        }
+        let addresses = process.get_addresses();
+        Ok((
+            process.processid().index,
+            addresses.flash_start,
+            addresses.flash_end - addresses.flash_start,
+        ))
+    }
+
+    /// Find a running process that supersedes another binary of the same
+    /// application still stored in flash.
+    ///
+    /// An older binary is only kept until the update that replaced it is
+    /// committed, so this finds an update that was pending when the board
+    /// rebooted. Returns the index of the process in the processes array and
+    /// the start address and length of the older binary.
+    pub fn find_superseded_binary(&self) -> Option<(usize, usize, usize)> {
+        let mut remaining_flash = self.flash_bank.get();
+        loop {
+            let pb = match discover_process_binary(remaining_flash) {
+                Ok((new_flash, pb)) => {
+                    remaining_flash = new_flash;
+                    pb
+                }
+                Err((new_flash, err)) => {
+                    remaining_flash = new_flash;
+                    match err {
+                        ProcessBinaryError::NotEnoughFlash
+                        | ProcessBinaryError::TbfHeaderNotFound => return None,
+                        _ => continue,
+                    }
+                }
+            };
+            let version = NonZeroU32::new(pb.header.get_binary_version()).map(BinaryVersion::new);
+            let superseding = self.kernel.get_process_iter().find(|process| {
+                process.get_addresses().flash_start != pb.flash.as_ptr() as usize
+                    && self.is_blocked_from_loading_by_process(&pb, *process)
+                    && version < process.binary_version()
+            });
+            if let Some(process) = superseding {
+                return Some((
+                    process.processid().index,
+                    pb.flash.as_ptr() as usize,
+                    pb.flash.len(),
+                ));
+            }
+        }
+    }
+
+    /// Get the start address and length of the binary of the process at index
+    /// `process_index` of the processes array.
+    pub fn process_binary_location(&self, process_index: usize) -> Option<(usize, usize)> {
+        let process = self.kernel.process_slot(process_index)?.get()?;
+        let addresses = process.get_addresses();
+        Some((
+            addresses.flash_start,
+            addresses.flash_end - addresses.flash_start,
+        ))
+    }
+
+    /// Get the ShortId and version of the binary at address `app_address`
+    /// with size `app_size`.
+    ///
+    /// Binaries without a version have version 0. Returns `INVAL` if the
+    /// binary is not valid.
+    pub fn binary_short_id_and_version(
+        &self,
+        app_address: usize,
+        app_size: usize,
+    ) -> Result<(ShortId, u32), ErrorCode> {
+        let flash = self.flash_bank.get();
+        let process_address = app_address - flash.as_ptr() as usize;
+        let process_flash = flash
+            .get(process_address..process_address + app_size)
+            .ok_or(ErrorCode::INVAL)?;
+        let (_, pb) = discover_process_binary(process_flash).or(Err(ErrorCode::INVAL))?;
+        let short_id = self
+            .policy
+            .map_or(ShortId::LocallyUnique, |policy| policy.to_short_id(&pb));
+        Ok((short_id, pb.header.get_binary_version()))
+    }
+
+    /// Start loading the binary at address `app_address` with size `app_size`
+    /// in place of the process at index `process_index` of the processes
+    /// array.
+    ///
+    /// The binary is checked as for [`Self::load_new_process_binary`]. Once
+    /// its credentials are accepted, the running process is terminated and
+    /// the new process is created in its memory, together with any memory
+    /// left over by an earlier replacement. If the new process does not fit,
+    /// the running process is restarted and loading fails with
+    /// `NotEnoughMemory`.
+    pub fn replace_process_binary(
+        &self,
+        app_address: usize,
+        app_size: usize,
+        process_index: usize,
+    ) -> Result<(), ProcessLoadError> {
+        self.load_new_process_binary(app_address, app_size)?;
+        self.replace_index.set(process_index);
+        Ok(())
+    }
 }
 
 impl<'a, C: Chip, D: ProcessStandardDebug> ProcessLoadingAsync<'a>
@@ -1335,3 +1655,125 @@ impl<C: Chip, D: ProcessStandardDebug> crate::process_checker::ProcessCheckerMac
         self.deferred_call.set();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+    use std::vec;
+
+    const PROCESS_LEN: usize = 1024;
+
+    /// Memory for two processes, with the process in slot 0 followed by the
+    /// process in slot 1.
+    fn setup() -> (SpareMemory, usize) {
+        let memory: &'static mut [u8] = vec![0; 2 * PROCESS_LEN].leak();
+        let start = memory.as_ptr() as usize;
+        let slots: &'static mut [Option<&'static mut [u8]>; 2] = Box::leak(Box::new([None, None]));
+        (SpareMemory::new(slots), start)
+    }
+
+    fn range(memory: &[u8]) -> (usize, usize) {
+        let start = memory.as_ptr() as usize;
+        (start, start + memory.len())
+    }
+
+    #[test]
+    fn replace_with_smaller_process_and_roll_back() {
+        let (spare, start) = setup();
+
+        // The update only uses half of the memory of the replaced process.
+        let memory = unsafe { spare.take(0, start, start + PROCESS_LEN) };
+        assert_eq!(range(memory), (start, start + PROCESS_LEN));
+        let (_, unused) = memory.split_at_mut(PROCESS_LEN / 2);
+        spare.put(0, unused);
+
+        // Rolling back gets all of the memory back.
+        let memory = unsafe { spare.take(0, start, start + PROCESS_LEN / 2) };
+        assert_eq!(range(memory), (start, start + PROCESS_LEN));
+    }
+
+    #[test]
+    fn failed_update_gives_memory_back_to_restarted_process() {
+        let (spare, start) = setup();
+
+        // Creating the update fails without using the memory, so all of it
+        // comes back and the replaced process is restarted in it.
+        let memory = unsafe { spare.take(0, start, start + PROCESS_LEN) };
+        spare.restore(0, start + PROCESS_LEN, memory);
+        assert!(spare.take_slot(0).is_none());
+
+        // Replacing the next process must not take the memory of the
+        // restarted one.
+        let memory = unsafe { spare.take(1, start + PROCESS_LEN, start + 2 * PROCESS_LEN) };
+        assert_eq!(
+            range(memory),
+            (start + PROCESS_LEN, start + 2 * PROCESS_LEN)
+        );
+    }
+
+    #[test]
+    fn failed_update_keeps_spare_memory_after_restarted_process() {
+        let (spare, start) = setup();
+        let half = start + PROCESS_LEN / 2;
+
+        // The running process only uses the first half of its memory.
+        let memory = unsafe { spare.take(0, start, start + PROCESS_LEN) };
+        spare.put(0, memory.split_at_mut(PROCESS_LEN / 2).1);
+
+        // An update fails after skipping some memory at the start, for example
+        // because of a fixed RAM address. Only the memory after the restarted
+        // process stays spare.
+        let memory = unsafe { spare.take(0, start, half) };
+        assert_eq!(range(memory), (start, start + PROCESS_LEN));
+        spare.restore(0, half, memory.split_at_mut(16).1);
+        let memory = spare.take_slot(0).unwrap();
+        assert_eq!(range(memory), (half, start + PROCESS_LEN));
+        spare.put(0, memory);
+
+        // Rolling back still gets all of the memory.
+        let memory = unsafe { spare.take(0, start, half) };
+        assert_eq!(range(memory), (start, start + PROCESS_LEN));
+    }
+
+    #[test]
+    fn spare_memory_of_two_processes_is_kept() {
+        let (spare, start) = setup();
+        let second = start + PROCESS_LEN;
+
+        // Both processes are replaced by smaller ones.
+        let memory = unsafe { spare.take(0, start, second) };
+        spare.put(0, memory.split_at_mut(PROCESS_LEN / 2).1);
+        let memory = unsafe { spare.take(1, second, second + PROCESS_LEN) };
+        spare.put(1, memory.split_at_mut(PROCESS_LEN / 4).1);
+
+        // Both can be rolled back with all of their memory.
+        let memory = unsafe { spare.take(0, start, start + PROCESS_LEN / 2) };
+        assert_eq!(range(memory), (start, second));
+        let memory = unsafe { spare.take(1, second, second + PROCESS_LEN / 4) };
+        assert_eq!(range(memory), (second, second + PROCESS_LEN));
+    }
+
+    #[test]
+    fn replaced_process_is_only_restarted_if_memory_is_untouched() {
+        // Errors found before the memory is written to leave the
+        // `ProcessStandard` object of the replaced process intact.
+        assert!(memory_untouched(None));
+        assert!(memory_untouched(Some(&ProcessLoadError::NotEnoughMemory)));
+        assert!(memory_untouched(Some(
+            &ProcessLoadError::MemoryAddressMismatch {
+                actual_address: 0,
+                expected_address: 4,
+            }
+        )));
+        assert!(memory_untouched(Some(&ProcessLoadError::CheckError(
+            ProcessCheckError::InternalError
+        ))));
+
+        // After the grant region and process object were written, the replaced
+        // process is gone.
+        assert!(!memory_untouched(Some(&ProcessLoadError::InternalError)));
+    }
+}
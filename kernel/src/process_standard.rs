@@ -39,7 +39,7 @@ use crate::upcall::UpcallId;
 use crate::utilities::capability_ptr::{CapabilityPtr, CapabilityPtrPermissions};
 use crate::utilities::cells::{MapCell, NumericCellExt, OptionalCell};
 
-use tock_tbf::types::CommandPermissions;
+use tock_tbf::types::{CommandPermissions, TbfHeaderV2IpcPermissions};
 
 /// Interface supported by [`ProcessStandard`] for recording debug information.
 ///
@@ -76,6 +76,11 @@ pub trait ProcessStandardDebug: Default {
     fn set_app_stack_min_pointer(&self, ptr: *const u8);
     /// Get the lowest address of the process's stack , if it was recorded.
     fn get_app_stack_min_pointer(&self) -> Option<*const u8>;
+    /// Record the stack pointer of the process when it stopped executing.
+    fn set_app_stack_pointer(&self, ptr: *const u8);
+    /// Get the stack pointer of the process when it last stopped executing,
+    /// if it was recorded.
+    fn get_app_stack_pointer(&self) -> Option<*const u8>;
     /// Provide the current address of the bottom of the stack and record the
     /// address if it is the lowest address that the process's stack has
     /// reached.
@@ -128,6 +133,20 @@ pub trait ProcessStandardDebug: Default {
     /// Reset the recorded count of the number of the process has exceeded its
     /// timeslice to 0.
     fn reset_timeslice_expiration_count(&self);
+
+    /// Increase the recorded count of the number of times the process has
+    /// exhausted its CPU budget.
+    fn increment_budget_overrun_count(&self);
+    /// Get the recorded count of the number of times the process has exhausted
+    /// its CPU budget.
+    ///
+    /// This should return 0 if
+    /// [`ProcessStandardDebug::increment_budget_overrun_count()`] is never
+    /// called.
+    fn get_budget_overrun_count(&self) -> usize;
+    /// Reset the recorded count of the number of times the process has
+    /// exhausted its CPU budget to 0.
+    fn reset_budget_overrun_count(&self);
 }
 
 /// A debugging implementation for [`ProcessStandard`] that records the full
@@ -166,6 +185,9 @@ struct ProcessStandardDebugFullInner {
     /// How low have we ever seen the stack pointer.
     app_stack_min_pointer: Option<*const u8>,
 
+    /// The stack pointer when the process last stopped executing.
+    app_stack_pointer: Option<*const u8>,
+
     /// How many syscalls have occurred since the process started.
     syscall_count: usize,
 
@@ -179,6 +201,10 @@ struct ProcessStandardDebugFullInner {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// How many times this process has been paused because it exhausted the
+    /// CPU budget the scheduler reserved for it.
+    budget_overrun_count: usize,
 }
 
 impl ProcessStandardDebug for ProcessStandardDebugFull {
@@ -212,6 +238,12 @@ impl ProcessStandardDebug for ProcessStandardDebugFull {
     fn get_app_stack_min_pointer(&self) -> Option<*const u8> {
         self.debug.map_or(None, |d| d.app_stack_min_pointer)
     }
+    fn set_app_stack_pointer(&self, ptr: *const u8) {
+        self.debug.map(|d| d.app_stack_pointer = Some(ptr));
+    }
+    fn get_app_stack_pointer(&self) -> Option<*const u8> {
+        self.debug.map_or(None, |d| d.app_stack_pointer)
+    }
     fn set_new_app_stack_min_pointer(&self, ptr: *const u8) {
         self.debug.map(|d| {
             match d.app_stack_min_pointer {
@@ -265,6 +297,16 @@ impl ProcessStandardDebug for ProcessStandardDebugFull {
     fn reset_timeslice_expiration_count(&self) {
         self.debug.map(|d| d.timeslice_expiration_count = 0);
     }
+
+    fn increment_budget_overrun_count(&self) {
+        self.debug.map(|d| d.budget_overrun_count += 1);
+    }
+    fn get_budget_overrun_count(&self) -> usize {
+        self.debug.map_or(0, |d| d.budget_overrun_count)
+    }
+    fn reset_budget_overrun_count(&self) {
+        self.debug.map(|d| d.budget_overrun_count = 0);
+    }
 }
 
 impl Default for ProcessStandardDebugFull {
@@ -296,6 +338,10 @@ impl ProcessStandardDebug for () {
     fn get_app_stack_min_pointer(&self) -> Option<*const u8> {
         None
     }
+    fn set_app_stack_pointer(&self, _ptr: *const u8) {}
+    fn get_app_stack_pointer(&self) -> Option<*const u8> {
+        None
+    }
     fn set_new_app_stack_min_pointer(&self, _ptr: *const u8) {}
 
     fn set_last_syscall(&self, _syscall: Syscall) {}
@@ -319,6 +365,11 @@ impl ProcessStandardDebug for () {
         0
     }
     fn reset_timeslice_expiration_count(&self) {}
+    fn increment_budget_overrun_count(&self) {}
+    fn get_budget_overrun_count(&self) -> usize {
+        0
+    }
+    fn reset_budget_overrun_count(&self) {}
 }
 
 /// Entry that is stored in the grant pointer table at the top of process
@@ -342,6 +393,31 @@ struct GrantPointerEntry {
     grant_ptr: *mut u8,
 }
 
+/// Returns the address of the grant in `grant_entry`, with the lowest bit that
+/// marks the grant as entered cleared, or 0 if it is not allocated.
+fn unentered_grant_ptr(grant_entry: &GrantPointerEntry) -> usize {
+    grant_entry.grant_ptr as usize & !0x1
+}
+
+/// Returns the number of bytes the grant at `grant_ptr` takes up in the grant
+/// region that ends at `grant_region_end`, given the addresses of all grants
+/// of the process (0 for those that are not allocated).
+///
+/// Grants are allocated downward from the end of the grant region, so a grant
+/// extends up to the next grant above it. This includes the padding needed to
+/// align the grant, and any custom grants allocated between the two, which
+/// the kernel does not keep track of.
+fn grant_size_from_layout(
+    grant_ptr: usize,
+    grant_region_end: usize,
+    grant_ptrs: impl Iterator<Item = usize>,
+) -> usize {
+    let grant_end = grant_ptrs
+        .filter(|&ptr| ptr > grant_ptr)
+        .fold(grant_region_end, cmp::min);
+    grant_end.saturating_sub(grant_ptr)
+}
+
 /// A type for userspace processes in Tock.
 ///
 /// As its name implies, this is the standard implementation for Tock processes
@@ -711,6 +787,8 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
 
         // Mark the app as stopped so the scheduler won't try to run it.
         self.state.set(State::Terminated);
+
+        self.kernel.process_terminated(self.processid());
     }
 
     fn get_restart_count(&self) -> usize {
@@ -750,6 +828,10 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         self.storage_permissions
     }
 
+    fn get_ipc_permissions(&self) -> Option<TbfHeaderV2IpcPermissions<'static>> {
+        self.header.get_ipc_permissions()
+    }
+
     fn number_writeable_flash_regions(&self) -> usize {
         self.header.number_writeable_flash_regions()
     }
@@ -1222,6 +1304,28 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         })
     }
 
+    fn grant_allocation(&self, grant_num: usize) -> Option<(usize, usize, NonNull<u8>)> {
+        // Do not access the grant region of an inactive process.
+        if !self.is_running() {
+            return None;
+        }
+
+        self.grant_pointers.and_then(|grant_pointers| {
+            grant_pointers.get(grant_num).and_then(|grant_entry| {
+                let grant_ptr = NonNull::new(unentered_grant_ptr(grant_entry) as *mut u8)?;
+                // The grant region grows downward from this process struct,
+                // which is the lowest of the kernel data structures at the end
+                // of process memory.
+                let size = grant_size_from_layout(
+                    grant_ptr.as_ptr() as usize,
+                    ptr::from_ref(self) as usize,
+                    grant_pointers.iter().map(unentered_grant_ptr),
+                );
+                Some((grant_entry.driver_num, size, grant_ptr))
+            })
+        })
+    }
+
     fn lookup_grant_from_driver_num(&self, driver_num: usize) -> Result<usize, Error> {
         self.grant_pointers
             .map_or(Err(Error::KernelError), |grant_pointers| {
@@ -1367,6 +1471,7 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         // If the UKB implementation passed us a stack pointer, update our
         // debugging state. This is completely optional.
         if let Some(sp) = stack_pointer {
+            self.debug.set_app_stack_pointer(sp);
             self.debug.set_new_app_stack_min_pointer(sp);
         }
 
@@ -1389,6 +1494,14 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         self.debug.increment_timeslice_expiration_count();
     }
 
+    fn debug_budget_overrun_count(&self) -> usize {
+        self.debug.get_budget_overrun_count()
+    }
+
+    fn debug_budget_overrun(&self) {
+        self.debug.increment_budget_overrun_count();
+    }
+
     fn debug_syscall_called(&self, last_syscall: Syscall) {
         self.debug.increment_syscall_count();
         self.debug.set_last_syscall(last_syscall);
@@ -1413,6 +1526,7 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
             sram_heap_start: self.debug.get_app_heap_start_pointer().map(|p| p as usize),
             sram_stack_top: self.debug.get_app_stack_start_pointer().map(|p| p as usize),
             sram_stack_bottom: self.debug.get_app_stack_min_pointer().map(|p| p as usize),
+            sram_stack_pointer: self.debug.get_app_stack_pointer().map(|p| p as usize),
         }
     }
 
@@ -1430,18 +1544,7 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
             return;
         }
 
-        self.stored_state.map(|stored_state| {
-            // We guarantee the memory bounds pointers provided to the UKB are
-            // correct.
-            unsafe {
-                self.chip.userspace_kernel_boundary().print_context(
-                    self.mem_start(),
-                    self.app_break.get(),
-                    stored_state,
-                    writer,
-                );
-            }
-        });
+        self.print_context(writer);
 
         // Display grant information.
         let number_grants = self.kernel.get_grant_count_and_finalize();
@@ -1516,6 +1619,21 @@ impl<C: Chip, D: 'static + ProcessStandardDebug> Process for ProcessStandard<'_,
         }
     }
 
+    fn print_context(&self, writer: &mut dyn Write) {
+        self.stored_state.map(|stored_state| {
+            // We guarantee the memory bounds pointers provided to the UKB are
+            // correct.
+            unsafe {
+                self.chip.userspace_kernel_boundary().print_context(
+                    self.mem_start(),
+                    self.app_break.get(),
+                    stored_state,
+                    writer,
+                );
+            }
+        });
+    }
+
     fn get_stored_state(&self, out: &mut [u8]) -> Result<usize, ErrorCode> {
         self.stored_state
             .map(|stored_state| {
@@ -2021,6 +2139,7 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
         self.debug.reset_syscall_count();
         self.debug.reset_dropped_upcall_count();
         self.debug.reset_timeslice_expiration_count();
+        self.debug.reset_budget_overrun_count();
 
         // Reset MPU region configuration.
         //
@@ -2325,6 +2444,12 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
         }
     }
 
+    /// Get the number of bytes of persistent storage this process may use,
+    /// from the storage quota TBF header, if it exists.
+    pub fn get_tbf_storage_quota(&self) -> Option<u32> {
+        self.header.get_storage_quota()
+    }
+
     /// The start address of allocated RAM for this process.
     fn mem_start(&self) -> *const u8 {
         self.memory_start
@@ -2365,3 +2490,26 @@ impl<C: 'static + Chip, D: 'static + ProcessStandardDebug> ProcessStandard<'_, C
         self.app_break.get()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grant_size_extends_to_grant_above() {
+        let grants = [0x1f00, 0, 0x1e00, 0x1ec0];
+
+        assert_eq!(
+            grant_size_from_layout(0x1f00, 0x2000, grants.into_iter()),
+            0x100
+        );
+        assert_eq!(
+            grant_size_from_layout(0x1ec0, 0x2000, grants.into_iter()),
+            0x40
+        );
+        assert_eq!(
+            grant_size_from_layout(0x1e00, 0x2000, grants.into_iter()),
+            0xc0
+        );
+    }
+}
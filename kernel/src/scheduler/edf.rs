@@ -0,0 +1,561 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Earliest deadline first scheduler with CPU reservations for Tock
+//!
+//! Processes can be given a CPU reservation: a budget of CPU time they may
+//! use in every period. Each reservation is served by a constant bandwidth
+//! server (CBS), as described in "Integrating Multimedia Applications in Hard
+//! Real-Time Systems" by Luca Abeni and Giorgio Buttazzo. Reserved processes
+//! are scheduled earliest deadline first, and the kernel enforces their
+//! budget with the scheduler timer.
+//!
+//! This scheduler can be summarized by the following rules:
+//!
+//! - Rule 1: Of the ready processes with a reservation and remaining budget,
+//!   the one whose server has the earliest deadline runs, for at most its
+//!   remaining budget.
+//! - Rule 2: When a process wakes up, its server keeps its deadline and
+//!   budget if the remaining budget can be used before the deadline without
+//!   exceeding the reserved bandwidth. Otherwise, the server gets a full
+//!   budget and a deadline one period from now.
+//! - Rule 3: A process that exhausts its budget is throttled until the
+//!   deadline of its server, at which point the budget is replenished and the
+//!   deadline moves one period later (hard reservation). Exhausting the budget
+//!   while the process still wants to run is an overrun, which is recorded in
+//!   the process' debug state.
+//! - Rule 4: Processes without a reservation run round robin in the time that
+//!   is left.
+//!
+//! Reservations are assigned by `ShortId` through board configuration. The
+//! `ShortId` is assigned by the board's credential checking policy, so a
+//! process cannot claim the reservation of another process by using its name.
+//! Processes with a `LocallyUnique` `ShortId` never get a reservation.
+//! Reservations are not requested in the TBF header because whether they can
+//! be met depends on the reservations of all other processes, which only the
+//! board knows. As with any EDF scheduler, deadlines can only be met if the total utilization
+//! of all reservations (the sum of `budget_us / period_us`) stays below 1,
+//! minus the time the kernel itself spends handling interrupts.
+
+use core::cell::Cell;
+use core::num::NonZeroU32;
+
+use crate::collections::list::{List, ListLink, ListNode};
+use crate::hil::time::{self, ConvertTicks, Ticks};
+use crate::kernel::MIN_QUANTA_THRESHOLD_US;
+use crate::platform::chip::Chip;
+use crate::process::{Process, ProcessId, ProcessSlot, ShortId, StoppedExecutingReason};
+use crate::scheduler::{Scheduler, SchedulingDecision};
+
+/// A CPU reservation: `budget_us` of CPU time in every `period_us`.
+#[derive(Copy, Clone, Debug)]
+pub struct Reservation {
+    pub period_us: u32,
+    pub budget_us: u32,
+}
+
+/// Board configuration assigning a CPU reservation to the process with the
+/// given `ShortId`.
+pub struct ProcessReservation {
+    pub short_id: ShortId,
+    pub reservation: Reservation,
+}
+
+/// State of the constant bandwidth server of a process.
+#[derive(Copy, Clone)]
+struct Server<T: Ticks> {
+    /// The process this state belongs to. A restarted or replaced process
+    /// gets a fresh server.
+    processid: ProcessId,
+    reservation: Option<Reservation>,
+    /// Start of the current server period. The deadline is one period later.
+    release: T,
+    remaining_us: u32,
+    /// The process was ready the last time the scheduler looked at it.
+    active: bool,
+    /// The budget is exhausted until the deadline.
+    throttled: bool,
+}
+
+impl<T: Ticks> Server<T> {
+    /// Charges `execution_time_us` to the budget. Returns true if the budget
+    /// is exhausted, in which case the server is throttled until its
+    /// deadline.
+    fn charge(&mut self, execution_time_us: u32) -> bool {
+        self.remaining_us = self.remaining_us.saturating_sub(execution_time_us);
+        // The kernel does not run a process for less than the minimum quanta,
+        // so the budget is exhausted at that point.
+        if self.remaining_us <= MIN_QUANTA_THRESHOLD_US {
+            self.remaining_us = 0;
+            self.throttled = true;
+        }
+        self.throttled
+    }
+}
+
+/// Nodes store per-process state
+pub struct EdfProcessNode<'a, T: Ticks> {
+    proc: &'static ProcessSlot,
+    server: Cell<Option<Server<T>>>,
+    next: ListLink<'a, EdfProcessNode<'a, T>>,
+}
+
+impl<'a, T: Ticks> EdfProcessNode<'a, T> {
+    pub fn new(proc: &'static ProcessSlot) -> EdfProcessNode<'a, T> {
+        EdfProcessNode {
+            proc,
+            server: Cell::new(None),
+            next: ListLink::empty(),
+        }
+    }
+}
+
+impl<'a, T: Ticks> ListNode<'a, EdfProcessNode<'a, T>> for EdfProcessNode<'a, T> {
+    fn next(&'a self) -> &'a ListLink<'a, EdfProcessNode<'a, T>> {
+        &self.next
+    }
+}
+
+pub struct EdfSched<'a, A: 'static + time::Alarm<'static>> {
+    /// Used to read the time, and to wake the chip when a throttled process
+    /// can run again while no other process is ready. The kernel's scheduler
+    /// timer cannot do that, as it is only armed while a process runs.
+    alarm: &'static A,
+    reservations: &'static [ProcessReservation],
+    pub processes: List<'a, EdfProcessNode<'a, A::Ticks>>,
+    /// The process that was last scheduled.
+    last: Cell<Option<ProcessId>>,
+}
+
+impl<'a, A: 'static + time::Alarm<'static>> EdfSched<'a, A> {
+    /// How long a process without a reservation can run before being
+    /// pre-empted
+    pub const BACKGROUND_TIMESLICE_US: u32 = 10000;
+
+    pub fn new(alarm: &'static A, reservations: &'static [ProcessReservation]) -> Self {
+        Self {
+            alarm,
+            reservations,
+            processes: List::new(),
+            last: Cell::new(None),
+        }
+    }
+
+    fn deadline(&self, server: &Server<A::Ticks>, reservation: Reservation) -> A::Ticks {
+        server
+            .release
+            .wrapping_add(self.alarm.ticks_from_us(reservation.period_us))
+    }
+
+    /// Microseconds from `now` until the deadline of `server`, or 0 if it has
+    /// passed.
+    fn us_until_deadline(&self, now: A::Ticks, server: &Server<A::Ticks>) -> u32 {
+        match server.reservation {
+            Some(reservation) => {
+                let deadline = self.deadline(server, reservation);
+                if now.within_range(server.release, deadline) {
+                    self.alarm.ticks_to_us(deadline.wrapping_sub(now))
+                } else {
+                    0
+                }
+            }
+            None => 0,
+        }
+    }
+
+    /// Applies rules 2 and 3 to the server of a process with a reservation
+    /// at time `now`: a throttled server gets a new budget once its deadline
+    /// passed, and a server whose process woke up keeps its deadline only if
+    /// the remaining budget fits in the reserved bandwidth until then.
+    fn update_server(
+        &self,
+        server: &mut Server<A::Ticks>,
+        reservation: Reservation,
+        now: A::Ticks,
+        ready: bool,
+    ) {
+        if server.throttled && self.us_until_deadline(now, server) == 0 {
+            // Replenish the budget for the next period, or from now if the
+            // process has been throttled for longer.
+            let deadline = self.deadline(server, reservation);
+            let period = self.alarm.ticks_from_us(reservation.period_us);
+            server.release = if now.within_range(deadline, deadline.wrapping_add(period)) {
+                deadline
+            } else {
+                now
+            };
+            server.remaining_us = reservation.budget_us;
+            server.throttled = false;
+        }
+
+        if !ready {
+            server.active = false;
+        } else if !server.throttled && !server.active {
+            let until = self.us_until_deadline(now, server) as u64;
+            if server.remaining_us as u64 * reservation.period_us as u64
+                >= until * reservation.budget_us as u64
+            {
+                server.release = now;
+                server.remaining_us = reservation.budget_us;
+            }
+            server.active = true;
+        }
+    }
+
+    /// Returns the valid reservation configured for `short_id`, if any.
+    fn reservation_for(&self, short_id: ShortId) -> Option<Reservation> {
+        self.reservations
+            .iter()
+            .find(|r| r.short_id == short_id)
+            .map(|r| r.reservation)
+            .filter(|r| r.budget_us > 0 && r.budget_us <= r.period_us)
+    }
+
+    /// Returns the server state of the process in `node`, creating it if the
+    /// process in the slot changed.
+    fn server_for(
+        &self,
+        node: &EdfProcessNode<'a, A::Ticks>,
+        process: &dyn Process,
+        now: A::Ticks,
+    ) -> Server<A::Ticks> {
+        let processid = process.processid();
+        match node.server.get() {
+            Some(server) if server.processid == processid => server,
+            _ => {
+                let reservation = self.reservation_for(process.short_app_id());
+                Server {
+                    processid,
+                    reservation,
+                    release: now,
+                    remaining_us: reservation.map_or(0, |r| r.budget_us),
+                    active: false,
+                    throttled: false,
+                }
+            }
+        }
+    }
+
+    /// Moves `node` to the back of the list, so processes without a
+    /// reservation take turns.
+    fn move_to_tail(&self, node: &EdfProcessNode<'a, A::Ticks>) {
+        while let Some(head) = self.processes.pop_head() {
+            self.processes.push_tail(head);
+            if core::ptr::eq(head, node) {
+                break;
+            }
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>, C: Chip> Scheduler<C> for EdfSched<'_, A> {
+    fn next(&self) -> SchedulingDecision {
+        let now = self.alarm.now();
+
+        // Earliest deadline of a ready process with budget left.
+        let mut earliest: Option<(ProcessId, u32, u32)> = None;
+        // First ready process without a reservation.
+        let mut background: Option<ProcessId> = None;
+        // Time until the next ready process is no longer throttled.
+        let mut next_replenish_us: Option<u32> = None;
+
+        for node in self.processes.iter() {
+            let Some(process) = node.proc.get() else {
+                continue;
+            };
+            let mut server = self.server_for(node, process, now);
+            let ready = process.ready();
+
+            if let Some(reservation) = server.reservation {
+                self.update_server(&mut server, reservation, now, ready);
+
+                if ready && server.throttled {
+                    let until = self.us_until_deadline(now, &server);
+                    if next_replenish_us.is_none_or(|us| until < us) {
+                        next_replenish_us = Some(until);
+                    }
+                } else if ready {
+                    let until = self.us_until_deadline(now, &server);
+                    if earliest.is_none_or(|(_, deadline, _)| until < deadline) {
+                        earliest = Some((server.processid, until, server.remaining_us));
+                    }
+                }
+            } else if ready && background.is_none() {
+                background = Some(server.processid);
+            }
+
+            node.server.set(Some(server));
+        }
+
+        let (next, timeslice_us) = match (earliest, background) {
+            (Some((processid, _, remaining_us)), _) => (processid, remaining_us),
+            (None, Some(processid)) => (processid, Self::BACKGROUND_TIMESLICE_US),
+            (None, None) => {
+                // Wake up when the budget of a throttled process is
+                // replenished.
+                if let Some(us) = next_replenish_us {
+                    self.alarm
+                        .set_alarm(now, self.alarm.ticks_from_us(core::cmp::max(us, 1)));
+                }
+                return SchedulingDecision::TrySleep;
+            }
+        };
+
+        // Pre-empt the process when a throttled process can run again, so it
+        // gets its turn at its new deadline.
+        let timeslice_us = next_replenish_us.map_or(timeslice_us, |us| {
+            core::cmp::min(
+                timeslice_us,
+                core::cmp::max(us, MIN_QUANTA_THRESHOLD_US + 1),
+            )
+        });
+
+        self.last.set(Some(next));
+        SchedulingDecision::RunProcess((next, NonZeroU32::new(timeslice_us)))
+    }
+
+    fn result(&self, result: StoppedExecutingReason, execution_time_us: Option<u32>) {
+        // Should never be None as we never run processes cooperatively
+        let execution_time_us = execution_time_us.unwrap_or(0);
+        let Some(last) = self.last.take() else {
+            return;
+        };
+        let Some(node) = self
+            .processes
+            .iter()
+            .find(|node| node.server.get().is_some_and(|s| s.processid == last))
+        else {
+            return;
+        };
+        let Some(mut server) = node.server.get() else {
+            return;
+        };
+
+        if server.reservation.is_none() {
+            self.move_to_tail(node);
+            return;
+        }
+
+        if server.charge(execution_time_us) {
+            if result == StoppedExecutingReason::TimesliceExpired {
+                if let Some(process) = node.proc.get() {
+                    process.debug_budget_overrun();
+                }
+            }
+        }
+        node.server.set(Some(server));
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> time::AlarmClient for EdfSched<'_, A> {
+    fn alarm(&self) {
+        // Nothing to do here: the alarm interrupt wakes the chip from sleep,
+        // and the kernel loop then calls `next()`, which replenishes the
+        // budget of the throttled process.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::hil::time::{Alarm, AlarmClient, Freq1MHz, Ticks32, Time};
+    use crate::kernel::Kernel;
+    use crate::ErrorCode;
+
+    const RESERVATION: Reservation = Reservation {
+        period_us: 10_000,
+        budget_us: 2_000,
+    };
+
+    struct TestAlarm {
+        now: Cell<u32>,
+    }
+
+    impl Time for TestAlarm {
+        type Frequency = Freq1MHz;
+        type Ticks = Ticks32;
+
+        fn now(&self) -> Ticks32 {
+            self.now.get().into()
+        }
+    }
+
+    impl<'a> Alarm<'a> for TestAlarm {
+        fn set_alarm_client(&self, _client: &'a dyn AlarmClient) {}
+        fn set_alarm(&self, _reference: Ticks32, _dt: Ticks32) {}
+        fn get_alarm(&self) -> Ticks32 {
+            0.into()
+        }
+        fn disarm(&self) -> Result<(), ErrorCode> {
+            Ok(())
+        }
+        fn is_armed(&self) -> bool {
+            false
+        }
+        fn minimum_dt(&self) -> Ticks32 {
+            1.into()
+        }
+    }
+
+    fn scheduler(now: u32) -> EdfSched<'static, TestAlarm> {
+        let alarm = std::boxed::Box::leak(std::boxed::Box::new(TestAlarm {
+            now: Cell::new(now),
+        }));
+        EdfSched::new(alarm, &[])
+    }
+
+    fn short_id(id: u32) -> ShortId {
+        ShortId::Fixed(NonZeroU32::new(id).unwrap())
+    }
+
+    fn server(release: u32) -> Server<Ticks32> {
+        let kernel = std::boxed::Box::leak(std::boxed::Box::new(Kernel::new(&[])));
+        Server {
+            processid: ProcessId::new(kernel, 0, 0),
+            reservation: Some(RESERVATION),
+            release: release.into(),
+            remaining_us: RESERVATION.budget_us,
+            active: false,
+            throttled: false,
+        }
+    }
+
+    #[test]
+    fn budget_exhaustion_throttles() {
+        let mut server = server(0);
+        assert!(!server.charge(1_000));
+        assert_eq!(server.remaining_us, 1_000);
+        assert!(!server.throttled);
+
+        // No more than the minimum quanta left counts as exhausted.
+        assert!(server.charge(1_000 - MIN_QUANTA_THRESHOLD_US));
+        assert_eq!(server.remaining_us, 0);
+        assert!(server.throttled);
+    }
+
+    #[test]
+    fn throttled_until_deadline() {
+        let sched = scheduler(0);
+        let mut server = server(0);
+        sched.update_server(&mut server, RESERVATION, 0.into(), true);
+        assert!(server.charge(RESERVATION.budget_us));
+
+        // Still throttled before the deadline.
+        sched.update_server(&mut server, RESERVATION, 9_999.into(), true);
+        assert!(server.throttled);
+        assert_eq!(server.remaining_us, 0);
+        assert_eq!(sched.us_until_deadline(9_999.into(), &server), 1);
+    }
+
+    #[test]
+    fn deadline_postponed_on_replenish() {
+        let sched = scheduler(0);
+        let mut server = server(0);
+        sched.update_server(&mut server, RESERVATION, 0.into(), true);
+        assert!(server.charge(RESERVATION.budget_us));
+
+        // At the deadline the budget is replenished and the deadline moves
+        // one period later, keeping the periods aligned.
+        sched.update_server(&mut server, RESERVATION, 10_500.into(), true);
+        assert!(!server.throttled);
+        assert_eq!(server.remaining_us, RESERVATION.budget_us);
+        assert_eq!(server.release, 10_000.into());
+        assert_eq!(sched.us_until_deadline(10_500.into(), &server), 9_500);
+    }
+
+    #[test]
+    fn long_throttle_restarts_period_from_now() {
+        let sched = scheduler(0);
+        let mut server = server(0);
+        sched.update_server(&mut server, RESERVATION, 0.into(), true);
+        assert!(server.charge(RESERVATION.budget_us));
+
+        // More than a period after the deadline, the new period starts now.
+        sched.update_server(&mut server, RESERVATION, 25_000.into(), true);
+        assert!(!server.throttled);
+        assert_eq!(server.release, 25_000.into());
+    }
+
+    #[test]
+    fn wake_up_keeps_deadline_within_bandwidth() {
+        let sched = scheduler(0);
+        let mut server = server(0);
+        sched.update_server(&mut server, RESERVATION, 0.into(), true);
+        assert!(!server.charge(1_000));
+        sched.update_server(&mut server, RESERVATION, 1_000.into(), false);
+        assert!(!server.active);
+
+        // 1000us left for 6000us until the deadline is within the reserved
+        // bandwidth, so the deadline is kept.
+        sched.update_server(&mut server, RESERVATION, 4_000.into(), true);
+        assert!(server.active);
+        assert_eq!(server.release, 0.into());
+        assert_eq!(server.remaining_us, 1_000);
+    }
+
+    #[test]
+    fn wake_up_postpones_deadline_beyond_bandwidth() {
+        let sched = scheduler(0);
+        let mut server = server(0);
+        sched.update_server(&mut server, RESERVATION, 0.into(), true);
+        assert!(!server.charge(1_000));
+        sched.update_server(&mut server, RESERVATION, 1_000.into(), false);
+
+        // 1000us left for 2000us until the deadline would exceed the
+        // reserved bandwidth, so the server gets a new deadline one period
+        // from now and a full budget.
+        sched.update_server(&mut server, RESERVATION, 8_000.into(), true);
+        assert!(server.active);
+        assert_eq!(server.release, 8_000.into());
+        assert_eq!(server.remaining_us, RESERVATION.budget_us);
+        assert_eq!(sched.us_until_deadline(8_000.into(), &server), 10_000);
+    }
+
+    #[test]
+    fn running_process_keeps_deadline() {
+        let sched = scheduler(0);
+        let mut server = server(0);
+        sched.update_server(&mut server, RESERVATION, 0.into(), true);
+        assert!(!server.charge(1_000));
+
+        // A process that stayed ready is not re-evaluated.
+        sched.update_server(&mut server, RESERVATION, 9_000.into(), true);
+        assert_eq!(server.release, 0.into());
+        assert_eq!(server.remaining_us, 1_000);
+    }
+
+    #[test]
+    fn reservation_matched_by_short_id() {
+        let alarm = std::boxed::Box::leak(std::boxed::Box::new(TestAlarm { now: Cell::new(0) }));
+        let reservations = std::boxed::Box::leak(std::boxed::Box::new([
+            ProcessReservation {
+                short_id: short_id(1),
+                reservation: RESERVATION,
+            },
+            ProcessReservation {
+                short_id: short_id(2),
+                reservation: Reservation {
+                    period_us: 1_000,
+                    budget_us: 2_000,
+                },
+            },
+            ProcessReservation {
+                short_id: ShortId::LocallyUnique,
+                reservation: RESERVATION,
+            },
+        ]));
+        let sched = EdfSched::new(alarm, reservations);
+
+        assert!(sched
+            .reservation_for(short_id(1))
+            .is_some_and(|r| r.budget_us == RESERVATION.budget_us));
+        assert!(sched.reservation_for(short_id(3)).is_none());
+        // A budget larger than the period is ignored.
+        assert!(sched.reservation_for(short_id(2)).is_none());
+        // A `LocallyUnique` ShortId matches no configuration.
+        assert!(sched.reservation_for(ShortId::LocallyUnique).is_none());
+    }
+}
@@ -21,9 +21,10 @@ use crate::capabilities::KerneluserStorageCapability;
 /// fn StoragePermissions::check_read_permission(&self, stored_id: u32) -> bool;
 /// fn StoragePermissions::check_modify_permission(&self, stored_id: u32) -> bool;
 /// fn StoragePermissions::get_write_id(&self) -> Option<u32>;
+/// fn StoragePermissions::get_quota(&self) -> Option<usize>;
 /// ```
 #[derive(Clone, Copy)]
-pub struct StoragePermissions(StoragePermissionsPrivate);
+pub struct StoragePermissions(StoragePermissionsPrivate, Option<usize>);
 
 /// Inner enum type for types of permissions.
 ///
@@ -112,7 +113,7 @@ impl StoragePermissions {
         short_id_fixed: core::num::NonZeroU32,
         _cap: &dyn ApplicationStorageCapability,
     ) -> Self {
-        Self(StoragePermissionsPrivate::SelfOnly(short_id_fixed))
+        Self(StoragePermissionsPrivate::SelfOnly(short_id_fixed), None)
     }
 
     pub fn new_fixed_size(
@@ -125,15 +126,18 @@ impl StoragePermissions {
         modify_permissions: [u32; 8],
         _cap: &dyn ApplicationStorageCapability,
     ) -> Self {
-        Self(StoragePermissionsPrivate::FixedSize(FixedSizePermissions {
-            app_id,
-            write_permission,
-            read_modify_self,
-            read_count,
-            read_permissions,
-            modify_count,
-            modify_permissions,
-        }))
+        Self(
+            StoragePermissionsPrivate::FixedSize(FixedSizePermissions {
+                app_id,
+                write_permission,
+                read_modify_self,
+                read_count,
+                read_permissions,
+                modify_count,
+                modify_permissions,
+            }),
+            None,
+        )
     }
 
     pub fn new_listed(
@@ -144,21 +148,30 @@ impl StoragePermissions {
         modify_permissions: &'static [u32],
         _cap: &dyn ApplicationStorageCapability,
     ) -> Self {
-        Self(StoragePermissionsPrivate::Listed(ListedPermissions {
-            app_id,
-            write_permission,
-            read_modify_self,
-            read_permissions,
-            modify_permissions,
-        }))
+        Self(
+            StoragePermissionsPrivate::Listed(ListedPermissions {
+                app_id,
+                write_permission,
+                read_modify_self,
+                read_permissions,
+                modify_permissions,
+            }),
+            None,
+        )
     }
 
     pub fn new_kernel(_cap: &dyn KerneluserStorageCapability) -> Self {
-        Self(StoragePermissionsPrivate::Kernel)
+        Self(StoragePermissionsPrivate::Kernel, None)
     }
 
     pub fn new_null() -> Self {
-        Self(StoragePermissionsPrivate::Null)
+        Self(StoragePermissionsPrivate::Null, None)
+    }
+
+    /// Limit the number of bytes the application may store with these
+    /// permissions to `quota`.
+    pub fn with_quota(self, quota: usize, _cap: &dyn ApplicationStorageCapability) -> Self {
+        Self(self.0, Some(quota))
     }
 
     /// Check if these storage permissions grant read access to the stored state
@@ -218,4 +231,10 @@ impl StoragePermissions {
             StoragePermissionsPrivate::Null => None,
         }
     }
+
+    /// Retrieve the number of bytes the application may store. Returns `None`
+    /// if the application's storage is not limited.
+    pub fn get_quota(&self) -> Option<usize> {
+        self.1
+    }
 }
@@ -0,0 +1,203 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Tracing of the system calls made by processes.
+//!
+//! When a [`SyscallTracer`] is installed with
+//! [`Kernel::set_syscall_tracer()`](crate::Kernel::set_syscall_tracer), the
+//! kernel reports every system call it handles, together with the value it
+//! returned to the process. [`SyscallTraceBuffer`] is a tracer which records
+//! them in a fixed size ring buffer, overwriting the oldest entries, so they
+//! can be inspected later, for example from the process console.
+//!
+//! Tracing is disabled until [`SyscallTraceControl::set_enabled()`] is called,
+//! and can be restricted to a single process or driver.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let trace_buffer = static_init!(
+//!     [Option<SyscallTraceEntry>; 64],
+//!     [None; 64]
+//! );
+//! let syscall_trace = static_init!(
+//!     SyscallTraceBuffer<'static, nrf52840::rtc::Rtc>,
+//!     SyscallTraceBuffer::new(&base_peripherals.rtc, trace_buffer)
+//! );
+//! board_kernel.set_syscall_tracer(syscall_trace, &process_management_capability);
+//! process_console.set_syscall_trace(syscall_trace);
+//! ```
+
+use core::cell::Cell;
+
+use crate::hil::time::{ConvertTicks, Time};
+use crate::process::ProcessId;
+use crate::syscall::{Syscall, SyscallReturn};
+use crate::utilities::cells::{OptionalCell, TakeCell};
+
+/// A system call handled by the kernel.
+#[derive(Copy, Clone, Debug)]
+pub struct SyscallTraceEntry {
+    /// When the system call was handled, in microseconds. This wraps around
+    /// with the underlying timer.
+    pub timestamp_us: u32,
+    /// The process that made the system call.
+    pub processid: ProcessId,
+    /// The system call with its class, driver and subdriver numbers and its
+    /// arguments.
+    pub syscall: Syscall,
+    /// The value returned to the process. This is `None` for system calls
+    /// that do not return a value (yield and a successful exit).
+    pub result: Option<SyscallReturn>,
+}
+
+/// Receives the system calls handled by the kernel.
+pub trait SyscallTracer {
+    /// Called after the kernel handled `syscall` for the process `processid`
+    /// and returned `result` to it.
+    fn syscall_traced(
+        &self,
+        processid: ProcessId,
+        syscall: &Syscall,
+        result: Option<&SyscallReturn>,
+    );
+}
+
+/// Interface to control a system call trace and read its entries.
+pub trait SyscallTraceControl {
+    /// Start or stop recording system calls.
+    fn set_enabled(&self, enabled: bool);
+
+    /// Whether system calls are being recorded.
+    fn is_enabled(&self) -> bool;
+
+    /// Only record the system calls of `processid`, or of all processes if
+    /// `None`.
+    fn set_process_filter(&self, processid: Option<ProcessId>);
+
+    /// Only record the system calls to the driver `driver_num`, or all
+    /// system calls if `None`. System calls which do not target a driver
+    /// (yield, memop and exit) are not recorded while the filter is set.
+    fn set_driver_filter(&self, driver_num: Option<usize>);
+
+    /// Discard all recorded entries.
+    fn clear(&self);
+
+    /// The number of recorded entries.
+    fn entry_count(&self) -> usize;
+
+    /// Returns the recorded entry `index`, where 0 is the oldest one.
+    fn get_entry(&self, index: usize) -> Option<SyscallTraceEntry>;
+}
+
+/// Records system calls in a ring buffer.
+pub struct SyscallTraceBuffer<'a, T: Time> {
+    time: &'a T,
+    entries: TakeCell<'static, [Option<SyscallTraceEntry>]>,
+    /// Index the next entry is written to.
+    next: Cell<usize>,
+    /// Number of valid entries.
+    count: Cell<usize>,
+    enabled: Cell<bool>,
+    process_filter: OptionalCell<ProcessId>,
+    driver_filter: OptionalCell<usize>,
+}
+
+impl<'a, T: Time> SyscallTraceBuffer<'a, T> {
+    pub fn new(
+        time: &'a T,
+        entries: &'static mut [Option<SyscallTraceEntry>],
+    ) -> SyscallTraceBuffer<'a, T> {
+        SyscallTraceBuffer {
+            time,
+            entries: TakeCell::new(entries),
+            next: Cell::new(0),
+            count: Cell::new(0),
+            enabled: Cell::new(false),
+            process_filter: OptionalCell::empty(),
+            driver_filter: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<T: Time> SyscallTracer for SyscallTraceBuffer<'_, T> {
+    fn syscall_traced(
+        &self,
+        processid: ProcessId,
+        syscall: &Syscall,
+        result: Option<&SyscallReturn>,
+    ) {
+        if !self.enabled.get()
+            || self
+                .process_filter
+                .get()
+                .is_some_and(|filter| filter != processid)
+            || self
+                .driver_filter
+                .get()
+                .is_some_and(|filter| syscall.driver_number() != Some(filter))
+        {
+            return;
+        }
+
+        let entry = SyscallTraceEntry {
+            timestamp_us: self.time.ticks_to_us(self.time.now()),
+            processid,
+            syscall: *syscall,
+            result: result.copied(),
+        };
+        self.entries.map(|entries| {
+            if entries.is_empty() {
+                return;
+            }
+            let next = self.next.get();
+            entries[next] = Some(entry);
+            self.next.set((next + 1) % entries.len());
+            self.count
+                .set(core::cmp::min(self.count.get() + 1, entries.len()));
+        });
+    }
+}
+
+impl<T: Time> SyscallTraceControl for SyscallTraceBuffer<'_, T> {
+    fn set_enabled(&self, enabled: bool) {
+        self.enabled.set(enabled);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    fn set_process_filter(&self, processid: Option<ProcessId>) {
+        self.process_filter.insert(processid);
+    }
+
+    fn set_driver_filter(&self, driver_num: Option<usize>) {
+        self.driver_filter.insert(driver_num);
+    }
+
+    fn clear(&self) {
+        self.entries
+            .map(|entries| entries.iter_mut().for_each(|e| *e = None));
+        self.next.set(0);
+        self.count.set(0);
+    }
+
+    fn entry_count(&self) -> usize {
+        self.count.get()
+    }
+
+    fn get_entry(&self, index: usize) -> Option<SyscallTraceEntry> {
+        let count = self.count.get();
+        if index >= count {
+            return None;
+        }
+        self.entries.map_or(None, |entries| {
+            // The oldest entry is `count` entries before the next one.
+            let oldest = (self.next.get() + entries.len() - count) % entries.len();
+            entries[(oldest + index) % entries.len()]
+        })
+    }
+}
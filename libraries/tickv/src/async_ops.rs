@@ -129,7 +129,7 @@
 use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
 use crate::success_codes::SuccessCode;
-use crate::tickv::{State, TicKV};
+use crate::tickv::{KeyInfo, State, TicKV};
 use core::cell::Cell;
 
 /// The return type from the continue operation
@@ -149,6 +149,8 @@ pub struct AsyncTicKV<'a, C: FlashController<S>, const S: usize> {
     key: Cell<Option<u64>>,
     value: Cell<Option<&'static mut [u8]>>,
     value_length: Cell<usize>,
+    position: Cell<usize>,
+    key_info: Cell<Option<KeyInfo>>,
 }
 
 impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
@@ -164,6 +166,8 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             key: Cell::new(None),
             value: Cell::new(None),
             value_length: Cell::new(0),
+            position: Cell::new(0),
+            key_info: Cell::new(None),
         }
     }
 
@@ -257,6 +261,46 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         }
     }
 
+    /// Finds the next valid object at or after `position`.
+    ///
+    /// `position`: Where to start looking, in bytes from the start of flash.
+    /// `buf`: A buffer to store the value to. If the value does not fit, only
+    ///        the start of it is copied.
+    ///
+    /// Once the operation has completed the object that was found is available
+    /// from `key_info()`.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn next_key(
+        &self,
+        position: usize,
+        buf: &'static mut [u8],
+    ) -> Result<SuccessCode, (&'static mut [u8], ErrorCode)> {
+        self.key_info.set(None);
+        match self.tickv.next_key(position, buf) {
+            Ok(_code) => {
+                // Ok is a problem, since that means no asynchronous operations
+                // were called, which means our client will never get a
+                // callback. We need to error.
+                Err((buf, ErrorCode::ReadFail))
+            }
+            Err(e) => match e {
+                ErrorCode::ReadNotReady(_) => {
+                    self.position.set(position);
+                    self.value.replace(Some(buf));
+                    Ok(SuccessCode::Queued)
+                }
+                _ => Err((buf, e)),
+            },
+        }
+    }
+
+    /// The object found by the last completed `next_key()` operation.
+    pub fn key_info(&self) -> Option<KeyInfo> {
+        self.key_info.get()
+    }
+
     /// Invalidates the key in flash storage
     ///
     /// `hash`: A hashed key.
@@ -297,6 +341,51 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         }
     }
 
+    /// Starts a transaction. This does not access the flash.
+    ///
+    /// See `TicKV::begin_transaction()` for details.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn begin_transaction(&self) -> Result<SuccessCode, ErrorCode> {
+        self.tickv.begin_transaction()
+    }
+
+    /// Commits the transaction started by `begin_transaction()`.
+    ///
+    /// The commit requires multiple flash operations. Call
+    /// `continue_operation()` after each of them has completed, until it no
+    /// longer returns `ReadNotReady` or `WriteNotReady`.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn commit_transaction(&self) -> Result<SuccessCode, ErrorCode> {
+        match self.tickv.commit_transaction() {
+            Ok(_code) => Err(ErrorCode::WriteFail),
+            Err(e) => match e {
+                ErrorCode::ReadNotReady(_)
+                | ErrorCode::EraseNotReady(_)
+                | ErrorCode::WriteNotReady(_) => Ok(SuccessCode::Queued),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Aborts the transaction started by `begin_transaction()`. This does not
+    /// access the flash.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn abort_transaction(&self) -> Result<SuccessCode, ErrorCode> {
+        self.tickv.abort_transaction()
+    }
+
+    /// Returns true if the last operation is waiting on a flash operation
+    /// and `continue_operation()` has to be called once it has completed.
+    pub fn operation_pending(&self) -> bool {
+        self.tickv.state.get() != State::None
+    }
+
     /// Perform a garbage collection on TicKV
     ///
     /// On success a `SuccessCode` will be returned.
@@ -356,7 +445,21 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
                     Err(e) => (Err(e), 0),
                 }
             }
+            State::NextKey(_) => {
+                let buf = self.value.take().unwrap();
+                let ret = self.tickv.next_key(self.position.get(), buf);
+                let length = buf.len();
+                self.value.replace(Some(buf));
+                match ret {
+                    Ok((s, key_info)) => {
+                        self.key_info.set(Some(key_info));
+                        (Ok(s), key_info.value_length.min(length))
+                    }
+                    Err(e) => (Err(e), 0),
+                }
+            }
             State::InvalidateKey(_) => (self.tickv.invalidate_key(self.key.get().unwrap()), 0),
+            State::Transaction(_) => (self.tickv.commit_transaction(), 0),
             State::ZeroiseKey(_) => (self.tickv.zeroise_key(self.key.get().unwrap()), 0),
             State::GarbageCollect(_) => match self.tickv.garbage_collect() {
                 Ok(bytes_freed) => (Ok(SuccessCode::Complete), bytes_freed),
@@ -371,11 +474,9 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
                 (ret, self.value.take(), length)
             }
             Err(e) => match e {
-                ErrorCode::ReadNotReady(_) | ErrorCode::EraseNotReady(_) => (ret, None, 0),
-                ErrorCode::WriteNotReady(_) => {
-                    self.tickv.state.set(State::None);
-                    (ret, None, 0)
-                }
+                ErrorCode::ReadNotReady(_)
+                | ErrorCode::EraseNotReady(_)
+                | ErrorCode::WriteNotReady(_) => (ret, None, 0),
                 _ => {
                     self.tickv.state.set(State::None);
                     (ret, self.value.take(), length)
@@ -44,6 +44,12 @@ pub enum ErrorCode {
     WriteNotReady(usize),
     /// Indicates that the flash erase operation is not yet ready.
     EraseNotReady(usize),
+    /// A transaction has already been started.
+    TransactionActive,
+    /// There is no transaction to commit or abort.
+    NoTransaction,
+    /// The transaction can not track any more keys.
+    TransactionFull,
 }
 
 impl From<ErrorCode> for isize {
@@ -64,6 +70,9 @@ impl From<ErrorCode> for isize {
             ErrorCode::ReadNotReady(_) => -13,
             ErrorCode::WriteNotReady(_) => -14,
             ErrorCode::EraseNotReady(_) => -15,
+            ErrorCode::TransactionActive => -16,
+            ErrorCode::NoTransaction => -17,
+            ErrorCode::TransactionFull => -18,
         }
     }
 }
@@ -234,5 +234,7 @@ pub use crate::tickv::MAIN_KEY;
 #[macro_use]
 extern crate std;
 
+#[cfg(test)]
+mod power_loss_tests;
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,697 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Power-loss fault injection tests.
+//!
+//! `FaultFlashCtrl` is an in-memory flash controller that behaves like NOR
+//! flash (writes can only clear bits) and that loses power during a chosen
+//! write or erase. The tests run a random workload against `TicKV` and
+//! `AsyncTicKV`, losing power at every flash operation in turn, restart TicKV
+//! on the same flash and check that no committed key was lost or corrupted.
+
+use crate::async_ops::AsyncTicKV;
+use crate::error_codes::ErrorCode;
+use crate::flash_controller::FlashController;
+use crate::tickv::{TicKV, MAIN_KEY};
+use core::hash::{Hash, Hasher};
+use std::boxed::Box;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::vec::Vec;
+
+const REGION_SIZE: usize = 256;
+const NUM_REGIONS: usize = 8;
+const FLASH_SIZE: usize = REGION_SIZE * NUM_REGIONS;
+
+/// The number of different keys used by the workload
+const NUM_KEYS: usize = 10;
+const MAX_VALUE_LEN: usize = 24;
+
+/// The number of operations run before and after power is lost
+const OPERATIONS: usize = 40;
+const OPERATIONS_AFTER_RESTART: usize = 10;
+
+/// What happens to the flash operation during which power is lost.
+#[derive(Clone, Copy, Debug)]
+enum Fault {
+    /// Power is lost before the operation starts.
+    Cut,
+    /// Only the first `n` bytes are written or erased.
+    Tear(usize),
+    /// All bytes are written, except that bit `n` keeps its old value.
+    BitFlip(usize),
+}
+
+/// A write or erase done by `FaultFlashCtrl`.
+#[derive(Clone, Copy, Debug)]
+enum FlashOperation {
+    /// A write of the given number of bytes
+    Write(usize),
+    Erase,
+}
+
+/// The keys and values the store should contain.
+type Model = BTreeMap<u64, Vec<u8>>;
+
+struct FaultFlashCtrl {
+    flash: RefCell<[[u8; REGION_SIZE]; NUM_REGIONS]>,
+    /// Return `NotReady` errors and let the test complete the operations
+    asynchronous: bool,
+    /// Lose power during the given write or erase
+    fault: Option<(usize, Fault)>,
+    /// Every write and erase so far
+    operations: RefCell<Vec<FlashOperation>>,
+    powered: Cell<bool>,
+    /// The region being read by an asynchronous read
+    read_region: Cell<Option<usize>>,
+}
+
+impl FaultFlashCtrl {
+    fn new(asynchronous: bool, fault: Option<(usize, Fault)>) -> Self {
+        Self {
+            flash: RefCell::new([[0; REGION_SIZE]; NUM_REGIONS]),
+            asynchronous,
+            fault,
+            operations: RefCell::new(Vec::new()),
+            powered: Cell::new(true),
+            read_region: Cell::new(None),
+        }
+    }
+
+    /// Create a controller with the same flash contents, as if the device was
+    /// restarted.
+    fn restart(&self) -> Self {
+        let ctrl = Self::new(self.asynchronous, None);
+        ctrl.flash.replace(*self.flash.borrow());
+        ctrl
+    }
+
+    /// Set the bytes changed by `operation`, starting at `address`, to
+    /// `update(old_value, index)`, applying the fault if it is due.
+    fn update(
+        &self,
+        operation: FlashOperation,
+        address: usize,
+        update: impl Fn(u8, usize) -> u8,
+    ) -> bool {
+        let index = self.operations.borrow().len();
+        self.operations.borrow_mut().push(operation);
+        let len = match operation {
+            FlashOperation::Write(len) => len,
+            FlashOperation::Erase => REGION_SIZE,
+        };
+        let fault = self
+            .fault
+            .filter(|(fault_at, _)| *fault_at == index)
+            .map(|(_, fault)| fault);
+
+        let mut flash = self.flash.borrow_mut();
+        for i in 0..len {
+            let byte = &mut flash[(address + i) / REGION_SIZE][(address + i) % REGION_SIZE];
+            *byte = match fault {
+                Some(Fault::Cut) => break,
+                Some(Fault::Tear(n)) if i >= n => break,
+                Some(Fault::BitFlip(bit)) if bit / 8 == i => {
+                    let mask = 1 << (bit % 8);
+                    (update(*byte, i) & !mask) | (*byte & mask)
+                }
+                _ => update(*byte, i),
+            };
+        }
+
+        if fault.is_some() {
+            self.powered.set(false);
+        }
+        self.powered.get()
+    }
+}
+
+impl FlashController<REGION_SIZE> for FaultFlashCtrl {
+    fn read_region(
+        &self,
+        region_number: usize,
+        buf: &mut [u8; REGION_SIZE],
+    ) -> Result<(), ErrorCode> {
+        if !self.powered.get() {
+            return Err(ErrorCode::ReadFail);
+        }
+
+        if self.asynchronous {
+            self.read_region.set(Some(region_number));
+            return Err(ErrorCode::ReadNotReady(region_number));
+        }
+
+        buf.copy_from_slice(&self.flash.borrow()[region_number]);
+        Ok(())
+    }
+
+    fn write(&self, address: usize, buf: &[u8]) -> Result<(), ErrorCode> {
+        if !self.powered.get()
+            || !self.update(FlashOperation::Write(buf.len()), address, |old, i| {
+                old & buf[i]
+            })
+        {
+            return Err(ErrorCode::WriteFail);
+        }
+
+        if self.asynchronous {
+            return Err(ErrorCode::WriteNotReady(address));
+        }
+        Ok(())
+    }
+
+    fn erase_region(&self, region_number: usize) -> Result<(), ErrorCode> {
+        if !self.powered.get()
+            || !self.update(
+                FlashOperation::Erase,
+                region_number * REGION_SIZE,
+                |_, _| 0xFF,
+            )
+        {
+            return Err(ErrorCode::EraseFail);
+        }
+
+        if self.asynchronous {
+            return Err(ErrorCode::EraseNotReady(region_number));
+        }
+        Ok(())
+    }
+}
+
+/// The operations used by the workload, so that it can run against both
+/// `TicKV` and `AsyncTicKV`.
+trait Store {
+    fn controller(&self) -> &FaultFlashCtrl;
+    fn initialise(&self) -> Result<(), ErrorCode>;
+    fn append(&self, key: u64, value: &[u8]) -> Result<(), ErrorCode>;
+    fn get(&self, key: u64) -> Result<Vec<u8>, ErrorCode>;
+    fn invalidate(&self, key: u64) -> Result<(), ErrorCode>;
+    fn begin_transaction(&self) -> Result<(), ErrorCode>;
+    fn commit_transaction(&self) -> Result<(), ErrorCode>;
+    fn abort_transaction(&self) -> Result<(), ErrorCode>;
+    fn garbage_collect(&self) -> Result<(), ErrorCode>;
+}
+
+fn hashed_main_key() -> u64 {
+    let mut hash_function = DefaultHasher::new();
+    MAIN_KEY.hash(&mut hash_function);
+    hash_function.finish()
+}
+
+impl Store for TicKV<'_, FaultFlashCtrl, REGION_SIZE> {
+    fn controller(&self) -> &FaultFlashCtrl {
+        &self.controller
+    }
+
+    fn initialise(&self) -> Result<(), ErrorCode> {
+        self.initialise(hashed_main_key()).map(|_| ())
+    }
+
+    fn append(&self, key: u64, value: &[u8]) -> Result<(), ErrorCode> {
+        self.append_key(key, value).map(|_| ())
+    }
+
+    fn get(&self, key: u64) -> Result<Vec<u8>, ErrorCode> {
+        let mut buf = [0; MAX_VALUE_LEN];
+        self.get_key(key, &mut buf)
+            .map(|(_, len)| buf[..len].to_vec())
+    }
+
+    fn invalidate(&self, key: u64) -> Result<(), ErrorCode> {
+        self.invalidate_key(key).map(|_| ())
+    }
+
+    fn begin_transaction(&self) -> Result<(), ErrorCode> {
+        self.begin_transaction().map(|_| ())
+    }
+
+    fn commit_transaction(&self) -> Result<(), ErrorCode> {
+        self.commit_transaction().map(|_| ())
+    }
+
+    fn abort_transaction(&self) -> Result<(), ErrorCode> {
+        self.abort_transaction().map(|_| ())
+    }
+
+    fn garbage_collect(&self) -> Result<(), ErrorCode> {
+        self.garbage_collect().map(|_| ())
+    }
+}
+
+struct AsyncStore<'a> {
+    tickv: AsyncTicKV<'a, FaultFlashCtrl, REGION_SIZE>,
+    buf: Cell<Option<&'static mut [u8]>>,
+}
+
+impl<'a> AsyncStore<'a> {
+    fn new(controller: FaultFlashCtrl, read_buffer: &'a mut [u8; REGION_SIZE]) -> Self {
+        Self {
+            tickv: AsyncTicKV::new(controller, read_buffer, FLASH_SIZE),
+            buf: Cell::new(Some(Box::leak(Box::new([0; MAX_VALUE_LEN])))),
+        }
+    }
+
+    /// Complete flash operations, as the flash callbacks would, until the
+    /// operation has finished. Returns the result and the length of the value.
+    fn finish(&self) -> Result<usize, ErrorCode> {
+        assert!(self.tickv.operation_pending());
+        loop {
+            if let Some(region) = self.tickv.tickv.controller.read_region.take() {
+                let data = self.tickv.tickv.controller.flash.borrow()[region];
+                self.tickv.set_read_buffer(&data);
+            }
+
+            let (ret, buf, len) = self.tickv.continue_operation();
+            match ret {
+                Err(ErrorCode::ReadNotReady(_))
+                | Err(ErrorCode::WriteNotReady(_))
+                | Err(ErrorCode::EraseNotReady(_)) => {}
+                _ => {
+                    if buf.is_some() {
+                        self.buf.set(buf);
+                    }
+                    return ret.map(|_| len);
+                }
+            }
+        }
+    }
+}
+
+impl Store for AsyncStore<'_> {
+    fn controller(&self) -> &FaultFlashCtrl {
+        &self.tickv.tickv.controller
+    }
+
+    fn initialise(&self) -> Result<(), ErrorCode> {
+        match self.tickv.initialise(hashed_main_key()) {
+            Err(ErrorCode::ReadNotReady(_)) => self.finish().map(|_| ()),
+            ret => ret.map(|_| ()),
+        }
+    }
+
+    fn append(&self, key: u64, value: &[u8]) -> Result<(), ErrorCode> {
+        let buf = self.buf.take().unwrap();
+        buf[..value.len()].copy_from_slice(value);
+        match self.tickv.append_key(key, buf, value.len()) {
+            Ok(_) => self.finish().map(|_| ()),
+            Err((buf, e)) => {
+                self.buf.set(Some(buf));
+                Err(e)
+            }
+        }
+    }
+
+    fn get(&self, key: u64) -> Result<Vec<u8>, ErrorCode> {
+        let buf = self.buf.take().unwrap();
+        match self.tickv.get_key(key, buf) {
+            Ok(_) => {
+                let len = self.finish()?;
+                let buf = self.buf.take().unwrap();
+                let value = buf[..len].to_vec();
+                self.buf.set(Some(buf));
+                Ok(value)
+            }
+            Err((buf, e)) => {
+                self.buf.set(Some(buf));
+                Err(e)
+            }
+        }
+    }
+
+    fn invalidate(&self, key: u64) -> Result<(), ErrorCode> {
+        self.tickv.invalidate_key(key)?;
+        self.finish().map(|_| ())
+    }
+
+    fn begin_transaction(&self) -> Result<(), ErrorCode> {
+        self.tickv.begin_transaction().map(|_| ())
+    }
+
+    fn commit_transaction(&self) -> Result<(), ErrorCode> {
+        self.tickv.commit_transaction()?;
+        self.finish().map(|_| ())
+    }
+
+    fn abort_transaction(&self) -> Result<(), ErrorCode> {
+        self.tickv.abort_transaction().map(|_| ())
+    }
+
+    fn garbage_collect(&self) -> Result<(), ErrorCode> {
+        self.tickv.garbage_collect()?;
+        self.finish().map(|_| ())
+    }
+}
+
+/// Start TicKV on the flash of `controller`.
+fn boot(controller: FaultFlashCtrl, read_buffer: &mut [u8; REGION_SIZE]) -> Box<dyn Store + '_> {
+    if controller.asynchronous {
+        Box::new(AsyncStore::new(controller, read_buffer))
+    } else {
+        Box::new(TicKV::new(controller, read_buffer, FLASH_SIZE))
+    }
+}
+
+/// A xorshift generator, so that the workload is the same on every run.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+/// A random sequence of operations on the store, which tracks the keys that
+/// have been committed.
+struct Workload {
+    rng: Rng,
+    keys: [u64; NUM_KEYS],
+    model: Model,
+    /// Describes the fault for failure messages
+    context: std::string::String,
+}
+
+impl Workload {
+    fn new(seed: u64, context: std::string::String) -> Self {
+        let mut keys = [0; NUM_KEYS];
+        for (i, key) in keys.iter_mut().enumerate() {
+            let mut hash_function = DefaultHasher::new();
+            i.hash(&mut hash_function);
+            *key = hash_function.finish();
+        }
+
+        Self {
+            rng: Rng(seed),
+            keys,
+            model: Model::new(),
+            context,
+        }
+    }
+
+    /// Run `operations` random operations. If power is lost, returns the
+    /// contents the store would have had if the interrupted operation had
+    /// completed.
+    fn run(&mut self, store: &dyn Store, operations: usize) -> Result<(), Model> {
+        for _ in 0..operations {
+            let key = self.keys[self.rng.below(NUM_KEYS)];
+            match self.rng.below(10) {
+                0..=3 => self.set(store, key)?,
+                4..=5 => self.delete(store, key)?,
+                6..=7 => self.transaction(store)?,
+                8 => {
+                    let ret = store.garbage_collect();
+                    self.finish(store, ret, self.model.clone())?;
+                }
+                _ => self.check(store, key),
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Vec<u8> {
+        let len = 1 + self.rng.below(MAX_VALUE_LEN);
+        (0..len).map(|_| self.rng.below(256) as u8).collect()
+    }
+
+    /// Update the model to `after` if the operation succeeded.
+    fn finish(
+        &mut self,
+        store: &dyn Store,
+        ret: Result<(), ErrorCode>,
+        after: Model,
+    ) -> Result<(), Model> {
+        if !store.controller().powered.get() {
+            return Err(after);
+        }
+
+        match ret {
+            Ok(()) => self.model = after,
+            // There is no space until garbage has been collected
+            Err(ErrorCode::FlashFull) => {}
+            Err(e) => self.fail(e),
+        }
+        Ok(())
+    }
+
+    fn fail(&self, error: impl Debug) -> ! {
+        panic!("{}: {:?}", self.context, error);
+    }
+
+    fn set(&mut self, store: &dyn Store, key: u64) -> Result<(), Model> {
+        let value = self.value();
+        self.delete(store, key)?;
+
+        let mut after = self.model.clone();
+        after.insert(key, value.clone());
+        let ret = store.append(key, &value);
+        self.finish(store, ret, after)
+    }
+
+    fn delete(&mut self, store: &dyn Store, key: u64) -> Result<(), Model> {
+        if !self.model.contains_key(&key) {
+            return Ok(());
+        }
+
+        let mut after = self.model.clone();
+        after.remove(&key);
+        let ret = store.invalidate(key);
+        self.finish(store, ret, after)
+    }
+
+    /// Set or delete up to three keys in a transaction.
+    fn transaction(&mut self, store: &dyn Store) -> Result<(), Model> {
+        if let Err(e) = store.begin_transaction() {
+            self.fail(e);
+        }
+
+        let mut after = self.model.clone();
+        let mut used = Vec::new();
+        for _ in 0..(1 + self.rng.below(3)) {
+            let key = self.keys[self.rng.below(NUM_KEYS)];
+            let set = self.rng.below(3) != 0;
+            if used.contains(&key) {
+                // Each key can only be changed once in a transaction
+                continue;
+            }
+            used.push(key);
+
+            if after.contains_key(&key) {
+                // This does not write to flash
+                if let Err(e) = store.invalidate(key) {
+                    self.fail(e);
+                }
+                after.remove(&key);
+            }
+            if set {
+                let value = self.value();
+                match store.append(key, &value) {
+                    Ok(()) => {
+                        after.insert(key, value);
+                    }
+                    Err(_) if !store.controller().powered.get() => {
+                        // Nothing was committed
+                        return Err(self.model.clone());
+                    }
+                    Err(ErrorCode::FlashFull) => {
+                        store.abort_transaction().unwrap();
+                        return Ok(());
+                    }
+                    Err(e) => self.fail(e),
+                }
+            }
+        }
+
+        let ret = store.commit_transaction();
+        if ret == Err(ErrorCode::FlashFull) {
+            // There was no space for the commit marker
+            store.abort_transaction().unwrap();
+        }
+        self.finish(store, ret, after)
+    }
+
+    /// Check that `key` has the value that was committed.
+    fn check(&self, store: &dyn Store, key: u64) {
+        match (store.get(key), self.model.get(&key)) {
+            (Ok(value), Some(expected)) if value == *expected => {}
+            (Err(ErrorCode::KeyNotFound), None) => {}
+            (ret, expected) => self.fail((key, ret, expected)),
+        }
+    }
+
+    fn check_all(&self, store: &dyn Store) {
+        for key in self.keys {
+            self.check(store, key);
+        }
+    }
+
+    /// Check that the store holds either the keys from before power was lost,
+    /// or the keys the interrupted operation would have committed, and use
+    /// those from now on.
+    fn recover(&mut self, store: &dyn Store, after: Model) {
+        let observed: Vec<(u64, Result<Vec<u8>, ErrorCode>)> =
+            self.keys.iter().map(|&key| (key, store.get(key))).collect();
+
+        let holds = |model: &Model| {
+            observed
+                .iter()
+                .all(|(key, ret)| match (ret, model.get(key)) {
+                    (Ok(value), Some(expected)) => value == expected,
+                    (Err(ErrorCode::KeyNotFound), None) => true,
+                    // The object was being written when power was lost,
+                    // which is detected by the check sum or the length.
+                    (Err(_), None) => true,
+                    _ => false,
+                })
+        };
+
+        if holds(&after) {
+            self.model = after;
+        } else if !holds(&self.model) {
+            self.fail((observed, &self.model, after));
+        }
+
+        // Remove partially written objects so that the keys can be used again
+        for (key, ret) in observed {
+            if matches!(ret, Err(e) if e != ErrorCode::KeyNotFound) {
+                if let Err(e) = store.invalidate(key) {
+                    self.fail((key, e));
+                }
+            }
+        }
+    }
+}
+
+/// Run the workload, losing power during flash operation `fault_at`, then
+/// restart and check the store.
+fn check_power_loss(asynchronous: bool, seed: u64, fault_at: usize, fault: Fault) {
+    let mut workload = Workload::new(
+        seed,
+        format!(
+            "power lost during flash operation {} ({:?})",
+            fault_at, fault
+        ),
+    );
+    let mut read_buf = [0; REGION_SIZE];
+
+    let store = boot(
+        FaultFlashCtrl::new(asynchronous, Some((fault_at, fault))),
+        &mut read_buf,
+    );
+    let after = match store.initialise() {
+        Ok(()) => match workload.run(&*store, OPERATIONS) {
+            Ok(()) => workload.fail("power was not lost"),
+            Err(after) => after,
+        },
+        Err(_) if !store.controller().powered.get() => Model::new(),
+        Err(e) => workload.fail(e),
+    };
+
+    let controller = store.controller().restart();
+    drop(store);
+    let store = boot(controller, &mut read_buf);
+    if let Err(e) = store.initialise() {
+        workload.fail(e);
+    }
+    workload.recover(&*store, after);
+
+    // The store must keep working after the power loss
+    if let Err(after) = workload.run(&*store, OPERATIONS_AFTER_RESTART) {
+        workload.fail(after);
+    }
+    workload.check_all(&*store);
+}
+
+/// Lose power at every write and erase done by the workload, with every kind
+/// of fault.
+fn check_power_loss_everywhere(asynchronous: bool, seed: u64) {
+    // Find the flash operations done by the workload when power isn't lost
+    let mut read_buf = [0; REGION_SIZE];
+    let store = boot(FaultFlashCtrl::new(asynchronous, None), &mut read_buf);
+    let mut workload = Workload::new(seed, format!("seed {}", seed));
+    store.initialise().unwrap();
+    if let Err(after) = workload.run(&*store, OPERATIONS) {
+        workload.fail(after);
+    }
+    workload.check_all(&*store);
+    let operations = store.controller().operations.take();
+
+    for (fault_at, operation) in operations.into_iter().enumerate() {
+        check_power_loss(asynchronous, seed, fault_at, Fault::Cut);
+        match operation {
+            FlashOperation::Write(len) => {
+                for n in 1..len {
+                    check_power_loss(asynchronous, seed, fault_at, Fault::Tear(n));
+                }
+                for byte in 0..len {
+                    let bit = byte * 8 + byte % 8;
+                    check_power_loss(asynchronous, seed, fault_at, Fault::BitFlip(bit));
+                }
+            }
+            FlashOperation::Erase => {
+                // Erases are long, so only tear them at a few places
+                for n in (1..REGION_SIZE).step_by(REGION_SIZE / 16) {
+                    check_power_loss(asynchronous, seed, fault_at, Fault::Tear(n));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_power_loss() {
+    check_power_loss_everywhere(false, 0x5eed_1234_abcd_0001);
+}
+
+#[test]
+fn test_power_loss_async() {
+    check_power_loss_everywhere(true, 0x5eed_1234_abcd_0001);
+}
+
+/// Keys stored outside of their full home region must still be found after
+/// the objects in the home region were invalidated and garbage was collected.
+fn check_garbage_collect_keeps_overflowed_keys(asynchronous: bool) {
+    let mut read_buf = [0; REGION_SIZE];
+    let store = boot(FaultFlashCtrl::new(asynchronous, None), &mut read_buf);
+    store.initialise().unwrap();
+
+    // All of these keys have region 1 as their home region, which only fits
+    // six of the values, so the last one is stored in region 2.
+    let keys: Vec<u64> = (1..=7).map(|i| i << 16 | 1).collect();
+    let value = [0x42; MAX_VALUE_LEN];
+    for &key in &keys {
+        store.append(key, &value).unwrap();
+    }
+    assert_eq!(
+        store.controller().flash.borrow()[2][3..11],
+        keys[6].to_be_bytes()
+    );
+
+    for &key in &keys[..6] {
+        store.invalidate(key).unwrap();
+    }
+    store.garbage_collect().unwrap();
+    assert_eq!(store.get(keys[6]), Ok(value.to_vec()));
+
+    // Once the key is gone, the region can be erased.
+    store.invalidate(keys[6]).unwrap();
+    store.garbage_collect().unwrap();
+    assert!(store.controller().flash.borrow()[1]
+        .iter()
+        .all(|b| *b == 0xFF));
+    assert_eq!(store.get(keys[6]), Err(ErrorCode::KeyNotFound));
+}
+
+#[test]
+fn test_garbage_collect_keeps_overflowed_keys() {
+    check_garbage_collect_keeps_overflowed_keys(false);
+}
+
+#[test]
+fn test_garbage_collect_keeps_overflowed_keys_async() {
+    check_garbage_collect_keeps_overflowed_keys(true);
+}
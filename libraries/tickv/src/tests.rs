@@ -4,7 +4,10 @@
 
 use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
-use crate::tickv::{TicKV, HASH_OFFSET, LEN_OFFSET, MAIN_KEY, VERSION, VERSION_OFFSET};
+use crate::success_codes::SuccessCode;
+use crate::tickv::{
+    TicKV, HASH_OFFSET, LEN_OFFSET, MAIN_KEY, TRANSACTION_KEY, VERSION, VERSION_OFFSET,
+};
 use core::hash::{Hash, Hasher};
 use std::cell::Cell;
 use std::cell::RefCell;
@@ -248,6 +251,7 @@ mod store_flast_ctrl {
     struct FlashCtrl {
         buf: RefCell<[[u8; 1024]; 64]>,
         run: Cell<u8>,
+        fail_write: Cell<Option<u8>>,
     }
 
     impl FlashCtrl {
@@ -255,6 +259,17 @@ mod store_flast_ctrl {
             Self {
                 buf: RefCell::new([[0xFF; 1024]; 64]),
                 run: Cell::new(0),
+                fail_write: Cell::new(None),
+            }
+        }
+
+        /// Create a controller with the same flash contents, as if the
+        /// device was restarted.
+        fn restart(&self) -> Self {
+            Self {
+                buf: RefCell::new(*self.buf.borrow()),
+                run: Cell::new(100),
+                fail_write: Cell::new(None),
             }
         }
     }
@@ -277,6 +292,13 @@ mod store_flast_ctrl {
                 address / 1024
             );
 
+            if self.fail_write.get() == Some(self.run.get()) {
+                // Pretend power was lost before this write
+                println!("  Failed");
+                self.run.set(self.run.get() + 1);
+                return Err(ErrorCode::WriteFail);
+            }
+
             for (i, d) in buf.iter().enumerate() {
                 self.buf.borrow_mut()[address / 1024][(address % 1024) + i] = *d;
             }
@@ -449,6 +471,243 @@ mod store_flast_ctrl {
         );
     }
 
+    #[test]
+    fn test_next_key() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initialise(hash).unwrap();
+
+        let value: [u8; 32] = [0x23; 32];
+        let mut buf: [u8; 8] = [0; 8];
+
+        println!("Add keys ONE and TWO");
+        tickv.append_key(get_hashed_key(b"ONE"), &value).unwrap();
+        tickv.append_key(get_hashed_key(b"TWO"), &value).unwrap();
+
+        println!("Delete Key ONE");
+        tickv.invalidate_key(get_hashed_key(b"ONE")).unwrap();
+
+        println!("Find all keys");
+        let mut keys = std::vec::Vec::new();
+        let mut position = 0;
+        loop {
+            match tickv.next_key(position, &mut buf) {
+                Ok((_, key_info)) => {
+                    keys.push((key_info.hashed_key, key_info.value_length));
+                    assert!(key_info.next_position > position);
+                    position = key_info.next_position;
+                }
+                Err(e) => {
+                    assert_eq!(e, ErrorCode::KeyNotFound);
+                    break;
+                }
+            }
+        }
+
+        keys.sort();
+        let mut expected = std::vec![(hash, 0), (get_hashed_key(b"TWO"), 32)];
+        expected.sort();
+        assert_eq!(keys, expected);
+        assert_eq!(buf, [0x23; 8]);
+    }
+
+    #[test]
+    fn test_next_key_corrupt_object() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initialise(hash).unwrap();
+
+        println!("Store an object in the last region whose value runs past the region");
+        {
+            let mut flash = tickv.controller.buf.borrow_mut();
+            let region = &mut flash[63];
+            region[0] = 1;
+            region[1] = 0x8F;
+            region[2] = 0xFF;
+            region[3..11].copy_from_slice(&[0x12; 8]);
+        }
+
+        let mut buf: [u8; 2048] = [0; 2048];
+        assert_eq!(
+            tickv.next_key(63 * 1024, &mut buf),
+            Err(ErrorCode::CorruptData)
+        );
+
+        println!("The read buffer is still available");
+        let (_, key_info) = tickv.next_key(0, &mut buf).unwrap();
+        assert_eq!(key_info.hashed_key, hash);
+    }
+
+    /// Add key ONE and then replace it and add key TWO in a transaction
+    fn start_transaction(tickv: &TicKV<FlashCtrl, 1024>) {
+        let value: [u8; 32] = [0x23; 32];
+        let new_value: [u8; 32] = [0x42; 32];
+        let mut buf: [u8; 32] = [0; 32];
+
+        // Set an invalid value here to skip checking the keys
+        tickv.controller.run.set(100);
+
+        println!("Add key ONE");
+        tickv.append_key(get_hashed_key(b"ONE"), &value).unwrap();
+
+        println!("Start transaction");
+        tickv.begin_transaction().unwrap();
+        assert_eq!(tickv.begin_transaction(), Err(ErrorCode::TransactionActive));
+
+        println!("Replace key ONE and add key TWO");
+        assert_eq!(
+            tickv.append_key(get_hashed_key(b"ONE"), &new_value),
+            Err(ErrorCode::KeyAlreadyExists)
+        );
+        assert_eq!(
+            tickv.invalidate_key(get_hashed_key(b"ONE")),
+            Ok(SuccessCode::Complete)
+        );
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &new_value)
+            .unwrap();
+        tickv
+            .append_key(get_hashed_key(b"TWO"), &new_value)
+            .unwrap();
+
+        println!("Check the changes are not visible yet");
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, value);
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"TWO"), &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+    }
+
+    #[test]
+    fn test_transaction_commit() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initialise(hash).unwrap();
+
+        let mut buf: [u8; 32] = [0; 32];
+
+        start_transaction(&tickv);
+
+        println!("Commit transaction");
+        tickv.commit_transaction().unwrap();
+        assert_eq!(tickv.commit_transaction(), Err(ErrorCode::NoTransaction));
+
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, [0x42; 32]);
+        tickv.get_key(get_hashed_key(b"TWO"), &mut buf).unwrap();
+        assert_eq!(buf, [0x42; 32]);
+        assert_eq!(
+            tickv.get_key(TRANSACTION_KEY, &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+    }
+
+    #[test]
+    fn test_transaction_abort() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initialise(hash).unwrap();
+
+        let mut buf: [u8; 32] = [0; 32];
+
+        start_transaction(&tickv);
+
+        println!("Abort transaction");
+        tickv.abort_transaction().unwrap();
+        assert_eq!(tickv.commit_transaction(), Err(ErrorCode::NoTransaction));
+
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, [0x23; 32]);
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"TWO"), &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+
+        println!("Add key TWO outside of a transaction");
+        tickv.append_key(get_hashed_key(b"TWO"), &buf).unwrap();
+        tickv.get_key(get_hashed_key(b"TWO"), &mut buf).unwrap();
+        assert_eq!(buf, [0x23; 32]);
+    }
+
+    #[test]
+    fn test_transaction_power_loss_before_commit() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initialise(hash).unwrap();
+
+        start_transaction(&tickv);
+
+        println!("Lose power while writing the commit marker");
+        tickv.controller.fail_write.set(Some(103));
+        assert_eq!(tickv.commit_transaction(), Err(ErrorCode::WriteFail));
+
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv =
+            TicKV::<FlashCtrl, 1024>::new(tickv.controller.restart(), &mut read_buf, 0x10000);
+        tickv.initialise(hash).unwrap();
+
+        let mut buf: [u8; 32] = [0; 32];
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, [0x23; 32]);
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"TWO"), &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+    }
+
+    #[test]
+    fn test_transaction_power_loss_during_commit() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initialise(hash).unwrap();
+
+        start_transaction(&tickv);
+
+        println!("Lose power after writing the commit marker");
+        tickv.controller.fail_write.set(Some(104));
+        assert_eq!(tickv.commit_transaction(), Err(ErrorCode::WriteFail));
+
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv =
+            TicKV::<FlashCtrl, 1024>::new(tickv.controller.restart(), &mut read_buf, 0x10000);
+        tickv.initialise(hash).unwrap();
+
+        let mut buf: [u8; 32] = [0; 32];
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, [0x42; 32]);
+        tickv.get_key(get_hashed_key(b"TWO"), &mut buf).unwrap();
+        assert_eq!(buf, [0x42; 32]);
+        assert_eq!(
+            tickv.get_key(TRANSACTION_KEY, &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+    }
+
     #[test]
     fn test_garbage_collect() {
         let mut read_buf: [u8; 1024] = [0; 1024];
@@ -666,3 +925,82 @@ mod no_check_store_flast_ctrl {
         );
     }
 }
+
+/// Tests using a flash controller with more regions than `garbage_collect()`
+/// collects at a time
+mod many_regions_flash_ctrl {
+    use super::*;
+
+    const REGIONS: usize = 136;
+
+    struct FlashCtrl {
+        buf: RefCell<std::vec::Vec<[u8; 64]>>,
+    }
+
+    impl FlashCtrl {
+        fn new() -> Self {
+            Self {
+                buf: RefCell::new(vec![[0xFF; 64]; REGIONS]),
+            }
+        }
+    }
+
+    impl FlashController<64> for FlashCtrl {
+        fn read_region(&self, region_number: usize, buf: &mut [u8; 64]) -> Result<(), ErrorCode> {
+            *buf = self.buf.borrow()[region_number];
+            Ok(())
+        }
+
+        fn write(&self, address: usize, buf: &[u8]) -> Result<(), ErrorCode> {
+            for (i, d) in buf.iter().enumerate() {
+                self.buf.borrow_mut()[address / 64][(address % 64) + i] = *d;
+            }
+            Ok(())
+        }
+
+        fn erase_region(&self, region_number: usize) -> Result<(), ErrorCode> {
+            self.buf.borrow_mut()[region_number] = [0xFF; 64];
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_garbage_collect_keeps_search_paths() {
+        let mut read_buf: [u8; 64] = [0; 64];
+        let main_key = get_hashed_key(MAIN_KEY);
+        // The regions used below don't hold the main key.
+        assert_eq!((main_key & 0xFFFF) as usize % REGIONS, 4);
+
+        let tickv = TicKV::<FlashCtrl, 64>::new(FlashCtrl::new(), &mut read_buf, 64 * REGIONS);
+        tickv.initialise(main_key).unwrap();
+
+        // Only one of these values fits in a region.
+        let value: [u8; 24] = [0x23; 24];
+        let mut buf: [u8; 24] = [0; 24];
+        // A key with home region `home`.
+        let key = |i: u64, home: u64| i << 16 | home;
+
+        // The second key with home region 1 is stored in region 2, the second
+        // one with home region 70 in region 71.
+        for home in [1, 70] {
+            tickv.append_key(key(1, home), &value).unwrap();
+            tickv.append_key(key(2, home), &value).unwrap();
+            tickv.invalidate_key(key(1, home)).unwrap();
+        }
+        // Region 65 is 1 modulo 64.
+        tickv.append_key(key(1, 65), &value).unwrap();
+        tickv.invalidate_key(key(1, 65)).unwrap();
+
+        // Only region 65 can be erased.
+        assert_eq!(tickv.garbage_collect(), Ok(64));
+        assert!(tickv.controller.buf.borrow()[65].iter().all(|b| *b == 0xFF));
+        for home in [1, 70] {
+            tickv.get_key(key(2, home), &mut buf).unwrap();
+        }
+
+        for home in [1, 70] {
+            tickv.invalidate_key(key(2, home)).unwrap();
+        }
+        assert_eq!(tickv.garbage_collect(), Ok(4 * 64));
+    }
+}
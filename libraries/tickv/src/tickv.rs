@@ -9,6 +9,7 @@ use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
 use crate::success_codes::SuccessCode;
 use core::cell::Cell;
+use core::cmp;
 
 /// The current version of TicKV
 pub const VERSION: u8 = 1;
@@ -23,6 +24,8 @@ pub(crate) enum InitState {
     EraseComplete,
     /// Trying to read a region while appending a key
     AppendKeyReadRegion(usize),
+    /// Recovering a transaction that was interrupted while being committed
+    Recover(TransactionState),
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -33,10 +36,26 @@ pub(crate) enum KeyState {
 
 #[derive(Clone, Copy, PartialEq)]
 pub(crate) enum RubbishState {
+    /// Trying to read a region to find the objects stored outside of their
+    /// home region, with the bytes freed so far
+    ScanRegion(usize, usize),
     ReadRegion(usize, usize),
     EraseRegion(usize, usize),
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum TransactionState {
+    /// Trying to write the commit marker, after reading the region if set
+    WriteMarker(Option<usize>),
+    /// Trying to update the flags of the object at the index of the
+    /// transaction, after reading its region if set
+    Apply(usize, bool),
+    /// Trying to invalidate the commit marker, after reading the region if set
+    ClearMarker(Option<usize>),
+    /// Trying to find the commit marker, after reading the region if set
+    FindMarker(Option<usize>),
+}
+
 #[derive(Clone, Copy, PartialEq)]
 /// The current state machine when trying to complete a previous operation.
 /// This is used when returning from a complete async `FlashController` call.
@@ -53,6 +72,10 @@ pub(crate) enum State {
     InvalidateKey(KeyState),
     /// Zeroizing a key
     ZeroiseKey(KeyState),
+    /// Finding the next key
+    NextKey(KeyState),
+    /// Committing a transaction
+    Transaction(TransactionState),
     /// Running garbage collection
     GarbageCollect(RubbishState),
 }
@@ -64,6 +87,24 @@ pub struct TicKV<'a, C: FlashController<S>, const S: usize> {
     flash_size: usize,
     pub(crate) read_buffer: Cell<Option<&'a mut [u8; S]>>,
     pub(crate) state: Cell<State>,
+    transaction: Cell<Option<Transaction>>,
+    /// Regions that `garbage_collect()` must not erase, as they are searched
+    /// before a valid object stored outside of its home region is found.
+    /// They are tracked for `SEARCH_PATH_WINDOW` regions at a time, as the
+    /// first region of the window and a mask where bit `n` is set if region
+    /// `first + n` must be kept.
+    search_path_regions: Cell<(usize, u64)>,
+}
+
+/// A valid object found by `next_key()`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyInfo {
+    /// The hashed key of the object.
+    pub hashed_key: u64,
+    /// The length of the value of the object.
+    pub value_length: usize,
+    /// The position to pass to `next_key()` to find the object after this one.
+    pub next_position: usize,
 }
 
 /// This is the current object header used for TicKV objects
@@ -77,6 +118,7 @@ struct ObjectHeader {
 }
 
 pub(crate) const FLAGS_VALID: u8 = 8;
+pub(crate) const FLAGS_PENDING: u8 = 4;
 
 impl ObjectHeader {
     fn new(hashed_key: u64, len: u16) -> Self {
@@ -101,6 +143,137 @@ pub(crate) const CHECK_SUM_LEN: usize = 4;
 /// `initialise()`.
 pub const MAIN_KEY: &[u8; 15] = b"tickv-super-key";
 
+/// The hashed key of the commit marker written by `commit_transaction()`.
+pub const TRANSACTION_KEY: u64 = 0x7469_636b_762d_7478;
+
+/// The maximum number of keys that can be appended, and the maximum number of
+/// keys that can be invalidated, in a single transaction.
+pub const MAX_TRANSACTION_KEYS: usize = 8;
+
+/// The length of the commit marker value. This is the number of appended and
+/// invalidated objects followed by the address and hashed key of each of them.
+const MARKER_LENGTH: usize = 2 + 2 * MAX_TRANSACTION_KEYS * MARKER_OBJECT_LENGTH;
+const MARKER_OBJECT_LENGTH: usize = 4 + 8;
+
+/// The number of regions `garbage_collect()` collects after each search for
+/// the regions it has to keep.
+const SEARCH_PATH_WINDOW: usize = 64;
+
+/// An object that is changed when a transaction is committed.
+#[derive(Clone, Copy)]
+struct TransactionObject {
+    hashed_key: u64,
+    /// The offset of the object from the start of flash
+    address: usize,
+}
+
+/// The objects appended and invalidated in a transaction.
+#[derive(Clone, Copy)]
+struct Transaction {
+    appended: [TransactionObject; MAX_TRANSACTION_KEYS],
+    num_appended: usize,
+    invalidated: [TransactionObject; MAX_TRANSACTION_KEYS],
+    num_invalidated: usize,
+}
+
+impl Transaction {
+    const EMPTY_OBJECT: TransactionObject = TransactionObject {
+        hashed_key: 0,
+        address: 0,
+    };
+
+    fn new() -> Self {
+        Self {
+            appended: [Self::EMPTY_OBJECT; MAX_TRANSACTION_KEYS],
+            num_appended: 0,
+            invalidated: [Self::EMPTY_OBJECT; MAX_TRANSACTION_KEYS],
+            num_invalidated: 0,
+        }
+    }
+
+    fn appended(&self, hashed_key: u64) -> bool {
+        self.appended[..self.num_appended]
+            .iter()
+            .any(|o| o.hashed_key == hashed_key)
+    }
+
+    fn invalidated(&self, hashed_key: u64) -> bool {
+        self.invalidated[..self.num_invalidated]
+            .iter()
+            .any(|o| o.hashed_key == hashed_key)
+    }
+
+    /// Get the object at `index` and the bit of its length byte that has to
+    /// be cleared to commit it.
+    fn object(&self, index: usize) -> Option<(TransactionObject, u8)> {
+        if index < self.num_appended {
+            Some((self.appended[index], FLAGS_PENDING << 4))
+        } else if index - self.num_appended < self.num_invalidated {
+            Some((
+                self.invalidated[index - self.num_appended],
+                FLAGS_VALID << 4,
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Encode the commit marker value into `buf`, returning its length.
+    fn write_marker(&self, buf: &mut [u8; MARKER_LENGTH]) -> usize {
+        buf[0] = self.num_appended as u8;
+        buf[1] = self.num_invalidated as u8;
+
+        let objects = self.appended[..self.num_appended]
+            .iter()
+            .chain(self.invalidated[..self.num_invalidated].iter());
+        let mut len = 2;
+        for o in objects {
+            buf[len..(len + 4)].copy_from_slice(&(o.address as u32).to_le_bytes());
+            buf[(len + 4)..(len + MARKER_OBJECT_LENGTH)]
+                .copy_from_slice(&o.hashed_key.to_le_bytes());
+            len += MARKER_OBJECT_LENGTH;
+        }
+        len
+    }
+
+    /// Decode a commit marker value. Returns `None` if the value is invalid.
+    fn from_marker(buf: &[u8], flash_size: usize) -> Option<Self> {
+        let mut transaction = Self::new();
+        transaction.num_appended = *buf.first()? as usize;
+        transaction.num_invalidated = *buf.get(1)? as usize;
+        if transaction.num_appended > MAX_TRANSACTION_KEYS
+            || transaction.num_invalidated > MAX_TRANSACTION_KEYS
+        {
+            return None;
+        }
+
+        for i in 0..(transaction.num_appended + transaction.num_invalidated) {
+            let start = 2 + i * MARKER_OBJECT_LENGTH;
+            let address = u32::from_le_bytes(buf.get(start..(start + 4))?.try_into().ok()?);
+            let hashed_key = u64::from_le_bytes(
+                buf.get((start + 4)..(start + MARKER_OBJECT_LENGTH))?
+                    .try_into()
+                    .ok()?,
+            );
+            let object = TransactionObject {
+                hashed_key,
+                address: address as usize,
+            };
+            if object.address + HEADER_LENGTH > flash_size {
+                return None;
+            }
+
+            if i < transaction.num_appended {
+                transaction.appended[i] = object;
+            } else {
+                transaction.invalidated[i - transaction.num_appended] = object;
+            }
+        }
+
+        Some(transaction)
+    }
+}
+
 /// This is the main TicKV struct.
 impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     /// Create a new struct
@@ -115,6 +288,8 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
             flash_size,
             read_buffer: Cell::new(Some(read_buffer)),
             state: Cell::new(State::None),
+            transaction: Cell::new(None),
+            search_path_regions: Cell::new((0, 0)),
         }
     }
 
@@ -126,6 +301,9 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     /// If the specified region has not already been setup for TicKV
     /// the entire region will be erased.
     ///
+    /// If power was lost while a transaction was being committed, the commit
+    /// is completed.
+    ///
     /// On success nothing will be returned.
     /// On error a `ErrorCode` will be returned.
     pub fn initialise(&self, hashed_main_key: u64) -> Result<SuccessCode, ErrorCode> {
@@ -135,13 +313,19 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
             State::None => self.get_key(hashed_main_key, &mut buf),
             State::Init(state) => match state {
                 InitState::GetKeyReadRegion(_) => self.get_key(hashed_main_key, &mut buf),
+                InitState::Recover(step) => {
+                    return self
+                        .run_transaction(step, |step| State::Init(InitState::Recover(step)));
+                }
                 _ => Err(ErrorCode::EraseNotReady(0)),
             },
             _ => unreachable!(),
         };
 
         match key_ret {
-            Ok((ret, _len)) => Ok(ret),
+            Ok(_) => self.run_transaction(TransactionState::FindMarker(None), |step| {
+                State::Init(InitState::Recover(step))
+            }),
             Err(e) => {
                 match e {
                     ErrorCode::ReadNotReady(reg) => {
@@ -279,8 +463,9 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
         loop {
             if offset + HEADER_LENGTH >= S {
-                // We have reached the end of the region
-                return Err((false, ErrorCode::KeyNotFound));
+                // We have reached the end of the region. The region is full,
+                // so the key might have been stored in another region.
+                return Err((true, ErrorCode::KeyNotFound));
             }
 
             // Check to see if we have data
@@ -318,13 +503,12 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     return Err((false, ErrorCode::KeyNotFound));
                 }
 
-                // Check to see if the entry has been deleted
-                if *region_data
+                // Check to see if the entry has been deleted, or is part of
+                // a transaction that has not been committed
+                let flags = *region_data
                     .get(offset + LEN_OFFSET)
-                    .ok_or((false, ErrorCode::CorruptData))?
-                    & 0x80
-                    != 0x80
-                {
+                    .ok_or((false, ErrorCode::CorruptData))?;
+                if flags & 0x80 != 0x80 || flags & 0x40 == 0x40 {
                     // Increment our offset by the length and repeat the loop
                     offset += total_length as usize;
                     continue;
@@ -372,8 +556,11 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 // If we get here we have found out value (assuming no collisions)
                 return Ok((offset, total_length));
             } else {
-                // We hit the end.
-                return Err((!empty, ErrorCode::KeyNotFound));
+                // We hit the end. A region that was only partially erased
+                // isn't empty, as objects are appended to other regions
+                // until `garbage_collect()` erases it again.
+                let erased = empty && region_data.iter().all(|b| *b == 0xFF);
+                return Err((!erased, ErrorCode::KeyNotFound));
             }
         }
     }
@@ -384,9 +571,25 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     ///         or remove the `value`.
     /// `value`: A buffer containing the data to be stored to flash.
     ///
+    /// If a transaction has been started the key will not be found until the
+    /// transaction is committed. A key that has been invalidated in the
+    /// transaction can be appended again.
+    ///
     /// On success nothing will be returned.
     /// On error a `ErrorCode` will be returned.
     pub fn append_key(&self, hash: u64, value: &[u8]) -> Result<SuccessCode, ErrorCode> {
+        match self.append_object(hash, value, self.transaction.get().is_some()) {
+            Err(ErrorCode::WriteNotReady(_)) => Ok(SuccessCode::Queued),
+            ret => ret,
+        }
+    }
+
+    fn append_object(
+        &self,
+        hash: u64,
+        value: &[u8],
+        pending: bool,
+    ) -> Result<SuccessCode, ErrorCode> {
         let region = self.get_region(hash);
         let check_sum = crc32::Crc32::new();
 
@@ -398,6 +601,16 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
             return Err(ErrorCode::ObjectTooLarge);
         }
 
+        let mut transaction = self.transaction.get();
+        if let Some(t) = transaction.as_ref().filter(|_| pending) {
+            if t.appended(hash) {
+                return Err(ErrorCode::KeyAlreadyExists);
+            }
+            if t.num_appended == MAX_TRANSACTION_KEYS {
+                return Err(ErrorCode::TransactionFull);
+            }
+        }
+
         // Create the header:
         let header = ObjectHeader::new(hash, object_length as u16);
 
@@ -434,8 +647,11 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 }
             }
 
-            if self.find_key_offset(hash, region_data).is_ok() {
-                // Check to make sure we don't already have this key
+            if self.find_key_offset(hash, region_data).is_ok()
+                && !(pending && transaction.is_some_and(|t| t.invalidated(hash)))
+            {
+                // Check to make sure we don't already have this key, unless
+                // it will be invalidated by the transaction
                 self.read_buffer.replace(Some(region_data));
                 return Err(ErrorCode::KeyAlreadyExists);
             }
@@ -443,7 +659,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
             let mut offset: usize = 0;
 
             loop {
-                if offset + package_length >= S {
+                if offset + object_length >= S {
                     // We have reached the end of the region
                     // We will need to try the next region
 
@@ -496,73 +712,17 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 }
 
                 // If we get here we have found an empty spot
-                // Double check that there is no valid hash
-
-                // Check to see if the entire header is 0xFFFF_FFFF_FFFF_FFFF
-                // To avoid operating on 64-bit values check every 8 bytes at a time
-                if *region_data
-                    .get(offset + HASH_OFFSET)
-                    .ok_or(ErrorCode::CorruptData)?
-                    != 0xFF
-                {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-                if *region_data
-                    .get(offset + HASH_OFFSET + 1)
-                    .ok_or(ErrorCode::CorruptData)?
-                    != 0xFF
-                {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-                if *region_data
-                    .get(offset + HASH_OFFSET + 2)
-                    .ok_or(ErrorCode::CorruptData)?
-                    != 0xFF
-                {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-                if *region_data
-                    .get(offset + HASH_OFFSET + 3)
-                    .ok_or(ErrorCode::CorruptData)?
-                    != 0xFF
-                {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-                if *region_data
-                    .get(offset + HASH_OFFSET + 4)
-                    .ok_or(ErrorCode::CorruptData)?
-                    != 0xFF
-                {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-                if *region_data
-                    .get(offset + HASH_OFFSET + 5)
-                    .ok_or(ErrorCode::CorruptData)?
-                    != 0xFF
-                {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-                if *region_data
-                    .get(offset + HASH_OFFSET + 6)
-                    .ok_or(ErrorCode::CorruptData)?
-                    != 0xFF
-                {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
-                }
-                if *region_data
-                    .get(offset + HASH_OFFSET + 7)
+                // Double check that the rest of the region is erased. If it
+                // isn't, power was lost while the region was being erased, so
+                // treat it as full until `garbage_collect()` erases it again.
+                if region_data
+                    .get(offset..)
                     .ok_or(ErrorCode::CorruptData)?
-                    != 0xFF
+                    .iter()
+                    .any(|b| *b != 0xFF)
                 {
-                    self.read_buffer.replace(Some(region_data));
-                    return Err(ErrorCode::CorruptData);
+                    offset = S;
+                    continue;
                 }
 
                 // If we get here we have found an empty spot
@@ -611,6 +771,14 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                         .ok_or(ErrorCode::CorruptData)?,
                 );
 
+                // The check sum covers the object as it will be once the
+                // transaction is committed.
+                if pending {
+                    *region_data
+                        .get_mut(offset + LEN_OFFSET)
+                        .ok_or(ErrorCode::RegionFull)? |= FLAGS_PENDING << 4;
+                }
+
                 // Copy the value
                 let slice = region_data
                     .get_mut((offset + HEADER_LENGTH)..(offset + package_length))
@@ -627,6 +795,16 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     .ok_or(ErrorCode::ObjectTooLarge)?;
                 slice.copy_from_slice(&check_sum.to_ne_bytes());
 
+                // Remember where the object is so the transaction can commit it
+                if let Some(t) = transaction.as_mut().filter(|_| pending) {
+                    t.appended[t.num_appended] = TransactionObject {
+                        hashed_key: hash,
+                        address: S * new_region + offset,
+                    };
+                    t.num_appended += 1;
+                    self.transaction.set(transaction);
+                }
+
                 // Write the data back to the region
                 if let Err(e) = self.controller.write(
                     S * new_region + offset,
@@ -635,10 +813,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                         .ok_or(ErrorCode::ObjectTooLarge)?,
                 ) {
                     self.read_buffer.replace(Some(region_data));
-                    match e {
-                        ErrorCode::WriteNotReady(_) => return Ok(SuccessCode::Queued),
-                        _ => return Err(e),
-                    }
+                    return Err(e);
                 }
 
                 self.read_buffer.replace(Some(region_data));
@@ -697,6 +872,15 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
             match self.find_key_offset(hash, region_data) {
                 Ok((offset, total_length)) => {
+                    // Make sure the length is valid, it could be corrupt if
+                    // power was lost while the object was being written.
+                    if (total_length as usize) < HEADER_LENGTH + CHECK_SUM_LEN
+                        || offset + total_length as usize > S
+                    {
+                        self.read_buffer.replace(Some(region_data));
+                        return Err(ErrorCode::CorruptData);
+                    }
+
                     // Add the header data to the check hash
                     check_sum.update(
                         region_data
@@ -790,7 +974,18 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     ///
     /// If a power loss occurs before success is returned the data is
     /// assumed to be lost.
+    ///
+    /// If a transaction has been started the key is only invalidated once
+    /// the transaction is committed. Nothing is written to flash and
+    /// `SuccessCode::Complete` is returned.
     pub fn invalidate_key(&self, hash: u64) -> Result<SuccessCode, ErrorCode> {
+        match self.invalidate_object(hash, self.transaction.get().is_some()) {
+            Err(ErrorCode::WriteNotReady(_)) => Ok(SuccessCode::Queued),
+            ret => ret,
+        }
+    }
+
+    fn invalidate_object(&self, hash: u64, deferred: bool) -> Result<SuccessCode, ErrorCode> {
         let region = self.get_region(hash);
 
         let mut region_offset: isize = 0;
@@ -820,6 +1015,29 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
             match self.find_key_offset(hash, region_data) {
                 Ok((offset, _data_len)) => {
+                    if deferred {
+                        // Remember where the object is so the transaction can
+                        // invalidate it when it is committed
+                        self.read_buffer.replace(Some(region_data));
+                        self.state.set(State::None);
+
+                        let mut transaction =
+                            self.transaction.get().ok_or(ErrorCode::NoTransaction)?;
+                        if !transaction.invalidated(hash) {
+                            if transaction.num_invalidated == MAX_TRANSACTION_KEYS {
+                                return Err(ErrorCode::TransactionFull);
+                            }
+                            transaction.invalidated[transaction.num_invalidated] =
+                                TransactionObject {
+                                    hashed_key: hash,
+                                    address: S * new_region + offset,
+                                };
+                            transaction.num_invalidated += 1;
+                            self.transaction.set(Some(transaction));
+                        }
+                        return Ok(SuccessCode::Complete);
+                    }
+
                     // We found a key, let's delete it
                     *region_data
                         .get_mut(offset + LEN_OFFSET)
@@ -832,10 +1050,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                             .ok_or(ErrorCode::ObjectTooLarge)?,
                     ) {
                         self.read_buffer.replace(Some(region_data));
-                        match e {
-                            ErrorCode::WriteNotReady(_) => return Ok(SuccessCode::Queued),
-                            _ => return Err(e),
-                        }
+                        return Err(e);
                     }
 
                     self.read_buffer.replace(Some(region_data));
@@ -967,6 +1182,449 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         }
     }
 
+    /// Finds the next valid object at or after `position`.
+    ///
+    /// Objects are found region by region in the order they are stored in
+    /// flash. Start with a `position` of 0 and continue with the
+    /// `next_position` of the returned `KeyInfo` until `KeyNotFound` is
+    /// returned. Objects added or invalidated in the meantime may or may not
+    /// be found.
+    ///
+    /// `position`: Where to start looking, in bytes from the start of flash.
+    /// `buf`: A buffer to store the value to. If the value does not fit, only
+    ///        the start of it is copied. The check sum is not verified.
+    ///
+    /// On success a `SuccessCode` and the `KeyInfo` of the object will be
+    /// returned. On error a `ErrorCode` will be returned.
+    pub fn next_key(
+        &self,
+        position: usize,
+        buf: &mut [u8],
+    ) -> Result<(SuccessCode, KeyInfo), ErrorCode> {
+        let num_region = self.flash_size / S;
+        let mut region = position / S;
+
+        loop {
+            if let State::NextKey(KeyState::ReadRegion(reg)) = self.state.get() {
+                region = reg;
+            }
+
+            if region >= num_region {
+                self.state.set(State::None);
+                return Err(ErrorCode::KeyNotFound);
+            }
+
+            // Get the data from that region
+            let region_data = self.read_buffer.take().unwrap();
+            if self.state.get() != State::NextKey(KeyState::ReadRegion(region)) {
+                if let Err(e) = self.controller.read_region(region, region_data) {
+                    self.read_buffer.replace(Some(region_data));
+                    if let ErrorCode::ReadNotReady(reg) = e {
+                        self.state.set(State::NextKey(KeyState::ReadRegion(reg)));
+                    }
+                    return Err(e);
+                }
+            }
+
+            let offset = if region == position / S {
+                position % S
+            } else {
+                0
+            };
+
+            // The buffer is replaced before any error is returned.
+            let found = self.next_object_in_region(region_data, region, offset, buf);
+            self.read_buffer.replace(Some(region_data));
+            self.state.set(State::None);
+            if let Some(key_info) = found? {
+                return Ok((SuccessCode::Complete, key_info));
+            }
+
+            // Try the next region
+            region += 1;
+        }
+    }
+
+    /// Finds the next valid object at or after `offset` in `region_data`, the
+    /// contents of `region`, and copies the start of its value to `buf`.
+    ///
+    /// Returns `None` if there are no more valid objects in the region.
+    fn next_object_in_region(
+        &self,
+        region_data: &[u8],
+        region: usize,
+        mut offset: usize,
+        buf: &mut [u8],
+    ) -> Result<Option<KeyInfo>, ErrorCode> {
+        while offset + HEADER_LENGTH < S {
+            let version = *region_data
+                .get(offset + VERSION_OFFSET)
+                .ok_or(ErrorCode::CorruptData)?;
+            if version == 0xFF {
+                // We hit the end of the objects in this region.
+                break;
+            }
+            if version != VERSION {
+                return Err(ErrorCode::UnsupportedVersion);
+            }
+
+            let len_high = *region_data
+                .get(offset + LEN_OFFSET)
+                .ok_or(ErrorCode::CorruptData)?;
+            let len_low = *region_data
+                .get(offset + LEN_OFFSET + 1)
+                .ok_or(ErrorCode::CorruptData)?;
+            let total_length = ((len_high as usize) & 0x0F) << 8 | len_low as usize;
+
+            if total_length < HEADER_LENGTH + CHECK_SUM_LEN {
+                // We found something invalid here, skip the rest of the
+                // region.
+                break;
+            }
+
+            if len_high & 0x80 != 0x80 || len_high & 0x40 == 0x40 {
+                // The object has been invalidated or is part of a
+                // transaction that has not been committed
+                offset += total_length;
+                continue;
+            }
+
+            let mut hashed_key: u64 = 0;
+            for i in 0..8 {
+                hashed_key = hashed_key << 8
+                    | *region_data
+                        .get(offset + HASH_OFFSET + i)
+                        .ok_or(ErrorCode::CorruptData)? as u64;
+            }
+
+            let value_length = total_length - HEADER_LENGTH - CHECK_SUM_LEN;
+            let copy_length = value_length.min(buf.len());
+            let value = region_data
+                .get((offset + HEADER_LENGTH)..(offset + HEADER_LENGTH + copy_length))
+                .ok_or(ErrorCode::CorruptData)?;
+            buf.get_mut(..copy_length)
+                .ok_or(ErrorCode::CorruptData)?
+                .copy_from_slice(value);
+
+            return Ok(Some(KeyInfo {
+                hashed_key,
+                value_length,
+                next_position: S * region + offset + total_length,
+            }));
+        }
+        Ok(None)
+    }
+
+    /// Starts a transaction.
+    ///
+    /// Until the transaction is committed with `commit_transaction()`, keys
+    /// appended with `append_key()` can not be found and keys invalidated with
+    /// `invalidate_key()` can still be found. Once committed, all of the
+    /// changes take effect together, even if power is lost while committing.
+    /// At most `MAX_TRANSACTION_KEYS` keys can be appended and invalidated.
+    ///
+    /// `zeroise_key()` is not part of the transaction and takes effect
+    /// immediately.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn begin_transaction(&self) -> Result<SuccessCode, ErrorCode> {
+        if self.transaction.get().is_some() {
+            return Err(ErrorCode::TransactionActive);
+        }
+
+        self.transaction.set(Some(Transaction::new()));
+        Ok(SuccessCode::Complete)
+    }
+
+    /// Commits the transaction started by `begin_transaction()`.
+    ///
+    /// This writes a commit marker listing the objects changed by the
+    /// transaction, updates the objects and then invalidates the marker. If
+    /// power is lost after the marker is written, `initialise()` completes
+    /// the commit.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned. `ReadNotReady` and
+    /// `WriteNotReady` indicate this should be called again once the flash
+    /// operation has completed.
+    pub fn commit_transaction(&self) -> Result<SuccessCode, ErrorCode> {
+        let step = match self.state.get() {
+            State::None => {
+                if self.transaction.get().is_none() {
+                    return Err(ErrorCode::NoTransaction);
+                }
+                TransactionState::WriteMarker(None)
+            }
+            State::Transaction(step) => step,
+            _ => unreachable!(),
+        };
+
+        self.run_transaction(step, State::Transaction)
+    }
+
+    /// Aborts the transaction started by `begin_transaction()`.
+    ///
+    /// Nothing is written to flash. The keys appended in the transaction will
+    /// never be found and are removed by `garbage_collect()`.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    pub fn abort_transaction(&self) -> Result<SuccessCode, ErrorCode> {
+        match self.transaction.take() {
+            Some(_) => Ok(SuccessCode::Complete),
+            None => Err(ErrorCode::NoTransaction),
+        }
+    }
+
+    /// Run the steps of committing a transaction, starting at `step`.
+    ///
+    /// `wrap` converts the step to wait on to the state to store while a
+    /// flash operation is pending.
+    fn run_transaction(
+        &self,
+        mut step: TransactionState,
+        wrap: fn(TransactionState) -> State,
+    ) -> Result<SuccessCode, ErrorCode> {
+        loop {
+            step = match step {
+                TransactionState::FindMarker(region) => {
+                    self.state.set(match region {
+                        Some(reg) => State::GetKey(KeyState::ReadRegion(reg)),
+                        None => State::None,
+                    });
+
+                    let mut buf = [0; MARKER_LENGTH];
+                    match self.get_key(TRANSACTION_KEY, &mut buf) {
+                        Ok((_, len)) => {
+                            match buf.get(..len).and_then(|marker| {
+                                Transaction::from_marker(marker, self.flash_size)
+                            }) {
+                                Some(transaction) => {
+                                    // The transaction was committed, finish
+                                    // applying it.
+                                    self.transaction.set(Some(transaction));
+                                    TransactionState::Apply(0, false)
+                                }
+                                None => TransactionState::ClearMarker(None),
+                            }
+                        }
+                        Err(ErrorCode::ReadNotReady(reg)) => {
+                            self.state
+                                .set(wrap(TransactionState::FindMarker(Some(reg))));
+                            return Err(ErrorCode::ReadNotReady(reg));
+                        }
+                        Err(ErrorCode::KeyNotFound) => {
+                            self.state.set(State::None);
+                            return Ok(SuccessCode::Complete);
+                        }
+                        // The marker was only partially written, so the
+                        // transaction was never committed.
+                        Err(_) => TransactionState::ClearMarker(None),
+                    }
+                }
+                TransactionState::WriteMarker(region) => {
+                    let mut marker = [0; MARKER_LENGTH];
+                    let len = self
+                        .transaction
+                        .get()
+                        .ok_or(ErrorCode::NoTransaction)?
+                        .write_marker(&mut marker);
+
+                    self.state.set(match region {
+                        Some(reg) => State::AppendKey(KeyState::ReadRegion(reg)),
+                        None => State::None,
+                    });
+
+                    match self.append_object(TRANSACTION_KEY, &marker[..len], false) {
+                        Ok(_) => TransactionState::Apply(0, false),
+                        Err(ErrorCode::ReadNotReady(reg)) => {
+                            self.state
+                                .set(wrap(TransactionState::WriteMarker(Some(reg))));
+                            return Err(ErrorCode::ReadNotReady(reg));
+                        }
+                        Err(ErrorCode::WriteNotReady(address)) => {
+                            self.state.set(wrap(TransactionState::Apply(0, false)));
+                            return Err(ErrorCode::WriteNotReady(address));
+                        }
+                        Err(e) => {
+                            // Nothing has been committed, so the transaction
+                            // can be committed again or aborted.
+                            self.state.set(State::None);
+                            return Err(e);
+                        }
+                    }
+                }
+                TransactionState::Apply(index, read) => {
+                    let object = self
+                        .transaction
+                        .get()
+                        .and_then(|transaction| transaction.object(index));
+                    let (object, mask) = match object {
+                        Some(object) => object,
+                        None => {
+                            step = TransactionState::ClearMarker(None);
+                            continue;
+                        }
+                    };
+                    let region = object.address / S;
+                    let offset = object.address % S;
+
+                    let region_data = self.read_buffer.take().unwrap();
+                    if !read {
+                        if let Err(e) = self.controller.read_region(region, region_data) {
+                            self.read_buffer.replace(Some(region_data));
+                            if let ErrorCode::ReadNotReady(_) = e {
+                                self.state.set(wrap(TransactionState::Apply(index, true)));
+                            } else {
+                                self.transaction.set(None);
+                                self.state.set(State::None);
+                            }
+                            return Err(e);
+                        }
+                    }
+
+                    // Only update the object if it is still there and needs
+                    // it, so that this can be repeated after a power loss.
+                    let hashed_key = region_data
+                        .get((offset + HASH_OFFSET)..(offset + HEADER_LENGTH))
+                        .and_then(|hash| hash.try_into().ok())
+                        .map(u64::from_be_bytes);
+                    let version = region_data.get(offset + VERSION_OFFSET).copied();
+                    let flags = region_data.get(offset + LEN_OFFSET).copied();
+                    if let (Some(VERSION), Some(flags)) = (version, flags) {
+                        if hashed_key == Some(object.hashed_key) && flags & mask != 0 {
+                            let flags = [flags & !mask];
+                            if let Err(e) =
+                                self.controller.write(object.address + LEN_OFFSET, &flags)
+                            {
+                                self.read_buffer.replace(Some(region_data));
+                                if let ErrorCode::WriteNotReady(_) = e {
+                                    self.state
+                                        .set(wrap(TransactionState::Apply(index + 1, false)));
+                                } else {
+                                    self.transaction.set(None);
+                                    self.state.set(State::None);
+                                }
+                                return Err(e);
+                            }
+                        }
+                    }
+
+                    self.read_buffer.replace(Some(region_data));
+                    TransactionState::Apply(index + 1, false)
+                }
+                TransactionState::ClearMarker(region) => {
+                    self.state.set(match region {
+                        Some(reg) => State::InvalidateKey(KeyState::ReadRegion(reg)),
+                        None => State::None,
+                    });
+
+                    let ret = match self.invalidate_object(TRANSACTION_KEY, false) {
+                        Err(ErrorCode::ReadNotReady(reg)) => {
+                            self.state
+                                .set(wrap(TransactionState::ClearMarker(Some(reg))));
+                            return Err(ErrorCode::ReadNotReady(reg));
+                        }
+                        Err(ErrorCode::WriteNotReady(_)) => Ok(SuccessCode::Queued),
+                        Ok(_) | Err(ErrorCode::KeyNotFound) => Ok(SuccessCode::Complete),
+                        Err(e) => Err(e),
+                    };
+
+                    // Once the marker has been written the transaction is
+                    // committed, even if updating the objects failed. They
+                    // are updated again by `initialise()`.
+                    self.transaction.set(None);
+                    self.state.set(State::None);
+                    return ret;
+                }
+            };
+        }
+    }
+
+    /// Finds the valid objects in `region` that are not stored in their home
+    /// region, and marks the regions that are searched before them in
+    /// `search_path_regions`.
+    fn scan_region(&self, region: usize, flash_freed: usize) -> Result<(), ErrorCode> {
+        // Get the data from that region
+        let region_data = self.read_buffer.take().unwrap();
+        if self.state.get() != State::GarbageCollect(RubbishState::ScanRegion(region, flash_freed))
+        {
+            if let Err(e) = self.controller.read_region(region, region_data) {
+                self.read_buffer.replace(Some(region_data));
+                if let ErrorCode::ReadNotReady(reg) = e {
+                    self.state
+                        .set(State::GarbageCollect(RubbishState::ScanRegion(
+                            reg,
+                            flash_freed,
+                        )));
+                }
+                return Err(e);
+            }
+        }
+
+        let mut offset: usize = 0;
+
+        while offset + HEADER_LENGTH < S {
+            if region_data[offset + VERSION_OFFSET] != VERSION {
+                // We hit the end of the objects in this region, or data that
+                // `garbage_collect_region()` reports.
+                break;
+            }
+
+            let flags = region_data[offset + LEN_OFFSET];
+            let total_length =
+                ((flags as usize) & 0x0F) << 8 | region_data[offset + LEN_OFFSET + 1] as usize;
+            if total_length < HEADER_LENGTH + CHECK_SUM_LEN {
+                // We found something invalid here, skip the rest of the
+                // region.
+                break;
+            }
+
+            // Objects of an open transaction will become valid, so they have
+            // to be found as well.
+            if flags & 0x80 == 0x80 && (flags & 0x40 != 0x40 || self.transaction.get().is_some()) {
+                let mut hashed_key: u64 = 0;
+                for i in 0..8 {
+                    hashed_key = hashed_key << 8 | region_data[offset + HASH_OFFSET + i] as u64;
+                }
+                if hashed_key != 0 && hashed_key != 0xFFFF_FFFF_FFFF_FFFF {
+                    self.mark_search_path(hashed_key, region);
+                }
+            }
+
+            offset += total_length;
+        }
+
+        self.read_buffer.replace(Some(region_data));
+        Ok(())
+    }
+
+    /// Marks the regions of the current window that are searched before
+    /// `region` when looking for `hashed_key`.
+    fn mark_search_path(&self, hashed_key: u64, region: usize) {
+        let (first, mut regions) = self.search_path_regions.get();
+        let home = self.get_region(hashed_key);
+        let mut region_offset: isize = 0;
+        let mut searched = home;
+
+        while searched != region {
+            if (first..first + SEARCH_PATH_WINDOW).contains(&searched) {
+                regions |= 1 << (searched - first);
+            }
+
+            match self.increment_region_offset(home, region_offset) {
+                Some(o) => {
+                    region_offset = o;
+                    searched = (home as isize + o) as usize;
+                }
+                None => break,
+            }
+        }
+
+        self.search_path_regions.set((first, regions));
+    }
+
     fn garbage_collect_region(
         &self,
         region: usize,
@@ -1027,12 +1685,13 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                         .get(offset + LEN_OFFSET + 1)
                         .ok_or(ErrorCode::CorruptData)? as u16;
 
-                // Check to see if the entry has been deleted
-                if *region_data
+                // Check to see if the entry has been deleted. Objects of a
+                // transaction that was never committed are deleted as well.
+                let flags = *region_data
                     .get(offset + LEN_OFFSET)
-                    .ok_or(ErrorCode::CorruptData)?
-                    & 0x80
-                    != 0x80
+                    .ok_or(ErrorCode::CorruptData)?;
+                if flags & 0x80 != 0x80
+                    || (flags & 0x40 == 0x40 && self.transaction.get().is_none())
                 {
                     // The entry has been deleted, this region might be ready
                     // for erasure.
@@ -1051,7 +1710,9 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 //    * The region is empty, we don't need to do anything
                 //    * The region has entries, all of which are marked for
                 //      deletion
-                if !entry_found {
+                //    * The region was only partially erased, so has to be
+                //      erased again
+                if !entry_found && region_data.iter().all(|b| *b == 0xFF) {
                     // We didn't find anything, don't bother erasing an empty region.
                     self.read_buffer.replace(Some(region_data));
                     return Ok(0);
@@ -1062,6 +1723,14 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
         self.read_buffer.replace(Some(region_data));
 
+        // A search for an object stored outside of its home region stops at
+        // an empty region, so don't erase a region that is searched before
+        // the object is found.
+        let (first, regions) = self.search_path_regions.get();
+        if regions & 1 << (region - first) != 0 {
+            return Ok(0);
+        }
+
         // If we got down here, the region is ready to be erased.
 
         if let Err(e) = self.controller.erase_region(region) {
@@ -1080,34 +1749,57 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
     /// Perform a garbage collection on TicKV
     ///
+    /// The regions are collected `SEARCH_PATH_WINDOW` at a time. Before each
+    /// window all regions are read to find the regions of the window that
+    /// have to be kept to find objects stored outside of their home region,
+    /// then the regions of the window that only contain invalid objects are
+    /// erased.
+    ///
     /// On success the number of bytes freed will be returned.
     /// On error a `ErrorCode` will be returned.
     pub fn garbage_collect(&self) -> Result<usize, ErrorCode> {
         let num_region = self.flash_size / S;
-        let mut flash_freed = 0;
-        let start = match self.state.get() {
-            State::None => 0,
+        let (mut scan_start, mut start, mut flash_freed) = match self.state.get() {
+            State::None => {
+                self.search_path_regions.set((0, 0));
+                (Some(0), 0, 0)
+            }
             State::GarbageCollect(state) => match state {
-                RubbishState::ReadRegion(reg, ff) => {
-                    flash_freed += ff;
-                    reg
+                RubbishState::ScanRegion(reg, ff) => {
+                    (Some(reg), self.search_path_regions.get().0, ff)
                 }
+                RubbishState::ReadRegion(reg, ff) => (None, reg, ff),
                 // We already erased region reg, so move to the next one
-                RubbishState::EraseRegion(reg, ff) => {
-                    flash_freed += ff;
-                    reg + 1
-                }
+                RubbishState::EraseRegion(reg, ff) => (None, reg + 1, ff),
             },
             _ => unreachable!(),
         };
 
-        for i in start..num_region {
-            match self.garbage_collect_region(i, flash_freed) {
-                Ok(freed) => flash_freed += freed,
-                Err(e) => return Err(e),
+        loop {
+            if let Some(scan_start) = scan_start {
+                for i in scan_start..num_region {
+                    self.scan_region(i, flash_freed)?;
+                }
+                self.state.set(State::None);
             }
-        }
 
-        Ok(flash_freed)
+            let (first, _) = self.search_path_regions.get();
+            let end = cmp::min(first + SEARCH_PATH_WINDOW, num_region);
+            for i in start..end {
+                match self.garbage_collect_region(i, flash_freed) {
+                    Ok(freed) => flash_freed += freed,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if end == num_region {
+                return Ok(flash_freed);
+            }
+
+            // Move on to the next window
+            self.search_path_regions.set((end, 0));
+            scan_start = Some(0);
+            start = end;
+        }
     }
 }
@@ -0,0 +1,120 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Process fault dump encoding and decoding library.
+//!
+//! When a process faults, the kernel can record its state in a compact binary
+//! record. This library defines that format, writes records on the board and
+//! parses them again, either on the board or in host-side tools.
+
+// Neither writing nor parsing fault dumps requires any unsafe operations.
+#![forbid(unsafe_code)]
+#![no_std]
+
+pub mod parse;
+pub mod types;
+pub mod write;
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::{parse_fault_dump, parse_fault_dump_header};
+    use crate::types::*;
+    use crate::write::{set_sequence, FaultDumpWriter};
+
+    const INFO: ProcessInfo = ProcessInfo {
+        short_id: 0x1234,
+        restart_count: 2,
+        fault_action: FaultAction::Restart,
+        syscall_count: 100,
+        dropped_upcall_count: 1,
+        timeslice_expiration_count: 3,
+        name: "blink",
+    };
+
+    fn write_record(buf: &mut [u8]) -> usize {
+        let mut writer = FaultDumpWriter::new(buf, 7).unwrap();
+        writer.process(&INFO);
+        writer.memory(&MemoryLayout {
+            flash_start: 0x40000,
+            flash_end: 0x42000,
+            sram_start: 0x20004000,
+            sram_app_brk: 0x20005000,
+            sram_grant_start: 0x20005800,
+            sram_end: 0x20006000,
+            ..Default::default()
+        });
+        writer.mpu_regions(&[MpuRegion {
+            start: 0x40000,
+            size: 0x2000,
+            permissions: PERMISSION_READ | PERMISSION_EXECUTE,
+        }]);
+        writer.stored_state(|out| {
+            out.get_mut(..6)?.copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+            Some(6)
+        });
+        writer.stack(0x20004f00, |out| {
+            let len = out.len().min(32);
+            out[..len].fill(0xaa);
+            Some(len)
+        });
+        writer.finish()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut buf = [0xff; 256];
+        let len = write_record(&mut buf);
+        assert_eq!(parse_fault_dump_header(&buf[..HEADER_LEN]), Ok((7, len)));
+
+        let dump = parse_fault_dump(&buf).unwrap();
+        assert_eq!(dump.sequence(), 7);
+        assert_eq!(dump.process(), Some(INFO));
+        assert_eq!(dump.memory().unwrap().sram_app_brk, 0x20005000);
+        assert_eq!(dump.grants(), None);
+        let mut regions = dump.mpu_regions().unwrap();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions.next().unwrap().size, 0x2000);
+        assert_eq!(dump.stored_state(), Some(&[1, 2, 3, 4, 5, 6][..]));
+        let (start, contents) = dump.stack().unwrap();
+        assert_eq!(start, 0x20004f00);
+        assert_eq!(contents, &[0xaa; 32][..]);
+        assert!(dump.sections().all(|s| s.is_ok()));
+    }
+
+    #[test]
+    fn small_buffer_drops_sections() {
+        let mut buf = [0; 64];
+        let len = write_record(&mut buf);
+        assert!(len <= 64);
+
+        let dump = parse_fault_dump(&buf).unwrap();
+        assert_eq!(dump.process(), Some(INFO));
+        assert_eq!(dump.memory(), None);
+    }
+
+    #[test]
+    fn corrupted_record() {
+        let mut buf = [0; 256];
+        write_record(&mut buf);
+        buf[40] ^= 1;
+        assert!(matches!(
+            parse_fault_dump(&buf),
+            Err(FaultDumpParseError::ChecksumMismatch(_, _))
+        ));
+
+        let erased = [0xff; 64];
+        assert_eq!(
+            parse_fault_dump(&erased).err(),
+            Some(FaultDumpParseError::NoRecord)
+        );
+    }
+
+    #[test]
+    fn change_sequence() {
+        let mut buf = [0; 256];
+        write_record(&mut buf);
+        set_sequence(&mut buf, 42);
+        assert_eq!(parse_fault_dump(&buf).unwrap().sequence(), 42);
+    }
+}
@@ -0,0 +1,394 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Fault dump parsing code.
+
+use core::fmt;
+use core::str;
+
+use crate::types::{
+    self, read_u16, read_u32, FaultDumpParseError, GrantUsage, MemoryLayout, MpuRegion,
+    ProcessInfo, SectionType, HEADER_LEN, SECTION_HEADER_LEN,
+};
+
+/// Parse the header at the start of a record.
+///
+/// This only checks the magic value and the version, so it can be used to
+/// find stored records without reading them entirely.
+///
+/// ## Return
+///
+/// If the header is valid:
+/// - Ok((sequence number, length of the record))
+pub fn parse_fault_dump_header(header: &[u8]) -> Result<(u32, usize), FaultDumpParseError> {
+    let magic = read_u32(header, 0).ok_or(FaultDumpParseError::NotEnoughData)?;
+    if magic != types::MAGIC {
+        return Err(FaultDumpParseError::NoRecord);
+    }
+    let version = read_u16(header, 4).ok_or(FaultDumpParseError::NotEnoughData)?;
+    if version != types::VERSION {
+        return Err(FaultDumpParseError::UnsupportedVersion(version));
+    }
+    let length = read_u16(header, 6).ok_or(FaultDumpParseError::NotEnoughData)? as usize;
+    let sequence = read_u32(header, 8).ok_or(FaultDumpParseError::NotEnoughData)?;
+    if length < HEADER_LEN {
+        return Err(FaultDumpParseError::NotEnoughData);
+    }
+    Ok((sequence, length))
+}
+
+/// Parse and verify a fault dump record.
+///
+/// `record` must start with the record header, any bytes after the end of the
+/// record are ignored.
+pub fn parse_fault_dump(record: &[u8]) -> Result<FaultDump<'_>, FaultDumpParseError> {
+    let (sequence, length) = parse_fault_dump_header(record)?;
+    let record = record
+        .get(..length)
+        .ok_or(FaultDumpParseError::NotEnoughData)?;
+    let stored = read_u32(record, 12).ok_or(FaultDumpParseError::NotEnoughData)?;
+    let calculated = types::checksum(record);
+    if stored != calculated {
+        return Err(FaultDumpParseError::ChecksumMismatch(stored, calculated));
+    }
+    Ok(FaultDump {
+        sequence,
+        sections: &record[HEADER_LEN..],
+    })
+}
+
+/// A parsed fault dump record.
+#[derive(Copy, Clone, Debug)]
+pub struct FaultDump<'a> {
+    sequence: u32,
+    sections: &'a [u8],
+}
+
+impl<'a> FaultDump<'a> {
+    /// The sequence number of the record. Later faults have larger sequence
+    /// numbers.
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Iterate over the sections of the record.
+    pub fn sections(&self) -> Sections<'a> {
+        Sections {
+            data: self.sections,
+        }
+    }
+
+    fn find<T>(&self, f: impl Fn(Section<'a>) -> Option<T>) -> Option<T> {
+        self.sections().filter_map(|s| s.ok()).find_map(f)
+    }
+
+    pub fn process(&self) -> Option<ProcessInfo<'a>> {
+        self.find(|s| match s {
+            Section::Process(info) => Some(info),
+            _ => None,
+        })
+    }
+
+    pub fn memory(&self) -> Option<MemoryLayout> {
+        self.find(|s| match s {
+            Section::Memory(layout) => Some(layout),
+            _ => None,
+        })
+    }
+
+    pub fn grants(&self) -> Option<GrantUsage> {
+        self.find(|s| match s {
+            Section::Grants(usage) => Some(usage),
+            _ => None,
+        })
+    }
+
+    pub fn mpu_regions(&self) -> Option<MpuRegions<'a>> {
+        self.find(|s| match s {
+            Section::MpuRegions(regions) => Some(regions),
+            _ => None,
+        })
+    }
+
+    pub fn stored_state(&self) -> Option<&'a [u8]> {
+        self.find(|s| match s {
+            Section::StoredState(state) => Some(state),
+            _ => None,
+        })
+    }
+
+    /// The address of the first byte of the stack copy and the copy itself.
+    pub fn stack(&self) -> Option<(u32, &'a [u8])> {
+        self.find(|s| match s {
+            Section::Stack { start, contents } => Some((start, contents)),
+            _ => None,
+        })
+    }
+}
+
+/// A section of a fault dump.
+#[derive(Clone, Debug)]
+pub enum Section<'a> {
+    Process(ProcessInfo<'a>),
+    Memory(MemoryLayout),
+    Grants(GrantUsage),
+    MpuRegions(MpuRegions<'a>),
+    StoredState(&'a [u8]),
+    Stack {
+        start: u32,
+        contents: &'a [u8],
+    },
+    /// A section type this version of the library does not know.
+    Unknown {
+        section_type: u16,
+        value: &'a [u8],
+    },
+}
+
+/// Iterator over the sections of a fault dump.
+pub struct Sections<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Sections<'a> {
+    type Item = Result<Section<'a>, FaultDumpParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < SECTION_HEADER_LEN {
+            return None;
+        }
+        let section_type = read_u16(self.data, 0)?;
+        let length = read_u16(self.data, 2)? as usize;
+        let Some(value) = self
+            .data
+            .get(SECTION_HEADER_LEN..SECTION_HEADER_LEN + length)
+        else {
+            // Stop iterating, the rest of the record cannot be trusted.
+            self.data = &[];
+            return Some(Err(FaultDumpParseError::BadSection(section_type)));
+        };
+        let padded = (length + 3) & !3;
+        self.data = self.data.get(SECTION_HEADER_LEN + padded..).unwrap_or(&[]);
+        Some(parse_section(section_type, value))
+    }
+}
+
+fn parse_section(section_type: u16, value: &[u8]) -> Result<Section<'_>, FaultDumpParseError> {
+    let bad = FaultDumpParseError::BadSection(section_type);
+    let word = |index: usize| read_u32(value, index * 4).ok_or(bad);
+
+    let section = match section_type {
+        t if t == SectionType::Process as u16 => {
+            let name = value
+                .get(types::PROCESS_FIXED_LEN..)
+                .ok_or(bad)
+                .and_then(|name| {
+                    str::from_utf8(name).map_err(|_| FaultDumpParseError::BadProcessName)
+                })?;
+            Section::Process(ProcessInfo {
+                short_id: word(0)?,
+                restart_count: word(1)?,
+                fault_action: word(2)?.into(),
+                syscall_count: word(3)?,
+                dropped_upcall_count: word(4)?,
+                timeslice_expiration_count: word(5)?,
+                name,
+            })
+        }
+        t if t == SectionType::Memory as u16 => {
+            if value.len() < types::MEMORY_LEN {
+                return Err(bad);
+            }
+            Section::Memory(MemoryLayout {
+                flash_start: word(0)?,
+                flash_end: word(1)?,
+                sram_start: word(2)?,
+                sram_app_brk: word(3)?,
+                sram_grant_start: word(4)?,
+                sram_end: word(5)?,
+                sram_heap_start: word(6)?,
+                sram_stack_top: word(7)?,
+                sram_stack_bottom: word(8)?,
+            })
+        }
+        t if t == SectionType::Grants as u16 => {
+            if value.len() < types::GRANTS_LEN {
+                return Err(bad);
+            }
+            Section::Grants(GrantUsage {
+                allocated_grants: word(0)?,
+                allocated_grants_bitmap: word(1)?,
+                grant_pointers_bytes: word(2)?,
+                upcall_list_bytes: word(3)?,
+                process_control_block_bytes: word(4)?,
+            })
+        }
+        t if t == SectionType::MpuRegions as u16 => {
+            if value.len() % types::MPU_REGION_LEN != 0 {
+                return Err(bad);
+            }
+            Section::MpuRegions(MpuRegions { data: value })
+        }
+        t if t == SectionType::StoredState as u16 => Section::StoredState(value),
+        t if t == SectionType::Stack as u16 => Section::Stack {
+            start: word(0)?,
+            contents: &value[4..],
+        },
+        _ => Section::Unknown {
+            section_type,
+            value,
+        },
+    };
+    Ok(section)
+}
+
+/// The memory regions in an MPU regions section.
+#[derive(Clone, Debug)]
+pub struct MpuRegions<'a> {
+    data: &'a [u8],
+}
+
+impl MpuRegions<'_> {
+    pub fn len(&self) -> usize {
+        self.data.len() / types::MPU_REGION_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl Iterator for MpuRegions<'_> {
+    type Item = MpuRegion;
+
+    fn next(&mut self) -> Option<MpuRegion> {
+        let region = MpuRegion {
+            start: read_u32(self.data, 0)?,
+            size: read_u32(self.data, 4)?,
+            permissions: read_u32(self.data, 8)?,
+        };
+        self.data = &self.data[types::MPU_REGION_LEN..];
+        Some(region)
+    }
+}
+
+/// Human readable description of the entire record, for host-side tools.
+impl fmt::Display for FaultDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Fault dump #{}", self.sequence)?;
+        for section in self.sections() {
+            match section {
+                Ok(Section::Process(info)) => {
+                    writeln!(f, "Process:             {}", info.name)?;
+                    match info.short_id {
+                        0 => writeln!(f, "  ShortID:           Unique")?,
+                        id => writeln!(f, "  ShortID:           {:#010x}", id)?,
+                    }
+                    writeln!(f, "  Fault action:      {:?}", info.fault_action)?;
+                    writeln!(f, "  Restarts:          {}", info.restart_count)?;
+                    writeln!(f, "  Syscalls:          {}", info.syscall_count)?;
+                    writeln!(f, "  Dropped upcalls:   {}", info.dropped_upcall_count)?;
+                    writeln!(
+                        f,
+                        "  Timeslice expired: {}",
+                        info.timeslice_expiration_count
+                    )?;
+                }
+                Ok(Section::Memory(m)) => {
+                    writeln!(f, "Memory layout:")?;
+                    writeln!(
+                        f,
+                        "  Flash:             {:#010x}-{:#010x}",
+                        m.flash_start, m.flash_end
+                    )?;
+                    writeln!(
+                        f,
+                        "  RAM:               {:#010x}-{:#010x}",
+                        m.sram_start, m.sram_end
+                    )?;
+                    writeln!(f, "  App break:         {:#010x}", m.sram_app_brk)?;
+                    writeln!(f, "  Grants start:      {:#010x}", m.sram_grant_start)?;
+                    writeln!(f, "  Heap start:        {:#010x}", m.sram_heap_start)?;
+                    writeln!(f, "  Stack top:         {:#010x}", m.sram_stack_top)?;
+                    writeln!(f, "  Stack bottom:      {:#010x}", m.sram_stack_bottom)?;
+                }
+                Ok(Section::Grants(g)) => {
+                    writeln!(f, "Grants:")?;
+                    writeln!(f, "  Allocated:         {}", g.allocated_grants)?;
+                    writeln!(
+                        f,
+                        "  Allocated bitmap:  {:#034b}",
+                        g.allocated_grants_bitmap
+                    )?;
+                    writeln!(f, "  Grant pointers:    {} bytes", g.grant_pointers_bytes)?;
+                    writeln!(f, "  Upcall queue:      {} bytes", g.upcall_list_bytes)?;
+                    writeln!(
+                        f,
+                        "  Process struct:    {} bytes",
+                        g.process_control_block_bytes
+                    )?;
+                }
+                Ok(Section::MpuRegions(regions)) => {
+                    writeln!(f, "MPU regions:")?;
+                    for region in regions {
+                        writeln!(
+                            f,
+                            "  {:#010x}-{:#010x} {}{}{}",
+                            region.start,
+                            region.start.wrapping_add(region.size),
+                            if region.permissions & types::PERMISSION_READ != 0 {
+                                'R'
+                            } else {
+                                '-'
+                            },
+                            if region.permissions & types::PERMISSION_WRITE != 0 {
+                                'W'
+                            } else {
+                                '-'
+                            },
+                            if region.permissions & types::PERMISSION_EXECUTE != 0 {
+                                'X'
+                            } else {
+                                '-'
+                            },
+                        )?;
+                    }
+                }
+                Ok(Section::StoredState(state)) => {
+                    writeln!(f, "Stored state:")?;
+                    for (i, word) in state.chunks(4).enumerate() {
+                        let mut bytes = [0; 4];
+                        bytes[..word.len()].copy_from_slice(word);
+                        writeln!(f, "  [{:2}] {:#010x}", i, u32::from_le_bytes(bytes))?;
+                    }
+                }
+                Ok(Section::Stack { start, contents }) => {
+                    writeln!(f, "Stack ({} bytes):", contents.len())?;
+                    for (i, line) in contents.chunks(16).enumerate() {
+                        write!(f, "  {:#010x}:", start.wrapping_add(i as u32 * 16))?;
+                        for byte in line {
+                            write!(f, " {:02x}", byte)?;
+                        }
+                        writeln!(f)?;
+                    }
+                }
+                Ok(Section::Unknown {
+                    section_type,
+                    value,
+                }) => {
+                    writeln!(
+                        f,
+                        "Unknown section {} ({} bytes)",
+                        section_type,
+                        value.len()
+                    )?;
+                }
+                Err(e) => {
+                    writeln!(f, "Invalid section: {:?}", e)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
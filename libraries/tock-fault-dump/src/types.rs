@@ -0,0 +1,187 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Types and constants of the fault dump format.
+
+/// Magic value at the start of every fault dump record, `b"TFDP"`.
+pub const MAGIC: u32 = u32::from_le_bytes(*b"TFDP");
+
+/// The version of the fault dump format written by this library.
+pub const VERSION: u16 = 1;
+
+/// Length of the record header in bytes.
+pub const HEADER_LEN: usize = 16;
+
+/// Length of the type and length fields of a section.
+pub const SECTION_HEADER_LEN: usize = 4;
+
+/// Value of a memory layout field whose address is not known.
+pub const ADDRESS_UNKNOWN: u32 = 0;
+
+/// Read permission of a memory region.
+pub const PERMISSION_READ: u32 = 1 << 0;
+/// Write permission of a memory region.
+pub const PERMISSION_WRITE: u32 = 1 << 1;
+/// Execute permission of a memory region.
+pub const PERMISSION_EXECUTE: u32 = 1 << 2;
+
+/// Error when parsing a fault dump.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaultDumpParseError {
+    /// The buffer is shorter than the header or the length of the record.
+    NotEnoughData,
+
+    /// The record does not start with [`MAGIC`]. This is the case for erased
+    /// or never written storage.
+    NoRecord,
+
+    /// Unknown version of the fault dump format.
+    UnsupportedVersion(u16),
+
+    /// The checksum stored in the header does not match the record. First
+    /// value is the stored checksum, second value is the one we calculated.
+    ChecksumMismatch(u32, u32),
+
+    /// One of the sections is too short for its type, or extends past the end
+    /// of the record. The value is the section type.
+    BadSection(u16),
+
+    /// The process name is not valid UTF-8.
+    BadProcessName,
+}
+
+/// The types of the sections in a fault dump.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SectionType {
+    Process = 1,
+    Memory = 2,
+    Grants = 3,
+    MpuRegions = 4,
+    StoredState = 5,
+    Stack = 6,
+}
+
+/// The action the kernel took in response to the fault.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaultAction {
+    Panic,
+    Restart,
+    Stop,
+    /// An action this version of the library does not know.
+    Unknown(u32),
+}
+
+impl From<FaultAction> for u32 {
+    fn from(action: FaultAction) -> u32 {
+        match action {
+            FaultAction::Panic => 0,
+            FaultAction::Restart => 1,
+            FaultAction::Stop => 2,
+            FaultAction::Unknown(value) => value,
+        }
+    }
+}
+
+impl From<u32> for FaultAction {
+    fn from(value: u32) -> FaultAction {
+        match value {
+            0 => FaultAction::Panic,
+            1 => FaultAction::Restart,
+            2 => FaultAction::Stop,
+            _ => FaultAction::Unknown(value),
+        }
+    }
+}
+
+/// The faulted process and what the kernel did about it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ProcessInfo<'a> {
+    /// The fixed short ID of the process, or 0 if it is locally unique.
+    pub short_id: u32,
+    pub restart_count: u32,
+    pub fault_action: FaultAction,
+    pub syscall_count: u32,
+    pub dropped_upcall_count: u32,
+    pub timeslice_expiration_count: u32,
+    pub name: &'a str,
+}
+
+/// Length of the fixed size fields of the process section.
+pub(crate) const PROCESS_FIXED_LEN: usize = 24;
+
+/// Addresses of the memory regions of the process. Addresses that are not
+/// known are [`ADDRESS_UNKNOWN`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryLayout {
+    pub flash_start: u32,
+    pub flash_end: u32,
+    pub sram_start: u32,
+    pub sram_app_brk: u32,
+    pub sram_grant_start: u32,
+    pub sram_end: u32,
+    pub sram_heap_start: u32,
+    pub sram_stack_top: u32,
+    pub sram_stack_bottom: u32,
+}
+
+/// Length of the memory layout section.
+pub(crate) const MEMORY_LEN: usize = 36;
+
+/// Grant and kernel memory used by the process.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GrantUsage {
+    /// The number of grants the process had allocated.
+    pub allocated_grants: u32,
+    /// Bit `n` is set if grant number `n` was allocated, for the first 32
+    /// grants.
+    pub allocated_grants_bitmap: u32,
+    pub grant_pointers_bytes: u32,
+    pub upcall_list_bytes: u32,
+    pub process_control_block_bytes: u32,
+}
+
+/// Length of the grants section.
+pub(crate) const GRANTS_LEN: usize = 20;
+
+/// A memory region the process was allowed to access.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MpuRegion {
+    pub start: u32,
+    pub size: u32,
+    /// Combination of [`PERMISSION_READ`], [`PERMISSION_WRITE`] and
+    /// [`PERMISSION_EXECUTE`].
+    pub permissions: u32,
+}
+
+/// Length of a single MPU region.
+pub(crate) const MPU_REGION_LEN: usize = 12;
+
+/// Compute the checksum of a record: the XOR of all its 32 bit little endian
+/// words, with the checksum field treated as zero. A trailing partial word is
+/// padded with zeros.
+pub fn checksum(record: &[u8]) -> u32 {
+    record
+        .chunks(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 3)
+        .fold(0, |acc, (_, chunk)| {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            acc ^ u32::from_le_bytes(word)
+        })
+}
+
+/// Read the little endian `u32` at `offset`.
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .and_then(|b| b.try_into().ok())
+        .map(u32::from_le_bytes)
+}
+
+/// Read the little endian `u16` at `offset`.
+pub(crate) fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2)
+        .and_then(|b| b.try_into().ok())
+        .map(u16::from_le_bytes)
+}
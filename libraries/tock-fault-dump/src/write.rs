@@ -0,0 +1,210 @@
+// Licensed under the Apache License, Version 2.0 or the MIT License.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+// Copyright Tock Contributors 2022.
+
+//! Fault dump writing code.
+//!
+//! A record is written into a caller provided buffer section by section.
+//! Sections which do not fit in the remaining space are left out, so a record
+//! is always valid even if the buffer is small.
+
+use crate::types::{
+    self, GrantUsage, MemoryLayout, MpuRegion, ProcessInfo, SectionType, HEADER_LEN,
+    SECTION_HEADER_LEN,
+};
+
+/// Writes a fault dump record into a buffer.
+pub struct FaultDumpWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> FaultDumpWriter<'a> {
+    /// Start a record with the given sequence number in `buf`.
+    ///
+    /// Returns `None` if `buf` is too short for the header. Records are at
+    /// most `u16::MAX` bytes long, any space beyond that is not used.
+    pub fn new(buf: &'a mut [u8], sequence: u32) -> Option<FaultDumpWriter<'a>> {
+        if buf.len() < HEADER_LEN {
+            return None;
+        }
+        buf[0..4].copy_from_slice(&types::MAGIC.to_le_bytes());
+        buf[4..6].copy_from_slice(&types::VERSION.to_le_bytes());
+        buf[6..8].copy_from_slice(&0u16.to_le_bytes());
+        buf[8..12].copy_from_slice(&sequence.to_le_bytes());
+        buf[12..16].copy_from_slice(&0u32.to_le_bytes());
+        Some(FaultDumpWriter {
+            buf,
+            len: HEADER_LEN,
+        })
+    }
+
+    /// The number of bytes available for the value of the next section.
+    pub fn remaining(&self) -> usize {
+        core::cmp::min(self.buf.len(), u16::MAX as usize)
+            .saturating_sub(self.len + SECTION_HEADER_LEN)
+            & !3
+    }
+
+    /// Add a section whose value is written by `fill`.
+    ///
+    /// `fill` gets the space available for the value and returns how many
+    /// bytes it used, or `None` to leave the section out. Returns whether the
+    /// section was added.
+    pub fn section<F>(&mut self, section_type: SectionType, fill: F) -> bool
+    where
+        F: FnOnce(&mut [u8]) -> Option<usize>,
+    {
+        let remaining = self.remaining();
+        let start = self.len + SECTION_HEADER_LEN;
+        if start > self.buf.len() {
+            return false;
+        }
+        let value = &mut self.buf[start..start + remaining];
+        match fill(value) {
+            Some(len) if len <= remaining => {
+                let padded = (len + 3) & !3;
+                self.buf[start + len..start + padded].fill(0);
+                self.buf[self.len..self.len + 2]
+                    .copy_from_slice(&(section_type as u16).to_le_bytes());
+                self.buf[self.len + 2..self.len + 4].copy_from_slice(&(len as u16).to_le_bytes());
+                self.len = start + padded;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Add a section made of the given little endian words.
+    fn words(&mut self, section_type: SectionType, words: &[u32]) -> bool {
+        self.section(section_type, |value| {
+            let len = words.len() * 4;
+            let value = value.get_mut(..len)?;
+            for (chunk, word) in value.chunks_mut(4).zip(words) {
+                chunk.copy_from_slice(&word.to_le_bytes());
+            }
+            Some(len)
+        })
+    }
+
+    /// Add the process section. The name is truncated to fit.
+    pub fn process(&mut self, info: &ProcessInfo) -> bool {
+        self.section(SectionType::Process, |value| {
+            let fixed = [
+                info.short_id,
+                info.restart_count,
+                info.fault_action.into(),
+                info.syscall_count,
+                info.dropped_upcall_count,
+                info.timeslice_expiration_count,
+            ];
+            let len = core::cmp::min(value.len(), types::PROCESS_FIXED_LEN + info.name.len());
+            let (fixed_value, name_value) =
+                value[..len].split_at_mut_checked(types::PROCESS_FIXED_LEN)?;
+            for (chunk, word) in fixed_value.chunks_mut(4).zip(fixed) {
+                chunk.copy_from_slice(&word.to_le_bytes());
+            }
+            // Do not cut the name in the middle of a character.
+            let mut name_len = name_value.len();
+            while !info.name.is_char_boundary(name_len) {
+                name_len -= 1;
+            }
+            name_value[..name_len].copy_from_slice(&info.name.as_bytes()[..name_len]);
+            Some(types::PROCESS_FIXED_LEN + name_len)
+        })
+    }
+
+    /// Add the memory layout section.
+    pub fn memory(&mut self, layout: &MemoryLayout) -> bool {
+        self.words(
+            SectionType::Memory,
+            &[
+                layout.flash_start,
+                layout.flash_end,
+                layout.sram_start,
+                layout.sram_app_brk,
+                layout.sram_grant_start,
+                layout.sram_end,
+                layout.sram_heap_start,
+                layout.sram_stack_top,
+                layout.sram_stack_bottom,
+            ],
+        )
+    }
+
+    /// Add the grants section.
+    pub fn grants(&mut self, usage: &GrantUsage) -> bool {
+        self.words(
+            SectionType::Grants,
+            &[
+                usage.allocated_grants,
+                usage.allocated_grants_bitmap,
+                usage.grant_pointers_bytes,
+                usage.upcall_list_bytes,
+                usage.process_control_block_bytes,
+            ],
+        )
+    }
+
+    /// Add the MPU regions section with as many of `regions` as fit.
+    pub fn mpu_regions(&mut self, regions: &[MpuRegion]) -> bool {
+        self.section(SectionType::MpuRegions, |value| {
+            let mut len = 0;
+            for (chunk, region) in value.chunks_exact_mut(types::MPU_REGION_LEN).zip(regions) {
+                chunk[0..4].copy_from_slice(&region.start.to_le_bytes());
+                chunk[4..8].copy_from_slice(&region.size.to_le_bytes());
+                chunk[8..12].copy_from_slice(&region.permissions.to_le_bytes());
+                len += types::MPU_REGION_LEN;
+            }
+            Some(len)
+        })
+    }
+
+    /// Add the stored state section, written by `fill` as for
+    /// [`FaultDumpWriter::section()`].
+    pub fn stored_state<F>(&mut self, fill: F) -> bool
+    where
+        F: FnOnce(&mut [u8]) -> Option<usize>,
+    {
+        self.section(SectionType::StoredState, fill)
+    }
+
+    /// Add the stack section for the stack memory starting at `start`.
+    ///
+    /// `fill` gets the space available for the stack contents and returns how
+    /// many bytes it copied.
+    pub fn stack<F>(&mut self, start: u32, fill: F) -> bool
+    where
+        F: FnOnce(&mut [u8]) -> Option<usize>,
+    {
+        self.section(SectionType::Stack, |value| {
+            let (address, contents) = value.split_at_mut_checked(4)?;
+            address.copy_from_slice(&start.to_le_bytes());
+            fill(contents).map(|len| len + 4)
+        })
+    }
+
+    /// Complete the record by filling in its length and checksum. Returns the
+    /// length of the record.
+    pub fn finish(self) -> usize {
+        self.buf[6..8].copy_from_slice(&(self.len as u16).to_le_bytes());
+        let checksum = types::checksum(&self.buf[..self.len]);
+        self.buf[12..16].copy_from_slice(&checksum.to_le_bytes());
+        self.len
+    }
+}
+
+/// Change the sequence number of a completed record, updating its checksum.
+///
+/// This allows a record to be captured before the sequence number it will be
+/// stored with is known.
+pub fn set_sequence(record: &mut [u8], sequence: u32) {
+    let (Some(old_sequence), Some(checksum)) =
+        (types::read_u32(record, 8), types::read_u32(record, 12))
+    else {
+        return;
+    };
+    let checksum = checksum ^ old_sequence ^ sequence;
+    record[8..12].copy_from_slice(&sequence.to_le_bytes());
+    record[12..16].copy_from_slice(&checksum.to_le_bytes());
+}
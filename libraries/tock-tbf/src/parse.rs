@@ -129,6 +129,8 @@ pub fn parse_tbf_header(
                 let mut storage_permissions_pointer: Option<&[u8]> = None;
                 let mut kernel_version: Option<types::TbfHeaderV2KernelVersion> = None;
                 let mut short_id: Option<types::TbfHeaderV2ShortId> = None;
+                let mut ipc_permissions: Option<types::TbfHeaderV2IpcPermissions> = None;
+                let mut storage_quota: Option<types::TbfHeaderV2StorageQuota> = None;
 
                 // Iterate the remainder of the header looking for TLV entries.
                 while remaining.len() > 0 {
@@ -273,6 +275,31 @@ pub fn parse_tbf_header(
                             }
                         }
 
+                        types::TbfHeaderTypes::TbfHeaderIpcPermissions => {
+                            ipc_permissions = Some(
+                                remaining
+                                    .get(0..tlv_header.length as usize)
+                                    .ok_or(types::TbfParseError::NotEnoughFlash)?
+                                    .try_into()?,
+                            );
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderStorageQuota => {
+                            let entry_len = mem::size_of::<types::TbfHeaderV2StorageQuota>();
+                            if tlv_header.length as usize == entry_len {
+                                storage_quota = Some(
+                                    remaining
+                                        .get(0..entry_len)
+                                        .ok_or(types::TbfParseError::NotEnoughFlash)?
+                                        .try_into()?,
+                                );
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
                         _ => {}
                     }
 
@@ -297,6 +324,8 @@ pub fn parse_tbf_header(
                     storage_permissions: storage_permissions_pointer,
                     kernel_version,
                     short_id,
+                    ipc_permissions,
+                    storage_quota,
                 };
 
                 Ok(types::TbfHeader::TbfHeaderV2(tbf_header))
@@ -131,6 +131,8 @@ pub enum TbfHeaderTypes {
     TbfHeaderKernelVersion = 8,
     TbfHeaderProgram = 9,
     TbfHeaderShortId = 10,
+    TbfHeaderIpcPermissions = 11,
+    TbfHeaderStorageQuota = 12,
     TbfFooterCredentials = 128,
 
     /// Some field in the header that we do not understand. Since the TLV format
@@ -250,6 +252,57 @@ pub struct TbfHeaderV2ShortId {
     short_id: Option<core::num::NonZeroU32>,
 }
 
+/// The v2 storage quota for apps.
+///
+/// Header to limit the number of bytes of persistent storage an app may use.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2StorageQuota {
+    quota: u32,
+}
+
+/// The v2 IPC permissions for apps.
+///
+/// Header listing the apps which may send IPC messages to this app, either by
+/// their fixed ShortId or by their package name. The layout is:
+///
+/// ```text
+/// num_short_ids: u16
+/// num_package_names: u16
+/// short_ids: [u32; num_short_ids]
+/// package_names: num_package_names times { length: u8, name: [u8; length] }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2IpcPermissions<'a> {
+    short_ids: &'a [u8],
+    package_names: &'a [u8],
+}
+
+impl TbfHeaderV2IpcPermissions<'_> {
+    /// Return whether the app with the fixed ShortId `short_id` may send
+    /// messages to this app.
+    pub fn allows_short_id(&self, short_id: u32) -> bool {
+        self.short_ids
+            .chunks_exact(4)
+            .any(|id| id == short_id.to_le_bytes())
+    }
+
+    /// Return whether the app with package name `package_name` may send
+    /// messages to this app.
+    pub fn allows_package_name(&self, package_name: &str) -> bool {
+        let mut remaining = self.package_names;
+        while let Some((&length, rest)) = remaining.split_first() {
+            let Some((name, rest)) = rest.split_at_checked(length as usize) else {
+                return false;
+            };
+            if name == package_name.as_bytes() {
+                return true;
+            }
+            remaining = rest;
+        }
+        false
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum TbfFooterV2CredentialsType {
     Reserved = 0,
@@ -330,6 +383,8 @@ impl core::convert::TryFrom<u16> for TbfHeaderTypes {
             8 => Ok(TbfHeaderTypes::TbfHeaderKernelVersion),
             9 => Ok(TbfHeaderTypes::TbfHeaderProgram),
             10 => Ok(TbfHeaderTypes::TbfHeaderShortId),
+            11 => Ok(TbfHeaderTypes::TbfHeaderIpcPermissions),
+            12 => Ok(TbfHeaderTypes::TbfHeaderStorageQuota),
             128 => Ok(TbfHeaderTypes::TbfFooterCredentials),
             _ => Ok(TbfHeaderTypes::Unknown),
         }
@@ -588,6 +643,61 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2ShortId {
     }
 }
 
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2StorageQuota {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2StorageQuota, Self::Error> {
+        Ok(TbfHeaderV2StorageQuota {
+            quota: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+impl<'a> core::convert::TryFrom<&'a [u8]> for TbfHeaderV2IpcPermissions<'a> {
+    type Error = TbfParseError;
+
+    fn try_from(b: &'a [u8]) -> Result<TbfHeaderV2IpcPermissions<'a>, Self::Error> {
+        let num_short_ids = u16::from_le_bytes(
+            b.get(0..2)
+                .ok_or(TbfParseError::NotEnoughFlash)?
+                .try_into()?,
+        );
+        let num_package_names = u16::from_le_bytes(
+            b.get(2..4)
+                .ok_or(TbfParseError::NotEnoughFlash)?
+                .try_into()?,
+        );
+        let short_ids_end = 4 + num_short_ids as usize * 4;
+        let short_ids = b.get(4..short_ids_end).ok_or(TbfParseError::BadTlvEntry(
+            TbfHeaderTypes::TbfHeaderIpcPermissions as usize,
+        ))?;
+
+        // Check that all package names fit in the TLV, so the lookup does not
+        // have to.
+        let mut package_names_end = short_ids_end;
+        for _ in 0..num_package_names {
+            let length = *b.get(package_names_end).ok_or(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfHeaderIpcPermissions as usize,
+            ))?;
+            package_names_end += 1 + length as usize;
+        }
+        let package_names =
+            b.get(short_ids_end..package_names_end)
+                .ok_or(TbfParseError::BadTlvEntry(
+                    TbfHeaderTypes::TbfHeaderIpcPermissions as usize,
+                ))?;
+
+        Ok(TbfHeaderV2IpcPermissions {
+            short_ids,
+            package_names,
+        })
+    }
+}
+
 impl core::convert::TryFrom<&'static [u8]> for TbfFooterV2Credentials {
     type Error = TbfParseError;
 
@@ -661,6 +771,8 @@ pub struct TbfHeaderV2<'a> {
     pub(crate) storage_permissions: Option<&'a [u8]>,
     pub(crate) kernel_version: Option<TbfHeaderV2KernelVersion>,
     pub(crate) short_id: Option<TbfHeaderV2ShortId>,
+    pub(crate) ipc_permissions: Option<TbfHeaderV2IpcPermissions<'a>>,
+    pub(crate) storage_quota: Option<TbfHeaderV2StorageQuota>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -993,6 +1105,15 @@ impl<'a> TbfHeader<'a> {
         }
     }
 
+    /// Get the IPC permissions listing which apps may send messages to this
+    /// process. Returns `None` if the IPC permissions header is not included.
+    pub fn get_ipc_permissions(&self) -> Option<TbfHeaderV2IpcPermissions<'a>> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.ipc_permissions,
+            _ => None,
+        }
+    }
+
     /// Return the offset where the binary ends in the TBF or 0 if there
     /// is no binary. If there is a Main header the end offset is the size
     /// of the TBF, while if there is a Program header it can be smaller.
@@ -1022,4 +1143,13 @@ impl<'a> TbfHeader<'a> {
             _ => None,
         }
     }
+
+    /// Return the number of bytes of persistent storage the application may
+    /// use if it was specified in the TBF header.
+    pub fn get_storage_quota(&self) -> Option<u32> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.storage_quota.map(|sq| sq.quota),
+            _ => None,
+        }
+    }
 }